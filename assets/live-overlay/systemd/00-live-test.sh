@@ -0,0 +1,9 @@
+# Scenario harness markers (serial console only)
+case "$-" in
+    *i*) ;;
+    *) return 0 ;;
+esac
+
+if [ "$(tty 2>/dev/null)" = "/dev/ttyS0" ]; then
+    echo "___SHELL_READY___"
+fi
@@ -0,0 +1,6 @@
+#!/bin/sh
+# Live override: use portable C locale (always available).
+export LANG=C
+unset LC_ALL LC_CTYPE LC_NUMERIC LC_TIME LC_COLLATE LC_MESSAGES \
+      LC_MONETARY LC_PAPER LC_NAME LC_ADDRESS LC_TELEPHONE LC_MEASUREMENT \
+      LC_IDENTIFICATION
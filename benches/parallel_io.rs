@@ -0,0 +1,84 @@
+//! Serial vs parallel tree copy and hashing on a synthetic rootfs-like tree.
+//!
+//! The copy baseline is the serial [`copy_dir_recursive`] the parallel copy
+//! replaces, run with the thread setting unset.
+//!
+//! ```text
+//! cargo bench --bench parallel_io
+//! PARALLEL_IO_BENCH_FILES=100000 PARALLEL_IO_BENCH_THREADS=16 cargo bench --bench parallel_io
+//! ```
+
+use distro_builder::artifact::filesystem::{copy_dir_parallel, copy_dir_recursive};
+use distro_builder::cache::{hash_tree_with_threads, TreeHashMode};
+use distro_builder::parallel::IO_THREADS_ENV;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const FILES_ENV: &str = "PARALLEL_IO_BENCH_FILES";
+const THREADS_ENV: &str = "PARALLEL_IO_BENCH_THREADS";
+
+fn build_tree(root: &Path, files: usize) {
+    for i in 0..files {
+        let dir = root.join(format!("usr/lib/d{:03}/s{:02}", i % 400, i % 17));
+        fs::create_dir_all(&dir).unwrap();
+        // Mostly small files with the odd large one, like a real rootfs.
+        let size = if i % 500 == 0 {
+            1 << 20
+        } else {
+            512 + (i % 64) * 128
+        };
+        fs::write(dir.join(format!("f{}", i)), vec![(i % 251) as u8; size]).unwrap();
+    }
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+fn report(what: &str, serial: Duration, parallel: Duration, threads: usize) {
+    println!(
+        "{:<6} serial {:>8.2?}  parallel({:>2}) {:>8.2?}  speedup {:.2}x",
+        what,
+        serial,
+        threads,
+        parallel,
+        serial.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
+
+fn main() {
+    let setting = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+    let files = setting(FILES_ENV).unwrap_or(20_000);
+    let threads = setting(THREADS_ENV)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let temp = tempfile::TempDir::new().unwrap();
+    let src = temp.path().join("src");
+    build_tree(&src, files);
+    println!("{} files, {} threads", files, threads);
+
+    let (serial_hash, serial) =
+        time(|| hash_tree_with_threads(&src, TreeHashMode::Content, 1).unwrap());
+    let (parallel_hash, parallel) =
+        time(|| hash_tree_with_threads(&src, TreeHashMode::Content, threads).unwrap());
+    assert_eq!(serial_hash, parallel_hash);
+    report("hash", serial, parallel, threads);
+
+    std::env::remove_var(IO_THREADS_ENV);
+    let ((), serial) = time(|| copy_dir_recursive(&src, &temp.path().join("serial")).unwrap());
+    let ((), parallel) =
+        time(|| copy_dir_parallel(&src, &temp.path().join("parallel"), threads).unwrap());
+    report("copy", serial, parallel, threads);
+    assert_eq!(
+        hash_tree_with_threads(&temp.path().join("serial"), TreeHashMode::Content, threads)
+            .unwrap(),
+        hash_tree_with_threads(
+            &temp.path().join("parallel"),
+            TreeHashMode::Content,
+            threads
+        )
+        .unwrap()
+    );
+}
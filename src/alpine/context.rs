@@ -8,6 +8,8 @@
 use anyhow::Result;
 use std::path::{Path, PathBuf};
 
+use crate::contracts::kernel::FirmwareCompression;
+
 /// Shared context for all build operations.
 ///
 /// This provides the paths that the executor and custom operations
@@ -21,6 +23,10 @@ pub struct BuildContext {
     pub base_dir: PathBuf,
     /// Output directory for build artifacts.
     pub output: PathBuf,
+    /// Compression for firmware staged by [`firmware`](super::firmware).
+    pub firmware_compression: FirmwareCompression,
+    /// Kernel `.config` the firmware compression is checked against.
+    pub kernel_config: Option<PathBuf>,
 }
 
 impl BuildContext {
@@ -37,7 +43,7 @@ impl BuildContext {
     pub fn new(base_dir: &Path, staging: &Path, build_cmd: &str) -> Result<Self> {
         let downloads = base_dir.join("downloads");
         let source = downloads.join("rootfs");
-        let output = crate::artifact_store::central_output_dir_for_distro(base_dir);
+        let output = crate::artifact_store::central_output_dir_for_distro(base_dir)?;
 
         if !source.exists() || !source.join("bin").exists() {
             anyhow::bail!(
@@ -53,6 +59,8 @@ impl BuildContext {
             staging: staging.to_path_buf(),
             base_dir: base_dir.to_path_buf(),
             output,
+            firmware_compression: FirmwareCompression::None,
+            kernel_config: None,
         })
     }
 
@@ -61,7 +69,7 @@ impl BuildContext {
     /// This is a convenience method that creates the staging directory
     /// at `output/rootfs`.
     pub fn from_base_dir(base_dir: &Path, build_cmd: &str) -> Result<Self> {
-        let output = crate::artifact_store::central_output_dir_for_distro(base_dir);
+        let output = crate::artifact_store::central_output_dir_for_distro(base_dir)?;
         let staging = output.join("rootfs");
         Self::new(base_dir, &staging, build_cmd)
     }
@@ -77,9 +85,23 @@ impl BuildContext {
             staging: staging.to_path_buf(),
             base_dir: base_dir.to_path_buf(),
             output: base_dir.join("output"),
+            firmware_compression: FirmwareCompression::None,
+            kernel_config: None,
         }
     }
 
+    /// Compress staged firmware with `compression` once it is copied,
+    /// after checking the format against `kernel_config`.
+    pub fn with_firmware_compression(
+        mut self,
+        compression: FirmwareCompression,
+        kernel_config: Option<&Path>,
+    ) -> Self {
+        self.firmware_compression = compression;
+        self.kernel_config = kernel_config.map(Path::to_path_buf);
+        self
+    }
+
     /// Get the library path for this distribution.
     ///
     /// Alpine uses `/usr/lib` (musl), not `/usr/lib64` (glibc).
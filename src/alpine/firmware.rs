@@ -1,12 +1,15 @@
 //! Firmware custom operations.
 //!
-//! Copies WiFi and other hardware firmware from the source rootfs.
+//! Copies WiFi and other hardware firmware from the source rootfs, then
+//! compresses it as the context's
+//! [`firmware_compression`](BuildContext::firmware_compression) requests.
 
 use anyhow::Result;
 use std::fs;
 use std::path::Path;
 
 use super::context::BuildContext;
+use crate::build::firmware::compress_staged_firmware;
 
 /// Copy specific firmware directories from source to staging.
 ///
@@ -44,7 +47,7 @@ pub fn copy_firmware_dirs(ctx: &BuildContext, dirs: &[&str]) -> Result<()> {
         total_size as f64 / 1024.0 / 1024.0
     );
 
-    Ok(())
+    compress_copied_firmware(ctx)
 }
 
 /// Copy all firmware from source to staging.
@@ -75,6 +78,17 @@ pub fn copy_all_firmware(ctx: &BuildContext) -> Result<()> {
         size as f64 / 1024.0 / 1024.0
     );
 
+    compress_copied_firmware(ctx)
+}
+
+/// Compress the copied firmware once the requested format has been
+/// checked against the kernel config.
+fn compress_copied_firmware(ctx: &BuildContext) -> Result<()> {
+    compress_staged_firmware(
+        &ctx.staging,
+        ctx.firmware_compression,
+        ctx.kernel_config.as_deref(),
+    )?;
     Ok(())
 }
 
@@ -119,3 +133,34 @@ fn copy_firmware_tree(src: &Path, dst: &Path) -> Result<u64> {
 
     Ok(total_size)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::kernel::FirmwareCompression;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_all_firmware_checks_compression_against_kernel_config() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("source");
+        let staging = dir.path().join("staging");
+        fs::create_dir_all(source.join("lib/firmware/iwlwifi")).unwrap();
+        fs::write(source.join("lib/firmware/iwlwifi/fw.ucode"), "blob").unwrap();
+        let kernel_config = dir.path().join(".config");
+        fs::write(
+            &kernel_config,
+            "CONFIG_FW_LOADER_COMPRESS=y\nCONFIG_FW_LOADER_COMPRESS_ZSTD=y\n",
+        )
+        .unwrap();
+
+        let ctx = BuildContext::for_testing(&source, &staging, dir.path());
+        copy_all_firmware(&ctx).unwrap();
+        assert!(staging.join("lib/firmware/iwlwifi/fw.ucode").is_file());
+
+        let ctx = BuildContext::for_testing(&source, &staging, dir.path())
+            .with_firmware_compression(FirmwareCompression::Xz, Some(&kernel_config));
+        let err = copy_all_firmware(&ctx).unwrap_err();
+        assert!(err.to_string().contains("CONFIG_FW_LOADER_COMPRESS_XZ"));
+    }
+}
@@ -0,0 +1,513 @@
+//! Post-assembly boot consistency checks.
+//!
+//! Rebuilding the kernel or initramfs does not help if the ESP still carries
+//! the UKI from an earlier run. These checks read the ESP of a finished ISO
+//! or disk EFI partition back out (xorriso and mtools, no mounts), find every
+//! boot entry on it and compare what it would boot against a
+//! [`BootExpectation`]:
+//!
+//! - UKIs under `EFI/Linux/`: the `.uname` (or the bzImage version string in
+//!   `.linux`), `.initrd` and `.cmdline` PE sections
+//! - `loader/entries/*.conf`: the `linux`/`efi` and `initrd` files they point
+//!   at, and their `options`
+//!
+//! The initramfs matches when it is the tail of the entry's initrd, so a
+//! microcode archive prepended to it does not count as a mismatch.
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::artifact::disk::mtools::mtools_extract_all;
+use crate::artifact::esp::UKI_DIR;
+use crate::artifact::loader::LoaderEntry;
+use crate::artifact::usb_image::el_torito_efi_path;
+use crate::build::cmdline::CmdlineBuilder;
+use crate::process::Cmd;
+
+const LOADER_ENTRIES_DIR: &str = "loader/entries";
+
+/// Size and hash of the initramfs every entry must boot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitrdDigest {
+    pub len: u64,
+    pub sha256: String,
+}
+
+impl InitrdDigest {
+    pub fn of_bytes(data: &[u8]) -> Self {
+        Self {
+            len: data.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(data)),
+        }
+    }
+
+    pub fn of_file(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Self::of_bytes(&data))
+    }
+
+    /// Whether `initrd` is this initramfs, possibly with other archives
+    /// (microcode) in front of it.
+    fn matches(&self, initrd: &[u8]) -> bool {
+        let len = self.len as usize;
+        initrd.len() >= len
+            && format!("{:x}", Sha256::digest(&initrd[initrd.len() - len..])) == self.sha256
+    }
+}
+
+/// What the boot entries of an image must agree on.
+#[derive(Debug, Clone, Default)]
+pub struct BootExpectation {
+    kernel_release: Option<String>,
+    initrd: Option<InitrdDigest>,
+    /// Entry file name (e.g. `levitate-live.efi`, `levitate.conf`) and the
+    /// tokens its command line must carry.
+    cmdline: Vec<(String, Vec<String>)>,
+}
+
+impl BootExpectation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn kernel_release(mut self, release: impl Into<String>) -> Self {
+        self.kernel_release = Some(release.into());
+        self
+    }
+
+    /// Expect the release of a built kernel image. Images without a
+    /// readable version (anything but an x86 bzImage) leave the release
+    /// unchecked.
+    pub fn kernel_image(mut self, path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        if let Some(release) = bzimage_release(&data) {
+            self.kernel_release = Some(release);
+        }
+        Ok(self)
+    }
+
+    pub fn initrd(mut self, digest: InitrdDigest) -> Self {
+        self.initrd = Some(digest);
+        self
+    }
+
+    /// Expect the initramfs at `path`.
+    pub fn initrd_file(self, path: &Path) -> Result<Self> {
+        Ok(self.initrd(InitrdDigest::of_file(path)?))
+    }
+
+    /// Require `tokens` on the command line of the entry named `entry`
+    /// (file name only). The entry must exist.
+    pub fn require_cmdline<S: AsRef<str>>(mut self, entry: &str, tokens: &[S]) -> Self {
+        let tokens = tokens.iter().map(|t| t.as_ref().to_string()).collect();
+        self.cmdline.push((entry.to_string(), tokens));
+        self
+    }
+}
+
+/// What one boot entry on the ESP would boot.
+struct BootTarget {
+    /// ESP-relative path of the UKI or entry file.
+    path: String,
+    kernel_release: Option<String>,
+    /// initrd images in load order.
+    initrds: Vec<Vec<u8>>,
+    cmdline: String,
+}
+
+impl BootTarget {
+    fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Check an ESP extracted to `root`, returning one message per mismatch.
+pub fn check_esp_dir(root: &Path, expect: &BootExpectation) -> Result<Vec<String>> {
+    let targets = read_boot_targets(root)?;
+    let mut problems = Vec::new();
+    if targets.is_empty() {
+        problems.push(format!(
+            "no boot entries on the ESP (expected {}/*.efi or {}/*.conf)",
+            UKI_DIR, LOADER_ENTRIES_DIR
+        ));
+    }
+
+    for target in &targets {
+        if let Some(expected) = &expect.kernel_release {
+            match &target.kernel_release {
+                Some(found) if found == expected => {}
+                Some(found) => problems.push(format!(
+                    "{} boots kernel {} but the build produced {}",
+                    target.path, found, expected
+                )),
+                None => problems.push(format!(
+                    "{}: cannot determine the kernel release (expected {})",
+                    target.path, expected
+                )),
+            }
+        }
+        if let Some(digest) = &expect.initrd {
+            if !target.initrds.iter().any(|initrd| digest.matches(initrd)) {
+                problems.push(format!(
+                    "{} does not carry the built initramfs (sha256 {})",
+                    target.path, digest.sha256
+                ));
+            }
+        }
+    }
+
+    for (entry, tokens) in &expect.cmdline {
+        let Some(target) = targets.iter().find(|t| t.name() == entry) else {
+            problems.push(format!("boot entry '{}' is missing from the ESP", entry));
+            continue;
+        };
+        if let Err(err) = CmdlineBuilder::parse(&target.cmdline).and_then(|c| c.require(tokens)) {
+            problems.push(format!("{}: {:#}", target.path, err));
+        }
+    }
+    Ok(problems)
+}
+
+/// Fail unless the ESP extracted to `root` matches `expect`.
+pub fn verify_esp_dir(root: &Path, expect: &BootExpectation) -> Result<()> {
+    let problems = check_esp_dir(root, expect)?;
+    if !problems.is_empty() {
+        bail!(
+            "boot entries do not match the build:\n  - {}",
+            problems.join("\n  - ")
+        );
+    }
+    Ok(())
+}
+
+/// Verify a FAT ESP image (disk EFI partition or El Torito image).
+pub fn verify_esp_image(image: &Path, expect: &BootExpectation) -> Result<()> {
+    let work_dir = image.with_extension("boot-check");
+    with_scratch_dir(&work_dir, |dir| {
+        let esp_dir = dir.join("esp");
+        fs::create_dir_all(&esp_dir)
+            .with_context(|| format!("Failed to create {}", esp_dir.display()))?;
+        mtools_extract_all(image, &esp_dir)?;
+        verify_esp_dir(&esp_dir, expect)
+    })
+    .with_context(|| format!("checking boot entries of {}", image.display()))
+}
+
+/// Verify the UEFI El Torito ESP of an ISO.
+pub fn verify_iso(iso: &Path, expect: &BootExpectation) -> Result<()> {
+    let work_dir = iso.with_extension("boot-check");
+    with_scratch_dir(&work_dir, |dir| {
+        let report = Cmd::new("xorriso")
+            .args(["-indev"])
+            .arg_path(iso)
+            .args(["-report_el_torito", "plain"])
+            .error_msg("xorriso could not read the El Torito catalog")
+            .run()?;
+        let efi_path =
+            el_torito_efi_path(&report.stdout).context("ISO has no UEFI El Torito boot image")?;
+        let esp_image = dir.join("esp.img");
+        Cmd::new("xorriso")
+            .args(["-osirrox", "on", "-indev"])
+            .arg_path(iso)
+            .args([
+                "-extract",
+                &format!("/{}", efi_path.trim_start_matches('/')),
+            ])
+            .arg_path(&esp_image)
+            .error_msg("xorriso failed to extract the EFI boot image")
+            .run()?;
+        let esp_dir = dir.join("esp");
+        fs::create_dir_all(&esp_dir)
+            .with_context(|| format!("Failed to create {}", esp_dir.display()))?;
+        mtools_extract_all(&esp_image, &esp_dir)?;
+        verify_esp_dir(&esp_dir, expect)
+    })
+    .with_context(|| format!("checking boot entries of {}", iso.display()))
+}
+
+fn with_scratch_dir<T>(dir: &Path, f: impl FnOnce(&Path) -> Result<T>) -> Result<T> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let result = f(dir);
+    let _ = fs::remove_dir_all(dir);
+    result
+}
+
+fn read_boot_targets(root: &Path) -> Result<Vec<BootTarget>> {
+    let mut targets = Vec::new();
+    for rel in list_files(root, UKI_DIR, ".efi")? {
+        let data = read_esp_file(root, &rel)?;
+        targets.push(uki_target(rel, &data)?);
+    }
+    for rel in list_files(root, LOADER_ENTRIES_DIR, ".conf")? {
+        let content = String::from_utf8_lossy(&read_esp_file(root, &rel)?).into_owned();
+        let entry = LoaderEntry::parse(&content).with_context(|| format!("parsing {}", rel))?;
+        targets.extend(entry_target(root, rel, &entry)?);
+    }
+    Ok(targets)
+}
+
+fn uki_target(path: String, data: &[u8]) -> Result<BootTarget> {
+    let Some(sections) = pe_sections(data) else {
+        bail!("{} is not a PE image", path);
+    };
+    let section = |name: &str| {
+        sections
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, body)| *body)
+    };
+    let kernel_release = section(".uname")
+        .map(section_text)
+        .filter(|uname| !uname.is_empty())
+        .or_else(|| section(".linux").and_then(bzimage_release));
+    Ok(BootTarget {
+        kernel_release,
+        initrds: section(".initrd")
+            .map(|i| vec![i.to_vec()])
+            .unwrap_or_default(),
+        cmdline: section(".cmdline").map(section_text).unwrap_or_default(),
+        path,
+    })
+}
+
+/// `None` for entries starting an EFI tool (e.g. memtest86+) rather than a
+/// kernel.
+fn entry_target(root: &Path, path: String, entry: &LoaderEntry) -> Result<Option<BootTarget>> {
+    let options = entry.options.join(" ");
+    if let Some(efi) = &entry.efi {
+        let data = read_esp_file(root, efi)?;
+        if pe_sections(&data).is_some_and(|sections| sections.iter().all(|(n, _)| n != ".linux")) {
+            return Ok(None);
+        }
+        let mut target = uki_target(path, &data)?;
+        // systemd-boot passes the entry's options instead of the embedded ones.
+        if !options.is_empty() {
+            target.cmdline = options;
+        }
+        return Ok(Some(target));
+    }
+    let kernel_release = match &entry.linux {
+        Some(linux) => bzimage_release(&read_esp_file(root, linux)?),
+        None => None,
+    };
+    let initrds = entry
+        .initrd
+        .iter()
+        .map(|initrd| read_esp_file(root, initrd))
+        .collect::<Result<_>>()?;
+    Ok(Some(BootTarget {
+        path,
+        kernel_release,
+        initrds,
+        cmdline: options,
+    }))
+}
+
+/// ESP-relative paths of `dir/*<suffix>`, sorted; FAT is case-insensitive.
+fn list_files(root: &Path, dir: &str, suffix: &str) -> Result<Vec<String>> {
+    let path = root.join(dir);
+    if !path.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in
+        fs::read_dir(&path).with_context(|| format!("Failed to read {}", path.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file() && name.to_ascii_lowercase().ends_with(suffix) {
+            files.push(format!("{}/{}", dir, name));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_esp_file(root: &Path, rel: &str) -> Result<Vec<u8>> {
+    let path = root.join(rel.trim_start_matches('/'));
+    fs::read(&path).with_context(|| format!("{} is referenced but missing from the ESP", rel))
+}
+
+fn section_text(body: &[u8]) -> String {
+    String::from_utf8_lossy(body)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+/// Sections of a PE image as (name, contents).
+fn pe_sections(data: &[u8]) -> Option<Vec<(String, &[u8])>> {
+    if data.get(..2)? != b"MZ" {
+        return None;
+    }
+    let pe = u32_at(data, 0x3c)? as usize;
+    if data.get(pe..pe + 4)? != b"PE\0\0" {
+        return None;
+    }
+    let coff = pe + 4;
+    let count = u16_at(data, coff + 2)? as usize;
+    let optional_len = u16_at(data, coff + 16)? as usize;
+    let table = coff + 20 + optional_len;
+    (0..count)
+        .map(|i| {
+            let header = table + i * 40;
+            let name = data.get(header..header + 8)?;
+            let name = String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .to_string();
+            let virtual_size = u32_at(data, header + 8)? as usize;
+            let raw_size = u32_at(data, header + 16)? as usize;
+            let raw_ptr = u32_at(data, header + 20)? as usize;
+            // Raw data is padded to the file alignment; the virtual size is exact.
+            let len = if virtual_size == 0 {
+                raw_size
+            } else {
+                virtual_size.min(raw_size)
+            };
+            Some((name, data.get(raw_ptr..raw_ptr + len)?))
+        })
+        .collect()
+}
+
+/// Kernel release from an x86 bzImage setup header.
+fn bzimage_release(data: &[u8]) -> Option<String> {
+    if data.get(0x202..0x206)? != b"HdrS" {
+        return None;
+    }
+    let offset = u16_at(data, 0x20e)? as usize + 0x200;
+    let version = data.get(offset..)?;
+    let end = version.iter().take(256).position(|&b| b == 0)?;
+    let version = std::str::from_utf8(&version[..end]).ok()?;
+    version.split_whitespace().next().map(str::to_string)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn bzimage(release: &str) -> Vec<u8> {
+        let mut data = vec![0u8; 0x400];
+        data[0x202..0x206].copy_from_slice(b"HdrS");
+        data[0x20e..0x210].copy_from_slice(&0x100u16.to_le_bytes());
+        let version = format!("{} (builder@host) #1 SMP\0", release);
+        data[0x300..0x300 + version.len()].copy_from_slice(version.as_bytes());
+        data
+    }
+
+    /// Minimal PE image with the given sections.
+    fn uki(sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; 0x80];
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        data[0x40..0x44].copy_from_slice(b"PE\0\0");
+        data[0x46..0x48].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        // Optional header fills the gap up to the section table at 0x80.
+        data[0x54..0x56].copy_from_slice(&0x28u16.to_le_bytes());
+        let table = data.len();
+        data.resize(table + sections.len() * 40, 0);
+        for (i, (name, body)) in sections.iter().enumerate() {
+            let header = table + i * 40;
+            let ptr = data.len() as u32;
+            data[header..header + name.len()].copy_from_slice(name.as_bytes());
+            data[header + 8..header + 12].copy_from_slice(&(body.len() as u32).to_le_bytes());
+            data[header + 16..header + 20].copy_from_slice(&(body.len() as u32 + 7).to_le_bytes());
+            data[header + 20..header + 24].copy_from_slice(&ptr.to_le_bytes());
+            data.extend_from_slice(body);
+            data.extend_from_slice(&[0; 7]);
+        }
+        data
+    }
+
+    fn write(root: &Path, rel: &str, data: &[u8]) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_stale_uki_is_reported() {
+        let temp = TempDir::new().unwrap();
+        let initrd = b"070701-current-initramfs".to_vec();
+        let mut with_ucode = b"070701-microcode".to_vec();
+        with_ucode.extend_from_slice(&initrd);
+        write(
+            temp.path(),
+            "EFI/Linux/live.efi",
+            &uki(&[
+                (".linux", &bzimage("6.12.1-levitate")),
+                (".initrd", &with_ucode),
+                (".cmdline", b"root=live:LABEL=LEVI rd.live.image\0"),
+            ]),
+        );
+        write(
+            temp.path(),
+            "EFI/Linux/debug.efi",
+            &uki(&[
+                (".uname", b"6.11.9-levitate"),
+                (".initrd", b"old-initramfs"),
+                (".cmdline", b"debug"),
+            ]),
+        );
+
+        let expect = BootExpectation::new()
+            .kernel_release("6.12.1-levitate")
+            .initrd(InitrdDigest::of_bytes(&initrd))
+            .require_cmdline("live.efi", &["rd.live.image", "root"])
+            .require_cmdline("emergency.efi", &["emergency"]);
+        let problems = check_esp_dir(temp.path(), &expect).unwrap();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains("debug.efi boots kernel 6.11.9-levitate"));
+        assert!(problems[1].contains("debug.efi does not carry the built initramfs"));
+        assert!(problems[2].contains("'emergency.efi' is missing"));
+    }
+
+    #[test]
+    fn test_type1_entry() {
+        let temp = TempDir::new().unwrap();
+        write(temp.path(), "vmlinuz", &bzimage("6.12.1-acorn"));
+        write(temp.path(), "initramfs.img", b"initramfs");
+        write(
+            temp.path(),
+            "loader/entries/acorn.conf",
+            b"title AcornOS\nlinux /vmlinuz\ninitrd /initramfs.img\noptions root=PARTUUID=abc rw\n",
+        );
+        // EFI tools started from an entry are not kernels to check.
+        write(
+            temp.path(),
+            "EFI/memtest86/memtest.efi",
+            &uki(&[(".text", b"memtest")]),
+        );
+        write(
+            temp.path(),
+            "loader/entries/memtest.conf",
+            b"title Memory test\nefi /EFI/memtest86/memtest.efi\n",
+        );
+        let expect = BootExpectation::new()
+            .kernel_release("6.12.1-acorn")
+            .initrd(InitrdDigest::of_bytes(b"initramfs"))
+            .require_cmdline("acorn.conf", &["root=PARTUUID=abc"]);
+        assert!(verify_esp_dir(temp.path(), &expect).is_ok());
+
+        let expect = expect.require_cmdline("acorn.conf", &["quiet"]);
+        let err = verify_esp_dir(temp.path(), &expect).unwrap_err();
+        assert!(format!("{:#}", err).contains("quiet"));
+    }
+}
@@ -0,0 +1,370 @@
+//! Troubleshooting entries for the live ISO boot menu.
+//!
+//! Next to the live UKI, the ESP gets Type #1 entries that start the same
+//! UKI with a different command line, so a machine that hangs or shows a
+//! black screen can be diagnosed from the systemd-boot menu instead of by
+//! rebuilding the ISO:
+//!
+//! - `debug`: initramfs shell and debug logging (`rd.shell rd.debug`)
+//! - `nomodeset`: no kernel modesetting, for broken graphics drivers
+//! - `serial`: the serial console only, without `quiet` or a splash
+//! - a memtest86+ entry when the variant ships the EFI binary
+//!
+//! The release hook gets these files in `BOOT_MENU_ESP_DIR`, laid out like
+//! the ESP, and copies the tree next to its UKIs. Every entry's options are
+//! the live command line from the contract plus its own tokens.
+//! systemd-stub ignores command line overrides under Secure Boot; the
+//! emergency and debug UKIs cover that case. A variant tunes the menu with
+//! `boot-menu.toml`:
+//!
+//! ```toml
+//! # Built-in entries, in menu order (all by default).
+//! troubleshooting = ["debug", "serial"]
+//! # console= of the serial entry; the first device of consoles.toml by default.
+//! serial_console = "ttyS1,115200"
+//! # memtest86+ EFI binary, relative to the variant directory.
+//! memtest = "boot/memtest86+x64.efi"
+//!
+//! [[entry]]
+//! id = "noacpi"
+//! title = "ACPI disabled"
+//! options = "acpi=off"
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::artifact::loader::{validate_entry_filename, LoaderEntry};
+use crate::build::cmdline::CmdlineBuilder;
+use crate::build::consoles::SerialConsoles;
+
+/// Variant-local boot menu configuration file.
+pub const BOOT_MENU_FILENAME: &str = "boot-menu.toml";
+
+/// ESP path of the memtest86+ binary.
+pub const MEMTEST_EFI_PATH: &str = "EFI/memtest86/memtest.efi";
+
+/// A built-in troubleshooting entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TroubleshootMode {
+    Debug,
+    Nomodeset,
+    Serial,
+}
+
+impl TroubleshootMode {
+    pub const ALL: [TroubleshootMode; 3] = [Self::Debug, Self::Nomodeset, Self::Serial];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Nomodeset => "nomodeset",
+            Self::Serial => "serial",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Debug => "debug shell",
+            Self::Nomodeset => "safe graphics",
+            Self::Serial => "serial console only",
+        }
+    }
+}
+
+/// An extra entry defined by the variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomBootEntry {
+    pub id: String,
+    pub title: String,
+    /// Tokens appended to the live command line.
+    pub options: String,
+}
+
+/// `boot-menu.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootMenuConfig {
+    pub troubleshooting: Vec<TroubleshootMode>,
+    /// `console=` value of the serial entry; the variant's primary serial
+    /// console ([`SerialConsoles::kernel_console`]) when unset.
+    pub serial_console: Option<String>,
+    pub memtest: Option<PathBuf>,
+    #[serde(rename = "entry")]
+    pub entries: Vec<CustomBootEntry>,
+}
+
+impl Default for BootMenuConfig {
+    fn default() -> Self {
+        Self {
+            troubleshooting: TroubleshootMode::ALL.to_vec(),
+            serial_console: None,
+            memtest: None,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl BootMenuConfig {
+    /// Load `boot-menu.toml` from `variant_dir`; the built-in entries when
+    /// the variant has none. The serial entry defaults to the first device
+    /// of the variant's `consoles.toml`.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let mut config = Self::load_menu_file(variant_dir)?;
+        if config.serial_console.is_none() {
+            let consoles = SerialConsoles::load_for_variant(variant_dir)?.unwrap_or_default();
+            config.serial_console = Some(consoles.kernel_console());
+        }
+        Ok(config)
+    }
+
+    fn load_menu_file(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(BOOT_MENU_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if let Some(memtest) = &mut config.memtest {
+            *memtest = variant_dir.join(&*memtest);
+        }
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(console) = &self.serial_console {
+            if console.trim().is_empty() || console.contains(char::is_whitespace) {
+                bail!("serial_console '{}' is not a console= value", console);
+            }
+        }
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if entry.id.is_empty()
+                || !entry
+                    .id
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            {
+                bail!(
+                    "entry id '{}' must be lowercase letters, digits or '-'",
+                    entry.id
+                );
+            }
+            let builtin = TroubleshootMode::ALL.iter().any(|m| m.id() == entry.id);
+            if builtin || self.entries[..idx].iter().any(|e| e.id == entry.id) {
+                bail!("entry id '{}' is used twice", entry.id);
+            }
+        }
+        if let Some(memtest) = &self.memtest {
+            if !memtest.is_file() {
+                bail!("memtest binary {} does not exist", memtest.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Loader entries as `(filename, entry)` in menu order. `uki` is the
+    /// ESP path of the live UKI and `live_cmdline` its command line.
+    pub fn loader_entries(
+        &self,
+        os_name: &str,
+        uki: &str,
+        live_cmdline: &str,
+    ) -> Result<Vec<(String, LoaderEntry)>> {
+        let live = CmdlineBuilder::parse(live_cmdline)?;
+        let serial_console = self
+            .serial_console
+            .clone()
+            .unwrap_or_else(|| SerialConsoles::default().kernel_console());
+        let mut out = Vec::new();
+        let mut push = |id: &str, entry: LoaderEntry| -> Result<()> {
+            let filename = format!("troubleshoot-{:02}-{}.conf", out.len(), id);
+            validate_entry_filename(&filename)?;
+            entry.validate()?;
+            out.push((filename, entry));
+            Ok(())
+        };
+
+        for mode in &self.troubleshooting {
+            let cmdline = match mode {
+                TroubleshootMode::Debug => add_flags(
+                    live.clone().remove("quiet").remove("splash"),
+                    &["rd.shell", "rd.debug"],
+                ),
+                TroubleshootMode::Nomodeset => add_flags(live.clone(), &["nomodeset"]),
+                TroubleshootMode::Serial => live
+                    .clone()
+                    .remove("console")
+                    .remove("quiet")
+                    .remove("splash")
+                    .console(&serial_console),
+            };
+            let title = format!("{} ({})", os_name, mode.title());
+            push(
+                mode.id(),
+                LoaderEntry::new(title).efi(uki).options(cmdline.build()?),
+            )?;
+        }
+        for custom in &self.entries {
+            let cmdline = live
+                .clone()
+                .extend_str(&custom.options)?
+                .build()
+                .with_context(|| format!("boot menu entry '{}'", custom.id))?;
+            let title = format!("{} ({})", os_name, custom.title);
+            push(
+                &custom.id,
+                LoaderEntry::new(title).efi(uki).options(cmdline),
+            )?;
+        }
+        if self.memtest.is_some() {
+            push(
+                "memtest",
+                LoaderEntry::new("Memory test (memtest86+)").efi(format!("/{}", MEMTEST_EFI_PATH)),
+            )?;
+        }
+        Ok(out)
+    }
+
+    /// Write the entries (and the memtest binary) under `esp_dir`, laid out
+    /// like the ESP. Returns the number of entries.
+    pub fn stage_esp(
+        &self,
+        esp_dir: &Path,
+        os_name: &str,
+        uki: &str,
+        live_cmdline: &str,
+    ) -> Result<usize> {
+        let entries = self.loader_entries(os_name, uki, live_cmdline)?;
+        let entries_dir = esp_dir.join("loader/entries");
+        fs::create_dir_all(&entries_dir)
+            .with_context(|| format!("Failed to create {}", entries_dir.display()))?;
+        for (filename, entry) in &entries {
+            let path = entries_dir.join(filename);
+            fs::write(&path, entry.render()?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        if let Some(memtest) = &self.memtest {
+            let dest = esp_dir.join(MEMTEST_EFI_PATH);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            fs::copy(memtest, &dest).with_context(|| {
+                format!("Failed to copy {} to {}", memtest.display(), dest.display())
+            })?;
+        }
+        Ok(entries.len())
+    }
+}
+
+fn add_flags(mut cmdline: CmdlineBuilder, flags: &[&str]) -> CmdlineBuilder {
+    for flag in flags {
+        if !cmdline.contains(flag) {
+            cmdline = cmdline.flag(flag);
+        }
+    }
+    cmdline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const LIVE: &str = "root=live:LABEL=LEVI console=tty0 console=ttyS0,115200 quiet splash";
+
+    #[test]
+    fn test_builtin_entries() {
+        let entries = BootMenuConfig::default()
+            .loader_entries("LevitateOS", "/EFI/Linux/live.efi", LIVE)
+            .unwrap();
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "troubleshoot-00-debug.conf",
+                "troubleshoot-01-nomodeset.conf",
+                "troubleshoot-02-serial.conf"
+            ]
+        );
+        let options: Vec<String> = entries.iter().map(|(_, e)| e.options.join(" ")).collect();
+        assert_eq!(
+            options[0],
+            "root=live:LABEL=LEVI console=tty0 console=ttyS0,115200 rd.shell rd.debug"
+        );
+        assert!(options[1].ends_with("quiet splash nomodeset"));
+        assert_eq!(options[2], "root=live:LABEL=LEVI console=ttyS0,115200");
+        assert_eq!(entries[2].1.title, "LevitateOS (serial console only)");
+        assert_eq!(entries[0].1.efi.as_deref(), Some("/EFI/Linux/live.efi"));
+    }
+
+    #[test]
+    fn test_variant_config() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("memtest.efi"), "MZ").unwrap();
+        fs::write(
+            temp.path().join(BOOT_MENU_FILENAME),
+            "troubleshooting = [\"serial\"]\nserial_console = \"ttyAMA0\"\nmemtest = \"memtest.efi\"\n\n\
+             [[entry]]\nid = \"noacpi\"\ntitle = \"ACPI disabled\"\noptions = \"acpi=off\"\n",
+        )
+        .unwrap();
+        let config = BootMenuConfig::load_for_variant(temp.path()).unwrap();
+        let esp = temp.path().join("esp");
+        let count = config
+            .stage_esp(&esp, "AcornOS", "/EFI/Linux/live.efi", LIVE)
+            .unwrap();
+        assert_eq!(count, 3);
+        let serial =
+            fs::read_to_string(esp.join("loader/entries/troubleshoot-00-serial.conf")).unwrap();
+        assert!(serial.contains("options root=live:LABEL=LEVI console=ttyAMA0\n"));
+        let memtest =
+            fs::read_to_string(esp.join("loader/entries/troubleshoot-02-memtest.conf")).unwrap();
+        assert!(memtest.contains("efi /EFI/memtest86/memtest.efi"));
+        assert!(esp.join(MEMTEST_EFI_PATH).is_file());
+
+        fs::write(
+            temp.path().join(BOOT_MENU_FILENAME),
+            "[[entry]]\nid = \"debug\"\ntitle = \"x\"\noptions = \"x\"\n",
+        )
+        .unwrap();
+        assert!(BootMenuConfig::load_for_variant(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_serial_entry_follows_consoles_toml() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path()
+                .join(crate::build::consoles::CONSOLES_CONFIG_FILENAME),
+            "devices = [\"ttyAMA0\", \"hvc0\"]\nbaud = 38400\n",
+        )
+        .unwrap();
+        let config = BootMenuConfig::load_for_variant(temp.path()).unwrap();
+        assert_eq!(config.serial_console.as_deref(), Some("ttyAMA0,38400"));
+        let entries = config
+            .loader_entries("AcornOS", "/EFI/Linux/live.efi", LIVE)
+            .unwrap();
+        assert_eq!(
+            entries[2].1.options.join(" "),
+            "root=live:LABEL=LEVI console=ttyAMA0,38400"
+        );
+
+        // An explicit serial_console still wins.
+        fs::write(
+            temp.path().join(BOOT_MENU_FILENAME),
+            "serial_console = \"ttyS1,115200\"\n",
+        )
+        .unwrap();
+        let config = BootMenuConfig::load_for_variant(temp.path()).unwrap();
+        assert_eq!(config.serial_console.as_deref(), Some("ttyS1,115200"));
+    }
+}
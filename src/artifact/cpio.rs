@@ -6,44 +6,74 @@
 use anyhow::Result;
 use std::path::Path;
 
-use crate::process::shell;
+use crate::compression::{self, Compression};
+use crate::preflight::RequiredTools;
+use crate::process::Cmd;
+use crate::reproducible;
+
+/// Register the host tools [`build_cpio`] invokes with `compression`.
+pub fn register_host_tools(tools: &mut RequiredTools, compression: Compression) {
+    tools.require_all(
+        "cpio",
+        &[
+            ("bash", "bash"),
+            ("find", "findutils"),
+            ("sort", "coreutils"),
+            ("cpio", "cpio"),
+        ],
+    );
+    compression::register_host_tools(tools, compression);
+}
 
 /// Build a compressed cpio archive from a directory.
 ///
-/// Creates a gzip-compressed cpio archive in newc format, suitable for
-/// use as a Linux initramfs.
+/// Creates a cpio archive in newc format, compressed with `compression`,
+/// suitable for use as a Linux initramfs (the kernel needs the matching
+/// `CONFIG_RD_*` decompressor). Entries are sorted; with a `SOURCE_DATE_EPOCH`
+/// (see [`reproducible`]) mtimes in `root` are clamped to it first.
 ///
 /// # Arguments
 ///
 /// * `root` - Directory containing the initramfs contents
-/// * `output` - Path for the output .cpio.gz file
-/// * `gzip_level` - Gzip compression level (1-9, higher = smaller but slower)
+/// * `output` - Path for the output archive (e.g. `initramfs.cpio.gz`)
+/// * `compression` - Codec and level, e.g. `"gzip:6"` or `"zstd:19"`
 ///
 /// # Example
 ///
 /// ```rust,ignore
 /// use distro_builder::artifact::cpio::build_cpio;
+/// use distro_builder::compression::Compression;
 /// use std::path::Path;
 ///
 /// build_cpio(
 ///     Path::new("/tmp/initramfs-root"),
 ///     Path::new("/tmp/initramfs.cpio.gz"),
-///     6,
+///     Compression::initramfs_default(),
 /// )?;
 /// ```
-pub fn build_cpio(root: &Path, output: &Path, gzip_level: u32) -> Result<()> {
+pub fn build_cpio(root: &Path, output: &Path, compression: Compression) -> Result<()> {
+    if let Some(epoch) = reproducible::source_date_epoch()? {
+        reproducible::clamp_mtimes(root, epoch)?;
+    }
     // Use find + cpio to create the archive
-    // - find . -print0: List all files with null separator (handles special chars)
-    // - cpio --null -o -H newc: Create archive in newc format (required for Linux initramfs)
-    // - gzip -N: Compress with specified level
+    // - find . -print0 | sort -z: List all files with null separator, in a stable order
+    // - cpio --null -o -H newc: Create archive in newc format (required for Linux initramfs),
+    //   --reproducible zeroes inode and device numbers
+    // - compressor: e.g. gzip -n -c -6, without a name or timestamp
+    // The pipeline runs on the host (the tool container only wraps single
+    // programs); pipefail turns a missing or failing cpio into an error
+    // instead of an empty archive.
     let cpio_cmd = format!(
-        "cd {} && find . -print0 | cpio --null -o -H newc 2>/dev/null | gzip -{} > {}",
+        "set -o pipefail; cd {} && find . -print0 | LC_ALL=C sort -z | cpio --null --reproducible --quiet -o -H newc | {} > {}",
         root.display(),
-        gzip_level,
+        compression.shell_filter(),
         output.display()
     );
 
-    shell(&cpio_cmd)?;
+    Cmd::new("bash")
+        .args(["-c", &cpio_cmd])
+        .error_msg("Failed to build cpio archive")
+        .run()?;
 
     Ok(())
 }
@@ -66,7 +96,7 @@ mod tests {
         fs::write(root.join("init"), "#!/bin/sh\nexec /bin/sh\n").unwrap();
 
         // Build the cpio archive
-        build_cpio(&root, &output, 6).unwrap();
+        build_cpio(&root, &output, Compression::initramfs_default()).unwrap();
 
         // Verify output exists and has content
         assert!(output.exists());
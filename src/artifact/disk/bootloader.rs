@@ -0,0 +1,177 @@
+//! Bootloader choice for installed disk images.
+//!
+//! The boot entry is always described as a [`LoaderEntry`]/[`LoaderConf`]
+//! pair; each bootloader renders that into its own config format so
+//! distros do not carry three copies of the same kernel command line.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::artifact::esp::{EspLayout, FALLBACK_LOADER};
+use crate::artifact::loader::{LoaderConf, LoaderEntry, LoaderTimeout};
+
+/// Config next to the GRUB image, sourced via `$cmdpath`.
+pub const GRUB_CONFIG_PATH: &str = "EFI/BOOT/grub.cfg";
+
+/// First location Limine searches for its config.
+pub const LIMINE_CONFIG_PATH: &str = "limine.conf";
+
+/// Timeout used when `loader.conf` does not set one.
+const DEFAULT_TIMEOUT_SECS: u32 = 3;
+
+/// Bootloader installed into the EFI partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bootloader {
+    #[default]
+    SystemdBoot,
+    /// A standalone GRUB EFI image whose embedded config sources
+    /// `$cmdpath/grub.cfg` (i.e. `EFI/BOOT/grub.cfg`).
+    Grub,
+    /// Limine's `BOOTX64.EFI`.
+    Limine,
+}
+
+impl Bootloader {
+    pub fn name(self) -> &'static str {
+        match self {
+            Bootloader::SystemdBoot => "systemd-boot",
+            Bootloader::Grub => "grub",
+            Bootloader::Limine => "limine",
+        }
+    }
+
+    /// Add the loader binary and its config for `entry` to `layout`.
+    pub fn populate_esp(
+        self,
+        layout: EspLayout,
+        loader_efi: &Path,
+        entry_filename: &str,
+        entry: &LoaderEntry,
+        conf: &LoaderConf,
+    ) -> Result<EspLayout> {
+        entry.validate()?;
+        Ok(match self {
+            Bootloader::SystemdBoot => layout
+                .systemd_boot(loader_efi)
+                .loader_conf(conf.render()?)
+                .entry(entry_filename, entry.render()?),
+            Bootloader::Grub => layout
+                .file(loader_efi, FALLBACK_LOADER)
+                .write(GRUB_CONFIG_PATH, grub_config(entry, conf)?),
+            Bootloader::Limine => layout
+                .file(loader_efi, FALLBACK_LOADER)
+                .write(LIMINE_CONFIG_PATH, limine_config(entry, conf)?),
+        })
+    }
+}
+
+/// Render `grub.cfg` for a single entry.
+pub fn grub_config(entry: &LoaderEntry, conf: &LoaderConf) -> Result<String> {
+    let timeout = match conf.timeout {
+        Some(LoaderTimeout::Seconds(secs)) => secs as i64,
+        Some(LoaderTimeout::MenuForce) => -1,
+        Some(LoaderTimeout::MenuHidden) => 0,
+        None => DEFAULT_TIMEOUT_SECS as i64,
+    };
+    let mut out = format!("set default=0\nset timeout={}\n\n", timeout);
+    let _ = writeln!(out, "menuentry {} {{", grub_quote(&entry.title)?);
+    if let Some(linux) = &entry.linux {
+        // A standalone image starts with root on its memdisk; find the ESP.
+        let _ = writeln!(out, "    search --no-floppy --set=root --file {}", linux);
+        let _ = writeln!(out, "    linux {} {}", linux, entry.options.join(" "));
+        if !entry.initrd.is_empty() {
+            let _ = writeln!(out, "    initrd {}", entry.initrd.join(" "));
+        }
+        if let Some(devicetree) = &entry.devicetree {
+            let _ = writeln!(out, "    devicetree {}", devicetree);
+        }
+    } else if let Some(efi) = &entry.efi {
+        let _ = writeln!(out, "    chainloader {}", efi);
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// Render `limine.conf` (Limine 8+ syntax) for a single entry.
+pub fn limine_config(entry: &LoaderEntry, conf: &LoaderConf) -> Result<String> {
+    let timeout = match conf.timeout {
+        Some(LoaderTimeout::Seconds(secs)) => secs.to_string(),
+        Some(LoaderTimeout::MenuForce) => "no".to_string(),
+        Some(LoaderTimeout::MenuHidden) => "0".to_string(),
+        None => DEFAULT_TIMEOUT_SECS.to_string(),
+    };
+    if entry.title.contains('/') {
+        bail!("limine entry title '{}' cannot contain '/'", entry.title);
+    }
+    let mut out = format!("timeout: {}\n\n/{}\n", timeout, entry.title);
+    if let Some(linux) = &entry.linux {
+        out.push_str("    protocol: linux\n");
+        let _ = writeln!(out, "    path: boot():{}", linux);
+        if !entry.options.is_empty() {
+            let _ = writeln!(out, "    cmdline: {}", entry.options.join(" "));
+        }
+        for initrd in &entry.initrd {
+            let _ = writeln!(out, "    module_path: boot():{}", initrd);
+        }
+        if let Some(devicetree) = &entry.devicetree {
+            let _ = writeln!(out, "    dtb_path: boot():{}", devicetree);
+        }
+    } else if let Some(efi) = &entry.efi {
+        out.push_str("    protocol: efi\n");
+        let _ = writeln!(out, "    path: boot():{}", efi);
+    }
+    Ok(out)
+}
+
+fn grub_quote(value: &str) -> Result<String> {
+    if value.contains('\'') {
+        bail!("grub menu title '{}' cannot contain a single quote", value);
+    }
+    Ok(format!("'{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> LoaderEntry {
+        LoaderEntry::new("Acorn")
+            .linux("/vmlinuz")
+            .initrd("/initramfs.img")
+            .options("root=PARTUUID=1234 rw")
+    }
+
+    #[test]
+    fn test_grub_config() {
+        let conf = LoaderConf::new().timeout(LoaderTimeout::MenuForce);
+        assert_eq!(
+            grub_config(&entry(), &conf).unwrap(),
+            "set default=0\nset timeout=-1\n\nmenuentry 'Acorn' {\n    \
+             search --no-floppy --set=root --file /vmlinuz\n    \
+             linux /vmlinuz root=PARTUUID=1234 rw\n    initrd /initramfs.img\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_limine_config() {
+        let rendered = limine_config(&entry(), &LoaderConf::new()).unwrap();
+        assert!(rendered.starts_with("timeout: 3\n\n/Acorn\n    protocol: linux\n"));
+        assert!(rendered.contains("    cmdline: root=PARTUUID=1234 rw\n"));
+        assert!(rendered.contains("    module_path: boot():/initramfs.img\n"));
+    }
+
+    #[test]
+    fn test_bootloader_from_toml() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            bootloader: Bootloader,
+        }
+        let parsed: Wrapper = toml::from_str("bootloader = \"systemd-boot\"").unwrap();
+        assert_eq!(parsed.bootloader, Bootloader::SystemdBoot);
+        let parsed: Wrapper = toml::from_str("bootloader = \"limine\"").unwrap();
+        assert_eq!(parsed.bootloader, Bootloader::Limine);
+    }
+}
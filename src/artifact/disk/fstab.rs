@@ -0,0 +1,285 @@
+//! Declarative `/etc/fstab` generation for disk images.
+//!
+//! Distros used to format fstab by hand in
+//! [`DiskImageConfig::prepare_rootfs`](crate::contracts::disk::DiskImageConfig::prepare_rootfs).
+//! [`FstabBuilder`] takes the generated [`DiskUuids`] instead, so the
+//! common layout is one call and mistakes (duplicate mount points, a
+//! missing root, whitespace in a field) fail at build time:
+//!
+//! ```rust,ignore
+//! FstabBuilder::from_uuids(uuids)
+//!     .swap("/swapfile")
+//!     .mount("tmpfs", "/tmp", "tmpfs", &["nosuid", "nodev", "size=512M"])
+//!     .write_to(rootfs)?;
+//! ```
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::helpers::DiskUuids;
+
+/// Default ESP mount point; systemd-boot reads kernels from it.
+pub const DEFAULT_ESP_MOUNT_POINT: &str = "/boot";
+
+/// One fstab line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FstabEntry {
+    /// Device spec, e.g. `UUID=...`, `PARTUUID=...`, `tmpfs` or a path.
+    pub spec: String,
+    /// Absolute mount point, or `none` for swap.
+    pub mount_point: String,
+    pub fs_type: String,
+    pub options: Vec<String>,
+    pub dump: u8,
+    /// fsck order: 1 for root, 2 for other checked filesystems, 0 to skip.
+    pub pass: u8,
+}
+
+impl FstabEntry {
+    pub fn new(spec: &str, mount_point: &str, fs_type: &str, options: &[&str]) -> Self {
+        Self {
+            spec: spec.to_string(),
+            mount_point: mount_point.to_string(),
+            fs_type: fs_type.to_string(),
+            options: options.iter().map(|o| o.to_string()).collect(),
+            dump: 0,
+            pass: 0,
+        }
+    }
+
+    /// Set the fsck pass number.
+    pub fn pass(mut self, pass: u8) -> Self {
+        self.pass = pass;
+        self
+    }
+
+    fn is_swap(&self) -> bool {
+        self.fs_type == "swap"
+    }
+}
+
+impl fmt::Display for FstabEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let options = if self.options.is_empty() {
+            "defaults".to_string()
+        } else {
+            self.options.join(",")
+        };
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            escape_field(&self.spec),
+            escape_field(&self.mount_point),
+            self.fs_type,
+            options,
+            self.dump,
+            self.pass
+        )
+    }
+}
+
+/// Builder for `/etc/fstab`.
+#[derive(Debug, Clone, Default)]
+pub struct FstabBuilder {
+    entries: Vec<FstabEntry>,
+}
+
+impl FstabBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Root and ESP entries for the standard two-partition disk layout.
+    pub fn from_uuids(uuids: &DiskUuids) -> Self {
+        Self::new().root(uuids).esp(uuids, DEFAULT_ESP_MOUNT_POINT)
+    }
+
+    /// ext4 root filesystem by filesystem UUID.
+    pub fn root(self, uuids: &DiskUuids) -> Self {
+        self.entry(
+            FstabEntry::new(
+                &format!("UUID={}", uuids.root_fs_uuid),
+                "/",
+                "ext4",
+                &["defaults", "noatime"],
+            )
+            .pass(1),
+        )
+    }
+
+    /// EFI system partition by vfat serial, readable by root only.
+    pub fn esp(self, uuids: &DiskUuids, mount_point: &str) -> Self {
+        self.entry(
+            FstabEntry::new(
+                &format!("UUID={}", uuids.efi_fs_uuid),
+                mount_point,
+                "vfat",
+                &["umask=0077", "shortname=winnt"],
+            )
+            .pass(2),
+        )
+    }
+
+    /// Swap file or partition.
+    pub fn swap(self, spec: &str) -> Self {
+        self.entry(FstabEntry::new(spec, "none", "swap", &["defaults"]))
+    }
+
+    /// Any other mount; not fsck'd.
+    pub fn mount(self, spec: &str, mount_point: &str, fs_type: &str, options: &[&str]) -> Self {
+        self.entry(FstabEntry::new(spec, mount_point, fs_type, options))
+    }
+
+    pub fn entry(mut self, entry: FstabEntry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Validate and render the file.
+    pub fn build(&self) -> Result<String> {
+        let mut mount_points = BTreeSet::new();
+        for entry in &self.entries {
+            validate_entry(entry)?;
+            if !entry.is_swap() && !mount_points.insert(entry.mount_point.as_str()) {
+                bail!("fstab mounts '{}' twice", entry.mount_point);
+            }
+        }
+        if !mount_points.contains("/") {
+            bail!("fstab has no root ('/') entry");
+        }
+
+        let mut out = String::from("# /etc/fstab: generated by distro-builder\n");
+        out.push_str("# <spec>\t<mount>\t<type>\t<options>\t<dump>\t<pass>\n");
+        for entry in &self.entries {
+            out.push_str(&entry.to_string());
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    /// Write `<rootfs>/etc/fstab`.
+    pub fn write_to(&self, rootfs: &Path) -> Result<()> {
+        let content = self.build()?;
+        let etc = rootfs.join("etc");
+        fs::create_dir_all(&etc).with_context(|| format!("Failed to create {}", etc.display()))?;
+        let path = etc.join("fstab");
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+fn validate_entry(entry: &FstabEntry) -> Result<()> {
+    if entry.spec.trim().is_empty() {
+        bail!(
+            "fstab entry for '{}' has an empty device",
+            entry.mount_point
+        );
+    }
+    if entry.is_swap() {
+        if entry.mount_point != "none" {
+            bail!(
+                "swap entry '{}' must use mount point 'none', not '{}'",
+                entry.spec,
+                entry.mount_point
+            );
+        }
+    } else if !entry.mount_point.starts_with('/') {
+        bail!(
+            "fstab mount point '{}' for '{}' must be absolute",
+            entry.mount_point,
+            entry.spec
+        );
+    }
+    let fields = [&entry.fs_type]
+        .into_iter()
+        .chain(&entry.options)
+        .map(String::as_str);
+    for field in fields {
+        if field.is_empty() || field.contains(char::is_whitespace) || field.contains(',') {
+            bail!(
+                "invalid fstab field '{}' for '{}'",
+                field,
+                entry.mount_point
+            );
+        }
+    }
+    if entry.pass > 2 {
+        bail!("fstab pass for '{}' must be 0, 1 or 2", entry.mount_point);
+    }
+    Ok(())
+}
+
+/// Octal-escape whitespace and backslashes as fstab(5) requires.
+fn escape_field(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            ' ' => out.push_str("\\040"),
+            '\t' => out.push_str("\\011"),
+            '\n' => out.push_str("\\012"),
+            '\\' => out.push_str("\\134"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn uuids() -> DiskUuids {
+        DiskUuids {
+            root_fs_uuid: "0b5c6f3e-0000-4000-8000-000000000001".to_string(),
+            efi_fs_uuid: "ABCD-1234".to_string(),
+            root_part_uuid: "0b5c6f3e-0000-4000-8000-000000000002".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_standard_layout() {
+        let temp = TempDir::new().unwrap();
+        FstabBuilder::from_uuids(&uuids())
+            .swap("/swapfile")
+            .mount("/dev/sdb1", "/srv/my data", "xfs", &[])
+            .write_to(temp.path())
+            .unwrap();
+        let fstab = fs::read_to_string(temp.path().join("etc/fstab")).unwrap();
+        let lines: Vec<_> = fstab.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "UUID=0b5c6f3e-0000-4000-8000-000000000001\t/\text4\tdefaults,noatime\t0\t1",
+                "UUID=ABCD-1234\t/boot\tvfat\tumask=0077,shortname=winnt\t0\t2",
+                "/swapfile\tnone\tswap\tdefaults\t0\t0",
+                "/dev/sdb1\t/srv/my\\040data\txfs\tdefaults\t0\t0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_mistakes() {
+        let u = uuids();
+        assert!(FstabBuilder::new().esp(&u, "/efi").build().is_err());
+        assert!(FstabBuilder::from_uuids(&u)
+            .mount("tmpfs", "/boot", "tmpfs", &[])
+            .build()
+            .is_err());
+        assert!(FstabBuilder::from_uuids(&u)
+            .mount("tmpfs", "tmp", "tmpfs", &[])
+            .build()
+            .is_err());
+        assert!(FstabBuilder::from_uuids(&u)
+            .mount("tmpfs", "/tmp", "tmpfs", &["size=1G mode=1777"])
+            .build()
+            .is_err());
+        assert!(FstabBuilder::from_uuids(&u)
+            .swap("/swap-a")
+            .swap("/swap-b")
+            .build()
+            .is_ok());
+    }
+}
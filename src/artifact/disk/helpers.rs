@@ -1,6 +1,6 @@
 //! UUID generation and host tool verification helpers for disk image building.
 
-use crate::process::Cmd;
+use crate::preflight::RequiredTools;
 use anyhow::{bail, Context, Result};
 use std::path::Path;
 use std::process::Command;
@@ -27,27 +27,20 @@ const BASE_REQUIRED_TOOLS: &[(&str, &str)] = &[
     ("dd", "coreutils"),
 ];
 
+/// Register the host tools disk image assembly invokes: the base tools
+/// plus any extras the configuration needs.
+pub fn register_host_tools(tools: &mut RequiredTools, extra_tools: &[(&str, &str)]) {
+    tools.require_all("disk", BASE_REQUIRED_TOOLS);
+    tools.require_all("disk", extra_tools);
+}
+
 /// Verify all required host tools are available.
 ///
 /// Checks base tools plus any extras specified by the caller.
 pub fn check_host_tools(extra_tools: &[(&str, &str)]) -> Result<()> {
-    let mut missing = Vec::new();
-
-    for (tool, package) in BASE_REQUIRED_TOOLS.iter().chain(extra_tools.iter()) {
-        let result = Cmd::new("which").arg(tool).allow_fail().run();
-        if result.is_err() || !result.unwrap().success() {
-            missing.push(format!("  {} (install: {})", tool, package));
-        }
-    }
-
-    if !missing.is_empty() {
-        bail!(
-            "Missing required tools:\n{}\n\nInstall them first.",
-            missing.join("\n")
-        );
-    }
-
-    Ok(())
+    let mut tools = RequiredTools::new();
+    register_host_tools(&mut tools, extra_tools);
+    tools.check()
 }
 
 /// Generate a random UUID using uuidgen.
@@ -7,15 +7,25 @@
 //! Used by both leviso (LevitateOS → qcow2) and IuppiterOS (→ raw .img).
 
 pub mod assembly;
+pub mod bootloader;
+pub mod fstab;
 pub mod helpers;
 pub mod mtools;
 pub mod partitions;
 
 pub use crate::contracts::disk::DiskImageConfig;
+pub use bootloader::Bootloader;
+pub use fstab::{FstabBuilder, FstabEntry};
 pub use helpers::{generate_disk_uuids, DiskUuids};
 
+use crate::artifact::boot_check::{verify_esp_image, BootExpectation};
+use crate::artifact::loader::validate_entry_filename;
+use crate::build::chroot::Chroot;
+use crate::error::{ErrorKind, ResultExt};
 use crate::process::Cmd;
-use anyhow::{Context, Result};
+use crate::progress::Progress;
+use crate::workspace::dir_size_bytes;
+use anyhow::{bail, Context, Result};
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -47,7 +57,12 @@ pub fn build_disk_image_with_uuids(
 
     // Step 1: Check host tools
     println!("Checking host tools...");
-    let extra = config.extra_required_tools();
+    let mut extra = config.extra_required_tools();
+    let extra_packages = config.extra_packages();
+    if !extra_packages.is_empty() {
+        extra.push(("unshare", "util-linux"));
+        extra.push(("chroot", "coreutils"));
+    }
     helpers::check_host_tools(&extra)?;
 
     // Step 2: Print UUIDs
@@ -65,40 +80,86 @@ pub fn build_disk_image_with_uuids(
     // Step 4: Copy staging to work dir and prepare rootfs
     println!("\nPreparing rootfs...");
     let rootfs_work = work_dir.join("rootfs");
+    let staging_bytes = dir_size_bytes(staging_dir)?;
     Cmd::new("cp")
         .args(["-a"])
         .arg_path(staging_dir)
         .arg_path(&rootfs_work)
+        .progress(Progress::output_size(
+            "copy rootfs",
+            &rootfs_work,
+            Some(staging_bytes),
+        ))
         .error_msg("Failed to copy rootfs-staging")
         .run()?;
 
+    config
+        .identity()
+        .apply(&rootfs_work)
+        .context("Failed to apply disk image identity")?;
     config
         .prepare_rootfs(&rootfs_work, &uuids)
         .context("Failed to prepare rootfs for disk image")?;
 
+    if !extra_packages.is_empty() {
+        println!("\nInstalling extra packages...");
+        let Some(manager) = config.package_manager() else {
+            bail!(
+                "disk image requests extra packages but no package manager\n\
+                 Remediation: return Some(..) from DiskImageConfig::package_manager"
+            );
+        };
+        Chroot::new(&rootfs_work)
+            .install_packages(manager, &extra_packages)
+            .context("Failed to install extra packages into disk image rootfs")?;
+    }
+
+    // Packages can generate host keys or a machine-id; install the
+    // first-boot service last so it sees what the image ships with.
+    if let Some(first_boot) = config.first_boot() {
+        first_boot
+            .install_into_rootfs(&rootfs_work)
+            .context("Failed to install first-boot service")?;
+    }
+
     // Step 5: Create EFI partition
     println!("\nCreating EFI partition image...");
     let efi_image = work_dir.join("efi.img");
     let efi_size_mb = config.efi_size_mb();
-    let boot_entry_content = config.boot_entry_content(&uuids.root_part_uuid);
-    let loader_config = config.loader_config_content();
+    validate_entry_filename(config.boot_entry_filename())?;
+    let boot_entry = config.loader_entry(&uuids.root_part_uuid)?;
+    let loader_conf = config.loader_conf()?;
+    let bootloader = config.bootloader();
+    println!("  Bootloader: {}", bootloader.name());
 
-    partitions::create_efi_partition(
+    partitions::create_efi_partition_with_bootloader(
         &efi_image,
         efi_size_mb,
         &uuids,
+        bootloader,
+        config.bootloader_efi_path(),
         config.boot_entry_filename(),
-        &boot_entry_content,
-        &loader_config,
+        &boot_entry,
+        &loader_conf,
         config.kernel_path(),
         config.initramfs_path(),
-        config.bootloader_efi_path(),
     )?;
 
     if let Ok(meta) = fs::metadata(&efi_image) {
         println!("  EFI partition size: {} MB", meta.len() / 1024 / 1024);
     }
 
+    // GRUB and Limine configs are rendered from the same entry; only
+    // systemd-boot leaves loader entries that can be read back.
+    if matches!(bootloader, Bootloader::SystemdBoot) {
+        println!("  Verifying boot entry against kernel and initramfs...");
+        let expect = BootExpectation::new()
+            .kernel_image(config.kernel_path())?
+            .initrd_file(config.initramfs_path())?
+            .require_cmdline(config.boot_entry_filename(), &["root"]);
+        verify_esp_image(&efi_image, &expect)?;
+    }
+
     // Step 6: Create root partition
     println!("\nCreating root partition image...");
     let root_image = work_dir.join("root.img");
@@ -150,5 +211,13 @@ pub fn build_disk_image_with_uuids(
         println!("  Size: {} MB (sparse)", meta.len() / 1024 / 1024);
     }
 
+    // Step 10: Boot the image, when the distro asks for it
+    if let Some(test) = config.boot_test() {
+        println!("\n=== Booting Disk Image ===\n");
+        test.run(&output_path)
+            .with_context(|| format!("Boot test of {} failed", output_path.display()))
+            .error_kind(ErrorKind::BootTestFailed)?;
+    }
+
     Ok(output_path)
 }
@@ -76,3 +76,15 @@ mod tests {
         assert!(true);
     }
 }
+
+/// Copy the whole FAT image into `dest` (an existing directory).
+pub fn mtools_extract_all(image: &Path, dest: &Path) -> Result<()> {
+    Cmd::new("mcopy")
+        .args(["-s", "-n", "-i"])
+        .arg_path(image)
+        .arg("::*")
+        .arg_path(dest)
+        .error_msg(format!("mcopy failed to extract {}", image.display()))
+        .run()?;
+    Ok(())
+}
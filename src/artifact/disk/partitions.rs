@@ -1,7 +1,9 @@
 //! EFI and root partition creation for disk images.
 
+use super::bootloader::Bootloader;
 use super::helpers::DiskUuids;
-use super::mtools;
+use crate::artifact::esp::EspLayout;
+use crate::artifact::loader::{LoaderConf, LoaderEntry};
 use crate::process::Cmd;
 use anyhow::Result;
 use std::fs;
@@ -11,7 +13,7 @@ use std::path::Path;
 ///
 /// The caller provides boot entry content, loader config, kernel/initramfs paths,
 /// and the systemd-boot EFI binary path. This function creates a FAT32 image
-/// with the standard EFI directory structure.
+/// laid out by [`EspLayout`].
 #[allow(clippy::too_many_arguments)]
 pub fn create_efi_partition(
     image_path: &Path,
@@ -24,6 +26,53 @@ pub fn create_efi_partition(
     initramfs_path: &Path,
     bootloader_efi_path: &Path,
 ) -> Result<()> {
+    format_efi_image(image_path, efi_size_mb, uuids)?;
+
+    EspLayout::new()
+        .systemd_boot(bootloader_efi_path)
+        .loader_conf(loader_config_content)
+        .entry(boot_entry_filename, boot_entry_content)
+        .file(kernel_path, "vmlinuz")
+        .file(initramfs_path, "initramfs.img")
+        .render_to_fat(image_path)?;
+
+    Ok(())
+}
+
+/// Create an EFI partition image for any supported [`Bootloader`].
+///
+/// Same as [`create_efi_partition`], but the entry and loader config are
+/// typed and rendered into whichever config format `bootloader` reads.
+#[allow(clippy::too_many_arguments)]
+pub fn create_efi_partition_with_bootloader(
+    image_path: &Path,
+    efi_size_mb: u64,
+    uuids: &DiskUuids,
+    bootloader: Bootloader,
+    bootloader_efi_path: &Path,
+    boot_entry_filename: &str,
+    boot_entry: &LoaderEntry,
+    loader_conf: &LoaderConf,
+    kernel_path: &Path,
+    initramfs_path: &Path,
+) -> Result<()> {
+    format_efi_image(image_path, efi_size_mb, uuids)?;
+
+    let layout = EspLayout::new()
+        .file(kernel_path, "vmlinuz")
+        .file(initramfs_path, "initramfs.img");
+    bootloader
+        .populate_esp(
+            layout,
+            bootloader_efi_path,
+            boot_entry_filename,
+            boot_entry,
+            loader_conf,
+        )?
+        .render_to_fat(image_path)
+}
+
+fn format_efi_image(image_path: &Path, efi_size_mb: u64, uuids: &DiskUuids) -> Result<()> {
     // Create sparse image file
     let size_bytes = efi_size_mb * 1024 * 1024;
     {
@@ -38,33 +87,6 @@ pub fn create_efi_partition(
         .arg_path(image_path)
         .error_msg("mkfs.vfat failed")
         .run()?;
-
-    // Create directory structure using mtools
-    mtools::mtools_mkdir(image_path, "EFI")?;
-    mtools::mtools_mkdir(image_path, "EFI/BOOT")?;
-    mtools::mtools_mkdir(image_path, "EFI/systemd")?;
-    mtools::mtools_mkdir(image_path, "loader")?;
-    mtools::mtools_mkdir(image_path, "loader/entries")?;
-
-    // Copy systemd-boot EFI binary
-    mtools::mtools_copy(image_path, bootloader_efi_path, "EFI/BOOT/BOOTX64.EFI")?;
-    mtools::mtools_copy(
-        image_path,
-        bootloader_efi_path,
-        "EFI/systemd/systemd-bootx64.efi",
-    )?;
-
-    // Write loader.conf
-    mtools::mtools_write_file(image_path, "loader/loader.conf", loader_config_content)?;
-
-    // Write boot entry
-    let entry_path = format!("loader/entries/{}", boot_entry_filename);
-    mtools::mtools_write_file(image_path, &entry_path, boot_entry_content)?;
-
-    // Copy kernel and initramfs
-    mtools::mtools_copy(image_path, kernel_path, "vmlinuz")?;
-    mtools::mtools_copy(image_path, initramfs_path, "initramfs.img")?;
-
     Ok(())
 }
 
@@ -0,0 +1,373 @@
+//! EFI System Partition layout.
+//!
+//! ISO El Torito images and disk EFI partitions used to be populated by
+//! separate code paths (plus variant shell hooks), each with its own idea of
+//! where the bootloader, fallback loader, and entries live. [`EspLayout`]
+//! describes the ESP once and renders it either into a FAT image (mtools,
+//! no root needed) or into a plain directory.
+//!
+//! ```text
+//! EFI/BOOT/BOOTX64.EFI             fallback loader (bootloader, or shim)
+//! EFI/BOOT/grubx64.efi             second stage when shim is the fallback
+//! EFI/BOOT/mmx64.efi               MokManager when shim is used
+//! EFI/systemd/systemd-bootx64.efi  systemd-boot
+//! EFI/Linux/*.efi                  UKIs (auto-discovered by systemd-boot)
+//! loader/loader.conf
+//! loader/entries/*.conf
+//! ```
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::artifact::disk::mtools;
+use crate::secureboot::SecureBootKeys;
+
+/// Removable-media fallback loader path.
+pub const FALLBACK_LOADER: &str = "EFI/BOOT/BOOTX64.EFI";
+/// systemd-boot install path.
+pub const SYSTEMD_BOOT_PATH: &str = "EFI/systemd/systemd-bootx64.efi";
+/// Second stage shim chain-loads by default.
+pub const SHIM_SECOND_STAGE: &str = "EFI/BOOT/grubx64.efi";
+/// MokManager path shim expects next to itself.
+pub const MOK_MANAGER_PATH: &str = "EFI/BOOT/mmx64.efi";
+/// Directory systemd-boot scans for Type #2 (UKI) entries.
+pub const UKI_DIR: &str = "EFI/Linux";
+
+/// One file placed on the ESP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EspFile {
+    Copy { src: PathBuf, dest: String },
+    Write { dest: String, content: String },
+}
+
+impl EspFile {
+    pub fn dest(&self) -> &str {
+        match self {
+            EspFile::Copy { dest, .. } | EspFile::Write { dest, .. } => dest,
+        }
+    }
+}
+
+/// Builder describing the contents of an ESP.
+#[derive(Debug, Clone, Default)]
+pub struct EspLayout {
+    bootloader: Option<PathBuf>,
+    shim: Option<(PathBuf, PathBuf)>,
+    mok_cert: Option<PathBuf>,
+    loader_conf: Option<String>,
+    entries: Vec<(String, String)>,
+    ukis: Vec<(PathBuf, String)>,
+    files: Vec<EspFile>,
+    signing_keys: Option<SecureBootKeys>,
+}
+
+impl EspLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// systemd-boot binary; also used as the fallback loader unless shim is set.
+    pub fn systemd_boot(mut self, efi: &Path) -> Self {
+        self.bootloader = Some(efi.to_path_buf());
+        self
+    }
+
+    /// Boot through shim: `shim` becomes the fallback loader, chain-loading
+    /// the bootloader as `grubx64.efi`, with `mok_manager` next to it.
+    pub fn shim(mut self, shim: &Path, mok_manager: &Path) -> Self {
+        self.shim = Some((shim.to_path_buf(), mok_manager.to_path_buf()));
+        self
+    }
+
+    /// DER certificate shipped on the ESP for `mokutil --import`.
+    pub fn mok_cert(mut self, der: &Path) -> Self {
+        self.mok_cert = Some(der.to_path_buf());
+        self
+    }
+
+    pub fn loader_conf(mut self, content: impl Into<String>) -> Self {
+        self.loader_conf = Some(content.into());
+        self
+    }
+
+    /// A Type #1 entry under `loader/entries/`.
+    pub fn entry(mut self, filename: impl Into<String>, content: impl Into<String>) -> Self {
+        self.entries.push((filename.into(), content.into()));
+        self
+    }
+
+    /// A UKI under `EFI/Linux/`.
+    pub fn uki(mut self, src: &Path, filename: impl Into<String>) -> Self {
+        self.ukis.push((src.to_path_buf(), filename.into()));
+        self
+    }
+
+    /// Any other file, e.g. a kernel referenced by an entry.
+    pub fn file(mut self, src: &Path, dest: impl Into<String>) -> Self {
+        self.files.push(EspFile::Copy {
+            src: src.to_path_buf(),
+            dest: dest.into(),
+        });
+        self
+    }
+
+    /// A generated file, e.g. a `grub.cfg` next to the loader.
+    pub fn write(mut self, dest: impl Into<String>, content: impl Into<String>) -> Self {
+        self.files.push(EspFile::Write {
+            dest: dest.into(),
+            content: content.into(),
+        });
+        self
+    }
+
+    /// Sign the EFI binaries placed on the ESP with the db key of `keys`.
+    /// shim and MokManager keep their vendor signature.
+    pub fn sign_with(mut self, keys: SecureBootKeys) -> Self {
+        self.signing_keys = Some(keys);
+        self
+    }
+
+    /// Whether rendering signs the file placed at `dest`.
+    pub fn signs(&self, dest: &str) -> bool {
+        let dest = dest.to_ascii_lowercase();
+        self.signing_keys.is_some()
+            && dest.ends_with(".efi")
+            && dest != MOK_MANAGER_PATH.to_ascii_lowercase()
+            && !(self.shim.is_some() && dest == FALLBACK_LOADER.to_ascii_lowercase())
+    }
+
+    /// Resolve the layout into the ordered list of files to place.
+    pub fn files(&self) -> Result<Vec<EspFile>> {
+        let mut out = Vec::new();
+        match (&self.bootloader, &self.shim) {
+            (Some(loader), Some((shim, mok_manager))) => {
+                out.push(copy(shim, FALLBACK_LOADER));
+                out.push(copy(mok_manager, MOK_MANAGER_PATH));
+                out.push(copy(loader, SHIM_SECOND_STAGE));
+                out.push(copy(loader, SYSTEMD_BOOT_PATH));
+            }
+            (Some(loader), None) => {
+                out.push(copy(loader, FALLBACK_LOADER));
+                out.push(copy(loader, SYSTEMD_BOOT_PATH));
+            }
+            (None, Some(_)) => bail!("ESP layout uses shim but has no bootloader to chain-load"),
+            (None, None) => {}
+        }
+        if let Some(der) = &self.mok_cert {
+            out.push(copy(der, "EFI/BOOT/MOK.der"));
+        }
+        if let Some(content) = &self.loader_conf {
+            out.push(EspFile::Write {
+                dest: "loader/loader.conf".to_string(),
+                content: content.clone(),
+            });
+        }
+        for (filename, content) in &self.entries {
+            out.push(EspFile::Write {
+                dest: format!("loader/entries/{}", filename),
+                content: content.clone(),
+            });
+        }
+        for (src, filename) in &self.ukis {
+            out.push(copy(src, &format!("{}/{}", UKI_DIR, filename)));
+        }
+        out.extend(self.files.iter().cloned());
+
+        let mut seen = BTreeSet::new();
+        for file in &out {
+            let dest = file.dest();
+            if dest.starts_with('/') || dest.split('/').any(|part| part == "..") {
+                bail!("ESP destination '{}' must be a relative path", dest);
+            }
+            if !seen.insert(dest.to_ascii_lowercase()) {
+                // FAT is case-insensitive, so `BOOTX64.EFI` and `bootx64.efi` collide.
+                bail!("ESP layout places '{}' more than once", dest);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Directories that must exist, parents first.
+    pub fn dirs(&self) -> Result<Vec<String>> {
+        let mut dirs = BTreeSet::new();
+        for file in self.files()? {
+            let mut path = Path::new(file.dest()).parent();
+            while let Some(dir) = path.filter(|p| !p.as_os_str().is_empty()) {
+                dirs.insert(dir.to_string_lossy().to_string());
+                path = dir.parent();
+            }
+        }
+        Ok(dirs.into_iter().collect())
+    }
+
+    /// Total bytes of file content, for sizing the FAT image.
+    pub fn content_bytes(&self) -> Result<u64> {
+        let mut total = 0;
+        for file in self.files()? {
+            total += match file {
+                EspFile::Copy { src, .. } => fs::metadata(&src)
+                    .with_context(|| format!("Failed to stat {}", src.display()))?
+                    .len(),
+                EspFile::Write { content, .. } => content.len() as u64,
+            };
+        }
+        Ok(total)
+    }
+
+    /// Populate an already formatted FAT image.
+    pub fn render_to_fat(&self, image: &Path) -> Result<()> {
+        let files = self.files()?;
+        for dir in self.dirs()? {
+            mtools::mtools_mkdir(image, &dir)?;
+        }
+        let signed = image.with_extension("signed.efi");
+        for file in files {
+            match file {
+                EspFile::Copy { src, dest } => match &self.signing_keys {
+                    Some(keys) if self.signs(&dest) => {
+                        keys.sign_efi(&src, &signed)?;
+                        let copied = mtools::mtools_copy(image, &signed, &dest);
+                        let _ = fs::remove_file(&signed);
+                        copied?;
+                    }
+                    _ => mtools::mtools_copy(image, &src, &dest)?,
+                },
+                EspFile::Write { dest, content } => {
+                    mtools::mtools_write_file(image, &dest, &content)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Populate a directory (e.g. an ISO root or a mounted ESP).
+    pub fn render_to_dir(&self, root: &Path) -> Result<()> {
+        let files = self.files()?;
+        for dir in self.dirs()? {
+            fs::create_dir_all(root.join(&dir))?;
+        }
+        for file in files {
+            let dest = root.join(file.dest());
+            match file {
+                EspFile::Copy {
+                    src,
+                    dest: esp_dest,
+                } if self.signs(&esp_dest) => {
+                    if let Some(keys) = &self.signing_keys {
+                        keys.sign_efi(&src, &dest)?;
+                    }
+                }
+                EspFile::Copy { src, .. } => {
+                    fs::copy(&src, &dest).with_context(|| {
+                        format!("Failed to copy {} to {}", src.display(), dest.display())
+                    })?;
+                }
+                EspFile::Write { content, .. } => {
+                    fs::write(&dest, content)
+                        .with_context(|| format!("Failed to write {}", dest.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn copy(src: &Path, dest: &str) -> EspFile {
+    EspFile::Copy {
+        src: src.to_path_buf(),
+        dest: dest.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_shim_layout() {
+        let layout = EspLayout::new()
+            .systemd_boot(Path::new("/usr/lib/systemd/boot/efi/systemd-bootx64.efi"))
+            .shim(Path::new("/shim/shimx64.efi"), Path::new("/shim/mmx64.efi"))
+            .mok_cert(Path::new("/keys/db.der"));
+        let dests: Vec<String> = layout
+            .files()
+            .unwrap()
+            .iter()
+            .map(|f| f.dest().to_string())
+            .collect();
+        assert_eq!(
+            dests,
+            vec![
+                FALLBACK_LOADER,
+                MOK_MANAGER_PATH,
+                SHIM_SECOND_STAGE,
+                SYSTEMD_BOOT_PATH,
+                "EFI/BOOT/MOK.der"
+            ]
+        );
+        assert_eq!(
+            layout.dirs().unwrap(),
+            vec!["EFI", "EFI/BOOT", "EFI/systemd"]
+        );
+    }
+
+    #[test]
+    fn test_signed_files() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("GUID.txt"), "owner\n").unwrap();
+        for stem in ["PK", "KEK", "db"] {
+            for ext in ["key", "crt", "der", "esl"] {
+                fs::write(temp.path().join(format!("{}.{}", stem, ext)), "").unwrap();
+            }
+        }
+        let keys = SecureBootKeys::open(temp.path()).unwrap();
+
+        let plain = EspLayout::new().systemd_boot(Path::new("/boot.efi"));
+        assert!(!plain.signs(FALLBACK_LOADER));
+        let direct = plain.sign_with(keys.clone());
+        assert!(direct.signs(FALLBACK_LOADER));
+        assert!(direct.signs("EFI/Linux/live.efi"));
+        assert!(!direct.signs("loader/loader.conf"));
+        let shim = direct.shim(Path::new("/shim.efi"), Path::new("/mm.efi"));
+        assert!(!shim.signs(FALLBACK_LOADER));
+        assert!(!shim.signs(MOK_MANAGER_PATH));
+        assert!(shim.signs(SHIM_SECOND_STAGE));
+        assert!(shim.signs(SYSTEMD_BOOT_PATH));
+    }
+
+    #[test]
+    fn test_case_insensitive_collision() {
+        let layout = EspLayout::new()
+            .systemd_boot(Path::new("/boot.efi"))
+            .file(Path::new("/other.efi"), "EFI/boot/bootx64.efi");
+        assert!(layout.files().is_err());
+    }
+
+    #[test]
+    fn test_render_to_dir() {
+        let temp = TempDir::new().unwrap();
+        let loader = temp.path().join("systemd-bootx64.efi");
+        let uki = temp.path().join("uki.efi");
+        fs::write(&loader, "loader").unwrap();
+        fs::write(&uki, "uki").unwrap();
+
+        let root = temp.path().join("esp");
+        EspLayout::new()
+            .systemd_boot(&loader)
+            .loader_conf("timeout 3\n")
+            .entry("live.conf", "title Live\n")
+            .uki(&uki, "levitate.efi")
+            .render_to_dir(&root)
+            .unwrap();
+
+        assert!(root.join(FALLBACK_LOADER).is_file());
+        assert!(root.join("EFI/Linux/levitate.efi").is_file());
+        assert_eq!(
+            fs::read_to_string(root.join("loader/entries/live.conf")).unwrap(),
+            "title Live\n"
+        );
+    }
+}
@@ -2,9 +2,16 @@
 //!
 //! Common filesystem operations used during ISO, initramfs, and EROFS artifact creation.
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use std::ffi::CString;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::parallel;
 
 /// Recursively copy a directory, preserving symlinks.
 ///
@@ -14,7 +21,9 @@ use std::path::Path;
 /// - File permissions
 ///
 /// This uses the overwrite variant from `leviso-elf`, which replaces existing
-/// symlinks. This is the desired behavior for overlay creation.
+/// symlinks. This is the desired behavior for overlay creation. When
+/// [`parallel::IO_THREADS_ENV`] asks for more than one thread, the copy runs
+/// through [`copy_dir_parallel`] instead.
 ///
 /// # Arguments
 ///
@@ -33,12 +42,111 @@ use std::path::Path;
 /// )?;
 /// ```
 pub fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
+    let threads = parallel::io_threads()?;
+    if threads > 1 {
+        return copy_dir_parallel(src, dst, threads);
+    }
     // Use leviso-elf's overwrite variant (replaces existing symlinks)
     // and discard the byte count since callers expect Result<()>
     leviso_elf::copy_dir_recursive_overwrite(src, dst)?;
     Ok(())
 }
 
+/// [`copy_dir_recursive`] with file contents copied on `threads` threads.
+///
+/// Directories and symlinks are created first, in walk order; regular files
+/// are then copied concurrently with their permission bits, and directory
+/// permissions are applied last so read-only directories can be filled.
+/// FIFOs, sockets and device nodes are recreated with `mknod` rather than
+/// read. Existing symlinks at a destination are replaced rather than
+/// followed.
+pub fn copy_dir_parallel(src: &Path, dst: &Path, threads: usize) -> Result<()> {
+    if !src.is_dir() {
+        bail!("Source is not a directory: {}", src.display());
+    }
+    let mut files: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut dirs = Vec::new();
+    for entry in WalkDir::new(src).follow_links(false).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to walk {}", src.display()))?;
+        let rel = entry.path().strip_prefix(src).unwrap_or(entry.path());
+        let target = dst.join(rel);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)
+                .with_context(|| format!("Failed to create {}", target.display()))?;
+            let meta = entry
+                .metadata()
+                .with_context(|| format!("Failed to stat {}", entry.path().display()))?;
+            dirs.push((target, meta.permissions()));
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(entry.path())
+                .with_context(|| format!("Failed to read link {}", entry.path().display()))?;
+            remove_non_dir(&target)?;
+            symlink(&link, &target)
+                .with_context(|| format!("Failed to create symlink {}", target.display()))?;
+        } else if file_type.is_file() {
+            files.push((entry.into_path(), target));
+        } else {
+            copy_special(entry.path(), &target)?;
+        }
+    }
+    parallel::try_map(&files, threads, |(from, to)| {
+        remove_symlink(to)?;
+        fs::copy(from, to)
+            .with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+        Ok(())
+    })?;
+    for (dir, permissions) in dirs.into_iter().rev() {
+        fs::set_permissions(&dir, permissions)
+            .with_context(|| format!("Failed to set permissions on {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Recreate the FIFO, socket or device node `from` at `to`. Device nodes
+/// need `CAP_MKNOD`.
+fn copy_special(from: &Path, to: &Path) -> Result<()> {
+    let meta =
+        fs::symlink_metadata(from).with_context(|| format!("Failed to stat {}", from.display()))?;
+    remove_non_dir(to)?;
+    let to_c = CString::new(to.as_os_str().as_bytes())
+        .with_context(|| format!("Path contains a NUL byte: {}", to.display()))?;
+    // SAFETY: `to_c` is NUL-terminated and outlives the call.
+    let rc = unsafe { libc::mknod(to_c.as_ptr(), meta.mode(), meta.rdev()) };
+    if rc != 0 {
+        return Err(io::Error::last_os_error()).with_context(|| {
+            format!(
+                "Failed to recreate special file {} at {}",
+                from.display(),
+                to.display()
+            )
+        });
+    }
+    // mknod applies the umask.
+    fs::set_permissions(to, meta.permissions())
+        .with_context(|| format!("Failed to set permissions on {}", to.display()))
+}
+
+/// Remove whatever non-directory entry is at `path`.
+fn remove_non_dir(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if !meta.is_dir() => {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Remove `path` if it is a symlink, so a copy does not write through it.
+fn remove_symlink(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Create initramfs directory structure.
 ///
 /// Creates the minimal directory structure needed for a Linux initramfs:
@@ -104,6 +212,7 @@ pub fn atomic_move(src: &Path, dst: &Path) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::{hash_tree_with_threads, TreeHashMode};
     use tempfile::TempDir;
 
     #[test]
@@ -131,6 +240,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_copy_dir_parallel_matches_serial() {
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().join("src");
+        for i in 0..50 {
+            let dir = src.join(format!("d{}", i % 5));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("f{}", i)), format!("file {}", i)).unwrap();
+        }
+        std::os::unix::fs::symlink("d0/f0", src.join("link")).unwrap();
+
+        let serial = temp.path().join("serial");
+        let parallel = temp.path().join("parallel");
+        copy_dir_recursive(&src, &serial).unwrap();
+        // A stale symlink at a file destination is replaced, not followed.
+        fs::create_dir_all(parallel.join("d1")).unwrap();
+        std::os::unix::fs::symlink("/etc/hostname", parallel.join("d1/f1")).unwrap();
+        copy_dir_parallel(&src, &parallel, 4).unwrap();
+
+        assert_eq!(
+            hash_tree_with_threads(&serial, TreeHashMode::Content, 1).unwrap(),
+            hash_tree_with_threads(&parallel, TreeHashMode::Content, 4).unwrap()
+        );
+        assert!(!parallel.join("d1/f1").is_symlink());
+    }
+
+    #[test]
+    fn test_copy_dir_parallel_recreates_special_files() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().join("src");
+        fs::create_dir_all(src.join("dev")).unwrap();
+        fs::write(src.join("file"), "data").unwrap();
+        let fifo = CString::new(src.join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        let as_root = unsafe { libc::geteuid() } == 0;
+        if as_root {
+            let null = CString::new(src.join("dev/null").as_os_str().as_bytes()).unwrap();
+            let rdev = libc::makedev(1, 3);
+            assert_eq!(
+                unsafe { libc::mknod(null.as_ptr(), libc::S_IFCHR | 0o666, rdev) },
+                0
+            );
+        }
+
+        // Opening the FIFO for a content copy would block forever.
+        let dst = temp.path().join("dst");
+        copy_dir_parallel(&src, &dst, 4).unwrap();
+
+        let meta = fs::symlink_metadata(dst.join("fifo")).unwrap();
+        assert!(meta.file_type().is_fifo());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "data");
+        if as_root {
+            let meta = fs::symlink_metadata(dst.join("dev/null")).unwrap();
+            assert!(meta.file_type().is_char_device());
+            assert_eq!(meta.rdev(), libc::makedev(1, 3));
+        }
+    }
+
     #[test]
     fn test_create_initramfs_dirs() {
         let temp = TempDir::new().unwrap();
@@ -0,0 +1,531 @@
+//! Mount-free listing of EROFS and squashfs images.
+//!
+//! Evidence checks ("does the ISO's rootfs contain
+//! `/usr/lib/systemd/systemd`?") and image diffs only need the directory
+//! tree, not file data. EROFS metadata is read directly: the superblock, the
+//! inode table and the (uncompressed) directory blocks. Regular file
+//! contents are never touched, so compressed images list as fast as plain
+//! ones. Squashfs images are listed through `unsquashfs -lln`, which also
+//! reads metadata only.
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::process::{self, Cmd};
+
+const EROFS_SUPER_OFFSET: u64 = 1024;
+const EROFS_MAGIC: u32 = 0xE0F5_E1E2;
+const SQUASHFS_MAGIC: &[u8; 4] = b"hsqs";
+
+const INODE_SLOT_SIZE: u64 = 32;
+const DIRENT_SIZE: usize = 12;
+
+const LAYOUT_FLAT_PLAIN: u16 = 0;
+const LAYOUT_FLAT_INLINE: u16 = 2;
+
+/// Upper bound on directory depth, guarding against corrupt images with
+/// directory cycles.
+const MAX_DEPTH: usize = 256;
+
+/// File type of an image entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink { target: String },
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+/// One entry of an image, with an absolute path such as `/usr/bin/sh`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    /// Permission bits including setuid/setgid/sticky.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Size in bytes for files and symlinks, 0 otherwise.
+    pub size: u64,
+}
+
+/// List every entry of an EROFS or squashfs image, sorted by path.
+///
+/// The root directory itself is not included.
+pub fn list_contents(image: &Path) -> Result<Vec<ImageEntry>> {
+    let mut file =
+        File::open(image).with_context(|| format!("Failed to open {}", image.display()))?;
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)
+        .with_context(|| format!("Failed to read {}", image.display()))?;
+    let mut entries = if &magic == SQUASHFS_MAGIC {
+        list_squashfs(image)?
+    } else {
+        ErofsReader::new(file)
+            .and_then(|mut reader| reader.list())
+            .with_context(|| format!("Failed to list EROFS image {}", image.display()))?
+    };
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Whether `path` (absolute, e.g. `/usr/lib/systemd/systemd`) is in `entries`.
+pub fn contains_path(entries: &[ImageEntry], path: &str) -> bool {
+    let wanted = format!("/{}", path.trim_matches('/'));
+    entries
+        .binary_search_by(|entry| entry.path.as_str().cmp(&wanted))
+        .is_ok()
+}
+
+struct ErofsReader<R> {
+    image: R,
+    block_size: u64,
+    meta_base: u64,
+    root_nid: u64,
+}
+
+struct ErofsInode {
+    offset: u64,
+    /// Inode plus xattr area; inline data starts right after.
+    header_len: u64,
+    layout: u16,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    raw_blkaddr: u64,
+}
+
+impl<R: Read + Seek> ErofsReader<R> {
+    fn new(mut image: R) -> Result<Self> {
+        let mut sb = [0u8; 128];
+        image.seek(SeekFrom::Start(EROFS_SUPER_OFFSET))?;
+        image
+            .read_exact(&mut sb)
+            .context("image is too small for an EROFS superblock")?;
+        if le32(&sb, 0) != EROFS_MAGIC {
+            bail!("not an EROFS or squashfs image (bad magic)");
+        }
+        let blkszbits = sb[12];
+        if !(9..=16).contains(&blkszbits) {
+            bail!("unsupported EROFS block size 2^{}", blkszbits);
+        }
+        let block_size = 1u64 << blkszbits;
+        Ok(Self {
+            image,
+            block_size,
+            meta_base: u64::from(le32(&sb, 40)) * block_size,
+            root_nid: u64::from(le16(&sb, 14)),
+        })
+    }
+
+    fn list(&mut self) -> Result<Vec<ImageEntry>> {
+        let mut entries = Vec::new();
+        let root = self.inode(self.root_nid)?;
+        if root.mode & S_IFMT != S_IFDIR {
+            bail!("EROFS root inode is not a directory");
+        }
+        self.walk(&root, "", 0, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn walk(
+        &mut self,
+        dir: &ErofsInode,
+        prefix: &str,
+        depth: usize,
+        entries: &mut Vec<ImageEntry>,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            bail!(
+                "directory nesting deeper than {} at '{}'",
+                MAX_DEPTH,
+                prefix
+            );
+        }
+        let data = self
+            .read_data(dir)
+            .with_context(|| format!("reading directory '{}/'", prefix))?;
+        for (nid, name) in parse_dir_data(&data, self.block_size as usize)? {
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = format!("{}/{}", prefix, name);
+            let inode = self.inode(nid)?;
+            let kind = match inode.mode & S_IFMT {
+                S_IFREG => EntryKind::File,
+                S_IFDIR => EntryKind::Dir,
+                S_IFLNK => {
+                    let target = self
+                        .read_data(&inode)
+                        .with_context(|| format!("reading symlink '{}'", path))?;
+                    EntryKind::Symlink {
+                        target: String::from_utf8_lossy(&target).into_owned(),
+                    }
+                }
+                S_IFCHR => EntryKind::CharDevice,
+                S_IFBLK => EntryKind::BlockDevice,
+                S_IFIFO => EntryKind::Fifo,
+                S_IFSOCK => EntryKind::Socket,
+                other => bail!("unknown file type {:o} for '{}'", other, path),
+            };
+            let is_dir = kind == EntryKind::Dir;
+            entries.push(ImageEntry {
+                path: path.clone(),
+                size: match kind {
+                    EntryKind::File | EntryKind::Symlink { .. } => inode.size,
+                    _ => 0,
+                },
+                kind,
+                mode: inode.mode & 0o7777,
+                uid: inode.uid,
+                gid: inode.gid,
+            });
+            if is_dir {
+                self.walk(&inode, &path, depth + 1, entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn inode(&mut self, nid: u64) -> Result<ErofsInode> {
+        let offset = self.meta_base + nid * INODE_SLOT_SIZE;
+        let mut raw = [0u8; 64];
+        self.image.seek(SeekFrom::Start(offset))?;
+        self.image
+            .read_exact(&mut raw[..32])
+            .with_context(|| format!("reading inode {}", nid))?;
+        let format = le16(&raw, 0);
+        let extended = format & 1 == 1;
+        let layout = (format >> 1) & 0x7;
+        let xattr_icount = u64::from(le16(&raw, 2));
+        let xattr_len = if xattr_icount == 0 {
+            0
+        } else {
+            12 + (xattr_icount - 1) * 4
+        };
+        let mode = u32::from(le16(&raw, 4));
+        let raw_blkaddr = u64::from(le32(&raw, 16));
+        let (inode_len, size, uid, gid) = if extended {
+            self.image
+                .read_exact(&mut raw[32..])
+                .with_context(|| format!("reading extended inode {}", nid))?;
+            (64, le64(&raw, 8), le32(&raw, 24), le32(&raw, 28))
+        } else {
+            (
+                32,
+                u64::from(le32(&raw, 8)),
+                u32::from(le16(&raw, 24)),
+                u32::from(le16(&raw, 26)),
+            )
+        };
+        Ok(ErofsInode {
+            offset,
+            header_len: inode_len + xattr_len,
+            layout,
+            mode,
+            uid,
+            gid,
+            size,
+            raw_blkaddr,
+        })
+    }
+
+    /// Data of an uncompressed inode (directories and symlinks).
+    fn read_data(&mut self, inode: &ErofsInode) -> Result<Vec<u8>> {
+        let size = usize::try_from(inode.size).context("inode size overflows")?;
+        let mut data = vec![0u8; size];
+        match inode.layout {
+            LAYOUT_FLAT_PLAIN => {
+                self.image
+                    .seek(SeekFrom::Start(inode.raw_blkaddr * self.block_size))?;
+                self.image.read_exact(&mut data)?;
+            }
+            LAYOUT_FLAT_INLINE => {
+                let block_len = (inode.size / self.block_size * self.block_size) as usize;
+                if block_len > 0 {
+                    self.image
+                        .seek(SeekFrom::Start(inode.raw_blkaddr * self.block_size))?;
+                    self.image.read_exact(&mut data[..block_len])?;
+                }
+                self.image
+                    .seek(SeekFrom::Start(inode.offset + inode.header_len))?;
+                self.image.read_exact(&mut data[block_len..])?;
+            }
+            other => bail!(
+                "inode data layout {} is not supported for directories or symlinks",
+                other
+            ),
+        }
+        Ok(data)
+    }
+}
+
+/// Split directory data into `(nid, name)` pairs, block by block.
+fn parse_dir_data(data: &[u8], block_size: usize) -> Result<Vec<(u64, String)>> {
+    let mut out = Vec::new();
+    for block in data.chunks(block_size) {
+        if block.len() < DIRENT_SIZE {
+            bail!("truncated directory block");
+        }
+        let first_nameoff = usize::from(le16(block, 8));
+        if first_nameoff < DIRENT_SIZE
+            || first_nameoff % DIRENT_SIZE != 0
+            || first_nameoff > block.len()
+        {
+            bail!("corrupt directory block (name offset {})", first_nameoff);
+        }
+        let count = first_nameoff / DIRENT_SIZE;
+        for i in 0..count {
+            let dirent = &block[i * DIRENT_SIZE..];
+            let nid = le64(dirent, 0);
+            let start = usize::from(le16(dirent, 8));
+            let end = if i + 1 < count {
+                usize::from(le16(&block[(i + 1) * DIRENT_SIZE..], 8))
+            } else {
+                block.len()
+            };
+            if start > end || end > block.len() {
+                bail!("corrupt directory entry name bounds");
+            }
+            let raw = &block[start..end];
+            let raw = raw.split(|b| *b == 0).next().unwrap_or(raw);
+            out.push((nid, String::from_utf8_lossy(raw).into_owned()));
+        }
+    }
+    Ok(out)
+}
+
+const S_IFMT: u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFIFO: u32 = 0o010000;
+
+fn le16(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+fn le32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().expect("4 bytes"))
+}
+
+fn le64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().expect("8 bytes"))
+}
+
+fn list_squashfs(image: &Path) -> Result<Vec<ImageEntry>> {
+    if !process::exists("unsquashfs") {
+        bail!("unsquashfs not found. Install squashfs-tools to list squashfs images.");
+    }
+    let result = Cmd::new("unsquashfs")
+        .args(["-lln", "-d", ""])
+        .arg_path(image)
+        .error_msg(format!("unsquashfs failed listing {}", image.display()))
+        .run()?;
+    Ok(result
+        .stdout
+        .lines()
+        .filter_map(parse_unsquashfs_line)
+        .collect())
+}
+
+/// Parse one `unsquashfs -lln -d ''` line, e.g.
+/// `lrwxrwxrwx 0/0 7 2024-01-01 00:00 /bin -> usr/bin`.
+fn parse_unsquashfs_line(line: &str) -> Option<ImageEntry> {
+    // Size is one token, or "major, minor" for devices; the path follows
+    // the date and time tokens.
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let perms = *tokens.first()?;
+    let (uid, gid) = tokens.get(1)?.split_once('/')?;
+    let date_idx = tokens
+        .iter()
+        .position(|t| t.len() == 10 && t.as_bytes()[4] == b'-' && t.as_bytes()[7] == b'-')?;
+    let time = tokens.get(date_idx + 1)?;
+    let time_end = line.find(&format!("{} {} ", tokens[date_idx], time))?
+        + tokens[date_idx].len()
+        + time.len()
+        + 2;
+    let rest = &line[time_end..];
+    if rest.is_empty() || rest == "/" {
+        return None;
+    }
+    let kind_char = perms.chars().next()?;
+    let (path, kind) = match kind_char {
+        'l' => {
+            let (path, target) = rest.split_once(" -> ")?;
+            (
+                path,
+                EntryKind::Symlink {
+                    target: target.to_string(),
+                },
+            )
+        }
+        'd' => (rest, EntryKind::Dir),
+        'c' => (rest, EntryKind::CharDevice),
+        'b' => (rest, EntryKind::BlockDevice),
+        'p' => (rest, EntryKind::Fifo),
+        's' => (rest, EntryKind::Socket),
+        _ => (rest, EntryKind::File),
+    };
+    let size = match kind {
+        EntryKind::File | EntryKind::Symlink { .. } => {
+            tokens[2..date_idx].join("").parse().unwrap_or(0)
+        }
+        _ => 0,
+    };
+    Some(ImageEntry {
+        path: format!("/{}", path.trim_start_matches('/')),
+        kind,
+        mode: parse_perms(perms),
+        uid: uid.parse().ok()?,
+        gid: gid.parse().ok()?,
+        size,
+    })
+}
+
+/// `-rwsr-xr-x` -> `0o4755`.
+fn parse_perms(perms: &str) -> u32 {
+    let bits: Vec<char> = perms.chars().skip(1).take(9).collect();
+    if bits.len() != 9 {
+        return 0;
+    }
+    let mut mode = 0;
+    for (i, c) in bits.iter().enumerate() {
+        let shift = 8 - i as u32;
+        if !matches!(c, '-' | 'S' | 'T') {
+            mode |= 1 << shift;
+        }
+    }
+    if matches!(bits[2], 's' | 'S') {
+        mode |= 0o4000;
+    }
+    if matches!(bits[5], 's' | 'S') {
+        mode |= 0o2000;
+    }
+    if matches!(bits[8], 't' | 'T') {
+        mode |= 0o1000;
+    }
+    mode
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BLOCK: usize = 4096;
+
+    fn compact_inode(mode: u16, layout: u16, size: u32, blkaddr: u32, uid: u16) -> [u8; 32] {
+        let mut raw = [0u8; 32];
+        raw[0..2].copy_from_slice(&(layout << 1).to_le_bytes());
+        raw[4..6].copy_from_slice(&mode.to_le_bytes());
+        raw[6..8].copy_from_slice(&1u16.to_le_bytes());
+        raw[8..12].copy_from_slice(&size.to_le_bytes());
+        raw[16..20].copy_from_slice(&blkaddr.to_le_bytes());
+        raw[24..26].copy_from_slice(&uid.to_le_bytes());
+        raw
+    }
+
+    fn dir_block(entries: &[(u64, &str, u8)]) -> Vec<u8> {
+        let mut block = Vec::new();
+        let mut names = Vec::new();
+        let mut nameoff = entries.len() * DIRENT_SIZE;
+        for (nid, name, ftype) in entries {
+            block.extend_from_slice(&nid.to_le_bytes());
+            block.extend_from_slice(&(nameoff as u16).to_le_bytes());
+            block.push(*ftype);
+            block.push(0);
+            names.extend_from_slice(name.as_bytes());
+            nameoff += name.len();
+        }
+        block.extend_from_slice(&names);
+        block
+    }
+
+    /// Block 0: superblock. Block 1: inodes. Blocks 2-3: directory data.
+    ///
+    /// nid 0 `/` (plain), nid 1 `/usr` (plain), nid 2 `/bin -> usr/bin`
+    /// (inline), nid 4 `/usr/sh` (setuid file, uid 7).
+    fn sample_image() -> Vec<u8> {
+        let mut image = vec![0u8; BLOCK * 4];
+        let sb = EROFS_SUPER_OFFSET as usize;
+        image[sb..sb + 4].copy_from_slice(&EROFS_MAGIC.to_le_bytes());
+        image[sb + 12] = 12;
+        image[sb + 14..sb + 16].copy_from_slice(&0u16.to_le_bytes());
+        image[sb + 40..sb + 44].copy_from_slice(&1u32.to_le_bytes());
+
+        let root = dir_block(&[(0, ".", 2), (0, "..", 2), (2, "bin", 7), (1, "usr", 2)]);
+        let usr = dir_block(&[(1, ".", 2), (0, "..", 2), (4, "sh", 1)]);
+        let meta = BLOCK;
+        let inodes: [(usize, [u8; 32]); 4] = [
+            (0, compact_inode(0o040755, 0, root.len() as u32, 2, 0)),
+            (1, compact_inode(0o040755, 0, usr.len() as u32, 3, 0)),
+            (2, compact_inode(0o120777, 2, 7, 0, 0)),
+            (4, compact_inode(0o104755, 0, 1234, 0, 7)),
+        ];
+        for (nid, raw) in inodes {
+            image[meta + nid * 32..meta + nid * 32 + 32].copy_from_slice(&raw);
+        }
+        image[meta + 3 * 32..meta + 3 * 32 + 7].copy_from_slice(b"usr/bin");
+        image[2 * BLOCK..2 * BLOCK + root.len()].copy_from_slice(&root);
+        image[3 * BLOCK..3 * BLOCK + usr.len()].copy_from_slice(&usr);
+        image
+    }
+
+    #[test]
+    fn test_list_erofs_metadata() {
+        let mut entries = ErofsReader::new(Cursor::new(sample_image()))
+            .unwrap()
+            .list()
+            .unwrap();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["/bin", "/usr", "/usr/sh"]);
+        assert_eq!(
+            entries[0].kind,
+            EntryKind::Symlink {
+                target: "usr/bin".to_string()
+            }
+        );
+        assert_eq!(entries[2].mode, 0o4755);
+        assert_eq!(entries[2].uid, 7);
+        assert_eq!(entries[2].size, 1234);
+        assert!(contains_path(&entries, "usr/sh"));
+        assert!(!contains_path(&entries, "/usr/lib"));
+    }
+
+    #[test]
+    fn test_list_contents_rejects_unknown_images() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(temp.path(), vec![0u8; 4096]).unwrap();
+        assert!(list_contents(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_parse_unsquashfs_lines() {
+        let link =
+            parse_unsquashfs_line("lrwxrwxrwx 0/0 7 2024-01-01 00:00 /bin -> usr/bin").unwrap();
+        assert_eq!(link.path, "/bin");
+        assert_eq!(
+            link.kind,
+            EntryKind::Symlink {
+                target: "usr/bin".to_string()
+            }
+        );
+        let file =
+            parse_unsquashfs_line("-rwsr-xr-x 0/0 51232 2024-01-01 00:00 /usr/bin/su").unwrap();
+        assert_eq!((file.mode, file.size), (0o4755, 51232));
+        let dev = parse_unsquashfs_line("crw-rw-rw- 0/0 1,  3 2024-01-01 00:00 /dev/null").unwrap();
+        assert_eq!(dev.kind, EntryKind::CharDevice);
+        assert!(parse_unsquashfs_line("drwxr-xr-x 0/0 3 2024-01-01 00:00 ").is_none());
+    }
+}
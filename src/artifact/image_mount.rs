@@ -0,0 +1,498 @@
+//! Read-only mounts of built EROFS images for inspection tests.
+//!
+//! [`list_contents`](super::image_contents::list_contents) answers "is this
+//! path in the image?"; tests that need file data ("does `/etc/os-release`
+//! name the right distro?") need the image mounted. [`MountedImage`] does
+//! that without root:
+//!
+//! - `erofsfuse`, when installed and `/dev/fuse` is usable, mounts the image
+//!   in the caller's namespace.
+//! - Otherwise a helper process mounts it inside a private mount namespace
+//!   (a user namespace too, when not root) and stays alive; the tree is read
+//!   through `/proc/<pid>/root`.
+//!
+//! Either way the mount is gone once the [`MountedImage`] is dropped, and
+//! nothing is left behind when the test panics.
+//!
+//! [`MountedIso`] does the same for a finished ISO and the rootfs EROFS it
+//! carries, so tests like "the live ISO ships sshd and boots with the
+//! required cmdline" run without QEMU. iso9660 cannot be mounted in a user
+//! namespace, so unless the caller is root the rootfs image (and any ISO
+//! file asked for) is extracted with xorriso instead.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::artifact::boot_check::{verify_iso, BootExpectation};
+use crate::process::{self, Cmd};
+
+/// Mounts the image, reports readiness, then holds the namespace open.
+/// `$1` is the image, `$2` the mountpoint.
+const HOLDER_SCRIPT: &str = r#"set -e
+mount -t erofs -o ro "$1" "$2"
+echo ready
+exec sleep 2147483647
+"#;
+
+/// Like [`HOLDER_SCRIPT`] for an ISO: `$1` is the ISO, `$2` a directory
+/// with `iso` and `rootfs` mountpoints, `$3` the rootfs image in the ISO.
+const ISO_HOLDER_SCRIPT: &str = r#"set -e
+mount -t iso9660 -o ro "$1" "$2/iso"
+mount -t erofs -o ro "$2/iso/$3" "$2/rootfs"
+echo ready
+exec sleep 2147483647
+"#;
+
+/// How long `erofsfuse` gets to bring the mount up.
+const FUSE_MOUNT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a [`MountedImage`] is mounted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountBackend {
+    Erofsfuse,
+    Namespace,
+}
+
+/// A built EROFS image mounted read-only until dropped.
+#[derive(Debug)]
+pub struct MountedImage {
+    backend: MountBackend,
+    mountpoint: PathBuf,
+    root: PathBuf,
+    child: Child,
+}
+
+impl MountedImage {
+    /// Mount `image`, preferring `erofsfuse` and falling back to a private
+    /// mount namespace.
+    pub fn mount(image: &Path) -> Result<Self> {
+        if !image.is_file() {
+            bail!("EROFS image not found: {}", image.display());
+        }
+        let image = image
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", image.display()))?;
+        let mountpoint = create_mountpoint()?;
+
+        let mounted = match Self::mount_fuse(&image, &mountpoint) {
+            Ok(Some(mounted)) => Ok(mounted),
+            Ok(None) => Self::mount_namespace(&image, &mountpoint),
+            Err(fuse_err) => Self::mount_namespace(&image, &mountpoint)
+                .with_context(|| format!("erofsfuse failed first: {:#}", fuse_err)),
+        };
+        if mounted.is_err() {
+            let _ = fs::remove_dir(&mountpoint);
+        }
+        mounted.with_context(|| format!("Failed to mount {}", image.display()))
+    }
+
+    /// Root of the mounted tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn backend(&self) -> MountBackend {
+        self.backend
+    }
+
+    /// Host path of `path` inside the image (e.g. `/etc/os-release`).
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    /// Contents of a text file inside the image.
+    pub fn read_to_string(&self, path: &str) -> Result<String> {
+        let host = self.path(path);
+        fs::read_to_string(&host)
+            .with_context(|| format!("Failed to read {} from mounted image", path))
+    }
+
+    /// `Ok(None)` when erofsfuse is not usable on this host.
+    fn mount_fuse(image: &Path, mountpoint: &Path) -> Result<Option<Self>> {
+        if which::which("erofsfuse").is_err() || !Path::new("/dev/fuse").exists() {
+            return Ok(None);
+        }
+        let parent_dev = fs::metadata(mountpoint)
+            .with_context(|| format!("Failed to stat {}", mountpoint.display()))?
+            .dev();
+        let mut child = Command::new("erofsfuse")
+            .arg("-f")
+            .arg(image)
+            .arg(mountpoint)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to start erofsfuse")?;
+
+        let deadline = Instant::now() + FUSE_MOUNT_TIMEOUT;
+        loop {
+            let mounted = fs::metadata(mountpoint).is_ok_and(|meta| meta.dev() != parent_dev);
+            if mounted {
+                return Ok(Some(Self {
+                    backend: MountBackend::Erofsfuse,
+                    mountpoint: mountpoint.to_path_buf(),
+                    root: mountpoint.to_path_buf(),
+                    child,
+                }));
+            }
+            if let Some(status) = child.try_wait().context("Failed to poll erofsfuse")? {
+                bail!(
+                    "erofsfuse exited with {}: {}",
+                    status,
+                    child_stderr(&mut child)
+                );
+            }
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("erofsfuse did not mount within {:?}", FUSE_MOUNT_TIMEOUT);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn mount_namespace(image: &Path, mountpoint: &Path) -> Result<Self> {
+        let child = spawn_holder(
+            HOLDER_SCRIPT,
+            &[image.as_os_str(), mountpoint.as_os_str()],
+            "install erofsfuse, or run as root if the kernel refuses EROFS mounts in user namespaces",
+        )?;
+        let root = holder_path(&child, mountpoint);
+        Ok(Self {
+            backend: MountBackend::Namespace,
+            mountpoint: mountpoint.to_path_buf(),
+            root,
+            child,
+        })
+    }
+}
+
+/// Start a holder running `script` in a private mount namespace (a user
+/// namespace too, when not root) and wait until it reports `ready`.
+fn spawn_holder(script: &str, args: &[&OsStr], remediation: &str) -> Result<Child> {
+    let mut unshare = Command::new("unshare");
+    // The mount must not propagate back to the host namespace.
+    unshare.args(["--mount", "--propagation", "private"]);
+    if unsafe { libc::geteuid() } != 0 {
+        unshare.args(["--user", "--map-root-user"]);
+    }
+    let mut child = unshare
+        .args(["sh", "-c", script, "sh"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start unshare (util-linux)")?;
+
+    let mut line = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        BufReader::new(stdout)
+            .read_line(&mut line)
+            .context("Failed to read mount helper output")?;
+    }
+    if line.trim() != "ready" {
+        let _ = child.kill();
+        let status = child.wait().context("Failed to wait for mount helper")?;
+        bail!(
+            "mount inside a private namespace failed ({}): {}\nRemediation: {}",
+            status,
+            child_stderr(&mut child),
+            remediation
+        );
+    }
+    Ok(child)
+}
+
+/// Host path of `path` as the holder `child` sees it.
+fn holder_path(child: &Child, path: &Path) -> PathBuf {
+    PathBuf::from(format!("/proc/{}/root", child.id())).join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// A built ISO opened read-only, with the rootfs EROFS it carries mounted.
+#[derive(Debug)]
+pub struct MountedIso {
+    iso: PathBuf,
+    /// Mounted ISO tree, or the directory ISO files are extracted into.
+    iso_root: PathBuf,
+    extracted: bool,
+    scratch: PathBuf,
+    rootfs: Option<MountedImage>,
+}
+
+impl MountedIso {
+    /// Open `iso` and mount the EROFS image at `rootfs` (ISO-relative, e.g.
+    /// `live/filesystem.erofs`). Loop-mounts both in a private mount
+    /// namespace when allowed, else extracts the rootfs image with xorriso.
+    pub fn mount(iso: &Path, rootfs: &str) -> Result<Self> {
+        if !iso.is_file() {
+            bail!("ISO not found: {}", iso.display());
+        }
+        let iso = iso
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", iso.display()))?;
+        let rootfs = rootfs.trim_start_matches('/');
+        let scratch = create_mountpoint()?;
+
+        let mounted = match Self::mount_namespace(&iso, rootfs, &scratch) {
+            Ok(mounted) => Ok(mounted),
+            Err(ns_err) => Self::mount_extracted(&iso, rootfs, &scratch)
+                .with_context(|| format!("loop mount failed first: {:#}", ns_err)),
+        };
+        if mounted.is_err() {
+            let _ = fs::remove_dir_all(&scratch);
+        }
+        mounted.with_context(|| format!("Failed to open {}", iso.display()))
+    }
+
+    /// The mounted rootfs.
+    pub fn rootfs(&self) -> &MountedImage {
+        self.rootfs
+            .as_ref()
+            .expect("rootfs is mounted until the ISO is dropped")
+    }
+
+    /// Whether ISO files are extracted on demand rather than mounted.
+    pub fn is_extracted(&self) -> bool {
+        self.extracted
+    }
+
+    /// Host path of `path` on the ISO (e.g. `/boot/grub/grub.cfg`),
+    /// extracting it first when the ISO is not mounted.
+    pub fn iso_path(&self, path: &str) -> Result<PathBuf> {
+        let rel = path.trim_start_matches('/');
+        let host = self.iso_root.join(rel);
+        if self.extracted && !host.exists() {
+            extract_iso_file(&self.iso, rel, &host)?;
+        }
+        Ok(host)
+    }
+
+    /// Contents of a text file on the ISO.
+    pub fn read_iso_to_string(&self, path: &str) -> Result<String> {
+        let host = self.iso_path(path)?;
+        fs::read_to_string(&host).with_context(|| format!("Failed to read {} from ISO", path))
+    }
+
+    /// Check the ISO's UEFI boot entries, including required cmdlines.
+    pub fn verify_boot(&self, expect: &BootExpectation) -> Result<()> {
+        verify_iso(&self.iso, expect)
+    }
+
+    fn mount_namespace(iso: &Path, rootfs: &str, scratch: &Path) -> Result<Self> {
+        let iso_dir = scratch.join("iso");
+        let rootfs_dir = scratch.join("rootfs");
+        for dir in [&iso_dir, &rootfs_dir] {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let child = spawn_holder(
+            ISO_HOLDER_SCRIPT,
+            &[iso.as_os_str(), scratch.as_os_str(), OsStr::new(rootfs)],
+            "run as root to loop-mount the ISO, or install xorriso",
+        )?;
+        let iso_root = holder_path(&child, &iso_dir);
+        let root = holder_path(&child, &rootfs_dir);
+        Ok(Self {
+            iso: iso.to_path_buf(),
+            iso_root,
+            extracted: false,
+            scratch: scratch.to_path_buf(),
+            rootfs: Some(MountedImage {
+                backend: MountBackend::Namespace,
+                mountpoint: rootfs_dir,
+                root,
+                child,
+            }),
+        })
+    }
+
+    fn mount_extracted(iso: &Path, rootfs: &str, scratch: &Path) -> Result<Self> {
+        let iso_root = scratch.join("iso");
+        let image = iso_root.join(rootfs);
+        extract_iso_file(iso, rootfs, &image)?;
+        let mounted = MountedImage::mount(&image)?;
+        Ok(Self {
+            iso: iso.to_path_buf(),
+            iso_root,
+            extracted: true,
+            scratch: scratch.to_path_buf(),
+            rootfs: Some(mounted),
+        })
+    }
+}
+
+impl Drop for MountedIso {
+    fn drop(&mut self) {
+        // Unmount before removing what the mounts were made from.
+        drop(self.rootfs.take());
+        if let Err(err) = fs::remove_dir_all(&self.scratch) {
+            eprintln!(
+                "  [WARN] Failed to remove {}: {}",
+                self.scratch.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Extract the ISO file `rel` to `dest` with xorriso.
+fn extract_iso_file(iso: &Path, rel: &str, dest: &Path) -> Result<()> {
+    if !process::exists("xorriso") {
+        bail!("xorriso not found. Install xorriso to read files from an ISO.");
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    Cmd::new("xorriso")
+        .args(["-osirrox", "on", "-indev"])
+        .arg_path(iso)
+        .args(["-extract", &format!("/{}", rel)])
+        .arg_path(dest)
+        .error_msg(format!("xorriso failed to extract /{}", rel))
+        .run()?;
+    Ok(())
+}
+
+impl Drop for MountedImage {
+    fn drop(&mut self) {
+        if self.backend == MountBackend::Erofsfuse {
+            let unmounted = ["fusermount3", "fusermount"].iter().any(|program| {
+                Command::new(program)
+                    .arg("-u")
+                    .arg(&self.mountpoint)
+                    .stderr(Stdio::null())
+                    .status()
+                    .is_ok_and(|status| status.success())
+            });
+            if !unmounted {
+                let _ = self.child.kill();
+            }
+        } else {
+            // The namespace, and with it the mount, dies with the holder.
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+        if let Err(err) = fs::remove_dir(&self.mountpoint) {
+            eprintln!(
+                "  [WARN] Failed to remove mountpoint {}: {}",
+                self.mountpoint.display(),
+                err
+            );
+        }
+    }
+}
+
+fn create_mountpoint() -> Result<PathBuf> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let dir = std::env::temp_dir().join(format!(
+        "distro-builder-mount-{}-{}",
+        std::process::id(),
+        nanos
+    ));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+fn child_stderr(child: &mut Child) -> String {
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    stderr.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_mount_built_image() {
+        if which::which("mkfs.erofs").is_err() {
+            eprintln!("skipping: mkfs.erofs not installed");
+            return;
+        }
+        let temp = TempDir::new().unwrap();
+        let tree = temp.path().join("tree");
+        fs::create_dir_all(tree.join("etc")).unwrap();
+        fs::write(tree.join("etc/os-release"), "ID=test\n").unwrap();
+        let image = temp.path().join("rootfs.erofs");
+        let built = Command::new("mkfs.erofs")
+            .arg(&image)
+            .arg(&tree)
+            .output()
+            .unwrap();
+        assert!(built.status.success());
+
+        let mounted = match MountedImage::mount(&image) {
+            Ok(mounted) => mounted,
+            Err(err) => {
+                eprintln!("skipping: no unprivileged EROFS mount here: {:#}", err);
+                return;
+            }
+        };
+        assert_eq!(
+            mounted.read_to_string("/etc/os-release").unwrap(),
+            "ID=test\n"
+        );
+        let mountpoint = mounted.mountpoint.clone();
+        drop(mounted);
+        assert!(!mountpoint.exists());
+    }
+
+    #[test]
+    fn test_mount_built_iso() {
+        if which::which("mkfs.erofs").is_err() || which::which("xorriso").is_err() {
+            eprintln!("skipping: mkfs.erofs or xorriso not installed");
+            return;
+        }
+        let temp = TempDir::new().unwrap();
+        let tree = temp.path().join("tree");
+        fs::create_dir_all(tree.join("usr/sbin")).unwrap();
+        fs::write(tree.join("usr/sbin/sshd"), "").unwrap();
+        let iso_root = temp.path().join("iso-root");
+        fs::create_dir_all(iso_root.join("live")).unwrap();
+        fs::write(iso_root.join("live/cmdline"), "rd.live.dir=live\n").unwrap();
+        let built = Command::new("mkfs.erofs")
+            .arg(iso_root.join("live/filesystem.erofs"))
+            .arg(&tree)
+            .output()
+            .unwrap();
+        assert!(built.status.success());
+        let iso = temp.path().join("test.iso");
+        let built = Command::new("xorriso")
+            .args(["-as", "mkisofs", "-R", "-o"])
+            .arg(&iso)
+            .arg(&iso_root)
+            .output()
+            .unwrap();
+        assert!(built.status.success());
+
+        let mounted = match MountedIso::mount(&iso, "live/filesystem.erofs") {
+            Ok(mounted) => mounted,
+            Err(err) => {
+                eprintln!("skipping: no unprivileged ISO/EROFS mount here: {:#}", err);
+                return;
+            }
+        };
+        assert!(mounted.rootfs().path("/usr/sbin/sshd").is_file());
+        assert_eq!(
+            mounted.read_iso_to_string("/live/cmdline").unwrap(),
+            "rd.live.dir=live\n"
+        );
+        let scratch = mounted.scratch.clone();
+        drop(mounted);
+        assert!(!scratch.exists());
+    }
+}
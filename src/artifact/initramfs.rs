@@ -11,6 +11,9 @@
 use anyhow::Result;
 use std::path::Path;
 
+use crate::compression::Compression;
+use crate::repo_config::RepoConfig;
+
 /// Options for building an initramfs.
 #[derive(Debug, Clone)]
 pub struct InitramfsOptions<'a> {
@@ -20,11 +23,21 @@ pub struct InitramfsOptions<'a> {
     /// Boot modules to include (paths relative to /lib/modules/<version>/).
     pub boot_modules: &'a [&'a str],
 
-    /// Gzip compression level (1-9).
+    /// Archive compression.
     ///
-    /// Higher = smaller file, slower compression.
-    /// Default: 6
-    pub gzip_level: u8,
+    /// Default: gzip level 6; [`InitramfsOptions::for_repo`] takes
+    /// `[compression] initramfs` from `distro-builder.toml`.
+    pub compression: Compression,
+}
+
+impl InitramfsOptions<'_> {
+    /// Default options with the compression configured for `repo_root`.
+    pub fn for_repo(repo_root: &Path) -> Result<Self> {
+        Ok(Self {
+            compression: RepoConfig::load(repo_root)?.compression.initramfs,
+            ..Default::default()
+        })
+    }
 }
 
 impl Default for InitramfsOptions<'_> {
@@ -32,7 +45,7 @@ impl Default for InitramfsOptions<'_> {
         Self {
             busybox_commands: &[],
             boot_modules: &[],
-            gzip_level: 6,
+            compression: Compression::initramfs_default(),
         }
     }
 }
@@ -80,7 +93,7 @@ pub const STANDARD_BUSYBOX_COMMANDS: &[&str] = &[
 /// let options = InitramfsOptions {
 ///     busybox_commands: STANDARD_BUSYBOX_COMMANDS,
 ///     boot_modules: &["erofs", "overlay", "loop"],
-///     gzip_level: 9,
+///     compression: "zstd:19".parse()?,
 /// };
 ///
 /// build_initramfs(Path::new("output/"), &options)?;
@@ -0,0 +1,490 @@
+//! Initramfs content contract checks.
+//!
+//! Inspects a built initramfs archive (newc cpio, optionally compressed
+//! with any [`Codec`](crate::compression::Codec)) and verifies that the entries needed to reach the real root
+//! are present: an executable `/init`, a shell, `switch_root`, and the kernel
+//! modules on the boot path.
+//!
+//! The defaults cover a busybox-style initramfs that mounts an EROFS root
+//! under an overlay, as every live product does. Built-in modules count only
+//! when the archive ships `modules.builtin`. Distros tighten or relax the
+//! contract with an `initramfs-contract.toml` in their variant directory:
+//!
+//! ```toml
+//! modules = ["erofs", "overlay", "loop", "squashfs"]
+//! extra_paths = ["etc/initrd-release"]
+//! commands = ["losetup"]
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::compression::decompress_bytes;
+
+/// File name of the per-distro contract override, relative to the variant dir.
+pub const INITRAMFS_CONTRACT_FILENAME: &str = "initramfs-contract.toml";
+
+const NEWC_MAGIC: &[u8] = b"070701";
+const NEWC_HEADER_LEN: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+/// Directories a required command may live in.
+const COMMAND_DIRS: [&str; 4] = ["bin", "sbin", "usr/bin", "usr/sbin"];
+
+/// Modules every live boot path needs: the EROFS rootfs and the overlay
+/// holding the writable layer.
+const DEFAULT_MODULES: [&str; 2] = ["erofs", "overlay"];
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFREG: u32 = 0o100000;
+
+/// Required initramfs contents.
+///
+/// Paths are relative to the archive root. Lists of alternatives are
+/// satisfied when any one of them is present.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InitramfsContract {
+    /// Path of the init program; must be executable (or a symlink).
+    pub init: String,
+    /// Shell candidates (busybox counts).
+    pub shells: Vec<String>,
+    /// `switch_root` candidates.
+    pub switch_root: Vec<String>,
+    /// Kernel modules that must be shipped as `.ko*` files or listed in
+    /// `modules.builtin`. Dashes and underscores are interchangeable.
+    pub modules: Vec<String>,
+    /// Additional paths that must exist verbatim.
+    pub extra_paths: Vec<String>,
+    /// Commands that must be in `bin`, `sbin`, `usr/bin` or `usr/sbin`
+    /// (busybox applet links count).
+    pub commands: Vec<String>,
+    /// Shell functions some script in the archive must call; the
+    /// definition itself does not count.
+    pub called_functions: Vec<String>,
+}
+
+impl Default for InitramfsContract {
+    fn default() -> Self {
+        Self {
+            init: "init".to_string(),
+            shells: [
+                "bin/sh",
+                "bin/busybox",
+                "usr/bin/sh",
+                "usr/bin/busybox",
+                "bin/bash",
+            ]
+            .map(String::from)
+            .to_vec(),
+            switch_root: [
+                "sbin/switch_root",
+                "bin/switch_root",
+                "usr/sbin/switch_root",
+                "usr/bin/switch_root",
+            ]
+            .map(String::from)
+            .to_vec(),
+            modules: DEFAULT_MODULES.map(String::from).to_vec(),
+            extra_paths: Vec::new(),
+            commands: Vec::new(),
+            called_functions: Vec::new(),
+        }
+    }
+}
+
+impl InitramfsContract {
+    /// Load the contract for a variant, falling back to defaults when the
+    /// variant does not ship an override file.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(INITRAMFS_CONTRACT_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+/// A single entry read from a cpio archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpioEntry {
+    pub mode: u32,
+    pub size: u64,
+    /// Link target for symlinks.
+    pub link_target: Option<String>,
+}
+
+impl CpioEntry {
+    fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    fn is_executable_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG && self.mode & 0o111 != 0
+    }
+}
+
+/// Parsed initramfs listing, keyed by normalized path (no leading `./` or `/`).
+#[derive(Debug, Default)]
+pub struct InitramfsListing {
+    pub entries: BTreeMap<String, CpioEntry>,
+    builtin_modules: Vec<String>,
+    /// Text of the regular files starting with `#!`.
+    scripts: BTreeMap<String, String>,
+}
+
+impl InitramfsListing {
+    /// Read and decompress an initramfs image from disk.
+    pub fn read(path: &Path) -> Result<Self> {
+        let raw = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let data = decompress_bytes(&raw)
+            .with_context(|| format!("Failed to decompress initramfs {}", path.display()))?;
+        Self::parse(&data).with_context(|| format!("Failed to parse cpio {}", path.display()))
+    }
+
+    /// Parse an uncompressed newc cpio stream (concatenated archives allowed).
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut listing = Self::default();
+        let mut pos = 0usize;
+
+        while pos < data.len() {
+            // Concatenated archives are separated by zero padding.
+            if data[pos] == 0 {
+                pos += 1;
+                continue;
+            }
+            if data.len() < pos + NEWC_HEADER_LEN || &data[pos..pos + 6] != NEWC_MAGIC {
+                bail!("unsupported cpio header at offset {}", pos);
+            }
+
+            let field = |index: usize| -> Result<u64> {
+                let start = pos + 6 + index * 8;
+                let text = std::str::from_utf8(&data[start..start + 8])?;
+                Ok(u64::from_str_radix(text, 16)?)
+            };
+            let mode = field(1)? as u32;
+            let size = field(6)?;
+            let name_size = field(11)? as usize;
+
+            let name_start = pos + NEWC_HEADER_LEN;
+            let name_end = name_start + name_size;
+            if name_size == 0 || name_end > data.len() {
+                bail!("truncated cpio entry name at offset {}", pos);
+            }
+            let name = String::from_utf8_lossy(&data[name_start..name_end - 1]).to_string();
+            let data_start = align4(name_end);
+            let data_end = data_start + size as usize;
+            if data_end > data.len() {
+                bail!("truncated cpio entry '{}'", name);
+            }
+            pos = align4(data_end);
+
+            if name == CPIO_TRAILER {
+                continue;
+            }
+
+            let path = normalize_path(&name);
+            let body = &data[data_start..data_end];
+            let link_target =
+                (mode & S_IFMT == S_IFLNK).then(|| String::from_utf8_lossy(body).to_string());
+            if path.ends_with("/modules.builtin") {
+                listing.builtin_modules.extend(
+                    String::from_utf8_lossy(body)
+                        .lines()
+                        .filter_map(module_name_from_path),
+                );
+            }
+            if mode & S_IFMT == S_IFREG && body.starts_with(b"#!") {
+                listing
+                    .scripts
+                    .insert(path.clone(), String::from_utf8_lossy(body).into_owned());
+            }
+            if !path.is_empty() {
+                listing.entries.insert(
+                    path,
+                    CpioEntry {
+                        mode,
+                        size,
+                        link_target,
+                    },
+                );
+            }
+        }
+
+        Ok(listing)
+    }
+
+    /// Whether a path exists in the archive.
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(&normalize_path(path))
+    }
+
+    /// Whether a kernel module is available (loadable or built in).
+    pub fn has_module(&self, module: &str) -> bool {
+        let wanted = canonical_module_name(module);
+        self.builtin_modules.iter().any(|m| *m == wanted)
+            || self.entries.keys().any(|path| {
+                (path.starts_with("lib/modules/") || path.starts_with("usr/lib/modules/"))
+                    && module_name_from_path(path).as_deref() == Some(wanted.as_str())
+            })
+    }
+
+    /// Whether `command` is in one of the [`COMMAND_DIRS`].
+    pub fn has_command(&self, command: &str) -> bool {
+        COMMAND_DIRS
+            .iter()
+            .any(|dir| self.contains(&format!("{}/{}", dir, command)))
+    }
+
+    /// Whether a script calls the shell function `name` outside comments
+    /// and its definition.
+    pub fn calls_function(&self, name: &str) -> bool {
+        let definition = format!("{}()", name);
+        self.scripts.values().any(|script| {
+            script.lines().map(str::trim_start).any(|line| {
+                !line.starts_with('#') && !line.starts_with(&definition) && line.contains(name)
+            })
+        })
+    }
+
+    /// Return every contract violation; empty when the archive conforms.
+    pub fn check(&self, contract: &InitramfsContract) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        match self.entries.get(&normalize_path(&contract.init)) {
+            None => problems.push(format!("missing /{}", contract.init)),
+            Some(entry) if !entry.is_symlink() && !entry.is_executable_file() => {
+                problems.push(format!("/{} is not executable", contract.init))
+            }
+            Some(_) => {}
+        }
+
+        if !contract.shells.is_empty() && !contract.shells.iter().any(|p| self.contains(p)) {
+            problems.push(format!(
+                "no shell found (tried {})",
+                contract.shells.join(", ")
+            ));
+        }
+        if !contract.switch_root.is_empty()
+            && !contract.switch_root.iter().any(|p| self.contains(p))
+        {
+            problems.push(format!(
+                "no switch_root found (tried {})",
+                contract.switch_root.join(", ")
+            ));
+        }
+        for module in &contract.modules {
+            if !self.has_module(module) {
+                problems.push(format!("missing kernel module '{}'", module));
+            }
+        }
+        for path in &contract.extra_paths {
+            if !self.contains(path) {
+                problems.push(format!("missing /{}", normalize_path(path)));
+            }
+        }
+        for command in &contract.commands {
+            if !self.has_command(command) {
+                problems.push(format!("missing command '{}'", command));
+            }
+        }
+        for function in &contract.called_functions {
+            if !self.calls_function(function) {
+                problems.push(format!("no script calls '{}'", function));
+            }
+        }
+
+        problems
+    }
+}
+
+/// Verify an initramfs image against a contract, failing with every violation.
+pub fn check_initramfs(path: &Path, contract: &InitramfsContract) -> Result<()> {
+    let listing = InitramfsListing::read(path)?;
+    let problems = listing.check(contract);
+    if !problems.is_empty() {
+        bail!(
+            "initramfs '{}' violates its content contract:\n  {}",
+            path.display(),
+            problems.join("\n  ")
+        );
+    }
+    println!(
+        "  Initramfs contract OK: {} ({} entries)",
+        path.display(),
+        listing.entries.len()
+    );
+    Ok(())
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches("./")
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn canonical_module_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+fn module_name_from_path(path: &str) -> Option<String> {
+    let file = path.rsplit('/').next()?;
+    let (stem, _) = file.split_once(".ko")?;
+    Some(canonical_module_name(stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_entry(out: &mut Vec<u8>, name: &str, mode: u32, body: &[u8]) {
+        let header = format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            0,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            body.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        );
+        out.extend_from_slice(header.as_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.resize(align4(out.len()), 0);
+        out.extend_from_slice(body);
+        out.resize(align4(out.len()), 0);
+    }
+
+    fn archive(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (name, mode, body) in entries {
+            push_entry(&mut out, name, *mode, body);
+        }
+        push_entry(&mut out, CPIO_TRAILER, 0, b"");
+        out
+    }
+
+    #[test]
+    fn conforming_archive_passes() {
+        let data = archive(&[
+            (".", 0o040755, b""),
+            ("./init", 0o100755, b"#!/bin/sh\n"),
+            ("./bin/busybox", 0o100755, b"ELF"),
+            ("./sbin/switch_root", 0o120777, b"/bin/busybox"),
+            (
+                "./lib/modules/6.1/kernel/fs/erofs/erofs.ko.zst",
+                0o100644,
+                b"",
+            ),
+            (
+                "./lib/modules/6.1/modules.builtin",
+                0o100644,
+                b"kernel/drivers/block/loop.ko\n",
+            ),
+        ]);
+        let listing = InitramfsListing::parse(&data).unwrap();
+        let contract = InitramfsContract {
+            modules: vec!["erofs".into(), "loop".into()],
+            ..Default::default()
+        };
+
+        assert_eq!(listing.check(&contract), Vec::<String>::new());
+        assert_eq!(
+            listing.entries["sbin/switch_root"].link_target.as_deref(),
+            Some("/bin/busybox")
+        );
+    }
+
+    #[test]
+    fn violations_are_all_reported() {
+        let data = archive(&[("init", 0o100644, b"#!/bin/sh\n")]);
+        let listing = InitramfsListing::parse(&data).unwrap();
+        let contract = InitramfsContract {
+            modules: vec!["overlay".into()],
+            extra_paths: vec!["/etc/initrd-release".into()],
+            ..Default::default()
+        };
+
+        let problems = listing.check(&contract);
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert_eq!(problems[0], "/init is not executable");
+        assert!(problems[1].starts_with("no shell found"));
+        assert!(problems[2].starts_with("no switch_root found"));
+        assert_eq!(problems[3], "missing kernel module 'overlay'");
+        assert_eq!(problems[4], "missing /etc/initrd-release");
+    }
+
+    #[test]
+    fn commands_and_function_calls() {
+        let data = archive(&[
+            (
+                "init",
+                0o100755,
+                b"#!/bin/sh\n# mount_writable_layer comes from the library\n. /lib/layer.sh\n",
+            ),
+            (
+                "lib/layer.sh",
+                0o100644,
+                b"#!/bin/sh\nmount_writable_layer() {\n    :\n}\n",
+            ),
+            ("usr/sbin/losetup", 0o120777, b"/bin/busybox"),
+        ]);
+        let contract = InitramfsContract {
+            commands: vec!["losetup".into(), "mke2fs".into()],
+            called_functions: vec!["mount_writable_layer".into()],
+            ..Default::default()
+        };
+        let problems = InitramfsListing::parse(&data).unwrap().check(&contract);
+        assert!(problems.contains(&"missing command 'mke2fs'".to_string()));
+        assert!(!problems.iter().any(|p| p.contains("losetup")));
+        assert!(problems.contains(&"no script calls 'mount_writable_layer'".to_string()));
+
+        let data = archive(&[(
+            "init",
+            0o100755,
+            b"#!/bin/sh\nmount_writable_layer /run/overlay || exit 1\n",
+        )]);
+        assert!(InitramfsListing::parse(&data)
+            .unwrap()
+            .calls_function("mount_writable_layer"));
+    }
+
+    #[test]
+    fn contract_toml_overrides_defaults() {
+        let temp = tempfile::TempDir::new().unwrap();
+        assert_eq!(
+            InitramfsContract::load_for_variant(temp.path())
+                .unwrap()
+                .modules,
+            DEFAULT_MODULES
+        );
+        fs::write(
+            temp.path().join(INITRAMFS_CONTRACT_FILENAME),
+            "modules = [\"erofs\"]\nswitch_root = []\n",
+        )
+        .unwrap();
+
+        let contract = InitramfsContract::load_for_variant(temp.path()).unwrap();
+        assert_eq!(contract.modules, vec!["erofs"]);
+        assert!(contract.switch_root.is_empty());
+        assert_eq!(contract.init, "init");
+    }
+}
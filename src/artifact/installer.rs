@@ -0,0 +1,419 @@
+//! Guided text-mode installer payload.
+//!
+//! The installer ships as its own read-only squashfs image next to the rootfs
+//! on the live media. It bundles the install tools (`recstrap`, `recfstab`,
+//! `recchroot`) together with a guided shell front-end, so every distro gets
+//! the same install flow without carrying per-distro installer scripts.
+//!
+//! Building the payload is split in three steps:
+//!
+//! 1. [`stage_installer_payload`] - assemble the payload tree from built tools
+//! 2. [`build_installer_squashfs`] - pack the tree into `installer.squashfs`
+//! 3. [`wire_installer_launcher`] - add the mount service and launcher command
+//!    to a live overlay
+//!
+//! [`build_installer_stage`] runs all three. The live-tools stage calls it
+//! with the tools it just installed, so every distro's live-tools product
+//! carries the installer image and launcher (Stage 03 Install).
+//!
+//! At runtime the mount service loop-mounts the image at [`INSTALLER_MOUNT`]
+//! and the launcher (`<os_id>-install`) runs the guided script from there.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::artifact::payload_role::PayloadRole;
+use crate::build::shell_check::check_script;
+use crate::contracts::InitSystem;
+use crate::preflight::RequiredTools;
+use crate::process::Cmd;
+use crate::progress::Progress;
+
+/// Tools the guided installer drives, in the order they run.
+pub const INSTALLER_TOOLS: &[&str] = &["recstrap", "recfstab", "recchroot"];
+
+/// Default file name of the installer image inside the ISO `live/` directory.
+pub const INSTALLER_IMAGE_FILENAME: &str = "installer.squashfs";
+
+/// Payload tree [`build_installer_stage`] stages under its output dir; removed
+/// once the image is packed.
+const INSTALLER_PAYLOAD_DIRNAME: &str = "installer-payload";
+
+/// Where the installer image is mounted in the live session.
+pub const INSTALLER_MOUNT: &str = "/run/installer";
+
+/// Live-session wiring for the installer payload.
+#[derive(Debug)]
+pub struct InstallerLauncherConfig<'a> {
+    /// OS display name shown by the guided installer.
+    pub os_name: &'a str,
+    /// OS id used for the launcher command name (`<os_id>-install`).
+    pub os_id: &'a str,
+    /// Init system of the live rootfs.
+    pub init_system: InitSystem,
+    /// Image path on the mounted live media, e.g. `/run/live-media/live/installer.squashfs`.
+    pub image_path: &'a str,
+}
+
+/// Register the host tools the installer image build invokes.
+pub fn register_host_tools(tools: &mut RequiredTools) {
+    tools.require("installer", "mksquashfs", "squashfs-tools");
+}
+
+/// Run the installer stage: stage the payload from `tool_dir`, pack it into
+/// `output_dir/installer.squashfs` and wire the launcher into `live_overlay`.
+///
+/// Returns the image path.
+pub fn build_installer_stage(
+    tool_dir: &Path,
+    output_dir: &Path,
+    live_overlay: &Path,
+    config: &InstallerLauncherConfig,
+) -> Result<PathBuf> {
+    let payload_dir = output_dir.join(INSTALLER_PAYLOAD_DIRNAME);
+    let image = output_dir.join(INSTALLER_IMAGE_FILENAME);
+    stage_installer_payload(tool_dir, &payload_dir, config.os_name)?;
+    build_installer_squashfs(&payload_dir, &image)?;
+    fs::remove_dir_all(&payload_dir).with_context(|| {
+        format!(
+            "removing installer payload tree '{}'",
+            payload_dir.display()
+        )
+    })?;
+    wire_installer_launcher(live_overlay, config)?;
+    Ok(image)
+}
+
+/// Assemble the installer payload tree at `payload_dir`.
+///
+/// `tool_dir` must contain every binary in [`INSTALLER_TOOLS`]. Any existing
+/// payload tree is replaced.
+pub fn stage_installer_payload(tool_dir: &Path, payload_dir: &Path, os_name: &str) -> Result<()> {
+    let missing: Vec<&str> = INSTALLER_TOOLS
+        .iter()
+        .copied()
+        .filter(|tool| !tool_dir.join(tool).is_file())
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "installer tools missing from '{}': {}\n\
+             Remediation: build the install tools before staging the installer payload.",
+            tool_dir.display(),
+            missing.join(", ")
+        );
+    }
+
+    if payload_dir.exists() {
+        fs::remove_dir_all(payload_dir).with_context(|| {
+            format!(
+                "removing existing installer payload '{}'",
+                payload_dir.display()
+            )
+        })?;
+    }
+    let bin_dir = payload_dir.join("bin");
+    fs::create_dir_all(&bin_dir)
+        .with_context(|| format!("creating installer payload dir '{}'", bin_dir.display()))?;
+
+    for tool in INSTALLER_TOOLS {
+        let dest = bin_dir.join(tool);
+        fs::copy(tool_dir.join(tool), &dest)
+            .with_context(|| format!("copying installer tool '{}'", tool))?;
+        set_mode(&dest, 0o755)?;
+    }
+
+    write_executable(
+        &payload_dir.join("install"),
+        &guided_install_script(os_name),
+    )?;
+    PayloadRole::Installer.write(payload_dir).with_context(|| {
+        format!(
+            "writing installer payload role marker in '{}'",
+            payload_dir.display()
+        )
+    })?;
+
+    println!("  Installer payload staged at {}", payload_dir.display());
+    Ok(())
+}
+
+/// Pack a staged installer payload into a squashfs image.
+///
+/// Files are owned by root in the image; `SOURCE_DATE_EPOCH` fixes its
+/// timestamps.
+pub fn build_installer_squashfs(payload_dir: &Path, output: &Path) -> Result<()> {
+    if !payload_dir.join("install").is_file() {
+        bail!(
+            "'{}' is not a staged installer payload (missing 'install')",
+            payload_dir.display()
+        );
+    }
+    PayloadRole::Installer.expect(payload_dir)?;
+    let mut tools = RequiredTools::new();
+    register_host_tools(&mut tools);
+    tools.check()?;
+
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("creating installer image dir '{}'", parent.display()))?;
+    }
+    Cmd::new("mksquashfs")
+        .arg_path(payload_dir)
+        .arg_path(output)
+        .args(["-noappend", "-comp", "zstd", "-all-root", "-progress"])
+        .error_msg("mksquashfs failed building the installer image")
+        .progress(Progress::mksquashfs("installer"))
+        .run()?;
+    println!("  Installer image built at {}", output.display());
+    Ok(())
+}
+
+/// Add the installer mount service and launcher command to a live overlay.
+pub fn wire_installer_launcher(
+    live_overlay: &Path,
+    config: &InstallerLauncherConfig,
+) -> Result<()> {
+    let launcher_path = live_overlay.join(format!("usr/local/bin/{}-install", config.os_id));
+    let launcher = format!(
+        "#!/bin/sh\n\
+set -eu\n\
+if ! mountpoint -q {mount}; then\n\
+    mkdir -p {mount}\n\
+    mount -t squashfs -o loop,ro {image} {mount}\n\
+fi\n\
+exec {mount}/install \"$@\"\n",
+        mount = INSTALLER_MOUNT,
+        image = config.image_path,
+    );
+    write_executable(&launcher_path, &launcher)?;
+
+    match config.init_system {
+        InitSystem::Systemd => {
+            let units = live_overlay.join("etc/systemd/system");
+            fs::create_dir_all(units.join("multi-user.target.wants"))?;
+            let unit = format!(
+                "[Unit]\n\
+Description={os_name} installer payload\n\
+After=local-fs.target\n\
+ConditionPathExists={image}\n\
+\n\
+[Service]\n\
+Type=oneshot\n\
+RemainAfterExit=yes\n\
+ExecStartPre=/bin/mkdir -p {mount}\n\
+ExecStart=/bin/mount -t squashfs -o loop,ro {image} {mount}\n\
+ExecStop=/bin/umount {mount}\n\
+\n\
+[Install]\n\
+WantedBy=multi-user.target\n",
+                os_name = config.os_name,
+                image = config.image_path,
+                mount = INSTALLER_MOUNT,
+            );
+            fs::write(units.join("installer-payload.service"), unit)?;
+            replace_symlink(
+                "/etc/systemd/system/installer-payload.service",
+                &units.join("multi-user.target.wants/installer-payload.service"),
+            )?;
+        }
+        InitSystem::OpenRC => {
+            let script = format!(
+                "#!/sbin/openrc-run\n\
+description=\"{os_name} installer payload\"\n\
+\n\
+depend() {{\n\
+\tneed localmount\n\
+}}\n\
+\n\
+start() {{\n\
+\t[ -f {image} ] || return 0\n\
+\tebegin \"Mounting installer payload\"\n\
+\tmkdir -p {mount}\n\
+\tmountpoint -q {mount} || mount -t squashfs -o loop,ro {image} {mount}\n\
+\teend $?\n\
+}}\n\
+\n\
+stop() {{\n\
+\tmountpoint -q {mount} && umount {mount}\n\
+\treturn 0\n\
+}}\n",
+                os_name = config.os_name,
+                image = config.image_path,
+                mount = INSTALLER_MOUNT,
+            );
+            write_executable(&live_overlay.join("etc/init.d/installer-payload"), &script)?;
+            crate::executor::openrc::enable_service(live_overlay, "installer-payload", "default")?;
+        }
+    }
+
+    println!(
+        "  Installer launcher wired into {} ({}-install)",
+        live_overlay.display(),
+        config.os_id
+    );
+    Ok(())
+}
+
+fn guided_install_script(os_name: &str) -> String {
+    format!(
+        r#"#!/bin/sh
+# Guided text-mode installer for {os_name}.
+set -eu
+
+BIN="$(dirname "$0")/bin"
+TARGET="${{INSTALL_TARGET:-/mnt}}"
+
+ask() {{
+    printf '%s ' "$1" >&2
+    read -r answer
+    printf '%s\n' "$answer"
+}}
+
+echo "=== {os_name} installer ==="
+echo
+lsblk -d -o NAME,SIZE,MODEL 2>/dev/null || true
+echo
+
+disk="${{INSTALL_DISK:-}}"
+[ -n "$disk" ] || disk="$(ask 'Install to which disk (e.g. /dev/vda)?')"
+[ -b "$disk" ] || {{ echo "not a block device: $disk" >&2; exit 1; }}
+
+if [ "${{INSTALL_ASSUME_YES:-0}}" != "1" ]; then
+    confirm="$(ask "All data on $disk will be erased. Type 'yes' to continue:")"
+    [ "$confirm" = "yes" ] || {{ echo "aborted" >&2; exit 1; }}
+fi
+
+case "$disk" in
+    *[0-9]) part="${{disk}}p" ;;
+    *) part="$disk" ;;
+esac
+
+echo "[1/5] Partitioning $disk"
+printf 'label: gpt\nsize=512M, type=U\ntype=L\n' | sfdisk --wipe always "$disk"
+udevadm settle 2>/dev/null || sleep 2
+
+echo "[2/5] Creating filesystems"
+mkfs.vfat -F 32 "${{part}}1"
+mkfs.ext4 -F "${{part}}2"
+mkdir -p "$TARGET"
+mount "${{part}}2" "$TARGET"
+mkdir -p "$TARGET/boot"
+mount "${{part}}1" "$TARGET/boot"
+
+echo "[3/5] Extracting system (recstrap)"
+"$BIN/recstrap" "$TARGET"
+
+echo "[4/5] Writing fstab (recfstab)"
+"$BIN/recfstab" "$TARGET" >> "$TARGET/etc/fstab"
+
+echo "[5/5] Configuring system (recchroot)"
+"$BIN/recchroot" "$TARGET" /bin/sh -c \
+    'if command -v bootctl >/dev/null 2>&1; then bootctl install; fi; passwd root'
+
+umount "$TARGET/boot" "$TARGET"
+echo
+echo "{os_name} installed to $disk. Remove the install media and reboot."
+"#
+    )
+}
+
+fn replace_symlink(target: &str, link: &Path) -> Result<()> {
+    if fs::symlink_metadata(link).is_ok() {
+        fs::remove_file(link)?;
+    }
+    symlink(target, link).with_context(|| format!("linking '{}' -> '{}'", link.display(), target))
+}
+
+fn write_executable(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content).with_context(|| format!("writing '{}'", path.display()))?;
+    check_script(path)?;
+    set_mode(path, 0o755)
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("setting permissions on '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn stage_requires_all_tools() {
+        let temp = TempDir::new().unwrap();
+        let tools = temp.path().join("tools");
+        fs::create_dir_all(&tools).unwrap();
+        fs::write(tools.join("recstrap"), "").unwrap();
+
+        let err = stage_installer_payload(&tools, &temp.path().join("payload"), "TestOS")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("recfstab, recchroot"), "{}", err);
+    }
+
+    #[test]
+    fn stage_and_wire_openrc() {
+        let temp = TempDir::new().unwrap();
+        let tools = temp.path().join("tools");
+        fs::create_dir_all(&tools).unwrap();
+        for tool in INSTALLER_TOOLS {
+            fs::write(tools.join(tool), "#!/bin/sh\n").unwrap();
+        }
+        let payload = temp.path().join("payload");
+        stage_installer_payload(&tools, &payload, "TestOS").unwrap();
+        assert!(payload.join("bin/recchroot").is_file());
+        PayloadRole::Installer.expect(&payload).unwrap();
+        assert!(fs::read_to_string(payload.join("install"))
+            .unwrap()
+            .contains("=== TestOS installer ==="));
+
+        let overlay = temp.path().join("overlay");
+        let config = InstallerLauncherConfig {
+            os_name: "TestOS",
+            os_id: "testos",
+            init_system: InitSystem::OpenRC,
+            image_path: "/run/live-media/live/installer.squashfs",
+        };
+        wire_installer_launcher(&overlay, &config).unwrap();
+        assert!(overlay.join("usr/local/bin/testos-install").is_file());
+        assert!(overlay
+            .join("etc/runlevels/default/installer-payload")
+            .is_symlink());
+    }
+
+    #[test]
+    fn stage_builds_image_and_wires_systemd() {
+        if which::which("mksquashfs").is_err() {
+            eprintln!("skipping: mksquashfs not installed");
+            return;
+        }
+        let temp = TempDir::new().unwrap();
+        let tools = temp.path().join("overlay/usr/bin");
+        fs::create_dir_all(&tools).unwrap();
+        for tool in INSTALLER_TOOLS {
+            fs::write(tools.join(tool), "#!/bin/sh\n").unwrap();
+        }
+        let output = temp.path().join("out");
+        let overlay = temp.path().join("overlay");
+        let config = InstallerLauncherConfig {
+            os_name: "TestOS",
+            os_id: "testos",
+            init_system: InitSystem::Systemd,
+            image_path: "/run/live-media/live/installer.squashfs",
+        };
+        let image = build_installer_stage(&tools, &output, &overlay, &config).unwrap();
+        assert_eq!(image, output.join(INSTALLER_IMAGE_FILENAME));
+        assert!(image.is_file());
+        assert!(!output.join(INSTALLER_PAYLOAD_DIRNAME).exists());
+        assert!(overlay
+            .join("etc/systemd/system/multi-user.target.wants/installer-payload.service")
+            .is_symlink());
+    }
+}
@@ -7,7 +7,11 @@ use anyhow::{Context, Result};
 use std::fs;
 use std::path::Path;
 
+use crate::artifact::esp::EspLayout;
+use crate::preflight::RequiredTools;
 use crate::process::Cmd;
+use crate::progress::Progress;
+use crate::secureboot::SecureBootKeys;
 use distro_spec::shared::{
     EFIBOOT_SIZE_MB, ISO_BOOT_DIR, ISO_CHECKSUM_SUFFIX, ISO_EFI_DIR, ISO_LIVE_DIR,
     SHA512_SEPARATOR, XORRISO_FS_FLAGS, XORRISO_PARTITION_OFFSET,
@@ -45,6 +49,25 @@ pub fn setup_iso_structure(iso_root: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Register the host tools ISO assembly invokes; `efi_boot_image` adds
+/// the FAT tools used to build the El Torito EFI image.
+pub fn register_host_tools(tools: &mut RequiredTools, efi_boot_image: bool) {
+    tools.require_all("iso", &[("xorriso", "xorriso"), ("sha512sum", "coreutils")]);
+    if efi_boot_image {
+        tools.require_all(
+            "iso",
+            &[
+                ("mkfs.fat", "dosfstools"),
+                ("mmd", "mtools"),
+                ("mcopy", "mtools"),
+            ],
+        );
+        if std::env::var_os(crate::secureboot::SECUREBOOT_KEYS_ENV).is_some_and(|d| !d.is_empty()) {
+            tools.require_all("iso", &[("sbsign", "sbsigntools")]);
+        }
+    }
+}
+
 /// Generate SHA512 checksum for an ISO file.
 ///
 /// Writes checksum in standard format: "<hash>  <filename>" (two spaces)
@@ -202,18 +225,27 @@ pub fn mcopy_to_fat(fat_image: &Path, src: &Path, dst: &str) -> Result<()> {
 /// )?;
 /// ```
 pub fn create_efi_boot_image(output: &Path, efi_files: &[(&Path, &str)]) -> Result<()> {
-    // Create FAT16 image
-    create_fat16_image(output, EFIBOOT_SIZE_MB)?;
-
-    // Create directory structure
-    create_efi_dirs_in_fat(output)?;
+    let layout = efi_files
+        .iter()
+        .fold(EspLayout::new(), |layout, (src, name)| {
+            layout.file(src, format!("EFI/BOOT/{}", name))
+        });
+    create_esp_image(output, &layout)
+}
 
-    // Copy each EFI file
-    for (src, _name) in efi_files {
-        mcopy_to_fat(output, src, "::EFI/BOOT/")?;
+/// Create an El Torito EFI boot image populated from an [`EspLayout`].
+///
+/// The image is `EFIBOOT_SIZE_MB` unless the layout needs more (UKIs are
+/// often larger than that), in which case it grows with 25% headroom. With
+/// [`SECUREBOOT_KEYS_ENV`](crate::secureboot::SECUREBOOT_KEYS_ENV) set, the
+/// EFI binaries are signed with its db key.
+pub fn create_esp_image(output: &Path, layout: &EspLayout) -> Result<()> {
+    let needed_mb = (layout.content_bytes()? * 5 / 4).div_ceil(1024 * 1024) as u32 + 1;
+    create_fat16_image(output, EFIBOOT_SIZE_MB.max(needed_mb))?;
+    match SecureBootKeys::from_env()? {
+        Some(keys) => layout.clone().sign_with(keys).render_to_fat(output),
+        None => layout.render_to_fat(output),
     }
-
-    Ok(())
 }
 
 /// Run xorriso to create a bootable ISO.
@@ -270,6 +302,7 @@ pub fn run_xorriso(
     }
 
     cmd.arg_path(iso_root)
+        .progress(Progress::xorriso("xorriso"))
         .error_msg("xorriso failed. Install xorriso.")
         .run()?;
 
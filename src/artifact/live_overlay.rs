@@ -3,14 +3,27 @@
 //! This module provides:
 //! - OpenRC live overlay generation (AcornOS, IuppiterOS style)
 //! - Systemd live overlay generation (LevitateOS, RalphOS style)
+//! - Optional persistence of selected paths in an image on a labelled partition
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use std::fs;
 use std::os::unix::fs::symlink;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
+use crate::artifact::payload_role::PayloadRole;
+use crate::assets::AssetResolver;
+use crate::build::accessibility::Accessibility;
+use crate::build::banner::BuildMetadata;
+use crate::build::consoles::SerialConsoles;
+use crate::build::services::disable_openrc_services;
+use crate::build::shell_check::check_script;
+use crate::build::swap::LiveSwap;
+use crate::contracts::component::Op;
 use crate::copy_dir_recursive;
+use crate::executor::fragments::tmpfiles_fragment;
+use crate::guest_protocol::{framed_ready_marker, READY_PREFIX};
+use crate::identity::IdentityPolicy;
 
 /// Inittab variant controlling which consoles are enabled.
 #[derive(Debug, Clone, Copy)]
@@ -21,6 +34,122 @@ pub enum InittabVariant {
     SerialOnly,
 }
 
+/// Filesystem label searched for by default.
+///
+/// Fits the 11-character FAT limit so the partition can be created on any
+/// host, e.g. as the ISO's appended partition or on a USB stick.
+pub const DEFAULT_PERSISTENCE_LABEL: &str = "LIVEPERSIST";
+
+/// Paths kept across boots by default: SSH host keys and logs.
+pub const DEFAULT_PERSISTENT_PATHS: &[&str] = &["etc/ssh", "var/log"];
+
+/// ext4 image file on the persistence partition holding the persisted paths.
+pub const DEFAULT_PERSISTENCE_IMAGE: &str = "live-persist.img";
+
+/// Size of a newly created persistence image.
+pub const DEFAULT_PERSISTENCE_IMAGE_MB: u64 = 256;
+
+/// Writable persistence area for live media.
+///
+/// At boot the partition labelled `label` is mounted, and the ext4 image
+/// `image` on it (created with `image_size_mb` on first use) is
+/// loop-mounted at `/run/live-persist/store`. Each of `paths` is then
+/// bind-mounted from the image. Keeping the data in an image file means
+/// the partition itself may be FAT, while host keys still get the modes
+/// and ownership sshd insists on. The first boot seeds the persisted copy
+/// from the live image; when no such partition exists the session stays
+/// volatile.
+#[derive(Debug, Clone, Copy)]
+pub struct LivePersistence<'a> {
+    /// Filesystem label of the persistence partition.
+    pub label: &'a str,
+    /// Directories to persist, relative to `/`.
+    pub paths: &'a [&'a str],
+    /// File name of the ext4 image on the partition.
+    pub image: &'a str,
+    /// Size of the image when it has to be created.
+    pub image_size_mb: u64,
+}
+
+impl Default for LivePersistence<'_> {
+    fn default() -> Self {
+        Self {
+            label: DEFAULT_PERSISTENCE_LABEL,
+            paths: DEFAULT_PERSISTENT_PATHS,
+            image: DEFAULT_PERSISTENCE_IMAGE,
+            image_size_mb: DEFAULT_PERSISTENCE_IMAGE_MB,
+        }
+    }
+}
+
+impl LivePersistence<'_> {
+    /// Whether `rel` (relative to `/`) is persisted.
+    pub fn covers(&self, rel: &str) -> bool {
+        self.paths.iter().any(|p| p.trim_matches('/') == rel)
+    }
+
+    /// Contents of `/etc/live-persist.conf`, read by the boot script.
+    fn conf(&self) -> Result<String> {
+        if self.label.is_empty()
+            || self.label.len() > 16
+            || !self
+                .label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        {
+            bail!(
+                "invalid live persistence label '{}': use up to 16 of [A-Za-z0-9_-]",
+                self.label
+            );
+        }
+        if self.image.is_empty()
+            || self.image.starts_with('.')
+            || !self
+                .image
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            bail!(
+                "invalid live persistence image '{}': use a plain file name of [A-Za-z0-9_.-]",
+                self.image
+            );
+        }
+        if self.image_size_mb < 16 {
+            bail!(
+                "live persistence image of {} MiB is too small for ext4; use at least 16",
+                self.image_size_mb
+            );
+        }
+        if self.paths.is_empty() {
+            bail!("live persistence needs at least one path");
+        }
+        let mut paths = Vec::with_capacity(self.paths.len());
+        for raw in self.paths {
+            let rel = raw.trim_matches('/');
+            let valid = !rel.is_empty()
+                && rel.split('/').all(|part| {
+                    !part.is_empty()
+                        && part != "."
+                        && part != ".."
+                        && part
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                });
+            if !valid {
+                bail!("invalid live persistence path '{}'", raw);
+            }
+            paths.push(rel);
+        }
+        Ok(format!(
+            "# Generated by distro-builder\nLABEL=\"{}\"\nIMAGE=\"{}\"\nIMAGE_SIZE_MB=\"{}\"\nPATHS=\"{}\"\n",
+            self.label,
+            self.image,
+            self.image_size_mb,
+            paths.join(" ")
+        ))
+    }
+}
+
 /// Configuration for creating an OpenRC live overlay.
 #[derive(Debug)]
 pub struct LiveOverlayConfig<'a> {
@@ -30,8 +159,24 @@ pub struct LiveOverlayConfig<'a> {
     pub inittab: InittabVariant,
     /// Optional path to a variant-local live overlay seed directory to copy first.
     pub seed_overlay: Option<&'a Path>,
-    /// Optional override for `/etc/issue`.
+    /// Optional `/etc/issue` template (see [`crate::build::banner`]).
     pub issue_message: Option<&'a str>,
+    /// Optional `/etc/motd` template.
+    pub motd_template: Option<&'a str>,
+    /// Build the banners identify; just the OS name when `None`.
+    pub build: Option<&'a BuildMetadata>,
+    /// Override lookup for script assets; embedded copies when `None`.
+    pub assets: Option<&'a AssetResolver>,
+    /// Keep selected paths on a persistence partition; volatile when `None`.
+    pub persistence: Option<LivePersistence<'a>>,
+    /// Console font, beeper and screen reader defaults.
+    pub accessibility: Option<&'a Accessibility>,
+    /// OpenRC services to remove from every runlevel of the overlay.
+    pub disabled_services: &'a [&'a str],
+    /// Compressed swap for low-memory machines; none when `None`.
+    pub swap: Option<&'a LiveSwap>,
+    /// Serial consoles with an autologin getty; `ttyS0` when `None`.
+    pub consoles: Option<&'a SerialConsoles>,
 }
 
 /// Configuration for creating a systemd live overlay.
@@ -39,17 +184,31 @@ pub struct LiveOverlayConfig<'a> {
 pub struct SystemdLiveOverlayConfig<'a> {
     /// OS display name (e.g., "LevitateOS", "RalphOS").
     pub os_name: &'a str,
-    /// Optional override for `/etc/issue`.
+    /// Optional `/etc/issue` template (see [`crate::build::banner`]).
     pub issue_message: Option<&'a str>,
+    /// Optional `/etc/motd` template.
+    pub motd_template: Option<&'a str>,
+    /// Build the banners identify; just the OS name when `None`.
+    pub build: Option<&'a BuildMetadata>,
     /// Systemd unit names to mask by linking to `/dev/null`.
     pub masked_units: &'a [&'a str],
     /// Whether to write a serial-console scenario test marker profile script.
     pub write_serial_test_profile: bool,
-    /// Optional machine-id content to write at `/etc/machine-id`.
-    pub machine_id: Option<&'a str>,
+    /// Identity (machine-id, hostname) baked into the overlay.
+    pub identity: Option<&'a IdentityPolicy>,
     /// When true, install a strict UTF-8 locale profile for live shells.
     /// Live-rootfs producers must ensure UTF-8 locale payload exists in rootfs.
     pub enforce_utf8_locale_profile: bool,
+    /// Override lookup for script assets; embedded copies when `None`.
+    pub assets: Option<&'a AssetResolver>,
+    /// Keep selected paths on a persistence partition; volatile when `None`.
+    pub persistence: Option<LivePersistence<'a>>,
+    /// Console font, beeper and screen reader defaults.
+    pub accessibility: Option<&'a Accessibility>,
+    /// Compressed swap for low-memory machines; none when `None`.
+    pub swap: Option<&'a LiveSwap>,
+    /// Serial consoles with an autologin getty; `ttyS0` when `None`.
+    pub consoles: Option<&'a SerialConsoles>,
 }
 
 /// Create an OpenRC live overlay at `output_dir/live-overlay`.
@@ -61,6 +220,8 @@ pub fn create_openrc_live_overlay(
     config: &LiveOverlayConfig,
 ) -> Result<PathBuf> {
     println!("Creating live overlay...");
+    let embedded = AssetResolver::embedded_only();
+    let assets = config.assets.unwrap_or(&embedded);
 
     let live_overlay = output_dir.join("live-overlay");
 
@@ -88,59 +249,21 @@ pub fn create_openrc_live_overlay(
 
     // Serial autologin script
     fs::create_dir_all(live_overlay.join("usr/local/bin"))?;
-    let autologin_script = r#"#!/bin/sh
-# Autologin for serial console testing
-# Called by agetty -l as the login program
-# agetty has already set up stdin/stdout/stderr on the tty
-
-# Optional boot-injection environment (e.g. SSH_AUTHORIZED_KEY, LEVITATE_INSTALL_SERIAL_UX).
-# Parse as literal KEY=VALUE lines to avoid executing payload content.
-if [ -r /run/boot-injection/payload.env ]; then
-    while IFS= read -r line; do
-        case "$line" in
-            ""|\#*) continue ;;
-            *=*)
-                key="${line%%=*}"
-                value="${line#*=}"
-                case "$key" in
-                    [A-Za-z_][A-Za-z0-9_]*) export "$key=$value" ;;
-                esac
-                ;;
-        esac
-    done < /run/boot-injection/payload.env
-fi
-
-echo "[autologin] Starting login shell..."
-echo "___SHELL_READY___"
-echo "[autologin] Starting login shell..." >/dev/console 2>/dev/null || true
-echo "___SHELL_READY___" >/dev/console 2>/dev/null || true
-echo "___SHELL_READY___" >/dev/kmsg 2>/dev/null || true
-
-if [ "${LEVITATE_INSTALL_SERIAL_UX:-0}" = "1" ] && [ -x /usr/local/bin/levitate-install-entrypoint ]; then
-    echo "[autologin] Launching install UX on serial console..."
-    export LEVITATE_INSTALL_UX_LAUNCHED=1
-    exec /usr/local/bin/levitate-install-entrypoint
-fi
-
-# Run sh as login shell (sources /etc/profile and /etc/profile.d/*)
-# In Alpine, /bin/sh is busybox ash
-exec /bin/sh -l
-"#;
+    let autologin_script = assets.load("live-overlay/openrc/serial-autologin")?;
     write_executable(
         &live_overlay.join("usr/local/bin/serial-autologin"),
-        autologin_script,
+        &autologin_script.replace(READY_PREFIX, &framed_ready_marker()),
     )?;
 
-    // /etc/issue
-    let default_issue = format!(
-        "\n{} Live - \\l\n\nLogin as 'root' (no password)\n\n",
-        config.os_name
-    );
-    fs::write(
-        live_overlay.join("etc/issue"),
-        config.issue_message.unwrap_or(&default_issue),
+    // /etc/issue and /etc/motd
+    write_live_banners(
+        &live_overlay,
+        config.os_name,
+        config.build,
+        config.issue_message,
+        config.motd_template,
     )?;
-    fs::write(live_overlay.join(".live-payload-role"), "overlay\n")?;
+    PayloadRole::Overlay.write(&live_overlay)?;
 
     // Empty root password for live session
     let shadow_content = "root::0:0:99999:7:::\n\
@@ -153,6 +276,8 @@ exec /bin/sh -l
     fs::set_permissions(live_overlay.join("etc/shadow"), perms)?;
 
     // Inittab
+    let default_consoles = SerialConsoles::default();
+    let consoles = config.consoles.unwrap_or(&default_consoles);
     let inittab_content = match config.inittab {
         InittabVariant::DesktopWithSerial => format!(
             r#"# /etc/inittab - {} Live
@@ -170,7 +295,7 @@ tty6::respawn:/sbin/getty 38400 tty6
 
 # Serial console with autologin for test harness
 # Uses wrapper script that spawns ash as login shell (sources /etc/profile.d/*)
-ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0 vt100
+{}
 
 # Continue remaining services after serial shell is available
 ::wait:/sbin/openrc default
@@ -181,7 +306,8 @@ ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0
 # Shutdown
 ::shutdown:/sbin/openrc shutdown
 "#,
-            config.os_name
+            config.os_name,
+            consoles.inittab_entries()
         ),
         InittabVariant::SerialOnly => format!(
             r#"# /etc/inittab - {} Live (headless appliance)
@@ -189,9 +315,9 @@ ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0
 ::sysinit:/sbin/openrc sysinit
 ::sysinit:/sbin/openrc boot
 
-# Serial console PRIMARY with autologin (ttyS0) - appliance has no display
+# Serial console PRIMARY with autologin - appliance has no display
 # Uses wrapper script that spawns ash as login shell (sources /etc/profile.d/*)
-ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0 vt100
+{}
 
 # Continue remaining services after serial shell is available
 ::wait:/sbin/openrc default
@@ -202,7 +328,8 @@ ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0
 # Shutdown
 ::shutdown:/sbin/openrc shutdown
 "#,
-            config.os_name
+            config.os_name,
+            consoles.inittab_entries()
         ),
     };
     fs::write(live_overlay.join("etc/inittab"), inittab_content)?;
@@ -211,59 +338,39 @@ ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0
     fs::create_dir_all(live_overlay.join("etc/runlevels/default"))?;
     fs::create_dir_all(live_overlay.join("etc/conf.d"))?;
 
-    // Volatile log storage
-    let fstab_content = format!(
-        "# {} Live fstab\n\
-         # Volatile log storage - prevents logs from filling overlay tmpfs\n\
-         tmpfs   /var/log    tmpfs   nosuid,nodev,noexec,size=64M,mode=0755   0 0\n",
-        config.os_name
-    );
+    // Volatile log storage, unless logs go to the persistence partition
+    let persist_logs = config
+        .persistence
+        .is_some_and(|persistence| persistence.covers("var/log"));
+    let fstab_content = if persist_logs {
+        format!(
+            "# {} Live fstab\n\
+             # /var/log is bind-mounted from the persistence partition\n",
+            config.os_name
+        )
+    } else {
+        format!(
+            "# {} Live fstab\n\
+             # Volatile log storage - prevents logs from filling overlay tmpfs\n\
+             tmpfs   /var/log    tmpfs   nosuid,nodev,noexec,size=64M,mode=0755   0 0\n",
+            config.os_name
+        )
+    };
     fs::write(live_overlay.join("etc/fstab"), fstab_content)?;
 
     // local.d scripts
     fs::create_dir_all(live_overlay.join("etc/local.d"))?;
 
-    let volatile_log_script = r#"#!/bin/sh
-# Ensure volatile log storage for live session
-# This runs early in boot to catch any logs before syslog starts
-
-# Only mount if not already a tmpfs (idempotent)
-if ! mountpoint -q /var/log 2>/dev/null; then
-    # Preserve any existing logs created before mount
-    if [ -d /var/log ]; then
-        mkdir -p /tmp/log-backup
-        cp -a /var/log/* /tmp/log-backup/ 2>/dev/null || true
-    fi
-
-    mount -t tmpfs -o nosuid,nodev,noexec,size=64M,mode=0755 tmpfs /var/log
-
-    # Restore preserved logs
-    if [ -d /tmp/log-backup ]; then
-        cp -a /tmp/log-backup/* /var/log/ 2>/dev/null || true
-        rm -rf /tmp/log-backup
-    fi
-
-    # Ensure log directories exist
-    mkdir -p /var/log/chrony 2>/dev/null || true
-fi
-"#;
+    let volatile_log_script = assets.load("live-overlay/openrc/00-volatile-log.start")?;
     write_executable(
         &live_overlay.join("etc/local.d/00-volatile-log.start"),
-        volatile_log_script,
+        &volatile_log_script,
     )?;
 
-    let efivars_script = r#"#!/bin/sh
-# Ensure efivarfs is mounted for UEFI support
-# Needed for efibootmgr, bootctl, and install tests
-
-if [ -d /sys/firmware/efi ]; then
-    mkdir -p /sys/firmware/efi/efivars 2>/dev/null
-    mount -t efivarfs efivarfs /sys/firmware/efi/efivars 2>/dev/null || true
-fi
-"#;
+    let efivars_script = assets.load("live-overlay/openrc/01-efivarfs.start")?;
     write_executable(
         &live_overlay.join("etc/local.d/01-efivarfs.start"),
-        efivars_script,
+        &efivars_script,
     )?;
 
     // Do-not-suspend configuration
@@ -331,6 +438,26 @@ esac
         logind_conf,
     )?;
 
+    if let Some(persistence) = &config.persistence {
+        write_persistence(&live_overlay, persistence, assets)?;
+        let init_script = assets.load("live-overlay/openrc/live-persist.initd")?;
+        fs::create_dir_all(live_overlay.join("etc/init.d"))?;
+        write_executable(&live_overlay.join("etc/init.d/live-persist"), &init_script)?;
+        fs::create_dir_all(live_overlay.join("etc/runlevels/boot"))?;
+        symlink(
+            "/etc/init.d/live-persist",
+            live_overlay.join("etc/runlevels/boot/live-persist"),
+        )?;
+    }
+
+    if let Some(accessibility) = config.accessibility {
+        accessibility.apply_openrc(&live_overlay)?;
+    }
+    if let Some(swap) = config.swap {
+        swap.apply_openrc(&live_overlay)?;
+    }
+    disable_openrc_services(&live_overlay, config.disabled_services)?;
+
     println!("  Live overlay created at {}", live_overlay.display());
     Ok(live_overlay)
 }
@@ -341,12 +468,14 @@ esac
 /// - tty1 autologin drop-in
 /// - serial-getty template autologin drop-in
 /// - empty root password for live session (`/etc/shadow`)
-/// - `/etc/issue` banner
+/// - `/etc/issue` and `/etc/motd` banners
 pub fn create_systemd_live_overlay(
     output_dir: &Path,
     config: &SystemdLiveOverlayConfig,
 ) -> Result<PathBuf> {
     println!("Creating systemd live overlay...");
+    let embedded = AssetResolver::embedded_only();
+    let assets = config.assets.unwrap_or(&embedded);
 
     let live_overlay = output_dir.join("live-overlay");
     if live_overlay.exists() {
@@ -365,50 +494,16 @@ pub fn create_systemd_live_overlay(
     fs::create_dir_all(live_overlay.join("usr/local/bin"))?;
     fs::create_dir_all(live_overlay.join("usr/local/sbin"))?;
 
-    let live_help_script = r#"#!/bin/sh
-cat <<'EOF'
-LevitateOS Live Quick Help
-
-Common commands:
-  levitate-install-entrypoint --probe
-  install.sh
-  live-net-setup.service (systemd unit)
-  recstrap / recfstab / recchroot
-  docs-tui --slug installation
-
-Split-pane controls:
-  Ctrl-g  toggle focused pane
-  Ctrl-q  quit split-pane UI
-EOF
-"#;
+    let live_help_script = assets.load("live-overlay/systemd/live-help")?;
     write_executable(
         &live_overlay.join("usr/local/bin/live-help"),
-        live_help_script,
+        &live_help_script,
     )?;
 
-    let shell_ux_profile = r#"#!/bin/sh
-case "$-" in
-    *i*) ;;
-    *) return 0 ;;
-esac
-
-export EDITOR="${EDITOR:-vi}"
-export VISUAL="${VISUAL:-$EDITOR}"
-export PAGER="${PAGER:-less}"
-export LESS="${LESS:--FRSX}"
-
-if [ -z "${PS1:-}" ]; then
-    export PS1='[live \u@\h \W]\$ '
-fi
-
-if [ "${LIVE_HELP_HINT_SHOWN:-0}" != "1" ]; then
-    export LIVE_HELP_HINT_SHOWN=1
-    echo "[live] run 'live-help' for common commands."
-fi
-"#;
+    let shell_ux_profile = assets.load("live-overlay/systemd/20-live-shell-ux.sh")?;
     write_executable(
         &live_overlay.join("etc/profile.d/20-live-shell-ux.sh"),
-        shell_ux_profile,
+        &shell_ux_profile,
     )?;
 
     let tty1_autologin =
@@ -418,159 +513,54 @@ fi
         tty1_autologin,
     )?;
 
-    let serial_autologin_script = r#"#!/bin/sh
-# Live override: use portable C locale (always available in minimal rootfs).
-export LANG=C
-unset LC_ALL LC_CTYPE LC_NUMERIC LC_TIME LC_COLLATE LC_MESSAGES \
-      LC_MONETARY LC_PAPER LC_NAME LC_ADDRESS LC_TELEPHONE LC_MEASUREMENT \
-      LC_IDENTIFICATION
-
-# Optional boot-injection environment (e.g. SSH_AUTHORIZED_KEY, LEVITATE_INSTALL_SERIAL_UX).
-# Parse as literal KEY=VALUE lines to avoid executing payload content.
-if [ -r /run/boot-injection/payload.env ]; then
-    while IFS= read -r line; do
-        case "$line" in
-            ""|\#*) continue ;;
-            *=*)
-                key="${line%%=*}"
-                value="${line#*=}"
-                case "$key" in
-                    [A-Za-z_][A-Za-z0-9_]*) export "$key=$value" ;;
-                esac
-                ;;
-        esac
-    done < /run/boot-injection/payload.env
-fi
-
-echo "___SHELL_READY___"
-
-# Keep default serial shell readable, but allow explicit verbose override for debugging.
-if [ "${LEVITATE_INSTALL_SERIAL_VERBOSE:-0}" = "1" ]; then
-    if [ -w /proc/sys/kernel/printk ]; then
-        echo 7 >/proc/sys/kernel/printk 2>/dev/null || true
-    fi
-    if command -v dmesg >/dev/null 2>&1; then
-        dmesg -n 7 >/dev/null 2>&1 || true
-    fi
-else
-    if [ -w /proc/sys/kernel/printk ]; then
-        echo 1 >/proc/sys/kernel/printk 2>/dev/null || true
-    fi
-    if command -v dmesg >/dev/null 2>&1; then
-        dmesg -n 1 >/dev/null 2>&1 || true
-    fi
-fi
-
-if [ "${LEVITATE_INSTALL_SERIAL_UX:-0}" = "1" ] && [ -x /usr/local/bin/levitate-install-entrypoint ]; then
-    echo "[autologin] Launching install UX on serial console..."
-    export LEVITATE_INSTALL_UX_LAUNCHED=1
-    exec /usr/local/bin/levitate-install-entrypoint
-fi
-
-exec /bin/bash -il
-"#;
+    let serial_autologin_script = assets.load("live-overlay/systemd/serial-autologin")?;
     write_executable(
         &live_overlay.join("usr/local/bin/serial-autologin"),
-        serial_autologin_script,
+        &serial_autologin_script.replace(READY_PREFIX, &framed_ready_marker()),
     )?;
 
-    let serial_autologin = "[Service]\nExecStart=\nExecStart=-/sbin/agetty --autologin root --keep-baud 115200,57600,38400,9600 %I vt100\n";
+    let default_consoles = SerialConsoles::default();
+    let consoles = config.consoles.unwrap_or(&default_consoles);
+    let serial_autologin = format!(
+        "[Service]\nExecStart=\nExecStart=-/sbin/agetty --autologin root --keep-baud {} %I vt100\n",
+        consoles.agetty_bauds()
+    );
     fs::write(
         live_overlay.join("etc/systemd/system/serial-getty@.service.d/zz-autologin.conf"),
-        serial_autologin,
+        &serial_autologin,
     )?;
     fs::write(
         live_overlay.join("etc/systemd/system/getty@.service.d/zz-autologin.conf"),
-        serial_autologin,
+        &serial_autologin,
     )?;
     symlink(
         "/usr/lib/systemd/system/getty@.service",
         live_overlay.join("etc/systemd/system/getty.target.wants/getty@tty1.service"),
     )?;
-    symlink(
-        "/usr/lib/systemd/system/serial-getty@.service",
-        live_overlay.join("etc/systemd/system/basic.target.wants/serial-getty@ttyS0.service"),
-    )?;
-    symlink(
-        "/usr/lib/systemd/system/serial-getty@.service",
-        live_overlay.join("etc/systemd/system/getty.target.wants/serial-getty@ttyS0.service"),
-    )?;
+    for unit in consoles.systemd_getty_units() {
+        for target in ["basic.target.wants", "getty.target.wants"] {
+            symlink(
+                "/usr/lib/systemd/system/serial-getty@.service",
+                live_overlay
+                    .join("etc/systemd/system")
+                    .join(target)
+                    .join(&unit),
+            )?;
+        }
+    }
     // Deterministic live NIC bring-up for slirp hostfwd SSH (QEMU usernet defaults).
     // Resolve the first non-loopback NIC dynamically to avoid brittle interface names
     // (e.g. ens3 vs ens4 depending on device ordering).
-    let live_net_setup_script = r#"#!/bin/sh
-set -eu
-
-IP_BIN="$(command -v ip 2>/dev/null || true)"
-if [ -z "$IP_BIN" ]; then
-    if [ -x /usr/sbin/ip ]; then
-        IP_BIN=/usr/sbin/ip
-    elif [ -x /usr/bin/ip ]; then
-        IP_BIN=/usr/bin/ip
-    else
-        exit 1
-    fi
-fi
-
-# Retry because virtio NIC names can race (e.g. eth0 -> ens5), and keep
-# applying until IPv4 + default route are stable.
-stable_ok=0
-i=0
-while [ "$i" -lt 45 ]; do
-    for dev in /sys/class/net/*; do
-        [ -e "$dev" ] || continue
-        name="$(basename "$dev")"
-        case "$name" in
-            lo|sit*|ip6tnl*|tun*|tap*|dummy*|bond*|veth*|docker*|virbr*|br*|wg*)
-                continue
-                ;;
-        esac
-        if "$IP_BIN" link set "$name" up 2>/dev/null; then
-            "$IP_BIN" addr replace 10.0.2.15/24 dev "$name" 2>/dev/null || true
-            "$IP_BIN" route replace default via 10.0.2.2 dev "$name" 2>/dev/null || true
-        fi
-    done
-
-    addr_if="$("$IP_BIN" -o -4 addr show 2>/dev/null | awk '$4 ~ /^10\\.0\\.2\\.15\\// {print $2; exit}')"
-    default_if="$("$IP_BIN" -o -4 route show default 2>/dev/null | awk '/^default via 10\\.0\\.2\\.2/ {for (i=1; i<=NF; i++) if ($i==\"dev\") {print $(i+1); exit}}')"
-    if [ -n "$addr_if" ] && [ "$addr_if" = "$default_if" ]; then
-        stable_ok=$((stable_ok + 1))
-        if [ "$stable_ok" -ge 3 ]; then
-            exit 0
-        fi
-    else
-        stable_ok=0
-    fi
-
-    i=$((i + 1))
-    sleep 1
-done
-
-exit 1
-"#;
+    let live_net_setup_script = assets.load("live-overlay/systemd/live-net-setup")?;
     write_executable(
         &live_overlay.join("usr/local/sbin/live-net-setup"),
-        live_net_setup_script,
+        &live_net_setup_script,
     )?;
 
-    let live_net_setup_unit = r#"[Unit]
-Description=Levitate live network setup (slirp SSH)
-DefaultDependencies=no
-After=basic.target local-fs.target
-Before=network.target network-online.target sshd.service
-Wants=network.target
-
-[Service]
-Type=oneshot
-RemainAfterExit=yes
-ExecStart=/usr/local/sbin/live-net-setup
-
-[Install]
-WantedBy=multi-user.target
-"#;
+    let live_net_setup_unit = assets.load("live-overlay/systemd/live-net-setup.service")?;
     fs::write(
         live_overlay.join("etc/systemd/system/live-net-setup.service"),
-        live_net_setup_unit,
+        &live_net_setup_unit,
     )?;
     symlink(
         "/etc/systemd/system/live-net-setup.service",
@@ -580,60 +570,21 @@ WantedBy=multi-user.target
         symlink(
             "/dev/null",
             live_overlay.join(format!("etc/systemd/system/{unit}")),
-        )?;
+        )
+        .with_context(|| format!("Failed to mask {unit}"))?;
     }
 
-    let shutdown_cleanup_script = r#"#!/bin/sh
-# Live ISO shutdown cleanup:
-# release loop-backed live mounts before systemd reaches umount.target.
-set +e
-
-for mp in /live-overlay /rootfs /run/live-media; do
-    if mountpoint -q "$mp"; then
-        umount "$mp" >/dev/null 2>&1 || umount -l "$mp" >/dev/null 2>&1
-    fi
-done
-
-if [ -d /run/live-media ]; then
-    rmdir /run/live-media 2>/dev/null || true
-fi
-
-for loopdev in /dev/loop1 /dev/loop0; do
-    if [ -b "$loopdev" ]; then
-        losetup -d "$loopdev" >/dev/null 2>&1 || true
-    fi
-done
-
-if mountpoint -q /media/cdrom; then
-    umount /media/cdrom >/dev/null 2>&1 || umount -l /media/cdrom >/dev/null 2>&1
-fi
-
-exit 0
-"#;
+    let shutdown_cleanup_script = assets.load("live-overlay/systemd/live-shutdown-cleanup")?;
     write_executable(
         &live_overlay.join("usr/local/sbin/live-shutdown-cleanup"),
-        shutdown_cleanup_script,
+        &shutdown_cleanup_script,
     )?;
 
-    let shutdown_cleanup_unit = r#"[Unit]
-Description=Live ISO shutdown cleanup
-DefaultDependencies=no
-After=multi-user.target
-Before=run-live-media.mount umount.target shutdown.target
-Conflicts=shutdown.target umount.target
-
-[Service]
-Type=oneshot
-RemainAfterExit=yes
-ExecStart=/bin/true
-ExecStop=/usr/local/sbin/live-shutdown-cleanup
-
-[Install]
-WantedBy=multi-user.target
-"#;
+    let shutdown_cleanup_unit =
+        assets.load("live-overlay/systemd/live-shutdown-cleanup.service")?;
     fs::write(
         live_overlay.join("etc/systemd/system/live-shutdown-cleanup.service"),
-        shutdown_cleanup_unit,
+        &shutdown_cleanup_unit,
     )?;
     symlink(
         "/etc/systemd/system/live-shutdown-cleanup.service",
@@ -641,52 +592,61 @@ WantedBy=multi-user.target
             .join("etc/systemd/system/multi-user.target.wants/live-shutdown-cleanup.service"),
     )?;
 
-    let default_issue = format!(
-        "\n{} Live - \\l\n\nLogin as 'root' (no password)\n\n",
-        config.os_name
-    );
-    fs::write(
-        live_overlay.join("etc/issue"),
-        config.issue_message.unwrap_or(&default_issue),
+    write_live_banners(
+        &live_overlay,
+        config.os_name,
+        config.build,
+        config.issue_message,
+        config.motd_template,
     )?;
 
     if config.write_serial_test_profile {
-        let test_profile = r#"# Scenario harness markers (serial console only)
-case "$-" in
-    *i*) ;;
-    *) return 0 ;;
-esac
-
-if [ "$(tty 2>/dev/null)" = "/dev/ttyS0" ]; then
-    echo "___SHELL_READY___"
-fi
-"#;
+        let test_profile = assets.load("live-overlay/systemd/00-live-test.sh")?;
+        let test_profile_path = live_overlay.join("etc/profile.d/00-live-test.sh");
         fs::write(
-            live_overlay.join("etc/profile.d/00-live-test.sh"),
-            test_profile,
+            &test_profile_path,
+            test_profile.replace(READY_PREFIX, &framed_ready_marker()),
         )?;
+        check_script(&test_profile_path)?;
     }
 
     if config.enforce_utf8_locale_profile {
         // Live override: use portable C locale for minimal rootfs payloads.
-        let locale_profile = r#"#!/bin/sh
-# Live override: use portable C locale (always available).
-export LANG=C
-unset LC_ALL LC_CTYPE LC_NUMERIC LC_TIME LC_COLLATE LC_MESSAGES \
-      LC_MONETARY LC_PAPER LC_NAME LC_ADDRESS LC_TELEPHONE LC_MEASUREMENT \
-      LC_IDENTIFICATION
-"#;
-        write_executable(&live_overlay.join("etc/profile.d/lang.sh"), locale_profile)?;
+        let locale_profile = assets.load("live-overlay/systemd/lang.sh")?;
+        write_executable(&live_overlay.join("etc/profile.d/lang.sh"), &locale_profile)?;
     }
 
     // Ensure runtime sshd directory exists on tmpfs-backed /run before sshd starts.
-    fs::write(
-        live_overlay.join("etc/tmpfiles.d/sshd-local.conf"),
-        "d /run/sshd 0755 root root -\n",
-    )?;
+    if let Some(sshd_tmpfiles) = tmpfiles_fragment(&[Op::Dir("run/sshd".into())]) {
+        fs::write(
+            live_overlay.join("etc/tmpfiles.d/sshd-local.conf"),
+            sshd_tmpfiles,
+        )?;
+    }
 
-    if let Some(machine_id) = config.machine_id {
-        fs::write(live_overlay.join("etc/machine-id"), machine_id)?;
+    if let Some(identity) = config.identity {
+        identity.apply(&live_overlay)?;
+    }
+
+    if let Some(persistence) = &config.persistence {
+        write_persistence(&live_overlay, persistence, assets)?;
+        let unit = assets.load("live-overlay/systemd/live-persist.service")?;
+        fs::write(
+            live_overlay.join("etc/systemd/system/live-persist.service"),
+            &unit,
+        )?;
+        fs::create_dir_all(live_overlay.join("etc/systemd/system/sysinit.target.wants"))?;
+        symlink(
+            "/etc/systemd/system/live-persist.service",
+            live_overlay.join("etc/systemd/system/sysinit.target.wants/live-persist.service"),
+        )?;
+    }
+
+    if let Some(accessibility) = config.accessibility {
+        accessibility.apply_systemd(&live_overlay)?;
+    }
+    if let Some(swap) = config.swap {
+        swap.apply_systemd(&live_overlay)?;
     }
 
     // Keep root password empty for live autologin, but avoid "password change
@@ -699,7 +659,7 @@ unset LC_ALL LC_CTYPE LC_NUMERIC LC_TIME LC_COLLATE LC_MESSAGES \
     let mut perms = fs::metadata(live_overlay.join("etc/shadow"))?.permissions();
     perms.set_mode(0o600);
     fs::set_permissions(live_overlay.join("etc/shadow"), perms)?;
-    fs::write(live_overlay.join(".live-payload-role"), "overlay\n")?;
+    PayloadRole::Overlay.write(&live_overlay)?;
 
     println!(
         "  Systemd live overlay created at {}",
@@ -708,11 +668,125 @@ unset LC_ALL LC_CTYPE LC_NUMERIC LC_TIME LC_COLLATE LC_MESSAGES \
     Ok(live_overlay)
 }
 
+/// Install the persistence script and its configuration.
+fn write_persistence(
+    live_overlay: &Path,
+    persistence: &LivePersistence,
+    assets: &AssetResolver,
+) -> Result<()> {
+    let conf = persistence.conf()?;
+    fs::create_dir_all(live_overlay.join("usr/local/sbin"))?;
+    let script = assets.load("live-overlay/common/live-persist")?;
+    write_executable(&live_overlay.join("usr/local/sbin/live-persist"), &script)?;
+    fs::write(live_overlay.join("etc/live-persist.conf"), conf)?;
+    Ok(())
+}
+
+/// Write `/etc/issue`, and `/etc/motd` when there is a template or a build
+/// to name.
+fn write_live_banners(
+    live_overlay: &Path,
+    os_name: &str,
+    build: Option<&BuildMetadata>,
+    issue_template: Option<&str>,
+    motd_template: Option<&str>,
+) -> Result<()> {
+    let fallback = BuildMetadata::new(os_name);
+    let build = build.unwrap_or(&fallback);
+    let issue = build
+        .live_issue(issue_template)
+        .context("Failed to render /etc/issue")?;
+    fs::write(live_overlay.join("etc/issue"), issue)?;
+    if let Some(motd) = build
+        .live_motd(motd_template)
+        .context("Failed to render /etc/motd")?
+    {
+        fs::write(live_overlay.join("etc/motd"), motd)?;
+    }
+    Ok(())
+}
+
 /// Write a file and make it executable (mode 0o755).
 fn write_executable(path: &Path, content: &str) -> Result<()> {
     fs::write(path, content)?;
+    check_script(path)?;
     let mut perms = fs::metadata(path)?.permissions();
     perms.set_mode(0o755);
     fs::set_permissions(path, perms)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_persistence_conf_validation() {
+        let conf = LivePersistence::default().conf().unwrap();
+        assert!(conf.contains("LABEL=\"LIVEPERSIST\""));
+        assert!(conf.contains("IMAGE=\"live-persist.img\""));
+        assert!(conf.contains("IMAGE_SIZE_MB=\"256\""));
+        assert!(conf.contains("PATHS=\"etc/ssh var/log\""));
+
+        let bad_label = LivePersistence {
+            label: "live persist",
+            ..Default::default()
+        };
+        assert!(bad_label.conf().is_err());
+        for image in ["", "../live.img", ".hidden", "live persist.img"] {
+            let bad_image = LivePersistence {
+                image,
+                ..Default::default()
+            };
+            assert!(bad_image.conf().is_err(), "{image:?} should be rejected");
+        }
+        let tiny = LivePersistence {
+            image_size_mb: 1,
+            ..Default::default()
+        };
+        assert!(tiny.conf().is_err());
+        for path in ["", "/", "../etc", "etc/ssh keys", "var/$(reboot)"] {
+            let paths = [path];
+            let bad_path = LivePersistence {
+                paths: &paths,
+                ..Default::default()
+            };
+            assert!(bad_path.conf().is_err(), "{path:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_openrc_persistence_replaces_volatile_logs() {
+        let temp = TempDir::new().unwrap();
+        let mut config = LiveOverlayConfig {
+            os_name: "TestOS",
+            inittab: InittabVariant::SerialOnly,
+            seed_overlay: None,
+            issue_message: None,
+            motd_template: None,
+            build: None,
+            assets: None,
+            persistence: None,
+            accessibility: None,
+            disabled_services: &[],
+            swap: None,
+            consoles: None,
+        };
+        let overlay = create_openrc_live_overlay(temp.path(), &config).unwrap();
+        let fstab = fs::read_to_string(overlay.join("etc/fstab")).unwrap();
+        assert!(fstab.contains("tmpfs   /var/log"));
+        assert!(!overlay.join("etc/live-persist.conf").exists());
+
+        config.persistence = Some(LivePersistence::default());
+        let overlay = create_openrc_live_overlay(temp.path(), &config).unwrap();
+        let fstab = fs::read_to_string(overlay.join("etc/fstab")).unwrap();
+        assert!(!fstab.contains("tmpfs"));
+        assert!(overlay.join("usr/local/sbin/live-persist").is_file());
+        assert!(overlay.join("etc/live-persist.conf").is_file());
+        assert!(overlay
+            .join("etc/runlevels/boot/live-persist")
+            .symlink_metadata()
+            .is_ok());
+    }
+}
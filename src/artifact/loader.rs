@@ -0,0 +1,480 @@
+//! Typed systemd-boot configuration (Boot Loader Specification Type #1).
+//!
+//! systemd-boot skips entries it cannot parse without telling anyone: a
+//! missing `.conf` suffix, a `linux` path without a leading slash, or an
+//! entry with both `linux` and `efi` just disappears from the menu.
+//! [`LoaderEntry`] and [`LoaderConf`] validate before rendering, and
+//! [`LoaderEntry::parse`] lets raw strings from older configs go through the
+//! same checks.
+
+use anyhow::{bail, Context, Result};
+use std::fmt::Write as _;
+
+/// One `loader/entries/*.conf` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoaderEntry {
+    pub title: String,
+    pub version: Option<String>,
+    pub machine_id: Option<String>,
+    pub sort_key: Option<String>,
+    /// Kernel path on the ESP (Type #1 entry).
+    pub linux: Option<String>,
+    pub initrd: Vec<String>,
+    /// EFI program path, e.g. a UKI; exclusive with `linux`.
+    pub efi: Option<String>,
+    pub options: Vec<String>,
+    pub devicetree: Option<String>,
+    pub architecture: Option<String>,
+}
+
+impl LoaderEntry {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    pub fn sort_key(mut self, sort_key: impl Into<String>) -> Self {
+        self.sort_key = Some(sort_key.into());
+        self
+    }
+
+    pub fn linux(mut self, path: impl Into<String>) -> Self {
+        self.linux = Some(path.into());
+        self
+    }
+
+    pub fn initrd(mut self, path: impl Into<String>) -> Self {
+        self.initrd.push(path.into());
+        self
+    }
+
+    pub fn efi(mut self, path: impl Into<String>) -> Self {
+        self.efi = Some(path.into());
+        self
+    }
+
+    /// Append kernel command line options (space separated words are fine).
+    pub fn options(mut self, options: impl Into<String>) -> Self {
+        self.options.push(options.into());
+        self
+    }
+
+    pub fn devicetree(mut self, path: impl Into<String>) -> Self {
+        self.devicetree = Some(path.into());
+        self
+    }
+
+    /// Check the entry against what systemd-boot will accept.
+    pub fn validate(&self) -> Result<()> {
+        if self.title.trim().is_empty() {
+            bail!("loader entry has no title");
+        }
+        for (key, value) in self.fields() {
+            if value.contains('\n') {
+                bail!("loader entry '{}': {} contains a newline", self.title, key);
+            }
+        }
+        match (&self.linux, &self.efi) {
+            (None, None) => bail!("loader entry '{}' needs 'linux' or 'efi'", self.title),
+            (Some(_), Some(_)) => {
+                bail!("loader entry '{}' sets both 'linux' and 'efi'", self.title)
+            }
+            (None, Some(_)) if !self.initrd.is_empty() || self.devicetree.is_some() => bail!(
+                "loader entry '{}': 'initrd' and 'devicetree' require 'linux'",
+                self.title
+            ),
+            _ => {}
+        }
+        let paths = self
+            .linux
+            .iter()
+            .chain(&self.efi)
+            .chain(&self.initrd)
+            .chain(&self.devicetree);
+        for path in paths {
+            if !path.starts_with('/') {
+                bail!(
+                    "loader entry '{}': path '{}' must be absolute from the ESP root",
+                    self.title,
+                    path
+                );
+            }
+        }
+        if let Some(sort_key) = &self.sort_key {
+            validate_token("sort-key", sort_key, |c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-')
+            })?;
+        }
+        if let Some(version) = &self.version {
+            validate_token("version", version, |c| c.is_ascii_graphic())?;
+        }
+        if let Some(machine_id) = &self.machine_id {
+            if machine_id.len() != 32 || !machine_id.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!(
+                    "loader entry machine-id '{}' is not 32 hex digits",
+                    machine_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate and serialize.
+    pub fn render(&self) -> Result<String> {
+        self.validate()?;
+        let mut out = String::new();
+        for (key, value) in self.fields() {
+            let _ = writeln!(out, "{} {}", key, value);
+        }
+        Ok(out)
+    }
+
+    /// Parse an entry file; unknown keys are rejected.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut entry = Self::default();
+        for (key, value) in parse_lines(content)? {
+            match key {
+                "title" => entry.title = value.to_string(),
+                "version" => entry.version = Some(value.to_string()),
+                "machine-id" => entry.machine_id = Some(value.to_string()),
+                "sort-key" => entry.sort_key = Some(value.to_string()),
+                "linux" => entry.linux = Some(value.to_string()),
+                "initrd" => entry.initrd.push(value.to_string()),
+                "efi" => entry.efi = Some(value.to_string()),
+                "options" => entry.options.push(value.to_string()),
+                "devicetree" => entry.devicetree = Some(value.to_string()),
+                "architecture" => entry.architecture = Some(value.to_string()),
+                other => bail!("unknown loader entry key '{}'", other),
+            }
+        }
+        entry.validate()?;
+        Ok(entry)
+    }
+
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        let mut fields = vec![("title", self.title.as_str())];
+        let singles = [
+            ("version", &self.version),
+            ("machine-id", &self.machine_id),
+            ("sort-key", &self.sort_key),
+            ("architecture", &self.architecture),
+            ("linux", &self.linux),
+            ("efi", &self.efi),
+        ];
+        for (key, value) in singles {
+            if let Some(value) = value {
+                fields.push((key, value.as_str()));
+            }
+        }
+        fields.extend(self.initrd.iter().map(|p| ("initrd", p.as_str())));
+        if let Some(devicetree) = &self.devicetree {
+            fields.push(("devicetree", devicetree.as_str()));
+        }
+        fields.extend(self.options.iter().map(|o| ("options", o.as_str())));
+        fields
+    }
+}
+
+/// Check a `loader/entries/` file name; anything not ending in `.conf` is ignored.
+pub fn validate_entry_filename(filename: &str) -> Result<()> {
+    let Some(stem) = filename.strip_suffix(".conf") else {
+        bail!(
+            "loader entry filename '{}' must end in .conf or systemd-boot ignores it",
+            filename
+        );
+    };
+    validate_token("entry filename", stem, |c| {
+        c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | '@')
+    })
+}
+
+/// Menu timeout in `loader.conf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoaderTimeout {
+    Seconds(u32),
+    /// Always show the menu and wait for a choice.
+    MenuForce,
+    /// Boot the default immediately unless a key is held.
+    MenuHidden,
+}
+
+/// `loader/loader.conf`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoaderConf {
+    /// Default entry id or glob, e.g. `levitate.conf` or `levitate-*`.
+    pub default: Option<String>,
+    pub timeout: Option<LoaderTimeout>,
+    pub console_mode: Option<String>,
+    pub editor: Option<bool>,
+    pub auto_entries: Option<bool>,
+    pub auto_firmware: Option<bool>,
+    pub auto_poweroff: Option<bool>,
+    pub auto_reboot: Option<bool>,
+    pub beep: Option<bool>,
+    pub reboot_for_bitlocker: Option<bool>,
+    pub secure_boot_enroll: Option<String>,
+    /// Keys this model does not know, e.g. from a newer systemd-boot; kept
+    /// verbatim in file order.
+    pub extra: Vec<(String, String)>,
+}
+
+const CONSOLE_MODES: &[&str] = &["0", "1", "2", "auto", "max", "keep"];
+
+const SECURE_BOOT_ENROLL_MODES: &[&str] = &["off", "manual", "if-safe", "force"];
+
+impl LoaderConf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn default_entry(mut self, pattern: impl Into<String>) -> Self {
+        self.default = Some(pattern.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: LoaderTimeout) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn console_mode(mut self, mode: impl Into<String>) -> Self {
+        self.console_mode = Some(mode.into());
+        self
+    }
+
+    pub fn editor(mut self, enabled: bool) -> Self {
+        self.editor = Some(enabled);
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(default) = &self.default {
+            validate_token("default", default, |c| c.is_ascii_graphic())?;
+        }
+        if let Some(mode) = &self.console_mode {
+            if !CONSOLE_MODES.contains(&mode.as_str()) {
+                bail!(
+                    "loader.conf console-mode '{}' must be one of {}",
+                    mode,
+                    CONSOLE_MODES.join(", ")
+                );
+            }
+        }
+        if let Some(mode) = &self.secure_boot_enroll {
+            if !SECURE_BOOT_ENROLL_MODES.contains(&mode.as_str()) {
+                bail!(
+                    "loader.conf secure-boot-enroll '{}' must be one of {}",
+                    mode,
+                    SECURE_BOOT_ENROLL_MODES.join(", ")
+                );
+            }
+        }
+        for (key, value) in &self.extra {
+            validate_token("loader.conf key", key, |c| {
+                c.is_ascii_alphanumeric() || c == '-'
+            })?;
+            if value.trim().is_empty() || value.contains('\n') {
+                bail!("loader.conf {} has an invalid value '{}'", key, value);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn render(&self) -> Result<String> {
+        self.validate()?;
+        let mut out = String::new();
+        if let Some(default) = &self.default {
+            let _ = writeln!(out, "default {}", default);
+        }
+        match self.timeout {
+            Some(LoaderTimeout::Seconds(secs)) => {
+                let _ = writeln!(out, "timeout {}", secs);
+            }
+            Some(LoaderTimeout::MenuForce) => out.push_str("timeout menu-force\n"),
+            Some(LoaderTimeout::MenuHidden) => out.push_str("timeout menu-hidden\n"),
+            None => {}
+        }
+        if let Some(mode) = &self.console_mode {
+            let _ = writeln!(out, "console-mode {}", mode);
+        }
+        let flags = [
+            ("editor", self.editor),
+            ("auto-entries", self.auto_entries),
+            ("auto-firmware", self.auto_firmware),
+            ("auto-poweroff", self.auto_poweroff),
+            ("auto-reboot", self.auto_reboot),
+            ("beep", self.beep),
+            ("reboot-for-bitlocker", self.reboot_for_bitlocker),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                let _ = writeln!(out, "{} {}", key, if value { "yes" } else { "no" });
+            }
+        }
+        if let Some(mode) = &self.secure_boot_enroll {
+            let _ = writeln!(out, "secure-boot-enroll {}", mode);
+        }
+        for (key, value) in &self.extra {
+            let _ = writeln!(out, "{} {}", key, value);
+        }
+        Ok(out)
+    }
+
+    /// Parse `loader.conf`; unknown keys are kept verbatim with a warning.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut conf = Self::default();
+        for (key, value) in parse_lines(content)? {
+            match key {
+                "default" => conf.default = Some(value.to_string()),
+                "timeout" => {
+                    conf.timeout =
+                        Some(match value {
+                            "menu-force" => LoaderTimeout::MenuForce,
+                            "menu-hidden" => LoaderTimeout::MenuHidden,
+                            secs => LoaderTimeout::Seconds(secs.parse().with_context(|| {
+                                format!("invalid loader.conf timeout '{}'", secs)
+                            })?),
+                        })
+                }
+                "console-mode" => conf.console_mode = Some(value.to_string()),
+                "editor" => conf.editor = Some(parse_bool(key, value)?),
+                "auto-entries" => conf.auto_entries = Some(parse_bool(key, value)?),
+                "auto-firmware" => conf.auto_firmware = Some(parse_bool(key, value)?),
+                "auto-poweroff" => conf.auto_poweroff = Some(parse_bool(key, value)?),
+                "auto-reboot" => conf.auto_reboot = Some(parse_bool(key, value)?),
+                "beep" => conf.beep = Some(parse_bool(key, value)?),
+                "reboot-for-bitlocker" => conf.reboot_for_bitlocker = Some(parse_bool(key, value)?),
+                "secure-boot-enroll" => conf.secure_boot_enroll = Some(value.to_string()),
+                other => {
+                    eprintln!(
+                        "  [WARN] unknown loader.conf key '{}'; passing it through unchecked",
+                        other
+                    );
+                    conf.extra.push((other.to_string(), value.to_string()));
+                }
+            }
+        }
+        conf.validate()?;
+        Ok(conf)
+    }
+}
+
+fn parse_lines(content: &str) -> Result<Vec<(&str, &str)>> {
+    let mut out = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once(char::is_whitespace)
+            .with_context(|| format!("line {}: '{}' has no value", idx + 1, line))?;
+        out.push((key, value.trim()));
+    }
+    Ok(out)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "yes" | "true" | "1" | "on" => Ok(true),
+        "no" | "false" | "0" | "off" => Ok(false),
+        _ => bail!("invalid boolean '{}' for {}", value, key),
+    }
+}
+
+fn validate_token(what: &str, value: &str, allowed: impl Fn(char) -> bool) -> Result<()> {
+    if value.is_empty() {
+        bail!("{} is empty", what);
+    }
+    if let Some(bad) = value.chars().find(|c| !allowed(*c)) {
+        bail!("{} '{}' contains invalid character {:?}", what, value, bad);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_round_trip() {
+        let entry = LoaderEntry::new("LevitateOS")
+            .version("6.12.0-levitate")
+            .sort_key("levitate")
+            .linux("/vmlinuz")
+            .initrd("/initramfs.img")
+            .options("root=PARTUUID=abc rw");
+        let rendered = entry.render().unwrap();
+        assert_eq!(
+            rendered,
+            "title LevitateOS\nversion 6.12.0-levitate\nsort-key levitate\nlinux /vmlinuz\n\
+             initrd /initramfs.img\noptions root=PARTUUID=abc rw\n"
+        );
+        assert_eq!(LoaderEntry::parse(&rendered).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_entry_rejects_what_systemd_boot_skips() {
+        assert!(LoaderEntry::new("x").render().is_err());
+        assert!(LoaderEntry::new("x").linux("vmlinuz").render().is_err());
+        assert!(LoaderEntry::new("x")
+            .linux("/vmlinuz")
+            .efi("/EFI/Linux/a.efi")
+            .render()
+            .is_err());
+        assert!(LoaderEntry::new("x")
+            .efi("/EFI/Linux/a.efi")
+            .sort_key("Levitate")
+            .render()
+            .is_err());
+        assert!(LoaderEntry::parse("title x\nlinux /vmlinuz\nkernel /x\n").is_err());
+        assert!(validate_entry_filename("levitate").is_err());
+        assert!(validate_entry_filename("levitate.conf").is_ok());
+    }
+
+    #[test]
+    fn test_loader_conf() {
+        let conf = LoaderConf::new()
+            .default_entry("levitate.conf")
+            .timeout(LoaderTimeout::Seconds(3))
+            .editor(false);
+        let rendered = conf.render().unwrap();
+        assert_eq!(rendered, "default levitate.conf\ntimeout 3\neditor no\n");
+        assert_eq!(LoaderConf::parse(&rendered).unwrap(), conf);
+        assert!(LoaderConf::new().console_mode("huge").render().is_err());
+    }
+
+    #[test]
+    fn test_loader_conf_full_key_set() {
+        let content = "\
+timeout menu-hidden
+console-mode max
+editor no
+auto-entries yes
+auto-firmware no
+auto-poweroff no
+auto-reboot no
+beep no
+reboot-for-bitlocker yes
+secure-boot-enroll if-safe
+reboot-on-error auto
+";
+        let conf = LoaderConf::parse(content).unwrap();
+        assert_eq!(conf.console_mode.as_deref(), Some("max"));
+        assert_eq!(conf.reboot_for_bitlocker, Some(true));
+        assert_eq!(conf.secure_boot_enroll.as_deref(), Some("if-safe"));
+        assert_eq!(
+            conf.extra,
+            vec![("reboot-on-error".to_string(), "auto".to_string())]
+        );
+        assert_eq!(conf.render().unwrap(), content);
+        assert!(LoaderConf::parse("secure-boot-enroll always\n").is_err());
+    }
+}
@@ -4,10 +4,25 @@
 //! - [`cpio`] - Compressed cpio archives for initramfs
 //! - [`filesystem`] - Directory copying, initramfs structure creation
 //! - [`iso_utils`] - ISO creation utilities (xorriso, checksums, EFI boot images)
+//! - [`boot_check`] - Post-assembly kernel/initrd/cmdline consistency of boot entries
+//! - [`boot_menu`] - Troubleshooting loader entries (debug, nomodeset, serial, memtest)
+//! - [`esp`] - EFI System Partition layout shared by ISO and disk images
+//! - [`loader`] - Typed systemd-boot loader entries and loader.conf
+//! - [`multi_iso`] - Multi-distro ISO layout with a per-distro boot menu
 //! - [`rootfs`] - Compressed filesystem images (EROFS)
+//! - [`image_contents`] - Mount-free EROFS/squashfs content listing
+//! - [`image_mount`] - Rootless read-only EROFS and ISO mounts for inspection tests
 //! - [`overlayfs`] - Live overlay payload images (EROFS)
+//! - [`overlay_conflicts`] - Paths shadowed between rootfs and live overlay
+//! - [`payload_role`] - `.live-payload-role` markers and pre-packing role checks
 //! - [`initramfs`] - Initial RAM filesystem archives (trait definitions)
+//! - [`initramfs_check`] - Content contract checks for built initramfs images
+//! - [`netboot`] - Network-fetching initramfs `/init` for diskless boot tests
 //! - [`iso`] - Bootable ISO images (trait definitions)
+//! - [`installer`] - Guided installer payload image and live launcher
+//! - [`uki`] - Live, emergency and debug UKIs built from one kernel and initramfs
+//! - [`usb_image`] - dd-ready GPT USB images with a persistence partition
+//! - [`xattr`] - Extended attribute (SELinux label) preservation for staged trees
 //!
 //! # Usage
 //!
@@ -17,12 +32,27 @@
 //! The trait modules (`initramfs`, `iso`, `rootfs`) define interfaces that
 //! each distro implements with their specific configuration.
 
+pub mod boot_check;
+pub mod boot_menu;
 pub mod cpio;
 pub mod disk;
+pub mod esp;
 pub mod filesystem;
+pub mod image_contents;
+pub mod image_mount;
 pub mod initramfs;
+pub mod initramfs_check;
+pub mod installer;
 pub mod iso;
 pub mod iso_utils;
 pub mod live_overlay;
+pub mod loader;
+pub mod multi_iso;
+pub mod netboot;
+pub mod overlay_conflicts;
 pub mod overlayfs;
+pub mod payload_role;
 pub mod rootfs;
+pub mod uki;
+pub mod usb_image;
+pub mod xattr;
@@ -0,0 +1,304 @@
+//! Multi-distro ISO layout: one medium, one boot menu entry per distro.
+//!
+//! Each distro contributes its stage payload (kernel, initramfs, rootfs and
+//! optional overlay). Rootfs and overlay images go under `live/` on the ISO
+//! filesystem; kernels and initramfs images go on the ESP because
+//! systemd-boot can only load from there. Kernels with identical content
+//! are stored once and shared between entries, which is the common case when
+//! several distros are built from the same kernel recipe.
+//!
+//! Each distro's initramfs looks for its images under `live/` by the file
+//! names baked into it, exactly as on its own stage ISO, so payload file
+//! names must be unique across distros.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::artifact::esp::EspLayout;
+use crate::artifact::iso_utils::{create_esp_image, setup_iso_structure};
+use crate::artifact::loader::{LoaderConf, LoaderEntry, LoaderTimeout};
+use crate::cache::hash_file;
+use distro_spec::shared::ISO_LIVE_DIR;
+
+/// Seconds the menu waits before booting the first distro.
+const MENU_TIMEOUT_SECS: u32 = 10;
+
+/// One distro's boot payload.
+#[derive(Debug, Clone)]
+pub struct MultiIsoPayload {
+    /// Distro identifier; used for directory names and the entry filename.
+    pub distro_id: String,
+    /// Menu title.
+    pub title: String,
+    pub kernel: PathBuf,
+    pub initramfs: PathBuf,
+    pub rootfs: PathBuf,
+    pub overlay: Option<PathBuf>,
+    /// Kernel command line of the entry.
+    pub cmdline: String,
+}
+
+impl MultiIsoPayload {
+    pub fn new(
+        distro_id: impl Into<String>,
+        title: impl Into<String>,
+        kernel: &Path,
+        initramfs: &Path,
+        rootfs: &Path,
+    ) -> Self {
+        Self {
+            distro_id: distro_id.into(),
+            title: title.into(),
+            kernel: kernel.to_path_buf(),
+            initramfs: initramfs.to_path_buf(),
+            rootfs: rootfs.to_path_buf(),
+            overlay: None,
+            cmdline: String::new(),
+        }
+    }
+
+    pub fn overlay(mut self, overlay: &Path) -> Self {
+        self.overlay = Some(overlay.to_path_buf());
+        self
+    }
+
+    pub fn cmdline(mut self, cmdline: impl Into<String>) -> Self {
+        self.cmdline = cmdline.into();
+        self
+    }
+}
+
+/// Resolved layout for a set of payloads.
+#[derive(Debug, Clone)]
+pub struct MultiIsoPlan {
+    /// ESP contents: loader config, entries, kernels and initramfs images.
+    pub esp: EspLayout,
+    /// ISO filesystem copies as `(source, path relative to the ISO root)`.
+    pub iso_files: Vec<(PathBuf, String)>,
+    /// Number of distinct kernels stored on the ESP.
+    pub kernel_count: usize,
+}
+
+/// Plan the multi-distro layout. The first payload is the default entry.
+pub fn plan_multi_iso(
+    systemd_boot_efi: &Path,
+    payloads: &[MultiIsoPayload],
+) -> Result<MultiIsoPlan> {
+    if payloads.is_empty() {
+        bail!("multi-distro ISO needs at least one payload");
+    }
+    for (idx, payload) in payloads.iter().enumerate() {
+        validate_distro_id(&payload.distro_id)?;
+        if payloads[..idx]
+            .iter()
+            .any(|p| p.distro_id == payload.distro_id)
+        {
+            bail!(
+                "distro '{}' appears twice in the multi-distro ISO",
+                payload.distro_id
+            );
+        }
+    }
+
+    // Kernel content hash -> ESP path of the shared copy.
+    let mut kernels: BTreeMap<String, String> = BTreeMap::new();
+    // ISO file name under `live/` -> distro that ships it.
+    let mut live_names: BTreeMap<String, &str> = BTreeMap::new();
+    let mut esp = EspLayout::new().systemd_boot(systemd_boot_efi);
+    let mut iso_files = Vec::new();
+
+    for payload in payloads {
+        let hash = hash_file(&payload.kernel)
+            .with_context(|| format!("Failed to hash kernel {}", payload.kernel.display()))?;
+        let kernel_dest = match kernels.get(&hash) {
+            Some(dest) => dest.clone(),
+            None => {
+                let dest = format!("shared/vmlinuz-{}", &hash[..12]);
+                esp = esp.file(&payload.kernel, dest.clone());
+                kernels.insert(hash, dest.clone());
+                dest
+            }
+        };
+        let initramfs_dest = format!("{}/initramfs.img", payload.distro_id);
+        esp = esp.file(&payload.initramfs, initramfs_dest.clone());
+
+        for image in std::iter::once(&payload.rootfs).chain(&payload.overlay) {
+            let name = file_name(image)?;
+            if let Some(owner) = live_names.insert(name.clone(), &payload.distro_id) {
+                bail!(
+                    "'{}/{}' is shipped by both '{}' and '{}'\n\
+                     Remediation: give each distro's rootfs and overlay images distinct file names.",
+                    ISO_LIVE_DIR,
+                    name,
+                    owner,
+                    payload.distro_id
+                );
+            }
+            iso_files.push((image.clone(), format!("{}/{}", ISO_LIVE_DIR, name)));
+        }
+
+        let mut entry = LoaderEntry::new(&payload.title)
+            .sort_key(&payload.distro_id)
+            .linux(format!("/{}", kernel_dest))
+            .initrd(format!("/{}", initramfs_dest));
+        if !payload.cmdline.trim().is_empty() {
+            entry = entry.options(payload.cmdline.trim());
+        }
+        esp = esp.entry(entry_filename(&payload.distro_id), entry.render()?);
+    }
+
+    let conf = LoaderConf::new()
+        .default_entry(entry_filename(&payloads[0].distro_id))
+        .timeout(LoaderTimeout::Seconds(MENU_TIMEOUT_SECS));
+    esp = esp.loader_conf(conf.render()?);
+
+    Ok(MultiIsoPlan {
+        esp,
+        iso_files,
+        kernel_count: kernels.len(),
+    })
+}
+
+/// Lay out `iso_root` and write the EFI boot image for `payloads`.
+///
+/// The caller runs xorriso afterwards, as for single-distro ISOs.
+pub fn assemble_multi_iso(
+    iso_root: &Path,
+    efiboot_image: &Path,
+    systemd_boot_efi: &Path,
+    payloads: &[MultiIsoPayload],
+) -> Result<MultiIsoPlan> {
+    let plan = plan_multi_iso(systemd_boot_efi, payloads)?;
+    setup_iso_structure(iso_root)?;
+
+    for (src, rel) in &plan.iso_files {
+        let dest = iso_root.join(rel);
+        fs::copy(src, &dest)
+            .with_context(|| format!("Failed to copy {} to {}", src.display(), dest.display()))?;
+    }
+    create_esp_image(efiboot_image, &plan.esp)?;
+
+    println!(
+        "  Multi-distro ISO: {} entries, {} shared kernel(s)",
+        payloads.len(),
+        plan.kernel_count
+    );
+    Ok(plan)
+}
+
+fn entry_filename(distro_id: &str) -> String {
+    format!("{}.conf", distro_id)
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .with_context(|| format!("payload path has no file name: {}", path.display()))?
+        .to_string_lossy()
+        .to_string())
+}
+
+fn validate_distro_id(id: &str) -> Result<()> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        bail!(
+            "distro id '{}' must be non-empty lowercase letters, digits or '-'",
+            id
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn payload(dir: &Path, id: &str, kernel: &[u8]) -> MultiIsoPayload {
+        let kernel_path = dir.join(format!("{}-vmlinuz", id));
+        fs::write(&kernel_path, kernel).unwrap();
+        let initramfs = dir.join(format!("{}-initramfs.img", id));
+        fs::write(&initramfs, id).unwrap();
+        let rootfs = dir.join(format!("{}-filesystem.erofs", id));
+        fs::write(&rootfs, id).unwrap();
+        MultiIsoPayload::new(
+            id,
+            format!("{} live", id),
+            &kernel_path,
+            &initramfs,
+            &rootfs,
+        )
+        .cmdline("console=ttyS0")
+    }
+
+    #[test]
+    fn test_identical_kernels_are_shared() {
+        let temp = TempDir::new().unwrap();
+        let efi = temp.path().join("systemd-bootx64.efi");
+        fs::write(&efi, "efi").unwrap();
+        let payloads = [
+            payload(temp.path(), "levitate", b"kernel-a"),
+            payload(temp.path(), "acorn", b"kernel-a"),
+            payload(temp.path(), "iuppiter", b"kernel-b"),
+        ];
+
+        let plan = plan_multi_iso(&efi, &payloads).unwrap();
+        assert_eq!(plan.kernel_count, 2);
+
+        let esp_root = temp.path().join("esp");
+        plan.esp.render_to_dir(&esp_root).unwrap();
+        let levitate = fs::read_to_string(esp_root.join("loader/entries/levitate.conf")).unwrap();
+        let acorn = fs::read_to_string(esp_root.join("loader/entries/acorn.conf")).unwrap();
+        let linux_line = |s: &str| {
+            s.lines()
+                .find(|l| l.starts_with("linux"))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(linux_line(&levitate), linux_line(&acorn));
+        assert!(acorn.contains("options console=ttyS0\n"));
+        assert!(plan
+            .iso_files
+            .iter()
+            .any(|(_, rel)| rel == "live/acorn-filesystem.erofs"));
+        assert!(fs::read_to_string(esp_root.join("loader/loader.conf"))
+            .unwrap()
+            .contains("default levitate.conf"));
+    }
+
+    #[test]
+    fn test_rejects_duplicate_and_invalid_ids() {
+        let temp = TempDir::new().unwrap();
+        let efi = temp.path().join("systemd-bootx64.efi");
+        fs::write(&efi, "efi").unwrap();
+        let dup = [
+            payload(temp.path(), "acorn", b"k"),
+            payload(temp.path(), "acorn", b"k"),
+        ];
+        assert!(plan_multi_iso(&efi, &dup).is_err());
+        assert!(plan_multi_iso(&efi, &[payload(temp.path(), "Acorn", b"k")]).is_err());
+        assert!(plan_multi_iso(&efi, &[]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_colliding_image_names() {
+        let temp = TempDir::new().unwrap();
+        let efi = temp.path().join("systemd-bootx64.efi");
+        fs::write(&efi, "efi").unwrap();
+        let other = temp.path().join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("acorn-filesystem.erofs"), "iuppiter").unwrap();
+        let mut iuppiter = payload(temp.path(), "iuppiter", b"k");
+        iuppiter.rootfs = other.join("acorn-filesystem.erofs");
+
+        let err = plan_multi_iso(&efi, &[payload(temp.path(), "acorn", b"k"), iuppiter])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("both 'acorn' and 'iuppiter'"), "{err}");
+    }
+}
@@ -0,0 +1,388 @@
+//! Netboot initramfs: fetch the live rootfs over the network.
+//!
+//! The stage ISOs carry their EROFS payloads on the medium. For diskless
+//! testing the same payloads can be served over HTTP, NFS or iSCSI instead;
+//! a netboot initramfs brings the network up, fetches the image named on the
+//! kernel command line and boots it under a tmpfs overlay, exactly like the
+//! live ISO does:
+//!
+//! ```text
+//! rd.netboot=http://10.0.2.2:8000/filesystem.erofs
+//! rd.netboot=nfs:10.0.2.2:/srv/live/filesystem.erofs
+//! rd.netboot=iscsi:10.0.2.2:iqn.2026-01.org.levitate:live
+//! rd.netboot.overlay=http://10.0.2.2:8000/overlay.erofs   (optional)
+//! rd.netboot.iface=eth0                                   (default eth0)
+//! ```
+//!
+//! With QEMU user networking the host is reachable as `10.0.2.2`, so a
+//! plain `python3 -m http.server` next to the release artifacts is enough.
+//!
+//! A variant opts in with `netboot.toml` (`dhcp = true`, `http = true`, ...);
+//! its release hook then writes the generated `/init` into the initramfs
+//! tree with `$DISTRO_BUILDER_BIN artifact netboot-init <distro> <dir>`.
+//! When the initramfs carries the writable layer library (see
+//! [`crate::build::writable_layer`]), the overlay's upper layer is mounted
+//! through it instead of living unbounded on `/run`.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::artifact::initramfs_check::InitramfsContract;
+use crate::build::shell_check::check_script;
+
+/// Kernel parameter naming the rootfs image source.
+pub const NETBOOT_PARAM: &str = "rd.netboot";
+
+/// Variant-local netboot feature selection.
+pub const NETBOOT_CONFIG_FILENAME: &str = "netboot.toml";
+
+const UDHCPC_SCRIPT_PATH: &str = "etc/udhcpc.script";
+
+const BASE_MODULES: &[&str] = &[
+    "erofs",
+    "overlay",
+    "loop",
+    "af_packet",
+    "virtio_net",
+    "e1000",
+    "e1000e",
+];
+const NFS_MODULES: &[&str] = &["sunrpc", "nfs", "nfsv3"];
+const ISCSI_MODULES: &[&str] = &["scsi_transport_iscsi", "libiscsi", "iscsi_tcp", "sd_mod"];
+
+/// Network features compiled into the netboot `/init`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetbootFeatures {
+    /// Configure the interface with busybox `udhcpc`. Without it the kernel
+    /// `ip=` parameter must set up networking.
+    pub dhcp: bool,
+    pub http: bool,
+    pub nfs: bool,
+    pub iscsi: bool,
+}
+
+impl NetbootFeatures {
+    /// DHCP plus every fetch method.
+    pub fn all() -> Self {
+        Self {
+            dhcp: true,
+            http: true,
+            nfs: true,
+            iscsi: true,
+        }
+    }
+
+    /// DHCP and HTTP only: the smallest useful set for QEMU testing.
+    pub fn http() -> Self {
+        Self {
+            dhcp: true,
+            http: true,
+            ..Self::default()
+        }
+    }
+
+    /// Load `netboot.toml` from `variant_dir`; `None` when the variant does
+    /// not build a netboot initramfs.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(NETBOOT_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let features: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        features
+            .validate()
+            .with_context(|| format!("Invalid {}", path.display()))?;
+        Ok(Some(features))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(self.http || self.nfs || self.iscsi) {
+            bail!("netboot initramfs needs at least one of http, nfs or iscsi");
+        }
+        Ok(())
+    }
+
+    /// Busybox applets the generated scripts need on top of
+    /// [`STANDARD_BUSYBOX_COMMANDS`](super::initramfs::STANDARD_BUSYBOX_COMMANDS).
+    pub fn busybox_commands(&self) -> Vec<&'static str> {
+        let mut commands = vec!["ip"];
+        if self.dhcp {
+            commands.push("udhcpc");
+        }
+        if self.http {
+            commands.push("wget");
+        }
+        commands
+    }
+
+    /// Non-busybox binaries the initramfs must carry.
+    pub fn extra_binaries(&self) -> Vec<&'static str> {
+        if self.iscsi {
+            vec!["iscsistart"]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Kernel modules `/init` tries to load (built-ins are fine).
+    pub fn modules(&self) -> Vec<&'static str> {
+        let mut modules = BASE_MODULES.to_vec();
+        if self.nfs {
+            modules.extend_from_slice(NFS_MODULES);
+        }
+        if self.iscsi {
+            modules.extend_from_slice(ISCSI_MODULES);
+        }
+        modules
+    }
+
+    /// Require the protocol pieces in the initramfs content contract.
+    pub fn extend_initramfs_contract(&self, contract: &mut InitramfsContract) {
+        if self.dhcp {
+            contract.extra_paths.push(UDHCPC_SCRIPT_PATH.to_string());
+        }
+        if self.nfs {
+            contract.modules.push("nfs".to_string());
+        }
+        if self.iscsi {
+            contract.modules.push("iscsi_tcp".to_string());
+        }
+    }
+
+    /// Render the `/init` script.
+    pub fn init_script(&self) -> Result<String> {
+        self.validate()?;
+        let mut s = String::new();
+        s.push_str(INIT_PRELUDE);
+        s.push_str(&format!(
+            "for mod in {}; do\n    modprobe \"$mod\" 2>/dev/null || true\ndone\n\n",
+            self.modules().join(" ")
+        ));
+        s.push_str(INIT_NETWORK_UP);
+        if self.dhcp {
+            s.push_str(INIT_DHCP);
+        }
+        s.push_str("fetch() {\n    case \"$1\" in\n");
+        if self.http {
+            s.push_str(FETCH_HTTP);
+        }
+        if self.nfs {
+            s.push_str(FETCH_NFS);
+        }
+        if self.iscsi {
+            s.push_str(FETCH_ISCSI);
+        }
+        s.push_str(
+            "        *) echo \"[netboot] unsupported source: $1\" >&2; return 1 ;;\n    esac\n}\n\n",
+        );
+        s.push_str(INIT_MOUNT_AND_SWITCH);
+        Ok(s)
+    }
+
+    /// Write `/init` (and the DHCP helper) into an initramfs staging root.
+    pub fn write_init(&self, initramfs_root: &Path) -> Result<()> {
+        write_script(&initramfs_root.join("init"), &self.init_script()?)?;
+        if self.dhcp {
+            write_script(&initramfs_root.join(UDHCPC_SCRIPT_PATH), UDHCPC_SCRIPT)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_script(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    check_script(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to chmod {}", path.display()))
+}
+
+const INIT_PRELUDE: &str = r#"#!/bin/sh
+# Generated by distro-builder: netboot live init.
+export PATH=/bin:/sbin:/usr/bin:/usr/sbin
+
+mkdir -p /proc /sys /dev /run
+mount -t proc proc /proc
+mount -t sysfs sysfs /sys
+mount -t devtmpfs devtmpfs /dev 2>/dev/null || true
+mount -t tmpfs -o mode=0755 tmpfs /run
+
+fail() {
+    echo "[netboot] $*" >&2
+    echo "[netboot] dropping to emergency shell" >&2
+    exec /bin/sh
+}
+
+cmdline_value() {
+    for arg in $(cat /proc/cmdline); do
+        case "$arg" in
+            "$1"=*) echo "${arg#*=}"; return 0 ;;
+        esac
+    done
+    return 1
+}
+
+"#;
+
+const INIT_NETWORK_UP: &str = r#"source="$(cmdline_value rd.netboot)" || fail "no rd.netboot= on the kernel command line"
+overlay_source="$(cmdline_value rd.netboot.overlay)" || overlay_source=""
+iface="$(cmdline_value rd.netboot.iface)" || iface=eth0
+
+ip link set lo up
+ip link set "$iface" up || fail "network interface $iface not found"
+
+"#;
+
+const INIT_DHCP: &str = r#"udhcpc -i "$iface" -n -q -t 10 -s /etc/udhcpc.script || fail "DHCP on $iface failed"
+
+"#;
+
+const FETCH_HTTP: &str = r#"        http://*|https://*)
+            mkdir -p /run/netboot
+            wget -q -O "/run/netboot/$2.erofs" "$1" || return 1
+            echo "/run/netboot/$2.erofs"
+            ;;
+"#;
+
+const FETCH_NFS: &str = r#"        nfs:*)
+            spec="${1#nfs:}"
+            mkdir -p "/run/netboot-nfs/$2"
+            mount -t nfs -o ro,nolock "${spec%/*}" "/run/netboot-nfs/$2" || return 1
+            echo "/run/netboot-nfs/$2/${spec##*/}"
+            ;;
+"#;
+
+const FETCH_ISCSI: &str = r#"        iscsi:*)
+            spec="${1#iscsi:}"
+            portal="${spec%%:iqn.*}"
+            target="${spec#"$portal":}"
+            iscsistart -i "iqn.2026-01.org.distro-builder:netboot" -t "$target" -g 1 -a "$portal" || return 1
+            dev="$(cmdline_value rd.netboot.dev)" || dev=/dev/sda
+            i=0
+            while [ ! -b "$dev" ] && [ "$i" -lt 20 ]; do
+                sleep 1
+                i=$((i + 1))
+            done
+            [ -b "$dev" ] || return 1
+            echo "$dev"
+            ;;
+"#;
+
+const INIT_MOUNT_AND_SWITCH: &str = r#"mount_image() {
+    mkdir -p "$2"
+    if [ -b "$1" ]; then
+        mount -t erofs -o ro "$1" "$2"
+    else
+        mount -t erofs -o ro,loop "$1" "$2"
+    fi
+}
+
+image="$(fetch "$source" rootfs)" || fail "could not fetch rootfs from $source"
+mount_image "$image" /run/rootfs || fail "could not mount rootfs image $image"
+lower=/run/rootfs
+if [ -n "$overlay_source" ]; then
+    overlay_image="$(fetch "$overlay_source" overlay)" || fail "could not fetch overlay from $overlay_source"
+    mount_image "$overlay_image" /run/live-overlay || fail "could not mount overlay image $overlay_image"
+    lower="/run/live-overlay:$lower"
+fi
+
+if [ -r /lib/distro-builder/writable-layer.sh ]; then
+    . /lib/distro-builder/writable-layer.sh
+    mount_writable_layer /run/overlay || fail "could not set up the writable layer"
+else
+    mkdir -p /run/overlay/upper /run/overlay/work
+fi
+mkdir -p /newroot
+mount -t overlay overlay -o "lowerdir=$lower,upperdir=/run/overlay/upper,workdir=/run/overlay/work" /newroot \
+    || fail "could not assemble overlay root"
+
+umount /sys 2>/dev/null || true
+umount /proc 2>/dev/null || true
+echo "[netboot] switching to $source"
+exec switch_root /newroot /sbin/init
+"#;
+
+const UDHCPC_SCRIPT: &str = r#"#!/bin/sh
+# busybox udhcpc hook: apply the lease with iproute2-style busybox ip.
+case "$1" in
+    bound|renew)
+        ip addr flush dev "$interface"
+        ip addr add "$ip/${mask:-24}" dev "$interface"
+        if [ -n "$router" ]; then
+            ip route replace default via "${router%% *}" dev "$interface"
+        fi
+        : > /etc/resolv.conf
+        for ns in $dns; do
+            echo "nameserver $ns" >> /etc/resolv.conf
+        done
+        ;;
+esac
+exit 0
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::shell_check::lint;
+
+    #[test]
+    fn test_init_script_per_feature() {
+        let http = NetbootFeatures::http().init_script().unwrap();
+        assert!(http.contains("udhcpc -i"));
+        assert!(http.contains("http://*|https://*)"));
+        assert!(!http.contains("nfs:*)"));
+        assert!(!http.contains("iscsi_tcp"));
+
+        let all = NetbootFeatures::all().init_script().unwrap();
+        assert!(all.contains("nfs:*)") && all.contains("iscsistart"));
+        assert_eq!(lint(&all), Vec::new());
+        assert_eq!(lint(UDHCPC_SCRIPT), Vec::new());
+
+        assert!(NetbootFeatures::default().init_script().is_err());
+        assert_eq!(
+            NetbootFeatures::http().busybox_commands(),
+            vec!["ip", "udhcpc", "wget"]
+        );
+    }
+
+    #[test]
+    fn test_write_init_and_contract() {
+        let temp = tempfile::TempDir::new().unwrap();
+        NetbootFeatures::http().write_init(temp.path()).unwrap();
+        let mode = fs::metadata(temp.path().join("init"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert!(temp.path().join(UDHCPC_SCRIPT_PATH).is_file());
+
+        fs::write(
+            temp.path().join(NETBOOT_CONFIG_FILENAME),
+            "dhcp = true\nnfs = true\n",
+        )
+        .unwrap();
+        let loaded = NetbootFeatures::load_for_variant(temp.path())
+            .unwrap()
+            .unwrap();
+        assert!(loaded.nfs && !loaded.http);
+        fs::write(temp.path().join(NETBOOT_CONFIG_FILENAME), "dhcp = true\n").unwrap();
+        assert!(NetbootFeatures::load_for_variant(temp.path()).is_err());
+
+        let mut contract = InitramfsContract::default();
+        NetbootFeatures::all().extend_initramfs_contract(&mut contract);
+        assert!(contract.modules.contains(&"iscsi_tcp".to_string()));
+        assert!(contract
+            .extra_paths
+            .contains(&UDHCPC_SCRIPT_PATH.to_string()));
+    }
+}
@@ -0,0 +1,393 @@
+//! Paths provided by both the rootfs and the live overlay.
+//!
+//! Overlayfs resolves a path present in both layers in the overlay's
+//! favour, and a non-directory in the overlay hides a whole rootfs
+//! directory. Both are easy to do by accident, so the packing step lists
+//! every shadowed path before the trees become images:
+//!
+//! - **error**: a file and a directory meet (the overlay hides a rootfs
+//!   subtree, or a directory replaces a rootfs file or symlink)
+//! - **warning**: a security-sensitive file (accounts, sudo, PAM, sshd) is
+//!   replaced with different contents, or a file becomes a symlink
+//! - **info**: any other replacement, or an identical copy
+//!
+//! Paths the variant means to replace go into `overlay-conflicts.toml`:
+//!
+//! ```toml
+//! # Exact paths, or subtrees with a trailing '/'.
+//! allow = ["etc/sudoers.d/", "etc/pam.d/login"]
+//! # Fail on warnings as well as errors.
+//! strict = true
+//! ```
+//!
+//! Replacements the builder's own live overlay makes (`etc/shadow`,
+//! `etc/inittab`, `etc/fstab`, the payload role marker) are always
+//! allowed.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use crate::artifact::payload_role::PAYLOAD_ROLE_MARKER;
+
+/// Variant-local conflict allowlist file.
+pub const OVERLAY_CONFLICTS_FILENAME: &str = "overlay-conflicts.toml";
+
+/// Paths the generated live overlays replace on purpose.
+const BUILTIN_ALLOWED: &[&str] = &[
+    "etc/shadow",
+    "etc/inittab",
+    "etc/fstab",
+    PAYLOAD_ROLE_MARKER,
+];
+
+/// Replacing these with different contents changes who can log in.
+const SENSITIVE_PATHS: &[&str] = &[
+    "etc/passwd",
+    "etc/shadow",
+    "etc/group",
+    "etc/gshadow",
+    "etc/sudoers",
+    "etc/sudoers.d/",
+    "etc/doas.conf",
+    "etc/pam.d/",
+    "etc/security/",
+    "etc/ssh/",
+];
+
+/// How surprising a shadowed path is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConflictSeverity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for ConflictSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// A path both layers provide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayConflict {
+    /// Relative to both tree roots.
+    pub path: PathBuf,
+    pub severity: ConflictSeverity,
+    pub reason: &'static str,
+    /// Matched by the allowlist.
+    pub allowed: bool,
+}
+
+/// Allowlist and strictness of one variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConflictPolicy {
+    /// Exact relative paths, or subtrees ending in `/`.
+    pub allow: Vec<String>,
+    /// Fail on warnings too.
+    pub strict: bool,
+}
+
+impl ConflictPolicy {
+    /// Load `overlay-conflicts.toml` from `variant_dir`; the default policy
+    /// when the variant has none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(OVERLAY_CONFLICTS_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let policy: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        for entry in &policy.allow {
+            if entry.is_empty() || entry.starts_with('/') || entry.split('/').any(|c| c == "..") {
+                bail!(
+                    "invalid allow entry '{}' in {}: use a path relative to the rootfs",
+                    entry,
+                    path.display()
+                );
+            }
+        }
+        Ok(policy)
+    }
+
+    fn allows(&self, path: &Path) -> bool {
+        BUILTIN_ALLOWED
+            .iter()
+            .copied()
+            .chain(self.allow.iter().map(String::as_str))
+            .any(|entry| matches_entry(entry, path))
+    }
+
+    /// Report the conflicts between `rootfs` and `overlay` and fail on any
+    /// unallowed error (or warning, when strict).
+    pub fn check(&self, rootfs: &Path, overlay: &Path) -> Result<Vec<OverlayConflict>> {
+        let conflicts = find_overlay_conflicts(rootfs, overlay, self)?;
+        let threshold = if self.strict {
+            ConflictSeverity::Warning
+        } else {
+            ConflictSeverity::Error
+        };
+        let mut failing = Vec::new();
+        let mut info = 0;
+        for conflict in &conflicts {
+            if conflict.allowed || conflict.severity == ConflictSeverity::Info {
+                info += 1;
+            } else if conflict.severity >= threshold {
+                failing.push(format!(
+                    "  [{}] /{}: {}",
+                    conflict.severity,
+                    conflict.path.display(),
+                    conflict.reason
+                ));
+            } else {
+                eprintln!(
+                    "  [WARN] overlay shadows /{}: {}",
+                    conflict.path.display(),
+                    conflict.reason
+                );
+            }
+        }
+        if info > 0 {
+            println!("  Overlay replaces {} rootfs path(s) as expected", info);
+        }
+        if !failing.is_empty() {
+            bail!(
+                "live overlay conflicts with the rootfs:\n{}\n\
+Remediation: remove the path from one layer, or list it in {} when the overlay is meant to replace it",
+                failing.join("\n"),
+                OVERLAY_CONFLICTS_FILENAME
+            );
+        }
+        Ok(conflicts)
+    }
+}
+
+fn matches_entry(entry: &str, path: &Path) -> bool {
+    match entry.strip_suffix('/') {
+        Some(dir) => path.starts_with(dir) && path != Path::new(dir),
+        None => path == Path::new(entry),
+    }
+}
+
+/// Every path present in both `rootfs` and `overlay`, in path order.
+/// Directories in both layers merge and are not reported themselves.
+pub fn find_overlay_conflicts(
+    rootfs: &Path,
+    overlay: &Path,
+    policy: &ConflictPolicy,
+) -> Result<Vec<OverlayConflict>> {
+    let mut conflicts = Vec::new();
+    walk(rootfs, overlay, Path::new(""), policy, &mut conflicts)?;
+    Ok(conflicts)
+}
+
+fn walk(
+    rootfs: &Path,
+    overlay: &Path,
+    rel: &Path,
+    policy: &ConflictPolicy,
+    conflicts: &mut Vec<OverlayConflict>,
+) -> Result<()> {
+    let dir = overlay.join(rel);
+    let mut entries: Vec<_> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .collect::<std::io::Result<_>>()
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let rel = rel.join(entry.file_name());
+        let Ok(lower) = fs::symlink_metadata(rootfs.join(&rel)) else {
+            continue;
+        };
+        let upper = entry
+            .metadata()
+            .with_context(|| format!("Failed to stat {}", entry.path().display()))?;
+        if upper.is_dir() && lower.is_dir() {
+            walk(rootfs, overlay, &rel, policy, conflicts)?;
+            continue;
+        }
+        let (severity, reason) = classify(&rootfs.join(&rel), &entry.path(), &lower, &upper, &rel)?;
+        conflicts.push(OverlayConflict {
+            allowed: policy.allows(&rel),
+            path: rel,
+            severity,
+            reason,
+        });
+    }
+    Ok(())
+}
+
+fn classify(
+    lower_path: &Path,
+    upper_path: &Path,
+    lower: &fs::Metadata,
+    upper: &fs::Metadata,
+    rel: &Path,
+) -> Result<(ConflictSeverity, &'static str)> {
+    let (lower_type, upper_type) = (lower.file_type(), upper.file_type());
+    if upper_type.is_char_device() && upper.rdev() == 0 {
+        return Ok((ConflictSeverity::Info, "removed by an overlay whiteout"));
+    }
+    if upper_type.is_dir() || lower_type.is_dir() {
+        return Ok(if lower_type.is_dir() {
+            (
+                ConflictSeverity::Error,
+                "a non-directory hides the rootfs directory",
+            )
+        } else {
+            (
+                ConflictSeverity::Error,
+                "a directory replaces a rootfs non-directory",
+            )
+        });
+    }
+    let identical = if upper_type.is_symlink() && lower_type.is_symlink() {
+        fs::read_link(upper_path)? == fs::read_link(lower_path)?
+    } else if upper_type.is_file() && lower_type.is_file() {
+        same_contents(lower_path, upper_path)?
+    } else if upper_type.is_symlink() != lower_type.is_symlink() {
+        return Ok((ConflictSeverity::Warning, "a symlink and a file meet"));
+    } else {
+        false
+    };
+    if identical {
+        return Ok((ConflictSeverity::Info, "identical copy"));
+    }
+    let sensitive = SENSITIVE_PATHS
+        .iter()
+        .any(|entry| matches_entry(entry, rel));
+    Ok(if sensitive {
+        (
+            ConflictSeverity::Warning,
+            "security-sensitive file replaced",
+        )
+    } else {
+        (ConflictSeverity::Info, "replaced")
+    })
+}
+
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    };
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let (mut a, mut b) = (open(a)?, open(b)?);
+    let (mut buf_a, mut buf_b) = ([0u8; 8192], [0u8; 8192]);
+    loop {
+        let read = a.read(&mut buf_a)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..read])?;
+        if buf_a[..read] != buf_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    fn trees() -> (TempDir, PathBuf, PathBuf) {
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path().join("rootfs");
+        let overlay = temp.path().join("overlay");
+        for tree in [&rootfs, &overlay] {
+            fs::create_dir_all(tree.join("etc/sudoers.d")).unwrap();
+            fs::write(tree.join("etc/hostname"), "live\n").unwrap();
+        }
+        fs::write(rootfs.join("etc/issue"), "installed\n").unwrap();
+        fs::write(overlay.join("etc/issue"), "live\n").unwrap();
+        fs::write(rootfs.join("etc/sudoers.d/wheel"), "%wheel ALL=(ALL) ALL\n").unwrap();
+        fs::write(
+            overlay.join("etc/sudoers.d/wheel"),
+            "ALL ALL=(ALL) NOPASSWD: ALL\n",
+        )
+        .unwrap();
+        fs::write(rootfs.join("etc/shadow"), "root:!:1:::::\n").unwrap();
+        fs::write(overlay.join("etc/shadow"), "root::0:::::\n").unwrap();
+        fs::create_dir_all(rootfs.join("usr/bin")).unwrap();
+        symlink("usr/bin", rootfs.join("bin")).unwrap();
+        fs::create_dir_all(overlay.join("bin")).unwrap();
+        fs::write(overlay.join("only-in-overlay"), "").unwrap();
+        (temp, rootfs, overlay)
+    }
+
+    #[test]
+    fn test_conflict_classification() {
+        let (_temp, rootfs, overlay) = trees();
+        let conflicts =
+            find_overlay_conflicts(&rootfs, &overlay, &ConflictPolicy::default()).unwrap();
+        let found: Vec<(String, ConflictSeverity, bool)> = conflicts
+            .iter()
+            .map(|c| (c.path.display().to_string(), c.severity, c.allowed))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("bin".to_string(), ConflictSeverity::Error, false),
+                ("etc/hostname".to_string(), ConflictSeverity::Info, false),
+                ("etc/issue".to_string(), ConflictSeverity::Info, false),
+                ("etc/shadow".to_string(), ConflictSeverity::Warning, true),
+                (
+                    "etc/sudoers.d/wheel".to_string(),
+                    ConflictSeverity::Warning,
+                    false
+                ),
+            ]
+        );
+        assert_eq!(conflicts[1].reason, "identical copy");
+    }
+
+    #[test]
+    fn test_policy_check() {
+        let (temp, rootfs, overlay) = trees();
+        let err = ConflictPolicy::default()
+            .check(&rootfs, &overlay)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("[error] /bin"), "{err}");
+        assert!(!err.contains("sudoers"), "{err}");
+
+        fs::write(
+            temp.path().join(OVERLAY_CONFLICTS_FILENAME),
+            "allow = [\"bin\"]\nstrict = true\n",
+        )
+        .unwrap();
+        let strict = ConflictPolicy::load_for_variant(temp.path()).unwrap();
+        let err = strict.check(&rootfs, &overlay).unwrap_err().to_string();
+        assert!(err.contains("[warning] /etc/sudoers.d/wheel"), "{err}");
+
+        let relaxed = ConflictPolicy {
+            allow: vec!["bin".to_string(), "etc/sudoers.d/".to_string()],
+            strict: true,
+        };
+        assert_eq!(relaxed.check(&rootfs, &overlay).unwrap().len(), 5);
+
+        fs::write(
+            temp.path().join(OVERLAY_CONFLICTS_FILENAME),
+            "allow = [\"/etc\"]\n",
+        )
+        .unwrap();
+        assert!(ConflictPolicy::load_for_variant(temp.path()).is_err());
+    }
+}
@@ -2,11 +2,30 @@
 //!
 //! Live overlay payloads are first-class filesystem artifacts represented as
 //! read-only EROFS images. This module provides the canonical builder helpers.
+//!
+//! An overlay layer can also delete from the lower rootfs. [`OverlayDeletions`]
+//! records whiteouts (hide one path) and opaque directories (hide everything
+//! the lower layer has below a directory). Creating real whiteouts needs
+//! `mknod` and `trusted.*` xattrs, so the layer is instead staged as a tar
+//! stream with OCI `.wh.` markers and converted by `mkfs.erofs --tar --aufs`,
+//! which writes the overlayfs character-device whiteouts and opaque xattrs
+//! into the image without privileges.
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
 use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::artifact::rootfs::{create_erofs, erofs_input_key_path};
+use crate::process::Cmd;
+use crate::reproducible;
 
-use crate::artifact::rootfs::create_erofs;
+/// OCI whiteout file prefix.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// OCI opaque directory marker.
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
 
 /// Build a live overlay payload image as EROFS with explicit settings.
 pub fn create_overlayfs_erofs(
@@ -27,15 +46,416 @@ pub fn create_overlayfs_erofs(
 
 /// Build a live overlay payload image using shared overlayfs defaults.
 pub fn build_overlayfs_default(source_dir: &Path, output: &Path) -> Result<()> {
+    build_overlayfs_default_with_deletions(source_dir, output, &OverlayDeletions::default())
+}
+
+/// Like [`build_overlayfs_default`], also hiding `deletions` from the lower
+/// layer.
+pub fn build_overlayfs_default_with_deletions(
+    source_dir: &Path,
+    output: &Path,
+    deletions: &OverlayDeletions,
+) -> Result<()> {
     use distro_spec::shared::overlayfs::{
         OVERLAYFS_CHUNK_SIZE, OVERLAYFS_COMPRESSION, OVERLAYFS_COMPRESSION_LEVEL,
     };
 
-    create_overlayfs_erofs(
+    create_overlayfs_erofs_with_deletions(
         source_dir,
         output,
+        deletions,
         OVERLAYFS_COMPRESSION,
         OVERLAYFS_COMPRESSION_LEVEL,
         OVERLAYFS_CHUNK_SIZE,
     )
 }
+
+/// Build an overlay payload image that adds `source_dir` and applies
+/// `deletions` to the lower layer.
+///
+/// Without deletions this is [`create_overlayfs_erofs`]. With deletions it
+/// needs erofs-utils 1.7+ for `--tar` and `--aufs`.
+pub fn create_overlayfs_erofs_with_deletions(
+    source_dir: &Path,
+    output: &Path,
+    deletions: &OverlayDeletions,
+    compression: &str,
+    compression_level: u8,
+    chunk_size: u32,
+) -> Result<()> {
+    if deletions.is_empty() {
+        return create_overlayfs_erofs(
+            source_dir,
+            output,
+            compression,
+            compression_level,
+            chunk_size,
+        );
+    }
+
+    let parent = output
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(parent)
+        .with_context(|| format!("Failed to create output directory: {}", parent.display()))?;
+    let layer_tar = parent.join(format!(
+        ".{}.layer.tar",
+        output
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    ));
+    write_layer_tar(source_dir, deletions, &layer_tar)?;
+    // Whiteouts are not part of the tree hash, so this image is never reused.
+    let key_file = erofs_input_key_path(output);
+    if key_file.exists() {
+        fs::remove_file(&key_file)
+            .with_context(|| format!("Failed to remove {}", key_file.display()))?;
+    }
+
+    println!(
+        "Creating overlay EROFS with {} whiteout(s) and {} opaque dir(s)...",
+        deletions.whiteouts.len(),
+        deletions.opaque_dirs.len()
+    );
+    let result = Cmd::new("mkfs.erofs")
+        .arg("--tar=f")
+        .arg("--aufs")
+        .args(["-z", &format!("{},{}", compression, compression_level)])
+        .args(["-C", &chunk_size.to_string()])
+        .arg("--all-root")
+        .arg(format!("-T{}", reproducible::erofs_timestamp()?))
+        .arg_path(output)
+        .arg_path(&layer_tar)
+        .error_msg(
+            "mkfs.erofs failed building overlay layer with deletions.\n\
+             NOTE: erofs-utils 1.7+ required for --tar and --aufs.",
+        )
+        .run();
+    let _ = fs::remove_file(&layer_tar);
+    result.map(|_| ())
+}
+
+/// Lower-layer paths an overlay layer hides.
+///
+/// Paths are relative to the image root (`usr/lib/foo`; a leading `/` is
+/// accepted and stripped).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverlayDeletions {
+    whiteouts: BTreeSet<String>,
+    opaque_dirs: BTreeSet<String>,
+}
+
+impl OverlayDeletions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hide `path` (and, for a directory, everything below it).
+    pub fn whiteout(mut self, path: &str) -> Result<Self> {
+        self.whiteouts.insert(normalize_layer_path(path)?);
+        Ok(self)
+    }
+
+    /// Hide the lower layer's contents of directory `path`; the directory
+    /// itself and whatever this layer puts in it stay visible.
+    pub fn opaque(mut self, path: &str) -> Result<Self> {
+        self.opaque_dirs.insert(normalize_layer_path(path)?);
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.whiteouts.is_empty() && self.opaque_dirs.is_empty()
+    }
+
+    pub fn whiteouts(&self) -> impl Iterator<Item = &str> {
+        self.whiteouts.iter().map(String::as_str)
+    }
+
+    pub fn opaque_dirs(&self) -> impl Iterator<Item = &str> {
+        self.opaque_dirs.iter().map(String::as_str)
+    }
+
+    /// Whether a lower-layer `path` is invisible in the merged view.
+    pub fn hides(&self, path: &str) -> bool {
+        let Ok(path) = normalize_layer_path(path) else {
+            return false;
+        };
+        self.whiteouts
+            .iter()
+            .any(|w| path == *w || is_strictly_below(&path, w))
+            || self
+                .opaque_dirs
+                .iter()
+                .any(|dir| is_strictly_below(&path, dir))
+    }
+}
+
+fn is_strictly_below(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn normalize_layer_path(path: &str) -> Result<String> {
+    let trimmed = path.trim_matches('/');
+    if trimmed.is_empty() {
+        bail!("overlay deletion path '{}' names the image root", path);
+    }
+    if trimmed
+        .split('/')
+        .any(|part| matches!(part, "" | "." | ".."))
+    {
+        bail!(
+            "invalid overlay deletion path '{}': use a plain relative path without '.' or '..'",
+            path
+        );
+    }
+    if trimmed
+        .split('/')
+        .any(|part| part.starts_with(WHITEOUT_PREFIX))
+    {
+        bail!(
+            "invalid overlay deletion path '{}': '{}' names are reserved for markers",
+            path,
+            WHITEOUT_PREFIX
+        );
+    }
+    Ok(trimmed.to_string())
+}
+
+/// Write `source_dir` plus OCI deletion markers as a tar stream.
+fn write_layer_tar(source_dir: &Path, deletions: &OverlayDeletions, out: &Path) -> Result<()> {
+    if !source_dir.is_dir() {
+        bail!("Source directory does not exist: {}", source_dir.display());
+    }
+    for path in deletions.whiteouts() {
+        if fs::symlink_metadata(source_dir.join(path)).is_ok() {
+            bail!(
+                "overlay layer both adds and whites out '{}'; remove it from {} or drop the whiteout",
+                path,
+                source_dir.display()
+            );
+        }
+    }
+
+    let file = File::create(out).with_context(|| format!("Failed to create {}", out.display()))?;
+    let mut builder = tar::Builder::new(file);
+    builder.follow_symlinks(false);
+
+    let walker = WalkDir::new(source_dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .follow_links(false);
+    for entry in walker {
+        let entry = entry.with_context(|| format!("walking {}", source_dir.display()))?;
+        let rel = entry
+            .path()
+            .strip_prefix(source_dir)
+            .unwrap_or(entry.path());
+        append_entry(&mut builder, entry.path(), rel)
+            .with_context(|| format!("adding {} to overlay layer", entry.path().display()))?;
+    }
+
+    let mut markers: Vec<String> = deletions
+        .whiteouts()
+        .map(|path| match path.rsplit_once('/') {
+            Some((dir, name)) => format!("{}/{}{}", dir, WHITEOUT_PREFIX, name),
+            None => format!("{}{}", WHITEOUT_PREFIX, path),
+        })
+        .chain(
+            deletions
+                .opaque_dirs()
+                .map(|dir| format!("{}/{}", dir, OPAQUE_MARKER)),
+        )
+        .collect();
+    markers.sort();
+    // Directories holding markers must exist in the stream.
+    let missing_dirs: BTreeSet<&str> = markers
+        .iter()
+        .flat_map(|marker| {
+            marker
+                .match_indices('/')
+                .map(move |(idx, _)| &marker[..idx])
+        })
+        .filter(|dir| !source_dir.join(dir).is_dir())
+        .collect();
+    for dir in missing_dirs {
+        append_empty(&mut builder, dir, tar::EntryType::Directory, 0o755)?;
+    }
+    for marker in &markers {
+        append_empty(&mut builder, marker, tar::EntryType::Regular, 0o644)?;
+    }
+
+    builder
+        .into_inner()
+        .context("Failed to finalize overlay layer tar")?;
+    Ok(())
+}
+
+/// Append `path` as `name` with its full mode (setuid, 0600, 0440, ...)
+/// and file type, normalising only the owner and mtime.
+fn append_entry(builder: &mut tar::Builder<File>, path: &Path, name: &Path) -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let meta = fs::symlink_metadata(path)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(&meta, tar::HeaderMode::Complete);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_username("root")?;
+    header.set_groupname("root")?;
+
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        header.set_size(0);
+        let target = fs::read_link(path)?;
+        builder.append_link(&mut header, name, target)?;
+    } else if file_type.is_file() {
+        builder.append_data(&mut header, name, File::open(path)?)?;
+    } else {
+        if file_type.is_char_device() || file_type.is_block_device() {
+            let (major, minor) = dev_major_minor(meta.rdev());
+            header.set_device_major(major)?;
+            header.set_device_minor(minor)?;
+        }
+        header.set_size(0);
+        builder.append_data(&mut header, name, std::io::empty())?;
+    }
+    Ok(())
+}
+
+/// Split a Linux `dev_t` into its major and minor numbers.
+fn dev_major_minor(rdev: u64) -> (u32, u32) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
+
+fn append_empty(
+    builder: &mut tar::Builder<File>,
+    path: &str,
+    kind: tar::EntryType,
+    mode: u32,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(kind);
+    header.set_size(0);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mode(mode);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, path, std::io::empty())
+        .with_context(|| format!("adding '{}' to overlay layer", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn deletions() -> OverlayDeletions {
+        OverlayDeletions::new()
+            .whiteout("/usr/bin/ssh-agent")
+            .unwrap()
+            .whiteout("usr/share/doc")
+            .unwrap()
+            .opaque("etc/skel")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_deletion_semantics() {
+        let d = deletions();
+        assert!(d.hides("usr/bin/ssh-agent"));
+        assert!(d.hides("/usr/share/doc"));
+        assert!(d.hides("usr/share/doc/bash/README"));
+        assert!(!d.hides("usr/share/docs"));
+        assert!(!d.hides("usr/bin/ssh"));
+        // Opaque: the directory stays, its lower contents go.
+        assert!(!d.hides("etc/skel"));
+        assert!(d.hides("etc/skel/.bashrc"));
+        assert!(!d.hides("etc/skeleton"));
+
+        assert!(OverlayDeletions::new().whiteout("/").is_err());
+        assert!(OverlayDeletions::new().whiteout("usr/../etc").is_err());
+        assert!(OverlayDeletions::new().opaque("etc/.wh.x").is_err());
+    }
+
+    #[test]
+    fn test_layer_tar_markers() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("layer");
+        fs::create_dir_all(source.join("etc/skel")).unwrap();
+        fs::write(source.join("etc/skel/.profile"), "# live\n").unwrap();
+        let tar_path = temp.path().join("layer.tar");
+        write_layer_tar(&source, &deletions(), &tar_path).unwrap();
+
+        let mut names: Vec<String> = tar::Archive::new(File::open(&tar_path).unwrap())
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        for expected in [
+            "etc/skel/.profile",
+            "etc/skel/.wh..wh..opq",
+            "usr/bin",
+            "usr/bin/.wh.ssh-agent",
+            "usr/share/.wh.doc",
+        ] {
+            assert!(
+                names.iter().any(|n| n.trim_end_matches('/') == expected),
+                "missing {expected} in {names:?}"
+            );
+        }
+
+        fs::create_dir_all(source.join("usr/share/doc")).unwrap();
+        assert!(write_layer_tar(&source, &deletions(), &tar_path).is_err());
+    }
+
+    #[test]
+    fn test_layer_tar_keeps_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("layer");
+        fs::create_dir_all(source.join("usr/bin")).unwrap();
+        fs::create_dir_all(source.join("etc")).unwrap();
+        let su = source.join("usr/bin/su");
+        fs::write(&su, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&su, fs::Permissions::from_mode(0o4755)).unwrap();
+        let shadow = source.join("etc/shadow");
+        fs::write(&shadow, "root:*:1::::::\n").unwrap();
+        fs::set_permissions(&shadow, fs::Permissions::from_mode(0o600)).unwrap();
+        let tar_path = temp.path().join("layer.tar");
+        write_layer_tar(&source, &OverlayDeletions::new(), &tar_path).unwrap();
+
+        let unpacked = temp.path().join("unpacked");
+        let mut archive = tar::Archive::new(File::open(&tar_path).unwrap());
+        archive.set_preserve_permissions(true);
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!(header.uid().unwrap(), 0);
+            assert_eq!(header.gid().unwrap(), 0);
+            assert_eq!(header.mtime().unwrap(), 0);
+        }
+        let mut archive = tar::Archive::new(File::open(&tar_path).unwrap());
+        archive.set_preserve_permissions(true);
+        archive.unpack(&unpacked).unwrap();
+
+        let mode = |rel: &str| {
+            fs::metadata(unpacked.join(rel))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        assert_eq!(mode("usr/bin/su"), 0o4755);
+        assert_eq!(mode("etc/shadow"), 0o600);
+    }
+}
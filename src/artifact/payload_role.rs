@@ -0,0 +1,162 @@
+//! `.live-payload-role` markers of staged payload trees.
+//!
+//! Each tree packed into live media carries a one-line marker naming what
+//! it is, so the live init and the packing step can tell a rootfs from the
+//! overlay laid over it. The rootfs marker comes from the variant contract
+//! (a `write_text` producer), the overlay and installer markers from the
+//! builders here; all of them go through [`PayloadRole`].
+//!
+//! [`verify_live_payloads`] runs before the rootfs and overlay trees are
+//! packed, so swapped or cross-contaminated directories fail the build
+//! instead of producing media that mounts the wrong tree.
+
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Marker file name, relative to the payload tree root.
+pub const PAYLOAD_ROLE_MARKER: &str = ".live-payload-role";
+
+/// What a payload tree is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadRole {
+    /// The read-only root filesystem.
+    Rootfs,
+    /// The live overlay laid over the rootfs.
+    Overlay,
+    /// The guided installer image.
+    Installer,
+}
+
+impl PayloadRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Rootfs => "rootfs",
+            Self::Overlay => "overlay",
+            Self::Installer => "installer",
+        }
+    }
+
+    /// Marker file contents.
+    pub fn marker(self) -> String {
+        format!("{}\n", self.as_str())
+    }
+
+    /// Parse marker file contents; surrounding whitespace is ignored.
+    pub fn parse(content: &str) -> Result<Self> {
+        match content.trim() {
+            "rootfs" => Ok(Self::Rootfs),
+            "overlay" => Ok(Self::Overlay),
+            "installer" => Ok(Self::Installer),
+            other => bail!(
+                "unknown payload role '{}' (expected rootfs, overlay or installer)",
+                other
+            ),
+        }
+    }
+
+    /// Write the marker into `tree`.
+    pub fn write(self, tree: &Path) -> Result<()> {
+        let path = tree.join(PAYLOAD_ROLE_MARKER);
+        fs::write(&path, self.marker())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The role marked in `tree`; `None` when it has no marker.
+    pub fn read(tree: &Path) -> Result<Option<Self>> {
+        let path = tree.join(PAYLOAD_ROLE_MARKER);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content)
+            .map(Some)
+            .with_context(|| format!("invalid {}", path.display()))
+    }
+
+    /// Fail unless `tree` is marked with this role.
+    pub fn expect(self, tree: &Path) -> Result<()> {
+        match Self::read(tree)? {
+            Some(role) if role == self => Ok(()),
+            found => bail!(
+                "'{}' is not a {} payload (marked {})\n\
+                 Remediation: rebuild the payload; the directories may have been swapped or mixed.",
+                tree.display(),
+                self,
+                found.map_or("nothing", Self::as_str)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for PayloadRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Check the markers of a rootfs tree and its live overlay before packing.
+///
+/// A marker, when present, must name the tree's role. A base rootfs has no
+/// marker and an empty overlay, so only a non-empty overlay must be marked.
+pub fn verify_live_payloads(rootfs: &Path, overlay: &Path) -> Result<()> {
+    if PayloadRole::read(rootfs)?.is_some() {
+        PayloadRole::Rootfs.expect(rootfs)?;
+    }
+    let overlay_empty = fs::read_dir(overlay)
+        .with_context(|| format!("Failed to read {}", overlay.display()))?
+        .next()
+        .is_none();
+    if !overlay_empty {
+        PayloadRole::Overlay.expect(overlay)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_marker_round_trip() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(PayloadRole::read(temp.path()).unwrap(), None);
+        for role in [
+            PayloadRole::Rootfs,
+            PayloadRole::Overlay,
+            PayloadRole::Installer,
+        ] {
+            role.write(temp.path()).unwrap();
+            assert_eq!(PayloadRole::read(temp.path()).unwrap(), Some(role));
+            role.expect(temp.path()).unwrap();
+        }
+        assert_eq!(
+            PayloadRole::parse(" rootfs \n").unwrap(),
+            PayloadRole::Rootfs
+        );
+        fs::write(temp.path().join(PAYLOAD_ROLE_MARKER), "live\n").unwrap();
+        assert!(PayloadRole::read(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_verify_live_payloads() {
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path().join("rootfs");
+        let overlay = temp.path().join("overlay");
+        fs::create_dir_all(&rootfs).unwrap();
+        fs::create_dir_all(&overlay).unwrap();
+        verify_live_payloads(&rootfs, &overlay).unwrap();
+
+        PayloadRole::Rootfs.write(&rootfs).unwrap();
+        fs::write(overlay.join("issue"), "live\n").unwrap();
+        let err = verify_live_payloads(&rootfs, &overlay).unwrap_err();
+        assert!(err.to_string().contains("marked nothing"), "{err}");
+
+        PayloadRole::Overlay.write(&overlay).unwrap();
+        verify_live_payloads(&rootfs, &overlay).unwrap();
+        assert!(verify_live_payloads(&overlay, &rootfs).is_err());
+    }
+}
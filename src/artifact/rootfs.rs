@@ -30,6 +30,19 @@
 //! build_erofs_default(Path::new("staging/"), Path::new("output/filesystem.erofs"))?;
 //! ```
 //!
+//! # Incremental rebuilds
+//!
+//! Packing is skipped when the staging tree is unchanged. The tree hash
+//! (see [`EROFS_TREE_HASH_ENV`]), the mkfs settings and the `mkfs.erofs`
+//! version form an input key, stored next to the image as
+//! `.<image>-inputs.hash`. When the key matches the existing image, it is
+//! kept as is. [`create_erofs_with_store`] also keeps images in the
+//! artifact store by key, so a new run directory can reuse an image built
+//! by an earlier run.
+//!
+//! With copy verification on (see [`crate::integrity`]) the image also gets
+//! a manifest of its source files, checked when a later stage extracts it.
+//!
 //! # Note on Squashfs
 //!
 //! Squashfs support is intentionally NOT provided here. Both LevitateOS and
@@ -37,10 +50,35 @@
 //! in upstream ecosystems, but this builder does not emit squashfs artifacts.
 
 use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+pub use crate::artifact::image_contents::list_contents;
+use crate::artifact_store::ArtifactStore;
+use crate::build_summary;
+use crate::cache::{hash_tree, read_cached_hash, write_cached_hash, TreeHashMode};
+use crate::integrity;
+use crate::preflight::RequiredTools;
 use crate::process::{self, Cmd};
+use crate::progress::Progress;
+use crate::reproducible;
+
+/// How the staging tree is hashed to detect unchanged rebuilds: `content`
+/// (default), `metadata` (path, size and mtime only), or `off`.
+pub const EROFS_TREE_HASH_ENV: &str = "DISTRO_BUILDER_EROFS_TREE_HASH";
+
+/// Artifact store kind for images reused across runs.
+pub const EROFS_STORE_KIND: &str = "rootfs_erofs";
+
+/// Images kept in the store; older ones are pruned.
+const EROFS_STORE_KEEP: usize = 3;
+
+/// Register the host tools EROFS packing invokes.
+pub fn register_host_tools(tools: &mut RequiredTools) {
+    tools.require("erofs", "mkfs.erofs", "erofs-utils");
+}
 
 /// Create an EROFS image from a directory.
 ///
@@ -74,6 +112,25 @@ pub fn create_erofs(
     compression: &str,
     compression_level: u8,
     chunk_size: u32,
+) -> Result<()> {
+    create_erofs_with_store(
+        None,
+        source_dir,
+        output,
+        compression,
+        compression_level,
+        chunk_size,
+    )
+}
+
+/// Like [`create_erofs`], also reusing and recording images in `store`.
+pub fn create_erofs_with_store(
+    store: Option<&ArtifactStore>,
+    source_dir: &Path,
+    output: &Path,
+    compression: &str,
+    compression_level: u8,
+    chunk_size: u32,
 ) -> Result<()> {
     // Validate source directory
     if !source_dir.exists() {
@@ -85,6 +142,19 @@ pub fn create_erofs(
 
     ensure_owner_readable_files(source_dir)?;
 
+    let key_file = erofs_input_key_path(output);
+    let input_key = erofs_input_key(source_dir, compression, compression_level, chunk_size)?;
+    if let Some(key) = &input_key {
+        if reuse_erofs(store, key, &key_file, output)? {
+            return integrity::record_image_manifest(source_dir, output);
+        }
+    }
+    // The old key no longer describes the image once it is rebuilt.
+    if key_file.exists() {
+        fs::remove_file(&key_file)
+            .with_context(|| format!("Failed to remove {}", key_file.display()))?;
+    }
+
     // Check tool availability
     if !process::exists("mkfs.erofs") {
         bail!(
@@ -103,6 +173,12 @@ pub fn create_erofs(
             .with_context(|| format!("Failed to create output directory: {}", parent.display()))?;
     }
 
+    // The image may be a hardlink to a stored blob; never write through it.
+    if output.exists() {
+        fs::remove_file(output)
+            .with_context(|| format!("Failed to remove existing {}", output.display()))?;
+    }
+
     println!(
         "Creating EROFS with {} compression (level {})...",
         compression, compression_level
@@ -116,9 +192,10 @@ pub fn create_erofs(
         .args(["-z", &compression_arg])
         .args(["-C", &chunk_size.to_string()])
         .arg("--all-root") // All files owned by root (required for sshd, etc.)
-        .arg("-T0") // Reproducible builds (timestamp=0)
+        .arg(format!("-T{}", reproducible::erofs_timestamp()?)) // Reproducible builds
         .arg_path(output) // OUTPUT FIRST
         .arg_path(source_dir) // SOURCE SECOND
+        .progress(Progress::output_size("mkfs.erofs", output, None))
         .error_msg(
             "mkfs.erofs failed. Install erofs-utils: sudo dnf install erofs-utils\n\
              NOTE: erofs-utils 1.5+ required for lz4hc, 1.8+ for zstd.",
@@ -130,10 +207,139 @@ pub fn create_erofs(
     let bytes = metadata.len();
     println!("EROFS created: {}", format_size_human(bytes));
 
+    if let Some(key) = &input_key {
+        write_cached_hash(&key_file, key)
+            .with_context(|| format!("Failed to write {}", key_file.display()))?;
+        if let Some(store) = store {
+            if let Err(err) = store_erofs(store, key, source_dir, output) {
+                eprintln!("  [WARN] Failed to store EROFS image for reuse: {:#}", err);
+            }
+        }
+    }
+
+    integrity::record_image_manifest(source_dir, output)
+}
+
+/// Input key file for `output`: `.<image>-inputs.hash` in the same
+/// directory.
+pub fn erofs_input_key_path(output: &Path) -> PathBuf {
+    let name = output
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    output.with_file_name(format!(".{}-inputs.hash", name))
+}
+
+/// Input key for packing `source_dir` with the given settings, or `None`
+/// when tree hashing is turned off.
+pub fn erofs_input_key(
+    source_dir: &Path,
+    compression: &str,
+    compression_level: u8,
+    chunk_size: u32,
+) -> Result<Option<String>> {
+    let mode = match std::env::var(EROFS_TREE_HASH_ENV).ok().as_deref() {
+        None | Some("") | Some("content") => TreeHashMode::Content,
+        Some("metadata") => TreeHashMode::Metadata,
+        Some("off") => return Ok(None),
+        Some(other) => bail!(
+            "invalid {}='{}'\n\
+             Remediation: use content, metadata or off.",
+            EROFS_TREE_HASH_ENV,
+            other
+        ),
+    };
+    let tree = hash_tree(source_dir, mode)
+        .with_context(|| format!("hashing EROFS source tree {}", source_dir.display()))?;
+    // v2: file contents enter the tree hash as per-file digests.
+    let mut settings = format!(
+        "erofs-v2:{:?}:{}:{}:{}:{}:{}",
+        mode,
+        compression,
+        compression_level,
+        chunk_size,
+        mkfs_erofs_version(),
+        tree
+    );
+    // Keys of images built without an epoch stay valid.
+    let timestamp = reproducible::erofs_timestamp()?;
+    if timestamp != 0 {
+        settings.push_str(&format!(":T{}", timestamp));
+    }
+    Ok(Some(format!("{:x}", Sha256::digest(settings.as_bytes()))))
+}
+
+/// `mkfs.erofs --version` output, so an erofs-utils upgrade repacks images;
+/// empty when the tool is missing.
+fn mkfs_erofs_version() -> String {
+    Cmd::new("mkfs.erofs")
+        .arg("--version")
+        .allow_fail()
+        .run()
+        .map(|result| result.stdout.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Keep `output` when its recorded key matches, else restore it from
+/// `store`. Returns whether packing can be skipped.
+fn reuse_erofs(
+    store: Option<&ArtifactStore>,
+    key: &str,
+    key_file: &Path,
+    output: &Path,
+) -> Result<bool> {
+    let reused = reuse_erofs_from(store, key, key_file, output)?;
+    build_summary::record_cache("erofs", reused);
+    Ok(reused)
+}
+
+fn reuse_erofs_from(
+    store: Option<&ArtifactStore>,
+    key: &str,
+    key_file: &Path,
+    output: &Path,
+) -> Result<bool> {
+    if output.is_file() && read_cached_hash(key_file).as_deref() == Some(key) {
+        println!("EROFS source unchanged, keeping {}", output.display());
+        return Ok(true);
+    }
+    let Some(store) = store else {
+        return Ok(false);
+    };
+    match store.get(EROFS_STORE_KIND, key) {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(false),
+        Err(err) => {
+            eprintln!("  [WARN] Failed to look up stored EROFS image: {:#}", err);
+            return Ok(false);
+        }
+    }
+    if let Err(err) = store.materialize_to(EROFS_STORE_KIND, key, output) {
+        eprintln!("  [WARN] Failed to restore stored EROFS image: {:#}", err);
+        return Ok(false);
+    }
+    write_cached_hash(key_file, key)
+        .with_context(|| format!("Failed to write {}", key_file.display()))?;
+    println!(
+        "EROFS source unchanged, restored {} from the artifact store",
+        output.display()
+    );
+    Ok(true)
+}
+
+fn store_erofs(store: &ArtifactStore, key: &str, source_dir: &Path, output: &Path) -> Result<()> {
+    let mut meta = BTreeMap::new();
+    meta.insert(
+        "source_dir".to_string(),
+        serde_json::Value::String(source_dir.display().to_string()),
+    );
+    store.ingest_file_move_and_link(EROFS_STORE_KIND, key, output, meta)?;
+    store.prune_kind_keep_last(EROFS_STORE_KIND, EROFS_STORE_KEEP)?;
     Ok(())
 }
 
-fn format_size_human(bytes: u64) -> String {
+/// Format a byte count with binary units, e.g. `1.5 GB`.
+pub fn format_size_human(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
     if bytes < 1024 {
         return format!("{bytes} B");
@@ -223,11 +429,22 @@ fn ensure_owner_readable_files(source_dir: &Path) -> Result<()> {
 /// )?;
 /// ```
 pub fn build_erofs_default(source_dir: &Path, output: &Path) -> Result<()> {
+    build_erofs_default_with_store(None, source_dir, output)
+}
+
+/// Like [`build_erofs_default`], also reusing and recording images in
+/// `store`.
+pub fn build_erofs_default_with_store(
+    store: Option<&ArtifactStore>,
+    source_dir: &Path,
+    output: &Path,
+) -> Result<()> {
     use distro_spec::shared::rootfs::{
         EROFS_CHUNK_SIZE, EROFS_COMPRESSION, EROFS_COMPRESSION_LEVEL,
     };
 
-    create_erofs(
+    create_erofs_with_store(
+        store,
         source_dir,
         output,
         EROFS_COMPRESSION,
@@ -239,6 +456,7 @@ pub fn build_erofs_default(source_dir: &Path, output: &Path) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
 
     #[test]
     fn test_source_dir_validation() {
@@ -266,4 +484,48 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("not a directory"));
     }
+
+    #[test]
+    fn test_unchanged_tree_reuses_image() {
+        let temp = TempDir::new().unwrap();
+        let staging = temp.path().join("staging");
+        fs::create_dir_all(staging.join("etc")).unwrap();
+        fs::write(staging.join("etc/hostname"), "levitate\n").unwrap();
+        let key = erofs_input_key(&staging, "zstd", 6, 1048576)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            erofs_input_key(&staging, "zstd", 6, 1048576).unwrap(),
+            Some(key.clone())
+        );
+        assert_ne!(
+            erofs_input_key(&staging, "lz4", 6, 1048576).unwrap(),
+            Some(key.clone())
+        );
+
+        // A matching key keeps the image without running mkfs.erofs.
+        let output = temp.path().join("run-1/filesystem.erofs");
+        fs::create_dir_all(output.parent().unwrap()).unwrap();
+        fs::write(&output, "image").unwrap();
+        write_cached_hash(&erofs_input_key_path(&output), &key).unwrap();
+        create_erofs(&staging, &output, "zstd", 6, 1048576).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"image");
+
+        // A later run restores it from the store.
+        let store = ArtifactStore::open(temp.path()).unwrap();
+        store_erofs(&store, &key, &staging, &output).unwrap();
+        let next = temp.path().join("run-2/filesystem.erofs");
+        create_erofs_with_store(Some(&store), &staging, &next, "zstd", 6, 1048576).unwrap();
+        assert_eq!(fs::read(&next).unwrap(), b"image");
+        assert_eq!(
+            read_cached_hash(&erofs_input_key_path(&next)),
+            Some(key.clone())
+        );
+
+        fs::write(staging.join("etc/hostname"), "acorn\n").unwrap();
+        assert_ne!(
+            erofs_input_key(&staging, "zstd", 6, 1048576).unwrap(),
+            Some(key)
+        );
+    }
 }
@@ -0,0 +1,291 @@
+//! Live, emergency and debug UKIs.
+//!
+//! The live UKI transform names three UKIs, which release hooks used to
+//! build with their own `ukify` calls and hand-written command lines.
+//! [`UkiBuilder::build_set`] builds all three from one kernel and initramfs;
+//! only the embedded command line differs ([`UkiFlavor::cmdline`]):
+//!
+//! - live: the live command line as given
+//! - emergency: a shell in the initramfs before the root is mounted
+//!   (`break=pre-mount rd.shell`)
+//! - debug: verbose kernel and initramfs logging (`debug rd.debug loglevel=7`)
+//!
+//! Both troubleshooting UKIs drop `quiet` and the splash, so their output
+//! reaches the console. Unlike boot menu entries, they work under Secure
+//! Boot, where systemd-stub ignores command line overrides. Before the ESP
+//! is packed, the set is read back and checked: all three present, each
+//! carrying the built kernel, initramfs and its flavor's tokens. With
+//! [`UkiBuilder::sign_with`], each UKI is signed with the Secure Boot db key.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::artifact::boot_check::{verify_esp_dir, BootExpectation};
+use crate::artifact::esp::UKI_DIR;
+use crate::build::cmdline::CmdlineBuilder;
+use crate::preflight::RequiredTools;
+use crate::process::Cmd;
+use crate::secureboot::{SecureBootKeys, SECUREBOOT_KEYS_ENV};
+
+/// Tokens that would hide console output in a troubleshooting UKI.
+const QUIET_TOKENS: &[&str] = &["quiet", "splash", "rhgb", "loglevel"];
+
+/// One of the three UKIs of a live ISO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UkiFlavor {
+    Live,
+    Emergency,
+    Debug,
+}
+
+impl UkiFlavor {
+    pub const ALL: [UkiFlavor; 3] = [Self::Live, Self::Emergency, Self::Debug];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Live => "live",
+            Self::Emergency => "emergency",
+            Self::Debug => "debug",
+        }
+    }
+
+    /// Tokens this flavor adds to the live command line.
+    pub fn tokens(self) -> &'static [&'static str] {
+        match self {
+            Self::Live => &[],
+            Self::Emergency => &["break=pre-mount", "rd.shell"],
+            Self::Debug => &["debug", "rd.debug", "loglevel=7"],
+        }
+    }
+
+    /// This flavor's command line, derived from the live one.
+    pub fn cmdline(self, live_cmdline: &str) -> Result<String> {
+        let mut cmdline = CmdlineBuilder::parse(live_cmdline)?;
+        if self == Self::Live {
+            return cmdline.build();
+        }
+        for key in QUIET_TOKENS.iter().chain(self.tokens()) {
+            cmdline = cmdline.remove(key.split('=').next().unwrap_or(key));
+        }
+        cmdline
+            .extend_tokens(self.tokens())?
+            .build()
+            .with_context(|| format!("deriving the {} UKI command line", self.id()))
+    }
+}
+
+/// File names of the three UKIs under `EFI/Linux`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UkiSet {
+    pub live: String,
+    pub emergency: String,
+    pub debug: String,
+}
+
+impl UkiSet {
+    /// Names in contract order: live, emergency, debug.
+    pub fn from_output_names<S: AsRef<str>>(names: &[S]) -> Result<Self> {
+        let [live, emergency, debug] = names else {
+            bail!(
+                "expected three live UKI output names (live, emergency, debug), found {}",
+                names.len()
+            );
+        };
+        let set = Self {
+            live: live.as_ref().to_string(),
+            emergency: emergency.as_ref().to_string(),
+            debug: debug.as_ref().to_string(),
+        };
+        for flavor in UkiFlavor::ALL {
+            let name = set.filename(flavor);
+            if !name.ends_with(".efi") || name.contains('/') {
+                bail!(
+                    "{} UKI output name '{}' must be a plain '*.efi' file name",
+                    flavor.id(),
+                    name
+                );
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn filename(&self, flavor: UkiFlavor) -> &str {
+        match flavor {
+            UkiFlavor::Live => &self.live,
+            UkiFlavor::Emergency => &self.emergency,
+            UkiFlavor::Debug => &self.debug,
+        }
+    }
+
+    /// Add the checks of this set to `expect`: every UKI present, the
+    /// troubleshooting ones with their flavor's tokens.
+    pub fn expect(&self, mut expect: BootExpectation) -> BootExpectation {
+        for flavor in UkiFlavor::ALL {
+            expect = expect.require_cmdline(self.filename(flavor), flavor.tokens());
+        }
+        expect
+    }
+
+    /// Fail unless all three UKIs are non-empty files under `esp_root`.
+    pub fn verify_present(&self, esp_root: &Path) -> Result<()> {
+        let missing: Vec<String> = UkiFlavor::ALL
+            .iter()
+            .map(|flavor| format!("{}/{}", UKI_DIR, self.filename(*flavor)))
+            .filter(|rel| fs::metadata(esp_root.join(rel)).map_or(true, |m| m.len() == 0))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "UKI(s) missing or empty under {}: {}\n\
+                 Remediation: build the live, emergency and debug UKIs (`distro-builder artifact build-ukis`) before packing the ISO.",
+                esp_root.display(),
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `ukify` invocation shared by the UKIs of one build.
+#[derive(Debug, Clone)]
+pub struct UkiBuilder {
+    kernel: PathBuf,
+    initrd: PathBuf,
+    os_release: Option<PathBuf>,
+    splash: Option<PathBuf>,
+    signing_keys: Option<SecureBootKeys>,
+}
+
+impl UkiBuilder {
+    pub fn new(kernel: &Path, initrd: &Path) -> Self {
+        Self {
+            kernel: kernel.to_path_buf(),
+            initrd: initrd.to_path_buf(),
+            os_release: None,
+            splash: None,
+            signing_keys: None,
+        }
+    }
+
+    /// os-release embedded as the `.osrel` section (the boot menu title).
+    pub fn os_release(mut self, path: &Path) -> Self {
+        self.os_release = Some(path.to_path_buf());
+        self
+    }
+
+    /// BMP embedded as the `.splash` section of the live UKI.
+    pub fn splash(mut self, bitmap: &Path) -> Self {
+        self.splash = Some(bitmap.to_path_buf());
+        self
+    }
+
+    /// Sign every UKI with the db key of `keys`.
+    pub fn sign_with(mut self, keys: SecureBootKeys) -> Self {
+        self.signing_keys = Some(keys);
+        self
+    }
+
+    /// Build one UKI with `cmdline` at `output`.
+    pub fn build(&self, cmdline: &str, output: &Path, splash: bool) -> Result<()> {
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let unsigned = output.with_extension("unsigned.efi");
+        let built = match self.signing_keys {
+            Some(_) => unsigned.as_path(),
+            None => output,
+        };
+        let mut cmd = Cmd::new("ukify")
+            .arg("build")
+            .arg(format!("--linux={}", self.kernel.display()))
+            .arg(format!("--initrd={}", self.initrd.display()))
+            .arg(format!("--cmdline={}", cmdline))
+            .arg(format!("--output={}", built.display()));
+        if let Some(os_release) = &self.os_release {
+            cmd = cmd.arg(format!("--os-release=@{}", os_release.display()));
+        }
+        if let Some(bitmap) = self.splash.as_ref().filter(|_| splash) {
+            cmd = cmd.arg(format!("--splash={}", bitmap.display()));
+        }
+        cmd.error_msg(format!(
+            "ukify failed to build {}. Install systemd-ukify.",
+            output.display()
+        ))
+        .run()?;
+        if let Some(keys) = &self.signing_keys {
+            keys.sign_efi(&unsigned, output)?;
+            fs::remove_file(&unsigned)
+                .with_context(|| format!("Failed to remove {}", unsigned.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Build the live, emergency and debug UKIs into `esp_root/EFI/Linux`,
+    /// then check the set against the kernel and initramfs they were built
+    /// from.
+    pub fn build_set(&self, set: &UkiSet, live_cmdline: &str, esp_root: &Path) -> Result<()> {
+        for flavor in UkiFlavor::ALL {
+            let output = esp_root.join(UKI_DIR).join(set.filename(flavor));
+            let cmdline = flavor.cmdline(live_cmdline)?;
+            self.build(&cmdline, &output, flavor == UkiFlavor::Live)
+                .with_context(|| format!("building the {} UKI", flavor.id()))?;
+        }
+        set.verify_present(esp_root)?;
+        let expect = BootExpectation::new()
+            .kernel_image(&self.kernel)?
+            .initrd_file(&self.initrd)?;
+        verify_esp_dir(esp_root, &set.expect(expect))
+    }
+}
+
+/// Register the host tools [`UkiBuilder`] invokes.
+pub fn register_host_tools(tools: &mut RequiredTools) {
+    tools.require_all("uki", &[("ukify", "systemd-ukify")]);
+    if std::env::var_os(SECUREBOOT_KEYS_ENV).is_some_and(|dir| !dir.is_empty()) {
+        tools.require_all("uki", &[("sbsign", "sbsigntools")]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const LIVE: &str = "root=live:LABEL=LEVI rd.live.image quiet splash loglevel=3 console=tty0";
+
+    #[test]
+    fn test_flavor_cmdlines() {
+        assert_eq!(UkiFlavor::Live.cmdline(LIVE).unwrap(), LIVE);
+        assert_eq!(
+            UkiFlavor::Emergency.cmdline(LIVE).unwrap(),
+            "root=live:LABEL=LEVI rd.live.image console=tty0 break=pre-mount rd.shell"
+        );
+        assert_eq!(
+            UkiFlavor::Debug.cmdline(LIVE).unwrap(),
+            "root=live:LABEL=LEVI rd.live.image console=tty0 debug rd.debug loglevel=7"
+        );
+        // Deriving twice does not duplicate tokens.
+        let debug = UkiFlavor::Debug.cmdline(LIVE).unwrap();
+        assert_eq!(UkiFlavor::Debug.cmdline(&debug).unwrap(), debug);
+    }
+
+    #[test]
+    fn test_set_names_and_presence() {
+        let set = UkiSet::from_output_names(&["live.efi", "emergency.efi", "debug.efi"]).unwrap();
+        assert_eq!(set.filename(UkiFlavor::Emergency), "emergency.efi");
+        assert!(UkiSet::from_output_names(&["live.efi", "debug.efi"]).is_err());
+        assert!(UkiSet::from_output_names(&["live.efi", "x/emergency.efi", "debug.efi"]).is_err());
+
+        let temp = TempDir::new().unwrap();
+        let uki_dir = temp.path().join(UKI_DIR);
+        fs::create_dir_all(&uki_dir).unwrap();
+        fs::write(uki_dir.join("live.efi"), "MZ").unwrap();
+        fs::write(uki_dir.join("emergency.efi"), "").unwrap();
+        let err = format!("{:#}", set.verify_present(temp.path()).unwrap_err());
+        assert!(
+            err.contains("EFI/Linux/emergency.efi, EFI/Linux/debug.efi"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! dd-ready USB stick images built from a live ISO.
+//!
+//! Hybrid ISOs boot from USB, but their partition table is synthesized by
+//! xorriso and leaves no room for a writable partition. This module lays
+//! the same payload out as a plain GPT disk:
+//!
+//! ```text
+//! p1  ESP          the ISO's El Torito EFI image, copied verbatim
+//! p2  ISO payload  the whole ISO9660 image (the live initramfs finds it by
+//!                  volume label, exactly as on optical media)
+//! p3  persistence  empty ext4 labelled for live persistence
+//! ```
+//!
+//! The GPT names are `ESP`, `ISO` and the persistence label.
+//!
+//! Everything is done on image files with `xorriso`, `sfdisk`, `mkfs.ext4`
+//! and `dd`; no loop devices or root are needed.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::artifact::live_overlay::DEFAULT_PERSISTENCE_LABEL;
+use crate::preflight::RequiredTools;
+use crate::process::Cmd;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Space before the first partition and after the last (backup GPT).
+const GPT_RESERVED_MIB: u64 = 1;
+
+/// Default persistence partition size.
+pub const DEFAULT_PERSISTENCE_MB: u64 = 512;
+
+/// Options for [`build_usb_image`].
+#[derive(Debug, Clone)]
+pub struct UsbImageOptions {
+    /// Filesystem label of the persistence partition; must match the label
+    /// the live overlay looks for.
+    pub persistence_label: String,
+    /// Persistence partition size; `0` leaves the partition out.
+    pub persistence_mb: u64,
+    /// Path of the EFI boot image inside the ISO; detected from the El
+    /// Torito catalog when `None`.
+    pub efi_image_path: Option<String>,
+}
+
+impl Default for UsbImageOptions {
+    fn default() -> Self {
+        Self {
+            persistence_label: DEFAULT_PERSISTENCE_LABEL.to_string(),
+            persistence_mb: DEFAULT_PERSISTENCE_MB,
+            efi_image_path: None,
+        }
+    }
+}
+
+/// One partition of the stick image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UsbPartition {
+    start_mib: u64,
+    size_mib: u64,
+    /// sfdisk type shorthand.
+    type_code: &'static str,
+    name: &'static str,
+    /// GPT partition name.
+    label: String,
+}
+
+/// Register the host tools USB image conversion invokes.
+pub fn register_host_tools(tools: &mut RequiredTools) {
+    tools.require_all(
+        "usb-image",
+        &[
+            ("xorriso", "xorriso"),
+            ("sfdisk", "util-linux"),
+            ("mkfs.ext4", "e2fsprogs"),
+            ("dd", "coreutils"),
+        ],
+    );
+}
+
+/// Convert `iso` into a GPT USB image at `output`.
+///
+/// Returns `output`. Work files are created next to it and removed.
+pub fn build_usb_image(iso: &Path, output: &Path, options: &UsbImageOptions) -> Result<PathBuf> {
+    let mut tools = RequiredTools::new();
+    register_host_tools(&mut tools);
+    tools.check()?;
+    if !iso.is_file() {
+        bail!("ISO not found: {}", iso.display());
+    }
+
+    let work_dir = output.with_extension("usb-work");
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir)
+            .with_context(|| format!("Failed to remove {}", work_dir.display()))?;
+    }
+    fs::create_dir_all(&work_dir)
+        .with_context(|| format!("Failed to create {}", work_dir.display()))?;
+    let result = build_in(iso, output, options, &work_dir);
+    let _ = fs::remove_dir_all(&work_dir);
+    result?;
+    Ok(output.to_path_buf())
+}
+
+fn build_in(iso: &Path, output: &Path, options: &UsbImageOptions, work_dir: &Path) -> Result<()> {
+    println!("Building USB image from {}...", iso.display());
+
+    let efi_path = match &options.efi_image_path {
+        Some(path) => path.clone(),
+        None => {
+            let report = Cmd::new("xorriso")
+                .args(["-indev"])
+                .arg_path(iso)
+                .args(["-report_el_torito", "plain"])
+                .error_msg("xorriso could not read the El Torito catalog")
+                .run()?;
+            el_torito_efi_path(&report.stdout).with_context(|| {
+                format!(
+                    "{} has no UEFI El Torito boot image; pass the ESP path explicitly",
+                    iso.display()
+                )
+            })?
+        }
+    };
+    let esp_image = work_dir.join("esp.img");
+    Cmd::new("xorriso")
+        .args(["-osirrox", "on", "-indev"])
+        .arg_path(iso)
+        .args([
+            "-extract",
+            &format!("/{}", efi_path.trim_start_matches('/')),
+        ])
+        .arg_path(&esp_image)
+        .error_msg("xorriso failed to extract the EFI boot image")
+        .run()?;
+
+    let persistence_image = work_dir.join("persistence.img");
+    if options.persistence_mb > 0 {
+        validate_ext4_label(&options.persistence_label)?;
+        fs::File::create(&persistence_image)
+            .and_then(|file| file.set_len(options.persistence_mb * MIB))
+            .with_context(|| format!("Failed to create {}", persistence_image.display()))?;
+        Cmd::new("mkfs.ext4")
+            .args(["-q", "-L", &options.persistence_label])
+            .arg_path(&persistence_image)
+            .error_msg("mkfs.ext4 failed for the persistence partition")
+            .run()?;
+    }
+
+    let esp_size = file_size(&esp_image)?;
+    let iso_size = file_size(iso)?;
+    let partitions = plan_partitions(
+        esp_size,
+        iso_size,
+        options.persistence_mb,
+        &options.persistence_label,
+    );
+    let total_mib = partitions
+        .last()
+        .map(|p| p.start_mib + p.size_mib)
+        .unwrap_or(GPT_RESERVED_MIB)
+        + GPT_RESERVED_MIB;
+
+    if output.exists() {
+        fs::remove_file(output)
+            .with_context(|| format!("Failed to remove {}", output.display()))?;
+    }
+    fs::File::create(output)
+        .and_then(|file| file.set_len(total_mib * MIB))
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    write_partition_table(output, &partitions)?;
+
+    let sources = [esp_image.as_path(), iso, persistence_image.as_path()];
+    for (partition, source) in partitions.iter().zip(sources) {
+        println!(
+            "  Writing {} partition at {} MiB ({} MiB)...",
+            partition.name, partition.start_mib, partition.size_mib
+        );
+        Cmd::new("dd")
+            .arg(format!("if={}", source.display()))
+            .arg(format!("of={}", output.display()))
+            .args(["bs=1M", "conv=notrunc,sparse"])
+            .arg(format!("seek={}", partition.start_mib))
+            .error_msg(format!("dd failed for the {} partition", partition.name))
+            .run()?;
+    }
+
+    println!(
+        "  USB image: {} ({} MiB); write it with dd to the whole device",
+        output.display(),
+        total_mib
+    );
+    Ok(())
+}
+
+/// Lay partitions out back to back on 1 MiB boundaries. The persistence
+/// partition's GPT name is its filesystem label.
+fn plan_partitions(
+    esp_bytes: u64,
+    iso_bytes: u64,
+    persistence_mb: u64,
+    persistence_label: &str,
+) -> Vec<UsbPartition> {
+    let mut partitions = vec![
+        UsbPartition {
+            start_mib: 0,
+            size_mib: esp_bytes.div_ceil(MIB),
+            type_code: "U",
+            name: "EFI",
+            label: "ESP".to_string(),
+        },
+        UsbPartition {
+            start_mib: 0,
+            size_mib: iso_bytes.div_ceil(MIB),
+            type_code: "L",
+            name: "ISO payload",
+            label: "ISO".to_string(),
+        },
+    ];
+    if persistence_mb > 0 {
+        partitions.push(UsbPartition {
+            start_mib: 0,
+            size_mib: persistence_mb,
+            type_code: "L",
+            name: "persistence",
+            label: persistence_label.to_string(),
+        });
+    }
+    let mut next_mib = GPT_RESERVED_MIB;
+    for partition in &mut partitions {
+        partition.start_mib = next_mib;
+        next_mib += partition.size_mib;
+    }
+    partitions
+}
+
+fn sfdisk_script(partitions: &[UsbPartition]) -> String {
+    let sectors_per_mib = MIB / 512;
+    let mut script = String::from("label: gpt\n");
+    for partition in partitions {
+        script.push_str(&format!(
+            "start={}, size={}, type={}, name=\"{}\"\n",
+            partition.start_mib * sectors_per_mib,
+            partition.size_mib * sectors_per_mib,
+            partition.type_code,
+            partition.label
+        ));
+    }
+    script
+}
+
+fn write_partition_table(disk: &Path, partitions: &[UsbPartition]) -> Result<()> {
+    let mut child = Command::new("sfdisk")
+        .arg(disk)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run sfdisk")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(sfdisk_script(partitions).as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "sfdisk failed to create the USB partition table: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Path of the UEFI boot image from `xorriso -report_el_torito plain`.
+pub(crate) fn el_torito_efi_path(report: &str) -> Option<String> {
+    // El Torito boot img :   2  UEFI  y   none  0x0000  0x00    5760     123
+    // El Torito img path :   2  /boot/efiboot.img
+    let index = report.lines().find_map(|line| {
+        let fields = line.strip_prefix("El Torito boot img :")?;
+        let mut fields = fields.split_whitespace();
+        let index = fields.next()?;
+        (fields.next()? == "UEFI").then(|| index.to_string())
+    })?;
+    report.lines().find_map(|line| {
+        let fields = line.strip_prefix("El Torito img path :")?;
+        let mut fields = fields.split_whitespace();
+        (fields.next()? == index).then(|| fields.next().map(str::to_string))?
+    })
+}
+
+fn validate_ext4_label(label: &str) -> Result<()> {
+    if label.is_empty() || label.len() > 16 || !label.is_ascii() || label.contains('"') {
+        bail!(
+            "persistence label '{}' must be 1-16 ASCII characters other than '\"'",
+            label
+        );
+    }
+    Ok(())
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    Ok(fs::metadata(path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?
+        .len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_plan() {
+        let partitions = plan_partitions(5 * MIB + 1, 700 * MIB, 256, DEFAULT_PERSISTENCE_LABEL);
+        let layout: Vec<_> = partitions
+            .iter()
+            .map(|p| (p.start_mib, p.size_mib))
+            .collect();
+        assert_eq!(layout, vec![(1, 6), (7, 700), (707, 256)]);
+        assert_eq!(
+            sfdisk_script(&partitions),
+            "label: gpt\n\
+             start=2048, size=12288, type=U, name=\"ESP\"\n\
+             start=14336, size=1433600, type=L, name=\"ISO\"\n\
+             start=1447936, size=524288, type=L, name=\"LIVEPERSIST\"\n"
+        );
+        assert_eq!(
+            plan_partitions(MIB, MIB, 0, DEFAULT_PERSISTENCE_LABEL).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_el_torito_efi_path() {
+        let report = "\
+El Torito catalog  : 33  1
+El Torito images   :   N  Pltf  B   Emul  Ld_seg  Hdpt  Ldsiz         LBA
+El Torito boot img :   1  BIOS  y   none  0x0000  0x00      4          54
+El Torito boot img :   2  UEFI  y   none  0x0000  0x00   5760         123
+El Torito img path :   1  /boot/isolinux/isolinux.bin
+El Torito img path :   2  /boot/efiboot.img
+";
+        assert_eq!(
+            el_torito_efi_path(report).as_deref(),
+            Some("/boot/efiboot.img")
+        );
+        assert_eq!(el_torito_efi_path("El Torito boot img :   1  BIOS"), None);
+    }
+}
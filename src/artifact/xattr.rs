@@ -0,0 +1,270 @@
+//! Extended attribute preservation for staged rootfs trees.
+//!
+//! `fs::copy`, `rsync -a` and `fsck.erofs --extract` all drop extended
+//! attributes, so a payload derived from an SELinux distribution (Rocky)
+//! lost its `security.selinux` labels on the way into an image and the
+//! installed system booted unlabeled. [`copy_tree_xattrs`] copies the
+//! attributes of a source tree onto an already-copied destination tree.
+//!
+//! Copying is best-effort per attribute: a destination filesystem without
+//! xattr support, or an unprivileged builder writing `security.*` or
+//! `trusted.*`, refuses the write and the attribute is counted in
+//! [`XattrCopy::skipped`] rather than failing the build. A variant that
+//! depends on labels relabels the result (`build::selinux`).
+
+use anyhow::{Context, Result};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Outcome of copying extended attributes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XattrCopy {
+    /// Attributes written to the destination.
+    pub copied: usize,
+    /// Attributes the destination refused (unsupported or not permitted).
+    pub skipped: usize,
+}
+
+impl XattrCopy {
+    fn add(&mut self, other: XattrCopy) {
+        self.copied += other.copied;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Copy the extended attributes of `src` onto `dst`, without following
+/// symlinks.
+pub fn copy_xattrs(src: &Path, dst: &Path) -> Result<XattrCopy> {
+    let src_c = c_path(src)?;
+    let dst_c = c_path(dst)?;
+    let mut result = XattrCopy::default();
+    let names = list_xattrs(&src_c)
+        .with_context(|| format!("Failed to list xattrs of {}", src.display()))?;
+    for name in names {
+        let value = match get_xattr(&src_c, &name) {
+            Ok(value) => value,
+            // Removed between listing and reading.
+            Err(err) if err.raw_os_error() == Some(libc::ENODATA) => continue,
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!(
+                        "Failed to read xattr {} of {}",
+                        name.to_string_lossy(),
+                        src.display()
+                    )
+                })
+            }
+        };
+        // SAFETY: both strings are NUL-terminated and `value` outlives the call.
+        let rc = unsafe {
+            libc::lsetxattr(
+                dst_c.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if rc == 0 {
+            result.copied += 1;
+            continue;
+        }
+        let err = io::Error::last_os_error();
+        if is_refused(&err) {
+            result.skipped += 1;
+            continue;
+        }
+        return Err(err).with_context(|| {
+            format!(
+                "Failed to set xattr {} on {}",
+                name.to_string_lossy(),
+                dst.display()
+            )
+        });
+    }
+    Ok(result)
+}
+
+/// The extended attributes of `path`, sorted by name, without following
+/// symlinks.
+pub fn read_xattrs(path: &Path) -> Result<Vec<(CString, Vec<u8>)>> {
+    let path_c = c_path(path)?;
+    let mut names = list_xattrs(&path_c)
+        .with_context(|| format!("Failed to list xattrs of {}", path.display()))?;
+    names.sort();
+    let mut xattrs = Vec::with_capacity(names.len());
+    for name in names {
+        match get_xattr(&path_c, &name) {
+            Ok(value) => xattrs.push((name, value)),
+            // Removed between listing and reading.
+            Err(err) if err.raw_os_error() == Some(libc::ENODATA) => continue,
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!(
+                        "Failed to read xattr {} of {}",
+                        name.to_string_lossy(),
+                        path.display()
+                    )
+                })
+            }
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Copy the extended attributes of every entry under `src` (including
+/// `src` itself) onto the entry at the same relative path under `dst`.
+///
+/// Entries missing from `dst` are ignored, so this can follow a copy that
+/// filtered the tree.
+pub fn copy_tree_xattrs(src: &Path, dst: &Path) -> Result<XattrCopy> {
+    let mut result = XattrCopy::default();
+    if dst.symlink_metadata().is_err() {
+        return Ok(result);
+    }
+    result.add(copy_xattrs(src, dst)?);
+    let meta =
+        fs::symlink_metadata(src).with_context(|| format!("Failed to stat {}", src.display()))?;
+    if !meta.is_dir() {
+        return Ok(result);
+    }
+    for entry in fs::read_dir(src).with_context(|| format!("Failed to read {}", src.display()))? {
+        let entry = entry.with_context(|| format!("Failed to read {}", src.display()))?;
+        result.add(copy_tree_xattrs(
+            &entry.path(),
+            &dst.join(entry.file_name()),
+        )?);
+    }
+    Ok(result)
+}
+
+/// Whether the destination refused the attribute rather than failed.
+fn is_refused(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ENOTSUP) | Some(libc::EPERM) | Some(libc::EACCES)
+    )
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("Path contains a NUL byte: {}", path.display()))
+}
+
+fn list_xattrs(path: &CStr) -> io::Result<Vec<CString>> {
+    loop {
+        // SAFETY: a null buffer of size 0 asks for the required size.
+        let size = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOTSUP) {
+                return Ok(Vec::new());
+            }
+            return Err(err);
+        }
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let mut buf = vec![0u8; size as usize];
+        // SAFETY: `buf` is writable for `buf.len()` bytes.
+        let len = unsafe { libc::llistxattr(path.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ERANGE) {
+                // Grew since the size query.
+                continue;
+            }
+            return Err(err);
+        }
+        buf.truncate(len as usize);
+        return Ok(buf
+            .split(|byte| *byte == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect());
+    }
+}
+
+fn get_xattr(path: &CStr, name: &CStr) -> io::Result<Vec<u8>> {
+    loop {
+        // SAFETY: a null buffer of size 0 asks for the required size.
+        let size =
+            unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        // SAFETY: `buf` is writable for `buf.len()` bytes.
+        let len = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(err);
+        }
+        buf.truncate(len as usize);
+        return Ok(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn set(path: &Path, name: &str, value: &[u8]) -> bool {
+        let path = c_path(path).unwrap();
+        let name = CString::new(name).unwrap();
+        // SAFETY: NUL-terminated strings and a live buffer.
+        unsafe {
+            libc::lsetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            ) == 0
+        }
+    }
+
+    #[test]
+    fn test_copy_tree_xattrs() {
+        let temp = TempDir::new().unwrap();
+        let src = temp.path().join("src");
+        let dst = temp.path().join("dst");
+        for root in [&src, &dst] {
+            fs::create_dir_all(root.join("etc")).unwrap();
+            fs::write(root.join("etc/shadow"), "").unwrap();
+        }
+        fs::write(src.join("only-in-src"), "").unwrap();
+        if !set(&src.join("etc/shadow"), "user.test", b"shadow_t") {
+            // The temp filesystem has no user xattrs (e.g. tmpfs without
+            // user_xattr); nothing to exercise.
+            return;
+        }
+        set(&src.join("only-in-src"), "user.test", b"x");
+
+        // Hosts running SELinux label the temp tree as well.
+        assert!(copy_tree_xattrs(&src, &dst).unwrap().copied >= 1);
+        let dst_c = c_path(&dst.join("etc/shadow")).unwrap();
+        assert_eq!(
+            get_xattr(&dst_c, &CString::new("user.test").unwrap()).unwrap(),
+            b"shadow_t"
+        );
+        assert_eq!(
+            copy_tree_xattrs(&src.join("missing"), &dst.join("missing")).unwrap(),
+            XattrCopy::default()
+        );
+    }
+}
@@ -7,9 +7,31 @@
 //!   `output/.<artifact>-inputs.hash` files) so distros can quickly restore
 //!   missing outputs without rebuilding.
 //!
+//! Named tags (`nightly`, `levitate-latest`) point at one index entry of a
+//! kind, so consumers can ask for "the latest successful ISO" without
+//! scraping run directories. Each tag is `tags/<kind>/<tag>.json` plus a
+//! `tags/<kind>/<tag>` symlink to the blob for tools that only need a path.
+//! Tagged entries survive pruning.
+//!
+//! Every write, removal and tag change is appended to the audit log (see
+//! [`crate::audit`]). [`ArtifactStore::sync_from`] copies what another
+//! store has and this one lacks.
+//!
+//! Writes of one kind and input key (the `put_*` calls and sync) hold an
+//! exclusive lock under `locks/`, so concurrent builds storing the same
+//! entry take turns instead of interleaving index updates; a writer that
+//! finds the lock held waits for the other one.
+//!
+//! Directories are stored as deterministic tarballs compressed with the
+//! store's [`Compression`] (`[compression] store` in `distro-builder.toml`,
+//! zstd by default). The format is recorded per entry, so changing the
+//! setting never invalidates existing blobs.
+//!
 //! This is intentionally NOT a package manager. It stores *build outputs* only.
 
 use crate::artifact::filesystem::copy_dir_recursive;
+use crate::audit::{self, AuditAction};
+use crate::compression::{self, Codec, Compression};
 use anyhow::{bail, Context, Result};
 use fs2::FileExt;
 use serde::{Deserialize, Serialize};
@@ -22,6 +44,10 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use tar::Builder as TarBuilder;
 use walkdir::WalkDir;
 
+mod sync;
+
+pub use sync::{SyncConflict, SyncFilter, SyncReport};
+
 /// Default store directory name at repo root.
 pub const DEFAULT_STORE_DIR: &str = ".artifacts";
 
@@ -39,6 +65,35 @@ pub enum ArtifactFormat {
     File,
     /// A tar archive compressed with zstd.
     TarZst,
+    /// A tar archive compressed with xz.
+    TarXz,
+    /// A tar archive compressed with lz4.
+    TarLz4,
+    /// A tar archive compressed with gzip.
+    TarGz,
+}
+
+impl ArtifactFormat {
+    /// Tar archive format compressed with `codec`.
+    pub fn tar(codec: Codec) -> Self {
+        match codec {
+            Codec::Zstd => Self::TarZst,
+            Codec::Xz => Self::TarXz,
+            Codec::Lz4 => Self::TarLz4,
+            Codec::Gzip => Self::TarGz,
+        }
+    }
+
+    /// Codec of a tar archive format; `None` for plain files.
+    pub fn codec(self) -> Option<Codec> {
+        match self {
+            Self::File => None,
+            Self::TarZst => Some(Codec::Zstd),
+            Self::TarXz => Some(Codec::Xz),
+            Self::TarLz4 => Some(Codec::Lz4),
+            Self::TarGz => Some(Codec::Gzip),
+        }
+    }
 }
 
 /// Index entry mapping an input key to a content-addressed blob.
@@ -54,6 +109,15 @@ pub struct IndexEntry {
     pub meta: BTreeMap<String, serde_json::Value>,
 }
 
+/// A named pointer at one index entry of a kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagEntry {
+    pub kind: String,
+    pub tag: String,
+    pub input_key: String,
+    pub tagged_at_unix: u64,
+}
+
 /// A stored artifact resolved from the index.
 #[derive(Debug, Clone)]
 pub struct StoredArtifact {
@@ -61,31 +125,64 @@ pub struct StoredArtifact {
     pub blob_path: PathBuf,
 }
 
-/// Artifact store rooted at `<repo>/.artifacts`.
+/// Artifact store rooted at `<repo>/.artifacts` (see
+/// [`artifacts_root`](crate::repo_config::artifacts_root) for overrides).
 #[derive(Debug, Clone)]
 pub struct ArtifactStore {
     root: PathBuf,
+    compression: Compression,
 }
 
 impl ArtifactStore {
-    /// Open (and create if needed) the store at `<repo_root>/.artifacts`.
+    /// Open (and create if needed) the store at `<repo_root>/.artifacts`,
+    /// or at the configured output root.
     pub fn open(repo_root: &Path) -> Result<Self> {
-        let root = repo_root.join(DEFAULT_STORE_DIR);
-        let store = Self { root };
+        let root = crate::repo_config::artifacts_root(repo_root)?;
+        let compression = crate::repo_config::RepoConfig::load(repo_root)?
+            .compression
+            .store;
+        let store = Self { root, compression };
         store.ensure_layout()?;
         Ok(store)
     }
 
+    /// Open an existing store whose root is `root` (an artifacts root, not
+    /// a checkout), e.g. another machine's store on a shared mount. Nothing
+    /// is created. The root carries no configuration, so the caller passes
+    /// the checkout's `[compression] store`.
+    pub fn open_existing(root: &Path, compression: Compression) -> Result<Self> {
+        let store = Self {
+            root: root.to_path_buf(),
+            compression,
+        };
+        if !store.index_dir().is_dir() || !store.blobs_dir().is_dir() {
+            bail!(
+                "No artifact store at {} (expected index/ and blobs/)",
+                root.display()
+            );
+        }
+        Ok(store)
+    }
+
     /// Open the store for a distro crate directory (e.g. `<repo>/AcornOS`).
     pub fn open_for_distro(base_dir: &Path) -> Result<Self> {
-        let repo_root = base_dir.parent().unwrap_or(base_dir);
-        Self::open(repo_root)
+        Self::open(crate::repo_layout::RepoLayout::for_distro_dir(base_dir).root())
+    }
+
+    /// Compress directory blobs written from now on with `compression`.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
     }
 
     pub fn root(&self) -> &Path {
         &self.root
     }
 
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
     fn ensure_layout(&self) -> Result<()> {
         fs::create_dir_all(self.blobs_dir().join("sha256"))?;
         fs::create_dir_all(self.index_dir())?;
@@ -102,7 +199,7 @@ impl ArtifactStore {
         self.root.join("index")
     }
 
-    fn tmp_dir(&self) -> PathBuf {
+    pub(crate) fn tmp_dir(&self) -> PathBuf {
         self.root.join("tmp")
     }
 
@@ -110,6 +207,16 @@ impl ArtifactStore {
         self.root.join("locks")
     }
 
+    fn tags_dir(&self) -> PathBuf {
+        self.root.join("tags")
+    }
+
+    fn tag_path(&self, kind: &str, tag: &str) -> Result<PathBuf> {
+        validate_kind(kind)?;
+        validate_tag(tag)?;
+        Ok(self.tags_dir().join(kind).join(format!("{}.json", tag)))
+    }
+
     fn kind_dir(&self, kind: &str) -> Result<PathBuf> {
         validate_kind(kind)?;
         Ok(self.index_dir().join(kind))
@@ -186,6 +293,7 @@ impl ArtifactStore {
                 format!("Failed to copy {} to {}", src_file.display(), tmp.display())
             })?;
             atomic_rename(&tmp, &blob_path)?;
+            self.audit_blob_write(&sha256, size_bytes);
         }
 
         meta.insert(
@@ -260,6 +368,7 @@ impl ArtifactStore {
                     })?;
                 }
             }
+            self.audit_blob_write(&sha256, size_bytes);
         }
 
         // Ensure the original path exists and points at the blob (hardlink if possible).
@@ -295,13 +404,38 @@ impl ArtifactStore {
         Ok(sha256)
     }
 
-    /// Store a directory as a deterministic `tar.zst` blob and update the index.
-    pub fn put_dir_as_tar_zst(
+    /// Store a directory as a deterministic tarball, compressed with the
+    /// store's [`Compression`], and update the index.
+    pub fn put_dir_as_tar(
+        &self,
+        kind: &str,
+        input_key: &str,
+        src_dir: &Path,
+        meta: BTreeMap<String, serde_json::Value>,
+    ) -> Result<String> {
+        self.put_tar(kind, input_key, src_dir, meta, TarOwnership::Root)
+    }
+
+    /// Store a root filesystem tree like [`put_dir_as_tar`](Self::put_dir_as_tar),
+    /// but record each entry's uid and gid so a restore as root gets the
+    /// same ownership back.
+    pub fn put_tree_as_tar(
+        &self,
+        kind: &str,
+        input_key: &str,
+        src_dir: &Path,
+        meta: BTreeMap<String, serde_json::Value>,
+    ) -> Result<String> {
+        self.put_tar(kind, input_key, src_dir, meta, TarOwnership::Preserve)
+    }
+
+    fn put_tar(
         &self,
         kind: &str,
         input_key: &str,
         src_dir: &Path,
         mut meta: BTreeMap<String, serde_json::Value>,
+        ownership: TarOwnership,
     ) -> Result<String> {
         if !src_dir.is_dir() {
             bail!("Source directory not found: {}", src_dir.display());
@@ -309,8 +443,11 @@ impl ArtifactStore {
 
         let _lock = self.acquire_lock(kind, input_key)?;
 
-        let tmp_tar = self.tmp_dir().join(tmp_name("artifact.tar.zst"));
-        create_tar_zst(src_dir, &tmp_tar)?;
+        let tmp_tar = self.tmp_dir().join(tmp_name(&format!(
+            "artifact.tar.{}",
+            self.compression.codec.extension()
+        )));
+        write_tar(src_dir, &tmp_tar, self.compression, ownership)?;
 
         let (sha256, size_bytes) = sha256_file(&tmp_tar)?;
         let blob_path = self.blob_path(&sha256)?;
@@ -322,6 +459,7 @@ impl ArtifactStore {
 
         if !blob_path.exists() {
             atomic_rename(&tmp_tar, &blob_path)?;
+            self.audit_blob_write(&sha256, size_bytes);
         } else {
             // Blob already exists; remove tmp.
             let _ = fs::remove_file(&tmp_tar);
@@ -337,7 +475,7 @@ impl ArtifactStore {
             kind: kind.to_string(),
             input_key: input_key.to_string(),
             blob_sha256: sha256.clone(),
-            format: ArtifactFormat::TarZst,
+            format: ArtifactFormat::tar(self.compression.codec),
             size_bytes,
             stored_at_unix,
             meta,
@@ -349,7 +487,7 @@ impl ArtifactStore {
 
     /// Store the kernel payload (vmlinuz + modules) from a staging directory.
     ///
-    /// This stores a `tar.zst` containing:
+    /// This stores a tarball (compressed like [`put_dir_as_tar`](Self::put_dir_as_tar)) containing:
     /// - `boot/vmlinuz`
     /// - `lib/modules/**` OR `usr/lib/modules/**` (whichever exists)
     ///
@@ -405,8 +543,11 @@ impl ArtifactStore {
         }
         copy_dir_recursive(&modules_dir, &dst_modules)?;
 
-        let tmp_tar = self.tmp_dir().join(tmp_name("kernel_payload.tar.zst"));
-        create_tar_zst(&payload_dir, &tmp_tar)?;
+        let tmp_tar = self.tmp_dir().join(tmp_name(&format!(
+            "kernel_payload.tar.{}",
+            self.compression.codec.extension()
+        )));
+        create_tar(&payload_dir, &tmp_tar, self.compression)?;
         let _ = fs::remove_dir_all(&payload_dir);
 
         let (sha256, size_bytes) = sha256_file(&tmp_tar)?;
@@ -417,6 +558,7 @@ impl ArtifactStore {
 
         if !blob_path.exists() {
             atomic_rename(&tmp_tar, &blob_path)?;
+            self.audit_blob_write(&sha256, size_bytes);
         } else {
             let _ = fs::remove_file(&tmp_tar);
         }
@@ -430,7 +572,7 @@ impl ArtifactStore {
             kind: kind.to_string(),
             input_key: input_key.to_string(),
             blob_sha256: sha256.clone(),
-            format: ArtifactFormat::TarZst,
+            format: ArtifactFormat::tar(self.compression.codec),
             size_bytes,
             stored_at_unix: now_unix(),
             meta,
@@ -448,12 +590,12 @@ impl ArtifactStore {
             .get(kind, input_key)?
             .with_context(|| format!("No stored artifact for {kind}:{input_key}"))?;
 
-        if stored.entry.format != ArtifactFormat::TarZst {
+        let Some(codec) = stored.entry.format.codec() else {
             bail!(
-                "kernel_payload has unexpected format {:?} (expected tar_zst)",
+                "kernel_payload has unexpected format {:?} (expected a tarball)",
                 stored.entry.format
             );
-        }
+        };
 
         // Verify blob hash on read.
         let (actual_sha, _sz) = sha256_file(&stored.blob_path)?;
@@ -484,20 +626,14 @@ impl ArtifactStore {
             }
         }
 
-        let f = File::open(&stored.blob_path)?;
-        let decoder = zstd::stream::Decoder::new(f)?;
-        let mut archive = tar::Archive::new(decoder);
-        archive
-            .unpack(staging_dir)
-            .with_context(|| format!("Failed to unpack {}", stored.blob_path.display()))?;
-
-        Ok(())
+        unpack_tar(&stored.blob_path, codec, staging_dir)
     }
 
     /// Materialize an artifact from the store into the requested destination.
     ///
     /// - `ArtifactFormat::File`: `dest` is a file path.
-    /// - `ArtifactFormat::TarZst`: `dest` is a directory path.
+    /// - tarball formats (`ArtifactFormat::TarZst`, ...): `dest` is a
+    ///   directory path.
     pub fn materialize_to(&self, kind: &str, input_key: &str, dest: &Path) -> Result<()> {
         let stored = self
             .get(kind, input_key)?
@@ -524,9 +660,9 @@ impl ArtifactStore {
             );
         }
 
-        match stored.entry.format {
-            ArtifactFormat::File => materialize_file(&stored.blob_path, dest),
-            ArtifactFormat::TarZst => materialize_tar_zst_dir(&stored.blob_path, dest),
+        match stored.entry.format.codec() {
+            None => materialize_file(&stored.blob_path, dest),
+            Some(codec) => materialize_tar_dir(&stored.blob_path, codec, dest),
         }
     }
 
@@ -557,16 +693,155 @@ impl ArtifactStore {
         Ok(out)
     }
 
+    /// Point `tag` at the `kind` entry stored under `input_key`, replacing
+    /// whatever it pointed at before.
+    pub fn tag(&self, kind: &str, input_key: &str, tag: &str) -> Result<()> {
+        self.write_tag(&TagEntry {
+            kind: kind.to_string(),
+            tag: tag.to_string(),
+            input_key: input_key.to_string(),
+            tagged_at_unix: now_unix(),
+        })
+    }
+
+    fn write_tag(&self, entry: &TagEntry) -> Result<()> {
+        let (kind, tag, input_key) = (&entry.kind, &entry.tag, &entry.input_key);
+        let path = self.tag_path(kind, tag)?;
+        let stored = self
+            .get(kind, input_key)?
+            .with_context(|| format!("Cannot tag {kind}:{input_key} as '{tag}': not stored"))?;
+        fs::create_dir_all(self.tags_dir().join(kind))?;
+        let tmp = self.tmp_dir().join(tmp_name("tag.json"));
+        fs::write(&tmp, serde_json::to_vec_pretty(entry)?)?;
+        atomic_rename(&tmp, &path)?;
+        self.link_tag(kind, tag, &stored.entry.blob_sha256)?;
+        audit::record(
+            &self.root,
+            AuditAction::Tag {
+                kind: kind.to_string(),
+                tag: tag.to_string(),
+                input_key: input_key.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove `tag`. Returns whether it existed.
+    pub fn untag(&self, kind: &str, tag: &str) -> Result<bool> {
+        let path = self.tag_path(kind, tag)?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        let _ = fs::remove_file(path.with_extension(""));
+        audit::record(
+            &self.root,
+            AuditAction::Untag {
+                kind: kind.to_string(),
+                tag: tag.to_string(),
+            },
+        );
+        Ok(true)
+    }
+
+    /// The entry `tag` points at; `None` when the tag does not exist.
+    ///
+    /// Fails when the tagged entry is gone, rather than pretending the tag
+    /// was never set.
+    pub fn resolve_tag(&self, kind: &str, tag: &str) -> Result<Option<StoredArtifact>> {
+        let path = self.tag_path(kind, tag)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let entry = read_tag(&path)?;
+        match self.get(kind, &entry.input_key)? {
+            Some(stored) => Ok(Some(stored)),
+            None => bail!(
+                "Tag '{}' points at {}:{}, which is no longer stored",
+                tag,
+                kind,
+                entry.input_key
+            ),
+        }
+    }
+
+    /// Materialize the entry `tag` points at (see [`materialize_to`](Self::materialize_to)).
+    pub fn materialize_tag(&self, kind: &str, tag: &str, dest: &Path) -> Result<IndexEntry> {
+        let stored = self
+            .resolve_tag(kind, tag)?
+            .with_context(|| format!("No tag '{tag}' for {kind}"))?;
+        self.materialize_to(kind, &stored.entry.input_key, dest)?;
+        Ok(stored.entry)
+    }
+
+    /// Tags of a kind, by name.
+    pub fn list_tags(&self, kind: &str) -> Result<Vec<TagEntry>> {
+        validate_kind(kind)?;
+        let dir = self.tags_dir().join(kind);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut out = vec![];
+        for ent in
+            fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let path = ent?.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                out.push(read_tag(&path)?);
+            }
+        }
+        out.sort_by(|a, b| a.tag.cmp(&b.tag));
+        Ok(out)
+    }
+
+    /// Input keys of `kind` that some tag points at.
+    fn tagged_keys(&self, kind: &str) -> Result<BTreeSet<String>> {
+        Ok(self
+            .list_tags(kind)?
+            .into_iter()
+            .map(|tag| tag.input_key)
+            .collect())
+    }
+
+    /// (Re)point the `tags/<kind>/<tag>` symlink at a blob.
+    fn link_tag(&self, kind: &str, tag: &str, sha256: &str) -> Result<()> {
+        validate_sha256(sha256)?;
+        let link = self.tags_dir().join(kind).join(tag);
+        let target = Path::new("../../blobs/sha256")
+            .join(&sha256[0..2])
+            .join(sha256);
+        let tmp = self.tags_dir().join(kind).join(tmp_name(".link"));
+        std::os::unix::fs::symlink(&target, &tmp)
+            .with_context(|| format!("Failed to create {}", tmp.display()))?;
+        fs::rename(&tmp, &link).with_context(|| format!("Failed to update {}", link.display()))
+    }
+
+    /// Re-point the symlinks of tags on `kind:input_key` after it was stored
+    /// again with new content.
+    fn refresh_tag_links(&self, kind: &str, input_key: &str, sha256: &str) -> Result<()> {
+        for tag in self.list_tags(kind)? {
+            if tag.input_key == input_key {
+                self.link_tag(kind, &tag.tag, sha256)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Best-effort garbage collection: remove blobs not referenced by any index entry.
     pub fn gc(&self) -> Result<usize> {
+        Ok(self.gc_report()?.removed_blobs)
+    }
+
+    /// Like [`ArtifactStore::gc`], also reporting how many bytes were freed.
+    pub fn gc_report(&self) -> Result<GcReport> {
         let referenced = self.collect_referenced_blobs()?;
 
+        let mut report = GcReport::default();
         let blobs_root = self.blobs_dir().join("sha256");
         if !blobs_root.exists() {
-            return Ok(0);
+            return Ok(report);
         }
 
-        let mut removed = 0usize;
         for ent in WalkDir::new(&blobs_root).into_iter().filter_map(Result::ok) {
             if !ent.file_type().is_file() {
                 continue;
@@ -578,20 +853,29 @@ impl ArtifactStore {
             if referenced.contains(&name) {
                 continue;
             }
+            let size = ent.metadata().map(|md| md.len()).unwrap_or(0);
             fs::remove_file(ent.path()).with_context(|| {
                 format!(
                     "Failed to remove unreferenced blob {}",
                     ent.path().display()
                 )
             })?;
-            removed += 1;
+            report.removed_blobs += 1;
+            report.freed_bytes += size;
+            audit::record(
+                &self.root,
+                AuditAction::BlobRemove {
+                    sha256: name,
+                    size_bytes: size,
+                },
+            );
         }
 
-        Ok(removed)
+        Ok(report)
     }
 
-    /// Prune index entries, keeping only the newest `keep_last` per kind.
-    /// Returns the number of index entries removed.
+    /// Prune index entries, keeping only the newest `keep_last` per kind
+    /// plus tagged ones. Returns the number of index entries removed.
     pub fn prune_keep_last(&self, keep_last: usize) -> Result<usize> {
         if keep_last == 0 {
             bail!("keep_last must be >= 1");
@@ -602,9 +886,10 @@ impl ArtifactStore {
 
         for kind in kinds {
             let entries = self.list_kind(&kind)?;
+            let tagged = self.tagged_keys(&kind)?;
             let mut to_remove = vec![];
             for (i, e) in entries.iter().enumerate() {
-                if i >= keep_last {
+                if i >= keep_last && !tagged.contains(&e.input_key) {
                     to_remove.push(e.input_key.clone());
                 }
             }
@@ -614,6 +899,7 @@ impl ArtifactStore {
                 if path.exists() {
                     fs::remove_file(&path)?;
                     removed += 1;
+                    self.audit_index_remove(&kind, &k);
                 }
             }
         }
@@ -621,6 +907,29 @@ impl ArtifactStore {
         Ok(removed)
     }
 
+    /// Prune one kind's index entries, keeping only the newest `keep_last`
+    /// plus tagged ones. Returns the number of index entries removed.
+    pub fn prune_kind_keep_last(&self, kind: &str, keep_last: usize) -> Result<usize> {
+        if keep_last == 0 {
+            bail!("keep_last must be >= 1");
+        }
+
+        let tagged = self.tagged_keys(kind)?;
+        let mut removed = 0usize;
+        for entry in self.list_kind(kind)?.into_iter().skip(keep_last) {
+            if tagged.contains(&entry.input_key) {
+                continue;
+            }
+            let path = self.index_path(kind, &entry.input_key)?;
+            if path.exists() {
+                fs::remove_file(&path)?;
+                removed += 1;
+                self.audit_index_remove(kind, &entry.input_key);
+            }
+        }
+        Ok(removed)
+    }
+
     /// Return basic store statistics.
     pub fn status(&self) -> Result<StoreStatus> {
         let referenced = self.collect_referenced_blobs()?;
@@ -654,6 +963,71 @@ impl ArtifactStore {
         })
     }
 
+    /// Check every index entry against its blob: present, of the recorded
+    /// size and, with `rehash`, of the recorded sha256. Unreadable index
+    /// files are reported too. Nothing is modified.
+    pub fn verify(&self, rehash: bool) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        for kind in self.list_kinds()? {
+            let dir = self.kind_dir(&kind)?;
+            for ent in
+                fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?
+            {
+                let path = ent?.path();
+                if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                    continue;
+                }
+                report.checked_entries += 1;
+                let entry = fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| Ok(serde_json::from_slice::<IndexEntry>(&bytes)?));
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        report.problems.push(format!(
+                            "{}: unreadable index: {:#}",
+                            path.display(),
+                            err
+                        ));
+                        continue;
+                    }
+                };
+                if let Some(problem) = self.verify_entry(&entry, rehash) {
+                    report
+                        .problems
+                        .push(format!("{}: {}", path.display(), problem));
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn verify_entry(&self, entry: &IndexEntry, rehash: bool) -> Option<String> {
+        let Ok(blob) = self.blob_path(&entry.blob_sha256) else {
+            return Some(format!("invalid blob sha256 '{}'", entry.blob_sha256));
+        };
+        let size = match fs::metadata(&blob) {
+            Ok(md) => md.len(),
+            Err(_) => return Some(format!("blob {} is missing", entry.blob_sha256)),
+        };
+        if size != entry.size_bytes {
+            return Some(format!(
+                "blob {} is {} bytes, index records {}",
+                entry.blob_sha256, size, entry.size_bytes
+            ));
+        }
+        if rehash {
+            match sha256_file(&blob) {
+                Ok((sha, _)) if sha == entry.blob_sha256 => {}
+                Ok((sha, _)) => {
+                    return Some(format!("blob {} hashes to {}", entry.blob_sha256, sha))
+                }
+                Err(err) => return Some(format!("{:#}", err)),
+            }
+        }
+        None
+    }
+
     fn write_index(&self, kind: &str, input_key: &str, entry: &IndexEntry) -> Result<()> {
         let dir = self.kind_dir(kind)?;
         fs::create_dir_all(&dir)?;
@@ -663,7 +1037,35 @@ impl ArtifactStore {
         let tmp = self.tmp_dir().join(tmp_name("index.json"));
         fs::write(&tmp, bytes)?;
         atomic_rename(&tmp, &path)?;
-        Ok(())
+        audit::record(
+            &self.root,
+            AuditAction::IndexUpdate {
+                kind: kind.to_string(),
+                input_key: input_key.to_string(),
+                blob_sha256: entry.blob_sha256.clone(),
+            },
+        );
+        self.refresh_tag_links(kind, input_key, &entry.blob_sha256)
+    }
+
+    fn audit_blob_write(&self, sha256: &str, size_bytes: u64) {
+        audit::record(
+            &self.root,
+            AuditAction::BlobWrite {
+                sha256: sha256.to_string(),
+                size_bytes,
+            },
+        );
+    }
+
+    fn audit_index_remove(&self, kind: &str, input_key: &str) {
+        audit::record(
+            &self.root,
+            AuditAction::IndexRemove {
+                kind: kind.to_string(),
+                input_key: input_key.to_string(),
+            },
+        );
     }
 
     fn collect_referenced_blobs(&self) -> Result<BTreeSet<String>> {
@@ -731,17 +1133,18 @@ impl ArtifactStore {
             .with_context(|| format!("Failed to create lock file: {}", lock_path.display()))?;
 
         if lock_file.try_lock_exclusive().is_err() {
-            drop(lock_file);
-            return Err(anyhow::anyhow!(
-                "Artifact store key is locked by another process: {}",
+            eprintln!(
+                "  Waiting for another process storing {}/{} ({})",
+                kind,
+                input_key,
                 lock_path.display()
-            ));
+            );
+            lock_file.lock_exclusive().with_context(|| {
+                format!("Failed to lock artifact store key: {}", lock_path.display())
+            })?;
         }
 
-        Ok(ArtifactLock {
-            _file: lock_file,
-            path: lock_path,
-        })
+        Ok(ArtifactLock { _file: lock_file })
     }
 }
 
@@ -752,25 +1155,19 @@ impl ArtifactStore {
 ///
 /// Example:
 /// - `.../LevitateOS/leviso` -> `.../LevitateOS/.artifacts/out/levitate`
-pub fn central_output_dir_for_distro(base_dir: &Path) -> PathBuf {
-    // In the monorepo, distro crates live at `<repo>/<DistroDir>`.
-    // Use the parent as the repo root (standalone support can be added later).
-    let repo_root = base_dir.parent().unwrap_or(base_dir);
+pub fn central_output_dir_for_distro(base_dir: &Path) -> Result<PathBuf> {
+    let layout = crate::repo_layout::RepoLayout::for_distro_dir(base_dir);
+    let repo_root = layout.root();
     let distro_name = base_dir
         .file_name()
         .and_then(|s| s.to_str())
         .unwrap_or("distro");
-    let output_name = match distro_name {
-        "leviso" => "levitate",
-        "AcornOS" => "acorn",
-        "IuppiterOS" => "iuppiter",
-        "RalphOS" => "ralph",
-        other => other,
-    };
-    repo_root
-        .join(DEFAULT_STORE_DIR)
+    let output_name = crate::repo_config::RepoConfig::load(repo_root)?
+        .resolve_distro_alias(distro_name)
+        .unwrap_or_else(|| distro_name.to_string());
+    Ok(crate::repo_config::artifacts_root(repo_root)?
         .join(DEFAULT_OUTPUT_SUBDIR)
-        .join(output_name)
+        .join(output_name))
 }
 
 /// Read an input key file (typically `output/.<artifact>-inputs.hash`) as a trimmed string.
@@ -874,18 +1271,26 @@ pub struct StoreStatus {
     pub referenced_bytes: u64,
 }
 
-/// RAII guard: unlocks and removes the lock file on drop.
+/// Result of [`ArtifactStore::verify`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub checked_entries: usize,
+    /// One line per broken entry, naming its index file.
+    pub problems: Vec<String>,
+}
+
+/// Result of [`ArtifactStore::gc_report`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed_blobs: usize,
+    pub freed_bytes: u64,
+}
+
+/// RAII guard: the lock is released when the file is closed on drop. The
+/// file itself stays, since a waiter may still hold it open.
 #[derive(Debug)]
 struct ArtifactLock {
-    #[allow(dead_code)]
     _file: File,
-    path: PathBuf,
-}
-
-impl Drop for ArtifactLock {
-    fn drop(&mut self) {
-        let _ = fs::remove_file(&self.path);
-    }
 }
 
 fn now_unix() -> u64 {
@@ -963,6 +1368,25 @@ fn validate_key(key: &str) -> Result<()> {
     Ok(())
 }
 
+fn validate_tag(tag: &str) -> Result<()> {
+    let valid = !tag.is_empty()
+        && !tag.starts_with('.')
+        && !tag.ends_with(".json")
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        bail!("invalid artifact tag '{tag}': use letters, digits, '-', '_' and '.'");
+    }
+    Ok(())
+}
+
+fn read_tag(path: &Path) -> Result<TagEntry> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read tag {}", path.display()))?;
+    serde_json::from_slice(&bytes)
+        .with_context(|| format!("Failed to parse tag {}", path.display()))
+}
+
 fn validate_sha256(sha256: &str) -> Result<()> {
     if !is_hex_64(sha256) {
         bail!("invalid sha256: {sha256}");
@@ -1019,7 +1443,7 @@ fn hardlink_or_copy(src: &Path, dest: &Path) -> Result<()> {
     Ok(())
 }
 
-fn materialize_tar_zst_dir(blob: &Path, dest_dir: &Path) -> Result<()> {
+fn materialize_tar_dir(blob: &Path, codec: Codec, dest_dir: &Path) -> Result<()> {
     if dest_dir.exists() {
         fs::remove_dir_all(dest_dir)
             .with_context(|| format!("Failed to remove {}", dest_dir.display()))?;
@@ -1030,13 +1454,7 @@ fn materialize_tar_zst_dir(blob: &Path, dest_dir: &Path) -> Result<()> {
     let tmp = parent.join(tmp_name("extract"));
     fs::create_dir_all(&tmp)?;
 
-    // Extract
-    let f = File::open(blob)?;
-    let decoder = zstd::stream::Decoder::new(f)?;
-    let mut archive = tar::Archive::new(decoder);
-    archive
-        .unpack(&tmp)
-        .with_context(|| format!("Failed to unpack {}", blob.display()))?;
+    unpack_tar(blob, codec, &tmp)?;
 
     // Atomic-ish: rename into place.
     // (Not fully atomic across filesystems, but tmp and dest are in same parent.)
@@ -1054,11 +1472,46 @@ fn materialize_tar_zst_dir(blob: &Path, dest_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-fn create_tar_zst(src_dir: &Path, out_path: &Path) -> Result<()> {
+/// Unpack the `codec`-compressed tarball `blob` into `dest_dir`, keeping the
+/// recorded modes (setuid and sticky bits included) and, when running as
+/// root, the recorded ownership.
+fn unpack_tar(blob: &Path, codec: Codec, dest_dir: &Path) -> Result<()> {
+    let f = File::open(blob)?;
+    let mut archive = tar::Archive::new(compression::decoder(codec, f)?);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(unsafe { libc::geteuid() } == 0);
+    archive
+        .unpack(dest_dir)
+        .with_context(|| format!("Failed to unpack {}", blob.display()))?;
+    archive
+        .into_inner()
+        .finish()
+        .with_context(|| format!("Failed to unpack {}", blob.display()))
+}
+
+/// Owner ids recorded in a stored tarball.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TarOwnership {
+    /// Every entry is owned by root, whoever built the tree.
+    Root,
+    /// Every entry keeps its uid and gid.
+    Preserve,
+}
+
+/// Write `src_dir` as a deterministic tarball compressed with `compression`.
+pub fn create_tar(src_dir: &Path, out_path: &Path, compression: Compression) -> Result<()> {
+    write_tar(src_dir, out_path, compression, TarOwnership::Root)
+}
+
+fn write_tar(
+    src_dir: &Path,
+    out_path: &Path,
+    compression: Compression,
+    ownership: TarOwnership,
+) -> Result<()> {
     let out = File::create(out_path)
         .with_context(|| format!("Failed to create {}", out_path.display()))?;
-    let encoder = zstd::stream::Encoder::new(out, 3)?;
-    let mut builder = TarBuilder::new(encoder);
+    let mut builder = TarBuilder::new(compression.encoder(out)?);
 
     // Collect paths deterministically.
     let mut entries: Vec<PathBuf> = vec![];
@@ -1093,8 +1546,7 @@ fn create_tar_zst(src_dir: &Path, out_path: &Path) -> Result<()> {
             header.set_entry_type(tar::EntryType::Directory);
             header.set_size(0);
             header.set_mtime(0);
-            header.set_uid(0);
-            header.set_gid(0);
+            set_owner(&mut header, &md, ownership);
             #[cfg(unix)]
             {
                 use std::os::unix::fs::PermissionsExt;
@@ -1116,8 +1568,7 @@ fn create_tar_zst(src_dir: &Path, out_path: &Path) -> Result<()> {
             header.set_entry_type(tar::EntryType::Symlink);
             header.set_size(0);
             header.set_mtime(0);
-            header.set_uid(0);
-            header.set_gid(0);
+            set_owner(&mut header, &md, ownership);
             #[cfg(unix)]
             {
                 use std::os::unix::fs::PermissionsExt;
@@ -1139,8 +1590,7 @@ fn create_tar_zst(src_dir: &Path, out_path: &Path) -> Result<()> {
             header.set_entry_type(tar::EntryType::Regular);
             header.set_size(md.len());
             header.set_mtime(0);
-            header.set_uid(0);
-            header.set_gid(0);
+            set_owner(&mut header, &md, ownership);
             #[cfg(unix)]
             {
                 use std::os::unix::fs::PermissionsExt;
@@ -1163,6 +1613,22 @@ fn create_tar_zst(src_dir: &Path, out_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Record the owner of `md` in `header`, or root for [`TarOwnership::Root`].
+fn set_owner(header: &mut tar::Header, md: &fs::Metadata, ownership: TarOwnership) {
+    let (uid, gid) = match ownership {
+        TarOwnership::Root => (0, 0),
+        #[cfg(unix)]
+        TarOwnership::Preserve => {
+            use std::os::unix::fs::MetadataExt;
+            (md.uid(), md.gid())
+        }
+        #[cfg(not(unix))]
+        TarOwnership::Preserve => (0, 0),
+    };
+    header.set_uid(uid.into());
+    header.set_gid(gid.into());
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1192,6 +1658,130 @@ mod tests {
         assert_eq!(out, b"hello");
     }
 
+    #[test]
+    fn put_waits_for_concurrent_writer() {
+        let tmp = TempDir::new().unwrap();
+        let store = ArtifactStore::open(tmp.path()).unwrap();
+        let src = tmp.path().join("src.bin");
+        fs::write(&src, b"hello").unwrap();
+
+        let held = store.acquire_lock("rootfs_erofs", "deadbeef").unwrap();
+        let writer = {
+            let store = store.clone();
+            let src = src.clone();
+            std::thread::spawn(move || {
+                store.put_blob_file("rootfs_erofs", "deadbeef", &src, BTreeMap::new())
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        assert!(!writer.is_finished());
+        drop(held);
+        writer.join().unwrap().unwrap();
+        assert!(store.get("rootfs_erofs", "deadbeef").unwrap().is_some());
+        // The lock file is kept for later writers.
+        assert!(store
+            .lock_path("rootfs_erofs", "deadbeef")
+            .unwrap()
+            .is_file());
+    }
+
+    #[test]
+    fn verify_reports_broken_entries() {
+        let tmp = TempDir::new().unwrap();
+        let repo = tmp.path().join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        let store = ArtifactStore::open(&repo).unwrap();
+        let src = tmp.path().join("src.bin");
+        fs::write(&src, b"hello").unwrap();
+        let sha = store
+            .put_blob_file("rootfs_erofs", "k1", &src, BTreeMap::new())
+            .unwrap();
+        assert_eq!(store.verify(true).unwrap().problems, Vec::<String>::new());
+
+        // Same size, different bytes: only a rehash notices.
+        let blob = store.blob_path(&sha).unwrap();
+        fs::remove_file(&blob).unwrap();
+        fs::write(&blob, b"jello").unwrap();
+        assert!(store.verify(false).unwrap().problems.is_empty());
+        let report = store.verify(true).unwrap();
+        assert_eq!(report.checked_entries, 1);
+        assert!(report.problems[0].contains("hashes to"), "{:?}", report);
+
+        fs::remove_file(&blob).unwrap();
+        fs::write(
+            store.kind_dir("rootfs_erofs").unwrap().join("bad.json"),
+            "{",
+        )
+        .unwrap();
+        let report = store.verify(false).unwrap();
+        assert_eq!(report.checked_entries, 2);
+        assert_eq!(report.problems.len(), 2, "{:?}", report);
+        assert!(report.problems.iter().any(|p| p.contains("is missing")));
+        assert!(report
+            .problems
+            .iter()
+            .any(|p| p.contains("unreadable index")));
+    }
+
+    #[test]
+    fn tags_resolve_and_survive_pruning() {
+        let tmp = TempDir::new().unwrap();
+        let repo = tmp.path().join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        let store = ArtifactStore::open(&repo).unwrap();
+        let kind = "release-live-boot-iso";
+
+        let src = tmp.path().join("src.iso");
+        for (key, body) in [("k1", "one"), ("k2", "two"), ("k3", "three")] {
+            fs::write(&src, body).unwrap();
+            store
+                .put_blob_file(kind, key, &src, BTreeMap::new())
+                .unwrap();
+        }
+        assert!(store.tag(kind, "missing", "nightly").is_err());
+        assert!(store.tag(kind, "k1", "../escape").is_err());
+        store.tag(kind, "k1", "nightly").unwrap();
+        assert_eq!(
+            store
+                .resolve_tag(kind, "stable")
+                .unwrap()
+                .map(|s| s.entry.input_key),
+            None
+        );
+
+        // Make k3 the newest and k2 the only prunable entry.
+        for (key, age) in [("k2", 10), ("k3", 20)] {
+            let mut entry = store.get(kind, key).unwrap().unwrap().entry;
+            entry.stored_at_unix += age;
+            store.write_index(kind, key, &entry).unwrap();
+        }
+        assert_eq!(store.prune_kind_keep_last(kind, 1).unwrap(), 1);
+        assert!(store.get(kind, "k2").unwrap().is_none());
+
+        let stored = store.resolve_tag(kind, "nightly").unwrap().unwrap();
+        assert_eq!(stored.entry.input_key, "k1");
+        assert_eq!(
+            fs::read(store.root().join("tags").join(kind).join("nightly")).unwrap(),
+            b"one"
+        );
+        let dest = tmp.path().join("out.iso");
+        store.materialize_tag(kind, "nightly", &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"one");
+
+        fs::write(&src, "one again").unwrap();
+        store
+            .put_blob_file(kind, "k1", &src, BTreeMap::new())
+            .unwrap();
+        assert_eq!(
+            fs::read(store.root().join("tags").join(kind).join("nightly")).unwrap(),
+            b"one again"
+        );
+
+        assert_eq!(store.list_tags(kind).unwrap().len(), 1);
+        assert!(store.untag(kind, "nightly").unwrap());
+        assert!(store.materialize_tag(kind, "nightly", &dest).is_err());
+    }
+
     #[test]
     fn dir_tar_zst_roundtrip() {
         let tmp = TempDir::new().unwrap();
@@ -1207,7 +1797,7 @@ mod tests {
         fs::write(src_dir.join("boot/vmlinuz"), b"kernel").unwrap();
 
         store
-            .put_dir_as_tar_zst(kind, key, &src_dir, BTreeMap::new())
+            .put_dir_as_tar(kind, key, &src_dir, BTreeMap::new())
             .unwrap();
 
         let dest_dir = tmp.path().join("out-staging");
@@ -1215,4 +1805,36 @@ mod tests {
         let bytes = fs::read(dest_dir.join("boot/vmlinuz")).unwrap();
         assert_eq!(bytes, b"kernel");
     }
+
+    #[test]
+    fn dir_tar_follows_store_compression() {
+        if !crate::process::exists("gzip") {
+            eprintln!("skipping: gzip not installed");
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let zstd_store = ArtifactStore::open(&tmp.path().join("repo")).unwrap();
+        let gzip_store = zstd_store
+            .clone()
+            .with_compression(Compression::with_default_level(Codec::Gzip));
+
+        let src_dir = tmp.path().join("staging");
+        fs::create_dir_all(src_dir.join("boot")).unwrap();
+        fs::write(src_dir.join("boot/vmlinuz"), b"kernel").unwrap();
+        fs::create_dir_all(src_dir.join("lib/modules/6.1.0")).unwrap();
+        gzip_store
+            .put_kernel_payload("gz", &src_dir, BTreeMap::new())
+            .unwrap();
+        let stored = gzip_store.get("kernel_payload", "gz").unwrap().unwrap();
+        assert_eq!(stored.entry.format, ArtifactFormat::TarGz);
+        assert_eq!(
+            Codec::detect_file(&stored.blob_path).unwrap(),
+            Some(Codec::Gzip)
+        );
+
+        // A store writing zstd still reads what was stored as gzip.
+        let restored = tmp.path().join("restored");
+        zstd_store.restore_kernel_payload("gz", &restored).unwrap();
+        assert_eq!(fs::read(restored.join("boot/vmlinuz")).unwrap(), b"kernel");
+    }
 }
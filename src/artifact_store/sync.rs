@@ -0,0 +1,321 @@
+//! Replication from another local store.
+//!
+//! A developer with a workstation and a laptop sharing a LAN mount can
+//! carry warm caches along: [`ArtifactStore::sync_from`] copies the index
+//! entries, blobs and tags the other store has and this one lacks. Blobs are
+//! content-addressed, so one already present is never copied again, and
+//! every copied blob is re-hashed before it lands.
+//!
+//! When both stores hold an entry for the same kind and input key with
+//! different blobs, or a tag of the same name pointing at different keys,
+//! [`SyncConflict`] decides which side wins.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use super::{atomic_rename, sha256_file, tmp_name, validate_kind, ArtifactStore, IndexEntry};
+
+/// Which entry wins when both stores have one under the same name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncConflict {
+    /// Never replace a local entry or tag; report the conflict.
+    #[default]
+    KeepLocal,
+    /// Take the other store's entry when it was stored (or tagged) later.
+    PreferNewer,
+    /// Always take the other store's entry.
+    PreferRemote,
+}
+
+impl SyncConflict {
+    fn takes_remote(self, local_unix: u64, remote_unix: u64) -> bool {
+        match self {
+            Self::KeepLocal => false,
+            Self::PreferNewer => remote_unix > local_unix,
+            Self::PreferRemote => true,
+        }
+    }
+}
+
+/// Which of the other store's entries to copy, and how.
+#[derive(Debug, Clone, Default)]
+pub struct SyncFilter {
+    /// Only the newest entries per kind; tagged entries always qualify.
+    pub keep_last: Option<usize>,
+    /// Only entries stored at or after this time.
+    pub stored_since_unix: Option<u64>,
+    pub conflict: SyncConflict,
+    /// Report what would be copied without writing anything.
+    pub dry_run: bool,
+}
+
+impl SyncFilter {
+    fn selects(&self, position: usize, entry: &IndexEntry, tagged: bool) -> bool {
+        (tagged || self.keep_last.is_none_or(|keep| position < keep))
+            && self
+                .stored_since_unix
+                .is_none_or(|since| entry.stored_at_unix >= since)
+    }
+}
+
+/// What [`ArtifactStore::sync_from`] did (or would do, for a dry run).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Entries this store did not have.
+    pub added_entries: usize,
+    /// Local entries replaced under [`SyncConflict`].
+    pub replaced_entries: usize,
+    pub copied_blobs: usize,
+    pub copied_bytes: u64,
+    pub copied_tags: usize,
+    /// `kind:input_key` (or `kind@tag`) kept local on a conflict.
+    pub conflicts: Vec<String>,
+    /// `kind:input_key` entries whose blob the other store no longer has.
+    pub missing_blobs: Vec<String>,
+}
+
+impl ArtifactStore {
+    /// Copy the entries of `kinds` (every kind when empty) selected by
+    /// `filter` from `other`, then the tags pointing at entries both stores
+    /// now share. Entries and tags carry over with their original times
+    /// and metadata, plus a `synced_from` note on each entry.
+    pub fn sync_from(
+        &self,
+        other: &ArtifactStore,
+        kinds: &[String],
+        filter: &SyncFilter,
+    ) -> Result<SyncReport> {
+        if same_dir(self.root(), other.root()) {
+            bail!(
+                "Cannot sync the artifact store at {} with itself",
+                self.root().display()
+            );
+        }
+        let kinds = if kinds.is_empty() {
+            other.list_kinds()?
+        } else {
+            kinds.to_vec()
+        };
+
+        let mut report = SyncReport::default();
+        for kind in &kinds {
+            validate_kind(kind)?;
+            let tagged = other.tagged_keys(kind)?;
+            let mut shared = BTreeSet::new();
+            for (position, entry) in other.list_kind(kind)?.iter().enumerate() {
+                if !filter.selects(position, entry, tagged.contains(&entry.input_key)) {
+                    continue;
+                }
+                if self
+                    .sync_entry(other, entry, filter, &mut report)
+                    .with_context(|| {
+                        format!(
+                            "syncing {}:{} from {}",
+                            kind,
+                            entry.input_key,
+                            other.root().display()
+                        )
+                    })?
+                {
+                    shared.insert(entry.input_key.clone());
+                }
+            }
+            for tag in other.list_tags(kind)? {
+                if !shared.contains(&tag.input_key) {
+                    continue;
+                }
+                let local_path = self.tag_path(kind, &tag.tag)?;
+                if local_path.exists() {
+                    let local = super::read_tag(&local_path)?;
+                    if local.input_key == tag.input_key {
+                        continue;
+                    }
+                    if !filter
+                        .conflict
+                        .takes_remote(local.tagged_at_unix, tag.tagged_at_unix)
+                    {
+                        report.conflicts.push(format!("{}@{}", kind, tag.tag));
+                        continue;
+                    }
+                }
+                if !filter.dry_run {
+                    self.write_tag(&tag)?;
+                }
+                report.copied_tags += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Bring `entry` of `other` into this store. Returns whether this store
+    /// has (or, for a dry run, would have) the same entry afterwards.
+    fn sync_entry(
+        &self,
+        other: &ArtifactStore,
+        entry: &IndexEntry,
+        filter: &SyncFilter,
+        report: &mut SyncReport,
+    ) -> Result<bool> {
+        let label = format!("{}:{}", entry.kind, entry.input_key);
+        let replacing = match self.get(&entry.kind, &entry.input_key)? {
+            None => false,
+            Some(local) if local.entry.blob_sha256 == entry.blob_sha256 => {
+                if local.blob_path.is_file() {
+                    return Ok(true);
+                }
+                // Same entry, blob lost locally: copy the blob back only.
+                false
+            }
+            Some(local) => {
+                if !filter
+                    .conflict
+                    .takes_remote(local.entry.stored_at_unix, entry.stored_at_unix)
+                {
+                    report.conflicts.push(label);
+                    return Ok(false);
+                }
+                true
+            }
+        };
+        let source = other.blob_path(&entry.blob_sha256)?;
+        if !source.is_file() {
+            report.missing_blobs.push(label);
+            return Ok(false);
+        }
+
+        let _lock = (!filter.dry_run)
+            .then(|| self.acquire_lock(&entry.kind, &entry.input_key))
+            .transpose()?;
+        let blob_present = self.blob_path(&entry.blob_sha256)?.is_file();
+        if !blob_present {
+            if !filter.dry_run {
+                self.copy_blob(&source, &entry.blob_sha256)?;
+            }
+            report.copied_blobs += 1;
+            report.copied_bytes += entry.size_bytes;
+        }
+        let local_entry = self.get(&entry.kind, &entry.input_key)?;
+        if local_entry.is_some_and(|local| local.entry.blob_sha256 == entry.blob_sha256) {
+            return Ok(true);
+        }
+        if replacing {
+            report.replaced_entries += 1;
+        } else {
+            report.added_entries += 1;
+        }
+        if !filter.dry_run {
+            let mut entry = entry.clone();
+            entry.meta.insert(
+                "synced_from".to_string(),
+                serde_json::json!(other.root().display().to_string()),
+            );
+            self.write_index(&entry.kind, &entry.input_key, &entry)?;
+        }
+        Ok(true)
+    }
+
+    /// Copy `source` in as the blob `sha256`, checking its content first.
+    fn copy_blob(&self, source: &Path, sha256: &str) -> Result<()> {
+        let blob_path = self.blob_path(sha256)?;
+        let tmp = self
+            .tmp_dir()
+            .join(tmp_name(&format!("sync-{}", &sha256[..16])));
+        fs::copy(source, &tmp)
+            .with_context(|| format!("Failed to copy {} to {}", source.display(), tmp.display()))?;
+        let (actual, size_bytes) = sha256_file(&tmp)?;
+        if actual != sha256 {
+            let _ = fs::remove_file(&tmp);
+            bail!(
+                "Blob {} does not match its hash (got {})\n\
+Remediation: run `distro-builder clean --store-unreferenced` on the other store and rebuild the artifact there.",
+                source.display(),
+                actual
+            );
+        }
+        atomic_rename(&tmp, &blob_path)?;
+        self.audit_blob_write(sha256, size_bytes);
+        Ok(())
+    }
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    fn put(store: &ArtifactStore, dir: &Path, kind: &str, key: &str, body: &str) {
+        let src = dir.join("src");
+        fs::write(&src, body).unwrap();
+        store
+            .put_blob_file(kind, key, &src, BTreeMap::new())
+            .unwrap();
+    }
+
+    fn set_stored_at(store: &ArtifactStore, kind: &str, key: &str, unix: u64) {
+        let mut entry = store.get(kind, key).unwrap().unwrap().entry;
+        entry.stored_at_unix = unix;
+        store.write_index(kind, key, &entry).unwrap();
+    }
+
+    #[test]
+    fn sync_copies_missing_entries_and_applies_conflict_rules() {
+        let tmp = TempDir::new().unwrap();
+        let laptop = ArtifactStore::open(&tmp.path().join("laptop")).unwrap();
+        let workstation = ArtifactStore::open(&tmp.path().join("workstation")).unwrap();
+        let kind = "rootfs_erofs";
+        put(&workstation, tmp.path(), kind, "k1", "one");
+        put(&workstation, tmp.path(), kind, "k2", "two");
+        workstation.tag(kind, "k1", "nightly").unwrap();
+        put(&laptop, tmp.path(), kind, "k2", "two, laptop build");
+        set_stored_at(&laptop, kind, "k2", 100);
+        set_stored_at(&workstation, kind, "k2", 200);
+
+        assert!(laptop
+            .sync_from(&laptop, &[], &SyncFilter::default())
+            .is_err());
+        let dry_run = SyncFilter {
+            dry_run: true,
+            ..Default::default()
+        };
+        let planned = laptop.sync_from(&workstation, &[], &dry_run).unwrap();
+        assert_eq!(planned.added_entries, 1);
+        assert_eq!(planned.copied_tags, 1);
+        assert_eq!(planned.conflicts, vec![format!("{kind}:k2")]);
+        assert!(laptop.get(kind, "k1").unwrap().is_none());
+
+        let report = laptop
+            .sync_from(&workstation, &[], &SyncFilter::default())
+            .unwrap();
+        assert_eq!(report, planned);
+        let dest = tmp.path().join("out");
+        laptop.materialize_tag(kind, "nightly", &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"one");
+        let synced = laptop.get(kind, "k1").unwrap().unwrap().entry;
+        assert!(synced.meta.contains_key("synced_from"));
+
+        let prefer_newer = SyncFilter {
+            conflict: SyncConflict::PreferNewer,
+            ..Default::default()
+        };
+        let report = laptop
+            .sync_from(&workstation, &[kind.to_string()], &prefer_newer)
+            .unwrap();
+        assert_eq!(report.replaced_entries, 1);
+        assert_eq!(report.copied_blobs, 1);
+        laptop.materialize_to(kind, "k2", &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"two");
+
+        let again = laptop.sync_from(&workstation, &[], &prefer_newer).unwrap();
+        assert_eq!(again, SyncReport::default());
+    }
+}
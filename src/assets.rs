@@ -0,0 +1,171 @@
+//! Default overlay and profile files shipped inside the binary.
+//!
+//! Larger shell payloads live as real files under `assets/` in this crate so
+//! they can be edited and linted as shell, and are compiled in with
+//! `include_str!`. At build time an [`AssetResolver`] looks each asset up in
+//! order:
+//!
+//! 1. `distro-variants/<distro>/assets/<name>` (variant override)
+//! 2. `distro-builder/assets/<name>` in the checkout (repo default, picked
+//!    up without recompiling)
+//! 3. the embedded copy
+//!
+//! Asset names are relative paths such as `live-overlay/systemd/live-help`.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+macro_rules! embed {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/", $name)))),*]
+    };
+}
+
+/// Every embedded asset as `(name, content)`.
+const EMBEDDED: &[(&str, &str)] = embed![
+    "live-overlay/common/live-persist",
+    "live-overlay/openrc/00-volatile-log.start",
+    "live-overlay/openrc/01-efivarfs.start",
+    "live-overlay/openrc/live-persist.initd",
+    "live-overlay/openrc/serial-autologin",
+    "live-overlay/systemd/00-live-test.sh",
+    "live-overlay/systemd/20-live-shell-ux.sh",
+    "live-overlay/systemd/lang.sh",
+    "live-overlay/systemd/live-help",
+    "live-overlay/systemd/live-net-setup",
+    "live-overlay/systemd/live-net-setup.service",
+    "live-overlay/systemd/live-persist.service",
+    "live-overlay/systemd/live-shutdown-cleanup",
+    "live-overlay/systemd/live-shutdown-cleanup.service",
+    "live-overlay/systemd/serial-autologin",
+];
+
+/// Directory name searched for overrides in variant and repo directories.
+pub const ASSETS_DIR: &str = "assets";
+
+/// Embedded content for `name`, ignoring overrides.
+pub fn embedded(name: &str) -> Option<&'static str> {
+    EMBEDDED
+        .iter()
+        .find(|(embedded_name, _)| *embedded_name == name)
+        .map(|(_, content)| *content)
+}
+
+/// Names of all embedded assets.
+pub fn embedded_names() -> impl Iterator<Item = &'static str> {
+    EMBEDDED.iter().map(|(name, _)| *name)
+}
+
+/// Where a resolved asset came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetSource {
+    File(PathBuf),
+    Embedded,
+}
+
+/// Looks assets up in override directories before the embedded copy.
+#[derive(Debug, Clone, Default)]
+pub struct AssetResolver {
+    search_dirs: Vec<PathBuf>,
+}
+
+impl AssetResolver {
+    /// Resolver that only serves embedded assets.
+    pub fn embedded_only() -> Self {
+        Self::default()
+    }
+
+    /// Standard resolution order for `distro_id` in the checkout at `repo_root`.
+    pub fn for_variant(repo_root: &Path, distro_id: &str) -> Self {
+        let layout = crate::repo_layout::layout_or_default(repo_root);
+        Self::default()
+            .with_dir(layout.variant_dir(distro_id).join(ASSETS_DIR))
+            .with_dir(layout.builder_dir().join(ASSETS_DIR))
+    }
+
+    /// Append an override directory; earlier directories win.
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_dirs.push(dir.into());
+        self
+    }
+
+    /// Find `name`, returning its content and origin.
+    pub fn resolve(&self, name: &str) -> Result<(String, AssetSource)> {
+        validate_name(name)?;
+        for dir in &self.search_dirs {
+            let candidate = dir.join(name);
+            if candidate.is_file() {
+                let content = fs::read_to_string(&candidate).with_context(|| {
+                    format!("Failed to read asset override {}", candidate.display())
+                })?;
+                return Ok((content, AssetSource::File(candidate)));
+            }
+        }
+        match embedded(name) {
+            Some(content) => Ok((content.to_string(), AssetSource::Embedded)),
+            None => bail!(
+                "asset '{}' is not embedded and no override exists in: {}",
+                name,
+                self.search_dirs
+                    .iter()
+                    .map(|d| d.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Content of `name` after override resolution.
+    pub fn load(&self, name: &str) -> Result<String> {
+        self.resolve(name).map(|(content, _)| content)
+    }
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let path = Path::new(name);
+    if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        bail!("invalid asset name '{}'", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolution_order() {
+        let temp = TempDir::new().unwrap();
+        let variant = temp.path().join("variant");
+        let repo = temp.path().join("repo");
+        let name = "live-overlay/systemd/live-help";
+        let resolver = AssetResolver::embedded_only()
+            .with_dir(&variant)
+            .with_dir(&repo);
+
+        let (content, source) = resolver.resolve(name).unwrap();
+        assert_eq!(source, AssetSource::Embedded);
+        assert_eq!(content, embedded(name).unwrap());
+
+        fs::create_dir_all(repo.join("live-overlay/systemd")).unwrap();
+        fs::write(repo.join(name), "repo").unwrap();
+        assert_eq!(resolver.load(name).unwrap(), "repo");
+
+        fs::create_dir_all(variant.join("live-overlay/systemd")).unwrap();
+        fs::write(variant.join(name), "variant").unwrap();
+        let (content, source) = resolver.resolve(name).unwrap();
+        assert_eq!(content, "variant");
+        assert_eq!(source, AssetSource::File(variant.join(name)));
+    }
+
+    #[test]
+    fn test_rejects_unknown_and_escaping_names() {
+        let resolver = AssetResolver::embedded_only();
+        assert!(resolver.load("live-overlay/nope").is_err());
+        assert!(resolver.load("../Cargo.toml").is_err());
+        assert!(resolver.load("/etc/passwd").is_err());
+        assert!(embedded_names().all(|name| embedded(name).is_some()));
+    }
+}
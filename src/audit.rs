@@ -0,0 +1,229 @@
+//! Append-only audit log of artifact store and output mutations.
+//!
+//! Every blob write, index update or removal, tag change, garbage
+//! collection and run directory creation or expiry appends one JSON line to
+//! `<artifacts root>/audit/audit.jsonl`, recording who (user, host, pid),
+//! when and what:
+//!
+//! ```json
+//! {"at_unix":1760600000,"user":"dev","host":"buildbox","pid":4242,"action":"index_update","kind":"rootfs_erofs","input_key":"3f1c…","blob_sha256":"9ab0…"}
+//! ```
+//!
+//! The log exists for debugging cache corruption on shared machines, so it
+//! never fails the operation it describes: a log that cannot be written is
+//! reported as a warning. Lines are appended with one `write` under an
+//! exclusive lock, so concurrent builders do not interleave them.
+
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::artifact_store::DEFAULT_OUTPUT_SUBDIR;
+
+/// Audit directory under the artifacts root.
+pub const AUDIT_DIR: &str = "audit";
+
+/// Log file inside [`AUDIT_DIR`].
+pub const AUDIT_LOG_FILENAME: &str = "audit.jsonl";
+
+/// A mutation of the store or the output tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AuditAction {
+    BlobWrite {
+        sha256: String,
+        size_bytes: u64,
+    },
+    BlobRemove {
+        sha256: String,
+        size_bytes: u64,
+    },
+    IndexUpdate {
+        kind: String,
+        input_key: String,
+        blob_sha256: String,
+    },
+    IndexRemove {
+        kind: String,
+        input_key: String,
+    },
+    Tag {
+        kind: String,
+        tag: String,
+        input_key: String,
+    },
+    Untag {
+        kind: String,
+        tag: String,
+    },
+    RunCreate {
+        run_dir: PathBuf,
+    },
+    RunPrune {
+        run_dir: PathBuf,
+    },
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub at_unix: u64,
+    pub user: String,
+    pub host: String,
+    pub pid: u32,
+    #[serde(flatten)]
+    pub action: AuditAction,
+}
+
+impl AuditRecord {
+    /// `action` by this process, now.
+    pub fn now(action: AuditAction) -> Self {
+        Self {
+            at_unix: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            user: current_user(),
+            host: current_host(),
+            pid: std::process::id(),
+            action,
+        }
+    }
+}
+
+/// Path of the audit log under `artifacts_root`.
+pub fn audit_log_path(artifacts_root: &Path) -> PathBuf {
+    artifacts_root.join(AUDIT_DIR).join(AUDIT_LOG_FILENAME)
+}
+
+/// Append `action` to the log under `artifacts_root`; failures are warnings.
+pub fn record(artifacts_root: &Path, action: AuditAction) {
+    if let Err(err) = append(artifacts_root, &AuditRecord::now(action)) {
+        eprintln!("  [WARN] audit log not written: {:#}", err);
+    }
+}
+
+/// [`record`] for a path inside the output tree (`<root>/out/...`); paths
+/// outside an artifacts root are not audited.
+pub fn record_output(path: &Path, action: AuditAction) {
+    let root = path
+        .ancestors()
+        .find(|dir| {
+            dir.file_name()
+                .is_some_and(|name| name == DEFAULT_OUTPUT_SUBDIR)
+        })
+        .and_then(Path::parent);
+    if let Some(root) = root {
+        record(root, action);
+    }
+}
+
+fn append(artifacts_root: &Path, record: &AuditRecord) -> Result<()> {
+    let path = audit_log_path(artifacts_root);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.lock_exclusive()
+        .with_context(|| format!("Failed to lock {}", path.display()))?;
+    let written = file
+        .write_all(&line)
+        .with_context(|| format!("Failed to append to {}", path.display()));
+    let _ = FileExt::unlock(&file);
+    written
+}
+
+/// Records in the log under `artifacts_root`, oldest first; empty when
+/// nothing was logged yet.
+pub fn read_log(artifacts_root: &Path) -> Result<Vec<AuditRecord>> {
+    let path = audit_log_path(artifacts_root);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Failed to parse {} line {}", path.display(), i + 1))
+        })
+        .collect()
+}
+
+fn current_user() -> String {
+    ["USER", "LOGNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        // SAFETY: getuid has no preconditions.
+        .unwrap_or_else(|| format!("uid:{}", unsafe { libc::getuid() }))
+}
+
+fn current_host() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_append_and_read() {
+        let temp = TempDir::new().unwrap();
+        assert!(read_log(temp.path()).unwrap().is_empty());
+        record(
+            temp.path(),
+            AuditAction::IndexUpdate {
+                kind: "rootfs_erofs".to_string(),
+                input_key: "key".to_string(),
+                blob_sha256: "ab".repeat(32),
+            },
+        );
+        let run_dir = temp.path().join("out/levitate/releases/live-boot/run1");
+        record_output(
+            &run_dir,
+            AuditAction::RunCreate {
+                run_dir: run_dir.clone(),
+            },
+        );
+        record_output(
+            Path::new("/elsewhere/run1"),
+            AuditAction::RunPrune {
+                run_dir: PathBuf::from("/elsewhere/run1"),
+            },
+        );
+
+        let records = read_log(temp.path()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].pid, std::process::id());
+        assert_eq!(records[1].action, AuditAction::RunCreate { run_dir });
+
+        let content = fs::read_to_string(audit_log_path(temp.path())).unwrap();
+        assert!(
+            content
+                .lines()
+                .next()
+                .unwrap()
+                .contains("\"action\":\"index_update\""),
+            "{content}"
+        );
+    }
+}
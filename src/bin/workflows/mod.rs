@@ -1,28 +0,0 @@
-mod artifacts;
-mod build;
-mod commands;
-mod layout;
-mod parse;
-mod prepared_products;
-mod release_hook;
-
-pub(crate) use artifacts::{
-    build_overlayfs_erofs, build_prepared_product_erofs_cmd, build_rootfs_erofs,
-    materialize_rootfs_source_cmd, prepare_product_cmd, preseed_rootfs_source_cmd,
-};
-pub(crate) use build::{
-    build_all, build_one, enforce_legacy_binding_policy_guard, ensure_release_prerequisites,
-};
-pub(crate) use commands::{
-    dispatch_non_release_command, is_release_build_invocation, run_release_build_command,
-};
-pub(crate) use layout::locate_repo_root;
-pub(crate) use parse::{
-    discover_distro_ids, parse_product, parse_release_build_command, parse_release_product,
-    product_for_logical_name,
-};
-pub(crate) use prepared_products::{
-    canonical_initramfs_live_filename, canonical_iso_filename, canonical_overlay_erofs_filename,
-    canonical_rootfs_erofs_filename,
-};
-pub(crate) use release_hook::ensure_release_iso_via_variant_hook;
@@ -0,0 +1,483 @@
+//! Boot log failure detection with per-distro suppression.
+//!
+//! The boot test fails fast when a serial line contains one of
+//! [`FAILURE_PATTERNS`](crate::qemu::FAILURE_PATTERNS). Some payloads print
+//! those words harmlessly, e.g. "failed to mount" for an optional mount, so
+//! a variant can ship a `boot-log-policy.toml`:
+//!
+//! ```toml
+//! # Lines matching any of these never fail the boot.
+//! allow = ["failed to mount /boot/efi", "^\\[ *[0-9.]+\\] EROFS error .*xattr"]
+//!
+//! # "failed to mount" only fails the boot if no recovery line follows
+//! # within 5 lines.
+//! [[recover]]
+//! pattern = "failed to mount"
+//! recovery = "Mounted .*|Reached target Local File Systems"
+//! within = 5
+//! ```
+//!
+//! Patterns use a small regex subset: literals, `.`, `[...]`/`[^...]`,
+//! `\d \w \s` (and their negations), `*`, `+`, `?`, `^`, `$` and top-level
+//! `|`. Groups and counted repetition are rejected so a policy never
+//! silently means something else. Matching is unanchored unless `^`/`$`
+//! are used.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// File name of the per-distro policy, relative to the variant dir.
+pub const BOOT_LOG_POLICY_FILENAME: &str = "boot-log-policy.toml";
+
+/// Default look-ahead for a recovery line.
+const DEFAULT_RECOVERY_WINDOW: usize = 10;
+
+/// Per-distro boot log policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BootLogPolicy {
+    /// Regexes for lines that never count as failures.
+    pub allow: Vec<String>,
+    /// Failures that are forgiven when a recovery line follows.
+    pub recover: Vec<RecoveryRule>,
+}
+
+/// A failure that only counts when no recovery line follows.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecoveryRule {
+    /// Regex selecting the failure lines this rule applies to.
+    pub pattern: String,
+    /// Regex for the line that shows the boot recovered.
+    pub recovery: String,
+    /// Lines after the failure in which the recovery must appear.
+    #[serde(default = "default_recovery_window")]
+    pub within: usize,
+}
+
+fn default_recovery_window() -> usize {
+    DEFAULT_RECOVERY_WINDOW
+}
+
+impl BootLogPolicy {
+    /// Load the policy for a variant, or the empty policy when the variant
+    /// does not ship one.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(BOOT_LOG_POLICY_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let policy: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        policy
+            .matcher(&[])
+            .with_context(|| format!("Invalid {}", path.display()))?;
+        Ok(policy)
+    }
+
+    /// Compile the policy against a set of failure substrings.
+    pub fn matcher(&self, failure_patterns: &[&str]) -> Result<BootLogMatcher> {
+        let allow = self
+            .allow
+            .iter()
+            .map(|raw| Pattern::new(raw).with_context(|| format!("allow pattern '{}'", raw)))
+            .collect::<Result<Vec<_>>>()?;
+        let recover = self
+            .recover
+            .iter()
+            .map(|rule| {
+                Ok(CompiledRecovery {
+                    pattern: Pattern::new(&rule.pattern)
+                        .with_context(|| format!("recover pattern '{}'", rule.pattern))?,
+                    recovery: Pattern::new(&rule.recovery)
+                        .with_context(|| format!("recovery pattern '{}'", rule.recovery))?,
+                    within: rule.within,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(BootLogMatcher {
+            failures: failure_patterns.iter().map(|p| p.to_string()).collect(),
+            allow,
+            recover,
+            pending: Vec::new(),
+        })
+    }
+}
+
+/// A failure the boot test should stop on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootLogFailure {
+    /// Failure substring that matched.
+    pub pattern: String,
+    /// The offending serial line.
+    pub line: String,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRecovery {
+    pattern: Pattern,
+    recovery: Pattern,
+    within: usize,
+}
+
+#[derive(Debug, Clone)]
+struct PendingFailure {
+    failure: BootLogFailure,
+    recovery: Pattern,
+    lines_left: usize,
+}
+
+/// Line-at-a-time failure detector built by [`BootLogPolicy::matcher`].
+#[derive(Debug, Clone)]
+pub struct BootLogMatcher {
+    failures: Vec<String>,
+    allow: Vec<Pattern>,
+    recover: Vec<CompiledRecovery>,
+    pending: Vec<PendingFailure>,
+}
+
+impl BootLogMatcher {
+    /// Feed one serial line; returns a failure once it is definite.
+    pub fn feed(&mut self, line: &str) -> Option<BootLogFailure> {
+        self.pending
+            .retain(|pending| !pending.recovery.is_match(line));
+        for pending in &mut self.pending {
+            pending.lines_left = pending.lines_left.saturating_sub(1);
+            if pending.lines_left == 0 {
+                return Some(pending.failure.clone());
+            }
+        }
+
+        let pattern = self.failures.iter().find(|p| line.contains(p.as_str()))?;
+        if self.allow.iter().any(|allow| allow.is_match(line)) {
+            return None;
+        }
+        let failure = BootLogFailure {
+            pattern: pattern.clone(),
+            line: line.to_string(),
+        };
+        match self.recover.iter().find(|rule| rule.pattern.is_match(line)) {
+            Some(rule) => {
+                self.pending.push(PendingFailure {
+                    failure,
+                    recovery: rule.recovery.clone(),
+                    lines_left: rule.within,
+                });
+                None
+            }
+            None => Some(failure),
+        }
+    }
+
+    /// The oldest failure still waiting for its recovery line, for when
+    /// the boot reaches its end state before the window closes.
+    pub fn unresolved(&self) -> Option<&BootLogFailure> {
+        self.pending.first().map(|pending| &pending.failure)
+    }
+}
+
+/// A compiled pattern: alternatives of anchored node sequences.
+#[derive(Debug, Clone)]
+struct Pattern {
+    alternatives: Vec<Alternative>,
+}
+
+#[derive(Debug, Clone)]
+struct Alternative {
+    anchored_start: bool,
+    anchored_end: bool,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    atom: Atom,
+    repeat: Repeat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    One,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            Self::Char(x) => c == x,
+            Self::Range(lo, hi) => (lo..=hi).contains(&c),
+            Self::Digit(want) => c.is_ascii_digit() == want,
+            Self::Word(want) => (c.is_alphanumeric() || c == '_') == want,
+            Self::Space(want) => c.is_whitespace() == want,
+        }
+    }
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Char(x) => c == *x,
+            Self::Any => true,
+            Self::Class { negated, items } => items.iter().any(|i| i.matches(c)) != *negated,
+        }
+    }
+}
+
+impl Pattern {
+    fn new(raw: &str) -> Result<Self> {
+        if raw.is_empty() {
+            bail!("empty pattern");
+        }
+        let alternatives = split_alternatives(raw)
+            .into_iter()
+            .map(parse_alternative)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { alternatives })
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        self.alternatives.iter().any(|alt| {
+            let last_start = if alt.anchored_start { 0 } else { chars.len() };
+            (0..=last_start).any(|start| match_here(&alt.nodes, &chars[start..], alt.anchored_end))
+        })
+    }
+}
+
+fn split_alternatives(raw: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut in_class = false;
+    for (idx, c) in raw.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '|' if !in_class => {
+                parts.push(&raw[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&raw[start..]);
+    parts
+}
+
+fn parse_alternative(raw: &str) -> Result<Alternative> {
+    let mut chars = raw.chars().peekable();
+    let anchored_start = chars.next_if_eq(&'^').is_some();
+    let mut anchored_end = false;
+    let mut nodes: Vec<Node> = Vec::new();
+    while let Some(c) = chars.next() {
+        let atom = match c {
+            '$' if chars.peek().is_none() => {
+                anchored_end = true;
+                break;
+            }
+            '.' => Atom::Any,
+            '\\' => match chars.next() {
+                Some(e) => match escape_class(e) {
+                    Some(item) => Atom::Class {
+                        negated: false,
+                        items: vec![item],
+                    },
+                    None => Atom::Char(e),
+                },
+                None => bail!("trailing backslash"),
+            },
+            '[' => parse_class(&mut chars)?,
+            '*' | '+' | '?' => {
+                let Some(node) = nodes.last_mut().filter(|n| n.repeat == Repeat::One) else {
+                    bail!("'{}' must follow a single character or class", c);
+                };
+                node.repeat = match c {
+                    '*' => Repeat::ZeroOrMore,
+                    '+' => Repeat::OneOrMore,
+                    _ => Repeat::ZeroOrOne,
+                };
+                continue;
+            }
+            '(' | ')' | '{' | '}' | '^' | '$' => {
+                bail!("unsupported regex syntax '{}' (escape it as '\\{}')", c, c)
+            }
+            other => Atom::Char(other),
+        };
+        nodes.push(Node {
+            atom,
+            repeat: Repeat::One,
+        });
+    }
+    Ok(Alternative {
+        anchored_start,
+        anchored_end,
+        nodes,
+    })
+}
+
+fn escape_class(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit(true)),
+        'D' => Some(ClassItem::Digit(false)),
+        'w' => Some(ClassItem::Word(true)),
+        'W' => Some(ClassItem::Word(false)),
+        's' => Some(ClassItem::Space(true)),
+        'S' => Some(ClassItem::Space(false)),
+        _ => None,
+    }
+}
+
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<Atom> {
+    let negated = chars.next_if_eq(&'^').is_some();
+    let mut items = Vec::new();
+    // A leading ']' is literal.
+    if chars.next_if_eq(&']').is_some() {
+        items.push(ClassItem::Char(']'));
+    }
+    loop {
+        let c = match chars.next() {
+            Some(']') => break,
+            Some('\\') => {
+                let e = chars.next().context("trailing backslash in class")?;
+                if let Some(item) = escape_class(e) {
+                    items.push(item);
+                    continue;
+                }
+                e
+            }
+            Some(c) => c,
+            None => bail!("unterminated character class"),
+        };
+        let is_range = chars.peek() == Some(&'-') && {
+            let mut ahead = chars.clone();
+            ahead.next();
+            !matches!(ahead.peek(), Some(']') | None)
+        };
+        if is_range {
+            chars.next();
+            let hi = chars.next().context("unterminated character class")?;
+            if hi < c {
+                bail!("invalid class range '{}-{}'", c, hi);
+            }
+            items.push(ClassItem::Range(c, hi));
+        } else {
+            items.push(ClassItem::Char(c));
+        }
+    }
+    Ok(Atom::Class { negated, items })
+}
+
+fn match_here(nodes: &[Node], text: &[char], anchored_end: bool) -> bool {
+    let Some((node, rest)) = nodes.split_first() else {
+        return !anchored_end || text.is_empty();
+    };
+    let (min, max) = match node.repeat {
+        Repeat::One => (1, 1),
+        Repeat::ZeroOrOne => (0, 1),
+        Repeat::ZeroOrMore => (0, usize::MAX),
+        Repeat::OneOrMore => (1, usize::MAX),
+    };
+    let available = text
+        .iter()
+        .take(max)
+        .take_while(|c| node.atom.matches(**c))
+        .count();
+    if available < min {
+        return false;
+    }
+    // Greedy with backtracking.
+    (min..=available)
+        .rev()
+        .any(|taken| match_here(rest, &text[taken..], anchored_end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn test_pattern_subset() {
+        assert!(matches("failed to mount", "[ 2.1] foo: failed to mount /x"));
+        assert!(matches("^\\[ *[0-9.]+\\] EROFS", "[  12.50] EROFS error"));
+        assert!(!matches("^EROFS", "[ 1.0] EROFS error"));
+        assert!(matches("mount /boot/efi$", "failed to mount /boot/efi"));
+        assert!(!matches("mount /boot$", "failed to mount /boot/efi"));
+        assert!(matches("sd[a-c]\\d+", "sdb12"));
+        assert!(!matches("sd[^a-c]", "sda"));
+        assert!(matches("Mounted .*|Reached target", "Reached target Local"));
+        assert!(matches("colou?r", "color"));
+        assert!(matches("a.*b.*c", "a--b--c"));
+        assert!(Pattern::new("(optional)").is_err());
+        assert!(Pattern::new("x{2}").is_err());
+        assert!(Pattern::new("*x").is_err());
+        assert!(Pattern::new("[abc").is_err());
+    }
+
+    #[test]
+    fn test_allow_and_recovery() {
+        let policy: BootLogPolicy = toml::from_str(
+            r#"
+allow = ["failed to mount /boot/efi"]
+
+[[recover]]
+pattern = "failed to mount /mnt/optional"
+recovery = "^Mounted|continuing without"
+within = 2
+"#,
+        )
+        .unwrap();
+        let failures = ["Kernel panic", "failed to mount"];
+        let mut matcher = policy.matcher(&failures).unwrap();
+
+        assert_eq!(matcher.feed("mount: failed to mount /boot/efi"), None);
+
+        // Recovered within the window.
+        assert_eq!(matcher.feed("failed to mount /mnt/optional"), None);
+        assert!(matcher.unresolved().is_some());
+        assert_eq!(matcher.feed("noise"), None);
+        assert_eq!(matcher.feed("continuing without /mnt/optional"), None);
+        assert!(matcher.unresolved().is_none());
+
+        // Not recovered: fails once the window closes.
+        assert_eq!(matcher.feed("failed to mount /mnt/optional"), None);
+        assert_eq!(matcher.feed("noise"), None);
+        let failure = matcher.feed("noise").unwrap();
+        assert_eq!(failure.pattern, "failed to mount");
+
+        // Everything else still fails immediately.
+        let mut matcher = policy.matcher(&failures).unwrap();
+        assert!(matcher.feed("failed to mount /usr").is_some());
+        assert!(matcher.feed("Kernel panic - not syncing").is_some());
+    }
+}
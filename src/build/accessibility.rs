@@ -0,0 +1,269 @@
+//! Accessibility defaults for live images, configured per variant.
+//!
+//! A variant opts in by shipping `accessibility.toml`:
+//!
+//! ```toml
+//! large_font = true          # or console_font = "ter-v28b"
+//! beep = true                # PC speaker and audible shell bell
+//! braille = true             # enable brltty if the rootfs ships it
+//! speech = true              # enable espeakup (Speakup) if the rootfs ships it
+//! ```
+//!
+//! The live overlay generators apply it for both init systems. Services the
+//! rootfs does not ship are dropped with a warning by
+//! [`Accessibility::retain_shipped`], so one file can be shared by variants
+//! with different package sets.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+/// Variant-local accessibility settings file.
+pub const ACCESSIBILITY_CONFIG_FILENAME: &str = "accessibility.toml";
+
+/// Console font used for `large_font` (Terminus, 32px, from `terminus-font`).
+pub const LARGE_CONSOLE_FONT: &str = "ter-v32n";
+
+const BRAILLE_SERVICE: &str = "brltty";
+const SPEECH_SERVICE: &str = "espeakup";
+
+/// Places a service counts as shipped: systemd unit, then OpenRC script.
+const SERVICE_LOCATIONS: &[&str] = &[
+    "usr/lib/systemd/system/{}.service",
+    "lib/systemd/system/{}.service",
+    "etc/init.d/{}",
+];
+
+/// Accessibility settings for one variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Accessibility {
+    /// Console font name; overrides `large_font`.
+    pub console_font: Option<String>,
+    /// Use [`LARGE_CONSOLE_FONT`] on the console.
+    pub large_font: bool,
+    /// Load the PC speaker driver and make the shell bell audible.
+    pub beep: bool,
+    /// Start brltty for refreshable braille displays.
+    pub braille: bool,
+    /// Start espeakup so Speakup reads the console aloud.
+    pub speech: bool,
+}
+
+impl Accessibility {
+    /// Load `accessibility.toml` from `variant_dir`; `None` when the variant
+    /// has none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(ACCESSIBILITY_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if let Some(font) = &config.console_font {
+            let valid = !font.is_empty()
+                && font
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !valid {
+                bail!("invalid console font '{}' in {}", font, path.display());
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// Console font to configure, if any.
+    pub fn font(&self) -> Option<&str> {
+        self.console_font
+            .as_deref()
+            .or(self.large_font.then_some(LARGE_CONSOLE_FONT))
+    }
+
+    /// Services to enable, in start order.
+    pub fn services(&self) -> Vec<&'static str> {
+        let mut services = Vec::new();
+        if self.braille {
+            services.push(BRAILLE_SERVICE);
+        }
+        if self.speech {
+            services.push(SPEECH_SERVICE);
+        }
+        services
+    }
+
+    /// Drop services that `rootfs` does not ship.
+    pub fn retain_shipped(&mut self, rootfs: &Path) {
+        if self.braille && !ships_service(rootfs, BRAILLE_SERVICE) {
+            eprintln!("  [WARN] Rootfs does not ship brltty; braille support disabled");
+            self.braille = false;
+        }
+        if self.speech && !ships_service(rootfs, SPEECH_SERVICE) {
+            eprintln!("  [WARN] Rootfs does not ship espeakup; speech support disabled");
+            self.speech = false;
+        }
+    }
+
+    /// Kernel modules to load at boot.
+    fn modules(&self) -> Vec<&'static str> {
+        let mut modules = Vec::new();
+        if self.beep {
+            modules.push("pcspkr");
+        }
+        if self.speech {
+            modules.push("speakup_soft");
+        }
+        modules
+    }
+
+    /// Apply to a systemd live overlay.
+    pub fn apply_systemd(&self, live_overlay: &Path) -> Result<()> {
+        if let Some(font) = self.font() {
+            write(
+                &live_overlay.join("etc/vconsole.conf"),
+                &format!("KEYMAP=us\nFONT={}\n", font),
+            )?;
+        }
+        self.write_common(live_overlay)?;
+        let wants = live_overlay.join("etc/systemd/system/multi-user.target.wants");
+        for service in self.services() {
+            fs::create_dir_all(&wants)
+                .with_context(|| format!("Failed to create {}", wants.display()))?;
+            let unit = format!("{}.service", service);
+            symlink(
+                format!("/usr/lib/systemd/system/{}", unit),
+                wants.join(&unit),
+            )
+            .with_context(|| format!("Failed to enable {}", unit))?;
+        }
+        Ok(())
+    }
+
+    /// Apply to an OpenRC live overlay.
+    pub fn apply_openrc(&self, live_overlay: &Path) -> Result<()> {
+        if let Some(font) = self.font() {
+            write(
+                &live_overlay.join("etc/conf.d/consolefont"),
+                &format!("consolefont=\"{}\"\n", font),
+            )?;
+            enable_openrc(live_overlay, "boot", "consolefont")?;
+        }
+        self.write_common(live_overlay)?;
+        if !self.modules().is_empty() {
+            enable_openrc(live_overlay, "boot", "modules")?;
+        }
+        for service in self.services() {
+            enable_openrc(live_overlay, "default", service)?;
+        }
+        Ok(())
+    }
+
+    fn write_common(&self, live_overlay: &Path) -> Result<()> {
+        let modules = self.modules();
+        if !modules.is_empty() {
+            let mut content = String::from("# Generated by distro-builder: accessibility\n");
+            for module in modules {
+                content.push_str(module);
+                content.push('\n');
+            }
+            write(
+                &live_overlay.join("etc/modules-load.d/accessibility.conf"),
+                &content,
+            )?;
+        }
+        if self.beep {
+            write(
+                &live_overlay.join("etc/profile.d/30-accessibility-bell.sh"),
+                "# Generated by distro-builder: audible shell bell\n\
+                 [ -n \"$BASH_VERSION\" ] && bind 'set bell-style audible' 2>/dev/null\n\
+                 command -v setterm >/dev/null 2>&1 && setterm --blength 200 2>/dev/null\n\
+                 true\n",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn ships_service(rootfs: &Path, service: &str) -> bool {
+    SERVICE_LOCATIONS
+        .iter()
+        .any(|location| rootfs.join(location.replace("{}", service)).is_file())
+}
+
+fn enable_openrc(live_overlay: &Path, runlevel: &str, service: &str) -> Result<()> {
+    let dir = live_overlay.join("etc/runlevels").join(runlevel);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let link = dir.join(service);
+    if link.symlink_metadata().is_ok() {
+        return Ok(());
+    }
+    symlink(format!("/etc/init.d/{}", service), &link)
+        .with_context(|| format!("Failed to enable {} in runlevel {}", service, runlevel))
+}
+
+fn write(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_unshipped_services_are_dropped() {
+        let temp = TempDir::new().unwrap();
+        let variant = temp.path().join("variant");
+        let rootfs = temp.path().join("rootfs");
+        fs::create_dir_all(&variant).unwrap();
+        fs::create_dir_all(rootfs.join("etc/init.d")).unwrap();
+        fs::write(rootfs.join("etc/init.d/espeakup"), "").unwrap();
+        assert_eq!(Accessibility::load_for_variant(&variant).unwrap(), None);
+
+        fs::write(
+            variant.join(ACCESSIBILITY_CONFIG_FILENAME),
+            "large_font = true\nbeep = true\nbraille = true\nspeech = true\n",
+        )
+        .unwrap();
+        let mut config = Accessibility::load_for_variant(&variant).unwrap().unwrap();
+        assert_eq!(config.font(), Some(LARGE_CONSOLE_FONT));
+        config.retain_shipped(&rootfs);
+        assert_eq!(config.services(), vec![SPEECH_SERVICE]);
+
+        let overlay = temp.path().join("overlay");
+        config.apply_openrc(&overlay).unwrap();
+        assert!(fs::read_to_string(overlay.join("etc/conf.d/consolefont"))
+            .unwrap()
+            .contains(LARGE_CONSOLE_FONT));
+        assert_eq!(
+            fs::read_to_string(overlay.join("etc/modules-load.d/accessibility.conf"))
+                .unwrap()
+                .lines()
+                .skip(1)
+                .collect::<Vec<_>>(),
+            vec!["pcspkr", "speakup_soft"]
+        );
+        assert!(overlay
+            .join("etc/runlevels/default/espeakup")
+            .symlink_metadata()
+            .is_ok());
+        assert!(overlay
+            .join("etc/runlevels/default/brltty")
+            .symlink_metadata()
+            .is_err());
+
+        fs::write(
+            variant.join(ACCESSIBILITY_CONFIG_FILENAME),
+            "console_font = \"../x\"\n",
+        )
+        .unwrap();
+        assert!(Accessibility::load_for_variant(&variant).is_err());
+    }
+}
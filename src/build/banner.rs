@@ -0,0 +1,262 @@
+//! issue/motd templates filled in with build metadata.
+//!
+//! Live images identify the build they came from on the login banner, so a
+//! screenshot or serial log is enough to tell which run produced an image.
+//! Templates use `{{name}}` placeholders:
+//!
+//! | Placeholder      | Value                                         |
+//! |------------------|-----------------------------------------------|
+//! | `{{os_name}}`    | distro display name                           |
+//! | `{{stage}}`      | product label, e.g. `Boot`                    |
+//! | `{{title}}`      | `os_name` followed by `stage` when set        |
+//! | `{{version}}`    | `os_version` from the contract                |
+//! | `{{run_id}}`     | release run directory                         |
+//! | `{{git_commit}}` | checkout commit (short)                       |
+//! | `{{build}}`      | the set fields above, e.g. `1.0 (abc123, run-1)` |
+//! | `{{features}}`   | enabled feature flags, comma-separated, or `none` |
+//!
+//! Unset values render as `unknown`. The run id and commit differ between
+//! otherwise identical builds, so release builds only fill them in when the
+//! variant sets `banner_build_id = true` in its `build-context.toml`
+//! (see [`BuildSettings::banner_build_id`](crate::BuildSettings::banner_build_id)).
+//! The contract's `issue_message` is a
+//! template, and a variant can ship [`MOTD_TEMPLATE_FILENAME`]. Without
+//! either, [`BuildMetadata::live_issue`] and [`BuildMetadata::live_motd`]
+//! give the defaults.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::contracts::context::Features;
+use crate::process::{self, Cmd};
+use crate::run_history::{read_run_metadata, run_manifest_path};
+
+/// Variant-local motd template.
+pub const MOTD_TEMPLATE_FILENAME: &str = "motd.template";
+
+const UNKNOWN: &str = "unknown";
+
+/// What a live image knows about the build that produced it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildMetadata {
+    pub os_name: String,
+    pub stage: Option<String>,
+    pub version: Option<String>,
+    pub run_id: Option<String>,
+    pub git_commit: Option<String>,
+    pub features: Features,
+}
+
+impl BuildMetadata {
+    pub fn new(os_name: impl Into<String>) -> Self {
+        Self {
+            os_name: os_name.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn stage(mut self, stage: impl Into<String>) -> Self {
+        self.stage = Some(stage.into());
+        self
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into()).filter(|v: &String| !v.is_empty());
+        self
+    }
+
+    pub fn run_id(mut self, run_id: impl Into<String>) -> Self {
+        self.run_id = Some(run_id.into());
+        self
+    }
+
+    pub fn git_commit(mut self, commit: impl Into<String>) -> Self {
+        self.git_commit = Some(commit.into());
+        self
+    }
+
+    pub fn features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Fill in the run id from the run manifest in `output_dir` and the
+    /// commit of the checkout at `repo_root`, where available.
+    pub fn detect(mut self, repo_root: &Path, output_dir: &Path) -> Self {
+        let manifest = run_manifest_path(output_dir);
+        if manifest.is_file() {
+            match read_run_metadata(&manifest) {
+                Ok(metadata) => self.run_id = Some(metadata.run_id),
+                Err(err) => eprintln!("  [WARN] {:#}; banner will not show the run id", err),
+            }
+        }
+        if self.git_commit.is_none() {
+            self.git_commit = git_commit(repo_root);
+        }
+        self
+    }
+
+    /// `os_name` followed by the stage label.
+    pub fn title(&self) -> String {
+        match &self.stage {
+            Some(stage) => format!("{} {}", self.os_name, stage),
+            None => self.os_name.clone(),
+        }
+    }
+
+    /// One-line build identification, e.g. `2026.1 (1a2b3c4d5e6f, run-3)`;
+    /// `None` when nothing is known.
+    pub fn summary(&self) -> Option<String> {
+        let details: Vec<&str> = [&self.git_commit, &self.run_id]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        match (&self.version, details.is_empty()) {
+            (None, true) => None,
+            (Some(version), true) => Some(version.clone()),
+            (None, false) => Some(details.join(", ")),
+            (Some(version), false) => Some(format!("{} ({})", version, details.join(", "))),
+        }
+    }
+
+    /// Value of a template placeholder; `None` for unknown names.
+    fn value(&self, name: &str) -> Option<String> {
+        let or_unknown =
+            |value: &Option<String>| value.clone().unwrap_or_else(|| UNKNOWN.to_string());
+        Some(match name {
+            "os_name" => self.os_name.clone(),
+            "stage" => or_unknown(&self.stage),
+            "title" => self.title(),
+            "version" => or_unknown(&self.version),
+            "run_id" => or_unknown(&self.run_id),
+            "git_commit" => or_unknown(&self.git_commit),
+            "build" => self.summary().unwrap_or_else(|| UNKNOWN.to_string()),
+            "features" if self.features.is_empty() => "none".to_string(),
+            "features" => self.features.to_string(),
+            _ => return None,
+        })
+    }
+
+    /// Replace every `{{name}}` in `template`.
+    pub fn render(&self, template: &str) -> Result<String> {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let Some(len) = rest[start + 2..].find("}}") else {
+                bail!("unterminated '{{{{' in banner template");
+            };
+            let name = rest[start + 2..start + 2 + len].trim();
+            let Some(value) = self.value(name) else {
+                bail!("unknown banner placeholder '{{{{{}}}}}'", name);
+            };
+            out.push_str(&value);
+            rest = &rest[start + 2 + len + 2..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    /// Live `/etc/issue`: `template` when given, else
+    /// [`default_live_issue`](Self::default_live_issue).
+    pub fn live_issue(&self, template: Option<&str>) -> Result<String> {
+        match template {
+            Some(template) => self.render(template),
+            None => Ok(self.default_live_issue()),
+        }
+    }
+
+    /// Pre-login banner for live sessions, with a build line when anything
+    /// about the build is known.
+    pub fn default_live_issue(&self) -> String {
+        let build = self
+            .summary()
+            .map(|summary| format!("Build {}\n", summary))
+            .unwrap_or_default();
+        format!(
+            "\n{} Live - \\l\n{}\nLogin as 'root' (no password)\n\n",
+            self.title(),
+            build
+        )
+    }
+
+    /// Live `/etc/motd`: `template` when given, else a welcome naming the
+    /// build. `None` when there is neither a template nor anything to name.
+    pub fn live_motd(&self, template: Option<&str>) -> Result<Option<String>> {
+        if let Some(template) = template {
+            return self.render(template).map(Some);
+        }
+        Ok(self
+            .summary()
+            .map(|summary| format!("\nWelcome to {} Live!\nBuild {}\n\n", self.title(), summary)))
+    }
+}
+
+/// Load [`MOTD_TEMPLATE_FILENAME`] from `variant_dir`, if the variant has one.
+pub fn load_motd_template(variant_dir: &Path) -> Result<Option<String>> {
+    let path = variant_dir.join(MOTD_TEMPLATE_FILENAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    fs::read_to_string(&path)
+        .map(Some)
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Short commit of the checkout at `repo_root`, when it is a git checkout.
+fn git_commit(repo_root: &Path) -> Option<String> {
+    if !process::exists("git") {
+        return None;
+    }
+    let result = Cmd::new("git")
+        .arg("-C")
+        .arg_path(repo_root)
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .allow_fail()
+        .run()
+        .ok()?;
+    let commit = result.stdout_trimmed();
+    (result.success() && !commit.is_empty()).then(|| commit.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_build_metadata() {
+        let bare = BuildMetadata::new("AcornOS").stage("Boot");
+        assert_eq!(
+            bare.live_issue(None).unwrap(),
+            "\nAcornOS Boot Live - \\l\n\nLogin as 'root' (no password)\n\n"
+        );
+        assert_eq!(bare.live_motd(None).unwrap(), None);
+        assert_eq!(bare.render("{{version}}").unwrap(), "unknown");
+
+        let built = bare.version("2026.1").git_commit("1a2b3c").run_id("run-3");
+        assert_eq!(
+            built.render("{{ title }} {{build}}\n").unwrap(),
+            "AcornOS Boot 2026.1 (1a2b3c, run-3)\n"
+        );
+        assert_eq!(
+            built.live_issue(None).unwrap(),
+            "\nAcornOS Boot Live - \\l\nBuild 2026.1 (1a2b3c, run-3)\n\nLogin as 'root' (no password)\n\n"
+        );
+        assert!(built
+            .live_motd(None)
+            .unwrap()
+            .unwrap()
+            .contains("Build 2026.1 (1a2b3c, run-3)"));
+
+        assert_eq!(built.render("{{features}}").unwrap(), "none");
+        let flavored = built
+            .clone()
+            .features(Features::parse(["wifi", "desktop"]).unwrap());
+        assert_eq!(flavored.render("{{features}}").unwrap(), "desktop,wifi");
+
+        assert!(built.render("{{nope}}").is_err());
+        assert!(built.render("{{version").is_err());
+    }
+}
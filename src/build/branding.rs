@@ -0,0 +1,216 @@
+//! Distribution branding files.
+//!
+//! Renders `os-release`, `issue`, `motd`, and `lsb-release` from a single
+//! set of identity fields so every producer emits the same strings. The
+//! base rootfs baseline writes all four, and release builds take the boot
+//! menu title (`PRETTY_NAME`, embedded in each UKI) from the same fields.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use distro_builder::build::branding::Branding;
+//!
+//! let branding = Branding::new("AcornOS", "acornos")
+//!     .version("2026.1")
+//!     .home_url("https://acornos.org")
+//!     .ansi_color("0;32");
+//! branding.write_all(staging)?;
+//! ```
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::build::banner::BuildMetadata;
+use crate::contracts::DistroConfig;
+
+/// Identity fields used to render branding files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branding {
+    pub name: String,
+    pub id: String,
+    pub version: Option<String>,
+    pub home_url: Option<String>,
+    pub ansi_color: Option<String>,
+}
+
+impl Branding {
+    pub fn new(name: impl Into<String>, id: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            id: id.into(),
+            version: None,
+            home_url: None,
+            ansi_color: None,
+        }
+    }
+
+    /// Take every branding field from a distro configuration.
+    pub fn from_config(config: &dyn DistroConfig) -> Self {
+        Self {
+            name: config.os_name().to_string(),
+            id: config.os_id().to_string(),
+            version: config.os_version().map(str::to_string),
+            home_url: config.home_url().map(str::to_string),
+            ansi_color: config.ansi_color().map(str::to_string),
+        }
+    }
+
+    /// Release version; an empty one is ignored.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into()).filter(|v: &String| !v.is_empty());
+        self
+    }
+
+    pub fn home_url(mut self, url: impl Into<String>) -> Self {
+        self.home_url = Some(url.into());
+        self
+    }
+
+    /// ANSI SGR sequence for the distro name, e.g. `"0;34"`.
+    pub fn ansi_color(mut self, color: impl Into<String>) -> Self {
+        self.ansi_color = Some(color.into());
+        self
+    }
+
+    /// Name plus version when one is set.
+    pub fn pretty_name(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{} {}", self.name, version),
+            None => self.name.clone(),
+        }
+    }
+
+    /// `/etc/os-release` (see os-release(5)).
+    pub fn os_release(&self) -> String {
+        let mut out = format!("NAME=\"{}\"\nID={}\n", self.name, self.id);
+        if let Some(version) = &self.version {
+            out.push_str(&format!(
+                "VERSION=\"{}\"\nVERSION_ID={}\n",
+                version, version
+            ));
+        }
+        out.push_str(&format!("PRETTY_NAME=\"{}\"\n", self.pretty_name()));
+        if let Some(color) = &self.ansi_color {
+            out.push_str(&format!("ANSI_COLOR=\"{}\"\n", color));
+        }
+        if let Some(url) = &self.home_url {
+            out.push_str(&format!("HOME_URL=\"{}\"\n", url));
+        }
+        out
+    }
+
+    /// `/etc/lsb-release` for tools that predate os-release.
+    pub fn lsb_release(&self) -> String {
+        let mut out = format!("DISTRIB_ID={}\n", self.name);
+        if let Some(version) = &self.version {
+            out.push_str(&format!("DISTRIB_RELEASE={}\n", version));
+        }
+        out.push_str(&format!("DISTRIB_DESCRIPTION=\"{}\"\n", self.pretty_name()));
+        out
+    }
+
+    /// Pre-login banner for installed systems.
+    pub fn issue(&self) -> String {
+        format!("\n{} \\r (\\l)\n\n", self.colored(&self.pretty_name()))
+    }
+
+    /// Pre-login banner for live sessions; `label` names the product.
+    ///
+    /// Live overlays render theirs through [`BuildMetadata`], which adds
+    /// the build line.
+    pub fn live_issue(&self, label: Option<&str>) -> String {
+        let mut build = BuildMetadata::new(&self.name);
+        build.stage = label.map(str::to_string);
+        build.default_live_issue()
+    }
+
+    /// Post-login message of the day.
+    pub fn motd(&self) -> String {
+        let mut out = format!("\nWelcome to {}!\n", self.pretty_name());
+        if let Some(url) = &self.home_url {
+            out.push_str(&format!("\nDocumentation: {}\n", url));
+        }
+        out.push('\n');
+        out
+    }
+
+    /// The branding files with their paths relative to the root, e.g.
+    /// `etc/os-release`.
+    pub fn files(&self) -> [(&'static str, String); 4] {
+        [
+            ("etc/os-release", self.os_release()),
+            ("etc/lsb-release", self.lsb_release()),
+            ("etc/issue", self.issue()),
+            ("etc/motd", self.motd()),
+        ]
+    }
+
+    /// Write [`files`](Self::files) under `root`.
+    pub fn write_all(&self, root: &Path) -> Result<()> {
+        let etc = root.join("etc");
+        fs::create_dir_all(&etc).with_context(|| format!("Failed to create {}", etc.display()))?;
+        for (name, content) in self.files() {
+            let path = root.join(name);
+            fs::write(&path, content)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Wrap text in the ANSI color escape for agetty's issue file.
+    fn colored(&self, text: &str) -> String {
+        match &self.ansi_color {
+            Some(color) => format!("\x1b[{}m{}\x1b[0m", color, text),
+            None => text.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_minimal_os_release() {
+        let branding = Branding::new("AcornOS", "acornos");
+        assert_eq!(
+            branding.os_release(),
+            "NAME=\"AcornOS\"\nID=acornos\nPRETTY_NAME=\"AcornOS\"\n"
+        );
+        assert_eq!(branding.clone().version(""), branding);
+    }
+
+    #[test]
+    fn test_full_os_release() {
+        let branding = Branding::new("LevitateOS", "levitateos")
+            .version("2026.1")
+            .home_url("https://levitateos.org")
+            .ansi_color("0;34");
+        let os_release = branding.os_release();
+        assert!(os_release.contains("VERSION_ID=2026.1\n"));
+        assert!(os_release.contains("PRETTY_NAME=\"LevitateOS 2026.1\"\n"));
+        assert!(os_release.contains("ANSI_COLOR=\"0;34\"\n"));
+        assert!(os_release.contains("HOME_URL=\"https://levitateos.org\"\n"));
+        assert!(branding
+            .issue()
+            .contains("\x1b[0;34mLevitateOS 2026.1\x1b[0m"));
+    }
+
+    #[test]
+    fn test_write_all() {
+        let temp = TempDir::new().unwrap();
+        Branding::new("RalphOS", "ralphos")
+            .write_all(temp.path())
+            .unwrap();
+
+        for name in ["os-release", "lsb-release", "issue", "motd"] {
+            assert!(temp.path().join("etc").join(name).is_file(), "{}", name);
+        }
+        assert_eq!(
+            Branding::new("RalphOS", "ralphos").live_issue(Some("Boot")),
+            "\nRalphOS Boot Live - \\l\n\nLogin as 'root' (no password)\n\n"
+        );
+    }
+}
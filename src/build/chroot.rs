@@ -0,0 +1,303 @@
+//! Running commands inside a prepared rootfs.
+//!
+//! [`Chroot`] enters the rootfs through `unshare` so the `/proc`, `/sys`
+//! and `/dev` mounts it needs live in a private mount namespace and vanish
+//! with the command; nothing has to be unmounted afterwards, even when the
+//! command fails or is killed. Without root the namespace maps the caller
+//! to uid 0, which is enough for package managers writing into a rootfs
+//! the caller owns.
+//!
+//! The host's `/etc/resolv.conf` is copied in for the duration of the run
+//! so package managers can resolve mirrors, and the rootfs's own copy is
+//! put back afterwards.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::contracts::context::PackageManager;
+use crate::process::{Cmd, CommandResult};
+
+/// Enters the namespace's mounts, then replaces itself with the command.
+/// `$1` is the rootfs, the rest is the command line.
+const ENTER_SCRIPT: &str = r#"set -e
+root="$1"
+shift
+mount -t proc proc "$root/proc"
+mount --rbind /sys "$root/sys"
+mount --rbind /dev "$root/dev"
+exec chroot "$root" "$@"
+"#;
+
+/// Runs commands chrooted into a rootfs directory.
+#[derive(Debug, Clone)]
+pub struct Chroot {
+    root: PathBuf,
+    host_resolv_conf: bool,
+    env: Vec<(String, String)>,
+}
+
+impl Chroot {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            host_resolv_conf: true,
+            env: Vec::new(),
+        }
+    }
+
+    /// Whether to use the host's DNS configuration (default: yes).
+    pub fn host_resolv_conf(mut self, enabled: bool) -> Self {
+        self.host_resolv_conf = enabled;
+        self
+    }
+
+    /// Set an environment variable for the chrooted command.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether `program` exists in the rootfs (`/usr/bin`, `/usr/sbin`,
+    /// `/bin` or `/sbin`).
+    pub fn has_program(&self, program: &str) -> bool {
+        ["usr/bin", "usr/sbin", "bin", "sbin"]
+            .iter()
+            .any(|dir| self.root.join(dir).join(program).symlink_metadata().is_ok())
+    }
+
+    /// Run `argv` inside the rootfs, failing on a non-zero exit.
+    pub fn run(&self, argv: &[&str]) -> Result<CommandResult> {
+        let Some(program) = argv.first() else {
+            bail!("no command given for chroot {}", self.root.display());
+        };
+        for dir in ["proc", "sys", "dev"] {
+            let path = self.root.join(dir);
+            fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+        }
+
+        let resolv = if self.host_resolv_conf {
+            Some(ResolvConfGuard::install(&self.root)?)
+        } else {
+            None
+        };
+        let result = Cmd::new("unshare")
+            .args(self.unshare_args(argv))
+            .error_msg(format!("chroot into {} failed", self.root.display()))
+            .run();
+        if let Some(resolv) = resolv {
+            resolv.restore()?;
+        }
+        result.with_context(|| format!("'{}' in chroot {}", program, self.root.display()))
+    }
+
+    /// Install `packages` with the rootfs's own package manager.
+    pub fn install_packages(&self, manager: PackageManager, packages: &[String]) -> Result<()> {
+        if packages.is_empty() {
+            return Ok(());
+        }
+        let mut argv = self.install_command(manager)?;
+        argv.extend(packages.iter().map(String::as_str));
+        println!(
+            "  Installing {} package(s) into {}: {}",
+            packages.len(),
+            self.root.display(),
+            packages.join(" ")
+        );
+        self.run(&argv)?;
+        Ok(())
+    }
+
+    fn install_command(&self, manager: PackageManager) -> Result<Vec<&'static str>> {
+        match manager {
+            PackageManager::Apk => Ok(vec!["apk", "add", "--no-cache"]),
+            PackageManager::Pacman => Ok(vec!["pacman", "-S", "--noconfirm", "--needed"]),
+            PackageManager::Rpm => {
+                let Some(dnf) = ["dnf5", "dnf", "microdnf"]
+                    .into_iter()
+                    .find(|dnf| self.has_program(dnf))
+                else {
+                    bail!(
+                        "no dnf, dnf5 or microdnf in {}; cannot install packages",
+                        self.root.display()
+                    );
+                };
+                Ok(vec![
+                    dnf,
+                    "install",
+                    "-y",
+                    "--setopt=install_weak_deps=False",
+                ])
+            }
+        }
+    }
+
+    fn unshare_args(&self, argv: &[&str]) -> Vec<String> {
+        let mut args: Vec<String> = ["--mount", "--pid", "--fork"].map(String::from).to_vec();
+        if unsafe { libc::geteuid() } != 0 {
+            args.push("--map-root-user".to_string());
+        }
+        args.extend(["sh", "-c", ENTER_SCRIPT, "sh"].map(String::from));
+        args.push(self.root.to_string_lossy().into_owned());
+        if !self.env.is_empty() {
+            args.push("env".to_string());
+            args.extend(
+                self.env
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value)),
+            );
+        }
+        args.extend(argv.iter().map(|arg| arg.to_string()));
+        args
+    }
+}
+
+/// Host `resolv.conf` swapped into a rootfs, with the original kept aside.
+struct ResolvConfGuard {
+    path: PathBuf,
+    original: Option<Original>,
+    restored: bool,
+}
+
+enum Original {
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+impl ResolvConfGuard {
+    fn install(root: &Path) -> Result<Self> {
+        let path = root.join("etc/resolv.conf");
+        let original = match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => Some(Original::Symlink(
+                fs::read_link(&path)
+                    .with_context(|| format!("Failed to read link {}", path.display()))?,
+            )),
+            Ok(_) => {
+                Some(Original::File(fs::read(&path).with_context(|| {
+                    format!("Failed to read {}", path.display())
+                })?))
+            }
+            Err(_) => None,
+        };
+        let host = fs::read("/etc/resolv.conf").unwrap_or_default();
+        if original.is_some() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        } else if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&path, host).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(Self {
+            path,
+            original,
+            restored: false,
+        })
+    }
+
+    fn restore(mut self) -> Result<()> {
+        self.restore_in_place()
+    }
+
+    fn restore_in_place(&mut self) -> Result<()> {
+        if self.restored {
+            return Ok(());
+        }
+        self.restored = true;
+        let _ = fs::remove_file(&self.path);
+        match self.original.take() {
+            Some(Original::File(content)) => fs::write(&self.path, content)
+                .with_context(|| format!("Failed to restore {}", self.path.display())),
+            Some(Original::Symlink(target)) => std::os::unix::fs::symlink(&target, &self.path)
+                .with_context(|| format!("Failed to restore {}", self.path.display())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Early returns and panics must not ship the host's resolv.conf.
+impl Drop for ResolvConfGuard {
+    fn drop(&mut self) {
+        if let Err(err) = self.restore_in_place() {
+            eprintln!("  [WARN] {:#}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolv_conf_is_restored() {
+        let temp = TempDir::new().unwrap();
+        let etc = temp.path().join("etc");
+        fs::create_dir_all(&etc).unwrap();
+        std::os::unix::fs::symlink(
+            "../run/systemd/resolve/stub-resolv.conf",
+            etc.join("resolv.conf"),
+        )
+        .unwrap();
+
+        let guard = ResolvConfGuard::install(temp.path()).unwrap();
+        assert!(!etc.join("resolv.conf").is_symlink());
+        guard.restore().unwrap();
+        assert_eq!(
+            fs::read_link(etc.join("resolv.conf")).unwrap(),
+            Path::new("../run/systemd/resolve/stub-resolv.conf")
+        );
+    }
+
+    #[test]
+    fn test_resolv_conf_is_restored_on_drop() {
+        let temp = TempDir::new().unwrap();
+        let etc = temp.path().join("etc");
+        fs::create_dir_all(&etc).unwrap();
+        fs::write(etc.join("resolv.conf"), "nameserver 10.0.0.1\n").unwrap();
+
+        let failing = || -> Result<()> {
+            let _guard = ResolvConfGuard::install(temp.path())?;
+            bail!("package install failed")
+        };
+        assert!(failing().is_err());
+        assert_eq!(
+            fs::read_to_string(etc.join("resolv.conf")).unwrap(),
+            "nameserver 10.0.0.1\n"
+        );
+
+        fs::remove_file(etc.join("resolv.conf")).unwrap();
+        drop(ResolvConfGuard::install(temp.path()).unwrap());
+        assert!(fs::symlink_metadata(etc.join("resolv.conf")).is_err());
+    }
+
+    #[test]
+    fn test_command_passes_argv_verbatim() {
+        let temp = TempDir::new().unwrap();
+        let chroot = Chroot::new(temp.path()).env("LANG", "C");
+        let args = chroot.unshare_args(&["apk", "add", "a b"]);
+        assert!(args.contains(&ENTER_SCRIPT.to_string()));
+        assert_eq!(
+            args[args.len() - 5..],
+            ["env", "LANG=C", "apk", "add", "a b"]
+        );
+    }
+
+    #[test]
+    fn test_rpm_install_needs_dnf_in_rootfs() {
+        let temp = TempDir::new().unwrap();
+        let chroot = Chroot::new(temp.path());
+        assert!(chroot.install_command(PackageManager::Rpm).is_err());
+        fs::create_dir_all(temp.path().join("usr/bin")).unwrap();
+        fs::write(temp.path().join("usr/bin/dnf"), "").unwrap();
+        assert_eq!(
+            chroot.install_command(PackageManager::Rpm).unwrap()[0],
+            "dnf"
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Kernel command line construction and validation.
+//!
+//! Command lines come from several contract arrays (UKI extra cmdline, boot
+//! entry options, scenario-required tokens) and used to be joined as plain
+//! strings. [`CmdlineBuilder`] parses and assembles them as parameters so
+//! duplicates, quoting mistakes and misspelled parameters fail at build time
+//! instead of at boot.
+//!
+//! Keys without a `.` are checked against a table of parameters the kernel,
+//! the initramfs or init understand, including whether they take a value.
+//! Namespaced keys (`rd.*`, `systemd.*`, `<module>.<param>`) are not listed
+//! and pass as-is.
+//!
+//! ```rust,ignore
+//! use distro_builder::build::cmdline::CmdlineBuilder;
+//!
+//! let cmdline = CmdlineBuilder::new()
+//!     .root("LABEL=LEVITATE")
+//!     .console("ttyS0,115200")
+//!     .console("tty0")
+//!     .flag("rw")
+//!     .extend_str("quiet loglevel=3")?
+//!     .build()?;
+//! ```
+
+use anyhow::{bail, Result};
+use std::fmt;
+
+/// Parameters the kernel or init legitimately accepts more than once.
+const REPEATABLE_KEYS: &[&str] = &[
+    "console",
+    "memmap",
+    "modprobe.blacklist",
+    "rd.driver.pre",
+    "rd.driver.blacklist",
+    "systemd.mask",
+    "systemd.wants",
+];
+
+/// How a known parameter takes its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParamKind {
+    /// `key` only.
+    Flag,
+    /// `key=value` only.
+    Value,
+    /// Either form.
+    Either,
+}
+
+/// Un-namespaced parameters the kernel, the initramfs or init understand.
+const KNOWN_PARAMS: &[(&str, ParamKind)] = &[
+    ("acpi", ParamKind::Value),
+    ("acpi_backlight", ParamKind::Value),
+    ("acpi_osi", ParamKind::Either),
+    ("alpine_dev", ParamKind::Value),
+    ("alpine_repo", ParamKind::Value),
+    ("amd_iommu", ParamKind::Value),
+    ("apkovl", ParamKind::Value),
+    ("apparmor", ParamKind::Value),
+    ("audit", ParamKind::Value),
+    ("biosdevname", ParamKind::Value),
+    ("BOOT_IMAGE", ParamKind::Value),
+    ("break", ParamKind::Either),
+    ("cgroup_no_v1", ParamKind::Value),
+    ("clocksource", ParamKind::Value),
+    ("cma", ParamKind::Value),
+    ("console", ParamKind::Value),
+    ("consoleblank", ParamKind::Value),
+    ("crashkernel", ParamKind::Value),
+    ("debug", ParamKind::Either),
+    ("default_hugepagesz", ParamKind::Value),
+    ("earlycon", ParamKind::Either),
+    ("earlyprintk", ParamKind::Value),
+    ("efi", ParamKind::Value),
+    ("elevator", ParamKind::Value),
+    ("emergency", ParamKind::Flag),
+    ("enforcing", ParamKind::Value),
+    ("fbcon", ParamKind::Value),
+    ("fips", ParamKind::Value),
+    ("hugepages", ParamKind::Value),
+    ("hugepagesz", ParamKind::Value),
+    ("ignore_loglevel", ParamKind::Flag),
+    ("ima_appraise", ParamKind::Value),
+    ("ima_policy", ParamKind::Value),
+    ("init", ParamKind::Value),
+    ("init_on_alloc", ParamKind::Value),
+    ("init_on_free", ParamKind::Value),
+    ("initcall_debug", ParamKind::Flag),
+    ("initrd", ParamKind::Value),
+    ("intel_iommu", ParamKind::Value),
+    ("iommu", ParamKind::Value),
+    ("ip", ParamKind::Value),
+    ("isolcpus", ParamKind::Value),
+    ("keep_bootcon", ParamKind::Flag),
+    ("lockdown", ParamKind::Value),
+    ("log_buf_len", ParamKind::Value),
+    ("loglevel", ParamKind::Value),
+    ("lsm", ParamKind::Value),
+    ("mem", ParamKind::Value),
+    ("memmap", ParamKind::Value),
+    ("mitigations", ParamKind::Value),
+    ("modloop", ParamKind::Value),
+    ("modules", ParamKind::Value),
+    ("modules-load", ParamKind::Value),
+    ("nfsroot", ParamKind::Value),
+    ("nmi_watchdog", ParamKind::Value),
+    ("no_console_suspend", ParamKind::Flag),
+    ("noacpi", ParamKind::Flag),
+    ("noapic", ParamKind::Flag),
+    ("noefi", ParamKind::Flag),
+    ("nohz", ParamKind::Value),
+    ("nokaslr", ParamKind::Flag),
+    ("nolapic", ParamKind::Flag),
+    ("nomodeset", ParamKind::Flag),
+    ("noresume", ParamKind::Flag),
+    ("nosmp", ParamKind::Flag),
+    ("nosmt", ParamKind::Either),
+    ("nosplash", ParamKind::Flag),
+    ("nowatchdog", ParamKind::Flag),
+    ("numa", ParamKind::Value),
+    ("panic", ParamKind::Value),
+    ("pci", ParamKind::Value),
+    ("pcie_aspm", ParamKind::Value),
+    ("preempt", ParamKind::Value),
+    ("psi", ParamKind::Value),
+    ("pti", ParamKind::Value),
+    ("quiet", ParamKind::Flag),
+    ("rdinit", ParamKind::Value),
+    ("reboot", ParamKind::Value),
+    ("rescue", ParamKind::Flag),
+    ("resume", ParamKind::Value),
+    ("resume_offset", ParamKind::Value),
+    ("rhgb", ParamKind::Flag),
+    ("ro", ParamKind::Flag),
+    ("root", ParamKind::Value),
+    ("rootdelay", ParamKind::Value),
+    ("rootflags", ParamKind::Value),
+    ("rootfstype", ParamKind::Value),
+    ("rootwait", ParamKind::Flag),
+    ("rw", ParamKind::Flag),
+    ("security", ParamKind::Value),
+    ("selinux", ParamKind::Value),
+    ("single", ParamKind::Flag),
+    ("slab_nomerge", ParamKind::Flag),
+    ("splash", ParamKind::Either),
+    ("ssh_key", ParamKind::Value),
+    ("swiotlb", ParamKind::Value),
+    ("threadirqs", ParamKind::Flag),
+    ("transparent_hugepage", ParamKind::Value),
+    ("tsc", ParamKind::Value),
+    ("usbdelay", ParamKind::Value),
+    ("vga", ParamKind::Value),
+    ("video", ParamKind::Value),
+    ("vsyscall", ParamKind::Value),
+];
+
+/// A single `key` or `key=value` token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CmdlineParam {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl CmdlineParam {
+    /// Parse one unquoted token such as `root=LABEL=X` or `quiet`.
+    pub fn parse(token: &str) -> Self {
+        match token.split_once('=') {
+            Some((key, value)) => Self {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            },
+            None => Self {
+                key: token.to_string(),
+                value: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for CmdlineParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) if value.contains(char::is_whitespace) => {
+                write!(f, "{}=\"{}\"", self.key, value)
+            }
+            Some(value) => write!(f, "{}={}", self.key, value),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+/// Typed kernel command line builder.
+#[derive(Debug, Clone, Default)]
+pub struct CmdlineBuilder {
+    params: Vec<CmdlineParam>,
+}
+
+impl CmdlineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse an existing command line string.
+    pub fn parse(cmdline: &str) -> Result<Self> {
+        Self::new().extend_str(cmdline)
+    }
+
+    /// Append every token of a command line string, honouring double quotes.
+    pub fn extend_str(mut self, cmdline: &str) -> Result<Self> {
+        for token in split_cmdline(cmdline)? {
+            self.params.push(CmdlineParam::parse(&token));
+        }
+        Ok(self)
+    }
+
+    /// Append tokens from a contract array (each entry may hold several tokens).
+    pub fn extend_tokens<S: AsRef<str>>(mut self, tokens: &[S]) -> Result<Self> {
+        for token in tokens {
+            self = self.extend_str(token.as_ref())?;
+        }
+        Ok(self)
+    }
+
+    /// Append a bare flag such as `quiet` or `rw`.
+    pub fn flag(mut self, key: &str) -> Self {
+        self.params.push(CmdlineParam {
+            key: key.to_string(),
+            value: None,
+        });
+        self
+    }
+
+    /// Append `key=value`.
+    pub fn param(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.params.push(CmdlineParam {
+            key: key.to_string(),
+            value: Some(value.into()),
+        });
+        self
+    }
+
+    pub fn root(self, spec: &str) -> Self {
+        self.param("root", spec)
+    }
+
+    pub fn console(self, spec: &str) -> Self {
+        self.param("console", spec)
+    }
+
+    pub fn init(self, path: &str) -> Self {
+        self.param("init", path)
+    }
+
+    pub fn loglevel(self, level: u8) -> Self {
+        self.param("loglevel", level.to_string())
+    }
+
+    /// Drop every parameter named `key`, whatever its value.
+    pub fn remove(mut self, key: &str) -> Self {
+        self.params.retain(|p| p.key != key);
+        self
+    }
+
+    /// Whether a token is present; `key` matches any value, `key=value` must match exactly.
+    pub fn contains(&self, token: &str) -> bool {
+        let wanted = CmdlineParam::parse(token);
+        self.params
+            .iter()
+            .any(|p| p.key == wanted.key && (wanted.value.is_none() || p.value == wanted.value))
+    }
+
+    /// Fail unless every required token is present.
+    pub fn require<S: AsRef<str>>(&self, required: &[S]) -> Result<()> {
+        let missing: Vec<&str> = required
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|token| !self.contains(token))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "kernel cmdline '{}' is missing required token(s): {}",
+                self.render(),
+                missing.join(" ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Validate and render the command line.
+    pub fn build(&self) -> Result<String> {
+        let mut seen: Vec<&str> = Vec::new();
+        for param in &self.params {
+            if param.key.is_empty() {
+                bail!("kernel cmdline has an empty parameter name");
+            }
+            if param.value.as_deref().is_some_and(|v| v.contains('"')) {
+                bail!(
+                    "kernel cmdline value for '{}' contains a double quote, which the kernel cannot parse",
+                    param.key
+                );
+            }
+            check_known(param)?;
+            if !REPEATABLE_KEYS.contains(&param.key.as_str()) {
+                if seen.contains(&param.key.as_str()) {
+                    bail!(
+                        "kernel cmdline sets '{}' more than once: {}",
+                        param.key,
+                        self.render()
+                    );
+                }
+                seen.push(&param.key);
+            }
+        }
+        Ok(self.render())
+    }
+
+    fn render(&self) -> String {
+        self.params
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Check an un-namespaced parameter against [`KNOWN_PARAMS`].
+fn check_known(param: &CmdlineParam) -> Result<()> {
+    if param.key.contains('.') {
+        return Ok(());
+    }
+    let Some((_, kind)) = KNOWN_PARAMS.iter().find(|(key, _)| *key == param.key) else {
+        bail!(
+            "kernel cmdline parameter '{}' is not a known kernel, initramfs or init parameter\n\
+             Remediation: fix the spelling, or pass module parameters as <module>.<param>",
+            param
+        );
+    };
+    match (kind, &param.value) {
+        (ParamKind::Flag, Some(_)) => {
+            bail!(
+                "kernel cmdline flag '{}' takes no value: {}",
+                param.key,
+                param
+            )
+        }
+        (ParamKind::Value, None) => bail!("kernel cmdline parameter '{}' needs a value", param.key),
+        _ => {}
+    }
+    let level = param.value.as_deref().and_then(|v| v.parse::<u8>().ok());
+    if param.key == "loglevel" && level.is_none_or(|level| level > 7) {
+        bail!("kernel cmdline loglevel must be 0-7: {}", param);
+    }
+    Ok(())
+}
+
+/// Split on whitespace outside of double quotes, stripping the quotes.
+fn split_cmdline(cmdline: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in cmdline.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if in_quotes {
+        bail!("unterminated quote in kernel cmdline: {}", cmdline);
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_round_trip() {
+        let cmdline = CmdlineBuilder::new()
+            .root("LABEL=ACORN")
+            .console("tty0")
+            .console("ttyS0,115200n8")
+            .flag("rw")
+            .param("acpi_osi", "Windows 2020")
+            .build()
+            .unwrap();
+        assert_eq!(
+            cmdline,
+            "root=LABEL=ACORN console=tty0 console=ttyS0,115200n8 rw acpi_osi=\"Windows 2020\""
+        );
+
+        let reparsed = CmdlineBuilder::parse(&cmdline).unwrap();
+        assert_eq!(reparsed.build().unwrap(), cmdline);
+    }
+
+    #[test]
+    fn test_duplicate_rejected() {
+        let err = CmdlineBuilder::parse("root=/dev/vda2 quiet root=/dev/vda3")
+            .unwrap()
+            .build()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'root' more than once"), "{}", err);
+    }
+
+    #[test]
+    fn test_require() {
+        let builder = CmdlineBuilder::parse("console=ttyS0 rd.live.image quiet").unwrap();
+        builder
+            .require(&["rd.live.image", "console=ttyS0"])
+            .unwrap();
+        let err = builder
+            .require(&["console=tty0", "systemd.unit=multi-user.target"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("console=tty0 systemd.unit=multi-user.target"));
+    }
+
+    #[test]
+    fn test_known_params() {
+        let build = |cmdline: &str| CmdlineBuilder::parse(cmdline).unwrap().build();
+        build("root=live:LABEL=X rd.live.image i915.modeset=0 quiet splash=silent loglevel=3")
+            .unwrap();
+        let err = build("root=/dev/vda2 quite").unwrap_err().to_string();
+        assert!(err.contains("'quite' is not a known"), "{}", err);
+        let err = build("quiet=1").unwrap_err().to_string();
+        assert!(err.contains("'quiet' takes no value"), "{}", err);
+        let err = build("root").unwrap_err().to_string();
+        assert!(err.contains("'root' needs a value"), "{}", err);
+        assert!(build("loglevel=9").is_err());
+    }
+
+    #[test]
+    fn test_unterminated_quote() {
+        assert!(CmdlineBuilder::parse("foo=\"bar").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Serial console devices of the live session, configured per variant.
+//!
+//! Live overlays start an autologin getty on `ttyS0` at 115200 baud, and the
+//! boot tests read that port. Hardware without a 16550 UART (`ttyAMA0` on
+//! aarch64, `hvc0` on virtio-console) ships `consoles.toml`:
+//!
+//! ```toml
+//! devices = ["hvc0", "ttyS1"]   # the first one is what the boot tests watch
+//! baud = 115200
+//! ```
+//!
+//! The devices feed the OpenRC inittab, the systemd `serial-getty@` wants
+//! links and the QEMU serial wiring of the boot tests
+//! ([`crate::qemu::console_args`]). The boot menu's serial entry uses the
+//! first device ([`SerialConsoles::kernel_console`]); the live kernel command
+//! line still names its own `console=`, so keep it pointing there too.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Variant-local console settings file.
+pub const CONSOLES_CONFIG_FILENAME: &str = "consoles.toml";
+
+/// Console used when a variant configures none.
+pub const DEFAULT_CONSOLE_DEVICE: &str = "ttyS0";
+
+/// Line speed used when a variant configures none.
+pub const DEFAULT_CONSOLE_BAUD: u32 = 115200;
+
+/// Speeds agetty falls back to after the configured one.
+const FALLBACK_BAUDS: &[u32] = &[115200, 57600, 38400, 9600];
+
+/// Serial consoles that get an autologin getty.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConsoles {
+    /// Device names under `/dev`, primary first.
+    pub devices: Vec<String>,
+    pub baud: u32,
+}
+
+impl Default for SerialConsoles {
+    fn default() -> Self {
+        Self {
+            devices: vec![DEFAULT_CONSOLE_DEVICE.to_string()],
+            baud: DEFAULT_CONSOLE_BAUD,
+        }
+    }
+}
+
+impl SerialConsoles {
+    /// Load `consoles.toml` from `variant_dir`; `None` when the variant has
+    /// none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(CONSOLES_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", path.display()))?;
+        Ok(Some(config))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.devices.is_empty() {
+            bail!("devices must list at least one console");
+        }
+        for (i, device) in self.devices.iter().enumerate() {
+            let valid = device.len() <= 32
+                && device.starts_with(|c: char| c.is_ascii_alphabetic())
+                && device.chars().all(|c| c.is_ascii_alphanumeric());
+            if !valid {
+                bail!(
+                    "invalid console device '{}': use a name under /dev such as ttyS1, ttyAMA0 or hvc0",
+                    device
+                );
+            }
+            if self.devices[..i].contains(device) {
+                bail!("console device '{}' listed twice", device);
+            }
+        }
+        if self.baud == 0 {
+            bail!("baud must be positive");
+        }
+        Ok(())
+    }
+
+    /// The console the boot tests attach to.
+    pub fn primary(&self) -> &str {
+        self.devices
+            .first()
+            .map_or(DEFAULT_CONSOLE_DEVICE, String::as_str)
+    }
+
+    /// `console=` value for the primary device, e.g. `ttyS0,115200`.
+    pub fn kernel_console(&self) -> String {
+        format!("{},{}", self.primary(), self.baud)
+    }
+
+    /// Busybox inittab entries running the autologin wrapper on every
+    /// device, one per line.
+    pub fn inittab_entries(&self) -> String {
+        self.devices
+            .iter()
+            .map(|device| {
+                format!(
+                    "{device}::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin {} {device} vt100",
+                    self.baud
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// `serial-getty@` instances to enable.
+    pub fn systemd_getty_units(&self) -> impl Iterator<Item = String> + '_ {
+        self.devices
+            .iter()
+            .map(|device| format!("serial-getty@{}.service", device))
+    }
+
+    /// agetty `--keep-baud` list: the configured speed, then the usual
+    /// fallbacks.
+    pub fn agetty_bauds(&self) -> String {
+        let mut bauds = vec![self.baud];
+        bauds.extend(FALLBACK_BAUDS.iter().filter(|baud| **baud != self.baud));
+        bauds
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_default_matches_legacy_console() {
+        let consoles = SerialConsoles::default();
+        assert_eq!(consoles.primary(), "ttyS0");
+        assert_eq!(
+            consoles.inittab_entries(),
+            "ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0 vt100"
+        );
+        assert_eq!(consoles.agetty_bauds(), "115200,57600,38400,9600");
+    }
+
+    #[test]
+    fn test_variant_consoles() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(SerialConsoles::load_for_variant(temp.path()).unwrap(), None);
+
+        let path = temp.path().join(CONSOLES_CONFIG_FILENAME);
+        fs::write(&path, "devices = [\"hvc0\", \"ttyAMA0\"]\nbaud = 9600\n").unwrap();
+        let consoles = SerialConsoles::load_for_variant(temp.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(consoles.primary(), "hvc0");
+        assert_eq!(consoles.inittab_entries().lines().count(), 2);
+        assert!(consoles.inittab_entries().contains(" 9600 ttyAMA0 vt100"));
+        assert_eq!(
+            consoles.systemd_getty_units().collect::<Vec<_>>(),
+            ["serial-getty@hvc0.service", "serial-getty@ttyAMA0.service"]
+        );
+        assert_eq!(consoles.agetty_bauds(), "9600,115200,57600,38400");
+
+        for bad in [
+            "devices = []\n",
+            "devices = [\"../ttyS0\"]\n",
+            "devices = [\"ttyS0\", \"ttyS0\"]\n",
+            "baud = 0\n",
+            "speed = 9600\n",
+        ] {
+            fs::write(&path, bad).unwrap();
+            assert!(
+                SerialConsoles::load_for_variant(temp.path()).is_err(),
+                "{bad:?} should be rejected"
+            );
+        }
+    }
+}
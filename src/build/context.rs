@@ -1,12 +1,19 @@
 //! Build context and distro configuration.
 //!
 //! Traits and enums are defined in `distro-builder::contracts` and re-exported here.
-//! `SimpleBuildContext` is an implementation that lives in distro-builder.
+//! `SimpleBuildContext` and the layered `TomlBuildContext` are the
+//! implementations that live in distro-builder.
 
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::contracts::kernel::{FirmwareCompression, KernelInstallConfig, ModuleCompression};
 
 // Re-export contracts from distro-builder contracts module
-pub use crate::contracts::context::{BuildContext, DistroConfig, InitSystem, PackageManager};
+pub use crate::contracts::context::{
+    BuildContext, DistroConfig, Features, InitSystem, PackageManager, UidRanges,
+};
 
 /// Simple implementation of BuildContext for basic use cases.
 ///
@@ -34,3 +41,433 @@ impl SimpleBuildContext {
         }
     }
 }
+
+/// Variant-local layered build settings file.
+pub const BUILD_CONTEXT_FILENAME: &str = "build-context.toml";
+
+/// Prefix for environment variables that override individual settings,
+/// e.g. `DISTRO_BUILDER_CFG_ISO_LABEL=TESTISO`.
+pub const ENV_OVERRIDE_PREFIX: &str = "DISTRO_BUILDER_CFG_";
+
+/// Plain settings backing [`TomlBuildContext`].
+///
+/// Path fields are relative to the context's base directory unless absolute.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildSettings {
+    pub os_name: String,
+    pub os_id: String,
+    pub iso_label: String,
+    pub os_version: Option<String>,
+    pub home_url: Option<String>,
+    pub ansi_color: Option<String>,
+    pub boot_modules: Vec<String>,
+    pub uid_ranges: UidRanges,
+    pub default_shell: String,
+    pub init_system: InitSystem,
+    pub module_install_path: String,
+    pub kernel_filename: String,
+    pub module_compression: ModuleCompression,
+    pub strip_modules: bool,
+    pub firmware_compression: FirmwareCompression,
+    pub required_modules: Vec<String>,
+    pub forbidden_modules: Vec<String>,
+    /// Feature flags, e.g. `DISTRO_BUILDER_CFG_FEATURES=wifi,debug-tools`.
+    pub features: Vec<String>,
+    /// Name the release run and checkout commit on live banners. They
+    /// differ between otherwise identical builds, so this is off unless a
+    /// variant gives up reproducible images for it.
+    pub banner_build_id: bool,
+    pub source: PathBuf,
+    pub staging: PathBuf,
+    pub output: PathBuf,
+}
+
+impl Default for BuildSettings {
+    fn default() -> Self {
+        Self {
+            os_name: String::new(),
+            os_id: String::new(),
+            iso_label: String::new(),
+            os_version: None,
+            home_url: None,
+            ansi_color: None,
+            boot_modules: Vec::new(),
+            uid_ranges: UidRanges::default(),
+            default_shell: "/bin/sh".to_string(),
+            init_system: InitSystem::Systemd,
+            module_install_path: "/usr/lib/modules".to_string(),
+            kernel_filename: "vmlinuz".to_string(),
+            module_compression: ModuleCompression::None,
+            strip_modules: false,
+            firmware_compression: FirmwareCompression::None,
+            required_modules: Vec::new(),
+            forbidden_modules: Vec::new(),
+            features: Vec::new(),
+            banner_build_id: false,
+            source: PathBuf::from("rootfs-source"),
+            staging: PathBuf::from("staging"),
+            output: PathBuf::from("output"),
+        }
+    }
+}
+
+impl BuildSettings {
+    /// Defaults seeded with a distro identity.
+    pub fn with_identity(os_name: &str, os_id: &str, iso_label: &str) -> Self {
+        Self {
+            os_name: os_name.to_string(),
+            os_id: os_id.to_string(),
+            iso_label: iso_label.to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+/// [`BuildContext`] and [`DistroConfig`] loaded from layered configuration.
+///
+/// Layers are applied in order, later ones winning per key:
+///
+/// 1. the `defaults` passed by the caller
+/// 2. the variant TOML file, if it exists
+/// 3. `DISTRO_BUILDER_CFG_<KEY>` environment variables
+///
+/// Environment values starting with `[` or `"` are parsed as TOML
+/// (`["a", "b"]`, `"quoted"`), anything else is taken as a plain string;
+/// comma-separated values are accepted for list settings such as
+/// `boot_modules`.
+#[derive(Debug, Clone)]
+pub struct TomlBuildContext {
+    base_dir: PathBuf,
+    settings: BuildSettings,
+    source: PathBuf,
+    staging: PathBuf,
+    output: PathBuf,
+    features: Features,
+    layers: Vec<String>,
+}
+
+impl TomlBuildContext {
+    /// Load settings from `defaults`, `variant_toml`, and the process environment.
+    pub fn load(base_dir: &Path, defaults: BuildSettings, variant_toml: &Path) -> Result<Self> {
+        Self::load_with_env(base_dir, defaults, variant_toml, |key| {
+            std::env::var(key).ok()
+        })
+    }
+
+    /// Like [`load`](Self::load) with an explicit environment lookup.
+    pub fn load_with_env(
+        base_dir: &Path,
+        defaults: BuildSettings,
+        variant_toml: &Path,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let mut layers = vec!["defaults".to_string()];
+        let mut merged = toml::Value::try_from(&defaults).context("Failed to encode defaults")?;
+        let table = merged
+            .as_table_mut()
+            .context("build settings must serialize to a table")?;
+
+        if variant_toml.is_file() {
+            let content = std::fs::read_to_string(variant_toml)
+                .with_context(|| format!("Failed to read {}", variant_toml.display()))?;
+            let file: toml::Table = toml::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", variant_toml.display()))?;
+            for (key, value) in file {
+                if !table.contains_key(&key) && !is_optional_setting(&key) {
+                    bail!(
+                        "unknown build setting '{}' in {}",
+                        key,
+                        variant_toml.display()
+                    );
+                }
+                table.insert(key, value);
+            }
+            layers.push(variant_toml.display().to_string());
+        }
+
+        let keys: Vec<String> = table
+            .keys()
+            .cloned()
+            .chain(
+                ["os_version", "home_url", "ansi_color"]
+                    .into_iter()
+                    .map(String::from),
+            )
+            .collect();
+        for key in keys {
+            let var = format!("{}{}", ENV_OVERRIDE_PREFIX, key.to_uppercase());
+            if let Some(raw) = env(&var) {
+                let is_list = matches!(table.get(&key), Some(toml::Value::Array(_)));
+                let value = match (table.get(&key), raw.trim().parse::<bool>()) {
+                    (Some(toml::Value::Boolean(_)), Ok(flag)) => toml::Value::Boolean(flag),
+                    _ => env_value(&raw, is_list),
+                };
+                table.insert(key, value);
+                layers.push(format!("env:{}", var));
+            }
+        }
+
+        let settings: BuildSettings = merged
+            .try_into()
+            .context("Failed to apply layered build settings")?;
+        Features::parse(&settings.features).context("Failed to apply layered build settings")?;
+        Ok(Self::from_settings(base_dir, settings, layers))
+    }
+
+    /// Build a context directly from already resolved settings.
+    pub fn from_settings(base_dir: &Path, settings: BuildSettings, layers: Vec<String>) -> Self {
+        let resolve = |p: &Path| {
+            if p.is_absolute() {
+                p.to_path_buf()
+            } else {
+                base_dir.join(p)
+            }
+        };
+        // Invalid names are rejected by `load_with_env`; here they are dropped.
+        let features = Features::parse(&settings.features).unwrap_or_else(|err| {
+            eprintln!("  [WARN] {:#}; ignoring feature flags", err);
+            Features::default()
+        });
+        Self {
+            base_dir: base_dir.to_path_buf(),
+            source: resolve(&settings.source),
+            staging: resolve(&settings.staging),
+            output: resolve(&settings.output),
+            settings,
+            features,
+            layers,
+        }
+    }
+
+    /// The resolved settings.
+    pub fn settings(&self) -> &BuildSettings {
+        &self.settings
+    }
+
+    /// Which layers contributed, in application order.
+    pub fn layers(&self) -> &[String] {
+        &self.layers
+    }
+
+    /// Effective configuration and its provenance, for run manifests.
+    pub fn effective_config_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "settings": self.settings,
+            "layers": self.layers,
+        })
+    }
+}
+
+impl KernelInstallConfig for TomlBuildContext {
+    fn module_install_path(&self) -> &str {
+        &self.settings.module_install_path
+    }
+
+    fn kernel_filename(&self) -> &str {
+        &self.settings.kernel_filename
+    }
+
+    fn module_compression(&self) -> ModuleCompression {
+        self.settings.module_compression
+    }
+
+    fn strip_modules(&self) -> bool {
+        self.settings.strip_modules
+    }
+
+    fn firmware_compression(&self) -> FirmwareCompression {
+        self.settings.firmware_compression
+    }
+
+    fn required_modules(&self) -> &[String] {
+        &self.settings.required_modules
+    }
+
+    fn forbidden_modules(&self) -> &[String] {
+        &self.settings.forbidden_modules
+    }
+}
+
+impl DistroConfig for TomlBuildContext {
+    fn os_name(&self) -> &str {
+        &self.settings.os_name
+    }
+
+    fn os_id(&self) -> &str {
+        &self.settings.os_id
+    }
+
+    fn iso_label(&self) -> &str {
+        &self.settings.iso_label
+    }
+
+    fn boot_modules(&self) -> &[String] {
+        &self.settings.boot_modules
+    }
+
+    fn default_shell(&self) -> &str {
+        &self.settings.default_shell
+    }
+
+    fn init_system(&self) -> InitSystem {
+        self.settings.init_system
+    }
+
+    fn os_version(&self) -> Option<&str> {
+        self.settings.os_version.as_deref()
+    }
+
+    fn home_url(&self) -> Option<&str> {
+        self.settings.home_url.as_deref()
+    }
+
+    fn ansi_color(&self) -> Option<&str> {
+        self.settings.ansi_color.as_deref()
+    }
+
+    fn uid_ranges(&self) -> UidRanges {
+        self.settings.uid_ranges
+    }
+
+    fn features(&self) -> &Features {
+        &self.features
+    }
+}
+
+impl BuildContext for TomlBuildContext {
+    fn source(&self) -> &Path {
+        &self.source
+    }
+
+    fn staging(&self) -> &Path {
+        &self.staging
+    }
+
+    fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    fn output(&self) -> &Path {
+        &self.output
+    }
+
+    fn config(&self) -> &dyn DistroConfig {
+        self
+    }
+}
+
+/// Feature flags of the variant at `variant_dir`: its
+/// [`BUILD_CONTEXT_FILENAME`] plus the `DISTRO_BUILDER_CFG_FEATURES`
+/// override.
+pub fn load_variant_features(variant_dir: &Path) -> Result<Features> {
+    let context = TomlBuildContext::load(
+        variant_dir,
+        BuildSettings::default(),
+        &variant_dir.join(BUILD_CONTEXT_FILENAME),
+    )?;
+    Ok(context.features().clone())
+}
+
+/// Whether live banners of the variant at `variant_dir` name the build
+/// ([`BuildSettings::banner_build_id`]).
+pub fn load_variant_banner_build_id(variant_dir: &Path) -> Result<bool> {
+    let context = TomlBuildContext::load(
+        variant_dir,
+        BuildSettings::default(),
+        &variant_dir.join(BUILD_CONTEXT_FILENAME),
+    )?;
+    Ok(context.settings().banner_build_id)
+}
+
+/// Optional settings are omitted from the serialized defaults when unset.
+fn is_optional_setting(key: &str) -> bool {
+    matches!(key, "os_version" | "home_url" | "ansi_color")
+}
+
+fn env_value(raw: &str, is_list: bool) -> toml::Value {
+    let trimmed = raw.trim_start();
+    if trimmed.starts_with('[') || trimmed.starts_with('"') {
+        if let Ok(mut wrapper) = toml::from_str::<toml::Table>(&format!("v = {}", raw)) {
+            if let Some(value) = wrapper.remove("v") {
+                return value;
+            }
+        }
+    }
+    if is_list {
+        return toml::Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| toml::Value::String(s.to_string()))
+                .collect(),
+        );
+    }
+    toml::Value::String(raw.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_layers_apply_in_order() {
+        let temp = TempDir::new().unwrap();
+        let toml_path = temp.path().join("build-context.toml");
+        std::fs::write(
+            &toml_path,
+            "iso_label = \"FROMFILE\"\ninit_system = \"openrc\"\nos_version = \"3.1\"\nmodule_compression = \"zstd\"\nfirmware_compression = \"auto\"\n",
+        )
+        .unwrap();
+
+        let ctx = TomlBuildContext::load_with_env(
+            temp.path(),
+            BuildSettings::with_identity("AcornOS", "acornos", "ACORNOS"),
+            &toml_path,
+            |key| match key {
+                "DISTRO_BUILDER_CFG_ISO_LABEL" => Some("FROMENV".to_string()),
+                "DISTRO_BUILDER_CFG_BOOT_MODULES" => Some("erofs, overlay".to_string()),
+                "DISTRO_BUILDER_CFG_STRIP_MODULES" => Some("true".to_string()),
+                "DISTRO_BUILDER_CFG_FEATURES" => Some("wifi,debug-tools".to_string()),
+                _ => None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(ctx.os_name(), "AcornOS");
+        assert_eq!(ctx.iso_label(), "FROMENV");
+        assert_eq!(ctx.init_system(), InitSystem::OpenRC);
+        assert_eq!(ctx.os_version(), Some("3.1"));
+        assert_eq!(ctx.boot_modules(), &["erofs", "overlay"]);
+        assert_eq!(ctx.staging(), temp.path().join("staging"));
+        assert_eq!(ctx.module_compression(), ModuleCompression::Zstd);
+        assert!(ctx.strip_modules());
+        assert_eq!(ctx.firmware_compression(), FirmwareCompression::Auto);
+        assert!(ctx.features().contains("debug-tools"));
+        assert!(!ctx.features().contains("desktop"));
+        assert!(!ctx.settings().banner_build_id);
+        assert_eq!(ctx.layers().len(), 6);
+        assert_eq!(
+            ctx.effective_config_json()["settings"]["iso_label"],
+            "FROMENV"
+        );
+    }
+
+    #[test]
+    fn test_unknown_key_rejected() {
+        let temp = TempDir::new().unwrap();
+        let toml_path = temp.path().join("build-context.toml");
+        std::fs::write(&toml_path, "iso_lable = \"TYPO\"\n").unwrap();
+
+        let err = TomlBuildContext::load_with_env(
+            temp.path(),
+            BuildSettings::default(),
+            &toml_path,
+            |_| None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("iso_lable"), "{}", err);
+    }
+}
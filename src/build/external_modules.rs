@@ -0,0 +1,332 @@
+//! Out-of-tree kernel modules rebuilt alongside the kernel (DKMS-style).
+//!
+//! A variant lists third-party module sources in a registry file. Whenever
+//! the kernel payload changes, every registered module is rebuilt against
+//! the new kernel build tree and installed under `<modules>/<release>/extra`,
+//! so the payload never carries modules built for a different kernel.
+//!
+//! The registry digest covers each module's name, version, make arguments,
+//! and source tree contents. Callers fold it into the kernel payload cache
+//! key so editing a module source invalidates cached payloads.
+//!
+//! ```toml
+//! [[module]]
+//! name = "r8168"
+//! version = "8.053.00"
+//! source = "external/r8168"       # relative to the registry file
+//! make_args = ["ENABLE_USE_FIRMWARE_FILE=y"]
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::build::modules::run_staged_depmod;
+use crate::process::Cmd;
+
+/// Registry file name inside a variant's `build-host/kernel/` directory.
+pub const EXTERNAL_MODULES_FILENAME: &str = "external-modules.toml";
+
+/// Stamp recording which registry digest a staged tree was built with.
+pub const EXTERNAL_MODULES_STAMP: &str = ".external-modules.sha256";
+
+/// Directory under `<modules>/<release>/` that external modules install into.
+pub const EXTERNAL_MODULES_SUBDIR: &str = "extra";
+
+/// Build outputs skipped when hashing a module source tree.
+const BUILD_OUTPUT_EXTENSIONS: &[&str] = &["o", "ko", "mod", "symvers", "order", "cmd"];
+
+/// One out-of-tree module source.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalModule {
+    pub name: String,
+    pub version: String,
+    pub source: PathBuf,
+    #[serde(default)]
+    pub make_args: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    #[serde(default)]
+    module: Vec<ExternalModule>,
+}
+
+/// Registered external modules for one variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExternalModuleRegistry {
+    modules: Vec<ExternalModule>,
+}
+
+impl ExternalModuleRegistry {
+    /// Load a registry; a missing file is an empty registry.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let file: RegistryFile = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        let mut modules = file.module;
+        for module in &mut modules {
+            if module.source.is_relative() {
+                module.source = base.join(&module.source);
+            }
+        }
+        modules.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(pair) = modules.windows(2).find(|w| w[0].name == w[1].name) {
+            bail!(
+                "external module '{}' registered twice in {}",
+                pair[0].name,
+                path.display()
+            );
+        }
+        Ok(Self { modules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    pub fn modules(&self) -> &[ExternalModule] {
+        &self.modules
+    }
+
+    /// Digest of every registered module, including source contents.
+    pub fn digest(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for module in &self.modules {
+            hasher.update(module.name.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(module.version.as_bytes());
+            hasher.update(b"\0");
+            for arg in &module.make_args {
+                hasher.update(arg.as_bytes());
+                hasher.update(b"\0");
+            }
+            hasher.update(hash_source_tree(&module.source)?.as_bytes());
+            hasher.update(b"\n");
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Cache key for a kernel payload that includes these modules.
+    ///
+    /// An empty registry leaves `kernel_key` unchanged so existing payload
+    /// caches stay valid for variants without external modules.
+    pub fn payload_key(&self, kernel_key: &str) -> Result<String> {
+        if self.is_empty() {
+            return Ok(kernel_key.to_string());
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(kernel_key.as_bytes());
+        hasher.update(b"\0external-modules\0");
+        hasher.update(self.digest()?.as_bytes());
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Stamp recording these modules built against `kernel_release`; the
+    /// modules must be rebuilt when either side changes.
+    pub fn stamp(&self, kernel_release: &str) -> Result<String> {
+        self.payload_key(kernel_release)
+    }
+
+    /// Whether `kernel_root` was last populated with this registry's modules
+    /// built against `kernel_release`.
+    pub fn is_current(&self, kernel_root: &Path, kernel_release: &str) -> Result<bool> {
+        if self.is_empty() {
+            return Ok(true);
+        }
+        let stamp = fs::read_to_string(kernel_root.join(EXTERNAL_MODULES_STAMP)).ok();
+        Ok(stamp.as_deref().map(str::trim) == Some(self.stamp(kernel_release)?.as_str()))
+    }
+
+    /// Build every registered module against `kernel_build_dir` and install it
+    /// into `staging`, then regenerate depmod indexes and write the stamp
+    /// into `kernel_root`.
+    pub fn rebuild(
+        &self,
+        kernel_build_dir: &Path,
+        staging: &Path,
+        module_install_path: &str,
+        kernel_release: &str,
+        kernel_root: &Path,
+    ) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if !kernel_build_dir.join("Module.symvers").is_file() {
+            bail!(
+                "kernel build tree '{}' has no Module.symvers; external modules need the full kernel build\n\
+                 Remediation: rebuild the kernel (cargo xtask kernels build --rebuild) so the build tree is available.",
+                kernel_build_dir.display()
+            );
+        }
+
+        let modules_root = staging.join(module_install_path.trim_start_matches('/'));
+        let extra_dir = modules_root
+            .join(kernel_release)
+            .join(EXTERNAL_MODULES_SUBDIR);
+        if extra_dir.exists() {
+            fs::remove_dir_all(&extra_dir)
+                .with_context(|| format!("Failed to clear {}", extra_dir.display()))?;
+        }
+        // INSTALL_MOD_PATH is the prefix in front of `lib/modules`.
+        let install_prefix = modules_root
+            .parent()
+            .and_then(Path::parent)
+            .context("module install path must end in lib/modules")?;
+
+        for module in &self.modules {
+            println!(
+                "  Building external module {} {} for {}",
+                module.name, module.version, kernel_release
+            );
+            if !module.source.is_dir() {
+                bail!(
+                    "external module '{}' source not found: {}",
+                    module.name,
+                    module.source.display()
+                );
+            }
+            let m_arg = format!("M={}", module.source.display());
+            Cmd::new("make")
+                .arg("-C")
+                .arg_path(kernel_build_dir)
+                .arg(&m_arg)
+                .args(&module.make_args)
+                .arg("modules")
+                .error_msg(format!("building external module '{}' failed", module.name))
+                .run_interactive()?;
+            Cmd::new("make")
+                .arg("-C")
+                .arg_path(kernel_build_dir)
+                .arg(&m_arg)
+                .arg(format!("INSTALL_MOD_PATH={}", install_prefix.display()))
+                .arg(format!("INSTALL_MOD_DIR={}", EXTERNAL_MODULES_SUBDIR))
+                // Indexes are regenerated once below, against the staged tree.
+                .arg("DEPMOD=true")
+                .args(&module.make_args)
+                .arg("modules_install")
+                .error_msg(format!(
+                    "installing external module '{}' failed",
+                    module.name
+                ))
+                .run_interactive()?;
+        }
+
+        run_staged_depmod(staging, module_install_path, kernel_release)?;
+        fs::write(
+            kernel_root.join(EXTERNAL_MODULES_STAMP),
+            format!("{}\n", self.stamp(kernel_release)?),
+        )
+        .context("writing external modules stamp")?;
+        Ok(())
+    }
+}
+
+/// Hash a module source tree, ignoring dotfiles and kbuild outputs.
+fn hash_source_tree(root: &Path) -> Result<String> {
+    if !root.is_dir() {
+        bail!("external module source not found: {}", root.display());
+    }
+    let mut hasher = Sha256::new();
+    let walker = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker {
+        let entry = entry.with_context(|| format!("walking {}", root.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let is_output = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| BUILD_OUTPUT_EXTENSIONS.contains(&e))
+            || path.to_string_lossy().ends_with(".mod.c");
+        if is_output {
+            continue;
+        }
+        let rel = path.strip_prefix(root).unwrap_or(path);
+        hasher.update(rel.to_string_lossy().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(fs::read(path).with_context(|| format!("reading {}", path.display()))?);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn registry_with_source(temp: &TempDir) -> (PathBuf, PathBuf) {
+        let src = temp.path().join("external/r8168");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("r8168_n.c"), "int x;\n").unwrap();
+        fs::write(src.join("Makefile"), "obj-m := r8168.o\n").unwrap();
+        let registry = temp.path().join(EXTERNAL_MODULES_FILENAME);
+        fs::write(
+            &registry,
+            "[[module]]\nname = \"r8168\"\nversion = \"8.053.00\"\nsource = \"external/r8168\"\n",
+        )
+        .unwrap();
+        (registry, src)
+    }
+
+    #[test]
+    fn empty_registry_keeps_kernel_key() {
+        let registry = ExternalModuleRegistry::load(Path::new("/nonexistent.toml")).unwrap();
+        assert!(registry.is_empty());
+        assert_eq!(registry.payload_key("abc").unwrap(), "abc");
+    }
+
+    #[test]
+    fn source_edits_change_payload_key_but_build_outputs_do_not() {
+        let temp = TempDir::new().unwrap();
+        let (path, src) = registry_with_source(&temp);
+        let registry = ExternalModuleRegistry::load(&path).unwrap();
+        assert_eq!(registry.modules()[0].source, src);
+
+        let key = registry.payload_key("kernel").unwrap();
+        assert_ne!(key, "kernel");
+
+        fs::write(src.join("r8168_n.o"), "object").unwrap();
+        fs::write(src.join(".r8168_n.o.cmd"), "cmd").unwrap();
+        assert_eq!(registry.payload_key("kernel").unwrap(), key);
+
+        fs::write(src.join("r8168_n.c"), "int y;\n").unwrap();
+        assert_ne!(registry.payload_key("kernel").unwrap(), key);
+    }
+
+    #[test]
+    fn stamp_tracks_digest_and_kernel_release() {
+        let temp = TempDir::new().unwrap();
+        let (path, src) = registry_with_source(&temp);
+        let registry = ExternalModuleRegistry::load(&path).unwrap();
+        let root = temp.path().join("kernel");
+        fs::create_dir_all(&root).unwrap();
+
+        assert!(!registry.is_current(&root, "6.12.0-acorn").unwrap());
+        fs::write(
+            root.join(EXTERNAL_MODULES_STAMP),
+            registry.stamp("6.12.0-acorn").unwrap(),
+        )
+        .unwrap();
+        assert!(registry.is_current(&root, "6.12.0-acorn").unwrap());
+        assert!(!registry.is_current(&root, "6.12.1-acorn").unwrap());
+
+        fs::write(src.join("r8168_n.c"), "int y;\n").unwrap();
+        assert!(!registry.is_current(&root, "6.12.0-acorn").unwrap());
+    }
+}
@@ -0,0 +1,302 @@
+//! Compression of staged firmware blobs.
+//!
+//! `linux-firmware` is the largest single contributor to the live image,
+//! and most of it compresses well. Kernels built with
+//! `CONFIG_FW_LOADER_COMPRESS` look for `<name>.xz` (and, with
+//! `CONFIG_FW_LOADER_COMPRESS_ZSTD`, `<name>.zst`) when `<name>` is missing,
+//! so [`compress_staged_firmware`] compresses every blob in place and
+//! renames firmware symlinks to match. The requested format is checked
+//! against the kernel config first: firmware the kernel cannot decompress
+//! fails to load at boot, which is much harder to diagnose than a build
+//! error.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::artifact::rootfs::format_size_human;
+use crate::contracts::kernel::FirmwareCompression;
+use crate::process::{self, Cmd};
+
+/// Firmware locations relative to the rootfs, usr-merged first.
+const FIRMWARE_DIRS: &[&str] = &["usr/lib/firmware", "lib/firmware"];
+
+/// Extensions of blobs that are already compressed.
+const COMPRESSED_EXTENSIONS: &[&str] = &["xz", "zst"];
+
+/// Size accounting for a compression run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FirmwareReport {
+    pub files: usize,
+    pub links: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Firmware compression formats the kernel config can load, best first.
+pub fn kernel_firmware_compression(kernel_config: &Path) -> Result<Vec<FirmwareCompression>> {
+    let content = fs::read_to_string(kernel_config)
+        .with_context(|| format!("Failed to read {}", kernel_config.display()))?;
+    let enabled = |option: &str| {
+        content
+            .lines()
+            .any(|line| line.trim() == format!("{}=y", option))
+    };
+    if !enabled("CONFIG_FW_LOADER_COMPRESS") {
+        return Ok(Vec::new());
+    }
+    // Before 5.19 the loader only knew xz and had no per-format options.
+    if !content.contains("CONFIG_FW_LOADER_COMPRESS_") {
+        return Ok(vec![FirmwareCompression::Xz]);
+    }
+    let mut supported = Vec::new();
+    if enabled("CONFIG_FW_LOADER_COMPRESS_ZSTD") {
+        supported.push(FirmwareCompression::Zstd);
+    }
+    if enabled("CONFIG_FW_LOADER_COMPRESS_XZ") {
+        supported.push(FirmwareCompression::Xz);
+    }
+    Ok(supported)
+}
+
+/// Resolve `Auto` against the kernel config and reject formats the kernel
+/// cannot load.
+///
+/// Without a config an explicit format is trusted with a warning; `Auto`
+/// falls back to no compression when the kernel supports none.
+pub fn resolve_firmware_compression(
+    requested: FirmwareCompression,
+    kernel_config: Option<&Path>,
+) -> Result<FirmwareCompression> {
+    if requested == FirmwareCompression::None {
+        return Ok(requested);
+    }
+    let supported = match kernel_config {
+        Some(path) if path.is_file() => kernel_firmware_compression(path)?,
+        _ if requested == FirmwareCompression::Auto => bail!(
+            "firmware compression 'auto' needs the kernel .config to read CONFIG_FW_LOADER_COMPRESS*"
+        ),
+        _ => {
+            eprintln!(
+                "  [WARN] No kernel .config to check firmware compression {:?} against",
+                requested
+            );
+            return Ok(requested);
+        }
+    };
+    if requested == FirmwareCompression::Auto {
+        let resolved = supported
+            .first()
+            .copied()
+            .unwrap_or(FirmwareCompression::None);
+        if resolved == FirmwareCompression::None {
+            eprintln!(
+                "  [WARN] Kernel lacks CONFIG_FW_LOADER_COMPRESS; leaving firmware uncompressed"
+            );
+        }
+        return Ok(resolved);
+    }
+    if !supported.contains(&requested) {
+        let option = match requested {
+            FirmwareCompression::Zstd => "CONFIG_FW_LOADER_COMPRESS_ZSTD",
+            _ => "CONFIG_FW_LOADER_COMPRESS_XZ",
+        };
+        bail!(
+            "firmware compression {:?} is not supported by the kernel config\n\
+Remediation: enable CONFIG_FW_LOADER_COMPRESS and {} in the kconfig, or set firmware_compression = \"auto\"",
+            requested,
+            option
+        );
+    }
+    Ok(requested)
+}
+
+/// Compress the firmware under `staging` with the format resolved from
+/// `requested` and the kernel config.
+///
+/// Returns `None` when nothing was done (no firmware staged, or no
+/// compression).
+pub fn compress_staged_firmware(
+    staging: &Path,
+    requested: FirmwareCompression,
+    kernel_config: Option<&Path>,
+) -> Result<Option<FirmwareReport>> {
+    let compression = resolve_firmware_compression(requested, kernel_config)?;
+    if compression == FirmwareCompression::None {
+        return Ok(None);
+    }
+    let Some(firmware_dir) = FIRMWARE_DIRS
+        .iter()
+        .map(|dir| staging.join(dir))
+        .find(|dir| dir.is_dir())
+    else {
+        return Ok(None);
+    };
+    compress_firmware_dir(&firmware_dir, compression).map(Some)
+}
+
+/// Compress every blob under `firmware_dir` and point symlinks at the
+/// compressed names. `compression` must already be resolved.
+pub fn compress_firmware_dir(
+    firmware_dir: &Path,
+    compression: FirmwareCompression,
+) -> Result<FirmwareReport> {
+    let Some(ext) = compression.extension() else {
+        bail!("firmware compression must be resolved before compressing");
+    };
+    let tool = match compression {
+        FirmwareCompression::Zstd => "zstd",
+        _ => "xz",
+    };
+    if !process::exists(tool) {
+        bail!("{} not found. Install {}.", tool, tool);
+    }
+
+    let mut files = Vec::new();
+    let mut links = Vec::new();
+    for entry in WalkDir::new(firmware_dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to walk {}", firmware_dir.display()))?;
+        let already_compressed = entry
+            .path()
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| COMPRESSED_EXTENSIONS.contains(&e));
+        if already_compressed {
+            continue;
+        }
+        if entry.path_is_symlink() {
+            links.push(entry.into_path());
+        } else if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+
+    let mut report = FirmwareReport::default();
+    for file in &files {
+        report.bytes_before += fs::metadata(file)?.len();
+        let output = compress_blob(file, compression, ext)?;
+        report.bytes_after += fs::metadata(&output)?.len();
+        report.files += 1;
+    }
+    for link in &links {
+        if relink(link, ext)? {
+            report.links += 1;
+        }
+    }
+
+    println!(
+        "  Compressed {} firmware files ({}): {} -> {}",
+        report.files,
+        ext,
+        format_size_human(report.bytes_before),
+        format_size_human(report.bytes_after)
+    );
+    Ok(report)
+}
+
+fn compress_blob(file: &Path, compression: FirmwareCompression, ext: &str) -> Result<PathBuf> {
+    let cmd = match compression {
+        // The kernel's xz decoder only handles CRC32 checks.
+        FirmwareCompression::Xz => Cmd::new("xz").args(["--check=crc32", "-f"]),
+        _ => Cmd::new("zstd").args(["-q", "-f", "--rm"]),
+    };
+    cmd.arg_path(file)
+        .error_msg(format!("compressing {} failed", file.display()))
+        .run()?;
+    Ok(with_extension_suffix(file, ext))
+}
+
+/// Replace `link -> target` with `link.<ext> -> target.<ext>` when the
+/// target was compressed. Links to directories are left alone.
+fn relink(link: &Path, ext: &str) -> Result<bool> {
+    let target =
+        fs::read_link(link).with_context(|| format!("Failed to read {}", link.display()))?;
+    let resolved = link.parent().unwrap_or(Path::new("/")).join(&target);
+    if !with_extension_suffix(&resolved, ext).is_file() {
+        return Ok(false);
+    }
+    let new_link = with_extension_suffix(link, ext);
+    fs::remove_file(link).with_context(|| format!("Failed to remove {}", link.display()))?;
+    symlink(with_extension_suffix(&target, ext), &new_link)
+        .with_context(|| format!("Failed to create {}", new_link.display()))?;
+    Ok(true)
+}
+
+fn with_extension_suffix(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_kernel_config_support() {
+        let temp = TempDir::new().unwrap();
+        let config = temp.path().join(".config");
+
+        fs::write(
+            &config,
+            "CONFIG_FW_LOADER_COMPRESS=y\nCONFIG_FW_LOADER_COMPRESS_XZ=y\nCONFIG_FW_LOADER_COMPRESS_ZSTD=y\n",
+        )
+        .unwrap();
+        assert_eq!(
+            resolve_firmware_compression(FirmwareCompression::Auto, Some(&config)).unwrap(),
+            FirmwareCompression::Zstd
+        );
+
+        fs::write(
+            &config,
+            "CONFIG_FW_LOADER_COMPRESS=y\nCONFIG_FW_LOADER_COMPRESS_XZ=y\n# CONFIG_FW_LOADER_COMPRESS_ZSTD is not set\n",
+        )
+        .unwrap();
+        assert!(resolve_firmware_compression(FirmwareCompression::Zstd, Some(&config)).is_err());
+        assert_eq!(
+            resolve_firmware_compression(FirmwareCompression::Auto, Some(&config)).unwrap(),
+            FirmwareCompression::Xz
+        );
+
+        fs::write(&config, "CONFIG_FW_LOADER_COMPRESS=y\n").unwrap();
+        assert_eq!(
+            kernel_firmware_compression(&config).unwrap(),
+            vec![FirmwareCompression::Xz]
+        );
+
+        fs::write(&config, "# CONFIG_FW_LOADER_COMPRESS is not set\n").unwrap();
+        assert_eq!(
+            resolve_firmware_compression(FirmwareCompression::Auto, Some(&config)).unwrap(),
+            FirmwareCompression::None
+        );
+        assert!(resolve_firmware_compression(FirmwareCompression::Auto, None).is_err());
+    }
+
+    #[test]
+    fn test_compress_relinks_symlinks() {
+        if !process::exists("xz") {
+            eprintln!("skipping: xz not installed");
+            return;
+        }
+        let temp = TempDir::new().unwrap();
+        let firmware = temp.path().join("lib/firmware");
+        fs::create_dir_all(firmware.join("vendor")).unwrap();
+        fs::write(firmware.join("vendor/fw.bin"), vec![0u8; 4096]).unwrap();
+        symlink("vendor/fw.bin", firmware.join("alias.bin")).unwrap();
+        symlink("vendor", firmware.join("vendor-dir")).unwrap();
+
+        let report = compress_firmware_dir(&firmware, FirmwareCompression::Xz).unwrap();
+        assert_eq!((report.files, report.links), (1, 1));
+        assert!(firmware.join("vendor/fw.bin.xz").is_file());
+        assert!(!firmware.join("vendor/fw.bin").exists());
+        assert_eq!(
+            fs::read_link(firmware.join("alias.bin.xz")).unwrap(),
+            Path::new("vendor/fw.bin.xz")
+        );
+        assert!(firmware.join("vendor-dir").is_dir());
+    }
+}
@@ -0,0 +1,419 @@
+//! First-boot identity regeneration for installed disk images.
+//!
+//! Every machine written from the same disk image would otherwise share the
+//! build's machine-id and SSH host keys, and keep the root partition at
+//! image size on a larger disk. [`FirstBootService`] installs a script,
+//! `/usr/lib/distro-builder/first-boot`, and a service running it once
+//! (a systemd unit in `sysinit.target`, or an OpenRC service in the `boot`
+//! runlevel), before sshd starts:
+//!
+//! - a machine-id that is missing, `uninitialized` or the one the image was
+//!   built with is replaced (a systemd-generated one is kept);
+//! - SSH host keys are generated with `ssh-keygen -A`; the build's keys are
+//!   removed from the image;
+//! - the root partition and its ext4 filesystem grow to fill the disk, when
+//!   `growpart` and `resize2fs` are installed;
+//! - optionally the initramfs is regenerated, for host-only generators such
+//!   as dracut with `hostonly=yes`.
+//!
+//! `/var/lib/distro-builder/first-boot.done` marks the work as done.
+//!
+//! Disk images install it through
+//! [`DiskImageConfig::first_boot`](crate::contracts::disk::DiskImageConfig::first_boot);
+//! the live stages install it when the variant has a `first-boot.toml`, so
+//! systems installed from the live rootfs get their own identity too.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use crate::build::shell_check::check_script;
+use crate::identity::is_systemd_root;
+
+/// Variant-local first-boot settings file.
+pub const FIRST_BOOT_CONFIG_FILENAME: &str = "first-boot.toml";
+
+/// Script run by the service.
+pub const FIRST_BOOT_SCRIPT: &str = "usr/lib/distro-builder/first-boot";
+
+/// Written once the script has run; the service is skipped afterwards.
+pub const FIRST_BOOT_DONE: &str = "var/lib/distro-builder/first-boot.done";
+
+const SERVICE_NAME: &str = "distro-builder-first-boot";
+const SYSTEMD_UNIT_DIR: &str = "usr/lib/systemd/system";
+const OPENRC_INIT_DIR: &str = "etc/init.d";
+const SSH_DIR: &str = "etc/ssh";
+
+/// What the first boot regenerates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirstBootService {
+    pub machine_id: bool,
+    pub ssh_host_keys: bool,
+    pub resize_root: bool,
+    pub initramfs: bool,
+}
+
+impl Default for FirstBootService {
+    fn default() -> Self {
+        Self {
+            machine_id: true,
+            ssh_host_keys: true,
+            resize_root: true,
+            initramfs: false,
+        }
+    }
+}
+
+impl FirstBootService {
+    /// Load `first-boot.toml` from `variant_dir`; `None` when the variant
+    /// has none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(FIRST_BOOT_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(config))
+    }
+
+    /// Install the script and enable the service for the init system of
+    /// `rootfs`. Call after the image identity is applied and every package
+    /// is installed, so a machine-id or host key a package generated at
+    /// build time is recognized as the build's.
+    pub fn install_into_rootfs(&self, rootfs: &Path) -> Result<()> {
+        let build_machine_id = fs::read_to_string(rootfs.join("etc/machine-id"))
+            .ok()
+            .map(|id| id.trim().to_string())
+            .filter(|id| id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()))
+            .unwrap_or_default();
+        let script = rootfs.join(FIRST_BOOT_SCRIPT);
+        write(&script, &self.script(&build_machine_id))?;
+        check_script(&script)?;
+        make_executable(&script)?;
+
+        if self.ssh_host_keys {
+            remove_host_keys(&rootfs.join(SSH_DIR))?;
+        }
+
+        if is_systemd_root(rootfs) {
+            let unit = format!("{}.service", SERVICE_NAME);
+            write(&rootfs.join(SYSTEMD_UNIT_DIR).join(&unit), &systemd_unit())?;
+            enable(
+                rootfs,
+                &format!("etc/systemd/system/sysinit.target.wants/{}", unit),
+                &format!("/{}/{}", SYSTEMD_UNIT_DIR, unit),
+            )
+        } else if rootfs.join(OPENRC_INIT_DIR).is_dir() {
+            let init = rootfs.join(OPENRC_INIT_DIR).join(SERVICE_NAME);
+            write(&init, &openrc_service())?;
+            make_executable(&init)?;
+            enable(
+                rootfs,
+                &format!("etc/runlevels/boot/{}", SERVICE_NAME),
+                &format!("/{}/{}", OPENRC_INIT_DIR, SERVICE_NAME),
+            )
+        } else {
+            bail!(
+                "cannot install the first-boot service into {}: neither systemd nor OpenRC found",
+                rootfs.display()
+            )
+        }
+    }
+
+    /// The first-boot script; `build_machine_id` is the id baked into the
+    /// image, if any.
+    pub fn script(&self, build_machine_id: &str) -> String {
+        let flag = |on: bool| if on { 1 } else { 0 };
+        format!(
+            r#"#!/bin/sh
+# Generated by distro-builder: regenerate machine identity on first boot
+set -u
+done_file=/{done_file}
+[ -e "$done_file" ] && exit 0
+
+build_machine_id="{build_machine_id}"
+regen_machine_id={machine_id}
+regen_ssh_host_keys={ssh_host_keys}
+resize_root={resize_root}
+regen_initramfs={initramfs}
+
+log() {{
+    echo "first-boot: $*"
+}}
+
+new_machine_id() {{
+    rm -f /etc/machine-id
+    if command -v systemd-machine-id-setup >/dev/null 2>&1; then
+        systemd-machine-id-setup >/dev/null
+    elif command -v dbus-uuidgen >/dev/null 2>&1; then
+        dbus-uuidgen --ensure=/etc/machine-id
+    else
+        tr -d '-' </proc/sys/kernel/random/uuid >/etc/machine-id
+    fi
+    if [ -f /var/lib/dbus/machine-id ] && [ ! -L /var/lib/dbus/machine-id ]; then
+        cp /etc/machine-id /var/lib/dbus/machine-id
+    fi
+    log "new machine-id $(cat /etc/machine-id)"
+}}
+
+grow_root() {{
+    if ! command -v growpart >/dev/null 2>&1 || ! command -v resize2fs >/dev/null 2>&1; then
+        log "growpart or resize2fs missing; root not resized"
+        return 0
+    fi
+    dev=$(findmnt -no SOURCE /) || return 0
+    part=${{dev##*/}}
+    [ -r "/sys/class/block/$part/partition" ] || return 0
+    num=$(cat "/sys/class/block/$part/partition")
+    disk=$(basename "$(readlink -f "/sys/class/block/$part/..")")
+    if growpart "/dev/$disk" "$num" >/dev/null; then
+        resize2fs "$dev" >/dev/null && log "root filesystem grown to fill /dev/$disk"
+    fi
+}}
+
+new_initramfs() {{
+    if command -v dracut >/dev/null 2>&1; then
+        dracut -f --regenerate-all
+    elif command -v mkinitcpio >/dev/null 2>&1; then
+        mkinitcpio -P
+    elif command -v update-initramfs >/dev/null 2>&1; then
+        update-initramfs -u -k all
+    elif command -v mkinitfs >/dev/null 2>&1; then
+        mkinitfs
+    else
+        log "no initramfs generator; initramfs kept"
+        return 0
+    fi && log "initramfs regenerated"
+}}
+
+if [ "$regen_machine_id" = 1 ]; then
+    id=$(cat /etc/machine-id 2>/dev/null || true)
+    if [ -z "$id" ] || [ "$id" = uninitialized ] || [ "$id" = "$build_machine_id" ]; then
+        new_machine_id
+    fi
+fi
+if [ "$regen_ssh_host_keys" = 1 ] && command -v ssh-keygen >/dev/null 2>&1; then
+    ssh-keygen -A >/dev/null && log "SSH host keys generated"
+fi
+[ "$resize_root" = 1 ] && grow_root
+[ "$regen_initramfs" = 1 ] && new_initramfs
+
+mkdir -p "${{done_file%/*}}"
+: >"$done_file"
+"#,
+            done_file = FIRST_BOOT_DONE,
+            build_machine_id = build_machine_id,
+            machine_id = flag(self.machine_id),
+            ssh_host_keys = flag(self.ssh_host_keys),
+            resize_root = flag(self.resize_root),
+            initramfs = flag(self.initramfs),
+        )
+    }
+}
+
+fn systemd_unit() -> String {
+    format!(
+        "# Generated by distro-builder: first-boot identity regeneration\n\
+         [Unit]\n\
+         Description=Regenerate machine identity on first boot\n\
+         DefaultDependencies=no\n\
+         Requires=local-fs.target\n\
+         After=local-fs.target systemd-remount-fs.service systemd-machine-id-commit.service\n\
+         Before=sysinit.target shutdown.target sshd.service ssh.service\n\
+         Conflicts=shutdown.target\n\
+         ConditionPathExists=!/{done_file}\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         RemainAfterExit=yes\n\
+         ExecStart=/{script}\n\
+         \n\
+         [Install]\n\
+         WantedBy=sysinit.target\n",
+        done_file = FIRST_BOOT_DONE,
+        script = FIRST_BOOT_SCRIPT,
+    )
+}
+
+fn openrc_service() -> String {
+    format!(
+        "#!/sbin/openrc-run\n\
+         # Generated by distro-builder: first-boot identity regeneration\n\
+         description=\"Regenerate machine identity on first boot\"\n\
+         \n\
+         depend() {{\n\
+         \tneed localmount\n\
+         \tbefore sshd net\n\
+         }}\n\
+         \n\
+         start() {{\n\
+         \t[ -e /{done_file} ] && return 0\n\
+         \tebegin \"Regenerating machine identity\"\n\
+         \t/{script}\n\
+         \teend $?\n\
+         }}\n",
+        done_file = FIRST_BOOT_DONE,
+        script = FIRST_BOOT_SCRIPT,
+    )
+}
+
+fn remove_host_keys(ssh_dir: &Path) -> Result<()> {
+    let Ok(entries) = fs::read_dir(ssh_dir) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", ssh_dir.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with("ssh_host_") && (name.ends_with("_key") || name.ends_with("_key.pub")) {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("Failed to remove {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn enable(rootfs: &Path, link: &str, target: &str) -> Result<()> {
+    let link = rootfs.join(link);
+    if link.symlink_metadata().is_ok() {
+        return Ok(());
+    }
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    symlink(target, &link).with_context(|| format!("Failed to create {}", link.display()))
+}
+
+fn make_executable(path: &Path) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))
+}
+
+fn write(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn touch(root: &Path, rel: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn test_systemd_and_openrc_wiring() {
+        let temp = TempDir::new().unwrap();
+        let systemd = temp.path().join("systemd");
+        touch(&systemd, "usr/lib/systemd/systemd");
+        touch(&systemd, "etc/ssh/ssh_host_ed25519_key");
+        touch(&systemd, "etc/ssh/ssh_host_ed25519_key.pub");
+        touch(&systemd, "etc/ssh/sshd_config");
+        fs::write(
+            systemd.join("etc/machine-id"),
+            "0123456789abcdef0123456789abcdef\n",
+        )
+        .unwrap();
+        FirstBootService::default()
+            .install_into_rootfs(&systemd)
+            .unwrap();
+        let script = fs::read_to_string(systemd.join(FIRST_BOOT_SCRIPT)).unwrap();
+        assert!(script.contains("build_machine_id=\"0123456789abcdef0123456789abcdef\""));
+        assert!(script.contains("regen_initramfs=0"));
+        assert!(!systemd.join("etc/ssh/ssh_host_ed25519_key").exists());
+        assert!(systemd.join("etc/ssh/sshd_config").exists());
+        assert_eq!(
+            fs::read_link(
+                systemd.join(
+                    "etc/systemd/system/sysinit.target.wants/distro-builder-first-boot.service"
+                )
+            )
+            .unwrap(),
+            Path::new("/usr/lib/systemd/system/distro-builder-first-boot.service")
+        );
+
+        let openrc = temp.path().join("openrc");
+        fs::create_dir_all(openrc.join(OPENRC_INIT_DIR)).unwrap();
+        let service = FirstBootService {
+            initramfs: true,
+            ..Default::default()
+        };
+        service.install_into_rootfs(&openrc).unwrap();
+        assert!(fs::read_to_string(openrc.join(FIRST_BOOT_SCRIPT))
+            .unwrap()
+            .contains("build_machine_id=\"\"\n"));
+        assert!(openrc
+            .join("etc/runlevels/boot/distro-builder-first-boot")
+            .symlink_metadata()
+            .is_ok());
+
+        assert!(FirstBootService::default()
+            .install_into_rootfs(&temp.path().join("bare"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_load_for_variant() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(
+            FirstBootService::load_for_variant(temp.path()).unwrap(),
+            None
+        );
+        fs::write(
+            temp.path().join(FIRST_BOOT_CONFIG_FILENAME),
+            "resize_root = false\n",
+        )
+        .unwrap();
+        assert_eq!(
+            FirstBootService::load_for_variant(temp.path()).unwrap(),
+            Some(FirstBootService {
+                resize_root: false,
+                ..Default::default()
+            })
+        );
+        fs::write(
+            temp.path().join(FIRST_BOOT_CONFIG_FILENAME),
+            "hostname = true\n",
+        )
+        .unwrap();
+        assert!(FirstBootService::load_for_variant(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_script_runs_once() {
+        let temp = TempDir::new().unwrap();
+        let script = temp.path().join("first-boot");
+        let service = FirstBootService {
+            machine_id: false,
+            ssh_host_keys: false,
+            resize_root: false,
+            initramfs: false,
+        };
+        let done = temp.path().join("state/first-boot.done");
+        let content = service
+            .script("")
+            .replace(&format!("/{}", FIRST_BOOT_DONE), &done.to_string_lossy());
+        fs::write(&script, content).unwrap();
+        for _ in 0..2 {
+            let status = std::process::Command::new("sh")
+                .arg(&script)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        assert!(done.is_file());
+    }
+}
@@ -24,6 +24,9 @@ use std::path::{Path, PathBuf};
 use crate::process::Cmd;
 use distro_spec::shared::KernelSource;
 
+use crate::build::modules::{check_module_policy, post_process_modules, resolve_compression};
+use crate::contracts::kernel::ModuleCompression;
+
 // Re-export contracts from distro-builder contracts module
 pub use crate::contracts::kernel::KernelInstallConfig;
 
@@ -523,6 +526,20 @@ pub fn install_kernel(
     }
     println!("  Installed {} kernel modules", module_count);
 
+    let compression = resolve_compression(
+        config.module_compression(),
+        Some(&build_dir.join(".config")),
+    )?;
+    if compression != ModuleCompression::None || config.strip_modules() {
+        post_process_modules(
+            &temp_staging,
+            config.module_install_path(),
+            &version,
+            compression,
+            config.strip_modules(),
+        )?;
+    }
+
     // Final Integrity Check
     if !final_modules_dir.exists() {
         bail!(
@@ -530,6 +547,11 @@ pub fn install_kernel(
             version
         );
     }
+    check_module_policy(
+        &final_modules_dir,
+        config.required_modules(),
+        config.forbidden_modules(),
+    )?;
 
     // Atomic Swap: rename temp_staging to staging
     if staging.exists() {
@@ -0,0 +1,397 @@
+//! Kernel headers payload for building modules on installed systems.
+//!
+//! DKMS and out-of-tree drivers need the kernel's build tree, which the
+//! kernel payload does not carry. A variant that ships `kernel-headers.toml`
+//! gets a headers payload produced next to the kernel and stored in the
+//! artifact store (kind [`KERNEL_HEADERS_KIND`]); the listed products get it
+//! installed:
+//!
+//! ```toml
+//! # Products whose rootfs ships the headers (live-boot, live-tools,
+//! # installed-boot).
+//! products = ["installed-boot"]
+//! ```
+//!
+//! The payload is what `make M=...` needs: the top-level Makefile,
+//! `scripts/`, `include/` and `arch/x86` headers from the source tree, with
+//! `.config`, `Module.symvers` and the generated headers and host programs
+//! of the build tree on top. It lands in `/usr/src/kernels/<release>`, and
+//! `<modules>/<release>/build` points at it.
+//!
+//! Payloads are keyed by the kernel release and `.config`, so a kernel
+//! restored from another distro's shared build finds the headers that
+//! distro stored.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::artifact_store::ArtifactStore;
+use crate::repo_config::artifacts_root;
+use crate::workspace::WorkspaceManager;
+
+/// Variant-local kernel headers settings file.
+pub const KERNEL_HEADERS_FILENAME: &str = "kernel-headers.toml";
+
+/// Artifact store kind for headers payloads.
+pub const KERNEL_HEADERS_KIND: &str = "kernel_headers";
+
+/// Where the headers land in the rootfs, one directory per release.
+pub const KERNEL_HEADERS_DIR: &str = "usr/src/kernels";
+
+/// Products the headers can be installed into. The base rootfs is shared by
+/// every product, so it never carries them.
+const HEADERS_PRODUCTS: &[&str] = &["live-boot", "live-tools", "installed-boot"];
+
+const RELEASE_FILE: &str = "include/config/kernel.release";
+const MODULES_DIRS: &[&str] = &["usr/lib/modules", "lib/modules"];
+
+/// From the source tree, relative to its root.
+const SOURCE_PATHS: &[&str] = &[
+    "Makefile",
+    "scripts",
+    "include",
+    "arch/x86/Makefile",
+    "arch/x86/Makefile_32.cpu",
+    "arch/x86/include",
+];
+
+/// From the build tree, copied over the source paths.
+const BUILD_PATHS: &[&str] = &[
+    ".config",
+    "Module.symvers",
+    "System.map",
+    "scripts",
+    "include/config",
+    "include/generated",
+    "arch/x86/include/generated",
+    "tools/objtool/objtool",
+];
+
+/// Kernel headers settings for one variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KernelHeaders {
+    /// Products whose rootfs gets the headers.
+    pub products: Vec<String>,
+}
+
+impl KernelHeaders {
+    /// Load `kernel-headers.toml` from a variant directory; `None` if absent.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(KERNEL_HEADERS_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        for product in &config.products {
+            if !HEADERS_PRODUCTS.contains(&product.as_str()) {
+                bail!(
+                    "{}: cannot ship kernel headers in product '{}' (expected one of: {})",
+                    path.display(),
+                    product,
+                    HEADERS_PRODUCTS.join(", ")
+                );
+            }
+        }
+        Ok(Some(config))
+    }
+
+    pub fn includes(&self, product: &str) -> bool {
+        self.products.iter().any(|p| p == product)
+    }
+}
+
+/// Store input key of the headers for the kernel built in `kernel_root`.
+pub fn headers_input_key(kernel_root: &Path) -> Result<String> {
+    let release = kernel_release(kernel_root)?;
+    let config_path = kernel_root.join("kernel-build/.config");
+    let config = fs::read(&config_path)
+        .with_context(|| format!("Failed to read {}", config_path.display()))?;
+    let mut hasher = Sha256::new();
+    hasher.update(b"kernel-headers-v1\0");
+    hasher.update(release.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(Sha256::digest(&config));
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Produce the headers payload of the kernel in `kernel_root` and store it,
+/// unless the store already has it. Returns whether a payload was stored.
+///
+/// The payload is staged in a `kernel-headers` scratch directory of
+/// `workspace`, kept for inspection when staging fails.
+///
+/// Needs the build tree's `source` link to the kernel sources; a kernel
+/// restored from a shared build has none and relies on the publisher's
+/// payload.
+pub fn store_kernel_headers(
+    store: &ArtifactStore,
+    workspace: &WorkspaceManager,
+    distro_id: &str,
+    kernel_root: &Path,
+) -> Result<bool> {
+    let input_key = headers_input_key(kernel_root)?;
+    if store.get(KERNEL_HEADERS_KIND, &input_key)?.is_some() {
+        return Ok(false);
+    }
+    let release = kernel_release(kernel_root)?;
+    let mut scratch = workspace.scratch(distro_id, "kernel-headers")?;
+    let payload_dir = scratch.path().join("payload");
+    stage_kernel_headers(&kernel_root.join("kernel-build"), &payload_dir)?;
+    let mut meta = BTreeMap::new();
+    meta.insert("kernel_release".to_string(), serde_json::json!(release));
+    store.put_dir_as_tar(KERNEL_HEADERS_KIND, &input_key, &payload_dir, meta)?;
+    scratch.mark_success();
+    Ok(true)
+}
+
+/// Copy the headers of the kernel built in `build_dir` to `payload_dir`.
+pub fn stage_kernel_headers(build_dir: &Path, payload_dir: &Path) -> Result<()> {
+    let source_dir = source_tree(build_dir)?;
+    if payload_dir.exists() {
+        fs::remove_dir_all(payload_dir)
+            .with_context(|| format!("Failed to remove {}", payload_dir.display()))?;
+    }
+    for (root, paths) in [
+        (&source_dir, SOURCE_PATHS),
+        (&build_dir.to_path_buf(), BUILD_PATHS),
+    ] {
+        for rel in paths {
+            let src = root.join(rel);
+            if src.symlink_metadata().is_ok() {
+                copy_headers_path(&src, &payload_dir.join(rel))?;
+            }
+        }
+    }
+    for required in [
+        "Makefile",
+        ".config",
+        "Module.symvers",
+        "scripts/mod/modpost",
+    ] {
+        if !payload_dir.join(required).is_file() {
+            bail!(
+                "kernel headers from {} lack {}\n\
+                 Remediation: build the kernel with modules (`make modules`) before producing headers.",
+                build_dir.display(),
+                required
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Install the headers of the kernel in `kernel_root` from the store into
+/// `rootfs`, and link them as `<modules>/<release>/build`.
+pub fn install_kernel_headers(
+    store: &ArtifactStore,
+    kernel_root: &Path,
+    rootfs: &Path,
+) -> Result<()> {
+    let input_key = headers_input_key(kernel_root)?;
+    if store.get(KERNEL_HEADERS_KIND, &input_key)?.is_none() {
+        bail!(
+            "no kernel headers stored for the kernel in {}\n\
+             Remediation: rebuild the kernel stage with {} in the variant so the headers payload is produced.",
+            kernel_root.display(),
+            KERNEL_HEADERS_FILENAME
+        );
+    }
+    let release = kernel_release(kernel_root)?;
+    let dest = rootfs.join(KERNEL_HEADERS_DIR).join(&release);
+    store.materialize_to(KERNEL_HEADERS_KIND, &input_key, &dest)?;
+
+    for modules in MODULES_DIRS {
+        let release_dir = rootfs.join(modules).join(&release);
+        if !release_dir.is_dir() {
+            continue;
+        }
+        let link = release_dir.join("build");
+        if link.symlink_metadata().is_ok() {
+            fs::remove_file(&link)
+                .with_context(|| format!("Failed to remove {}", link.display()))?;
+        }
+        symlink(format!("/{}/{}", KERNEL_HEADERS_DIR, release), &link)
+            .with_context(|| format!("Failed to create {}", link.display()))?;
+    }
+    Ok(())
+}
+
+/// Install the headers into the rootfs of `product` when the variant asks
+/// for them there.
+pub fn install_for_product(
+    repo_root: &Path,
+    variant_dir: &Path,
+    distro_id: &str,
+    product: &str,
+    rootfs: &Path,
+) -> Result<()> {
+    let Some(headers) = KernelHeaders::load_for_variant(variant_dir)? else {
+        return Ok(());
+    };
+    if !headers.includes(product) {
+        return Ok(());
+    }
+    let store = ArtifactStore::open(repo_root)?;
+    install_kernel_headers(&store, &kernel_root_for(repo_root, distro_id)?, rootfs)
+}
+
+/// Installed kernel root of `distro_id` under the artifacts root.
+pub fn kernel_root_for(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
+    Ok(artifacts_root(repo_root)?
+        .join("kernel")
+        .join(distro_id)
+        .join("current"))
+}
+
+fn kernel_release(kernel_root: &Path) -> Result<String> {
+    let path = kernel_root.join("kernel-build").join(RELEASE_FILE);
+    let release =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(release.trim().to_string())
+}
+
+/// Kernel sources of `build_dir`: the `source` link of an `O=` build, or the
+/// build directory itself for an in-tree build.
+fn source_tree(build_dir: &Path) -> Result<PathBuf> {
+    let link = build_dir.join("source");
+    if link.is_dir() {
+        return fs::canonicalize(&link)
+            .with_context(|| format!("Failed to resolve {}", link.display()));
+    }
+    if build_dir.join("Kbuild").is_file() {
+        return Ok(build_dir.to_path_buf());
+    }
+    bail!(
+        "cannot find the kernel sources of {} (no source link)\n\
+         Remediation: build the kernel from source; a kernel restored from another distro's shared build uses the headers that distro stored.",
+        build_dir.display()
+    )
+}
+
+/// Copy a file or tree, leaving out object files and kbuild command files.
+fn copy_headers_path(src: &Path, dst: &Path) -> Result<()> {
+    for entry in WalkDir::new(src).follow_links(false) {
+        let entry = entry.with_context(|| format!("Failed to walk {}", src.display()))?;
+        let name = entry.file_name().to_string_lossy();
+        if entry.depth() > 0
+            && (name.ends_with(".o") || (name.starts_with('.') && name.ends_with(".cmd")))
+        {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(src).unwrap_or(entry.path());
+        let target = if rel.as_os_str().is_empty() {
+            dst.to_path_buf()
+        } else {
+            dst.join(rel)
+        };
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)
+                .with_context(|| format!("Failed to create {}", target.display()))?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        if target.symlink_metadata().is_ok() {
+            fs::remove_file(&target)
+                .with_context(|| format!("Failed to remove {}", target.display()))?;
+        }
+        if file_type.is_symlink() {
+            let link = fs::read_link(entry.path())
+                .with_context(|| format!("Failed to read link {}", entry.path().display()))?;
+            symlink(&link, &target)
+                .with_context(|| format!("Failed to create {}", target.display()))?;
+        } else {
+            fs::copy(entry.path(), &target).with_context(|| {
+                format!(
+                    "Failed to copy {} to {}",
+                    entry.path().display(),
+                    target.display()
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_store_and_install() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("linux");
+        write(&source.join("Makefile"), "VERSION = 6\n");
+        write(&source.join("include/linux/module.h"), "");
+        write(&source.join("arch/x86/include/asm/page.h"), "");
+        write(&source.join("drivers/net/e1000.c"), "");
+        let kernel_root = temp.path().join("kernel/acorn/current");
+        let build = kernel_root.join("kernel-build");
+        write(&build.join(".config"), "CONFIG_MODULES=y\n");
+        write(&build.join("Module.symvers"), "");
+        write(&build.join(RELEASE_FILE), "6.12.1-acorn\n");
+        write(&build.join("scripts/mod/modpost"), "");
+        write(&build.join("scripts/mod/.modpost.o.cmd"), "");
+        write(&build.join("scripts/mod/modpost.o"), "");
+        symlink(&source, build.join("source")).unwrap();
+
+        let store = ArtifactStore::open(&temp.path().join("store")).unwrap();
+        let workspace = WorkspaceManager::new(temp.path()).unwrap();
+        assert!(store_kernel_headers(&store, &workspace, "acorn", &kernel_root).unwrap());
+        assert!(!store_kernel_headers(&store, &workspace, "acorn", &kernel_root).unwrap());
+        assert!(workspace.usage().unwrap().iter().all(|u| u.bytes == 0));
+
+        let rootfs = temp.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("usr/lib/modules/6.12.1-acorn")).unwrap();
+        install_kernel_headers(&store, &kernel_root, &rootfs).unwrap();
+        let headers = rootfs.join("usr/src/kernels/6.12.1-acorn");
+        assert!(headers.join("include/linux/module.h").is_file());
+        assert!(headers.join("arch/x86/include/asm/page.h").is_file());
+        assert!(headers.join("scripts/mod/modpost").is_file());
+        assert!(!headers.join("scripts/mod/modpost.o").exists());
+        assert!(!headers.join("scripts/mod/.modpost.o.cmd").exists());
+        assert!(!headers.join("drivers").exists());
+        assert_eq!(
+            fs::read_link(rootfs.join("usr/lib/modules/6.12.1-acorn/build")).unwrap(),
+            Path::new("/usr/src/kernels/6.12.1-acorn")
+        );
+
+        // A different configuration is a different payload.
+        write(&build.join(".config"), "CONFIG_MODULES=y\nCONFIG_EFI=y\n");
+        assert!(install_kernel_headers(&store, &kernel_root, &rootfs).is_err());
+    }
+
+    #[test]
+    fn test_load_rejects_base_rootfs() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(KernelHeaders::load_for_variant(temp.path()).unwrap(), None);
+        let path = temp.path().join(KERNEL_HEADERS_FILENAME);
+        fs::write(&path, "products = [\"installed-boot\"]\n").unwrap();
+        let config = KernelHeaders::load_for_variant(temp.path())
+            .unwrap()
+            .unwrap();
+        assert!(config.includes("installed-boot"));
+        assert!(!config.includes("live-boot"));
+        fs::write(&path, "products = [\"base-rootfs\"]\n").unwrap();
+        assert!(KernelHeaders::load_for_variant(temp.path()).is_err());
+    }
+}
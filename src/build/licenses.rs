@@ -8,7 +8,7 @@
 
 use anyhow::{Context, Result};
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -22,7 +22,7 @@ use super::context::PackageManager;
 pub struct LicenseTracker {
     source: PathBuf,
     pkg_mgr: PackageManager,
-    packages: RefCell<HashSet<String>>,
+    packages: RefCell<BTreeSet<String>>,
     cache: RefCell<HashMap<String, Option<String>>>,
 }
 
@@ -35,7 +35,7 @@ impl LicenseTracker {
         Self {
             source,
             pkg_mgr,
-            packages: RefCell::new(HashSet::new()),
+            packages: RefCell::new(BTreeSet::new()),
             cache: RefCell::new(HashMap::new()),
         }
     }
@@ -68,6 +68,7 @@ impl LicenseTracker {
         let search_paths = match self.pkg_mgr {
             PackageManager::Rpm => vec![format!("usr/lib64/{}", lib), format!("usr/lib/{}", lib)],
             PackageManager::Apk => vec![format!("usr/lib/{}", lib), format!("lib/{}", lib)],
+            PackageManager::Pacman => vec![format!("usr/lib/{}", lib)],
         };
 
         for rel_path in &search_paths {
@@ -104,6 +105,7 @@ impl LicenseTracker {
         let result = match self.pkg_mgr {
             PackageManager::Rpm => self.rpm_query_file(rel_path),
             PackageManager::Apk => self.apk_query_file(rel_path),
+            PackageManager::Pacman => self.pacman_query_file(rel_path),
         };
 
         self.cache
@@ -170,6 +172,30 @@ impl LicenseTracker {
         }
     }
 
+    /// Query the pacman database for the package owning a file.
+    fn pacman_query_file(&self, rel_path: &str) -> Option<String> {
+        let abs_path = format!("/{}", rel_path);
+        let output = Command::new("pacman")
+            .args([
+                "--root",
+                self.source.to_str().unwrap_or(""),
+                "-Qqo",
+                &abs_path,
+            ])
+            .output()
+            .ok()?;
+
+        if output.status.success() {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        } else {
+            None
+        }
+    }
+
     /// Copy license directories for all used packages.
     ///
     /// Searches for licenses in:
@@ -0,0 +1,571 @@
+//! Locale and timezone payload trimming, configured per variant.
+//!
+//! A full glibc + tzdata payload carries hundreds of compiled locales,
+//! charmaps, message catalogs and every zone in three copies (`zoneinfo/`,
+//! `posix/`, `right/`). A variant that only needs a few trims the rest by
+//! shipping `locales.toml`:
+//!
+//! ```toml
+//! locales = ["en_US.UTF-8", "de_DE.UTF-8"]   # C.UTF-8 is always kept
+//! timezones = ["UTC", "Europe/*"]            # omit to keep every zone
+//! default = "en_US.UTF-8"                    # LANG in /etc/locale.conf
+//! ```
+//!
+//! Without the file nothing is trimmed and `LANG=C.UTF-8`, as before. The
+//! same list drives [`LocalePolicy::verify`], so a kept locale whose payload
+//! is missing fails the build rather than the first login.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::process::{self, Cmd};
+
+/// Variant-local locale settings file.
+pub const LOCALE_CONFIG_FILENAME: &str = "locales.toml";
+
+/// Locale every image keeps; the login fallback.
+pub const BASE_LOCALE: &str = "C.UTF-8";
+
+/// Compiled locale directories, usr-merged first.
+const LOCALE_DIRS: &[&str] = &[
+    "usr/lib/locale",
+    "lib/locale",
+    "usr/lib64/locale",
+    "lib64/locale",
+];
+
+const LOCALE_ARCHIVE: &str = "locale-archive";
+const CHARMAPS_DIR: &str = "usr/share/i18n/charmaps";
+const LOCALE_SOURCES_DIR: &str = "usr/share/i18n/locales";
+const MESSAGES_DIR: &str = "usr/share/locale";
+const ZONEINFO_DIR: &str = "usr/share/zoneinfo";
+
+/// Shared locale source fragments that territory locales `copy`; kept
+/// whatever the selection so kept locales still compile.
+const SHARED_LOCALE_SOURCES: &[&str] = &["i18n", "i18n_ctype", "iso14651_t1", "iso14651_t1_common"];
+
+/// Prefix of the transliteration tables locales `include`.
+const TRANSLIT_PREFIX: &str = "translit_";
+
+/// Zones that are always kept.
+const BASE_TIMEZONES: &[&str] = &["UTC", "Etc/UTC"];
+
+/// `zoneinfo/` subtrees holding alternate copies of every zone.
+const ZONEINFO_VARIANTS: &[&str] = &["posix", "right"];
+
+/// Per-variant locale and timezone selection.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalePolicy {
+    /// Locales to keep besides [`BASE_LOCALE`]; `None` keeps all.
+    pub locales: Option<Vec<String>>,
+    /// Zones to keep (`Region/City`, or `Region/*`); `None` keeps all.
+    pub timezones: Option<Vec<String>>,
+    /// `LANG` written to `/etc/locale.conf`; defaults to [`BASE_LOCALE`].
+    pub default: Option<String>,
+}
+
+/// What a trimming run removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrimReport {
+    pub locales: usize,
+    pub charmaps: usize,
+    pub catalogs: usize,
+    pub timezones: usize,
+}
+
+impl LocalePolicy {
+    /// Load the variant's settings, or the keep-everything default.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(LOCALE_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let policy: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if let Some(default) = &policy.default {
+            if !policy.keeps_locale(default) {
+                bail!(
+                    "default locale '{}' in {} is not in the kept locales",
+                    default,
+                    path.display()
+                );
+            }
+        }
+        Ok(policy)
+    }
+
+    /// `LANG` for `/etc/locale.conf`.
+    pub fn default_locale(&self) -> &str {
+        self.default.as_deref().unwrap_or(BASE_LOCALE)
+    }
+
+    /// Locales that must be present, [`BASE_LOCALE`] first.
+    pub fn required_locales(&self) -> Vec<String> {
+        let mut required = vec![BASE_LOCALE.to_string()];
+        for locale in self.locales.iter().flatten() {
+            if normalize_locale(locale) != normalize_locale(BASE_LOCALE) {
+                required.push(locale.clone());
+            }
+        }
+        required
+    }
+
+    fn keeps_locale(&self, locale: &str) -> bool {
+        let wanted = normalize_locale(locale);
+        match &self.locales {
+            None => true,
+            Some(_) => self
+                .required_locales()
+                .iter()
+                .any(|kept| normalize_locale(kept) == wanted),
+        }
+    }
+
+    /// Remove unselected locales, charmaps, message catalogs and zones from
+    /// `rootfs`.
+    pub fn trim(&self, rootfs: &Path) -> Result<TrimReport> {
+        let mut report = TrimReport::default();
+        if self.locales.is_some() {
+            self.trim_locales(rootfs, &mut report)?;
+        }
+        if let Some(timezones) = &self.timezones {
+            report.timezones = trim_timezones(rootfs, timezones)?;
+        }
+        if report != TrimReport::default() {
+            println!(
+                "  Trimmed {} locales, {} charmaps, {} message catalogs, {} timezones",
+                report.locales, report.charmaps, report.catalogs, report.timezones
+            );
+        }
+        Ok(report)
+    }
+
+    fn trim_locales(&self, rootfs: &Path, report: &mut TrimReport) -> Result<()> {
+        let kept: BTreeSet<String> = self
+            .required_locales()
+            .iter()
+            .map(|locale| normalize_locale(locale))
+            .collect();
+        let languages: BTreeSet<&str> = kept.iter().map(|locale| language(locale)).collect();
+        let codesets: BTreeSet<String> = self
+            .required_locales()
+            .iter()
+            .filter_map(|locale| locale.split_once('.').map(|(_, codeset)| codeset))
+            .map(|codeset| codeset.split('@').next().unwrap_or(codeset).to_string())
+            .collect();
+
+        for dir in LOCALE_DIRS.iter().map(|dir| rootfs.join(dir)) {
+            if dir.is_symlink() || !dir.is_dir() {
+                continue;
+            }
+            for entry in read_dir_sorted(&dir)? {
+                let name = file_name(&entry);
+                if entry.is_dir() && !entry.is_symlink() && !kept.contains(&normalize_locale(&name))
+                {
+                    remove(&entry)?;
+                    report.locales += 1;
+                }
+            }
+            // The archive cannot be trimmed in place; drop it only when the
+            // kept locales are all compiled as directories.
+            let archive = dir.join(LOCALE_ARCHIVE);
+            if archive.is_file()
+                && kept
+                    .iter()
+                    .all(|locale| dir.join(locale).join("LC_CTYPE").is_file())
+            {
+                remove(&archive)?;
+            }
+        }
+
+        let charmaps = rootfs.join(CHARMAPS_DIR);
+        if charmaps.is_dir() {
+            for entry in read_dir_sorted(&charmaps)? {
+                let name = file_name(&entry);
+                let charmap = name.strip_suffix(".gz").unwrap_or(&name);
+                let keep = codesets
+                    .iter()
+                    .any(|codeset| normalize_codeset(codeset) == normalize_codeset(charmap));
+                if !keep {
+                    remove(&entry)?;
+                    report.charmaps += 1;
+                }
+            }
+        }
+
+        let sources = rootfs.join(LOCALE_SOURCES_DIR);
+        if sources.is_dir() {
+            for entry in read_dir_sorted(&sources)? {
+                let name = file_name(&entry);
+                let shared = SHARED_LOCALE_SOURCES.contains(&name.as_str())
+                    || name.starts_with(TRANSLIT_PREFIX);
+                if name.contains('_') && !shared && !languages.contains(language(&name)) {
+                    remove(&entry)?;
+                }
+            }
+        }
+
+        let messages = rootfs.join(MESSAGES_DIR);
+        if messages.is_dir() {
+            for entry in read_dir_sorted(&messages)? {
+                if !entry.is_dir() {
+                    continue;
+                }
+                let name = file_name(&entry);
+                if !languages.contains(language(&name)) {
+                    remove(&entry)?;
+                    report.catalogs += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every kept locale has a payload and write
+    /// `/etc/locale.conf`.
+    pub fn verify(&self, rootfs: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        for locale in self.required_locales() {
+            if !locale_present(rootfs, &locale)? {
+                missing.push(locale);
+            }
+        }
+        if !missing.is_empty() {
+            bail!(
+                "missing locale payload in rootfs '{}' for: {}; expected <locale dir>/<name>/LC_CTYPE under one of {} or an entry in {}",
+                rootfs.display(),
+                missing.join(", "),
+                LOCALE_DIRS.join(", "),
+                LOCALE_ARCHIVE
+            );
+        }
+
+        let etc_dir = rootfs.join("etc");
+        fs::create_dir_all(&etc_dir)
+            .with_context(|| format!("creating '{}'", etc_dir.display()))?;
+        let locale_conf = etc_dir.join("locale.conf");
+        fs::write(&locale_conf, format!("LANG={}\n", self.default_locale()))
+            .with_context(|| format!("writing locale config '{}'", locale_conf.display()))?;
+        Ok(())
+    }
+}
+
+/// glibc's normalized locale name: `en_US.UTF-8` -> `en_US.utf8`.
+pub fn normalize_locale(locale: &str) -> String {
+    match locale.split_once('.') {
+        Some((base, rest)) => {
+            let (codeset, modifier) = match rest.split_once('@') {
+                Some((codeset, modifier)) => (codeset, Some(modifier)),
+                None => (rest, None),
+            };
+            let mut normalized = format!("{}.{}", base, normalize_codeset(codeset));
+            if let Some(modifier) = modifier {
+                normalized.push('@');
+                normalized.push_str(modifier);
+            }
+            normalized
+        }
+        None => locale.to_string(),
+    }
+}
+
+fn normalize_codeset(codeset: &str) -> String {
+    codeset
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Language part of a locale or catalog name: `pt_BR.utf8` -> `pt`.
+fn language(name: &str) -> &str {
+    name.split(['_', '.', '@']).next().unwrap_or(name)
+}
+
+fn locale_present(rootfs: &Path, locale: &str) -> Result<bool> {
+    let normalized = normalize_locale(locale);
+    for dir in LOCALE_DIRS.iter().map(|dir| rootfs.join(dir)) {
+        if dir.join(&normalized).join("LC_CTYPE").is_file() {
+            return Ok(true);
+        }
+        let archive = dir.join(LOCALE_ARCHIVE);
+        if archive.is_file() && archive_contains(&archive, &normalized)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn archive_contains(archive: &Path, normalized: &str) -> Result<bool> {
+    if !process::exists("localedef") {
+        eprintln!(
+            "  [WARN] localedef not installed; assuming {} provides {}",
+            archive.display(),
+            normalized
+        );
+        return Ok(true);
+    }
+    let listing = Cmd::new("localedef")
+        .arg("--list-archive")
+        .arg_path(archive)
+        .error_msg(format!("listing {} failed", archive.display()))
+        .run()?;
+    Ok(listing
+        .stdout
+        .lines()
+        .any(|line| normalize_locale(line.trim()) == normalized))
+}
+
+/// Remove zones not matched by `keep`, in every zoneinfo copy. The zone
+/// `/etc/localtime` points at is always kept.
+fn trim_timezones(rootfs: &Path, keep: &[String]) -> Result<usize> {
+    let zoneinfo = rootfs.join(ZONEINFO_DIR);
+    if !zoneinfo.is_dir() {
+        return Ok(0);
+    }
+    let mut patterns: Vec<String> = keep.to_vec();
+    patterns.extend(BASE_TIMEZONES.iter().map(|zone| zone.to_string()));
+    if let Ok(target) = fs::read_link(rootfs.join("etc/localtime")) {
+        let target = target.to_string_lossy().into_owned();
+        if let Some((_, zone)) = target.split_once("zoneinfo/") {
+            patterns.push(zone.to_string());
+        }
+    }
+
+    let mut removed = 0;
+    for root in std::iter::once(zoneinfo.clone()).chain(
+        ZONEINFO_VARIANTS
+            .iter()
+            .map(|variant| zoneinfo.join(variant)),
+    ) {
+        if !root.is_dir() || root.is_symlink() {
+            continue;
+        }
+        removed += trim_zone_tree(&root, &root, &patterns)?;
+    }
+    Ok(removed)
+}
+
+fn trim_zone_tree(root: &Path, dir: &Path, patterns: &[String]) -> Result<usize> {
+    let mut removed = 0;
+    for entry in read_dir_sorted(dir)? {
+        let zone = entry
+            .strip_prefix(root)
+            .unwrap_or(&entry)
+            .to_string_lossy()
+            .into_owned();
+        if dir == root && ZONEINFO_VARIANTS.contains(&zone.as_str()) {
+            continue;
+        }
+        if entry.is_dir() && !entry.is_symlink() {
+            removed += trim_zone_tree(root, &entry, patterns)?;
+            if fs::read_dir(&entry)?.next().is_none() {
+                fs::remove_dir(&entry)
+                    .with_context(|| format!("Failed to remove {}", entry.display()))?;
+            }
+            continue;
+        }
+        // Top-level tables (zone.tab, tzdata.zi, leapseconds) are metadata.
+        let is_zone = zone.contains('/') || zone.chars().next().is_some_and(char::is_uppercase);
+        if is_zone && !zone_matches(&zone, patterns) {
+            remove(&entry)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn zone_matches(zone: &str, patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix("/*") {
+            Some(region) => zone
+                .strip_prefix(region)
+                .is_some_and(|rest| rest.starts_with('/')),
+            None => pattern == zone,
+        })
+}
+
+fn read_dir_sorted(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn remove(path: &Path) -> Result<()> {
+    let result = if path.is_dir() && !path.is_symlink() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    result.with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn touch(root: &Path, rel: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x").unwrap();
+    }
+
+    #[test]
+    fn test_trim_keeps_selection() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        for rel in [
+            "usr/lib/locale/C.utf8/LC_CTYPE",
+            "usr/lib/locale/en_US.utf8/LC_CTYPE",
+            "usr/lib/locale/fr_FR.utf8/LC_CTYPE",
+            "usr/share/i18n/charmaps/UTF-8.gz",
+            "usr/share/i18n/charmaps/ISO-8859-1.gz",
+            "usr/share/locale/en/LC_MESSAGES/coreutils.mo",
+            "usr/share/locale/fr/LC_MESSAGES/coreutils.mo",
+            "usr/share/locale/locale.alias",
+            "usr/share/zoneinfo/UTC",
+            "usr/share/zoneinfo/zone.tab",
+            "usr/share/zoneinfo/Europe/Berlin",
+            "usr/share/zoneinfo/America/New_York",
+            "usr/share/zoneinfo/posix/Europe/Berlin",
+            "usr/share/zoneinfo/posix/Asia/Tokyo",
+        ] {
+            touch(root, rel);
+        }
+
+        let policy = LocalePolicy {
+            locales: Some(vec!["en_US.UTF-8".into()]),
+            timezones: Some(vec!["Europe/*".into()]),
+            default: Some("en_US.UTF-8".into()),
+        };
+        let report = policy.trim(root).unwrap();
+        assert_eq!(
+            report,
+            TrimReport {
+                locales: 1,
+                charmaps: 1,
+                catalogs: 1,
+                timezones: 2,
+            }
+        );
+        assert!(root.join("usr/lib/locale/en_US.utf8").is_dir());
+        assert!(!root.join("usr/lib/locale/fr_FR.utf8").exists());
+        assert!(root.join("usr/share/i18n/charmaps/UTF-8.gz").is_file());
+        assert!(root.join("usr/share/locale/locale.alias").is_file());
+        assert!(root.join("usr/share/zoneinfo/zone.tab").is_file());
+        assert!(root
+            .join("usr/share/zoneinfo/posix/Europe/Berlin")
+            .is_file());
+        assert!(!root.join("usr/share/zoneinfo/America").exists());
+
+        policy.verify(root).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("etc/locale.conf")).unwrap(),
+            "LANG=en_US.UTF-8\n"
+        );
+
+        let missing = LocalePolicy {
+            locales: Some(vec!["de_DE.UTF-8".into()]),
+            ..LocalePolicy::default()
+        };
+        assert!(missing.verify(root).is_err());
+    }
+
+    /// Locale sources `name` pulls in through `copy` and `include`.
+    fn referenced_sources(sources: &Path, name: &str, seen: &mut BTreeSet<String>) {
+        if !seen.insert(name.to_string()) {
+            return;
+        }
+        let content = fs::read_to_string(sources.join(name))
+            .unwrap_or_else(|_| panic!("locale source '{}' was trimmed", name));
+        for line in content.lines() {
+            let line = line.trim_start();
+            let Some(rest) = line
+                .strip_prefix("copy ")
+                .or_else(|| line.strip_prefix("include "))
+            else {
+                continue;
+            };
+            if let Some(referenced) = rest.split('"').nth(1) {
+                referenced_sources(sources, referenced, seen);
+            }
+        }
+    }
+
+    #[test]
+    fn test_trimmed_en_us_still_compiles() {
+        let host_sources = Path::new("/usr/share/i18n/locales");
+        let host_charmap = Path::new("/usr/share/i18n/charmaps/UTF-8.gz");
+        if !host_sources.join("en_US").is_file() || !host_charmap.is_file() {
+            eprintln!("skipping: glibc locale sources not installed");
+            return;
+        }
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("rootfs");
+        let sources = root.join(LOCALE_SOURCES_DIR);
+        fs::create_dir_all(&sources).unwrap();
+        for entry in read_dir_sorted(host_sources).unwrap() {
+            if entry.is_file() {
+                fs::copy(&entry, sources.join(file_name(&entry))).unwrap();
+            }
+        }
+        fs::create_dir_all(root.join(CHARMAPS_DIR)).unwrap();
+        fs::copy(host_charmap, root.join(CHARMAPS_DIR).join("UTF-8.gz")).unwrap();
+
+        let policy = LocalePolicy {
+            locales: Some(vec!["en_US.UTF-8".into()]),
+            ..LocalePolicy::default()
+        };
+        policy.trim(&root).unwrap();
+        assert!(!sources.join("fr_FR").exists());
+
+        // Every fragment en_US copies or includes survived the trim.
+        let mut needed = BTreeSet::new();
+        referenced_sources(&sources, "en_US", &mut needed);
+        for shared in ["i18n_ctype", "iso14651_t1", "iso14651_t1_common"] {
+            assert!(needed.contains(shared), "en_US no longer uses {}", shared);
+        }
+
+        if !process::exists("localedef") {
+            eprintln!("skipping compile: localedef not installed");
+            return;
+        }
+        let output = temp.path().join("en_US.UTF-8");
+        let status = std::process::Command::new("localedef")
+            .arg("--no-archive")
+            .arg("-i")
+            .arg(sources.join("en_US"))
+            .args(["-f", "UTF-8"])
+            .arg(&output)
+            .env("I18NPATH", root.join("usr/share/i18n"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "localedef en_US failed: {}", status);
+        assert!(output.join("LC_CTYPE").is_file());
+    }
+
+    #[test]
+    fn test_normalize_locale() {
+        assert_eq!(normalize_locale("en_US.UTF-8"), "en_US.utf8");
+        assert_eq!(normalize_locale("sr_RS.UTF-8@latin"), "sr_RS.utf8@latin");
+        assert_eq!(normalize_locale("C"), "C");
+    }
+}
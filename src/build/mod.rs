@@ -1,11 +1,49 @@
 //! Build utilities for creating distribution images.
 //!
 //! This module provides:
+//! - [`accessibility`] - Console font, beeper and screen reader defaults
+//! - [`banner`] - issue/motd templates with build metadata
+//! - [`branding`] - os-release, issue, motd, and lsb-release rendering
+//! - [`chroot`] - Running commands inside a prepared rootfs
+//! - [`cmdline`] - Kernel command line builder and validation
+//! - [`consoles`] - Per-variant serial console devices and line speed
 //! - [`context`] - Build context and distro configuration traits
 //! - [`filesystem`] - FHS directory structure utilities
+//! - [`firmware`] - Compression of staged firmware blobs
+//! - [`first_boot`] - Machine identity and root size regeneration on first boot
 //! - [`kernel`] - Kernel building and installation
+//! - [`kernel_headers`] - Kernel headers payload for building modules on installed systems
+//! - [`locales`] - Locale and timezone trimming to a per-variant keep-list
+//! - [`package_manager`] - Repositories and keyrings of installed systems
+//! - [`selinux`] - SELinux relabeling of staged rootfs trees with `setfiles`
+//! - [`services`] - Per-variant masked units and disabled services
+//! - [`shell_check`] - Syntax checks for generated shell scripts
+//! - [`splash`] - Per-variant boot splash (UKI bitmap, Plymouth theme)
+//! - [`swap`] - zram/zswap compressed swap for live sessions
+//! - [`timesync`] - NTP client, RTC policy and build-time clock seed
+//! - [`writable_layer`] - Live writable layer size cap and spill file
 
+pub mod accessibility;
+pub mod banner;
+pub mod branding;
+pub mod chroot;
+pub mod cmdline;
+pub mod consoles;
 pub mod context;
+pub mod external_modules;
 pub mod filesystem;
+pub mod firmware;
+pub mod first_boot;
 pub mod kernel;
+pub mod kernel_headers;
 pub mod licenses;
+pub mod locales;
+pub mod modules;
+pub mod package_manager;
+pub mod selinux;
+pub mod services;
+pub mod shell_check;
+pub mod splash;
+pub mod swap;
+pub mod timesync;
+pub mod writable_layer;
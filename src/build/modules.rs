@@ -0,0 +1,441 @@
+//! Post-install processing of kernel modules.
+//!
+//! `make modules_install` leaves modules uncompressed and with full debug
+//! info unless the kconfig says otherwise, which roughly triples their size
+//! on the live image. [`post_process_modules`] strips and compresses the
+//! staged modules and then regenerates the depmod indexes so `modprobe`
+//! finds the renamed `.ko.<ext>` files. [`check_module_policy`] then holds
+//! the installed tree to the contract's required and forbidden lists.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::artifact::rootfs::format_size_human;
+use crate::contracts::kernel::ModuleCompression;
+use crate::process::{self, Cmd};
+
+/// Size accounting for a post-processing run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModuleProcessReport {
+    pub modules: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Read the module compression the kernel was configured with.
+pub fn kernel_config_compression(kernel_config: &Path) -> Result<ModuleCompression> {
+    let content = fs::read_to_string(kernel_config)
+        .with_context(|| format!("Failed to read {}", kernel_config.display()))?;
+    for line in content.lines() {
+        let compression = match line.trim() {
+            "CONFIG_MODULE_COMPRESS_XZ=y" => ModuleCompression::Xz,
+            "CONFIG_MODULE_COMPRESS_ZSTD=y" => ModuleCompression::Zstd,
+            "CONFIG_MODULE_COMPRESS_GZIP=y" => ModuleCompression::Gzip,
+            _ => continue,
+        };
+        return Ok(compression);
+    }
+    Ok(ModuleCompression::None)
+}
+
+/// Resolve `Auto` against the kernel config and reject explicit choices that
+/// contradict it.
+pub fn resolve_compression(
+    requested: ModuleCompression,
+    kernel_config: Option<&Path>,
+) -> Result<ModuleCompression> {
+    let configured = match kernel_config {
+        Some(path) if path.is_file() => Some(kernel_config_compression(path)?),
+        _ => None,
+    };
+    match (requested, configured) {
+        (ModuleCompression::Auto, Some(configured)) => Ok(configured),
+        (ModuleCompression::Auto, None) => bail!(
+            "module compression 'auto' needs the kernel .config to read CONFIG_MODULE_COMPRESS_*"
+        ),
+        (requested, Some(ModuleCompression::None)) if requested != ModuleCompression::None => {
+            bail!(
+                "module compression {:?} requested, but the kernel is built with CONFIG_MODULE_COMPRESS_NONE\n\
+                 Remediation: enable CONFIG_MODULE_COMPRESS_{} in the kconfig, or set module compression to 'auto' or 'none'.",
+                requested,
+                format!("{:?}", requested).to_uppercase()
+            )
+        }
+        (requested, Some(configured))
+            if requested != ModuleCompression::None && requested != configured =>
+        {
+            bail!(
+                "module compression {:?} does not match kernel CONFIG_MODULE_COMPRESS_{:?}",
+                requested,
+                configured
+            )
+        }
+        (requested, _) => Ok(requested),
+    }
+}
+
+/// Strip and/or compress every module under `<staging>/<module_install_path>/<version>`,
+/// then run depmod against the staged tree.
+///
+/// Already compressed modules are left alone. `compression` must already be
+/// resolved (not `Auto`).
+pub fn post_process_modules(
+    staging: &Path,
+    module_install_path: &str,
+    version: &str,
+    compression: ModuleCompression,
+    strip: bool,
+) -> Result<ModuleProcessReport> {
+    if compression == ModuleCompression::Auto {
+        bail!("module compression must be resolved before post-processing");
+    }
+    let modules_dir = staging
+        .join(module_install_path.trim_start_matches('/'))
+        .join(version);
+    if !modules_dir.is_dir() {
+        bail!("modules directory not found: {}", modules_dir.display());
+    }
+
+    let mut tools = Vec::new();
+    if strip {
+        tools.push(("strip", "binutils"));
+    }
+    match compression {
+        ModuleCompression::Xz => tools.push(("xz", "xz")),
+        ModuleCompression::Zstd => tools.push(("zstd", "zstd")),
+        ModuleCompression::Gzip => tools.push(("gzip", "gzip")),
+        ModuleCompression::None | ModuleCompression::Auto => {}
+    }
+    for (tool, package) in tools {
+        if !process::exists(tool) {
+            bail!("{} not found. Install {}.", tool, package);
+        }
+    }
+
+    let modules: Vec<PathBuf> = WalkDir::new(&modules_dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "ko"))
+        .collect();
+
+    let mut report = ModuleProcessReport {
+        modules: modules.len(),
+        ..Default::default()
+    };
+    for module in &modules {
+        report.bytes_before += fs::metadata(module)?.len();
+        if strip {
+            Cmd::new("strip")
+                .arg("--strip-debug")
+                .arg_path(module)
+                .error_msg(format!("strip failed for {}", module.display()))
+                .run()?;
+        }
+        let output = compress_module(module, compression)?;
+        report.bytes_after += fs::metadata(&output)?.len();
+    }
+
+    run_staged_depmod(staging, module_install_path, version)?;
+
+    println!(
+        "  Processed {} modules: {} -> {}",
+        report.modules,
+        format_size_human(report.bytes_before),
+        format_size_human(report.bytes_after)
+    );
+    Ok(report)
+}
+
+fn compress_module(module: &Path, compression: ModuleCompression) -> Result<PathBuf> {
+    let Some(ext) = compression.extension() else {
+        return Ok(module.to_path_buf());
+    };
+    let cmd = match compression {
+        // Same flags as the kernel's own modules_install compression.
+        ModuleCompression::Xz => Cmd::new("xz").args(["--check=crc32", "--lzma2=dict=1MiB", "-f"]),
+        ModuleCompression::Zstd => Cmd::new("zstd").args(["-q", "-f", "--rm", "-T0"]),
+        _ => Cmd::new("gzip").args(["-n", "-f"]),
+    };
+    cmd.arg_path(module)
+        .error_msg(format!("compressing {} failed", module.display()))
+        .run()?;
+
+    let mut output = module.as_os_str().to_owned();
+    output.push(".");
+    output.push(ext);
+    Ok(PathBuf::from(output))
+}
+
+/// Modules that break a required/forbidden policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModulePolicyReport {
+    /// Required names neither installed nor built in.
+    pub missing: Vec<String>,
+    /// Installed (or built-in) module paths and the entry that forbids them.
+    pub forbidden: Vec<(String, String)>,
+}
+
+impl ModulePolicyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.forbidden.is_empty()
+    }
+
+    /// Diff-style listing: `-` for what is missing, `+` for what should go.
+    pub fn diff(&self) -> String {
+        let mut out = String::new();
+        for name in &self.missing {
+            out.push_str(&format!("  - {} (required)\n", name));
+        }
+        for (path, entry) in &self.forbidden {
+            out.push_str(&format!("  + {} (forbidden by '{}')\n", path, entry));
+        }
+        out
+    }
+}
+
+/// Compare the modules under `modules_dir` (`<install path>/<version>`)
+/// with the required and forbidden lists.
+///
+/// Names match with `-` and `_` treated alike, as modprobe does; required
+/// modules may also be built in (`modules.builtin`). Forbidden entries
+/// containing `/` match by path prefix, e.g. `kernel/drivers/staging`.
+pub fn scan_module_policy(
+    modules_dir: &Path,
+    required: &[String],
+    forbidden: &[String],
+) -> Result<ModulePolicyReport> {
+    let mut installed = Vec::new();
+    for entry in WalkDir::new(modules_dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to walk {}", modules_dir.display()))?;
+        if !entry.file_type().is_file() || module_name(entry.path()).is_none() {
+            continue;
+        }
+        let rel = entry
+            .path()
+            .strip_prefix(modules_dir)
+            .unwrap_or(entry.path())
+            .to_string_lossy()
+            .into_owned();
+        installed.push(rel);
+    }
+    let builtin_list = modules_dir.join("modules.builtin");
+    let builtin: Vec<String> = if builtin_list.is_file() {
+        fs::read_to_string(&builtin_list)
+            .with_context(|| format!("Failed to read {}", builtin_list.display()))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let present: BTreeSet<String> = installed
+        .iter()
+        .chain(&builtin)
+        .filter_map(|rel| module_name(Path::new(rel)))
+        .collect();
+    let mut report = ModulePolicyReport {
+        missing: required
+            .iter()
+            .filter(|name| !present.contains(&normalize_module_name(name)))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    for rel in installed.iter().chain(&builtin) {
+        let Some(name) = module_name(Path::new(rel)) else {
+            continue;
+        };
+        let matched = forbidden.iter().find(|entry| {
+            if entry.contains('/') {
+                let prefix = entry.trim_matches('/');
+                rel == prefix || rel.starts_with(&format!("{}/", prefix))
+            } else {
+                normalize_module_name(entry) == name
+            }
+        });
+        if let Some(entry) = matched {
+            report.forbidden.push((rel.clone(), entry.clone()));
+        }
+    }
+    Ok(report)
+}
+
+/// Fail with a diff when the installed modules break the policy.
+pub fn check_module_policy(
+    modules_dir: &Path,
+    required: &[String],
+    forbidden: &[String],
+) -> Result<()> {
+    if required.is_empty() && forbidden.is_empty() {
+        return Ok(());
+    }
+    let report = scan_module_policy(modules_dir, required, forbidden)?;
+    if !report.is_clean() {
+        bail!(
+            "kernel module policy failed for {}:\n{}\
+Remediation: enable required modules and disable forbidden ones in the kconfig",
+            modules_dir.display(),
+            report.diff()
+        );
+    }
+    println!(
+        "  Module policy OK ({} required, {} forbidden entries)",
+        required.len(),
+        forbidden.len()
+    );
+    Ok(())
+}
+
+/// Module name of a `.ko` / `.ko.<ext>` path, normalized.
+fn module_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let (stem, rest) = file_name.split_once(".ko")?;
+    if !(rest.is_empty() || rest.starts_with('.')) {
+        return None;
+    }
+    Some(normalize_module_name(stem))
+}
+
+fn normalize_module_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Run depmod against a staged tree.
+///
+/// depmod's `-b` looks under `<base>/lib/modules`; for usr-merged layouts
+/// without a `lib` symlink in staging, a temporary one is added.
+pub(crate) fn run_staged_depmod(
+    staging: &Path,
+    module_install_path: &str,
+    version: &str,
+) -> Result<()> {
+    let lib_dir = staging.join("lib");
+    let lib_modules = lib_dir.join("modules");
+    let created_lib = !lib_dir.exists();
+    let mut temp_link = None;
+    if !lib_modules.join(version).exists() && module_install_path.trim_matches('/') != "lib/modules"
+    {
+        if fs::symlink_metadata(&lib_modules).is_ok() {
+            bail!(
+                "cannot run depmod: '{}' exists but does not contain {}",
+                lib_modules.display(),
+                version
+            );
+        }
+        fs::create_dir_all(&lib_dir)?;
+        let target = Path::new("..").join(module_install_path.trim_start_matches('/'));
+        symlink(&target, &lib_modules)
+            .with_context(|| format!("linking {}", lib_modules.display()))?;
+        temp_link = Some(lib_modules);
+    }
+
+    let result = Cmd::new("depmod")
+        .args(["-a", "-b"])
+        .arg_path(staging)
+        .arg(version)
+        .error_msg("depmod failed. Install: sudo dnf install kmod")
+        .run();
+
+    if let Some(link) = temp_link {
+        let _ = fs::remove_file(&link);
+        if created_lib {
+            let _ = fs::remove_dir(&lib_dir);
+        }
+    }
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_kernel_config_compression() {
+        let temp = TempDir::new().unwrap();
+        let config = temp.path().join(".config");
+        fs::write(
+            &config,
+            "# CONFIG_MODULE_COMPRESS_NONE is not set\nCONFIG_MODULE_COMPRESS_ZSTD=y\n",
+        )
+        .unwrap();
+        assert_eq!(
+            kernel_config_compression(&config).unwrap(),
+            ModuleCompression::Zstd
+        );
+        assert_eq!(
+            resolve_compression(ModuleCompression::Auto, Some(&config)).unwrap(),
+            ModuleCompression::Zstd
+        );
+        assert!(resolve_compression(ModuleCompression::Xz, Some(&config)).is_err());
+        assert_eq!(
+            resolve_compression(ModuleCompression::None, Some(&config)).unwrap(),
+            ModuleCompression::None
+        );
+
+        fs::write(&config, "CONFIG_MODULE_COMPRESS_NONE=y\n").unwrap();
+        let err = resolve_compression(ModuleCompression::Zstd, Some(&config)).unwrap_err();
+        assert!(err.to_string().contains("CONFIG_MODULE_COMPRESS_NONE"));
+        assert_eq!(
+            resolve_compression(ModuleCompression::Auto, Some(&config)).unwrap(),
+            ModuleCompression::None
+        );
+    }
+
+    #[test]
+    fn test_module_policy_diff() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("kernel/fs/erofs")).unwrap();
+        fs::create_dir_all(dir.join("kernel/drivers/staging/rtl8723bs")).unwrap();
+        fs::write(dir.join("kernel/fs/erofs/erofs.ko.zst"), "").unwrap();
+        fs::write(dir.join("kernel/drivers/staging/rtl8723bs/r8723bs.ko"), "").unwrap();
+        fs::write(
+            dir.join("modules.builtin"),
+            "kernel/fs/overlayfs/overlay.ko\n",
+        )
+        .unwrap();
+
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let report = scan_module_policy(
+            dir,
+            &strings(&["erofs", "overlay", "squashfs"]),
+            &strings(&["kernel/drivers/staging/", "overlay"]),
+        )
+        .unwrap();
+        assert_eq!(report.missing, vec!["squashfs"]);
+        assert_eq!(
+            report.forbidden,
+            vec![
+                (
+                    "kernel/drivers/staging/rtl8723bs/r8723bs.ko".to_string(),
+                    "kernel/drivers/staging/".to_string()
+                ),
+                (
+                    "kernel/fs/overlayfs/overlay.ko".to_string(),
+                    "overlay".to_string()
+                ),
+            ]
+        );
+        assert!(report.diff().contains("  - squashfs (required)"));
+        assert!(check_module_policy(dir, &strings(&["erofs"]), &[]).is_ok());
+    }
+
+    #[test]
+    fn test_auto_requires_config() {
+        assert!(resolve_compression(ModuleCompression::Auto, None).is_err());
+        assert_eq!(
+            resolve_compression(ModuleCompression::Gzip, None).unwrap(),
+            ModuleCompression::Gzip
+        );
+    }
+}
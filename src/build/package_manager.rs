@@ -0,0 +1,363 @@
+//! Package manager bootstrap for installed systems, configured per variant.
+//!
+//! An installed system can only update itself when its package manager
+//! knows where packages come from and which keys sign them. A variant ships
+//! `package-manager.toml`:
+//!
+//! ```toml
+//! manager = "apk"   # "apk", "rpm" (dnf) or "pacman"
+//!
+//! [[repos]]
+//! name = "main"
+//! url = "https://dl-cdn.alpinelinux.org/alpine/v3.21/main"
+//! key = "keys/alpine-devel@lists.alpinelinux.org-6165ee59.rsa.pub"
+//! ```
+//!
+//! Key paths are relative to the variant directory. The installed-boot
+//! product gets the manager's repository configuration, the keys in its
+//! keyring directory and, where the parent rootfs has none, an empty
+//! package database:
+//!
+//! | manager  | repositories                  | keys                          | database                        |
+//! |----------|-------------------------------|-------------------------------|---------------------------------|
+//! | `apk`    | `/etc/apk/repositories`       | `/etc/apk/keys/`              | `/lib/apk/db/installed`         |
+//! | `rpm`    | `/etc/yum.repos.d/<name>.repo`| `/etc/pki/rpm-gpg/`           | `/usr/lib/sysimage/rpm/`        |
+//! | `pacman` | `/etc/pacman.conf` sections   | `/usr/share/pacman/keyrings/` | `/var/lib/pacman/local/`        |
+//!
+//! A repo without a key is written with signature checks off (apk needs
+//! `--allow-untrusted` for it) and a warning. pacman keyrings still need
+//! `pacman-key --init` and `pacman-key --populate` on first boot.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::contracts::context::PackageManager;
+
+/// Variant-local package manager bootstrap file.
+pub const PACKAGE_MANAGER_CONFIG_FILENAME: &str = "package-manager.toml";
+
+const APK_REPOSITORIES: &str = "etc/apk/repositories";
+const APK_KEYS_DIR: &str = "etc/apk/keys";
+const APK_INSTALLED_DB: &str = "lib/apk/db/installed";
+const YUM_REPOS_DIR: &str = "etc/yum.repos.d";
+const RPM_GPG_DIR: &str = "etc/pki/rpm-gpg";
+const RPM_DB_DIRS: &[&str] = &["usr/lib/sysimage/rpm", "var/lib/rpm"];
+const PACMAN_CONF: &str = "etc/pacman.conf";
+const PACMAN_KEYRINGS_DIR: &str = "usr/share/pacman/keyrings";
+const PACMAN_LOCAL_DB: &str = "var/lib/pacman/local";
+const PACMAN_SYNC_DB: &str = "var/lib/pacman/sync";
+/// Local database format of pacman 6.
+const ALPM_DB_VERSION: &str = "9";
+
+/// One package repository of the installed system.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageRepo {
+    /// Repository id (dnf section, pacman database name).
+    pub name: String,
+    pub url: String,
+    /// Signing key installed into the keyring; `None` disables signature
+    /// checks for this repo.
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+}
+
+/// Package manager setup of one variant's installed system.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageManagerBootstrap {
+    pub manager: PackageManager,
+    pub repos: Vec<PackageRepo>,
+}
+
+impl PackageManagerBootstrap {
+    /// Load `package-manager.toml` from `variant_dir`; `None` when the
+    /// variant has none. Key paths are resolved against `variant_dir`.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(PACKAGE_MANAGER_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        for repo in &mut config.repos {
+            if let Some(key) = &mut repo.key {
+                *key = variant_dir.join(&*key);
+            }
+        }
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", path.display()))?;
+        Ok(Some(config))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.repos.is_empty() {
+            bail!("repos must list at least one repository");
+        }
+        for (i, repo) in self.repos.iter().enumerate() {
+            let valid_name = repo.name.starts_with(|c: char| c.is_ascii_alphanumeric())
+                && repo
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !valid_name {
+                bail!(
+                    "invalid repo name '{}': use letters, digits, '-', '_' and '.'",
+                    repo.name
+                );
+            }
+            if self.repos[..i].iter().any(|other| other.name == repo.name) {
+                bail!("repo '{}' listed twice", repo.name);
+            }
+            let scheme_ok = ["https://", "http://", "ftp://", "file://"]
+                .iter()
+                .any(|scheme| repo.url.starts_with(scheme));
+            if !scheme_ok || repo.url.contains(char::is_whitespace) {
+                bail!(
+                    "invalid url '{}' for repo '{}': expected an http(s), ftp or file URL",
+                    repo.url,
+                    repo.name
+                );
+            }
+            if let Some(key) = &repo.key {
+                if !key.is_file() {
+                    bail!(
+                        "key '{}' of repo '{}' does not exist",
+                        key.display(),
+                        repo.name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the repository configuration, keys and database stubs into
+    /// `rootfs`. Existing databases are left alone.
+    pub fn install_into_rootfs(&self, rootfs: &Path) -> Result<()> {
+        for repo in self.repos.iter().filter(|repo| repo.key.is_none()) {
+            eprintln!(
+                "  [WARN] package repo '{}' has no key; signature checks are off for it",
+                repo.name
+            );
+        }
+        match self.manager {
+            PackageManager::Apk => self.install_apk(rootfs)?,
+            PackageManager::Rpm => self.install_rpm(rootfs)?,
+            PackageManager::Pacman => self.install_pacman(rootfs)?,
+        }
+        println!(
+            "  Configured {} with {} repo(s)",
+            self.manager.as_str(),
+            self.repos.len()
+        );
+        Ok(())
+    }
+
+    fn install_apk(&self, rootfs: &Path) -> Result<()> {
+        self.install_keys(rootfs, APK_KEYS_DIR)?;
+        let repositories: String = self
+            .repos
+            .iter()
+            .map(|repo| format!("{}\n", repo.url))
+            .collect();
+        write_file(&rootfs.join(APK_REPOSITORIES), &repositories)?;
+        create_if_missing(&rootfs.join(APK_INSTALLED_DB), "")
+    }
+
+    fn install_rpm(&self, rootfs: &Path) -> Result<()> {
+        let keys = self.install_keys(rootfs, RPM_GPG_DIR)?;
+        for (repo, key) in self.repos.iter().zip(&keys) {
+            let gpg = match key {
+                Some(key) => format!("gpgcheck=1\ngpgkey=file:///{}/{}\n", RPM_GPG_DIR, key),
+                None => "gpgcheck=0\n".to_string(),
+            };
+            let content = format!(
+                "[{name}]\nname={name}\nbaseurl={}\nenabled=1\n{}",
+                repo.url,
+                gpg,
+                name = repo.name
+            );
+            write_file(
+                &rootfs
+                    .join(YUM_REPOS_DIR)
+                    .join(format!("{}.repo", repo.name)),
+                &content,
+            )?;
+        }
+        if !RPM_DB_DIRS.iter().any(|dir| rootfs.join(dir).is_dir()) {
+            create_dir(&rootfs.join(RPM_DB_DIRS[0]))?;
+        }
+        Ok(())
+    }
+
+    fn install_pacman(&self, rootfs: &Path) -> Result<()> {
+        let keys = self.install_keys(rootfs, PACMAN_KEYRINGS_DIR)?;
+        let conf_path = rootfs.join(PACMAN_CONF);
+        let mut conf = if conf_path.is_file() {
+            fs::read_to_string(&conf_path)
+                .with_context(|| format!("Failed to read {}", conf_path.display()))?
+        } else {
+            "[options]\nArchitecture = auto\nSigLevel = Required DatabaseOptional\n".to_string()
+        };
+        for (repo, key) in self.repos.iter().zip(&keys) {
+            let header = format!("[{}]", repo.name);
+            if conf.lines().any(|line| line.trim() == header) {
+                bail!(
+                    "{} already defines repo '{}'\n\
+Remediation: drop it from {} or rename it",
+                    conf_path.display(),
+                    repo.name,
+                    PACKAGE_MANAGER_CONFIG_FILENAME
+                );
+            }
+            if !conf.ends_with('\n') {
+                conf.push('\n');
+            }
+            let sig_level = match key {
+                Some(_) => "Required",
+                None => "Optional TrustAll",
+            };
+            conf.push_str(&format!(
+                "\n{}\nSigLevel = {}\nServer = {}\n",
+                header, sig_level, repo.url
+            ));
+        }
+        write_file(&conf_path, &conf)?;
+        create_dir(&rootfs.join(PACMAN_SYNC_DB))?;
+        create_if_missing(
+            &rootfs.join(PACMAN_LOCAL_DB).join("ALPM_DB_VERSION"),
+            &format!("{}\n", ALPM_DB_VERSION),
+        )
+    }
+
+    /// Copy every repo key into `rootfs/keyring_dir`; the installed file
+    /// name per repo, in repo order.
+    fn install_keys(&self, rootfs: &Path, keyring_dir: &str) -> Result<Vec<Option<String>>> {
+        let keyring = rootfs.join(keyring_dir);
+        let mut installed = Vec::new();
+        for repo in &self.repos {
+            let Some(key) = &repo.key else {
+                installed.push(None);
+                continue;
+            };
+            let name = key
+                .file_name()
+                .with_context(|| format!("key path {} has no file name", key.display()))?
+                .to_string_lossy()
+                .into_owned();
+            create_dir(&keyring)?;
+            let dest = keyring.join(&name);
+            fs::copy(key, &dest).with_context(|| {
+                format!("Failed to copy key {} -> {}", key.display(), dest.display())
+            })?;
+            installed.push(Some(name));
+        }
+        Ok(installed)
+    }
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    fs::create_dir_all(path).with_context(|| format!("Failed to create {}", path.display()))
+}
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir(parent)?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn create_if_missing(path: &Path, content: &str) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    write_file(path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn variant(config: &str) -> (TempDir, Result<Option<PackageManagerBootstrap>>) {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir_all(temp.path().join("keys")).unwrap();
+        fs::write(temp.path().join("keys/signing.pub"), "KEY\n").unwrap();
+        fs::write(temp.path().join(PACKAGE_MANAGER_CONFIG_FILENAME), config).unwrap();
+        let loaded = PackageManagerBootstrap::load_for_variant(temp.path());
+        (temp, loaded)
+    }
+
+    const REPOS: &str = "[[repos]]\nname = \"main\"\nurl = \"https://pkgs.example/main\"\nkey = \"keys/signing.pub\"\n\n[[repos]]\nname = \"extra\"\nurl = \"https://pkgs.example/extra\"\n";
+
+    #[test]
+    fn test_config_validation() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(
+            PackageManagerBootstrap::load_for_variant(temp.path()).unwrap(),
+            None
+        );
+        let (_temp, loaded) = variant(&format!("manager = \"pacman\"\n{REPOS}"));
+        assert_eq!(loaded.unwrap().unwrap().manager, PackageManager::Pacman);
+
+        for bad in [
+            "manager = \"apk\"\nrepos = []\n",
+            "manager = \"zypper\"\nrepos = []\n",
+            "manager = \"apk\"\n[[repos]]\nname = \"main\"\nurl = \"pkgs.example\"\n",
+            "manager = \"apk\"\n[[repos]]\nname = \"a b\"\nurl = \"https://pkgs.example\"\n",
+            "manager = \"apk\"\n[[repos]]\nname = \"main\"\nurl = \"https://pkgs.example\"\nkey = \"keys/missing.pub\"\n",
+        ] {
+            let (_temp, loaded) = variant(bad);
+            assert!(loaded.is_err(), "{bad:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_install_per_manager() {
+        let rootfs = TempDir::new().unwrap();
+        let root = rootfs.path();
+
+        let (_apk, apk) = variant(&format!("manager = \"apk\"\n{REPOS}"));
+        apk.unwrap().unwrap().install_into_rootfs(root).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join(APK_REPOSITORIES)).unwrap(),
+            "https://pkgs.example/main\nhttps://pkgs.example/extra\n"
+        );
+        assert!(root.join(APK_KEYS_DIR).join("signing.pub").is_file());
+        assert!(root.join(APK_INSTALLED_DB).is_file());
+
+        let (_rpm, rpm) = variant(&format!("manager = \"rpm\"\n{REPOS}"));
+        rpm.unwrap().unwrap().install_into_rootfs(root).unwrap();
+        let main = fs::read_to_string(root.join("etc/yum.repos.d/main.repo")).unwrap();
+        assert!(
+            main.contains("gpgkey=file:///etc/pki/rpm-gpg/signing.pub"),
+            "{main}"
+        );
+        let extra = fs::read_to_string(root.join("etc/yum.repos.d/extra.repo")).unwrap();
+        assert!(extra.contains("gpgcheck=0"), "{extra}");
+        assert!(root.join("usr/lib/sysimage/rpm").is_dir());
+
+        let (_pacman, pacman) = variant(&format!("manager = \"pacman\"\n{REPOS}"));
+        let pacman = pacman.unwrap().unwrap();
+        pacman.install_into_rootfs(root).unwrap();
+        let conf = fs::read_to_string(root.join(PACMAN_CONF)).unwrap();
+        assert!(conf.starts_with("[options]\n"), "{conf}");
+        assert!(
+            conf.contains(
+                "[extra]\nSigLevel = Optional TrustAll\nServer = https://pkgs.example/extra\n"
+            ),
+            "{conf}"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join(PACMAN_LOCAL_DB).join("ALPM_DB_VERSION")).unwrap(),
+            "9\n"
+        );
+        assert!(pacman.install_into_rootfs(root).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! SELinux relabeling of staged rootfs trees, configured per variant.
+//!
+//! Rootfs payloads derived from an SELinux distribution carry
+//! `security.selinux` labels. The producer paths preserve them where the
+//! builder is allowed to (`artifact::xattr`), but files generated during the
+//! build are unlabeled, and an unprivileged build cannot write labels at
+//! all. A variant that boots with SELinux ships `selinux.toml`, and the
+//! staged tree is relabeled with `setfiles` against the policy:
+//!
+//! ```toml
+//! # Relative paths resolve inside the rootfs, absolute ones on the host.
+//! # Defaults to the file_contexts of SELINUXTYPE in etc/selinux/config.
+//! file_contexts = "etc/selinux/targeted/contexts/files/file_contexts"
+//! # Optional binary policy the contexts are validated against.
+//! policy = "etc/selinux/targeted/policy/policy.33"
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::preflight::RequiredTools;
+use crate::process::Cmd;
+
+/// Variant-local SELinux relabel file.
+pub const SELINUX_CONFIG_FILENAME: &str = "selinux.toml";
+
+/// SELinux settings of the target system, relative to the rootfs.
+const SELINUX_CONFIG: &str = "etc/selinux/config";
+
+/// Relabel settings for one variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelinuxRelabel {
+    /// `file_contexts` to label from.
+    pub file_contexts: Option<PathBuf>,
+    /// Binary policy passed to `setfiles -c`.
+    pub policy: Option<PathBuf>,
+}
+
+/// Register the host tools relabeling invokes.
+pub fn register_host_tools(tools: &mut RequiredTools) {
+    tools.require("selinux", "setfiles", "policycoreutils");
+}
+
+impl SelinuxRelabel {
+    /// Load `selinux.toml` from `variant_dir`; `None` when the variant has
+    /// none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(SELINUX_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        for (key, value) in [
+            ("file_contexts", &config.file_contexts),
+            ("policy", &config.policy),
+        ] {
+            if value.as_ref().is_some_and(|value| {
+                value.as_os_str().is_empty()
+                    || value.components().any(|c| c == Component::ParentDir)
+            }) {
+                bail!("invalid {} path in {}", key, path.display());
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// The `file_contexts` to label `rootfs` from.
+    pub fn file_contexts_for(&self, rootfs: &Path) -> Result<PathBuf> {
+        let path = match &self.file_contexts {
+            Some(path) => rootfs.join(path),
+            None => {
+                let policy_type = selinux_type(rootfs)?;
+                rootfs.join(format!(
+                    "etc/selinux/{}/contexts/files/file_contexts",
+                    policy_type
+                ))
+            }
+        };
+        if !path.is_file() {
+            bail!(
+                "SELinux file_contexts '{}' not found\n\
+Remediation: install the policy package into the rootfs or set file_contexts in {}",
+                path.display(),
+                SELINUX_CONFIG_FILENAME
+            );
+        }
+        Ok(path)
+    }
+
+    /// Relabel every file under `rootfs` with `setfiles`.
+    pub fn relabel(&self, rootfs: &Path) -> Result<()> {
+        self.relabel_tree(rootfs, rootfs)
+    }
+
+    /// Relabel `tree`, a tree laid over `rootfs` (a live overlay), with the
+    /// policy found in `rootfs`.
+    pub fn relabel_tree(&self, rootfs: &Path, tree: &Path) -> Result<()> {
+        let mut tools = RequiredTools::new();
+        register_host_tools(&mut tools);
+        tools.check()?;
+
+        let file_contexts = self.file_contexts_for(rootfs)?;
+        let mut cmd = Cmd::new("setfiles").arg("-F").arg("-r").arg_path(tree);
+        if let Some(policy) = &self.policy {
+            let policy = rootfs.join(policy);
+            if !policy.is_file() {
+                bail!("SELinux policy '{}' not found", policy.display());
+            }
+            cmd = cmd.arg("-c").arg_path(&policy);
+        }
+        cmd.arg_path(&file_contexts)
+            .arg_path(tree)
+            .error_msg(
+                "setfiles failed to relabel the rootfs\n\
+Remediation: relabeling writes security.selinux and needs root (CAP_SYS_ADMIN)",
+            )
+            .run()?;
+        Ok(())
+    }
+}
+
+/// `SELINUXTYPE` from the rootfs's `etc/selinux/config`.
+fn selinux_type(rootfs: &Path) -> Result<String> {
+    let path = rootfs.join(SELINUX_CONFIG);
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    content
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("SELINUXTYPE="))
+        .map(|value| value.trim().trim_matches('"').to_string())
+        .filter(|value| !value.is_empty() && !value.contains('/'))
+        .with_context(|| format!("no SELINUXTYPE in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_file_contexts_resolution() {
+        let temp = TempDir::new().unwrap();
+        let variant = temp.path().join("variant");
+        fs::create_dir_all(&variant).unwrap();
+        assert_eq!(SelinuxRelabel::load_for_variant(&variant).unwrap(), None);
+
+        fs::write(variant.join(SELINUX_CONFIG_FILENAME), "").unwrap();
+        let relabel = SelinuxRelabel::load_for_variant(&variant).unwrap().unwrap();
+        let rootfs = temp.path().join("rootfs");
+        assert!(relabel.file_contexts_for(&rootfs).is_err());
+
+        fs::create_dir_all(rootfs.join("etc/selinux/targeted/contexts/files")).unwrap();
+        fs::write(
+            rootfs.join(SELINUX_CONFIG),
+            "SELINUX=enforcing\nSELINUXTYPE=targeted\n",
+        )
+        .unwrap();
+        assert!(relabel.file_contexts_for(&rootfs).is_err());
+        let contexts = rootfs.join("etc/selinux/targeted/contexts/files/file_contexts");
+        fs::write(&contexts, "/.* system_u:object_r:default_t:s0\n").unwrap();
+        assert_eq!(relabel.file_contexts_for(&rootfs).unwrap(), contexts);
+
+        fs::write(
+            variant.join(SELINUX_CONFIG_FILENAME),
+            "file_contexts = \"../host/file_contexts\"\n",
+        )
+        .unwrap();
+        assert!(SelinuxRelabel::load_for_variant(&variant).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! Units masked and services disabled in live images, configured per variant.
+//!
+//! A variant ships `services.toml`:
+//!
+//! ```toml
+//! # systemd: linked to /dev/null in the live overlay
+//! masked_units = ["systemd-networkd-wait-online.service", "getty@tty6.service"]
+//! # OpenRC: removed from every runlevel of the live image
+//! disabled_services = ["chronyd", "crond"]
+//! ```
+//!
+//! Names are checked against unit-name syntax when the file is loaded, and
+//! a list meant for the other init system is rejected rather than ignored.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Variant-local service policy file.
+pub const SERVICES_CONFIG_FILENAME: &str = "services.toml";
+
+/// Longest unit name systemd accepts.
+const MAX_UNIT_NAME_LEN: usize = 255;
+
+/// Unit types that can be masked.
+const UNIT_TYPES: &[&str] = &[
+    "service",
+    "socket",
+    "target",
+    "timer",
+    "mount",
+    "automount",
+    "path",
+    "swap",
+    "slice",
+    "scope",
+    "device",
+];
+
+/// Masked units and disabled services for one variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServicePolicy {
+    /// systemd units to mask, with their type suffix.
+    pub masked_units: Vec<String>,
+    /// OpenRC services to drop from every runlevel.
+    pub disabled_services: Vec<String>,
+}
+
+impl ServicePolicy {
+    /// Load `services.toml` from `variant_dir`; empty when the variant has
+    /// none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(SERVICES_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let policy: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        for unit in &policy.masked_units {
+            if !is_valid_unit_name(unit) {
+                bail!(
+                    "invalid systemd unit '{}' in {}: expected name[@instance].type",
+                    unit,
+                    path.display()
+                );
+            }
+        }
+        for service in &policy.disabled_services {
+            if !is_valid_openrc_service(service) {
+                bail!("invalid OpenRC service '{}' in {}", service, path.display());
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masked_units.is_empty() && self.disabled_services.is_empty()
+    }
+
+    /// Check the policy against the image it is applied to: the lists must
+    /// match the init system, and nothing the boot test needs may be turned
+    /// off.
+    pub fn check(&self, systemd: bool, required_services: &[String]) -> Result<()> {
+        if systemd && !self.disabled_services.is_empty() {
+            bail!(
+                "disabled_services is for OpenRC variants, this variant boots systemd\n\
+Remediation: list the units under masked_units instead"
+            );
+        }
+        if !systemd && !self.masked_units.is_empty() {
+            bail!(
+                "masked_units is for systemd variants, this variant boots OpenRC\n\
+Remediation: list the services under disabled_services instead"
+            );
+        }
+        for required in required_services {
+            let unit = format!("{}.service", required);
+            if self.masked_units.contains(&unit) || self.disabled_services.contains(required) {
+                bail!(
+                    "service '{}' is required by the boot test and cannot be masked or disabled",
+                    required
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn masked_units(&self) -> Vec<&str> {
+        self.masked_units.iter().map(String::as_str).collect()
+    }
+
+    pub fn disabled_services(&self) -> Vec<&str> {
+        self.disabled_services.iter().map(String::as_str).collect()
+    }
+}
+
+/// Whether `name` is a systemd unit name: `prefix[@instance].type`, where
+/// a template (`getty@.service`) counts as well.
+pub fn is_valid_unit_name(name: &str) -> bool {
+    let Some((stem, unit_type)) = name.rsplit_once('.') else {
+        return false;
+    };
+    let prefix = stem.split_once('@').map_or(stem, |(prefix, _)| prefix);
+    name.len() <= MAX_UNIT_NAME_LEN
+        && UNIT_TYPES.contains(&unit_type)
+        && !prefix.is_empty()
+        && stem.matches('@').count() <= 1
+        && stem
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.' | '\\' | '@'))
+}
+
+/// Whether `name` is an OpenRC service (an `/etc/init.d` script name).
+pub fn is_valid_openrc_service(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Remove `services` from every runlevel under `root/etc/runlevels`.
+/// Returns the `runlevel/service` entries removed.
+pub fn disable_openrc_services(root: &Path, services: &[&str]) -> Result<Vec<String>> {
+    let runlevels = root.join("etc/runlevels");
+    let mut removed = Vec::new();
+    if services.is_empty() || !runlevels.is_dir() {
+        return Ok(removed);
+    }
+    let mut entries: Vec<_> = fs::read_dir(&runlevels)
+        .with_context(|| format!("Failed to read {}", runlevels.display()))?
+        .collect::<std::io::Result<_>>()
+        .with_context(|| format!("Failed to read {}", runlevels.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let runlevel = entry.file_name();
+        for service in services {
+            let link = entry.path().join(service);
+            if link.symlink_metadata().is_ok() {
+                fs::remove_file(&link)
+                    .with_context(|| format!("Failed to remove {}", link.display()))?;
+                removed.push(format!("{}/{}", runlevel.to_string_lossy(), service));
+            }
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_unit_name_syntax() {
+        for valid in [
+            "sshd.service",
+            "getty@tty6.service",
+            "getty@.service",
+            "dev-ttyS0.device",
+            "systemd-networkd-wait-online.service",
+            "sys-kernel-debug.mount",
+        ] {
+            assert!(is_valid_unit_name(valid), "{valid} should be accepted");
+        }
+        for invalid in [
+            "sshd",
+            "sshd.unit",
+            ".service",
+            "@tty1.service",
+            "a@b@c.service",
+            "../sshd.service",
+            "ssh d.service",
+        ] {
+            assert!(!is_valid_unit_name(invalid), "{invalid} should be rejected");
+        }
+        assert!(is_valid_unit_name(&format!("{}.service", "a".repeat(247))));
+        assert!(!is_valid_unit_name(&format!("{}.service", "a".repeat(248))));
+
+        assert!(is_valid_openrc_service("net.eth0"));
+        assert!(!is_valid_openrc_service("-sshd"));
+        assert!(!is_valid_openrc_service("../sshd"));
+    }
+
+    #[test]
+    fn test_policy_is_checked_and_applied() {
+        let temp = TempDir::new().unwrap();
+        let variant = temp.path().join("variant");
+        fs::create_dir_all(&variant).unwrap();
+        assert!(ServicePolicy::load_for_variant(&variant)
+            .unwrap()
+            .is_empty());
+
+        fs::write(
+            variant.join(SERVICES_CONFIG_FILENAME),
+            "disabled_services = [\"chronyd\", \"sshd\"]\n",
+        )
+        .unwrap();
+        let policy = ServicePolicy::load_for_variant(&variant).unwrap();
+        assert!(policy.check(false, &[]).is_ok());
+        assert!(policy.check(true, &[]).is_err());
+        assert!(policy.check(false, &["sshd".to_string()]).is_err());
+
+        let root = temp.path().join("rootfs");
+        for runlevel in ["boot", "default"] {
+            fs::create_dir_all(root.join("etc/runlevels").join(runlevel)).unwrap();
+        }
+        symlink(
+            "/etc/init.d/chronyd",
+            root.join("etc/runlevels/default/chronyd"),
+        )
+        .unwrap();
+        symlink("/etc/init.d/sshd", root.join("etc/runlevels/default/sshd")).unwrap();
+        symlink(
+            "/etc/init.d/hwclock",
+            root.join("etc/runlevels/boot/hwclock"),
+        )
+        .unwrap();
+        assert_eq!(
+            disable_openrc_services(&root, &policy.disabled_services()).unwrap(),
+            vec!["default/chronyd", "default/sshd"]
+        );
+        assert!(root
+            .join("etc/runlevels/boot/hwclock")
+            .symlink_metadata()
+            .is_ok());
+
+        fs::write(
+            variant.join(SERVICES_CONFIG_FILENAME),
+            "masked_units = [\"sshd\"]\n",
+        )
+        .unwrap();
+        assert!(ServicePolicy::load_for_variant(&variant).is_err());
+    }
+}
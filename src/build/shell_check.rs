@@ -0,0 +1,561 @@
+//! Syntax checks for shell scripts the builder generates.
+//!
+//! Every script written into a rootfs or overlay goes through
+//! [`check_script`] before the image is sealed. When `shellcheck` is on the
+//! host it runs at error severity; otherwise a small internal linter catches
+//! the mistakes that actually happen when scripts live in Rust string
+//! literals: unterminated quotes and here-documents, and unbalanced
+//! `if`/`fi`, `case`/`esac`, `do`/`done` and `{`/`}`.
+//!
+//! The internal linter is deliberately conservative. It understands quoting,
+//! `$(...)`, `$((...))`, comments and here-documents, but not the full
+//! grammar, so it reports only structure errors that `sh -n` would also
+//! reject.
+
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::process::{self, Cmd};
+
+/// A syntax problem found by [`lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintIssue {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Shell dialect for `path`, or `None` if it is not a shell script.
+///
+/// The shebang wins; without one, `.sh` files and OpenRC `local.d` hooks
+/// are treated as POSIX sh.
+pub fn shell_dialect(path: &Path, content: &str) -> Option<&'static str> {
+    if let Some(shebang) = content.lines().next().and_then(|l| l.strip_prefix("#!")) {
+        let mut parts = shebang.split_whitespace();
+        let mut interpreter = parts.next()?.rsplit('/').next()?;
+        if interpreter == "env" {
+            interpreter = parts.next()?;
+        }
+        return match interpreter {
+            "sh" | "ash" | "dash" | "openrc-run" => Some("sh"),
+            "bash" => Some("bash"),
+            _ => None,
+        };
+    }
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("sh" | "start" | "stop") => Some("sh"),
+        _ => None,
+    }
+}
+
+/// Check the script at `path`; non-shell files pass untouched.
+pub fn check_script(path: &Path) -> Result<()> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read script {}", path.display()))?;
+    let Some(dialect) = shell_dialect(path, &content) else {
+        return Ok(());
+    };
+
+    if process::exists("shellcheck") {
+        let result = Cmd::new("shellcheck")
+            .args(["--severity=error", "--format=gcc"])
+            .arg(format!("--shell={}", dialect))
+            .arg_path(path)
+            .allow_fail()
+            .run()?;
+        if !result.success() {
+            bail!(
+                "shellcheck rejected generated script {}:\n{}",
+                path.display(),
+                result.stdout_trimmed()
+            );
+        }
+        return Ok(());
+    }
+
+    let issues = lint(&content);
+    if !issues.is_empty() {
+        let lines: Vec<String> = issues.iter().map(|i| format!("  {}", i)).collect();
+        bail!(
+            "generated script {} has syntax errors:\n{}",
+            path.display(),
+            lines.join("\n")
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quote {
+    Single,
+    Double,
+    Backtick,
+    /// `$(...)` with its nested parenthesis depth.
+    Subst(u32),
+    /// `$((...))` with its nested parenthesis depth; `<<` in here is a shift.
+    Arith(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    If,
+    /// `for`/`while`/`until` waiting for `do`.
+    Loop,
+    Do,
+    Case,
+    Brace,
+}
+
+impl Block {
+    fn opener(self) -> &'static str {
+        match self {
+            Block::If => "if",
+            Block::Loop => "for/while",
+            Block::Do => "do",
+            Block::Case => "case",
+            Block::Brace => "{",
+        }
+    }
+
+    fn closer(self) -> &'static str {
+        match self {
+            Block::If => "fi",
+            Block::Loop => "do",
+            Block::Do => "done",
+            Block::Case => "esac",
+            Block::Brace => "}",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Linter {
+    issues: Vec<LintIssue>,
+    blocks: Vec<(Block, usize)>,
+    word: String,
+    word_quoted: bool,
+    command_pos: bool,
+}
+
+impl Linter {
+    fn issue(&mut self, line: usize, message: String) {
+        self.issues.push(LintIssue { line, message });
+    }
+
+    fn finish_word(&mut self, line: usize) {
+        let word = std::mem::take(&mut self.word);
+        let quoted = std::mem::replace(&mut self.word_quoted, false);
+        if word.is_empty() {
+            return;
+        }
+        // Keywords, `{` and `}` included, are only reserved in command
+        // position: `echo }` prints a brace.
+        if quoted || !self.command_pos {
+            self.command_pos = false;
+            return;
+        }
+        self.command_pos = true;
+        match word.as_str() {
+            "if" => self.blocks.push((Block::If, line)),
+            "then" | "elif" | "else" => self.expect_top(Block::If, &word, line, false),
+            "fi" => self.expect_top(Block::If, &word, line, true),
+            "while" | "until" => self.blocks.push((Block::Loop, line)),
+            "for" | "select" => {
+                self.blocks.push((Block::Loop, line));
+                self.command_pos = false;
+            }
+            "do" => {
+                self.expect_top(Block::Loop, &word, line, true);
+                self.blocks.push((Block::Do, line));
+            }
+            "done" => self.expect_top(Block::Do, &word, line, true),
+            "case" => {
+                self.blocks.push((Block::Case, line));
+                self.command_pos = false;
+            }
+            "esac" => self.expect_top(Block::Case, &word, line, true),
+            "{" => self.blocks.push((Block::Brace, line)),
+            "}" => self.expect_top(Block::Brace, &word, line, true),
+            "!" => {}
+            _ => self.command_pos = false,
+        }
+    }
+
+    fn expect_top(&mut self, block: Block, word: &str, line: usize, pop: bool) {
+        match self.blocks.last().copied() {
+            Some((top, _)) if top == block => {
+                if pop {
+                    self.blocks.pop();
+                }
+            }
+            Some((top, opened)) => self.issue(
+                line,
+                format!(
+                    "unexpected '{}' while '{}' from line {} is still open",
+                    word,
+                    top.opener(),
+                    opened
+                ),
+            ),
+            None => self.issue(line, format!("unexpected '{}'", word)),
+        }
+    }
+}
+
+/// Structural syntax check for POSIX sh / bash source.
+pub fn lint(content: &str) -> Vec<LintIssue> {
+    let chars: Vec<char> = content.chars().collect();
+    let at = |i: usize| chars.get(i).copied();
+
+    let mut linter = Linter {
+        command_pos: true,
+        ..Linter::default()
+    };
+    let mut quotes: Vec<(Quote, usize)> = Vec::new();
+    // Word state of the enclosing code while inside `$(...)`.
+    let mut saved: Vec<(String, bool, bool)> = Vec::new();
+    let mut heredocs: Vec<(String, bool, usize)> = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match quotes.last().map(|(q, _)| *q) {
+            Some(Quote::Single) => {
+                if c == '\'' {
+                    quotes.pop();
+                }
+                if c == '\n' {
+                    line += 1;
+                }
+                i += 1;
+                continue;
+            }
+            Some(Quote::Arith(depth)) => {
+                match c {
+                    '(' => quotes.last_mut().unwrap().0 = Quote::Arith(depth + 1),
+                    ')' if depth > 0 => quotes.last_mut().unwrap().0 = Quote::Arith(depth - 1),
+                    ')' if at(i + 1) == Some(')') => {
+                        quotes.pop();
+                        i += 2;
+                        continue;
+                    }
+                    '\n' => line += 1,
+                    _ => {}
+                }
+                i += 1;
+                continue;
+            }
+            Some(Quote::Double) | Some(Quote::Backtick) => {
+                let closing = if quotes.last().map(|(q, _)| *q) == Some(Quote::Double) {
+                    '"'
+                } else {
+                    '`'
+                };
+                match c {
+                    '\\' => {
+                        if at(i + 1) == Some('\n') {
+                            line += 1;
+                        }
+                        i += 2;
+                        continue;
+                    }
+                    '$' if at(i + 1) == Some('(') && at(i + 2) == Some('(') => {
+                        quotes.push((Quote::Arith(0), line));
+                        i += 3;
+                        continue;
+                    }
+                    '$' if at(i + 1) == Some('(') => {
+                        quotes.push((Quote::Subst(0), line));
+                        saved.push((
+                            std::mem::take(&mut linter.word),
+                            linter.word_quoted,
+                            linter.command_pos,
+                        ));
+                        linter.word_quoted = false;
+                        linter.command_pos = true;
+                        i += 2;
+                        continue;
+                    }
+                    '`' if closing == '"' => quotes.push((Quote::Backtick, line)),
+                    c if c == closing => {
+                        quotes.pop();
+                    }
+                    '\n' => line += 1,
+                    _ => {}
+                }
+                i += 1;
+                continue;
+            }
+            None | Some(Quote::Subst(_)) => {}
+        }
+
+        match c {
+            '\\' => {
+                if at(i + 1) == Some('\n') {
+                    line += 1;
+                } else {
+                    linter.word.push(c);
+                    linter.word_quoted = true;
+                }
+                i += 2;
+                continue;
+            }
+            '\'' | '"' | '`' => {
+                let quote = match c {
+                    '\'' => Quote::Single,
+                    '"' => Quote::Double,
+                    _ => Quote::Backtick,
+                };
+                quotes.push((quote, line));
+                linter.word.push(c);
+                linter.word_quoted = true;
+            }
+            '$' if at(i + 1) == Some('(') && at(i + 2) == Some('(') => {
+                linter.word.push('$');
+                linter.word_quoted = true;
+                quotes.push((Quote::Arith(0), line));
+                i += 3;
+                continue;
+            }
+            '$' if at(i + 1) == Some('(') => {
+                linter.word.push('$');
+                linter.word_quoted = true;
+                quotes.push((Quote::Subst(0), line));
+                saved.push((
+                    std::mem::take(&mut linter.word),
+                    linter.word_quoted,
+                    linter.command_pos,
+                ));
+                linter.word_quoted = false;
+                linter.command_pos = true;
+                i += 2;
+                continue;
+            }
+            '#' if linter.word.is_empty() => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '(' => {
+                linter.finish_word(line);
+                if let Some((Quote::Subst(depth), _)) = quotes.last_mut() {
+                    *depth += 1;
+                }
+                linter.command_pos = true;
+            }
+            ')' => {
+                linter.finish_word(line);
+                match quotes.last_mut() {
+                    Some((Quote::Subst(0), _)) => {
+                        quotes.pop();
+                        let (word, quoted, command_pos) = saved.pop().unwrap_or_default();
+                        linter.word = word;
+                        linter.word_quoted = quoted;
+                        linter.command_pos = command_pos;
+                    }
+                    Some((Quote::Subst(depth), _)) => *depth -= 1,
+                    _ => linter.command_pos = true,
+                }
+            }
+            '<' if at(i + 1) == Some('<') => {
+                linter.finish_word(line);
+                if at(i + 2) == Some('<') {
+                    i += 3;
+                    continue;
+                }
+                let mut j = i + 2;
+                let strip_tabs = at(j) == Some('-');
+                if strip_tabs {
+                    j += 1;
+                }
+                while matches!(at(j), Some(' ' | '\t')) {
+                    j += 1;
+                }
+                let mut delimiter = String::new();
+                while let Some(d) = at(j) {
+                    if d.is_whitespace() || ";&|<>()".contains(d) {
+                        break;
+                    }
+                    if !matches!(d, '\'' | '"' | '\\') {
+                        delimiter.push(d);
+                    }
+                    j += 1;
+                }
+                if delimiter.is_empty() {
+                    linter.issue(line, "here-document without a delimiter".to_string());
+                } else {
+                    heredocs.push((delimiter, strip_tabs, line));
+                }
+                i = j;
+                continue;
+            }
+            ';' | '&' | '|' => {
+                linter.finish_word(line);
+                linter.command_pos = true;
+            }
+            '\n' => {
+                linter.finish_word(line);
+                linter.command_pos = true;
+                line += 1;
+                i += 1;
+                for (delimiter, strip_tabs, opened) in std::mem::take(&mut heredocs) {
+                    loop {
+                        if i >= chars.len() {
+                            linter.issue(
+                                opened,
+                                format!("here-document '{}' is never terminated", delimiter),
+                            );
+                            break;
+                        }
+                        let end = chars[i..]
+                            .iter()
+                            .position(|&ch| ch == '\n')
+                            .map_or(chars.len(), |p| i + p);
+                        let text: String = chars[i..end].iter().collect();
+                        i = end + 1;
+                        line += 1;
+                        let text = if strip_tabs {
+                            text.trim_start_matches('\t')
+                        } else {
+                            text.as_str()
+                        };
+                        if text == delimiter {
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+            ' ' | '\t' | '<' | '>' => linter.finish_word(line),
+            _ => linter.word.push(c),
+        }
+        i += 1;
+    }
+    linter.finish_word(line);
+
+    for (delimiter, _, opened) in heredocs {
+        linter.issue(
+            opened,
+            format!("here-document '{}' is never terminated", delimiter),
+        );
+    }
+    for (quote, opened) in quotes {
+        let what = match quote {
+            Quote::Single => "single quote",
+            Quote::Double => "double quote",
+            Quote::Backtick => "backtick",
+            Quote::Subst(_) => "command substitution",
+            Quote::Arith(_) => "arithmetic expansion",
+        };
+        linter.issue(opened, format!("unterminated {}", what));
+    }
+    for (block, opened) in std::mem::take(&mut linter.blocks) {
+        linter.issue(
+            opened,
+            format!(
+                "'{}' is never closed with '{}'",
+                block.opener(),
+                block.closer()
+            ),
+        );
+    }
+    linter.issues.sort_by_key(|issue| issue.line);
+    linter.issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_assets_pass() {
+        for name in crate::assets::embedded_names() {
+            let content = crate::assets::embedded(name).unwrap();
+            if shell_dialect(Path::new(name), content).is_some() {
+                assert_eq!(lint(content), Vec::new(), "asset {}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_accepts_common_constructs() {
+        let script = r#"#!/bin/sh
+set -eu
+name="$(basename "$(dirname "$0")")"
+n=$((1 + 2))
+case "$name" in
+    ""|\#*) echo done ;;
+    *i*) ;;
+esac
+cat <<'EOF'
+if this were code it would not close
+EOF
+db() {
+    [ -n "$1" ] || { echo "missing" >&2; exit 1; }
+    for x in a b; do echo "$x fi"; done
+}
+"#;
+        assert_eq!(lint(script), Vec::new());
+    }
+
+    #[test]
+    fn test_no_false_positives_on_shifts_and_brace_arguments() {
+        let script = r#"#!/bin/sh
+size=$((1 << 20))
+echo "$(( (size >> 10) << 2 ))"
+echo }
+echo { not a group
+f() { if [ "$size" -gt 0 ]; then echo "$((size<<1))"; fi }
+"#;
+        assert_eq!(lint(script), Vec::new());
+        assert!(lint("n=$((1 + 2)\n")[0]
+            .message
+            .contains("unterminated arithmetic expansion"));
+    }
+
+    #[test]
+    fn test_reports_structure_errors() {
+        let issues = lint("#!/bin/sh\nif true; then\n  echo 'x\nfi\n");
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].message.contains("'if' is never closed"));
+        assert!(issues[1].message.contains("unterminated single quote"));
+
+        let issues = lint("while true; do\n  echo hi\nfi\n");
+        assert!(issues
+            .iter()
+            .any(|i| i.line == 3 && i.message.contains("unexpected 'fi'")));
+
+        let issues = lint("cat <<EOF\nbody\n");
+        assert!(issues[0].message.contains("'EOF' is never terminated"));
+    }
+
+    #[test]
+    fn test_dialect_detection() {
+        assert_eq!(
+            shell_dialect(Path::new("x"), "#!/usr/bin/env bash\n"),
+            Some("bash")
+        );
+        assert_eq!(
+            shell_dialect(Path::new("x"), "#!/sbin/openrc-run\n"),
+            Some("sh")
+        );
+        assert_eq!(
+            shell_dialect(Path::new("lang.sh"), "export LANG=C\n"),
+            Some("sh")
+        );
+        assert_eq!(
+            shell_dialect(Path::new("x.py"), "#!/usr/bin/python3\n"),
+            None
+        );
+        assert_eq!(shell_dialect(Path::new("unit.service"), "[Unit]\n"), None);
+    }
+}
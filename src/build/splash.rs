@@ -0,0 +1,247 @@
+//! Optional boot splash, configured per variant.
+//!
+//! A variant opts in by shipping `splash.toml` next to its other variant
+//! files:
+//!
+//! ```toml
+//! # UKI `.splash` section shown by systemd-stub (must be a BMP).
+//! bitmap = "branding/splash.bmp"
+//!
+//! [plymouth]
+//! theme = "levitate"
+//! theme_dir = "branding/plymouth/levitate"
+//! ```
+//!
+//! Paths are relative to the variant directory. The bitmap is handed to the
+//! release hook for `ukify --splash`; a Plymouth theme is copied into the
+//! rootfs, selected in `plymouthd.conf`, and required in the initramfs
+//! contract so a hook that forgets to pack Plymouth fails the build instead
+//! of booting to a blank screen.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::artifact::initramfs_check::InitramfsContract;
+use crate::build::cmdline::CmdlineBuilder;
+use crate::copy_dir_recursive;
+
+/// Variant-local splash configuration file.
+pub const SPLASH_CONFIG_FILENAME: &str = "splash.toml";
+
+const PLYMOUTH_THEMES_DIR: &str = "usr/share/plymouth/themes";
+const PLYMOUTHD_CONF: &str = "etc/plymouth/plymouthd.conf";
+const PLYMOUTHD_CANDIDATES: &[&str] = &["usr/sbin/plymouthd", "sbin/plymouthd"];
+
+/// Splash settings for one variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplashConfig {
+    /// BMP embedded as the UKI `.splash` section.
+    #[serde(default)]
+    pub bitmap: Option<PathBuf>,
+    #[serde(default)]
+    pub plymouth: Option<PlymouthTheme>,
+}
+
+/// A Plymouth theme shipped by the variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlymouthTheme {
+    pub theme: String,
+    /// Theme directory to install; omit to use one already in the rootfs.
+    #[serde(default)]
+    pub theme_dir: Option<PathBuf>,
+}
+
+impl SplashConfig {
+    /// Load `splash.toml` from `variant_dir`; `None` when the variant has no
+    /// splash. Relative paths are resolved against `variant_dir`.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(SPLASH_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        if let Some(bitmap) = &mut config.bitmap {
+            *bitmap = variant_dir.join(&*bitmap);
+            validate_bitmap(bitmap)?;
+        }
+        if let Some(plymouth) = &mut config.plymouth {
+            if plymouth.theme.is_empty() || plymouth.theme.contains('/') {
+                bail!(
+                    "invalid Plymouth theme name '{}' in {}",
+                    plymouth.theme,
+                    path.display()
+                );
+            }
+            if let Some(dir) = &mut plymouth.theme_dir {
+                *dir = variant_dir.join(&*dir);
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// Kernel parameters the splash needs.
+    ///
+    /// Plymouth is told to leave serial consoles alone so boot tests keep
+    /// reading plain text from ttyS0.
+    pub fn cmdline_tokens(&self) -> Vec<&'static str> {
+        match self.plymouth {
+            Some(_) => vec!["splash", "plymouth.ignore-serial-consoles"],
+            None => Vec::new(),
+        }
+    }
+
+    /// Append [`Self::cmdline_tokens`] to `cmdline`, skipping ones already set.
+    pub fn extend_cmdline(&self, cmdline: &str) -> Result<String> {
+        let mut builder = CmdlineBuilder::parse(cmdline)?;
+        for token in self.cmdline_tokens() {
+            if !builder.contains(token) {
+                builder = builder.flag(token);
+            }
+        }
+        builder.build()
+    }
+
+    /// Install the Plymouth theme into `rootfs` and select it.
+    pub fn install_into_rootfs(&self, rootfs: &Path) -> Result<()> {
+        let Some(plymouth) = &self.plymouth else {
+            return Ok(());
+        };
+        if !PLYMOUTHD_CANDIDATES
+            .iter()
+            .any(|rel| rootfs.join(rel).exists())
+        {
+            bail!(
+                "splash.toml selects Plymouth theme '{}' but plymouthd is not in the rootfs\n\
+                 Remediation: add plymouth to the variant's package set or drop [plymouth] from splash.toml.",
+                plymouth.theme
+            );
+        }
+
+        let theme_dest = rootfs.join(PLYMOUTH_THEMES_DIR).join(&plymouth.theme);
+        match &plymouth.theme_dir {
+            Some(src) => {
+                if !src.join(format!("{}.plymouth", plymouth.theme)).is_file() {
+                    bail!(
+                        "Plymouth theme dir {} has no {}.plymouth",
+                        src.display(),
+                        plymouth.theme
+                    );
+                }
+                copy_dir_recursive(src, &theme_dest).with_context(|| {
+                    format!(
+                        "Failed to copy Plymouth theme {} -> {}",
+                        src.display(),
+                        theme_dest.display()
+                    )
+                })?;
+            }
+            None if !theme_dest.is_dir() => bail!(
+                "Plymouth theme '{}' is not installed in the rootfs and no theme_dir is set",
+                plymouth.theme
+            ),
+            None => {}
+        }
+
+        let conf = rootfs.join(PLYMOUTHD_CONF);
+        if let Some(parent) = conf.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&conf, format!("[Daemon]\nTheme={}\n", plymouth.theme))
+            .with_context(|| format!("Failed to write {}", conf.display()))
+    }
+
+    /// Require the Plymouth bits in the initramfs when a theme is configured.
+    pub fn extend_initramfs_contract(&self, contract: &mut InitramfsContract) {
+        if let Some(plymouth) = &self.plymouth {
+            for path in [
+                "usr/sbin/plymouthd".to_string(),
+                "usr/bin/plymouth".to_string(),
+                PLYMOUTHD_CONF.to_string(),
+                format!("{}/{}", PLYMOUTH_THEMES_DIR, plymouth.theme),
+            ] {
+                if !contract.extra_paths.contains(&path) {
+                    contract.extra_paths.push(path);
+                }
+            }
+        }
+    }
+}
+
+/// systemd-stub only renders uncompressed BMP splash images.
+fn validate_bitmap(path: &Path) -> Result<()> {
+    let data = fs::read(path)
+        .with_context(|| format!("Failed to read splash bitmap {}", path.display()))?;
+    if data.len() < 26 || &data[..2] != b"BM" {
+        bail!(
+            "splash bitmap {} is not a BMP file (systemd-stub only supports BMP)",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_load_and_install_plymouth() {
+        let temp = TempDir::new().unwrap();
+        let variant = temp.path().join("variant");
+        let theme_dir = variant.join("branding/plymouth/acorn");
+        fs::create_dir_all(&theme_dir).unwrap();
+        fs::write(theme_dir.join("acorn.plymouth"), "[Plymouth Theme]\n").unwrap();
+        let mut bmp = b"BM".to_vec();
+        bmp.resize(64, 0);
+        fs::write(variant.join("splash.bmp"), bmp).unwrap();
+        fs::write(
+            variant.join(SPLASH_CONFIG_FILENAME),
+            "bitmap = \"splash.bmp\"\n[plymouth]\ntheme = \"acorn\"\ntheme_dir = \"branding/plymouth/acorn\"\n",
+        )
+        .unwrap();
+
+        let config = SplashConfig::load_for_variant(&variant).unwrap().unwrap();
+        assert_eq!(config.bitmap, Some(variant.join("splash.bmp")));
+        assert_eq!(
+            config.extend_cmdline("console=ttyS0 splash").unwrap(),
+            "console=ttyS0 splash plymouth.ignore-serial-consoles"
+        );
+
+        let rootfs = temp.path().join("rootfs");
+        assert!(config.install_into_rootfs(&rootfs).is_err());
+        fs::create_dir_all(rootfs.join("usr/sbin")).unwrap();
+        fs::write(rootfs.join("usr/sbin/plymouthd"), "").unwrap();
+        config.install_into_rootfs(&rootfs).unwrap();
+        assert!(rootfs
+            .join("usr/share/plymouth/themes/acorn/acorn.plymouth")
+            .is_file());
+        assert_eq!(
+            fs::read_to_string(rootfs.join(PLYMOUTHD_CONF)).unwrap(),
+            "[Daemon]\nTheme=acorn\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_config_and_bad_bitmap() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(SplashConfig::load_for_variant(temp.path()).unwrap(), None);
+
+        fs::write(temp.path().join("splash.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        fs::write(
+            temp.path().join(SPLASH_CONFIG_FILENAME),
+            "bitmap = \"splash.png\"\n",
+        )
+        .unwrap();
+        assert!(SplashConfig::load_for_variant(temp.path()).is_err());
+    }
+}
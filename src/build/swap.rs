@@ -0,0 +1,301 @@
+//! Compressed swap for live sessions, configured per variant.
+//!
+//! Live sessions run from RAM, so installing on a small machine can run out
+//! of memory. A variant opts in by shipping `swap.toml`:
+//!
+//! ```toml
+//! mode = "zram"         # or "zswap"
+//! ram_percent = 50      # zram device size as a share of RAM
+//! max_size_mb = 4096    # optional cap on the device size
+//! algorithm = "zstd"
+//! ```
+//!
+//! systemd overlays get a `zram-generator` config when the rootfs ships the
+//! generator; otherwise, and on OpenRC, a boot script sets the device up.
+//! `zswap` mode enables the kernel's compressed swap cache instead.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use crate::build::shell_check::check_script;
+
+/// Variant-local swap settings file.
+pub const SWAP_CONFIG_FILENAME: &str = "swap.toml";
+
+/// Where systemd looks for the zram generator.
+const ZRAM_GENERATOR: &str = "usr/lib/systemd/system-generators/zram-generator";
+
+/// Setup script installed into the overlay, relative to its root.
+const SETUP_SCRIPT: &str = "usr/local/sbin/live-swap-setup";
+
+/// Priority above any disk swap the user enables during install.
+const SWAP_PRIORITY: u32 = 100;
+
+/// Kind of compressed swap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwapMode {
+    /// Swap on a compressed RAM block device.
+    #[default]
+    Zram,
+    /// Compressed cache in front of disk swap.
+    Zswap,
+}
+
+/// Compressed swap settings for one variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LiveSwap {
+    pub mode: SwapMode,
+    /// zram device size, as a percentage of RAM.
+    pub ram_percent: u32,
+    /// Upper bound on the zram device size.
+    pub max_size_mb: Option<u64>,
+    /// Kernel compression algorithm.
+    pub algorithm: String,
+    /// Set by [`LiveSwap::detect_generator`].
+    #[serde(skip)]
+    pub zram_generator: bool,
+}
+
+impl Default for LiveSwap {
+    fn default() -> Self {
+        Self {
+            mode: SwapMode::Zram,
+            ram_percent: 50,
+            max_size_mb: None,
+            algorithm: "zstd".to_string(),
+            zram_generator: false,
+        }
+    }
+}
+
+impl LiveSwap {
+    /// Load `swap.toml` from `variant_dir`; `None` when the variant has none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(SWAP_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if !(1..=200).contains(&config.ram_percent) {
+            bail!(
+                "ram_percent {} in {} must be between 1 and 200",
+                config.ram_percent,
+                path.display()
+            );
+        }
+        if config.max_size_mb == Some(0) {
+            bail!("max_size_mb in {} must be positive", path.display());
+        }
+        let valid_algorithm = !config.algorithm.is_empty()
+            && config
+                .algorithm
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid_algorithm {
+            bail!(
+                "invalid compression algorithm '{}' in {}",
+                config.algorithm,
+                path.display()
+            );
+        }
+        Ok(Some(config))
+    }
+
+    /// Use `zram-generator` on systemd when `rootfs` ships it.
+    pub fn detect_generator(&mut self, rootfs: &Path) {
+        self.zram_generator = rootfs.join(ZRAM_GENERATOR).is_file();
+    }
+
+    /// `zram-size` expression for `zram-generator` (sizes in MiB).
+    fn generator_size(&self) -> String {
+        let size = format!("ram * {} / 100", self.ram_percent);
+        match self.max_size_mb {
+            Some(max) => format!("min({}, {})", size, max),
+            None => size,
+        }
+    }
+
+    /// `/etc/systemd/zram-generator.conf`.
+    pub fn zram_generator_conf(&self) -> String {
+        format!(
+            "# Generated by distro-builder: live zram swap\n\
+             [zram0]\n\
+             zram-size = {}\n\
+             compression-algorithm = {}\n\
+             swap-priority = {}\n",
+            self.generator_size(),
+            self.algorithm,
+            SWAP_PRIORITY
+        )
+    }
+
+    /// Boot script that sets up swap without a generator.
+    pub fn setup_script(&self) -> String {
+        let mut script = String::from("#!/bin/sh\n# Generated by distro-builder: live swap\n");
+        match self.mode {
+            SwapMode::Zswap => {
+                script.push_str(&format!(
+                    "params=/sys/module/zswap/parameters\n\
+                     [ -d \"$params\" ] || exit 0\n\
+                     echo {} > \"$params/compressor\" 2>/dev/null\n\
+                     echo 1 > \"$params/enabled\"\n",
+                    self.algorithm
+                ));
+            }
+            SwapMode::Zram => {
+                script.push_str(&format!(
+                    "grep -q '^/dev/zram0 ' /proc/swaps && exit 0\n\
+                     modprobe zram 2>/dev/null\n\
+                     [ -e /sys/block/zram0/disksize ] || exit 0\n\
+                     size_kb=$(awk '/^MemTotal:/ {{ print int($2 * {} / 100) }}' /proc/meminfo)\n",
+                    self.ram_percent
+                ));
+                if let Some(max) = self.max_size_mb {
+                    let max_kb = max * 1024;
+                    script.push_str(&format!(
+                        "[ \"$size_kb\" -gt {max_kb} ] && size_kb={max_kb}\n"
+                    ));
+                }
+                script.push_str(&format!(
+                    "echo {} > /sys/block/zram0/comp_algorithm 2>/dev/null\n\
+                     echo \"${{size_kb}}K\" > /sys/block/zram0/disksize || exit 0\n\
+                     mkswap /dev/zram0 >/dev/null && swapon -p {} /dev/zram0\n",
+                    self.algorithm, SWAP_PRIORITY
+                ));
+            }
+        }
+        script
+    }
+
+    /// Apply to a systemd live overlay.
+    pub fn apply_systemd(&self, live_overlay: &Path) -> Result<()> {
+        if self.mode == SwapMode::Zram && self.zram_generator {
+            return write(
+                &live_overlay.join("etc/systemd/zram-generator.conf"),
+                &self.zram_generator_conf(),
+            );
+        }
+        self.write_setup_script(live_overlay)?;
+        write(
+            &live_overlay.join("etc/systemd/system/live-swap.service"),
+            &format!(
+                "[Unit]\n\
+                 Description=Live session compressed swap\n\
+                 DefaultDependencies=no\n\
+                 After=systemd-modules-load.service\n\
+                 Before=swap.target\n\n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 ExecStart=/{}\n\
+                 RemainAfterExit=yes\n\n\
+                 [Install]\n\
+                 WantedBy=swap.target\n",
+                SETUP_SCRIPT
+            ),
+        )?;
+        let wants = live_overlay.join("etc/systemd/system/swap.target.wants");
+        fs::create_dir_all(&wants)
+            .with_context(|| format!("Failed to create {}", wants.display()))?;
+        symlink(
+            "/etc/systemd/system/live-swap.service",
+            wants.join("live-swap.service"),
+        )
+        .with_context(|| "Failed to enable live-swap.service")
+    }
+
+    /// Apply to an OpenRC live overlay, through the `local` service.
+    pub fn apply_openrc(&self, live_overlay: &Path) -> Result<()> {
+        self.write_setup_script(live_overlay)?;
+        write(
+            &live_overlay.join("etc/local.d/50-live-swap.start"),
+            &format!("#!/bin/sh\nexec /{}\n", SETUP_SCRIPT),
+        )?;
+        set_executable(&live_overlay.join("etc/local.d/50-live-swap.start"))?;
+        let default = live_overlay.join("etc/runlevels/default");
+        fs::create_dir_all(&default)
+            .with_context(|| format!("Failed to create {}", default.display()))?;
+        let link = default.join("local");
+        if link.symlink_metadata().is_ok() {
+            return Ok(());
+        }
+        symlink("/etc/init.d/local", &link).with_context(|| "Failed to enable local service")
+    }
+
+    fn write_setup_script(&self, live_overlay: &Path) -> Result<()> {
+        let path = live_overlay.join(SETUP_SCRIPT);
+        write(&path, &self.setup_script())?;
+        check_script(&path)?;
+        set_executable(&path)
+    }
+}
+
+fn set_executable(path: &Path) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))
+}
+
+fn write(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_live_swap_config() {
+        let temp = TempDir::new().unwrap();
+        let variant = temp.path().join("variant");
+        fs::create_dir_all(&variant).unwrap();
+        assert_eq!(LiveSwap::load_for_variant(&variant).unwrap(), None);
+
+        fs::write(
+            variant.join(SWAP_CONFIG_FILENAME),
+            "ram_percent = 75\nmax_size_mb = 2048\n",
+        )
+        .unwrap();
+        let mut swap = LiveSwap::load_for_variant(&variant).unwrap().unwrap();
+        assert_eq!(swap.mode, SwapMode::Zram);
+        assert!(swap
+            .zram_generator_conf()
+            .contains("zram-size = min(ram * 75 / 100, 2048)\n"));
+        assert!(swap
+            .setup_script()
+            .contains("[ \"$size_kb\" -gt 2097152 ] && size_kb=2097152\n"));
+
+        let rootfs = temp.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("usr/lib/systemd/system-generators")).unwrap();
+        fs::write(rootfs.join(ZRAM_GENERATOR), "").unwrap();
+        swap.detect_generator(&rootfs);
+        let overlay = temp.path().join("systemd");
+        swap.apply_systemd(&overlay).unwrap();
+        assert!(overlay.join("etc/systemd/zram-generator.conf").is_file());
+        assert!(!overlay.join(SETUP_SCRIPT).exists());
+
+        let overlay = temp.path().join("openrc");
+        swap.apply_openrc(&overlay).unwrap();
+        assert!(overlay
+            .join("etc/runlevels/default/local")
+            .symlink_metadata()
+            .is_ok());
+        assert!(overlay.join("etc/local.d/50-live-swap.start").is_file());
+
+        for invalid in ["ram_percent = 0\n", "algorithm = \"lz4; reboot\"\n"] {
+            fs::write(variant.join(SWAP_CONFIG_FILENAME), invalid).unwrap();
+            assert!(LiveSwap::load_for_variant(&variant).is_err(), "{invalid}");
+        }
+    }
+}
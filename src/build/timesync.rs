@@ -0,0 +1,492 @@
+//! Guest time synchronization and RTC policy, configured per variant.
+//!
+//! Without an NTP client a paused or suspended QEMU guest keeps the time it
+//! stopped at, and installed test systems drift until TLS and package
+//! signatures start failing. A variant ships `timesync.toml`:
+//!
+//! ```toml
+//! client = "auto"        # "chrony", "timesyncd" or "none"
+//! servers = ["0.pool.ntp.org", "1.pool.ntp.org"]
+//! rtc = "utc"            # or "local" for dual-boot machines
+//! time_seed = true       # never boot with a clock older than the build
+//! ```
+//!
+//! `auto` picks chrony when the rootfs ships `chronyd`, then
+//! systemd-timesyncd. The client is configured and enabled; chrony steps
+//! the clock on any offset, since guests routinely jump after a host
+//! suspend. The RTC policy goes to `/etc/adjtime` (and OpenRC's `hwclock`
+//! settings). The time seed is the build time (or `SOURCE_DATE_EPOCH`),
+//! written into `/usr/lib/clock-epoch` and used as its mtime, which systemd
+//! takes as the earliest plausible clock. EROFS images and store tarballs
+//! normalize mtimes, so a boot step also reads the seed from the file
+//! contents: a `sysinit` unit on systemd, a `local.d` script on OpenRC.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::build::shell_check::check_script;
+use crate::reproducible;
+
+/// Variant-local time sync settings file.
+pub const TIMESYNC_CONFIG_FILENAME: &str = "timesync.toml";
+
+const CHRONYD_CANDIDATES: &[&str] = &["usr/sbin/chronyd", "usr/bin/chronyd", "sbin/chronyd"];
+const CHRONY_CONF_CANDIDATES: &[&str] = &["etc/chrony.conf", "etc/chrony/chrony.conf"];
+const TIMESYNCD: &str = "usr/lib/systemd/systemd-timesyncd";
+const TIMESYNCD_DROPIN: &str = "etc/systemd/timesyncd.conf.d/50-distro-builder.conf";
+const SYSTEMD_UNIT_DIR: &str = "usr/lib/systemd/system";
+const OPENRC_INIT_DIR: &str = "etc/init.d";
+
+/// systemd raises the clock to this file's mtime early in boot; the
+/// contents hold the same time in seconds.
+const CLOCK_EPOCH: &str = "usr/lib/clock-epoch";
+/// Raises the clock to the seed in [`CLOCK_EPOCH`] on OpenRC, through the
+/// `local` service.
+const OPENRC_SEED_SCRIPT: &str = "etc/local.d/00-time-seed.start";
+/// The same script for systemd, run by [`SYSTEMD_SEED_UNIT`].
+const SYSTEMD_SEED_SCRIPT: &str = "usr/lib/distro-builder/time-seed";
+const SYSTEMD_SEED_UNIT: &str = "distro-builder-time-seed.service";
+
+const DEFAULT_SERVERS: &[&str] = &["0.pool.ntp.org", "1.pool.ntp.org", "2.pool.ntp.org"];
+
+/// NTP client to configure.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSyncClient {
+    /// Whichever client the rootfs ships, chrony first.
+    #[default]
+    Auto,
+    Chrony,
+    Timesyncd,
+    /// Leave time sync alone; RTC and seed settings still apply.
+    None,
+}
+
+/// What the hardware clock holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtcMode {
+    #[default]
+    Utc,
+    Local,
+}
+
+impl RtcMode {
+    fn adjtime_name(self) -> &'static str {
+        match self {
+            Self::Utc => "UTC",
+            Self::Local => "LOCAL",
+        }
+    }
+}
+
+/// Time sync settings for one variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeSync {
+    pub client: TimeSyncClient,
+    pub servers: Vec<String>,
+    pub rtc: RtcMode,
+    pub time_seed: bool,
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        Self {
+            client: TimeSyncClient::Auto,
+            servers: DEFAULT_SERVERS.iter().map(|s| s.to_string()).collect(),
+            rtc: RtcMode::Utc,
+            time_seed: true,
+        }
+    }
+}
+
+impl TimeSync {
+    /// Load `timesync.toml` from `variant_dir`; `None` when the variant has
+    /// none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(TIMESYNC_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if config.client != TimeSyncClient::None && config.servers.is_empty() {
+            bail!("servers in {} is empty", path.display());
+        }
+        for server in &config.servers {
+            let valid = !server.is_empty()
+                && server
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'));
+            if !valid {
+                bail!("invalid NTP server '{}' in {}", server, path.display());
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// Client that [`Self::install_into_rootfs`] configures for `rootfs`.
+    pub fn resolve_client(&self, rootfs: &Path) -> Result<TimeSyncClient> {
+        let has_chrony = CHRONYD_CANDIDATES
+            .iter()
+            .any(|rel| rootfs.join(rel).exists());
+        let has_timesyncd = rootfs.join(TIMESYNCD).exists();
+        match self.client {
+            TimeSyncClient::Auto if has_chrony => Ok(TimeSyncClient::Chrony),
+            TimeSyncClient::Auto if has_timesyncd => Ok(TimeSyncClient::Timesyncd),
+            TimeSyncClient::Auto => {
+                eprintln!("  [WARN] rootfs ships neither chronyd nor systemd-timesyncd; time sync not configured");
+                Ok(TimeSyncClient::None)
+            }
+            TimeSyncClient::Chrony if !has_chrony => {
+                bail!("timesync client 'chrony' requested but the rootfs has no chronyd")
+            }
+            TimeSyncClient::Timesyncd if !has_timesyncd => bail!(
+                "timesync client 'timesyncd' requested but the rootfs has no {}",
+                TIMESYNCD
+            ),
+            client => Ok(client),
+        }
+    }
+
+    /// Configure and enable the client, write the RTC policy and the time
+    /// seed into `rootfs`.
+    pub fn install_into_rootfs(&self, rootfs: &Path) -> Result<()> {
+        match self.resolve_client(rootfs)? {
+            TimeSyncClient::Chrony => self.install_chrony(rootfs)?,
+            TimeSyncClient::Timesyncd => self.install_timesyncd(rootfs)?,
+            TimeSyncClient::Auto | TimeSyncClient::None => {}
+        }
+        write(
+            &rootfs.join("etc/adjtime"),
+            &format!("0.0 0 0.0\n0\n{}\n", self.rtc.adjtime_name()),
+        )?;
+        if rootfs.join(OPENRC_INIT_DIR).join("hwclock").is_file() {
+            let clock = match self.rtc {
+                RtcMode::Utc => "UTC",
+                RtcMode::Local => "local",
+            };
+            write(
+                &rootfs.join("etc/conf.d/hwclock"),
+                &format!(
+                    "# Generated by distro-builder: RTC policy\nclock=\"{}\"\n",
+                    clock
+                ),
+            )?;
+        }
+        if self.time_seed {
+            let seed = reproducible::source_date_time()?.unwrap_or_else(SystemTime::now);
+            seed_file(&rootfs.join(CLOCK_EPOCH), seed)?;
+            if rootfs.join(OPENRC_INIT_DIR).join("local").is_file() {
+                write_seed_script(&rootfs.join(OPENRC_SEED_SCRIPT))?;
+                enable(rootfs, "etc/runlevels/default/local", "/etc/init.d/local")?;
+            }
+            if rootfs.join(SYSTEMD_UNIT_DIR).is_dir() {
+                write_seed_script(&rootfs.join(SYSTEMD_SEED_SCRIPT))?;
+                write(
+                    &rootfs.join(SYSTEMD_UNIT_DIR).join(SYSTEMD_SEED_UNIT),
+                    &seed_unit(),
+                )?;
+                enable(
+                    rootfs,
+                    &format!(
+                        "etc/systemd/system/sysinit.target.wants/{}",
+                        SYSTEMD_SEED_UNIT
+                    ),
+                    &format!("/{}/{}", SYSTEMD_UNIT_DIR, SYSTEMD_SEED_UNIT),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// chrony config; `makestep 1 -1` steps on every large offset instead of
+    /// only during the first updates.
+    pub fn chrony_conf(&self) -> String {
+        let mut conf = String::from("# Generated by distro-builder: time sync\n");
+        for server in &self.servers {
+            conf.push_str(&format!("server {} iburst\n", server));
+        }
+        conf.push_str(
+            "driftfile /var/lib/chrony/drift\n\
+             makestep 1 -1\n\
+             rtcsync\n",
+        );
+        conf
+    }
+
+    pub fn timesyncd_dropin(&self) -> String {
+        format!(
+            "# Generated by distro-builder: time sync\n[Time]\nNTP={}\n",
+            self.servers.join(" ")
+        )
+    }
+
+    fn install_chrony(&self, rootfs: &Path) -> Result<()> {
+        let conf = CHRONY_CONF_CANDIDATES
+            .iter()
+            .find(|rel| rootfs.join(rel).is_file())
+            .unwrap_or(&CHRONY_CONF_CANDIDATES[1]);
+        write(&rootfs.join(conf), &self.chrony_conf())?;
+        if rootfs.join(OPENRC_INIT_DIR).join("chronyd").is_file() {
+            return enable(
+                rootfs,
+                "etc/runlevels/default/chronyd",
+                "/etc/init.d/chronyd",
+            );
+        }
+        // Fedora names the unit chronyd.service, Debian chrony.service.
+        let unit = ["chronyd.service", "chrony.service"]
+            .into_iter()
+            .find(|unit| rootfs.join(SYSTEMD_UNIT_DIR).join(unit).is_file())
+            .context("rootfs has chronyd but no chrony init script or unit")?;
+        enable(
+            rootfs,
+            &format!("etc/systemd/system/multi-user.target.wants/{}", unit),
+            &format!("/{}/{}", SYSTEMD_UNIT_DIR, unit),
+        )
+    }
+
+    fn install_timesyncd(&self, rootfs: &Path) -> Result<()> {
+        write(&rootfs.join(TIMESYNCD_DROPIN), &self.timesyncd_dropin())?;
+        enable(
+            rootfs,
+            "etc/systemd/system/sysinit.target.wants/systemd-timesyncd.service",
+            &format!("/{}/systemd-timesyncd.service", SYSTEMD_UNIT_DIR),
+        )
+    }
+}
+
+fn enable(rootfs: &Path, link: &str, target: &str) -> Result<()> {
+    let link = rootfs.join(link);
+    if link.symlink_metadata().is_ok() {
+        return Ok(());
+    }
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    symlink(target, &link).with_context(|| format!("Failed to create {}", link.display()))
+}
+
+/// Shell script raising the clock to the seed in [`CLOCK_EPOCH`].
+fn seed_script() -> String {
+    format!(
+        "#!/bin/sh\n\
+         # Generated by distro-builder: never run with a clock older than the image\n\
+         seed=$(cat /{} 2>/dev/null) || exit 0\n\
+         case \"$seed\" in ''|*[!0-9]*) exit 0 ;; esac\n\
+         [ \"$(date +%s)\" -lt \"$seed\" ] && date -s \"@$seed\" >/dev/null\n\
+         exit 0\n",
+        CLOCK_EPOCH
+    )
+}
+
+fn write_seed_script(path: &Path) -> Result<()> {
+    write(path, &seed_script())?;
+    check_script(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))
+}
+
+/// Unit running [`SYSTEMD_SEED_SCRIPT`] before anything looks at the clock.
+fn seed_unit() -> String {
+    format!(
+        "# Generated by distro-builder: time seed\n\
+         [Unit]\n\
+         Description=Raise the clock to the image build time\n\
+         DefaultDependencies=no\n\
+         ConditionPathExists=/{}\n\
+         Before=sysinit.target time-set.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart=/{}\n",
+        CLOCK_EPOCH, SYSTEMD_SEED_SCRIPT
+    )
+}
+
+/// Write `time` into `path` as seconds since the epoch and set it as the
+/// mtime.
+fn seed_file(path: &Path, time: SystemTime) -> Result<()> {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("time seed is before 1970")?
+        .as_secs();
+    write(path, &format!("{}\n", secs))?;
+    File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(time))
+        .with_context(|| format!("Failed to set mtime of {}", path.display()))
+}
+
+fn write(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn touch(root: &Path, rel: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn test_systemd_rootfs() {
+        let temp = TempDir::new().unwrap();
+        let variant = temp.path().join("variant");
+        fs::create_dir_all(&variant).unwrap();
+        assert_eq!(TimeSync::load_for_variant(&variant).unwrap(), None);
+        fs::write(
+            variant.join(TIMESYNC_CONFIG_FILENAME),
+            "servers = [\"time.example.org\"]\nrtc = \"local\"\n",
+        )
+        .unwrap();
+        let config = TimeSync::load_for_variant(&variant).unwrap().unwrap();
+
+        let rootfs = temp.path().join("rootfs");
+        touch(&rootfs, TIMESYNCD);
+        touch(&rootfs, "usr/lib/systemd/system/systemd-timesyncd.service");
+        config.install_into_rootfs(&rootfs).unwrap();
+        assert_eq!(
+            fs::read_to_string(rootfs.join(TIMESYNCD_DROPIN)).unwrap(),
+            "# Generated by distro-builder: time sync\n[Time]\nNTP=time.example.org\n"
+        );
+        assert!(rootfs
+            .join("etc/systemd/system/sysinit.target.wants/systemd-timesyncd.service")
+            .symlink_metadata()
+            .is_ok());
+        assert!(fs::read_to_string(rootfs.join("etc/adjtime"))
+            .unwrap()
+            .ends_with("\nLOCAL\n"));
+        let seed = fs::metadata(rootfs.join(CLOCK_EPOCH))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert!(seed.elapsed().unwrap().as_secs() < 60);
+        let secs: u64 = fs::read_to_string(rootfs.join(CLOCK_EPOCH))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            secs,
+            seed.duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        );
+        assert!(rootfs.join(SYSTEMD_SEED_SCRIPT).is_file());
+        assert_eq!(
+            fs::read_link(rootfs.join(format!(
+                "etc/systemd/system/sysinit.target.wants/{}",
+                SYSTEMD_SEED_UNIT
+            )))
+            .unwrap(),
+            Path::new("/usr/lib/systemd/system").join(SYSTEMD_SEED_UNIT)
+        );
+
+        fs::write(
+            variant.join(TIMESYNC_CONFIG_FILENAME),
+            "client = \"chrony\"\n",
+        )
+        .unwrap();
+        let config = TimeSync::load_for_variant(&variant).unwrap().unwrap();
+        assert!(config.install_into_rootfs(&rootfs).is_err());
+    }
+
+    #[test]
+    fn test_openrc_chrony() {
+        let temp = TempDir::new().unwrap();
+        for rel in [
+            "usr/sbin/chronyd",
+            "etc/chrony/chrony.conf",
+            "etc/init.d/chronyd",
+            "etc/init.d/hwclock",
+            "etc/init.d/local",
+        ] {
+            touch(temp.path(), rel);
+        }
+        TimeSync::default()
+            .install_into_rootfs(temp.path())
+            .unwrap();
+        let conf = fs::read_to_string(temp.path().join("etc/chrony/chrony.conf")).unwrap();
+        assert!(conf.contains("server 0.pool.ntp.org iburst\n"));
+        assert!(conf.contains("makestep 1 -1\n"));
+        assert_eq!(
+            fs::read_link(temp.path().join("etc/runlevels/default/chronyd")).unwrap(),
+            Path::new("/etc/init.d/chronyd")
+        );
+        assert!(fs::read_to_string(temp.path().join("etc/conf.d/hwclock"))
+            .unwrap()
+            .contains("clock=\"UTC\""));
+        assert!(temp.path().join(OPENRC_SEED_SCRIPT).is_file());
+        assert!(temp
+            .path()
+            .join("etc/runlevels/default/local")
+            .symlink_metadata()
+            .is_ok());
+
+        let bad = "servers = [\"pool; reboot\"]\n";
+        fs::write(temp.path().join(TIMESYNC_CONFIG_FILENAME), bad).unwrap();
+        assert!(TimeSync::load_for_variant(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_seed_survives_erofs_image() {
+        if !crate::process::exists("mkfs.erofs") || !crate::process::exists("fsck.erofs") {
+            return;
+        }
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path().join("rootfs");
+        touch(&rootfs, "etc/init.d/local");
+        touch(&rootfs, "usr/lib/systemd/system/basic.target");
+        let config = TimeSync {
+            client: TimeSyncClient::None,
+            ..TimeSync::default()
+        };
+        reproducible::with_source_date_epoch(1_700_000_000, || {
+            config.install_into_rootfs(&rootfs).unwrap();
+        });
+
+        // Built without an epoch, the image zeroes every mtime.
+        let image = temp.path().join("rootfs.erofs");
+        crate::artifact::rootfs::create_erofs(&rootfs, &image, "lz4hc", 9, 1048576).unwrap();
+        let extracted = temp.path().join("extracted");
+        crate::process::Cmd::new("fsck.erofs")
+            .arg(format!("--extract={}", extracted.display()))
+            .arg_path(&image)
+            .run()
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(extracted.join(CLOCK_EPOCH)).unwrap(),
+            "1700000000\n"
+        );
+        for script in [OPENRC_SEED_SCRIPT, SYSTEMD_SEED_SCRIPT] {
+            assert_eq!(
+                fs::read_to_string(extracted.join(script)).unwrap(),
+                seed_script()
+            );
+        }
+        assert!(extracted
+            .join(SYSTEMD_UNIT_DIR)
+            .join(SYSTEMD_SEED_UNIT)
+            .is_file());
+    }
+}
@@ -0,0 +1,415 @@
+//! Size guard and spill file for the live session's writable layer.
+//!
+//! Live sessions stack a writable layer over the read-only EROFS rootfs.
+//! On tmpfs it is bounded only by RAM, so a package install on a small
+//! machine runs it out of memory. A variant caps the layer, and can move it
+//! to a file on the boot medium, with `writable-layer.toml`:
+//!
+//! ```toml
+//! size = "50%"              # tmpfs size: percent of RAM, or 512M, 2G, ...
+//! warn_percent = 85         # login warning once the layer is this full
+//!
+//! [spill]
+//! label = "LIVEPERSIST"     # writable partition on the boot medium
+//! file = "live-rw.img"
+//! size_mb = 4000            # below 4096 on a FAT partition
+//! below_ram_mb = 4096       # spill only on machines with less RAM
+//! ```
+//!
+//! The initramfs gets a shell library whose `mount_writable_layer <dir>`
+//! mounts the layer (the spill file when it applies and the partition is
+//! found, the capped tmpfs otherwise); the netboot `/init` uses it when
+//! present. A live ISO's `/init` comes from the release hook, which runs
+//! `$WRITABLE_LAYER_INIT` and must call the function too: the initramfs
+//! contract check, which `$BUILD_UKIS` runs before the ISO is packed, fails
+//! a variant with this file whose initramfs lacks the library, a call to
+//! it, or the modules and applets of the spill file. The spill file is
+//! recreated empty on each boot, so it never replays a previous session.
+//! `rd.live.overlay.size=<size>` and `rd.live.overlay.spill=0|1` override
+//! the baked-in choice at boot.
+//!
+//! The live overlay gets the same settings plus a login warning when the
+//! layer is nearly full.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::artifact::initramfs_check::InitramfsContract;
+use crate::artifact::live_overlay::DEFAULT_PERSISTENCE_LABEL;
+use crate::build::shell_check::check_script;
+
+/// Variant-local writable layer settings file.
+pub const WRITABLE_LAYER_FILENAME: &str = "writable-layer.toml";
+
+/// Settings read by the initramfs library and the login warning.
+pub const WRITABLE_LAYER_CONF: &str = "etc/live-writable-layer.conf";
+
+/// Shell library defining `mount_writable_layer`, relative to the initramfs.
+pub const WRITABLE_LAYER_LIBRARY: &str = "lib/distro-builder/writable-layer.sh";
+
+/// Login warning installed into the live overlay.
+const PROFILE_SCRIPT: &str = "etc/profile.d/live-writable-layer.sh";
+
+/// Function the init calls to mount the layer.
+const MOUNT_FUNCTION: &str = "mount_writable_layer";
+
+/// Busybox applets the spill file needs.
+const SPILL_BUSYBOX_COMMANDS: &[&str] = &["findfs", "dd", "mke2fs", "losetup", "awk"];
+
+/// Kernel modules the spill file needs: loop, and ext4, which mounts the
+/// ext2 image busybox `mke2fs` writes.
+const SPILL_MODULES: &[&str] = &["loop", "ext4"];
+
+/// Writable layer settings for one variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WritableLayer {
+    /// tmpfs size: a percentage of RAM (`50%`) or a size (`512M`, `2G`).
+    pub size: String,
+    /// Usage percentage at which login shells warn.
+    pub warn_percent: u32,
+    /// Keep the layer in a file on the boot medium; RAM only when `None`.
+    pub spill: Option<SpillFile>,
+}
+
+/// Writable layer file on a partition of the boot medium.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpillFile {
+    /// Filesystem label of the partition holding the file.
+    pub label: String,
+    /// File name at the partition's root.
+    pub file: String,
+    /// Size of the file, created on first use.
+    pub size_mb: u64,
+    /// Spill only when the machine has less RAM than this; always when
+    /// `None`.
+    pub below_ram_mb: Option<u64>,
+}
+
+impl Default for WritableLayer {
+    fn default() -> Self {
+        Self {
+            size: "50%".to_string(),
+            warn_percent: 85,
+            spill: None,
+        }
+    }
+}
+
+impl Default for SpillFile {
+    fn default() -> Self {
+        Self {
+            label: DEFAULT_PERSISTENCE_LABEL.to_string(),
+            file: "live-rw.img".to_string(),
+            size_mb: 4000,
+            below_ram_mb: None,
+        }
+    }
+}
+
+impl WritableLayer {
+    /// Load `writable-layer.toml` from `variant_dir`; `None` when the
+    /// variant keeps the unbounded tmpfs layer.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(WRITABLE_LAYER_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let layer: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        layer
+            .validate()
+            .with_context(|| format!("Invalid {}", path.display()))?;
+        Ok(Some(layer))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !valid_size(&self.size) {
+            bail!(
+                "writable layer size '{}' must be a percentage of RAM (1%-100%) or a size like 512M or 2G",
+                self.size
+            );
+        }
+        if !(1..=100).contains(&self.warn_percent) {
+            bail!(
+                "warn_percent {} must be between 1 and 100",
+                self.warn_percent
+            );
+        }
+        if let Some(spill) = &self.spill {
+            if spill.label.is_empty()
+                || spill.label.len() > 16
+                || !spill
+                    .label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+            {
+                bail!(
+                    "invalid spill partition label '{}': use up to 16 of [A-Za-z0-9_-]",
+                    spill.label
+                );
+            }
+            if spill.file.is_empty()
+                || spill.file.starts_with('.')
+                || !spill
+                    .file
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            {
+                bail!("invalid spill file name '{}'", spill.file);
+            }
+            if spill.size_mb == 0 {
+                bail!("spill size_mb must be positive");
+            }
+            if spill.below_ram_mb == Some(0) {
+                bail!("spill below_ram_mb must be positive (omit it to always spill)");
+            }
+        }
+        Ok(())
+    }
+
+    /// Contents of `/etc/live-writable-layer.conf`.
+    pub fn conf(&self) -> Result<String> {
+        self.validate()?;
+        let mut conf = format!(
+            "# Generated by distro-builder\nSIZE=\"{}\"\nWARN_PERCENT=\"{}\"\n",
+            self.size, self.warn_percent
+        );
+        if let Some(spill) = &self.spill {
+            conf.push_str(&format!(
+                "SPILL_LABEL=\"{}\"\nSPILL_FILE=\"{}\"\nSPILL_SIZE_MB=\"{}\"\nSPILL_BELOW_RAM_MB=\"{}\"\n",
+                spill.label,
+                spill.file,
+                spill.size_mb,
+                spill.below_ram_mb.unwrap_or(0)
+            ));
+        }
+        Ok(conf)
+    }
+
+    /// Busybox applets [`WRITABLE_LAYER_LIBRARY`] needs on top of
+    /// [`STANDARD_BUSYBOX_COMMANDS`](crate::artifact::initramfs::STANDARD_BUSYBOX_COMMANDS).
+    pub fn busybox_commands(&self) -> Vec<&'static str> {
+        if self.spill.is_some() {
+            SPILL_BUSYBOX_COMMANDS.to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Require the library, its settings, a call to it and what the spill
+    /// file needs in the initramfs content contract.
+    pub fn extend_initramfs_contract(&self, contract: &mut InitramfsContract) {
+        contract
+            .extra_paths
+            .push(WRITABLE_LAYER_LIBRARY.to_string());
+        contract.extra_paths.push(WRITABLE_LAYER_CONF.to_string());
+        contract.called_functions.push(MOUNT_FUNCTION.to_string());
+        contract
+            .commands
+            .extend(self.busybox_commands().into_iter().map(String::from));
+        if self.spill.is_some() {
+            for module in SPILL_MODULES {
+                if !contract.modules.iter().any(|m| m == module) {
+                    contract.modules.push(module.to_string());
+                }
+            }
+        }
+    }
+
+    /// Write the library and its settings into an initramfs staging root.
+    pub fn install_into_initramfs(&self, initramfs_root: &Path) -> Result<()> {
+        write(&initramfs_root.join(WRITABLE_LAYER_CONF), &self.conf()?)?;
+        let library = initramfs_root.join(WRITABLE_LAYER_LIBRARY);
+        write(&library, INIT_LIBRARY)?;
+        check_script(&library)
+    }
+
+    /// Write the settings and the login warning into a live overlay.
+    pub fn apply_overlay(&self, live_overlay: &Path) -> Result<()> {
+        write(&live_overlay.join(WRITABLE_LAYER_CONF), &self.conf()?)?;
+        let profile = live_overlay.join(PROFILE_SCRIPT);
+        write(&profile, PROFILE_WARNING)?;
+        check_script(&profile)?;
+        fs::set_permissions(&profile, fs::Permissions::from_mode(0o644))
+            .with_context(|| format!("Failed to set permissions on {}", profile.display()))
+    }
+}
+
+/// `50%`, or a number with an optional K/M/G suffix.
+fn valid_size(size: &str) -> bool {
+    if let Some(percent) = size.strip_suffix('%') {
+        return percent.parse::<u32>().is_ok_and(|p| (1..=100).contains(&p));
+    }
+    let digits = size.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G']);
+    size.len() - digits.len() <= 1
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && digits.parse::<u64>().is_ok_and(|n| n > 0)
+}
+
+fn write(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+const INIT_LIBRARY: &str = r#"#!/bin/sh
+# Generated by distro-builder: live writable layer.
+# Sourced by the initramfs /init. `mount_writable_layer DIR` mounts the
+# layer at DIR and creates DIR/upper and DIR/work for overlayfs.
+
+SIZE=50%
+SPILL_LABEL=
+SPILL_FILE=live-rw.img
+SPILL_SIZE_MB=4000
+SPILL_BELOW_RAM_MB=0
+[ -r /etc/live-writable-layer.conf ] && . /etc/live-writable-layer.conf
+
+writable_layer_param() {
+    for arg in $(cat /proc/cmdline); do
+        case "$arg" in
+            "$1"=*) echo "${arg#*=}"; return 0 ;;
+        esac
+    done
+    return 1
+}
+
+mount_spill_file() {
+    dev="$(findfs "LABEL=$SPILL_LABEL" 2>/dev/null)" || return 1
+    mkdir -p /run/live-medium
+    mount -o rw "$dev" /run/live-medium || return 1
+    file="/run/live-medium/$SPILL_FILE"
+    # Recreate the file each boot so a session never replays the last one.
+    rm -f "$file"
+    echo "[live] creating ${SPILL_SIZE_MB}M writable layer file on $dev" >&2
+    if ! dd if=/dev/zero of="$file" bs=1M count=0 seek="$SPILL_SIZE_MB" 2>/dev/null \
+        || ! mke2fs -q -F "$file" >/dev/null 2>&1 \
+        || ! mount -o loop "$file" "$1"; then
+        rm -f "$file"
+        umount /run/live-medium
+        return 1
+    fi
+}
+
+mount_writable_layer() {
+    size="$(writable_layer_param rd.live.overlay.size)" || size="$SIZE"
+    spill="$(writable_layer_param rd.live.overlay.spill)" || spill=auto
+    mkdir -p "$1"
+    if [ -n "$SPILL_LABEL" ] && [ "$spill" != 0 ]; then
+        mem_mb=$(awk '/^MemTotal:/ { print int($2 / 1024) }' /proc/meminfo)
+        if [ "$spill" = 1 ] || [ "$SPILL_BELOW_RAM_MB" -eq 0 ] || [ "${mem_mb:-0}" -lt "$SPILL_BELOW_RAM_MB" ]; then
+            if mount_spill_file "$1"; then
+                mkdir -p "$1/upper" "$1/work"
+                echo "[live] writable layer: $SPILL_FILE on LABEL=$SPILL_LABEL" >&2
+                return 0
+            fi
+            echo "[live] LABEL=$SPILL_LABEL unusable; writable layer stays in RAM" >&2
+        fi
+    fi
+    mount -t tmpfs -o "mode=0755,size=$size" tmpfs "$1" || return 1
+    mkdir -p "$1/upper" "$1/work"
+}
+"#;
+
+const PROFILE_WARNING: &str = r#"# Generated by distro-builder: warn when the live writable layer fills up.
+live_layer_warn=85
+[ -r /etc/live-writable-layer.conf ] && live_layer_warn="$(. /etc/live-writable-layer.conf && echo "$WARN_PERCENT")"
+live_layer_used="$(df -P / 2>/dev/null | awk 'NR == 2 { sub("%", "", $5); print $5 }')"
+if [ -n "$live_layer_used" ] && [ "$live_layer_used" -ge "${live_layer_warn:-85}" ]; then
+    echo "Warning: the live session's writable layer is ${live_layer_used}% full." >&2
+    echo "Large installs may fail; reboot with rd.live.overlay.size=<size> or rd.live.overlay.spill=1." >&2
+fi
+unset live_layer_warn live_layer_used
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::shell_check::lint;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_load_and_validate() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(WritableLayer::load_for_variant(temp.path()).unwrap(), None);
+
+        fs::write(
+            temp.path().join(WRITABLE_LAYER_FILENAME),
+            "size = \"2G\"\n[spill]\nbelow_ram_mb = 4096\n",
+        )
+        .unwrap();
+        let layer = WritableLayer::load_for_variant(temp.path())
+            .unwrap()
+            .unwrap();
+        let conf = layer.conf().unwrap();
+        assert!(conf.contains("SIZE=\"2G\"\n"));
+        assert!(conf.contains("SPILL_LABEL=\"LIVEPERSIST\"\n"));
+        assert!(conf.contains("SPILL_BELOW_RAM_MB=\"4096\"\n"));
+        assert!(layer.busybox_commands().contains(&"mke2fs"));
+
+        for invalid in [
+            "size = \"0%\"\n",
+            "size = \"2GB\"\n",
+            "size = \"$(reboot)\"\n",
+            "[spill]\nfile = \"../escape\"\n",
+            "[spill]\nsize_mb = 0\n",
+        ] {
+            fs::write(temp.path().join(WRITABLE_LAYER_FILENAME), invalid).unwrap();
+            assert!(
+                WritableLayer::load_for_variant(temp.path()).is_err(),
+                "{invalid}"
+            );
+        }
+        assert!(valid_size("512m") && valid_size("100%") && !valid_size("M"));
+    }
+
+    #[test]
+    fn test_install_into_initramfs_and_overlay() {
+        assert_eq!(lint(INIT_LIBRARY), Vec::new());
+        assert_eq!(lint(PROFILE_WARNING), Vec::new());
+
+        let temp = TempDir::new().unwrap();
+        let layer = WritableLayer::default();
+        layer.install_into_initramfs(temp.path()).unwrap();
+        assert!(temp.path().join(WRITABLE_LAYER_LIBRARY).is_file());
+        assert!(!fs::read_to_string(temp.path().join(WRITABLE_LAYER_CONF))
+            .unwrap()
+            .contains("SPILL_LABEL"));
+        let mut contract = InitramfsContract::default();
+        layer.extend_initramfs_contract(&mut contract);
+        assert!(contract
+            .extra_paths
+            .contains(&WRITABLE_LAYER_LIBRARY.to_string()));
+        assert_eq!(contract.called_functions, vec![MOUNT_FUNCTION]);
+        assert!(contract.commands.is_empty());
+
+        let spill = WritableLayer {
+            spill: Some(SpillFile::default()),
+            ..Default::default()
+        };
+        let mut contract = InitramfsContract::default();
+        spill.extend_initramfs_contract(&mut contract);
+        assert!(contract.modules.contains(&"loop".to_string()));
+        assert!(contract.modules.contains(&"ext4".to_string()));
+        for command in ["findfs", "mke2fs", "losetup", "awk"] {
+            assert!(
+                contract.commands.contains(&command.to_string()),
+                "{command}"
+            );
+        }
+
+        let overlay = temp.path().join("overlay");
+        layer.apply_overlay(&overlay).unwrap();
+        assert!(overlay.join(PROFILE_SCRIPT).is_file());
+        assert!(overlay.join(WRITABLE_LAYER_CONF).is_file());
+    }
+}
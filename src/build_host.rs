@@ -4,7 +4,7 @@ use std::path::Path;
 
 pub use crate::pipeline::kernel::{
     EvidenceSpec as BuildHostEvidenceSpec, KernelEnsureOutcome as BuildHostKernelEnsureOutcome,
-    KernelSpec as BuildHostKernelSpec,
+    KernelShareKey as BuildHostKernelShareKey, KernelSpec as BuildHostKernelSpec,
 };
 
 pub fn check_kernel_preinstalled_via_recipe(
@@ -23,6 +23,22 @@ pub fn check_kernel_preinstalled_via_recipe(
     )
 }
 
+pub fn acquire_kernel_source_via_recipe(
+    repo_root: &Path,
+    variant_paths: &VariantOwnerPaths,
+    distro_id: &str,
+    kernel_output_dir: &Path,
+    spec: &BuildHostKernelSpec,
+) -> Result<()> {
+    crate::pipeline::kernel::acquire_kernel_source_with_recipe(
+        repo_root,
+        variant_paths,
+        distro_id,
+        kernel_output_dir,
+        spec,
+    )
+}
+
 pub fn ensure_kernel_preinstalled_via_recipe(
     repo_root: &Path,
     variant_paths: &VariantOwnerPaths,
@@ -0,0 +1,530 @@
+//! Machine-readable summary of one CLI invocation.
+//!
+//! Tooling around the builder (CI dashboards, release bots, embedders) kept
+//! scraping console output and run manifests for the same handful of facts.
+//! While the CLI runs, the pipeline records them here: timed stages
+//! ([`stage`]), produced artifacts with size and sha256 ([`record_artifact`]),
+//! cache hits and misses per cache ([`record_cache`]), boot test results
+//! ([`record_tests`]) and the release run directories it created
+//! ([`record_run_dir`]). When the invocation ends, successful or not, the
+//! CLI turns the records into a [`BuildSummary`] and writes
+//! `build-summary.json` to:
+//!
+//! ```text
+//! <artifacts>/summaries/<started_at_utc>-<pid>/build-summary.json
+//! <artifacts>/summaries/latest -> <started_at_utc>-<pid>
+//! <release run dir>/build-summary.json     # every run the invocation created
+//! $DISTRO_BUILDER_SUMMARY_PATH             # when set
+//! ```
+//!
+//! The document is versioned by [`BUILD_SUMMARY_SCHEMA_VERSION`]; fields are
+//! only ever added within a version. [`crate::notify`] also sends it, with
+//! status `running`, each time a stage ends.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+use crate::error::ErrorReport;
+use crate::guest_protocol::TestStatus;
+use crate::guest_results::{GuestTestResults, TestRecord};
+
+/// File name of the summary in every location it is written to.
+pub const BUILD_SUMMARY_FILENAME: &str = "build-summary.json";
+
+/// Extra path the summary is copied to, for CI jobs that want a fixed one.
+pub const BUILD_SUMMARY_PATH_ENV: &str = "DISTRO_BUILDER_SUMMARY_PATH";
+
+/// Current `build-summary.json` schema version.
+pub const BUILD_SUMMARY_SCHEMA_VERSION: u32 = 1;
+
+/// Directory under the artifacts root holding per-invocation summaries.
+const SUMMARIES_DIR: &str = "summaries";
+
+/// Symlink in [`SUMMARIES_DIR`] to the most recent invocation.
+const LATEST_LINK: &str = "latest";
+
+/// Per-invocation summaries kept under the artifacts root.
+const SUMMARY_RETENTION_COUNT: usize = 50;
+
+static RECORDER: Recorder = Recorder::new();
+
+/// Outcome of the invocation or of one stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryStatus {
+    Success,
+    Failed,
+    /// The invocation has not ended yet; only in stage notifications.
+    Running,
+}
+
+impl SummaryStatus {
+    fn of<T>(result: &Result<T>) -> Self {
+        if result.is_ok() {
+            Self::Success
+        } else {
+            Self::Failed
+        }
+    }
+}
+
+/// One timed pipeline stage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StageSummary {
+    pub name: String,
+    pub status: SummaryStatus,
+    pub duration_ms: u64,
+}
+
+/// One file the invocation produced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArtifactSummary {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+/// Lookups of one cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Boot test results for one target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TestSummary {
+    /// What was booted, e.g. `levitate:live-boot`.
+    pub target: String,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub records: Vec<TestRecord>,
+}
+
+/// Everything recorded so far in this process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Records {
+    pub stages: Vec<StageSummary>,
+    pub artifacts: Vec<ArtifactSummary>,
+    pub cache: BTreeMap<String, CacheCounts>,
+    pub tests: Vec<TestSummary>,
+    pub run_dirs: Vec<PathBuf>,
+}
+
+/// Collects [`Records`]; the CLI uses the process-wide one behind the free
+/// functions of this module.
+#[derive(Debug)]
+pub struct Recorder {
+    records: Mutex<Records>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self {
+            records: Mutex::new(Records {
+                stages: Vec::new(),
+                artifacts: Vec::new(),
+                cache: BTreeMap::new(),
+                tests: Vec::new(),
+                run_dirs: Vec::new(),
+            }),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Records) -> R) -> R {
+        let mut records = self
+            .records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(&mut records)
+    }
+
+    /// Run `f` as the stage `name`, recording its duration and outcome.
+    pub fn stage<T>(&self, name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let result = f();
+        let stage = StageSummary {
+            name: name.to_string(),
+            status: SummaryStatus::of(&result),
+            duration_ms: duration_ms(started.elapsed()),
+        };
+        self.with(|records| records.stages.push(stage));
+        result
+    }
+
+    /// Record `path` as a produced artifact, hashing it now.
+    pub fn record_artifact(&self, path: &Path) -> Result<()> {
+        let artifact = artifact_summary(path)?;
+        self.with(|records| {
+            records
+                .artifacts
+                .retain(|known| known.path != artifact.path);
+            records.artifacts.push(artifact);
+        });
+        Ok(())
+    }
+
+    /// Count a lookup in the cache `cache`.
+    pub fn record_cache(&self, cache: &str, hit: bool) {
+        self.with(|records| {
+            let counts = records.cache.entry(cache.to_string()).or_default();
+            if hit {
+                counts.hits += 1;
+            } else {
+                counts.misses += 1;
+            }
+        });
+    }
+
+    /// Record the boot test results for `target`.
+    pub fn record_tests(&self, target: &str, results: &GuestTestResults) {
+        let summary = TestSummary {
+            target: target.to_string(),
+            passed: results.count(TestStatus::Pass),
+            failed: results.count(TestStatus::Fail),
+            skipped: results.count(TestStatus::Skip),
+            records: results.records.clone(),
+        };
+        self.with(|records| records.tests.push(summary));
+    }
+
+    /// Record a release run directory the invocation created.
+    pub fn record_run_dir(&self, run_dir: &Path) {
+        self.with(|records| {
+            if !records.run_dirs.iter().any(|known| known == run_dir) {
+                records.run_dirs.push(run_dir.to_path_buf());
+            }
+        });
+    }
+
+    /// Copy of everything recorded so far.
+    pub fn snapshot(&self) -> Records {
+        self.with(|records| records.clone())
+    }
+
+    /// Take everything recorded so far, leaving the recorder empty.
+    pub fn take(&self) -> Records {
+        self.with(std::mem::take)
+    }
+}
+
+/// [`Recorder::stage`] on the process-wide recorder, notifying the
+/// installed [`crate::notify`] channels when the stage ends.
+pub fn stage<T>(name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let result = RECORDER.stage(name, f);
+    let stage = RECORDER.with(|records| records.stages.last().cloned());
+    if let Some(stage) = stage {
+        crate::notify::stage_finished(&stage);
+    }
+    result
+}
+
+/// [`Recorder::record_artifact`] on the process-wide recorder. A file that
+/// cannot be hashed is left out with a warning; the build already made it.
+pub fn record_artifact(path: &Path) {
+    if let Err(err) = RECORDER.record_artifact(path) {
+        eprintln!(
+            "  [WARN] not recording artifact in build summary: {:#}",
+            err
+        );
+    }
+}
+
+/// [`Recorder::record_cache`] on the process-wide recorder.
+pub fn record_cache(cache: &str, hit: bool) {
+    RECORDER.record_cache(cache, hit);
+}
+
+/// [`Recorder::record_tests`] on the process-wide recorder.
+pub fn record_tests(target: &str, results: &GuestTestResults) {
+    RECORDER.record_tests(target, results);
+}
+
+/// [`Recorder::record_run_dir`] on the process-wide recorder.
+pub fn record_run_dir(run_dir: &Path) {
+    RECORDER.record_run_dir(run_dir);
+}
+
+/// Copy the process-wide records.
+pub fn snapshot_records() -> Records {
+    RECORDER.snapshot()
+}
+
+/// Take the process-wide records.
+pub fn take_records() -> Records {
+    RECORDER.take()
+}
+
+/// The `build-summary.json` document.
+#[derive(Debug, Serialize)]
+pub struct BuildSummary {
+    pub schema_version: u32,
+    /// Command line arguments, without the program name.
+    pub command: Vec<String>,
+    pub started_at_utc: String,
+    pub finished_at_utc: String,
+    pub duration_ms: u64,
+    pub status: SummaryStatus,
+    /// Set when the invocation failed; same shape as `--json` error output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+    pub stages: Vec<StageSummary>,
+    pub artifacts: Vec<ArtifactSummary>,
+    /// Lookups per cache (`kernel`, `erofs`, `release`, `download`, ...).
+    pub cache: BTreeMap<String, CacheCounts>,
+    pub tests: Vec<TestSummary>,
+    pub run_dirs: Vec<PathBuf>,
+}
+
+impl BuildSummary {
+    /// Summary of an invocation of `command` that started at `started_at`
+    /// and ended with `result`.
+    pub fn new(
+        command: &[String],
+        started_at: OffsetDateTime,
+        result: &Result<()>,
+        records: Records,
+    ) -> Self {
+        let finished_at = OffsetDateTime::now_utc();
+        let elapsed = (finished_at - started_at).unsigned_abs();
+        Self {
+            schema_version: BUILD_SUMMARY_SCHEMA_VERSION,
+            command: command.to_vec(),
+            started_at_utc: utc_compact(started_at),
+            finished_at_utc: utc_compact(finished_at),
+            duration_ms: duration_ms(elapsed),
+            status: SummaryStatus::of(result),
+            error: result.as_ref().err().map(ErrorReport::new),
+            stages: records.stages,
+            artifacts: records.artifacts,
+            cache: records.cache,
+            tests: records.tests,
+            run_dirs: records.run_dirs,
+        }
+    }
+
+    /// Summary of an invocation of `command` that is still running, for
+    /// notifications sent before it ends.
+    pub fn in_progress(command: &[String], started_at: OffsetDateTime, records: Records) -> Self {
+        let mut summary = Self::new(command, started_at, &Ok(()), records);
+        summary.status = SummaryStatus::Running;
+        summary
+    }
+
+    /// Atomically write the summary to `path`.
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+        let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+        let payload = serde_json::to_vec_pretty(self).context("serializing build summary")?;
+        fs::write(&tmp, payload).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Write the summary to every location in the module docs. Summaries
+    /// under `artifacts_root` are skipped when it is `None` (no checkout
+    /// found). Returns the paths written; keeps going past failures and
+    /// reports the first.
+    pub fn write_all(&self, artifacts_root: Option<&Path>) -> Result<Vec<PathBuf>> {
+        let mut targets = Vec::new();
+        if let Some(path) = std::env::var_os(BUILD_SUMMARY_PATH_ENV).filter(|v| !v.is_empty()) {
+            targets.push(PathBuf::from(path));
+        }
+        for run_dir in &self.run_dirs {
+            // Runs pruned later in the same invocation are gone.
+            if run_dir.is_dir() {
+                targets.push(run_dir.join(BUILD_SUMMARY_FILENAME));
+            }
+        }
+        let invocation_dir = artifacts_root.map(|root| {
+            root.join(SUMMARIES_DIR)
+                .join(format!("{}-{}", self.started_at_utc, std::process::id()))
+        });
+        if let Some(dir) = &invocation_dir {
+            targets.push(dir.join(BUILD_SUMMARY_FILENAME));
+        }
+
+        let mut written = Vec::new();
+        let mut first_error = None;
+        for path in targets {
+            match self.write_to(&path) {
+                Ok(()) => written.push(path),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        if let Some(dir) = invocation_dir.filter(|dir| dir.is_dir()) {
+            if let Err(err) = update_latest(&dir).and_then(|()| prune_summaries(&dir)) {
+                first_error.get_or_insert(err);
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(written),
+        }
+    }
+}
+
+/// The most recent invocation summary under `artifacts_root`, if any.
+pub fn latest_summary_path(artifacts_root: &Path) -> PathBuf {
+    artifacts_root
+        .join(SUMMARIES_DIR)
+        .join(LATEST_LINK)
+        .join(BUILD_SUMMARY_FILENAME)
+}
+
+/// Point the `latest` link at `invocation_dir`.
+fn update_latest(invocation_dir: &Path) -> Result<()> {
+    let summaries = invocation_dir
+        .parent()
+        .ok_or_else(|| anyhow!("summary directory without parent"))?;
+    let name = invocation_dir
+        .file_name()
+        .ok_or_else(|| anyhow!("summary directory without name"))?;
+    let link = summaries.join(LATEST_LINK);
+    let tmp = summaries.join(format!(".{}-{}", LATEST_LINK, std::process::id()));
+    let _ = fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(name, &tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    fs::rename(&tmp, &link).with_context(|| format!("Failed to update {}", link.display()))
+}
+
+/// Remove all but the newest [`SUMMARY_RETENTION_COUNT`] invocation
+/// summaries next to `invocation_dir`, never `invocation_dir` itself.
+fn prune_summaries(invocation_dir: &Path) -> Result<()> {
+    let Some(summaries) = invocation_dir.parent() else {
+        return Ok(());
+    };
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(summaries)
+        .with_context(|| format!("Failed to read {}", summaries.display()))?
+    {
+        let entry = entry.with_context(|| format!("Failed to read {}", summaries.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name == LATEST_LINK || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        dirs.push(entry.path());
+    }
+    // Names start with the compact UTC timestamp, so they sort by age.
+    dirs.sort();
+    dirs.reverse();
+    for dir in dirs.into_iter().skip(SUMMARY_RETENTION_COUNT) {
+        if dir != invocation_dir {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn artifact_summary(path: &Path) -> Result<ArtifactSummary> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let size_bytes = io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash {}", path.display()))?;
+    Ok(ArtifactSummary {
+        path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        size_bytes,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
+fn duration_ms(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// `YYYYMMDDTHHMMSSZ`, the timestamp format of run manifests.
+fn utc_compact(at: OffsetDateTime) -> String {
+    let at = at.to_offset(time::UtcOffset::UTC);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        at.year(),
+        at.month() as u8,
+        at.day(),
+        at.hour(),
+        at.minute(),
+        at.second()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_summary_records_and_locations() {
+        let temp = TempDir::new().unwrap();
+        let recorder = Recorder::new();
+        let iso = temp.path().join("levitate.iso");
+        fs::write(&iso, "iso").unwrap();
+        let run_dir = temp.path().join("releases/live-boot/run-1");
+        fs::create_dir_all(&run_dir).unwrap();
+
+        recorder.stage("kernel", || Ok(())).unwrap();
+        assert!(recorder
+            .stage("iso", || -> Result<()> { bail!("xorriso failed") })
+            .is_err());
+        recorder.record_artifact(&iso).unwrap();
+        recorder.record_artifact(&iso).unwrap();
+        assert!(recorder
+            .record_artifact(&temp.path().join("missing"))
+            .is_err());
+        recorder.record_cache("kernel", true);
+        recorder.record_cache("erofs", false);
+        recorder.record_cache("erofs", true);
+        recorder.record_run_dir(&run_dir);
+        recorder.record_run_dir(&temp.path().join("pruned"));
+
+        let records = recorder.take();
+        assert_eq!(recorder.take(), Records::default());
+        assert_eq!(records.stages.len(), 2);
+        assert_eq!(records.stages[1].status, SummaryStatus::Failed);
+        assert_eq!(records.artifacts.len(), 1);
+        assert_eq!(records.artifacts[0].size_bytes, 3);
+        assert_eq!(records.cache["erofs"], CacheCounts { hits: 1, misses: 1 });
+
+        let started = OffsetDateTime::now_utc();
+        let command = vec!["release".to_string(), "build".to_string()];
+        let result = Err(anyhow!("boot failed"));
+        let summary = BuildSummary::new(&command, started, &result, records);
+        let artifacts = temp.path().join("artifacts");
+        let written = summary.write_all(Some(&artifacts)).unwrap();
+        assert_eq!(written.len(), 2);
+        assert!(run_dir.join(BUILD_SUMMARY_FILENAME).is_file());
+
+        let value: serde_json::Value =
+            serde_json::from_slice(&fs::read(latest_summary_path(&artifacts)).unwrap()).unwrap();
+        assert_eq!(value["schema_version"], BUILD_SUMMARY_SCHEMA_VERSION);
+        assert_eq!(value["status"], "failed");
+        assert_eq!(value["error"]["kind"], "internal");
+        assert_eq!(value["cache"]["kernel"]["hits"], 1);
+        assert_eq!(
+            value["artifacts"][0]["sha256"],
+            format!("{:x}", Sha256::digest(b"iso"))
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! Embedding API: drive builds from another program.
+//!
+//! [`Builder`] is the supported entry point for orchestrators that build
+//! images without scripting the CLI. It pins a checkout and a distro, runs
+//! [`Stage`]s, reports what happens through [`BuildEvent`]s and answers
+//! "which release runs exist and where is the ISO" from the run manifests.
+//!
+//! ```rust,ignore
+//! use distro_builder::builder::{BuildEvent, Builder, Stage};
+//!
+//! let builder = Builder::new("/srv/appliance-os", "acorn")?
+//!     .on_event(Arc::new(|event| {
+//!         if let BuildEvent::Progress(update) = event {
+//!             metrics.record(&update.label, update.percent);
+//!         }
+//!     }));
+//! builder.run(&Stage::release("live-boot"))?;
+//! let iso = builder.latest_release("live-boot")?.and_then(|run| run.iso());
+//! ```
+//!
+//! Stages run in this process, through the same orchestration the
+//! `distro-builder` binary uses. Tool progress arrives as typed
+//! [`ProgressUpdate`]s; a failed stage returns its own error, so
+//! [`kind_of`](crate::error::kind_of) classifies it as for the CLI. Variant
+//! hooks and recipes still call back into a `distro-builder` binary: the
+//! one set with [`Builder::cli`], else [`CLI_ENV`], else the one on `PATH`.
+
+use anyhow::{bail, Context, Result};
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::artifact_store::DEFAULT_OUTPUT_SUBDIR;
+use crate::error::{ErrorKind, ResultExt};
+use crate::pipeline::planner::{plan_product_realization, ProductRealizationPlan};
+use crate::progress::{format_update, ProgressUpdate};
+use crate::repo_layout::RepoLayout;
+use crate::run_history::{load_run_metadata, run_manifest_path, run_sort_key, RunMetadata};
+
+pub use crate::repo_layout::REPO_ROOT_ENV;
+
+/// CLI binary variant hooks and recipes call back into when
+/// [`Builder::cli`] is not set.
+pub const CLI_ENV: &str = "DISTRO_BUILDER_BIN";
+
+/// CLI set through [`Builder::cli`] for the stage running in this process.
+static STAGE_CLI: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// A unit of work [`Builder::run`] can execute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// Fetch every remote input without building.
+    WarmCache,
+    /// Build one release product ISO.
+    Release { product: String, smoke_test: bool },
+    /// Prepare a product's rootfs and overlay inputs into `output_dir`.
+    PrepareProduct {
+        product: String,
+        output_dir: PathBuf,
+    },
+    /// Stage 03 Install: pack the install tools in `tool_dir` into
+    /// `output_dir/installer.squashfs` and wire its launcher into
+    /// `live_overlay`.
+    Installer {
+        tool_dir: PathBuf,
+        output_dir: PathBuf,
+        live_overlay: PathBuf,
+    },
+}
+
+impl Stage {
+    /// Release build of `product` with the boot smoke test enabled.
+    pub fn release(product: impl Into<String>) -> Self {
+        Self::Release {
+            product: product.into(),
+            smoke_test: true,
+        }
+    }
+}
+
+/// Something observable during [`Builder::run`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildEvent {
+    StageStarted(Stage),
+    Progress(ProgressUpdate),
+    StageFinished { stage: Stage, success: bool },
+}
+
+/// Receives [`BuildEvent`]s. Called from helper threads too.
+pub type EventCallback = Arc<dyn Fn(&BuildEvent) + Send + Sync>;
+
+/// One release run of a product.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseRun {
+    pub dir: PathBuf,
+    pub metadata: RunMetadata,
+}
+
+impl ReleaseRun {
+    /// The run's ISO, if it recorded one.
+    pub fn iso(&self) -> Option<PathBuf> {
+        (!self.metadata.iso_path.is_empty()).then(|| PathBuf::from(&self.metadata.iso_path))
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        run_manifest_path(&self.dir)
+    }
+}
+
+/// Builds one distro of one checkout.
+#[derive(Clone)]
+pub struct Builder {
+    repo_root: PathBuf,
+    distro_id: String,
+    cli: Option<PathBuf>,
+    events: EventCallback,
+}
+
+impl std::fmt::Debug for Builder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Builder")
+            .field("repo_root", &self.repo_root)
+            .field("distro_id", &self.distro_id)
+            .field("cli", &self.cli)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Builder {
+    /// Builder for `distro` (an id or alias) in the checkout at `repo_root`.
+    pub fn new(repo_root: impl Into<PathBuf>, distro: &str) -> Result<Self> {
+        let repo_root = repo_root.into();
+        let layout = RepoLayout::at(&repo_root)?;
+        if !layout.variants_dir().is_dir() {
+            bail!(
+                "'{}' is not a distro-builder checkout (no {})",
+                repo_root.display(),
+                layout.variants_dir().display()
+            );
+        }
+        let distro_id = crate::repo_config::canonical_distro_id(&repo_root, distro, "builder")?;
+        Ok(Self {
+            repo_root,
+            distro_id,
+            cli: None,
+            events: Arc::new(print_event),
+        })
+    }
+
+    /// Let variant hooks and recipes call back into this `distro-builder`
+    /// binary.
+    pub fn cli(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cli = Some(path.into());
+        self
+    }
+
+    /// Send events to `callback` instead of printing them.
+    pub fn on_event(mut self, callback: EventCallback) -> Self {
+        self.events = callback;
+        self
+    }
+
+    pub fn repo_root(&self) -> &Path {
+        &self.repo_root
+    }
+
+    pub fn distro_id(&self) -> &str {
+        &self.distro_id
+    }
+
+    /// Root holding outputs, kernels, work dirs and the artifact store.
+    pub fn artifacts_root(&self) -> Result<PathBuf> {
+        crate::repo_config::artifacts_root(&self.repo_root)
+    }
+
+    /// Directory holding the release runs of `product`.
+    pub fn release_dir(&self, product: &str) -> Result<PathBuf> {
+        if product.is_empty() || product.contains(['/', '\\']) || product.starts_with('.') {
+            bail!("invalid product name '{}'", product);
+        }
+        Ok(self
+            .artifacts_root()?
+            .join(DEFAULT_OUTPUT_SUBDIR)
+            .join(&self.distro_id)
+            .join("releases")
+            .join(product))
+    }
+
+    /// Products that building `product` realizes, in order, with the parent
+    /// release images they would consume.
+    pub fn plan(&self, product: &str) -> Result<ProductRealizationPlan> {
+        let bundle = distro_contract::load_variant_contract_bundle_for_distro_from(
+            &self.repo_root,
+            &self.distro_id,
+        )
+        .with_context(|| format!("Failed to load variant contract for '{}'", self.distro_id))
+        .error_kind(ErrorKind::InvalidContract)?;
+        plan_product_realization(&self.repo_root, &self.distro_id, &bundle.contract, product)
+    }
+
+    /// Run `stage` to completion in this process.
+    ///
+    /// Stages of one process run one at a time: the progress reporter and
+    /// the callback CLI are process-wide while a stage runs.
+    pub fn run(&self, stage: &Stage) -> Result<()> {
+        let _serial = STAGE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let _cli = StageCli::set(self.cli.clone());
+        let events = Arc::clone(&self.events);
+        let _progress = crate::progress::install_reporter(Arc::new(move |update| {
+            events(&BuildEvent::Progress(update.clone()))
+        }));
+
+        (self.events)(&BuildEvent::StageStarted(stage.clone()));
+        let result = crate::cli::run_stage(&self.repo_root, &self.distro_id, stage);
+        (self.events)(&BuildEvent::StageFinished {
+            stage: stage.clone(),
+            success: result.is_ok(),
+        });
+        result.with_context(|| format!("stage {:?} for '{}' failed", stage, self.distro_id))
+    }
+
+    /// Release runs of `product`, newest first.
+    pub fn releases(&self, product: &str) -> Result<Vec<ReleaseRun>> {
+        let release_dir = self.release_dir(product)?;
+        let mut runs = load_run_metadata(&release_dir)?;
+        runs.sort_by_key(|run| Reverse(run_sort_key(run)));
+        Ok(runs
+            .into_iter()
+            .map(|metadata| ReleaseRun {
+                dir: release_dir.join(&metadata.run_id),
+                metadata,
+            })
+            .collect())
+    }
+
+    /// Newest successful release run of `product`.
+    pub fn latest_release(&self, product: &str) -> Result<Option<ReleaseRun>> {
+        Ok(self
+            .releases(product)?
+            .into_iter()
+            .find(|run| run.metadata.is_success()))
+    }
+}
+
+/// Serializes [`Builder::run`] within the process.
+static STAGE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Sets [`STAGE_CLI`] for one stage and clears it on drop.
+struct StageCli;
+
+impl StageCli {
+    fn set(cli: Option<PathBuf>) -> Self {
+        *STAGE_CLI.lock().unwrap_or_else(|err| err.into_inner()) = cli;
+        Self
+    }
+}
+
+impl Drop for StageCli {
+    fn drop(&mut self) {
+        *STAGE_CLI.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    }
+}
+
+/// The `distro-builder` binary variant hooks and recipes call back into:
+/// the one set through [`Builder::cli`] for the running stage, else
+/// [`CLI_ENV`], else this executable when it is the CLI, else the one on
+/// `PATH`.
+pub(crate) fn cli_binary() -> Option<PathBuf> {
+    if let Some(cli) = STAGE_CLI
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+    {
+        return Some(cli);
+    }
+    if let Some(cli) = std::env::var_os(CLI_ENV).filter(|value| !value.is_empty()) {
+        return Some(PathBuf::from(cli));
+    }
+    if let Some(exe) = std::env::current_exe()
+        .ok()
+        .filter(|exe| exe.file_name().is_some_and(|name| name == "distro-builder"))
+    {
+        return Some(exe);
+    }
+    which::which("distro-builder").ok()
+}
+
+/// Default event sink: progress in the CLI's own format.
+fn print_event(event: &BuildEvent) {
+    match event {
+        BuildEvent::Progress(update) => eprintln!("  [progress] {}", format_update(update)),
+        BuildEvent::StageStarted(_) | BuildEvent::StageFinished { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_history::{write_run_metadata, RunStatus};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_stays_in_process() {
+        let repo = TempDir::new().unwrap();
+        std::fs::create_dir_all(repo.path().join("distro-variants/birch")).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        // No CLI is started, so a missing one does not matter.
+        let builder = Builder::new(repo.path(), "birch")
+            .unwrap()
+            .cli(repo.path().join("missing-distro-builder"))
+            .on_event(Arc::new(move |event| {
+                sink.lock().unwrap().push(event.clone())
+            }));
+        let stage = Stage::PrepareProduct {
+            product: "desktop".into(),
+            output_dir: repo.path().join("prep"),
+        };
+
+        let err = builder.run(&stage).unwrap_err();
+        assert!(format!("{:#}", err).contains("unsupported product 'desktop'"));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                BuildEvent::StageStarted(stage.clone()),
+                BuildEvent::StageFinished {
+                    stage,
+                    success: false,
+                },
+            ]
+        );
+        assert!(STAGE_CLI.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_release_queries() {
+        let repo = TempDir::new().unwrap();
+        std::fs::create_dir_all(repo.path().join("distro-variants/birch")).unwrap();
+        let builder = Builder::new(repo.path(), "birch").unwrap();
+        assert!(builder.latest_release("live-boot").unwrap().is_none());
+
+        let release_dir = builder.release_dir("live-boot").unwrap();
+        for (run_id, status, at) in [
+            ("r1", RunStatus::Success, "20260101T000000Z"),
+            ("r2", RunStatus::Failed, "20260102T000000Z"),
+        ] {
+            let dir = release_dir.join(run_id);
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut metadata = RunMetadata::new(run_id, status, at.to_string());
+            metadata.iso_path = dir.join("birch.iso").display().to_string();
+            write_run_metadata(&run_manifest_path(&dir), &metadata).unwrap();
+        }
+
+        let runs = builder.releases("live-boot").unwrap();
+        assert_eq!(runs[0].metadata.run_id, "r2");
+        let latest = builder.latest_release("live-boot").unwrap().unwrap();
+        assert_eq!(latest.metadata.run_id, "r1");
+        assert_eq!(latest.iso(), Some(release_dir.join("r1/birch.iso")));
+        assert!(builder.release_dir("../x").is_err());
+    }
+}
@@ -5,8 +5,16 @@
 
 use anyhow::{Context, Result};
 use sha2::{Digest, Sha256};
-use std::fs;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::artifact::xattr::read_xattrs;
+use crate::parallel;
 
 /// Compute SHA256 hash of a file's contents.
 pub fn hash_file(path: &Path) -> Result<String> {
@@ -40,6 +48,103 @@ pub fn hash_files(paths: &[&Path]) -> Option<String> {
     Some(format!("{:x}", hasher.finalize()))
 }
 
+/// How [`hash_tree`] fingerprints regular files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeHashMode {
+    /// Size and mtime. Cheap, but a rewrite with identical bytes still
+    /// counts as a change.
+    Metadata,
+    /// Size and SHA256 of the contents. Survives re-extraction and copies.
+    Content,
+}
+
+/// Hash a directory tree: every entry's relative path, type and permission
+/// bits and extended attributes (sorted by name), plus symlink targets and
+/// file fingerprints per `mode`. Hardlinks are hashed as links to the first
+/// path of their (device, inode) group, so relinking or splitting files
+/// changes the digest even when the contents stay the same.
+///
+/// Ownership is not hashed; images are packed with `--all-root`. File
+/// contents are hashed on [`parallel::io_threads`] threads; the digest is the
+/// same for any thread count.
+pub fn hash_tree(root: &Path, mode: TreeHashMode) -> Result<String> {
+    hash_tree_with_threads(root, mode, parallel::io_threads()?)
+}
+
+/// [`hash_tree`] on an explicit number of threads.
+pub fn hash_tree_with_threads(root: &Path, mode: TreeHashMode, threads: usize) -> Result<String> {
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(root)
+        .follow_links(false)
+        .min_depth(1)
+        .sort_by_file_name()
+    {
+        let entry = entry.with_context(|| format!("Failed to walk {}", root.display()))?;
+        let meta = entry
+            .metadata()
+            .with_context(|| format!("Failed to stat {}", entry.path().display()))?;
+        entries.push((entry, meta));
+    }
+
+    let fingerprints = parallel::try_map(&entries, threads, |(entry, meta)| {
+        let path = entry.path();
+        let file_type = entry.file_type();
+        let mut fingerprint = Vec::new();
+        if file_type.is_symlink() {
+            let target = fs::read_link(path)
+                .with_context(|| format!("Failed to read link {}", path.display()))?;
+            fingerprint.extend_from_slice(target.as_os_str().as_bytes());
+        } else if file_type.is_file() {
+            fingerprint.extend_from_slice(&meta.len().to_le_bytes());
+            match mode {
+                TreeHashMode::Metadata => {
+                    fingerprint.extend_from_slice(&meta.mtime().to_le_bytes());
+                    fingerprint.extend_from_slice(&meta.mtime_nsec().to_le_bytes());
+                }
+                TreeHashMode::Content => {
+                    let mut file = File::open(path).with_context(|| {
+                        format!("Failed to read file for hashing: {}", path.display())
+                    })?;
+                    let mut content = Sha256::new();
+                    io::copy(&mut file, &mut content).with_context(|| {
+                        format!("Failed to read file for hashing: {}", path.display())
+                    })?;
+                    fingerprint.extend_from_slice(&content.finalize());
+                }
+            }
+        } else if !file_type.is_dir() {
+            fingerprint.extend_from_slice(&meta.rdev().to_le_bytes());
+        }
+        for (name, value) in read_xattrs(path)? {
+            fingerprint.extend_from_slice(b"\0xattr\0");
+            fingerprint.extend_from_slice(name.as_bytes());
+            fingerprint.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            fingerprint.extend_from_slice(&value);
+        }
+        Ok(fingerprint)
+    })?;
+
+    let mut hasher = Sha256::new();
+    let mut link_groups: HashMap<(u64, u64), &Path> = HashMap::new();
+    for ((entry, meta), fingerprint) in entries.iter().zip(fingerprints) {
+        let path = entry.path();
+        let rel = path.strip_prefix(root).unwrap_or(path);
+        hasher.update(rel.as_os_str().as_bytes());
+        hasher.update(format!("\0{:o}\0", meta.mode()).as_bytes());
+        hasher.update(&fingerprint);
+        if !meta.is_dir() && meta.nlink() > 1 {
+            // Entries are sorted, so the group's first path is stable.
+            let first = *link_groups.entry((meta.dev(), meta.ino())).or_insert(rel);
+            if first != rel {
+                hasher.update(b"\0hardlink\0");
+                hasher.update(first.as_os_str().as_bytes());
+            }
+        }
+        hasher.update(b"\n");
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 /// Read cached hash from a .hash file.
 /// Returns None if file doesn't exist.
 /// Logs a warning if file exists but can't be read.
@@ -118,3 +223,57 @@ pub fn is_newer(source: &Path, target: &Path) -> bool {
 
     src_time > tgt_time
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tree_hash(root: &Path) -> String {
+        hash_tree_with_threads(root, TreeHashMode::Content, 2).unwrap()
+    }
+
+    #[test]
+    fn test_hash_tree_sees_hardlink_grouping() {
+        let temp = TempDir::new().unwrap();
+        let linked = temp.path().join("linked");
+        let copied = temp.path().join("copied");
+        for root in [&linked, &copied] {
+            fs::create_dir_all(root).unwrap();
+            fs::write(root.join("a"), "same").unwrap();
+        }
+        fs::hard_link(linked.join("a"), linked.join("b")).unwrap();
+        fs::write(copied.join("b"), "same").unwrap();
+
+        assert_ne!(tree_hash(&linked), tree_hash(&copied));
+    }
+
+    #[test]
+    fn test_hash_tree_sees_xattrs() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        fs::create_dir_all(&root).unwrap();
+        let file = root.join("file");
+        fs::write(&file, "data").unwrap();
+        let before = tree_hash(&root);
+
+        let path = std::ffi::CString::new(file.as_os_str().as_bytes()).unwrap();
+        let name = std::ffi::CString::new("user.test").unwrap();
+        let value = b"label";
+        // SAFETY: NUL-terminated strings and a live buffer.
+        let rc = unsafe {
+            libc::lsetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if rc != 0 {
+            eprintln!("skipping: filesystem does not support user xattrs");
+            return;
+        }
+        assert_ne!(before, tree_hash(&root));
+    }
+}
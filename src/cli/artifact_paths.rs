@@ -0,0 +1,32 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::artifact_store::DEFAULT_OUTPUT_SUBDIR;
+use crate::repo_config::artifacts_root;
+
+pub fn output_dir_for(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
+    Ok(artifacts_root(repo_root)?
+        .join(DEFAULT_OUTPUT_SUBDIR)
+        .join(distro_id))
+}
+
+pub fn distro_output_root_for(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
+    output_dir_for(repo_root, distro_id)
+}
+
+pub fn release_product_dir_for(
+    repo_root: &Path,
+    distro_id: &str,
+    product_dir_name: &str,
+) -> Result<PathBuf> {
+    Ok(output_dir_for(repo_root, distro_id)?
+        .join("releases")
+        .join(product_dir_name))
+}
+
+pub fn kernel_output_dir_for(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
+    Ok(artifacts_root(repo_root)?
+        .join("kernel")
+        .join(distro_id)
+        .join("current"))
+}
@@ -0,0 +1,203 @@
+//! The `distro-builder` command line.
+//!
+//! The binary is a thin wrapper around [`main`]; release orchestration
+//! (kernel, products, variant hooks, smoke test) lives here so
+//! [`Builder`](crate::builder::Builder) can run the same stages in-process
+//! through [`run_stage`].
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::build_summary::BuildSummary;
+use crate::builder::Stage;
+use crate::error::{kind_of, Error, ErrorKind, ErrorReport};
+use crate::notify::Notifier;
+use crate::resource_limits::ResourceLimits;
+use crate::tool_container::ToolContainer;
+
+mod artifact_paths;
+mod run_manifest;
+mod workflows;
+
+const PRODUCT_BASE_ROOTFS: &str = "base-rootfs";
+const PRODUCT_LIVE_BOOT: &str = "live-boot";
+const PRODUCT_LIVE_TOOLS: &str = "live-tools";
+const PRODUCT_INSTALLED_BOOT: &str = "installed-boot";
+const DEFAULT_DISTRO_ID: &str = "levitate";
+const RELEASE_RUN_RETENTION_COUNT: usize = 5;
+
+/// Flag printing a failure as an [`ErrorReport`] JSON object on stdout.
+const JSON_FLAG: &str = "--json";
+
+/// Whether [`JSON_FLAG`] was given, for commands whose output it changes.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BuildProduct {
+    canonical: &'static str,
+    release_dir_name: &'static str,
+    release_hook_script_name: Option<&'static str>,
+    iso_suffix: &'static str,
+    live_overlay_dir_name: &'static str,
+    rootfs_source_pointer_filename: &'static str,
+    issue_banner_label: &'static str,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BuildOutputLayout {
+    root_dir: std::path::PathBuf,
+    output_dir: std::path::PathBuf,
+    run_id: Option<String>,
+}
+
+fn usage() -> &'static str {
+    "Usage:\n  distro-builder [--json] <command>...\n    --json prints failures as a JSON object with a stable error kind and exit code\n  distro-builder release build iso [<distro_id|product>] [<distro_id|product>] [--no-test] [--feature <name>]...\n    product defaults to base-rootfs, distro defaults to levitate\n    release products: base-rootfs | live-boot | live-tools\n    built ISOs are boot smoke-tested unless --no-test or the variant's smoke-test.toml skips them\n    --feature replaces the variant's feature flags (build-context.toml `features`)\n  distro-builder release build-all iso [base-rootfs|live-boot|live-tools] [--no-test] [--feature <name>]...\n  distro-builder product prepare <base-rootfs|live-boot|live-tools|installed-boot> <distro_id> <output_dir>\n  distro-builder transform build rootfs-erofs <source_dir> <output>\n  distro-builder transform build overlayfs-erofs <source_dir> <output>\n  distro-builder transform build installer-squashfs <payload_dir> <output>\n  distro-builder transform build product-erofs <prepared_product_dir>\n  distro-builder transform build usb-image <iso> <output> [--persistence-mb <n>] [--persistence-label <label>] [--efi-image <iso_path>]\n  distro-builder artifact preseed-rootfs-source <distro_id> [--refresh]\n  distro-builder artifact materialize-rootfs-source <distro_id>\n  distro-builder artifact build-installer <distro_id> <tool_dir> <output_dir> <live_overlay>\n    packs recstrap/recfstab/recchroot from <tool_dir> into <output_dir>/installer.squashfs and wires its launcher into <live_overlay>; product prepare live-tools runs this\n  distro-builder artifact check-initramfs <distro_id> <initramfs>\n  distro-builder artifact netboot-init <distro_id> <initramfs_root>\n  distro-builder artifact writable-layer-init <distro_id> <initramfs_root>\n    installs the live writable layer size cap and spill file logic (writable-layer.toml) into the initramfs\n  distro-builder artifact build-ukis <distro_id> <kernel_image> <initramfs> <esp_dir> [--cmdline <live_cmdline>] [--os-release <path>]\n    checks the initramfs contract, then builds the live, emergency and debug UKIs into <esp_dir>/EFI/Linux and checks them; without --os-release they embed the contract identity's os-release\n  distro-builder new-variant <distro_id> --init systemd|openrc [--from <distro_id>] [--name <os_name>]\n  distro-builder cache warm <distro_id>\n  distro-builder cache fetch <distro_id> <file_name> <url>... [--sha256 <hex>]\n    urls are tried in order; .torrent and magnet: sources need aria2c or transmission-cli\n  distro-builder cache sync <store_root> [--kind <kind>]... [--keep-last <n>] [--prefer newer|remote] [--dry-run]\n    copies entries, blobs and tags another artifact store has and this one lacks\n  distro-builder doctor [<distro_id>...] [--deep]\n    checks tools, layout, variant contracts, the artifact store and QEMU, then lists fixes by priority; run this first\n    --deep rehashes every store blob\n  distro-builder policy audit-legacy-bindings\n  distro-builder clean [--stage <distro_id> <product>] [--kernel] [--downloads] [--work] [--store-unreferenced]\n    --work keeps the downloads and rootfs-source-provider work namespaces\n  distro-builder remote <[user@]host> <command>...\n    syncs the checkout and artifact store to the host, runs the command there and pulls outputs back"
+}
+
+/// A malformed command line, reported with the usage text.
+fn usage_error() -> anyhow::Error {
+    Error::new(ErrorKind::Usage, anyhow!(usage())).into()
+}
+
+/// Entry point of the `distro-builder` binary.
+pub fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|arg| arg == JSON_FLAG);
+    JSON_OUTPUT.store(json, Ordering::SeqCst);
+    let args: Vec<String> = args.into_iter().filter(|arg| arg != JSON_FLAG).collect();
+
+    let started_at = time::OffsetDateTime::now_utc();
+    let result = run(&args, started_at);
+    write_build_summary(&args, started_at, &result);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            let kind = kind_of(&err);
+            if json {
+                match serde_json::to_string(&ErrorReport::new(&err)) {
+                    Ok(report) => println!("{report}"),
+                    Err(_) => eprintln!("Error: {:?}", err),
+                }
+            } else {
+                eprintln!("Error: {:?}", err);
+            }
+            ExitCode::from(kind.exit_code())
+        }
+    }
+}
+
+/// Whether this invocation prints failures as JSON.
+pub(crate) fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::SeqCst)
+}
+
+/// Write `build-summary.json` for this invocation. A summary that cannot be
+/// written never changes the exit status.
+fn write_build_summary(args: &[String], started_at: time::OffsetDateTime, result: &Result<()>) {
+    let records = crate::build_summary::take_records();
+    let summary = BuildSummary::new(args, started_at, result, records);
+    let artifacts_root = match workflows::locate_repo_root()
+        .map(|repo_root| crate::repo_config::artifacts_root(&repo_root))
+    {
+        Ok(Ok(artifacts_root)) => Some(artifacts_root),
+        Ok(Err(err)) => {
+            eprintln!("warning: not writing the build summary under the artifacts root: {err:#}");
+            None
+        }
+        Err(_) => None,
+    };
+    if let Err(err) = summary.write_all(artifacts_root.as_deref()) {
+        eprintln!("warning: failed to write build summary: {err:#}");
+    }
+    crate::notify::build_finished(&summary);
+}
+
+fn run(args: &[String], started_at: time::OffsetDateTime) -> Result<()> {
+    arm_parent_death_signal()?;
+    crate::supervise::install_signal_handlers()?;
+    let repo_root = workflows::locate_repo_root().ok();
+    crate::resource_limits::install(ResourceLimits::load(repo_root.as_deref())?)?;
+    crate::tool_container::install(ToolContainer::load(repo_root.as_deref())?);
+    crate::notify::install(Notifier::load(repo_root.as_deref())?, args, started_at);
+    if let Some(repo_root) = repo_root.as_deref() {
+        // A broken `distro-builder.toml` stops the run here, before any
+        // command has started work.
+        crate::repo_config::artifacts_root(repo_root).context("resolving the artifacts root")?;
+    }
+
+    if workflows::is_release_build_invocation(args) {
+        return workflows::run_release_build_command(args);
+    }
+
+    // The audit reports violations itself instead of tripping the guard.
+    if matches!(args, [policy, audit] if policy == "policy" && audit == "audit-legacy-bindings") {
+        return workflows::policy_audit_cmd();
+    }
+    // The doctor reports policy violations among its other findings.
+    if let [doctor, rest @ ..] = args {
+        if doctor == "doctor" {
+            return workflows::doctor_cmd(rest);
+        }
+    }
+
+    workflows::enforce_legacy_binding_policy_guard(&workflows::locate_repo_root()?)?;
+    workflows::dispatch_non_release_command(args)
+}
+
+/// Run `stage` for `distro_id` of the checkout at `repo_root` in this
+/// process, as the matching command would.
+///
+/// Signal handlers and the parent-death signal stay the embedding program's
+/// business; the checkout's resource limits and tool container are installed
+/// on first use.
+pub(crate) fn run_stage(repo_root: &Path, distro_id: &str, stage: &Stage) -> Result<()> {
+    crate::resource_limits::install(ResourceLimits::load(Some(repo_root))?)?;
+    crate::tool_container::install(ToolContainer::load(Some(repo_root))?);
+    crate::repo_config::artifacts_root(repo_root).context("resolving the artifacts root")?;
+    workflows::enforce_legacy_binding_policy_guard(repo_root)?;
+
+    match stage {
+        Stage::WarmCache => workflows::cache_warm_cmd(repo_root, distro_id),
+        Stage::Release {
+            product,
+            smoke_test,
+        } => {
+            let product = workflows::parse_release_product(Some(product))?;
+            let options = workflows::ReleaseBuildOptions {
+                smoke_test: *smoke_test,
+            };
+            workflows::ensure_release_prerequisites(repo_root, distro_id, product, options)?;
+            workflows::build_one(repo_root, distro_id, product, options)
+        }
+        Stage::PrepareProduct {
+            product,
+            output_dir,
+        } => workflows::prepare_product_cmd(repo_root, product, distro_id, output_dir),
+        Stage::Installer {
+            tool_dir,
+            output_dir,
+            live_overlay,
+        } => {
+            workflows::build_installer_cmd(repo_root, distro_id, tool_dir, output_dir, live_overlay)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn arm_parent_death_signal() -> Result<()> {
+    // If launcher/wrapper dies (cancel/abort), terminate this process too so
+    // long-running sub-steps do not continue as orphans.
+    let rc = unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to arm parent-death signal for distro-builder");
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn arm_parent_death_signal() -> Result<()> {
+    Ok(())
+}
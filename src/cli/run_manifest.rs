@@ -0,0 +1,8 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::run_history::RunMetadata;
+
+pub fn write_run_metadata(path: &Path, metadata: &RunMetadata) -> Result<()> {
+    crate::run_history::write_run_metadata(path, metadata)
+}
@@ -0,0 +1,769 @@
+use anyhow::{bail, Context, Result};
+use distro_contract::{
+    load_variant_contract_bundle_for_distro_from, ConformanceContract, LoadedVariantContract,
+    ProductDecl,
+};
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::artifact::esp::UKI_DIR;
+use crate::artifact_store::ArtifactStore;
+use crate::build::writable_layer::{WritableLayer, WRITABLE_LAYER_FILENAME};
+use crate::build_summary;
+use crate::error::{ErrorKind, ResultExt};
+use crate::recipe::alpine_rootfs_source::preseed_alpine_rootfs_source_assets;
+use crate::recipe::rootfs_source::preseed_rootfs_source_dvd;
+use crate::repo_config::artifacts_root;
+use crate::repo_layout::{layout_or_default, RepoLayout};
+use crate::secureboot::SecureBootKeys;
+use crate::{
+    build_erofs_default_with_store, build_installer_squashfs, build_overlayfs_default,
+    build_usb_image, check_initramfs, verify_live_payloads, Branding, ConflictPolicy,
+    InitramfsContract, NetbootFeatures, SplashConfig, UkiBuilder, UkiFlavor, UkiSet,
+    UsbImageOptions,
+};
+use crate::{
+    load_base_rootfs_product_spec, load_installed_boot_product_spec, load_live_boot_product_spec,
+    load_live_tools_product_spec, materialize_live_boot_source_rootfs, plan_product_realization,
+    prepare_base_rootfs_product, prepare_installed_boot_product, prepare_live_boot_product,
+    prepare_live_tools_product, BaseProductLayout, DerivedProductLayout, OverlayLayout,
+    ParentRootfsInput, ProductRealizationStep,
+};
+
+use crate::cli::workflows::prepared_products::{
+    canonical_prepared_output_names, canonical_rootfs_erofs_filename,
+    read_prepared_product_manifest, resolve_prepared_product_path, write_prepared_product_outputs,
+    PreparedProductInputs,
+};
+
+pub(crate) fn build_rootfs_erofs(source_dir: &Path, output: &Path) -> Result<()> {
+    // Rootfs images are reused across runs through the artifact store; the
+    // build still works without one.
+    let store = match crate::cli::workflows::locate_repo_root()
+        .and_then(|root| ArtifactStore::open(&root))
+    {
+        Ok(store) => Some(store),
+        Err(err) => {
+            eprintln!(
+                "  [WARN] Artifact store unavailable, rootfs EROFS will not be reused: {:#}",
+                err
+            );
+            None
+        }
+    };
+    build_erofs_default_with_store(store.as_ref(), source_dir, output).with_context(|| {
+        format!(
+            "building rootfs EROFS from '{}' to '{}'",
+            source_dir.display(),
+            output.display()
+        )
+    })?;
+    build_summary::record_artifact(output);
+    Ok(())
+}
+
+pub(crate) fn build_overlayfs_erofs(source_dir: &Path, output: &Path) -> Result<()> {
+    build_overlayfs_default(source_dir, output).with_context(|| {
+        format!(
+            "building overlayfs EROFS from '{}' to '{}'",
+            source_dir.display(),
+            output.display()
+        )
+    })?;
+    build_summary::record_artifact(output);
+    Ok(())
+}
+
+pub(crate) fn build_usb_image_cmd(iso: &Path, output: &Path, args: &[String]) -> Result<()> {
+    let mut options = UsbImageOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .with_context(|| format!("{} requires a value", flag))
+        };
+        match arg.as_str() {
+            "--persistence-mb" => {
+                let raw = value("--persistence-mb")?;
+                options.persistence_mb = raw.parse().with_context(|| {
+                    format!(
+                        "invalid --persistence-mb '{}': expected MiB, 0 to omit",
+                        raw
+                    )
+                })?;
+            }
+            "--persistence-label" => {
+                options.persistence_label = value("--persistence-label")?.clone();
+            }
+            "--efi-image" => options.efi_image_path = Some(value("--efi-image")?.clone()),
+            other => bail!(
+                "unknown usb-image option '{}'\n\
+Remediation: use --persistence-mb <n>, --persistence-label <label> or --efi-image <iso path>",
+                other
+            ),
+        }
+    }
+    build_usb_image(iso, output, &options).with_context(|| {
+        format!(
+            "building USB image from '{}' to '{}'",
+            iso.display(),
+            output.display()
+        )
+    })?;
+    build_summary::record_artifact(output);
+    Ok(())
+}
+
+pub(crate) fn build_installer_payload_squashfs(payload_dir: &Path, output: &Path) -> Result<()> {
+    build_installer_squashfs(payload_dir, output).with_context(|| {
+        format!(
+            "building installer squashfs from '{}' to '{}'",
+            payload_dir.display(),
+            output.display()
+        )
+    })?;
+    build_summary::record_artifact(output);
+    Ok(())
+}
+
+/// Run the installer stage on its own: `product prepare live-tools` runs it
+/// with the tools it installs into the live overlay.
+pub(crate) fn build_installer_cmd(
+    repo_root: &Path,
+    distro_id: &str,
+    tool_dir: &Path,
+    output_dir: &Path,
+    live_overlay: &Path,
+) -> Result<()> {
+    let bundle = load_variant_contract_bundle_for_distro_from(repo_root, distro_id)
+        .with_context(|| format!("loading canonical variant contract for '{}'", distro_id))
+        .error_kind(ErrorKind::InvalidContract)?;
+    let product = crate::cli::workflows::parse_product(Some(crate::cli::PRODUCT_LIVE_TOOLS))?;
+    let layout = canonical_derived_product_layout(&bundle.contract, product)?;
+    let spec = load_live_tools_product_spec(
+        &bundle.repo_root,
+        &bundle.variant_dir,
+        &bundle.contract,
+        distro_id,
+        layout,
+    )
+    .with_context(|| format!("loading {} config for '{}'", product.canonical, distro_id))?;
+    let image = spec.build_installer(tool_dir, output_dir, live_overlay)?;
+    build_summary::record_artifact(&image);
+    Ok(())
+}
+
+pub(crate) fn check_initramfs_cmd(distro_id: &str, initramfs: &Path) -> Result<()> {
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    check_initramfs_for_distro(&repo_root, distro_id, initramfs)
+}
+
+pub(crate) fn netboot_init_cmd(distro_id: &str, initramfs_root: &Path) -> Result<()> {
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    let variant_dir = RepoLayout::at(&repo_root)?.variant_dir(distro_id);
+    let netboot = NetbootFeatures::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading netboot config for '{}'", distro_id))?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "variant '{}' has no {}\n\
+                 Remediation: add distro-variants/{}/{} selecting dhcp/http/nfs/iscsi.",
+                distro_id,
+                crate::artifact::netboot::NETBOOT_CONFIG_FILENAME,
+                distro_id,
+                crate::artifact::netboot::NETBOOT_CONFIG_FILENAME
+            )
+        })?;
+    netboot.write_init(initramfs_root).with_context(|| {
+        format!(
+            "writing netboot init into '{}' for '{}'",
+            initramfs_root.display(),
+            distro_id
+        )
+    })?;
+    println!(
+        "  Wrote netboot /init ({}) into {}",
+        netboot.modules().join(" "),
+        initramfs_root.display()
+    );
+    if let Some(layer) = WritableLayer::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading writable layer config for '{}'", distro_id))?
+    {
+        install_writable_layer(distro_id, &layer, initramfs_root)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn writable_layer_init_cmd(distro_id: &str, initramfs_root: &Path) -> Result<()> {
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    let variant_dir = layout_or_default(&repo_root).variant_dir(distro_id);
+    let layer = WritableLayer::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading writable layer config for '{}'", distro_id))?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "variant '{}' has no {}\n\
+                 Remediation: add distro-variants/{}/{} with the layer size and optional [spill] file.",
+                distro_id,
+                WRITABLE_LAYER_FILENAME,
+                distro_id,
+                WRITABLE_LAYER_FILENAME
+            )
+        })?;
+    install_writable_layer(distro_id, &layer, initramfs_root)
+}
+
+fn install_writable_layer(
+    distro_id: &str,
+    layer: &WritableLayer,
+    initramfs_root: &Path,
+) -> Result<()> {
+    layer
+        .install_into_initramfs(initramfs_root)
+        .with_context(|| {
+            format!(
+                "writing writable layer library into '{}' for '{}'",
+                initramfs_root.display(),
+                distro_id
+            )
+        })?;
+    println!(
+        "  Wrote writable layer library (tmpfs {}{}) into {}",
+        layer.size,
+        layer
+            .spill
+            .as_ref()
+            .map(|spill| format!(", spill to {} on LABEL={}", spill.file, spill.label))
+            .unwrap_or_default(),
+        initramfs_root.display()
+    );
+    Ok(())
+}
+
+pub(crate) fn build_ukis_cmd(
+    distro_id: &str,
+    kernel: &Path,
+    initramfs: &Path,
+    esp_dir: &Path,
+    args: &[String],
+) -> Result<()> {
+    let mut cmdline = None;
+    let mut os_release = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .with_context(|| format!("{} requires a value", flag))
+        };
+        match arg.as_str() {
+            "--cmdline" => cmdline = Some(value("--cmdline")?.clone()),
+            "--os-release" => os_release = Some(PathBuf::from(value("--os-release")?)),
+            other => bail!(
+                "unknown build-ukis option '{}'\n\
+Remediation: use --cmdline <live_cmdline> or --os-release <path>",
+                other
+            ),
+        }
+    }
+
+    let cwd = std::env::current_dir().context("resolving current directory")?;
+    let bundle = load_variant_contract_bundle_for_distro_from(&cwd, distro_id)
+        .with_context(|| format!("loading canonical variant contract for '{}'", distro_id))
+        .error_kind(ErrorKind::InvalidContract)?;
+    let set = UkiSet::from_output_names(&bundle.contract.transforms.live_uki.output_names)
+        .with_context(|| format!("resolving live UKI output names for '{}'", distro_id))?;
+    // The release hook passes its final live command line; the contract's
+    // is the default.
+    let (contract_cmdline, splash) = crate::cli::workflows::live_uki_cmdline(&bundle, distro_id)?;
+    let cmdline = cmdline.unwrap_or(contract_cmdline);
+    // UKIs embed the initramfs, so this is the last point before the hook
+    // packs the ISO at which a broken one can still stop the release.
+    check_initramfs_for_distro(&bundle.repo_root, distro_id, initramfs)?;
+
+    // Without an explicit os-release the UKIs embed the contract's branding,
+    // whose PRETTY_NAME systemd-boot shows as the menu title.
+    let branded_os_release = esp_dir.with_extension("os-release");
+    let os_release = match os_release {
+        Some(os_release) => os_release,
+        None => {
+            fs::write(
+                &branded_os_release,
+                contract_branding(&bundle.contract).os_release(),
+            )
+            .with_context(|| format!("writing {}", branded_os_release.display()))?;
+            branded_os_release.clone()
+        }
+    };
+    let mut builder = UkiBuilder::new(kernel, initramfs).os_release(&os_release);
+    if let Some(bitmap) = &splash.bitmap {
+        builder = builder.splash(bitmap);
+    }
+    if let Some(keys) = SecureBootKeys::from_env()? {
+        builder = builder.sign_with(keys);
+    }
+    let built = builder
+        .build_set(&set, &cmdline, esp_dir)
+        .with_context(|| format!("building UKIs for '{}'", distro_id));
+    let _ = fs::remove_file(&branded_os_release);
+    built?;
+    for flavor in UkiFlavor::ALL {
+        println!(
+            "  Built {} UKI {}",
+            flavor.id(),
+            esp_dir.join(UKI_DIR).join(set.filename(flavor)).display()
+        );
+    }
+    Ok(())
+}
+
+/// The contract identity as [`Branding`]: os-release, issue and boot menu
+/// titles all come from it.
+pub(crate) fn contract_branding(contract: &ConformanceContract) -> Branding {
+    Branding::new(&contract.identity.os_name, &contract.identity.os_id)
+        .version(&contract.identity.os_version)
+}
+
+pub(crate) fn check_initramfs_for_distro(
+    repo_root: &Path,
+    distro_id: &str,
+    initramfs: &Path,
+) -> Result<()> {
+    let variant_dir = RepoLayout::at(repo_root)?.variant_dir(distro_id);
+    let mut contract = InitramfsContract::load_for_variant(&variant_dir).with_context(|| {
+        format!(
+            "loading initramfs content contract for '{}' from '{}'",
+            distro_id,
+            variant_dir.display()
+        )
+    })?;
+    if let Some(splash) = SplashConfig::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading splash config for '{}'", distro_id))?
+    {
+        splash.extend_initramfs_contract(&mut contract);
+    }
+    if let Some(netboot) = NetbootFeatures::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading netboot config for '{}'", distro_id))?
+    {
+        netboot.extend_initramfs_contract(&mut contract);
+    }
+    if let Some(layer) = WritableLayer::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading writable layer config for '{}'", distro_id))?
+    {
+        layer.extend_initramfs_contract(&mut contract);
+    }
+    check_initramfs(initramfs, &contract)
+        .with_context(|| format!("checking initramfs '{}'", initramfs.display()))
+}
+
+fn canonical_base_product_layout(product: crate::cli::BuildProduct) -> BaseProductLayout {
+    BaseProductLayout {
+        rootfs_source_dir: PathBuf::from("rootfs-source"),
+        live_overlay_dir_name: product.live_overlay_dir_name.to_string(),
+    }
+}
+
+fn canonical_derived_product_layout(
+    contract: &ConformanceContract,
+    product: crate::cli::BuildProduct,
+) -> Result<DerivedProductLayout> {
+    let runtime_product = runtime_product_decl(contract, product)?;
+    let parent_logical_name = runtime_product
+        .extends
+        .as_deref()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "missing canonical Ring 2 composition edge for '{}': product '{}' must declare `extends` in ring2/products.toml",
+                product.canonical,
+                runtime_product.logical_name
+            )
+        })?;
+    let parent_product = crate::cli::workflows::product_for_logical_name(parent_logical_name)?;
+    Ok(DerivedProductLayout {
+        rootfs_source_dir: PathBuf::from("rootfs-source"),
+        parent_rootfs: ParentRootfsInput {
+            release_dir_name: parent_product.release_dir_name.to_string(),
+            producer_label: parent_product.canonical.to_string(),
+            rootfs_filename: canonical_rootfs_erofs_filename(contract)?,
+        },
+        live_overlay: OverlayLayout {
+            issue_banner_label: product.issue_banner_label.to_string(),
+            dir_name: product.live_overlay_dir_name.to_string(),
+        },
+    })
+}
+
+fn runtime_product_decl<'a>(
+    contract: &'a ConformanceContract,
+    product: crate::cli::BuildProduct,
+) -> Result<&'a ProductDecl> {
+    match product.canonical {
+        crate::cli::PRODUCT_LIVE_BOOT => Ok(&contract.products.boot_live),
+        crate::cli::PRODUCT_LIVE_TOOLS => Ok(&contract.products.live_tools),
+        crate::cli::PRODUCT_INSTALLED_BOOT => contract.products.boot_installed.as_ref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "missing canonical Ring 2 product declaration for '{}': ring2/products.toml must define `boot_installed`",
+                product.canonical
+            )
+        }),
+        _ => unreachable!("canonical derived product layout is only valid for derived products"),
+    }
+}
+
+pub(crate) fn build_prepared_product_erofs_cmd(prepared_dir: &Path) -> Result<()> {
+    let manifest = read_prepared_product_manifest(prepared_dir)?;
+    let rootfs_source_dir =
+        resolve_prepared_product_path(prepared_dir, &manifest.rootfs_source_dir);
+    let live_overlay_dir = resolve_prepared_product_path(prepared_dir, &manifest.live_overlay_dir);
+    if !live_overlay_dir.is_dir() {
+        bail!(
+            "live overlay source directory missing for product '{}' at '{}'\n\
+             Remediation: rerun `distro-builder product prepare {} {} {}` and verify overlay preparation succeeds.",
+            manifest.product,
+            live_overlay_dir.display(),
+            manifest.product,
+            manifest.distro_id,
+            prepared_dir.display()
+        );
+    }
+    if !rootfs_source_dir.is_dir() {
+        bail!(
+            "rootfs source directory missing for product '{}' at '{}'\n\
+             Remediation: rerun `distro-builder product prepare {} {} {}` and verify rootfs preparation succeeds.",
+            manifest.product,
+            rootfs_source_dir.display(),
+            manifest.product,
+            manifest.distro_id,
+            prepared_dir.display()
+        );
+    }
+
+    verify_live_payloads(&rootfs_source_dir, &live_overlay_dir).with_context(|| {
+        format!(
+            "checking payload roles of product '{}' in '{}'",
+            manifest.product,
+            prepared_dir.display()
+        )
+    })?;
+    let conflict_policy = match crate::cli::workflows::locate_repo_root() {
+        Ok(repo_root) => ConflictPolicy::load_for_variant(
+            &layout_or_default(&repo_root).variant_dir(&manifest.distro_id),
+        )
+        .with_context(|| {
+            format!(
+                "loading overlay conflict allowlist for '{}'",
+                manifest.distro_id
+            )
+        })?,
+        Err(_) => ConflictPolicy::default(),
+    };
+    conflict_policy
+        .check(&rootfs_source_dir, &live_overlay_dir)
+        .with_context(|| {
+            format!(
+                "checking overlay conflicts of product '{}' in '{}'",
+                manifest.product,
+                prepared_dir.display()
+            )
+        })?;
+
+    let rootfs_output = prepared_dir.join(&manifest.rootfs_erofs_filename);
+    let overlay_output = prepared_dir.join(&manifest.overlay_erofs_filename);
+
+    build_rootfs_erofs(&rootfs_source_dir, &rootfs_output).with_context(|| {
+        format!(
+            "building product '{}' rootfs EROFS from '{}' to '{}'",
+            manifest.product,
+            rootfs_source_dir.display(),
+            rootfs_output.display()
+        )
+    })?;
+    build_overlayfs_erofs(&live_overlay_dir, &overlay_output).with_context(|| {
+        format!(
+            "building product '{}' overlayfs EROFS from '{}' to '{}'",
+            manifest.product,
+            live_overlay_dir.display(),
+            overlay_output.display()
+        )
+    })?;
+
+    println!(
+        "product '{}' EROFS artifacts built for {}:",
+        manifest.product, manifest.distro_id
+    );
+    println!("  prepared dir: {}", prepared_dir.display());
+    println!("  rootfs source: {}", rootfs_source_dir.display());
+    println!("  overlay source: {}", live_overlay_dir.display());
+    println!("  rootfs erofs: {}", rootfs_output.display());
+    println!("  overlay erofs: {}", overlay_output.display());
+    Ok(())
+}
+
+pub(crate) fn prepare_product_cmd(
+    repo_root: &Path,
+    product: &str,
+    distro_id: &str,
+    output_dir: &Path,
+) -> Result<()> {
+    let product = crate::cli::workflows::parse_product(Some(product))?;
+    let bundle = load_variant_contract_bundle_for_distro_from(repo_root, distro_id)
+        .with_context(|| format!("loading canonical variant contract for '{}'", distro_id))
+        .error_kind(ErrorKind::InvalidContract)?;
+    crate::cli::workflows::ensure_release_prerequisites(
+        &bundle.repo_root,
+        distro_id,
+        product,
+        crate::cli::workflows::ReleaseBuildOptions::default(),
+    )
+    .with_context(|| {
+        format!(
+            "realizing parent release prerequisites for product '{}' on '{}'",
+            product.canonical, distro_id
+        )
+    })?;
+    let realization_plan = plan_product_realization(
+        &bundle.repo_root,
+        distro_id,
+        &bundle.contract,
+        product.canonical,
+    )
+    .with_context(|| {
+        format!(
+            "planning canonical product realization for '{}' on '{}'",
+            product.canonical, distro_id
+        )
+    })?;
+    let planned_step = realization_plan.requested_step().with_context(|| {
+        format!(
+            "looking up requested realization step for canonical product '{}' on '{}'",
+            product.canonical, distro_id
+        )
+    })?;
+    let prepared = prepare_product_inputs(&bundle, product, distro_id, output_dir, planned_step)?;
+    let output_names = canonical_prepared_output_names(&bundle.contract, product)
+        .with_context(|| format!("resolving canonical Ring 1 outputs for '{}'", distro_id))?;
+    let source_path_file =
+        write_prepared_product_outputs(output_dir, product, distro_id, &prepared, &output_names)?;
+
+    println!(
+        "product '{}' inputs prepared for {}:",
+        product.canonical, distro_id
+    );
+    println!("  rootfs source: {}", prepared.rootfs_source_dir.display());
+    println!("  live overlay:  {}", prepared.live_overlay_dir.display());
+    if let Some(installer_image) = &prepared.installer_image {
+        println!("  installer:     {}", installer_image.display());
+    }
+    println!("  source path:   {}", source_path_file.display());
+    Ok(())
+}
+
+fn prepare_product_inputs(
+    bundle: &LoadedVariantContract,
+    product: crate::cli::BuildProduct,
+    distro_id: &str,
+    output_dir: &Path,
+    planned_step: &ProductRealizationStep,
+) -> Result<PreparedProductInputs> {
+    if planned_step.product != product.canonical {
+        bail!(
+            "planner mismatch while preparing canonical product '{}' on '{}': requested step resolved '{}' instead\nRemediation: regenerate the planner-backed realization chain before calling the preparer",
+            product.canonical,
+            distro_id,
+            planned_step.product
+        );
+    }
+
+    match product.canonical {
+        crate::cli::PRODUCT_BASE_ROOTFS => {
+            let output_root =
+                crate::cli::artifact_paths::distro_output_root_for(&bundle.repo_root, distro_id)?;
+            let spec = load_base_rootfs_product_spec(
+                distro_id,
+                &contract_branding(&bundle.contract),
+                &output_root,
+                canonical_base_product_layout(product),
+            )
+            .with_context(|| {
+                format!("loading {} baseline for '{}'", product.canonical, distro_id)
+            })?;
+            let prepared = prepare_base_rootfs_product(&spec, output_dir).with_context(|| {
+                format!("preparing {} inputs for '{}'", product.canonical, distro_id)
+            })?;
+            Ok(PreparedProductInputs {
+                rootfs_source_dir: prepared.rootfs_source_dir,
+                live_overlay_dir: prepared.live_overlay_dir,
+                installer_image: None,
+            })
+        }
+        crate::cli::PRODUCT_LIVE_BOOT => {
+            let layout = canonical_derived_product_layout(&bundle.contract, product)?;
+            let resolved_parent_rootfs_image =
+                planned_parent_rootfs_image(planned_step, product, distro_id, &layout)?;
+            let spec = load_live_boot_product_spec(
+                &bundle.repo_root,
+                &bundle.variant_dir,
+                &bundle.contract,
+                distro_id,
+                layout,
+            )
+            .map(|spec| spec.with_resolved_parent_rootfs_image(resolved_parent_rootfs_image))
+            .with_context(|| format!("loading {} config for '{}'", product.canonical, distro_id))?;
+            let prepared = prepare_live_boot_product(&spec, output_dir).with_context(|| {
+                format!("preparing {} inputs for '{}'", product.canonical, distro_id)
+            })?;
+            Ok(PreparedProductInputs {
+                rootfs_source_dir: prepared.rootfs_source_dir,
+                live_overlay_dir: prepared.live_overlay_dir,
+                installer_image: None,
+            })
+        }
+        crate::cli::PRODUCT_LIVE_TOOLS => {
+            let layout = canonical_derived_product_layout(&bundle.contract, product)?;
+            let resolved_parent_rootfs_image =
+                planned_parent_rootfs_image(planned_step, product, distro_id, &layout)?;
+            let spec = load_live_tools_product_spec(
+                &bundle.repo_root,
+                &bundle.variant_dir,
+                &bundle.contract,
+                distro_id,
+                layout,
+            )
+            .map(|spec| spec.with_resolved_parent_rootfs_image(resolved_parent_rootfs_image))
+            .with_context(|| format!("loading {} config for '{}'", product.canonical, distro_id))?;
+            let prepared = prepare_live_tools_product(&spec, output_dir).with_context(|| {
+                format!("preparing {} inputs for '{}'", product.canonical, distro_id)
+            })?;
+            Ok(PreparedProductInputs {
+                rootfs_source_dir: prepared.rootfs_source_dir,
+                live_overlay_dir: prepared.live_overlay_dir,
+                installer_image: Some(prepared.installer_image),
+            })
+        }
+        crate::cli::PRODUCT_INSTALLED_BOOT => {
+            let layout = canonical_derived_product_layout(&bundle.contract, product)?;
+            let resolved_parent_rootfs_image =
+                planned_parent_rootfs_image(planned_step, product, distro_id, &layout)?;
+            let spec = load_installed_boot_product_spec(
+                &bundle.repo_root,
+                &bundle.variant_dir,
+                &bundle.contract,
+                distro_id,
+                layout,
+            )
+            .map(|spec| spec.with_resolved_parent_rootfs_image(resolved_parent_rootfs_image))
+            .with_context(|| format!("loading {} config for '{}'", product.canonical, distro_id))?;
+            let prepared =
+                prepare_installed_boot_product(&spec, output_dir).with_context(|| {
+                    format!("preparing {} inputs for '{}'", product.canonical, distro_id)
+                })?;
+            Ok(PreparedProductInputs {
+                rootfs_source_dir: prepared.rootfs_source_dir,
+                live_overlay_dir: prepared.live_overlay_dir,
+                installer_image: None,
+            })
+        }
+        _ => unreachable!("validated in parse_product"),
+    }
+}
+
+fn planned_parent_rootfs_image(
+    planned_step: &ProductRealizationStep,
+    product: crate::cli::BuildProduct,
+    distro_id: &str,
+    layout: &DerivedProductLayout,
+) -> Result<PathBuf> {
+    planned_step
+        .resolved_parent_rootfs_image
+        .clone()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "planner did not resolve parent release '{}' for canonical product '{}' on '{}'\nRemediation: use the planner-backed `distro-builder product prepare {} {} <output_dir>` entrypoint after parent release realization succeeds",
+                layout.parent_rootfs.producer_label,
+                product.canonical,
+                distro_id,
+                product.canonical,
+                distro_id
+            )
+        })
+}
+
+pub(crate) fn preseed_rootfs_source_cmd(distro_id: &str, refresh: bool) -> Result<()> {
+    let cwd = std::env::current_dir().context("resolving current directory")?;
+    let bundle = load_variant_contract_bundle_for_distro_from(&cwd, distro_id)
+        .with_context(|| format!("loading canonical variant contract for '{}'", distro_id))
+        .error_kind(ErrorKind::InvalidContract)?;
+    let live_boot_spec = canonical_live_boot_product_spec(&bundle, distro_id)
+        .with_context(|| format!("loading canonical rootfs source policy for '{}'", distro_id))?;
+
+    if let Some(preseed_recipe_script) = live_boot_spec.rpm_dvd_preseed_recipe_script() {
+        let iso_path =
+            preseed_rootfs_source_dvd(&bundle.repo_root, distro_id, preseed_recipe_script, refresh)
+                .with_context(|| format!("preseeding rootfs source for '{}'", distro_id))?;
+        let trust_dir = match iso_path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => artifacts_root(&bundle.repo_root)?
+                .join("work")
+                .join(distro_id)
+                .join("downloads"),
+        };
+
+        println!("rootfs source preseed ready for {}:", distro_id);
+        println!("  ISO:   {}", iso_path.display());
+        println!("  Trust: {}", trust_dir.display());
+        return Ok(());
+    }
+
+    if live_boot_spec.uses_alpine_live_source_rootfs() {
+        let output = preseed_alpine_rootfs_source_assets(&bundle.repo_root, distro_id, refresh)
+            .with_context(|| format!("preseeding rootfs source for '{}'", distro_id))?;
+        let trust_dir = match output.iso_path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => artifacts_root(&bundle.repo_root)?
+                .join("work")
+                .join(distro_id)
+                .join("downloads"),
+        };
+
+        println!("rootfs source preseed ready for {}:", distro_id);
+        println!("  ISO:        {}", output.iso_path.display());
+        println!("  apk-tools:  {}", output.apk_tools_path.display());
+        println!("  Trust:      {}", trust_dir.display());
+        return Ok(());
+    }
+
+    bail!(
+        "rootfs source for '{}' does not use a canonical preseedable recipe in '{}'",
+        distro_id,
+        bundle.paths.ring3_sources_manifest.display()
+    );
+}
+
+pub(crate) fn materialize_rootfs_source_cmd(distro_id: &str) -> Result<()> {
+    let cwd = std::env::current_dir().context("resolving current directory")?;
+    let bundle = load_variant_contract_bundle_for_distro_from(&cwd, distro_id)
+        .with_context(|| format!("loading canonical variant contract for '{}'", distro_id))
+        .error_kind(ErrorKind::InvalidContract)?;
+    let live_boot_spec = canonical_live_boot_product_spec(&bundle, distro_id)
+        .with_context(|| format!("loading canonical rootfs source policy for '{}'", distro_id))?;
+
+    let source_rootfs_dir = materialize_live_boot_source_rootfs(&live_boot_spec)
+        .with_context(|| format!("materializing canonical rootfs source for '{}'", distro_id))?;
+
+    println!("rootfs source ready for {}:", distro_id);
+    println!("  rootfs source: {}", source_rootfs_dir.display());
+    Ok(())
+}
+
+pub(crate) fn canonical_live_boot_product_spec(
+    bundle: &LoadedVariantContract,
+    distro_id: &str,
+) -> Result<crate::LiveBootProductSpec> {
+    let product = crate::cli::workflows::parse_product(Some(crate::cli::PRODUCT_LIVE_BOOT))?;
+    let layout = canonical_derived_product_layout(&bundle.contract, product)?;
+    load_live_boot_product_spec(
+        &bundle.repo_root,
+        &bundle.variant_dir,
+        &bundle.contract,
+        distro_id,
+        layout,
+    )
+}
@@ -0,0 +1,672 @@
+use anyhow::{bail, Context, Result};
+use distro_contract::{
+    load_variant_contract_bundle_for_distro_from, require_valid_contract, LoadedVariantContract,
+};
+use std::path::Path;
+use time::OffsetDateTime;
+
+use crate::artifact_store::ArtifactStore;
+use crate::build::context::BUILD_CONTEXT_FILENAME;
+use crate::build::external_modules::{ExternalModuleRegistry, EXTERNAL_MODULES_FILENAME};
+use crate::build_host::{
+    ensure_kernel_preinstalled_via_recipe, run_build_host_evidence_script, BuildHostEvidenceSpec,
+    BuildHostKernelEnsureOutcome, BuildHostKernelShareKey, BuildHostKernelSpec,
+};
+use crate::build_summary;
+use crate::contracts::KernelInstallConfig;
+use crate::error::{ErrorKind, ResultExt};
+use crate::guest_inventory::{previous_inventory, write_inventory, GuestInventory};
+use crate::guest_results::{merge_into_run_manifest, GuestTestResults};
+use crate::preflight::{iso_pipeline_tools, RequiredTools, VariantHostTools};
+use crate::repo_config::RepoConfig;
+use crate::repo_layout::RepoLayout;
+use crate::run_history::{
+    allocate_run_dir, prune_old_runs, run_manifest_path, RunMetadata, RunStatus,
+};
+use crate::smoke_test::{failed_results, skipped_results, SmokeTestConfig};
+use crate::{
+    publish_release_artifacts, release_input_key, restore_release_artifacts, BuildSettings,
+    ReleaseArtifactFiles, TomlBuildContext,
+};
+
+use crate::cli::{BuildOutputLayout, BuildProduct};
+
+/// Per-invocation release build switches.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ReleaseBuildOptions {
+    /// Boot the assembled ISO before calling the build green; `--no-test`
+    /// clears it.
+    pub(crate) smoke_test: bool,
+}
+
+impl Default for ReleaseBuildOptions {
+    fn default() -> Self {
+        Self { smoke_test: true }
+    }
+}
+
+pub(crate) fn ensure_release_prerequisites(
+    repo_root: &Path,
+    distro_id: &str,
+    product: BuildProduct,
+    options: ReleaseBuildOptions,
+) -> Result<()> {
+    let bundle = load_variant_contract_bundle_for_distro_from(repo_root, distro_id)
+        .with_context(|| format!("loading variant contract for '{}'", distro_id))
+        .error_kind(ErrorKind::InvalidContract)?;
+    let prerequisite_plan = crate::plan_release_prerequisite_realization(
+        repo_root,
+        distro_id,
+        &bundle.contract,
+        product.canonical,
+    )?;
+    for prerequisite in prerequisite_plan.missing_products() {
+        let prerequisite = crate::cli::workflows::parse_product(Some(prerequisite))?;
+        let restored = restore_release_from_store(repo_root, distro_id, &bundle, prerequisite)?;
+        build_summary::record_cache("release", restored);
+        if restored {
+            println!(
+                "[release:iso:{}:{distro_id}] restored parent release '{}' from artifact store",
+                product.canonical, prerequisite.canonical
+            );
+            continue;
+        }
+        println!(
+            "[release:iso:{}:{distro_id}] materializing missing parent release '{}'...",
+            product.canonical, prerequisite.canonical
+        );
+        build_one(repo_root, distro_id, prerequisite, options)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn enforce_legacy_binding_policy_guard(repo_root: &Path) -> Result<()> {
+    crate::policy::enforce_legacy_binding_policy(repo_root).context(
+        "policy guard failed before distro-builder execution\n\
+Remediation: run `distro-builder policy audit-legacy-bindings` and fix violations first",
+    )
+}
+
+/// Print every legacy-binding violation; fails when there are any.
+pub(crate) fn policy_audit_cmd() -> Result<()> {
+    let repo_root = crate::cli::workflows::layout::locate_repo_root()?;
+    let violations = crate::policy::audit_legacy_bindings(&repo_root)?;
+    for violation in &violations {
+        println!("{}", violation);
+    }
+    if !violations.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} legacy-binding policy violation(s) in {}",
+            violations.len(),
+            repo_root.display()
+        ))
+        .error_kind(ErrorKind::PolicyViolation);
+    }
+    println!("legacy-binding policy: ok");
+    Ok(())
+}
+
+pub(crate) fn build_all(product: BuildProduct, options: ReleaseBuildOptions) -> Result<()> {
+    let cwd = std::env::current_dir().context("resolving current directory")?;
+    let distro_ids = crate::cli::workflows::parse::discover_distro_ids(&cwd)?;
+    for distro_id in &distro_ids {
+        println!(
+            "[release:iso:{}] building {}...",
+            product.canonical, distro_id
+        );
+        ensure_release_prerequisites(&cwd, distro_id, product, options)?;
+        build_one(&cwd, distro_id, product, options)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn build_one(
+    repo_root: &Path,
+    distro_id: &str,
+    product: BuildProduct,
+    options: ReleaseBuildOptions,
+) -> Result<()> {
+    let bundle = load_variant_contract_bundle_for_distro_from(repo_root, distro_id)
+        .with_context(|| format!("loading variant contract for '{distro_id}'"))
+        .error_kind(ErrorKind::InvalidContract)?;
+
+    require_valid_contract(&bundle.contract)
+        .with_context(|| format!("validating variant contract for '{distro_id}'"))
+        .error_kind(ErrorKind::InvalidContract)?;
+    let build_context = load_build_context(&bundle, distro_id)?;
+    let effective_config = build_context.effective_config_json();
+
+    let kernel_output_dir =
+        crate::cli::artifact_paths::kernel_output_dir_for(&bundle.repo_root, distro_id)?;
+    std::fs::create_dir_all(&kernel_output_dir).with_context(|| {
+        format!(
+            "creating kernel output directory '{}'",
+            kernel_output_dir.display()
+        )
+    })?;
+    let build_layout = product_release_output_layout_for(&bundle.repo_root, distro_id, product)?;
+
+    let output_dir = build_layout.output_dir.clone();
+    let build = &bundle.contract.build;
+
+    let external_modules = ExternalModuleRegistry::load(
+        &bundle
+            .paths
+            .build_host_declared_path(&format!("kernel/{}", EXTERNAL_MODULES_FILENAME)),
+    )
+    .with_context(|| format!("loading external kernel modules for '{distro_id}'"))?;
+    let share_key = BuildHostKernelShareKey::new(
+        &build.kernel.version,
+        &bundle
+            .paths
+            .build_host_declared_path(&build.kernel.kconfig_path),
+        &build.kernel.localversion,
+    )
+    .and_then(|key| key.with_external_modules(&external_modules))
+    .with_context(|| format!("computing shared kernel key for '{distro_id}'"))?;
+    let kernel_spec = BuildHostKernelSpec {
+        recipe_kernel_script: build.kernel.recipe_script.clone(),
+        kernel_kconfig_path: build.kernel.kconfig_path.clone(),
+        share_key: Some(share_key),
+        external_modules,
+        required_modules: build_context.required_modules().to_vec(),
+        forbidden_modules: build_context.forbidden_modules().to_vec(),
+    };
+
+    let base_iso_filename = crate::cli::workflows::canonical_iso_filename(&bundle.contract)
+        .with_context(|| format!("resolving canonical Ring 0 ISO output for '{}'", distro_id))?;
+    let created_at_utc = now_utc_compact()?;
+    let iso_path = output_dir.join(iso_filename_for_product(&base_iso_filename, product));
+
+    if let Some(run_id) = build_layout.run_id.as_deref() {
+        build_summary::record_run_dir(&output_dir);
+        let metadata_path = run_manifest_path(&output_dir);
+        crate::cli::run_manifest::write_run_metadata(
+            &metadata_path,
+            &release_run_metadata(
+                run_id,
+                distro_id,
+                product,
+                RunStatus::Building,
+                created_at_utc.clone(),
+                None,
+                &build_layout,
+                &iso_path,
+                effective_config.clone(),
+            ),
+        )?;
+    }
+
+    let mut smoke_results: Option<GuestTestResults> = None;
+    let stage_name = |stage: &str| format!("{distro_id}:{}:{stage}", product.canonical);
+    let build_result = (|| -> Result<()> {
+        build_summary::stage(&stage_name("preflight"), || {
+            release_required_tools(&bundle, distro_id, product, options)?
+                .check()
+                .with_context(|| format!("preflight for '{distro_id}' ({})", product.canonical))
+        })?;
+        let kernel_outcome = build_summary::stage(&stage_name("kernel"), || {
+            ensure_kernel_preinstalled_via_recipe(
+                &bundle.repo_root,
+                &bundle.paths,
+                distro_id,
+                &kernel_output_dir,
+                &kernel_spec,
+            )
+            .with_context(|| format!("ensuring kernel artifacts for '{distro_id}'"))
+        })?;
+        // Both outcomes reuse a built kernel; a missing one fails above.
+        build_summary::record_cache("kernel", true);
+        match kernel_outcome {
+            BuildHostKernelEnsureOutcome::AlreadyInstalled => {
+                println!(
+                    "[release:iso:{}:{distro_id}] kernel already installed",
+                    product.canonical
+                );
+            }
+            BuildHostKernelEnsureOutcome::RestoredShared => {
+                println!(
+                    "[release:iso:{}:{distro_id}] kernel restored from shared artifact store",
+                    product.canonical
+                );
+            }
+        }
+        build_summary::stage(&stage_name("iso"), || {
+            crate::cli::workflows::ensure_release_iso_via_variant_hook(
+                &bundle,
+                distro_id,
+                &kernel_output_dir,
+                &build_layout,
+                product,
+            )
+        })?;
+
+        let evidence_spec = BuildHostEvidenceSpec {
+            script_path: build.evidence.script_path.clone(),
+            pass_marker: build.evidence.pass_marker.clone(),
+            kernel_release_path: build.kernel.release_path.clone(),
+            kernel_image_path: build.kernel.image_path.clone(),
+            iso_filename: iso_filename_for_product(&base_iso_filename, product),
+        };
+
+        build_summary::stage(&stage_name("evidence"), || {
+            run_build_host_evidence_script(
+                &bundle.repo_root,
+                &bundle.paths,
+                &kernel_output_dir,
+                &output_dir,
+                &evidence_spec,
+            )
+            .with_context(|| format!("running build evidence for '{distro_id}'"))
+        })?;
+        record_release_artifacts(&bundle, &output_dir, &iso_path);
+
+        build_summary::stage(&stage_name("smoke-test"), || {
+            let (results, outcome) =
+                release_smoke_test(&bundle, distro_id, product, &iso_path, options);
+            build_summary::record_tests(&format!("{distro_id}:{}", product.canonical), &results);
+            smoke_results = Some(results);
+            outcome
+        })?;
+
+        println!(
+            "[release:iso:{}:{distro_id}] built at {}",
+            product.canonical,
+            output_dir
+                .join(iso_filename_for_product(&base_iso_filename, product))
+                .display()
+        );
+        Ok(())
+    })();
+
+    if let Some(run_id) = build_layout.run_id.as_deref() {
+        let metadata_path = run_manifest_path(&output_dir);
+        let finished_at_utc = Some(now_utc_compact()?);
+        let status = if build_result.is_ok() {
+            RunStatus::Success
+        } else {
+            RunStatus::Failed
+        };
+        let metadata_result = crate::cli::run_manifest::write_run_metadata(
+            &metadata_path,
+            &release_run_metadata(
+                run_id,
+                distro_id,
+                product,
+                status,
+                created_at_utc,
+                finished_at_utc,
+                &build_layout,
+                &iso_path,
+                effective_config,
+            ),
+        );
+        if let Err(err) = metadata_result {
+            if build_result.is_ok() {
+                return Err(err);
+            }
+            eprintln!(
+                "[release:iso:{}:{distro_id}] warning: failed to persist release run metadata: {err:#}",
+                product.canonical
+            );
+        }
+        if let Some(results) = &smoke_results {
+            if let Err(err) = merge_into_run_manifest(&output_dir, results) {
+                eprintln!(
+                    "[release:iso:{}:{distro_id}] warning: failed to record smoke test results: {err:#}",
+                    product.canonical
+                );
+            }
+            if let Some(inventory) = &results.inventory {
+                if let Err(err) = record_guest_inventory(&build_layout, run_id, inventory) {
+                    eprintln!(
+                        "[release:iso:{}:{distro_id}] warning: failed to record guest inventory: {err:#}",
+                        product.canonical
+                    );
+                }
+            }
+        }
+
+        if build_result.is_ok() {
+            if let Err(err) = publish_release_to_store(&bundle, distro_id, product, &output_dir) {
+                eprintln!(
+                    "[release:iso:{}:{distro_id}] warning: failed to publish release to artifact store: {err:#}",
+                    product.canonical
+                );
+            }
+            prune_old_runs(
+                &build_layout.root_dir,
+                crate::cli::RELEASE_RUN_RETENTION_COUNT,
+            )?;
+        }
+    }
+
+    build_result
+}
+
+/// Record the run's ISO, rootfs EROFS and live initramfs in the build
+/// summary.
+fn record_release_artifacts(bundle: &LoadedVariantContract, output_dir: &Path, iso_path: &Path) {
+    build_summary::record_artifact(iso_path);
+    let contract = &bundle.contract;
+    let others = [
+        Some(contract.artifacts.rootfs_name.clone()),
+        crate::cli::workflows::canonical_initramfs_live_filename(contract).ok(),
+    ];
+    for name in others.into_iter().flatten() {
+        let path = output_dir.join(name);
+        if path.is_file() {
+            build_summary::record_artifact(&path);
+        }
+    }
+}
+
+/// Host tools the release will invoke: the ISO pipeline, the variant's
+/// `host-tools.toml`, plus QEMU when the smoke test will run.
+pub(crate) fn release_required_tools(
+    bundle: &LoadedVariantContract,
+    distro_id: &str,
+    product: BuildProduct,
+    options: ReleaseBuildOptions,
+) -> Result<RequiredTools> {
+    let compression = RepoConfig::load(&bundle.repo_root)?.compression;
+    let mut tools = iso_pipeline_tools(compression.initramfs);
+    let variant_dir = RepoLayout::at(&bundle.repo_root)?.variant_dir(distro_id);
+    VariantHostTools::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading host tool requirements for '{distro_id}'"))?
+        .register(&mut tools);
+    if product.canonical == crate::cli::PRODUCT_LIVE_TOOLS {
+        crate::artifact::installer::register_host_tools(&mut tools);
+    }
+    // A config that fails to load fails the smoke test, so it still runs.
+    let config = SmokeTestConfig::load_for_variant(&variant_dir).ok();
+    let smoke_test = options.smoke_test
+        && config
+            .as_ref()
+            .is_none_or(|config| config.skip_reason(product.canonical).is_none());
+    if smoke_test {
+        crate::qemu::register_host_tools(&mut tools);
+        if config.is_some_and(|config| !config.test_bundles.is_empty()) {
+            crate::test_bundle::register_host_tools(&mut tools);
+        }
+    }
+    Ok(tools)
+}
+
+/// Boot the freshly built ISO unless the invocation or the variant opts
+/// out. Returns the records for the run manifest and the test outcome.
+fn release_smoke_test(
+    bundle: &LoadedVariantContract,
+    distro_id: &str,
+    product: BuildProduct,
+    iso_path: &Path,
+    options: ReleaseBuildOptions,
+) -> (GuestTestResults, Result<()>) {
+    let variant_dir = match RepoLayout::at(&bundle.repo_root) {
+        Ok(layout) => layout.variant_dir(distro_id),
+        Err(err) => return (failed_results(&err), Err(err)),
+    };
+    let config = match SmokeTestConfig::load_for_variant(&variant_dir) {
+        Ok(config) => config,
+        Err(err) => {
+            let err = err.context(format!("loading smoke test config for '{distro_id}'"));
+            return (failed_results(&err), Err(err));
+        }
+    };
+    let skip_reason = if options.smoke_test {
+        config.skip_reason(product.canonical)
+    } else {
+        Some("skipped with --no-test".to_string())
+    };
+    if let Some(reason) = skip_reason {
+        println!(
+            "[release:iso:{}:{distro_id}] smoke test skipped: {reason}",
+            product.canonical
+        );
+        return (skipped_results(&reason), Ok(()));
+    }
+
+    println!(
+        "[release:iso:{}:{distro_id}] smoke testing {}...",
+        product.canonical,
+        iso_path.display()
+    );
+    match config.run(iso_path, &variant_dir, distro_id) {
+        Ok(results) => (results, Ok(())),
+        Err(err) => {
+            let results = failed_results(&err);
+            let err = err.context(format!(
+                "smoke test failed for '{}' ({})\n\
+                 Remediation: fix the boot failure above, or pass --no-test to build without booting.",
+                distro_id, product.canonical
+            ));
+            (results, Err(err))
+        }
+    }
+}
+
+/// Store the guest inventory as run evidence and print how it drifted from
+/// the previous successful run's.
+fn record_guest_inventory(
+    build_layout: &BuildOutputLayout,
+    run_id: &str,
+    inventory: &GuestInventory,
+) -> Result<()> {
+    let path = write_inventory(&build_layout.output_dir, inventory)?;
+    println!("[release] guest inventory: {}", path.display());
+    let Some((previous_run, previous)) = previous_inventory(&build_layout.root_dir, run_id)? else {
+        return Ok(());
+    };
+    let drift = inventory.drift(&previous);
+    if drift.is_empty() {
+        println!("[release] guest inventory unchanged since run {previous_run}");
+    } else {
+        print!("[release] guest inventory drift since run {previous_run}:\n{drift}");
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn release_run_metadata(
+    run_id: &str,
+    distro_id: &str,
+    product: BuildProduct,
+    status: RunStatus,
+    created_at_utc: String,
+    finished_at_utc: Option<String>,
+    build_layout: &BuildOutputLayout,
+    iso_path: &Path,
+    effective_config: serde_json::Value,
+) -> RunMetadata {
+    let mut metadata = RunMetadata::new(run_id, status, created_at_utc);
+    metadata.distro_id = distro_id.to_string();
+    metadata.target_kind = "release-product".to_string();
+    metadata.target_name = product.canonical.to_string();
+    metadata.finished_at_utc = finished_at_utc;
+    metadata.root_dir = build_layout.root_dir.display().to_string();
+    metadata.output_dir = build_layout.output_dir.display().to_string();
+    metadata.iso_path = iso_path.display().to_string();
+    metadata.effective_config = Some(effective_config);
+    metadata
+}
+
+/// Put the run's ISO, rootfs EROFS and live initramfs into the artifact
+/// store under the release input key.
+fn publish_release_to_store(
+    bundle: &LoadedVariantContract,
+    distro_id: &str,
+    product: BuildProduct,
+    output_dir: &Path,
+) -> Result<()> {
+    let contract = &bundle.contract;
+    let input_key = release_input_key(&bundle.repo_root, distro_id, contract, product.canonical)?;
+    let base_iso_filename = crate::cli::workflows::canonical_iso_filename(contract)?;
+    let files = ReleaseArtifactFiles {
+        iso: Some(iso_filename_for_product(&base_iso_filename, product)),
+        rootfs: Some(contract.artifacts.rootfs_name.clone()),
+        initramfs: crate::cli::workflows::canonical_initramfs_live_filename(contract).ok(),
+    };
+    let store = ArtifactStore::open(&bundle.repo_root)?;
+    let published = publish_release_artifacts(
+        &store,
+        distro_id,
+        product.canonical,
+        &input_key,
+        output_dir,
+        &files,
+    )?;
+    println!(
+        "[release:iso:{}:{distro_id}] published {} artifact(s) to store (key {})",
+        product.canonical,
+        published,
+        &input_key[..12]
+    );
+    Ok(())
+}
+
+/// Restore a missing release from the artifact store when its current input
+/// key is stored. Returns `false` when the release must be built.
+fn restore_release_from_store(
+    repo_root: &Path,
+    distro_id: &str,
+    bundle: &LoadedVariantContract,
+    product: BuildProduct,
+) -> Result<bool> {
+    let input_key = release_input_key(repo_root, distro_id, &bundle.contract, product.canonical)
+        .with_context(|| {
+            format!(
+                "computing release input key for '{}' on '{}'",
+                product.canonical, distro_id
+            )
+        })?;
+    let store = ArtifactStore::open(repo_root)?;
+    let product_root = crate::cli::artifact_paths::release_product_dir_for(
+        repo_root,
+        distro_id,
+        product.release_dir_name,
+    )?;
+    Ok(restore_release_artifacts(
+        &store,
+        distro_id,
+        &product_root,
+        product.canonical,
+        &input_key,
+    )?
+    .is_some())
+}
+
+/// Layer the variant's `build-context.toml` and `DISTRO_BUILDER_CFG_*`
+/// overrides on top of the contract identity.
+fn load_build_context(bundle: &LoadedVariantContract, distro_id: &str) -> Result<TomlBuildContext> {
+    let identity = &bundle.contract.identity;
+    let variant_toml = RepoLayout::at(&bundle.repo_root)?
+        .variant_dir(distro_id)
+        .join(BUILD_CONTEXT_FILENAME);
+    TomlBuildContext::load(
+        &bundle.repo_root,
+        BuildSettings::with_identity(&identity.os_name, &identity.os_id, &identity.iso_label),
+        &variant_toml,
+    )
+    .with_context(|| format!("loading layered build configuration for '{distro_id}'"))
+}
+
+pub(crate) fn iso_filename_for_product(base_iso_filename: &str, product: BuildProduct) -> String {
+    match product.canonical {
+        crate::cli::PRODUCT_BASE_ROOTFS => base_iso_filename.to_string(),
+        crate::cli::PRODUCT_LIVE_BOOT | crate::cli::PRODUCT_LIVE_TOOLS => {
+            derive_product_iso_filename(base_iso_filename, product.iso_suffix)
+        }
+        _ => unreachable!("validated in parse_product"),
+    }
+}
+
+pub(crate) fn derive_product_iso_filename(base_iso_filename: &str, product_suffix: &str) -> String {
+    if let Some(base) = base_iso_filename.strip_suffix(".iso") {
+        return format!("{base}-{product_suffix}.iso");
+    }
+    format!("{base_iso_filename}-{product_suffix}.iso")
+}
+
+pub(crate) fn product_release_output_layout_for(
+    repo_root: &Path,
+    distro_id: &str,
+    product: BuildProduct,
+) -> Result<BuildOutputLayout> {
+    let root_dir = crate::cli::artifact_paths::release_product_dir_for(
+        repo_root,
+        distro_id,
+        product.release_dir_name,
+    )?;
+    std::fs::create_dir_all(&root_dir).with_context(|| {
+        format!(
+            "creating product release root directory '{}'",
+            root_dir.display()
+        )
+    })?;
+    let (run_id, run_root) = allocate_run_dir(&root_dir)?;
+
+    Ok(BuildOutputLayout {
+        root_dir,
+        output_dir: run_root,
+        run_id: Some(run_id),
+    })
+}
+
+pub(crate) fn now_utc_compact() -> Result<String> {
+    let now = OffsetDateTime::now_utc();
+    Ok(format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn product_iso_filename_is_product_native() {
+        let product = crate::cli::workflows::parse_product(Some(crate::cli::PRODUCT_LIVE_BOOT))
+            .expect("parse live-boot");
+        assert_eq!(
+            derive_product_iso_filename("levitateos-x86_64.iso", product.iso_suffix),
+            "levitateos-x86_64-live-boot.iso"
+        );
+    }
+
+    #[test]
+    fn base_rootfs_iso_filename_stays_base_name() {
+        let product = crate::cli::workflows::parse_product(Some(crate::cli::PRODUCT_BASE_ROOTFS))
+            .expect("parse base-rootfs");
+        assert_eq!(
+            iso_filename_for_product("levitateos-x86_64.iso", product),
+            "levitateos-x86_64.iso"
+        );
+    }
+
+    #[test]
+    fn product_release_output_layout_uses_release_root() {
+        let repo_root = tempfile::tempdir().expect("repo tempdir");
+        let product = crate::cli::workflows::parse_product(Some(crate::cli::PRODUCT_LIVE_TOOLS))
+            .expect("parse live-tools");
+        let layout = product_release_output_layout_for(repo_root.path(), "levitate", product)
+            .expect("allocate product release layout");
+        assert!(
+            layout.root_dir.ends_with("levitate/releases/live-tools"),
+            "unexpected release root '{}'",
+            layout.root_dir.display()
+        );
+        assert!(
+            layout.output_dir.starts_with(&layout.root_dir),
+            "run dir '{}' must live under release root '{}'",
+            layout.output_dir.display(),
+            layout.root_dir.display()
+        );
+    }
+}
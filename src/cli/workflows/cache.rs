@@ -0,0 +1,212 @@
+use anyhow::{bail, Context, Result};
+use distro_contract::{load_variant_contract_bundle_for_distro_from, require_valid_contract};
+use std::path::{Path, PathBuf};
+
+use crate::artifact::rootfs::format_size_human;
+use crate::artifact_store::{ArtifactStore, SyncConflict, SyncFilter};
+use crate::build_host::{
+    acquire_kernel_source_via_recipe, check_kernel_preinstalled_via_recipe, BuildHostKernelSpec,
+};
+use crate::error::{ErrorKind, ResultExt};
+use crate::recipe::alpine_rootfs_source::preseed_alpine_rootfs_source_assets;
+use crate::recipe::rootfs_source::preseed_rootfs_source_dvd;
+use crate::workspace::{dir_size_bytes, DOWNLOADS_NAMESPACE};
+use crate::{FetchRequest, Fetcher, WorkspaceManager};
+
+/// `cache warm <distro_id>`: fetch every remote build input without building.
+///
+/// Covers the rootfs source (Fedora DVD or Alpine ISO plus apk-tools, which
+/// also carries the Alpine packages) and the kernel source tarball. Inputs
+/// already present and verified are left alone, so CI can run this
+/// unconditionally before an offline or time-boxed build.
+pub(crate) fn cache_warm_cmd(repo_root: &Path, distro_id: &str) -> Result<()> {
+    let bundle = load_variant_contract_bundle_for_distro_from(repo_root, distro_id)
+        .with_context(|| format!("loading variant contract for '{distro_id}'"))
+        .error_kind(ErrorKind::InvalidContract)?;
+    require_valid_contract(&bundle.contract)
+        .with_context(|| format!("validating variant contract for '{distro_id}'"))
+        .error_kind(ErrorKind::InvalidContract)?;
+
+    let mut warmed: Vec<(&str, PathBuf)> = Vec::new();
+
+    println!("[cache:warm:{distro_id}] rootfs source");
+    let live_boot_spec = crate::cli::workflows::canonical_live_boot_product_spec(
+        &bundle, distro_id,
+    )
+    .with_context(|| format!("loading canonical rootfs source policy for '{}'", distro_id))?;
+    if let Some(preseed_recipe_script) = live_boot_spec.rpm_dvd_preseed_recipe_script() {
+        let iso_path =
+            preseed_rootfs_source_dvd(&bundle.repo_root, distro_id, preseed_recipe_script, false)
+                .with_context(|| format!("warming rootfs source for '{}'", distro_id))?;
+        warmed.push(("rootfs source ISO", iso_path));
+    } else if live_boot_spec.uses_alpine_live_source_rootfs() {
+        let output = preseed_alpine_rootfs_source_assets(&bundle.repo_root, distro_id, false)
+            .with_context(|| format!("warming rootfs source for '{}'", distro_id))?;
+        warmed.push(("rootfs source ISO", output.iso_path));
+        warmed.push(("apk-tools", output.apk_tools_path));
+    } else {
+        println!("  rootfs source has no remote inputs; skipping");
+    }
+
+    println!("[cache:warm:{distro_id}] kernel source");
+    let downloads = WorkspaceManager::new(&bundle.repo_root)?
+        .persistent_dir(distro_id, DOWNLOADS_NAMESPACE)
+        .with_context(|| format!("resolving download cache for '{}'", distro_id))?;
+    let kernel_output_dir =
+        crate::cli::artifact_paths::kernel_output_dir_for(&bundle.repo_root, distro_id)?;
+    let kernel_spec = BuildHostKernelSpec {
+        recipe_kernel_script: bundle.contract.build.kernel.recipe_script.clone(),
+        kernel_kconfig_path: bundle.contract.build.kernel.kconfig_path.clone(),
+        share_key: None,
+        external_modules: Default::default(),
+        required_modules: Vec::new(),
+        forbidden_modules: Vec::new(),
+    };
+    let kernel_installed = check_kernel_preinstalled_via_recipe(
+        &bundle.repo_root,
+        &bundle.paths,
+        distro_id,
+        &kernel_output_dir,
+        &kernel_spec,
+    )
+    .is_ok();
+    if kernel_installed {
+        println!("  kernel already installed; source tarball not needed");
+    } else {
+        acquire_kernel_source_via_recipe(
+            &bundle.repo_root,
+            &bundle.paths,
+            distro_id,
+            &kernel_output_dir,
+            &kernel_spec,
+        )
+        .with_context(|| format!("warming kernel source for '{}'", distro_id))?;
+        warmed.push(("kernel source", downloads.clone()));
+    }
+
+    println!("download cache warm for {}:", distro_id);
+    for (label, path) in &warmed {
+        println!("  {:<18} {}", format!("{}:", label), path.display());
+    }
+    println!(
+        "  {:<18} {}",
+        "cache size:",
+        format_size_human(dir_size_bytes(&downloads)?)
+    );
+    Ok(())
+}
+
+/// `cache fetch <distro_id> <file_name> <url>... [--sha256 <hex>]`: fetch one
+/// file into the distro's download cache and print its path.
+///
+/// Gives recipes the same resumable, checksum-verified, torrent-capable
+/// fetching the builder uses, so their inputs share one cache.
+pub(crate) fn cache_fetch_cmd(distro_id: &str, args: &[String]) -> Result<()> {
+    let mut request = None;
+    let mut urls = Vec::new();
+    let mut sha256 = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--sha256" => match iter.next() {
+                Some(hex) => sha256 = Some(hex.clone()),
+                None => bail!("--sha256 requires a value"),
+            },
+            flag if flag.starts_with("--") => bail!("unknown cache fetch option '{}'", flag),
+            name if request.is_none() => request = Some(FetchRequest::new(name)),
+            url => urls.push(url),
+        }
+    }
+    let Some(request) = request else {
+        return Err(crate::cli::usage_error());
+    };
+    if urls.is_empty() {
+        bail!(
+            "no source URL given for '{}'\nRemediation: pass one or more URLs after the file name",
+            request.file_name()
+        );
+    }
+    let mut request = request.sources(urls);
+    if let Some(sha256) = sha256 {
+        request = request.sha256(sha256);
+    }
+
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    let path = Fetcher::for_distro(&repo_root, distro_id)
+        .with_context(|| format!("resolving download cache for '{}'", distro_id))?
+        .fetch(&request)?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+/// `cache sync <store_root> [--kind <kind>]... [--keep-last <n>]
+/// [--prefer newer|remote] [--dry-run]`: copy what another artifact store
+/// (e.g. a workstation's, over a LAN mount) has and this checkout's lacks.
+pub(crate) fn cache_sync_cmd(args: &[String]) -> Result<()> {
+    let mut source = None;
+    let mut kinds = Vec::new();
+    let mut filter = SyncFilter::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--kind" => match iter.next() {
+                Some(kind) => kinds.push(kind.clone()),
+                None => bail!("--kind requires a value"),
+            },
+            "--keep-last" => match iter.next().map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => filter.keep_last = Some(n),
+                _ => bail!("--keep-last requires a positive number"),
+            },
+            "--prefer" => {
+                filter.conflict = match iter.next().map(String::as_str) {
+                    Some("newer") => SyncConflict::PreferNewer,
+                    Some("remote") => SyncConflict::PreferRemote,
+                    _ => bail!("--prefer requires 'newer' or 'remote'"),
+                }
+            }
+            "--dry-run" => filter.dry_run = true,
+            flag if flag.starts_with("--") => bail!("unknown cache sync option '{}'", flag),
+            path if source.is_none() => source = Some(PathBuf::from(path)),
+            _ => return Err(crate::cli::usage_error()),
+        }
+    }
+    let Some(source) = source else {
+        return Err(crate::cli::usage_error());
+    };
+
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    let store = ArtifactStore::open(&repo_root)?;
+    let other = ArtifactStore::open_existing(&source, store.compression())
+        .error_kind(ErrorKind::InvalidConfig)?;
+    let report = store.sync_from(&other, &kinds, &filter)?;
+
+    let verb = if filter.dry_run {
+        "would copy"
+    } else {
+        "copied"
+    };
+    println!(
+        "{} from {}: {} new and {} replaced entries, {} blobs ({}), {} tags",
+        verb,
+        other.root().display(),
+        report.added_entries,
+        report.replaced_entries,
+        report.copied_blobs,
+        format_size_human(report.copied_bytes),
+        report.copied_tags
+    );
+    for conflict in &report.conflicts {
+        println!(
+            "  kept local {} (pass --prefer newer|remote to replace)",
+            conflict
+        );
+    }
+    for missing in &report.missing_blobs {
+        eprintln!(
+            "  [WARN] {} has no blob in {}",
+            missing,
+            other.root().display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::artifact::rootfs::format_size_human;
+use crate::artifact_store::ArtifactStore;
+use crate::repo_config::artifacts_root;
+use crate::workspace::{dir_size_bytes, PERSISTENT_NAMESPACES};
+use crate::WorkspaceManager;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CleanTarget {
+    Stage { distro_id: String, product: String },
+    Kernel,
+    Downloads,
+    Work,
+    StoreUnreferenced,
+}
+
+fn parse_clean_targets(args: &[String]) -> Result<Vec<CleanTarget>> {
+    let mut targets = Vec::new();
+    let mut rest = args;
+    while let Some((flag, tail)) = rest.split_first() {
+        rest = tail;
+        let target = match flag.as_str() {
+            "--stage" => match rest {
+                [distro_id, product, tail @ ..] => {
+                    rest = tail;
+                    CleanTarget::Stage {
+                        distro_id: distro_id.clone(),
+                        product: product.clone(),
+                    }
+                }
+                _ => bail!("`clean --stage` expects `<distro_id> <product>`"),
+            },
+            "--kernel" => CleanTarget::Kernel,
+            "--downloads" => CleanTarget::Downloads,
+            "--work" => CleanTarget::Work,
+            "--store-unreferenced" => CleanTarget::StoreUnreferenced,
+            other => bail!(
+                "unsupported clean target '{}'; expected --stage <distro_id> <product>, --kernel, --downloads, --work, or --store-unreferenced",
+                other
+            ),
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    if targets.is_empty() {
+        return Err(crate::cli::usage_error());
+    }
+    Ok(targets)
+}
+
+pub(crate) fn clean_cmd(args: &[String]) -> Result<()> {
+    let targets = parse_clean_targets(args)?;
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+
+    let mut total = 0u64;
+    for target in &targets {
+        let freed = clean_target(&repo_root, target)?;
+        total += freed;
+    }
+    if targets.len() > 1 {
+        println!("Total freed: {}", format_size_human(total));
+    }
+    Ok(())
+}
+
+fn clean_target(repo_root: &Path, target: &CleanTarget) -> Result<u64> {
+    let workspace = WorkspaceManager::new(repo_root)?;
+    let freed = match target {
+        CleanTarget::Stage { distro_id, product } => {
+            let known_distros = crate::cli::workflows::discover_distro_ids(repo_root)?;
+            let distro_id = crate::cli::workflows::parse_distro_id(distro_id, &known_distros)?;
+            let product = crate::cli::workflows::parse_product(Some(product))?;
+            let dir = crate::cli::artifact_paths::release_product_dir_for(
+                repo_root,
+                &distro_id,
+                product.release_dir_name,
+            )?;
+            let freed = remove_tree(&dir)?;
+            println!(
+                "  clean {} {}: removed {} ({})",
+                distro_id,
+                product.canonical,
+                dir.display(),
+                format_size_human(freed)
+            );
+            freed
+        }
+        CleanTarget::Kernel => {
+            let dir = artifacts_root(repo_root)?.join("kernel");
+            let freed = remove_tree(&dir)?;
+            println!(
+                "  clean --kernel: removed {} ({})",
+                dir.display(),
+                format_size_human(freed)
+            );
+            freed
+        }
+        CleanTarget::Downloads => {
+            let freed = workspace.clean_downloads()?;
+            println!(
+                "  clean --downloads: freed {} under {}",
+                format_size_human(freed),
+                workspace.root().display()
+            );
+            freed
+        }
+        CleanTarget::Work => {
+            for entry in workspace.usage()? {
+                println!(
+                    "  {}/{}: {}",
+                    entry.distro_id,
+                    entry.namespace,
+                    format_size_human(entry.bytes)
+                );
+            }
+            let freed = workspace.clean_work()?;
+            println!(
+                "  clean --work: freed {} under {} ({} kept)",
+                format_size_human(freed),
+                workspace.root().display(),
+                PERSISTENT_NAMESPACES.join(" and ")
+            );
+            freed
+        }
+        CleanTarget::StoreUnreferenced => {
+            let store = ArtifactStore::open(repo_root)?;
+            let report = store.gc_report()?;
+            println!(
+                "  clean --store-unreferenced: removed {} blob(s), freed {}",
+                report.removed_blobs,
+                format_size_human(report.freed_bytes)
+            );
+            report.freed_bytes
+        }
+    };
+    Ok(freed)
+}
+
+fn remove_tree(dir: &Path) -> Result<u64> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let bytes = dir_size_bytes(dir)?;
+    fs::remove_dir_all(dir).with_context(|| format!("removing '{}'", dir.display()))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_combined_targets() {
+        let targets = parse_clean_targets(&args(&[
+            "--work",
+            "--stage",
+            "acorn",
+            "live-boot",
+            "--work",
+        ]))
+        .expect("parse clean targets");
+        assert_eq!(
+            targets,
+            vec![
+                CleanTarget::Work,
+                CleanTarget::Stage {
+                    distro_id: "acorn".into(),
+                    product: "live-boot".into()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_stage() {
+        assert!(parse_clean_targets(&args(&["--stage", "acorn"])).is_err());
+        assert!(parse_clean_targets(&args(&["--everything"])).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::build::context::ENV_OVERRIDE_PREFIX;
+use crate::Features;
+
+use crate::cli::workflows::ReleaseBuildOptions;
+
+/// Flag that skips the post-build boot smoke test.
+const NO_TEST_FLAG: &str = "--no-test";
+
+/// Flag enabling one feature flag for the build; repeatable.
+const FEATURE_FLAG: &str = "--feature";
+
+/// Strip release build flags from `args`.
+fn split_release_options(args: &[String]) -> (Vec<String>, ReleaseBuildOptions) {
+    let (args, _) = split_feature_flags(args);
+    let smoke_test = !args.iter().any(|arg| arg == NO_TEST_FLAG);
+    let args = args.into_iter().filter(|arg| arg != NO_TEST_FLAG).collect();
+    (args, ReleaseBuildOptions { smoke_test })
+}
+
+/// Strip `--feature <name>` pairs from `args`, returning the names.
+fn split_feature_flags(args: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut rest = Vec::new();
+    let mut features = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == FEATURE_FLAG {
+            if let Some(name) = iter.next() {
+                features.push(name.clone());
+                continue;
+            }
+        }
+        rest.push(arg.clone());
+    }
+    (rest, features)
+}
+
+/// Export `--feature` flags as the `features` build setting override, so
+/// variant hooks and `product prepare` subprocesses see them too. They
+/// replace the variant's own `features` list.
+fn export_feature_flags(args: &[String]) -> Result<()> {
+    let (_, names) = split_feature_flags(args);
+    if names.is_empty() {
+        return Ok(());
+    }
+    let features = Features::parse(&names).context("parsing --feature flags")?;
+    std::env::set_var(
+        format!("{}FEATURES", ENV_OVERRIDE_PREFIX),
+        features.to_string(),
+    );
+    println!("[release] feature flags: {}", features);
+    Ok(())
+}
+
+/// Checkout the release, prepare and warm commands load the variant
+/// contract from.
+fn current_dir() -> Result<PathBuf> {
+    std::env::current_dir().context("resolving current directory")
+}
+
+pub(crate) fn is_release_build_invocation(args: &[String]) -> bool {
+    let (args, _) = split_release_options(args);
+    let args = args.as_slice();
+    matches!(
+        args,
+        [release, build, iso]
+            | [release, build, iso, _]
+            | [release, build, iso, _, _]
+                if release == "release" && build == "build" && iso == "iso"
+    ) || matches!(
+        args,
+        [iso, build] | [iso, build, _] | [iso, build, _, _] if iso == "iso" && build == "build"
+    )
+}
+
+pub(crate) fn run_release_build_command(args: &[String]) -> Result<()> {
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    export_feature_flags(args)?;
+    let (args, options) = split_release_options(args);
+    let build_args: Vec<&String> = match args.as_slice() {
+        [release, build, iso] if release == "release" && build == "build" && iso == "iso" => {
+            vec![]
+        }
+        [release, build, iso, arg1] if release == "release" && build == "build" && iso == "iso" => {
+            vec![arg1]
+        }
+        [release, build, iso, arg1, arg2]
+            if release == "release" && build == "build" && iso == "iso" =>
+        {
+            vec![arg1, arg2]
+        }
+        [iso, build] if iso == "iso" && build == "build" => vec![],
+        [iso, build, arg1] if iso == "iso" && build == "build" => vec![arg1],
+        [iso, build, arg1, arg2] if iso == "iso" && build == "build" => vec![arg1, arg2],
+        _ => return Err(crate::cli::usage_error()),
+    };
+
+    let (distro_id, product) =
+        crate::cli::workflows::parse_release_build_command(build_args, &repo_root)?;
+    crate::cli::workflows::enforce_legacy_binding_policy_guard(&repo_root)?;
+    crate::cli::workflows::ensure_release_prerequisites(&repo_root, &distro_id, product, options)?;
+    crate::cli::workflows::build_one(&current_dir()?, &distro_id, product, options)
+}
+
+pub(crate) fn dispatch_non_release_command(args: &[String]) -> Result<()> {
+    let (args, release_options) = if args.get(1).is_some_and(|arg| arg == "build-all") {
+        export_feature_flags(args)?;
+        split_release_options(args)
+    } else {
+        (args.to_vec(), ReleaseBuildOptions::default())
+    };
+    let command = match args.as_slice() {
+        [release, build_all_cmd, iso]
+            if release == "release" && build_all_cmd == "build-all" && iso == "iso" =>
+        {
+            crate::cli::workflows::build_all(
+                crate::cli::workflows::parse_release_product(None)?,
+                release_options,
+            )
+        }
+        [release, build_all_cmd, iso, product]
+            if release == "release" && build_all_cmd == "build-all" && iso == "iso" =>
+        {
+            crate::cli::workflows::build_all(
+                crate::cli::workflows::parse_release_product(Some(product))?,
+                release_options,
+            )
+        }
+        [iso, build_all_cmd] if iso == "iso" && build_all_cmd == "build-all" => {
+            crate::cli::workflows::build_all(
+                crate::cli::workflows::parse_release_product(None)?,
+                release_options,
+            )
+        }
+        [iso, build_all_cmd, product] if iso == "iso" && build_all_cmd == "build-all" => {
+            crate::cli::workflows::build_all(
+                crate::cli::workflows::parse_release_product(Some(product))?,
+                release_options,
+            )
+        }
+        [product, prepare, product_name, distro, output_dir]
+            if product == "product" && prepare == "prepare" =>
+        {
+            crate::cli::workflows::prepare_product_cmd(
+                &current_dir()?,
+                product_name,
+                distro,
+                Path::new(output_dir),
+            )
+        }
+        [transform, build, rootfs, source_dir, output]
+            if transform == "transform" && build == "build" && rootfs == "rootfs-erofs" =>
+        {
+            crate::cli::workflows::build_rootfs_erofs(Path::new(source_dir), Path::new(output))
+        }
+        [transform, build, overlay, source_dir, output]
+            if transform == "transform" && build == "build" && overlay == "overlayfs-erofs" =>
+        {
+            crate::cli::workflows::build_overlayfs_erofs(Path::new(source_dir), Path::new(output))
+        }
+        [transform, build, installer, payload_dir, output]
+            if transform == "transform"
+                && build == "build"
+                && installer == "installer-squashfs" =>
+        {
+            crate::cli::workflows::build_installer_payload_squashfs(
+                Path::new(payload_dir),
+                Path::new(output),
+            )
+        }
+        [transform, build, usb, iso, output, options @ ..]
+            if transform == "transform" && build == "build" && usb == "usb-image" =>
+        {
+            crate::cli::workflows::build_usb_image_cmd(Path::new(iso), Path::new(output), options)
+        }
+        [transform, build, product_erofs, prepared_dir]
+            if transform == "transform" && build == "build" && product_erofs == "product-erofs" =>
+        {
+            crate::cli::workflows::build_prepared_product_erofs_cmd(Path::new(prepared_dir))
+        }
+        [artifact, build_rootfs, source_dir, output]
+            if artifact == "artifact" && build_rootfs == "build-rootfs-erofs" =>
+        {
+            crate::cli::workflows::build_rootfs_erofs(Path::new(source_dir), Path::new(output))
+        }
+        [artifact, build_overlay, source_dir, output]
+            if artifact == "artifact" && build_overlay == "build-overlayfs-erofs" =>
+        {
+            crate::cli::workflows::build_overlayfs_erofs(Path::new(source_dir), Path::new(output))
+        }
+        [artifact, preseed_stage01, distro]
+            if artifact == "artifact" && preseed_stage01 == "preseed-rootfs-source" =>
+        {
+            crate::cli::workflows::preseed_rootfs_source_cmd(distro, false)
+        }
+        [artifact, preseed_stage01, distro, refresh]
+            if artifact == "artifact"
+                && preseed_stage01 == "preseed-rootfs-source"
+                && refresh == "--refresh" =>
+        {
+            crate::cli::workflows::preseed_rootfs_source_cmd(distro, true)
+        }
+        [artifact, installer, distro, tool_dir, output_dir, live_overlay]
+            if artifact == "artifact" && installer == "build-installer" =>
+        {
+            crate::cli::workflows::build_installer_cmd(
+                &crate::cli::workflows::locate_repo_root()?,
+                distro,
+                Path::new(tool_dir),
+                Path::new(output_dir),
+                Path::new(live_overlay),
+            )
+        }
+        [artifact, check, distro, initramfs]
+            if artifact == "artifact" && check == "check-initramfs" =>
+        {
+            crate::cli::workflows::check_initramfs_cmd(distro, Path::new(initramfs))
+        }
+        [artifact, netboot, distro, initramfs_root]
+            if artifact == "artifact" && netboot == "netboot-init" =>
+        {
+            crate::cli::workflows::netboot_init_cmd(distro, Path::new(initramfs_root))
+        }
+        [artifact, writable_layer, distro, initramfs_root]
+            if artifact == "artifact" && writable_layer == "writable-layer-init" =>
+        {
+            crate::cli::workflows::writable_layer_init_cmd(distro, Path::new(initramfs_root))
+        }
+        [artifact, build_ukis, distro, kernel, initramfs, esp_dir, rest @ ..]
+            if artifact == "artifact" && build_ukis == "build-ukis" =>
+        {
+            crate::cli::workflows::build_ukis_cmd(
+                distro,
+                Path::new(kernel),
+                Path::new(initramfs),
+                Path::new(esp_dir),
+                rest,
+            )
+        }
+        [artifact, materialize_stage01, distro]
+            if artifact == "artifact" && materialize_stage01 == "materialize-rootfs-source" =>
+        {
+            crate::cli::workflows::materialize_rootfs_source_cmd(distro)
+        }
+        [new_variant, rest @ ..] if new_variant == "new-variant" => {
+            crate::cli::workflows::new_variant_cmd(rest)
+        }
+        [cache, warm, distro] if cache == "cache" && warm == "warm" => {
+            crate::cli::workflows::cache_warm_cmd(&current_dir()?, distro)
+        }
+        [cache, fetch, distro, rest @ ..] if cache == "cache" && fetch == "fetch" => {
+            crate::cli::workflows::cache_fetch_cmd(distro, rest)
+        }
+        [cache, sync, rest @ ..] if cache == "cache" && sync == "sync" => {
+            crate::cli::workflows::cache_sync_cmd(rest)
+        }
+        [clean, targets @ ..] if clean == "clean" => crate::cli::workflows::clean_cmd(targets),
+        [remote, host, rest @ ..] if remote == "remote" => {
+            crate::cli::workflows::remote_cmd(host, rest)
+        }
+        _ => Err(crate::cli::usage_error()),
+    };
+    command.with_context(|| format!("dispatching workflow for '{}'", args.join(" ")))
+}
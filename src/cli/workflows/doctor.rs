@@ -0,0 +1,312 @@
+use anyhow::{anyhow, bail, Result};
+use distro_contract::{load_variant_contract_bundle_for_distro_from, require_valid_contract};
+use std::path::Path;
+
+use crate::artifact::boot_menu::BootMenuConfig;
+use crate::artifact::initramfs_check::InitramfsContract;
+use crate::artifact::netboot::NetbootFeatures;
+use crate::artifact::overlay_conflicts::ConflictPolicy;
+use crate::boot_log::BootLogPolicy;
+use crate::build::accessibility::Accessibility;
+use crate::build::consoles::SerialConsoles;
+use crate::build::kernel_headers::KernelHeaders;
+use crate::build::locales::LocalePolicy;
+use crate::build::package_manager::PackageManagerBootstrap;
+use crate::build::selinux::SelinuxRelabel;
+use crate::build::services::ServicePolicy;
+use crate::build::splash::SplashConfig;
+use crate::build::swap::LiveSwap;
+use crate::build::timesync::TimeSync;
+use crate::build::writable_layer::WritableLayer;
+use crate::doctor::{
+    check_qemu, check_repo_layout, check_store, check_tools, DoctorReport, Finding, Severity,
+};
+use crate::error::{Error, ErrorKind};
+use crate::preflight::{iso_pipeline_tools, RequiredTools, VariantHostTools};
+use crate::pstore::Pstore;
+use crate::repo_config::RepoConfig;
+use crate::repo_layout::layout_or_default;
+use crate::smoke_test::SmokeTestConfig;
+
+/// Rehash every store blob instead of checking sizes only.
+const DEEP_FLAG: &str = "--deep";
+
+/// Parsed `doctor` arguments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct DoctorOptions {
+    /// Variants to lint; every variant when empty.
+    distro_ids: Vec<String>,
+    deep: bool,
+}
+
+fn parse_doctor_args(args: &[String]) -> Result<DoctorOptions> {
+    let mut options = DoctorOptions::default();
+    for arg in args {
+        match arg.as_str() {
+            DEEP_FLAG => options.deep = true,
+            flag if flag.starts_with("--") => bail!("unknown doctor option '{}'", flag),
+            distro_id => options.distro_ids.push(distro_id.to_string()),
+        }
+    }
+    Ok(options)
+}
+
+/// `doctor [<distro_id>...] [--deep]`: run every environment check and
+/// print the problems as prioritized remediation steps.
+///
+/// Covers the checkout layout, the host tools of the ISO pipeline and of
+/// each variant's release, each variant's contract and variant-local
+/// config files, the legacy-binding policy, the artifact store and QEMU.
+/// Fails with the exit code of the most severe blocker.
+pub(crate) fn doctor_cmd(args: &[String]) -> Result<()> {
+    let options = parse_doctor_args(args)?;
+    let mut report = DoctorReport::new();
+
+    let repo_root = match crate::cli::workflows::locate_repo_root() {
+        Ok(repo_root) => repo_root,
+        Err(err) => {
+            report.add(Finding::blocker(
+                "repo layout",
+                ErrorKind::InvalidConfig,
+                format!("{:#}", err),
+                "run from a distro-builder checkout, or set DISTRO_BUILDER_REPO_ROOT",
+            ));
+            return finish(&report);
+        }
+    };
+    println!("distro-builder doctor: {}\n", repo_root.display());
+
+    check_repo_layout(&mut report, &repo_root);
+    let distro_ids = if options.distro_ids.is_empty() {
+        crate::cli::workflows::discover_distro_ids(&repo_root).unwrap_or_default()
+    } else {
+        options.distro_ids.clone()
+    };
+
+    let compression = RepoConfig::load_or_default(&repo_root).compression;
+    let mut profiles = vec![(
+        "iso-pipeline".to_string(),
+        iso_pipeline_tools(compression.initramfs),
+    )];
+    for distro_id in &distro_ids {
+        if let Some(tools) = lint_variant(&mut report, &repo_root, distro_id) {
+            profiles.push((format!("{} release", distro_id), tools));
+        }
+    }
+    check_tools(&mut report, &profiles);
+    check_policy(&mut report, &repo_root);
+    check_store(&mut report, &repo_root, options.deep);
+    check_qemu(&mut report);
+    finish(&report)
+}
+
+fn finish(report: &DoctorReport) -> Result<()> {
+    print!("{}", report.render());
+    match report.blocker_kind() {
+        Some(kind) => Err(Error::new(
+            kind,
+            anyhow!(
+                "doctor found {} blocker(s); fix them before building",
+                report.count(Severity::Blocker)
+            ),
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Lint one variant: its contract and every variant-local config file.
+/// Returns the host tools its release registers when the contract loads.
+fn lint_variant(
+    report: &mut DoctorReport,
+    repo_root: &Path,
+    distro_id: &str,
+) -> Option<RequiredTools> {
+    const CHECK: &str = "variant contract";
+    let bundle = match load_variant_contract_bundle_for_distro_from(repo_root, distro_id) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            report.add(Finding::blocker(
+                CHECK,
+                ErrorKind::InvalidContract,
+                format!("{}: {:#}", distro_id, err),
+                format!("fix distro-variants/{}/ so its contract loads", distro_id),
+            ));
+            return None;
+        }
+    };
+    let before = report.findings().len();
+    if let Err(err) = require_valid_contract(&bundle.contract) {
+        report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::InvalidContract,
+            format!("{}: {:#}", distro_id, err),
+            format!("fix the contract of distro-variants/{}/", distro_id),
+        ));
+    }
+
+    let variant_dir = layout_or_default(&bundle.repo_root).variant_dir(distro_id);
+    for (file, err) in lint_variant_configs(&variant_dir) {
+        report.add(Finding::blocker(
+            "variant config",
+            ErrorKind::InvalidConfig,
+            format!("{}/{}: {:#}", distro_id, file, err),
+            format!("fix or remove distro-variants/{}/{}", distro_id, file),
+        ));
+    }
+    if report.findings().len() == before {
+        report.pass(format!(
+            "variant '{}' (contract and config files)",
+            distro_id
+        ));
+    }
+
+    let product = crate::cli::workflows::parse_release_product(None).ok()?;
+    crate::cli::workflows::release_required_tools(
+        &bundle,
+        distro_id,
+        product,
+        crate::cli::workflows::ReleaseBuildOptions::default(),
+    )
+    .ok()
+}
+
+/// Variant-local config files that exist but fail to load.
+fn lint_variant_configs(variant_dir: &Path) -> Vec<(&'static str, anyhow::Error)> {
+    use crate::artifact::{boot_menu, initramfs_check, netboot, overlay_conflicts};
+    use crate::build::{
+        accessibility, consoles, kernel_headers, locales, package_manager, selinux, services,
+        splash, swap, timesync, writable_layer,
+    };
+
+    let results = [
+        (
+            crate::preflight::HOST_TOOLS_FILENAME,
+            VariantHostTools::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            crate::boot_log::BOOT_LOG_POLICY_FILENAME,
+            BootLogPolicy::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            crate::smoke_test::SMOKE_TEST_CONFIG_FILENAME,
+            SmokeTestConfig::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            crate::pstore::PSTORE_CONFIG_FILENAME,
+            Pstore::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            services::SERVICES_CONFIG_FILENAME,
+            ServicePolicy::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            timesync::TIMESYNC_CONFIG_FILENAME,
+            TimeSync::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            accessibility::ACCESSIBILITY_CONFIG_FILENAME,
+            Accessibility::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            locales::LOCALE_CONFIG_FILENAME,
+            LocalePolicy::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            selinux::SELINUX_CONFIG_FILENAME,
+            SelinuxRelabel::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            consoles::CONSOLES_CONFIG_FILENAME,
+            SerialConsoles::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            kernel_headers::KERNEL_HEADERS_FILENAME,
+            KernelHeaders::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            writable_layer::WRITABLE_LAYER_FILENAME,
+            WritableLayer::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            package_manager::PACKAGE_MANAGER_CONFIG_FILENAME,
+            PackageManagerBootstrap::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            splash::SPLASH_CONFIG_FILENAME,
+            SplashConfig::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            swap::SWAP_CONFIG_FILENAME,
+            LiveSwap::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            initramfs_check::INITRAMFS_CONTRACT_FILENAME,
+            InitramfsContract::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            overlay_conflicts::OVERLAY_CONFLICTS_FILENAME,
+            ConflictPolicy::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            boot_menu::BOOT_MENU_FILENAME,
+            BootMenuConfig::load_for_variant(variant_dir).map(drop),
+        ),
+        (
+            netboot::NETBOOT_CONFIG_FILENAME,
+            NetbootFeatures::load_for_variant(variant_dir).map(drop),
+        ),
+    ];
+    results
+        .into_iter()
+        .filter_map(|(file, result)| result.err().map(|err| (file, err)))
+        .collect()
+}
+
+fn check_policy(report: &mut DoctorReport, repo_root: &Path) {
+    const CHECK: &str = "legacy-binding policy";
+    match crate::policy::audit_legacy_bindings(repo_root) {
+        Ok(violations) if violations.is_empty() => report.pass(CHECK),
+        Ok(violations) => report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::PolicyViolation,
+            format!(
+                "{} violation(s), first: {}",
+                violations.len(),
+                violations[0]
+            ),
+            "run `distro-builder policy audit-legacy-bindings` and fix every violation",
+        )),
+        Err(err) => report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::PolicyViolation,
+            format!("{:#}", err),
+            "run `distro-builder policy audit-legacy-bindings` for details",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_doctor_args() {
+        assert_eq!(
+            parse_doctor_args(&args(&[])).unwrap(),
+            DoctorOptions::default()
+        );
+        assert_eq!(
+            parse_doctor_args(&args(&["levitate", "--deep", "acorn"])).unwrap(),
+            DoctorOptions {
+                distro_ids: vec!["levitate".to_string(), "acorn".to_string()],
+                deep: true,
+            }
+        );
+        assert!(parse_doctor_args(&args(&["--fast"])).is_err());
+    }
+}
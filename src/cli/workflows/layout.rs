@@ -0,0 +1,17 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+use crate::RepoLayout;
+
+/// Checkout this binary builds from: `DISTRO_BUILDER_REPO_ROOT` when set
+/// (embedders), otherwise the checkout it was compiled in.
+pub(crate) fn locate_repo_root() -> Result<PathBuf> {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let layout = RepoLayout::discover(&manifest_dir).with_context(|| {
+        format!(
+            "unable to locate repository root from '{}'",
+            manifest_dir.display()
+        )
+    })?;
+    Ok(layout.root().to_path_buf())
+}
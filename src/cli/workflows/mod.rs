@@ -0,0 +1,42 @@
+mod artifacts;
+mod build;
+mod cache;
+mod clean;
+mod commands;
+mod doctor;
+mod layout;
+mod parse;
+mod prepared_products;
+mod release_hook;
+mod remote;
+mod scaffold;
+
+pub(crate) use artifacts::{
+    build_installer_cmd, build_installer_payload_squashfs, build_overlayfs_erofs,
+    build_prepared_product_erofs_cmd, build_rootfs_erofs, build_ukis_cmd, build_usb_image_cmd,
+    canonical_live_boot_product_spec, check_initramfs_cmd, check_initramfs_for_distro,
+    contract_branding, materialize_rootfs_source_cmd, netboot_init_cmd, prepare_product_cmd,
+    preseed_rootfs_source_cmd, writable_layer_init_cmd,
+};
+pub(crate) use build::{
+    build_all, build_one, enforce_legacy_binding_policy_guard, ensure_release_prerequisites,
+    policy_audit_cmd, release_required_tools, ReleaseBuildOptions,
+};
+pub(crate) use cache::{cache_fetch_cmd, cache_sync_cmd, cache_warm_cmd};
+pub(crate) use clean::clean_cmd;
+pub(crate) use commands::{
+    dispatch_non_release_command, is_release_build_invocation, run_release_build_command,
+};
+pub(crate) use doctor::doctor_cmd;
+pub(crate) use layout::locate_repo_root;
+pub(crate) use parse::{
+    discover_distro_ids, parse_distro_id, parse_product, parse_release_build_command,
+    parse_release_product, product_for_logical_name,
+};
+pub(crate) use prepared_products::{
+    canonical_initramfs_live_filename, canonical_iso_filename, canonical_overlay_erofs_filename,
+    canonical_rootfs_erofs_filename,
+};
+pub(crate) use release_hook::{ensure_release_iso_via_variant_hook, live_uki_cmdline};
+pub(crate) use remote::remote_cmd;
+pub(crate) use scaffold::new_variant_cmd;
@@ -3,15 +3,17 @@ use distro_contract::resolve_variant_owner_paths;
 use std::fs;
 use std::path::Path;
 
+use crate::repo_layout::RepoLayout;
+
 pub(crate) fn parse_release_build_command(
     args: Vec<&String>,
     repo_root: &Path,
-) -> Result<(String, crate::BuildProduct)> {
-    let known_distros = crate::workflows::discover_distro_ids(repo_root)?;
+) -> Result<(String, crate::cli::BuildProduct)> {
+    let known_distros = crate::cli::workflows::discover_distro_ids(repo_root)?;
 
     match args.as_slice() {
         [] => Ok((
-            crate::DEFAULT_DISTRO_ID.to_string(),
+            crate::cli::DEFAULT_DISTRO_ID.to_string(),
             parse_release_product(None)?,
         )),
         [arg] => parse_release_one_arg(arg, &known_distros),
@@ -25,20 +27,20 @@ pub(crate) fn parse_release_build_command(
 pub(crate) fn parse_release_one_arg(
     arg: &str,
     known_distros: &[String],
-) -> Result<(String, crate::BuildProduct)> {
+) -> Result<(String, crate::cli::BuildProduct)> {
     if let Ok(distro_id) = parse_distro_id(arg, known_distros) {
         return Ok((distro_id, parse_release_product(None)?));
     }
 
     let product = parse_release_product(Some(arg))?;
-    Ok((crate::DEFAULT_DISTRO_ID.to_string(), product))
+    Ok((crate::cli::DEFAULT_DISTRO_ID.to_string(), product))
 }
 
 pub(crate) fn parse_release_two_args(
     arg1: &str,
     arg2: &str,
     known_distros: &[String],
-) -> Result<(String, crate::BuildProduct)> {
+) -> Result<(String, crate::cli::BuildProduct)> {
     if let Ok(distro_id) = parse_distro_id(arg1, known_distros) {
         if let Ok(product) = parse_release_product(Some(arg2)) {
             return Ok((distro_id, product));
@@ -57,9 +59,9 @@ pub(crate) fn parse_release_two_args(
          available distros: {}",
         arg1,
         arg2,
-        crate::PRODUCT_BASE_ROOTFS,
-        crate::PRODUCT_LIVE_BOOT,
-        crate::PRODUCT_LIVE_TOOLS,
+        crate::cli::PRODUCT_BASE_ROOTFS,
+        crate::cli::PRODUCT_LIVE_BOOT,
+        crate::cli::PRODUCT_LIVE_TOOLS,
         known_distros
     )
 }
@@ -77,40 +79,40 @@ pub(crate) fn parse_distro_id(value: &str, known_distros: &[String]) -> Result<S
     )
 }
 
-pub(crate) fn parse_product(value: Option<&str>) -> Result<crate::BuildProduct> {
-    match value.unwrap_or(crate::PRODUCT_BASE_ROOTFS) {
-        crate::PRODUCT_BASE_ROOTFS => Ok(product_base_rootfs()),
-        crate::PRODUCT_LIVE_BOOT => Ok(product_live_boot()),
-        crate::PRODUCT_LIVE_TOOLS => Ok(product_live_tools()),
-        crate::PRODUCT_INSTALLED_BOOT => Ok(product_installed_boot()),
+pub(crate) fn parse_product(value: Option<&str>) -> Result<crate::cli::BuildProduct> {
+    match value.unwrap_or(crate::cli::PRODUCT_BASE_ROOTFS) {
+        crate::cli::PRODUCT_BASE_ROOTFS => Ok(product_base_rootfs()),
+        crate::cli::PRODUCT_LIVE_BOOT => Ok(product_live_boot()),
+        crate::cli::PRODUCT_LIVE_TOOLS => Ok(product_live_tools()),
+        crate::cli::PRODUCT_INSTALLED_BOOT => Ok(product_installed_boot()),
         other => bail!(
             "unsupported product '{}'; expected one of: '{}', '{}', '{}', '{}'",
             other,
-            crate::PRODUCT_BASE_ROOTFS,
-            crate::PRODUCT_LIVE_BOOT,
-            crate::PRODUCT_LIVE_TOOLS,
-            crate::PRODUCT_INSTALLED_BOOT
+            crate::cli::PRODUCT_BASE_ROOTFS,
+            crate::cli::PRODUCT_LIVE_BOOT,
+            crate::cli::PRODUCT_LIVE_TOOLS,
+            crate::cli::PRODUCT_INSTALLED_BOOT
         ),
     }
 }
 
-pub(crate) fn parse_release_product(value: Option<&str>) -> Result<crate::BuildProduct> {
+pub(crate) fn parse_release_product(value: Option<&str>) -> Result<crate::cli::BuildProduct> {
     let product = parse_product(value)?;
-    if product.canonical == crate::PRODUCT_INSTALLED_BOOT {
+    if product.canonical == crate::cli::PRODUCT_INSTALLED_BOOT {
         bail!(
             "unsupported release build product '{}'; release build supports '{}', '{}', '{}'.\n\
              '{}' is a canonical product preparation target, not a release ISO target.",
             product.canonical,
-            crate::PRODUCT_BASE_ROOTFS,
-            crate::PRODUCT_LIVE_BOOT,
-            crate::PRODUCT_LIVE_TOOLS,
-            crate::PRODUCT_INSTALLED_BOOT
+            crate::cli::PRODUCT_BASE_ROOTFS,
+            crate::cli::PRODUCT_LIVE_BOOT,
+            crate::cli::PRODUCT_LIVE_TOOLS,
+            crate::cli::PRODUCT_INSTALLED_BOOT
         );
     }
     Ok(product)
 }
 
-pub(crate) fn product_for_logical_name(logical_name: &str) -> Result<crate::BuildProduct> {
+pub(crate) fn product_for_logical_name(logical_name: &str) -> Result<crate::cli::BuildProduct> {
     match logical_name {
         "product.rootfs.base" => Ok(product_base_rootfs()),
         "product.payload.boot.live" => Ok(product_live_boot()),
@@ -123,10 +125,10 @@ pub(crate) fn product_for_logical_name(logical_name: &str) -> Result<crate::Buil
     }
 }
 
-fn product_base_rootfs() -> crate::BuildProduct {
-    crate::BuildProduct {
-        canonical: crate::PRODUCT_BASE_ROOTFS,
-        release_dir_name: crate::PRODUCT_BASE_ROOTFS,
+fn product_base_rootfs() -> crate::cli::BuildProduct {
+    crate::cli::BuildProduct {
+        canonical: crate::cli::PRODUCT_BASE_ROOTFS,
+        release_dir_name: crate::cli::PRODUCT_BASE_ROOTFS,
         release_hook_script_name: Some("build-release.sh"),
         iso_suffix: "base-rootfs",
         live_overlay_dir_name: "live-overlay",
@@ -135,10 +137,10 @@ fn product_base_rootfs() -> crate::BuildProduct {
     }
 }
 
-fn product_live_boot() -> crate::BuildProduct {
-    crate::BuildProduct {
-        canonical: crate::PRODUCT_LIVE_BOOT,
-        release_dir_name: crate::PRODUCT_LIVE_BOOT,
+fn product_live_boot() -> crate::cli::BuildProduct {
+    crate::cli::BuildProduct {
+        canonical: crate::cli::PRODUCT_LIVE_BOOT,
+        release_dir_name: crate::cli::PRODUCT_LIVE_BOOT,
         release_hook_script_name: Some("boot-release.sh"),
         iso_suffix: "live-boot",
         live_overlay_dir_name: "live-overlay",
@@ -147,10 +149,10 @@ fn product_live_boot() -> crate::BuildProduct {
     }
 }
 
-fn product_live_tools() -> crate::BuildProduct {
-    crate::BuildProduct {
-        canonical: crate::PRODUCT_LIVE_TOOLS,
-        release_dir_name: crate::PRODUCT_LIVE_TOOLS,
+fn product_live_tools() -> crate::cli::BuildProduct {
+    crate::cli::BuildProduct {
+        canonical: crate::cli::PRODUCT_LIVE_TOOLS,
+        release_dir_name: crate::cli::PRODUCT_LIVE_TOOLS,
         release_hook_script_name: Some("live-tools-release.sh"),
         iso_suffix: "live-tools",
         live_overlay_dir_name: "live-overlay",
@@ -159,10 +161,10 @@ fn product_live_tools() -> crate::BuildProduct {
     }
 }
 
-fn product_installed_boot() -> crate::BuildProduct {
-    crate::BuildProduct {
-        canonical: crate::PRODUCT_INSTALLED_BOOT,
-        release_dir_name: crate::PRODUCT_INSTALLED_BOOT,
+fn product_installed_boot() -> crate::cli::BuildProduct {
+    crate::cli::BuildProduct {
+        canonical: crate::cli::PRODUCT_INSTALLED_BOOT,
+        release_dir_name: crate::cli::PRODUCT_INSTALLED_BOOT,
         release_hook_script_name: None,
         iso_suffix: "installed-boot",
         live_overlay_dir_name: "boot-overlay",
@@ -172,8 +174,9 @@ fn product_installed_boot() -> crate::BuildProduct {
 }
 
 pub(crate) fn discover_distro_ids(repo_root: &Path) -> Result<Vec<String>> {
-    let variants_dir = repo_root.join("distro-variants");
-    let entries = fs::read_dir(&variants_dir)
+    let layout = RepoLayout::at(repo_root)?;
+    let variants_dir = layout.variants_dir();
+    let entries = fs::read_dir(variants_dir)
         .with_context(|| format!("reading variants directory '{}'", variants_dir.display()))?;
 
     let mut distro_ids = Vec::new();
@@ -236,28 +239,28 @@ mod tests {
     #[test]
     fn product_parser_accepts_canonical_names() {
         assert_eq!(
-            parse_product(Some(crate::PRODUCT_BASE_ROOTFS))
+            parse_product(Some(crate::cli::PRODUCT_BASE_ROOTFS))
                 .expect("parse base-rootfs")
                 .canonical,
-            crate::PRODUCT_BASE_ROOTFS
+            crate::cli::PRODUCT_BASE_ROOTFS
         );
         assert_eq!(
-            parse_product(Some(crate::PRODUCT_LIVE_BOOT))
+            parse_product(Some(crate::cli::PRODUCT_LIVE_BOOT))
                 .expect("parse live-boot")
                 .canonical,
-            crate::PRODUCT_LIVE_BOOT
+            crate::cli::PRODUCT_LIVE_BOOT
         );
         assert_eq!(
-            parse_product(Some(crate::PRODUCT_LIVE_TOOLS))
+            parse_product(Some(crate::cli::PRODUCT_LIVE_TOOLS))
                 .expect("parse live-tools")
                 .canonical,
-            crate::PRODUCT_LIVE_TOOLS
+            crate::cli::PRODUCT_LIVE_TOOLS
         );
         assert_eq!(
-            parse_product(Some(crate::PRODUCT_INSTALLED_BOOT))
+            parse_product(Some(crate::cli::PRODUCT_INSTALLED_BOOT))
                 .expect("parse installed-boot")
                 .canonical,
-            crate::PRODUCT_INSTALLED_BOOT
+            crate::cli::PRODUCT_INSTALLED_BOOT
         );
     }
 
@@ -282,31 +285,31 @@ mod tests {
             product_for_logical_name("product.rootfs.base")
                 .expect("map rootfs base")
                 .canonical,
-            crate::PRODUCT_BASE_ROOTFS
+            crate::cli::PRODUCT_BASE_ROOTFS
         );
         assert_eq!(
             product_for_logical_name("product.payload.boot.live")
                 .expect("map live boot")
                 .canonical,
-            crate::PRODUCT_LIVE_BOOT
+            crate::cli::PRODUCT_LIVE_BOOT
         );
         assert_eq!(
             product_for_logical_name("product.payload.live_tools")
                 .expect("map live tools")
                 .canonical,
-            crate::PRODUCT_LIVE_TOOLS
+            crate::cli::PRODUCT_LIVE_TOOLS
         );
         assert_eq!(
             product_for_logical_name("product.payload.boot.installed")
                 .expect("map installed boot")
                 .canonical,
-            crate::PRODUCT_INSTALLED_BOOT
+            crate::cli::PRODUCT_INSTALLED_BOOT
         );
     }
 
     #[test]
     fn release_product_parser_rejects_installed_boot() {
-        let err = parse_release_product(Some(crate::PRODUCT_INSTALLED_BOOT))
+        let err = parse_release_product(Some(crate::cli::PRODUCT_INSTALLED_BOOT))
             .expect_err("installed-boot must not be a release ISO product");
         assert!(
             err.to_string()
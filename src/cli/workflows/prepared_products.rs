@@ -1,13 +1,13 @@
-use std::fs;
-use std::path::{Path, PathBuf};
-
 use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub(crate) struct PreparedProductInputs {
     pub(crate) rootfs_source_dir: PathBuf,
     pub(crate) live_overlay_dir: PathBuf,
+    pub(crate) installer_image: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,7 +54,7 @@ pub(crate) fn canonical_iso_filename(
 
 pub(crate) fn canonical_prepared_output_names(
     contract: &distro_contract::ConformanceContract,
-    product: crate::BuildProduct,
+    product: crate::cli::BuildProduct,
 ) -> Result<PreparedOutputNames> {
     Ok(PreparedOutputNames {
         rootfs_source_pointer_filename: product.rootfs_source_pointer_filename.to_string(),
@@ -65,7 +65,7 @@ pub(crate) fn canonical_prepared_output_names(
 
 pub(crate) fn write_prepared_product_outputs(
     output_dir: &Path,
-    product: crate::BuildProduct,
+    product: crate::cli::BuildProduct,
     distro_id: &str,
     prepared: &PreparedProductInputs,
     output_names: &PreparedOutputNames,
@@ -190,7 +190,7 @@ mod tests {
     #[test]
     fn canonical_prepared_output_names_follow_ring1_filesystem_transforms() {
         let contract = workspace_contract("levitate");
-        let product = crate::workflows::parse_product(Some(crate::PRODUCT_LIVE_BOOT))
+        let product = crate::cli::workflows::parse_product(Some(crate::cli::PRODUCT_LIVE_BOOT))
             .expect("parse live-boot");
         let names = canonical_prepared_output_names(&contract, product)
             .expect("resolve canonical prepared output names");
@@ -0,0 +1,320 @@
+use anyhow::{bail, Context, Result};
+use distro_contract::LoadedVariantContract;
+use std::fs;
+use std::path::Path;
+
+use crate::artifact::esp::UKI_DIR;
+use crate::artifact::installer::INSTALLER_IMAGE_FILENAME;
+use crate::hook_env::{check_hook_version, HookEnvironment, HOOK_ENV_VERSION};
+use crate::process::Cmd;
+use crate::pstore::Pstore;
+use crate::repo_config::RepoConfig;
+use crate::repo_layout::RepoLayout;
+use crate::{
+    verify_iso, BootExpectation, BootMenuConfig, CmdlineBuilder, SplashConfig, UkiFlavor, UkiSet,
+};
+
+use crate::cli::{BuildOutputLayout, BuildProduct};
+
+pub(crate) fn ensure_release_iso_via_variant_hook(
+    bundle: &LoadedVariantContract,
+    distro_id: &str,
+    kernel_output_dir: &Path,
+    build_layout: &BuildOutputLayout,
+    product: BuildProduct,
+) -> Result<()> {
+    let output_dir = &build_layout.output_dir;
+    let live_uki = &bundle.contract.transforms.live_uki;
+    let uki_set = UkiSet::from_output_names(&live_uki.output_names).with_context(|| {
+        format!(
+            "invalid canonical Ring 1 live UKI transform for '{}' in `contract.transforms.live_uki.output_names`",
+            distro_id
+        )
+    })?;
+    let live_uki_filename = uki_set.filename(UkiFlavor::Live);
+    let (live_cmdline, splash) = live_uki_cmdline(bundle, distro_id)?;
+    let variant_dir = RepoLayout::at(&bundle.repo_root)?.variant_dir(distro_id);
+    let required_cmdline = product_required_kernel_cmdline(bundle, product).with_context(|| {
+        format!(
+            "validating required kernel cmdline for product '{}' on '{}'",
+            product.canonical, distro_id
+        )
+    })?;
+    // Troubleshooting entries reuse the live UKI with their own options,
+    // which replace the embedded command line, so they start from all of it.
+    let boot_menu = BootMenuConfig::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading boot menu config for '{}'", distro_id))?;
+    let mut menu_cmdline = CmdlineBuilder::parse(&live_cmdline)?;
+    for token in required_cmdline.split_whitespace() {
+        if !menu_cmdline.contains(token) {
+            menu_cmdline = menu_cmdline.extend_str(token)?;
+        }
+    }
+    let menu_cmdline = menu_cmdline.build()?;
+    let boot_menu_dir = output_dir.join(BOOT_MENU_STAGING_DIRNAME);
+    if boot_menu_dir.exists() {
+        fs::remove_dir_all(&boot_menu_dir)
+            .with_context(|| format!("removing stale {}", boot_menu_dir.display()))?;
+    }
+    boot_menu
+        .stage_esp(
+            &boot_menu_dir,
+            &crate::cli::workflows::contract_branding(&bundle.contract).pretty_name(),
+            &format!("/{}/{}", UKI_DIR, live_uki_filename),
+            &menu_cmdline,
+        )
+        .with_context(|| format!("generating boot menu entries for '{}'", distro_id))?;
+    let initramfs_live_filename = crate::cli::workflows::canonical_initramfs_live_filename(
+        &bundle.contract,
+    )
+    .with_context(|| {
+        format!(
+            "resolving canonical Ring 1 live initramfs output for '{}'",
+            distro_id
+        )
+    })?;
+    let rootfs_filename = crate::cli::workflows::canonical_rootfs_erofs_filename(&bundle.contract)
+        .with_context(|| {
+            format!(
+                "resolving canonical Ring 1 rootfs output for '{}'",
+                distro_id
+            )
+        })?;
+    let overlay_filename = crate::cli::workflows::canonical_overlay_erofs_filename(
+        &bundle.contract,
+    )
+    .with_context(|| {
+        format!(
+            "resolving canonical Ring 1 overlay output for '{}'",
+            distro_id
+        )
+    })?;
+    let base_iso_filename = crate::cli::workflows::canonical_iso_filename(&bundle.contract)
+        .with_context(|| format!("resolving canonical Ring 0 ISO output for '{}'", distro_id))?;
+    let iso_filename =
+        crate::cli::workflows::build::iso_filename_for_product(&base_iso_filename, product);
+    let iso_path = output_dir.join(&iso_filename);
+    let release_hook_script = product.release_hook_script_name.ok_or_else(|| {
+        anyhow::anyhow!(
+            "missing canonical release hook for product '{}': this product is not a release ISO target",
+            product.canonical
+        )
+    })?;
+    let native_build = bundle.paths.ring0_hook_path(release_hook_script);
+    if !native_build.is_file() {
+        bail!(
+            "missing variant release build hook for product '{}' on '{}': {}\n\
+             expected '{}' under '{}'.",
+            product.canonical,
+            distro_id,
+            native_build.display(),
+            release_hook_script,
+            bundle.paths.ring0_hooks_dir.display()
+        );
+    }
+    check_hook_version(&native_build)?;
+
+    let kernel_release_path = kernel_output_dir.join(&bundle.contract.build.kernel.release_path);
+    let kernel_image_path = kernel_output_dir.join(&bundle.contract.build.kernel.image_path);
+
+    println!(
+        "[release:iso:{}:{distro_id}] building release run {} via variant release hook {} (output: {})",
+        product.canonical,
+        build_layout.run_id.as_deref().unwrap_or("adhoc"),
+        native_build.display(),
+        iso_path.display()
+    );
+
+    let distro_builder_bin = crate::builder::cli_binary().ok_or_else(|| {
+        anyhow::anyhow!(
+            "distro-builder binary not found for the release hook of '{}'\n\
+             Remediation: install it on PATH, set {}, or call Builder::cli",
+            distro_id,
+            crate::builder::CLI_ENV
+        )
+    })?;
+    let hook_env = HookEnvironment {
+        version: HOOK_ENV_VERSION,
+        distro_id: distro_id.to_string(),
+        os_name: bundle.contract.identity.os_name.clone(),
+        os_id: bundle.contract.identity.os_id.clone(),
+        os_version: bundle.contract.identity.os_version.clone(),
+        iso_label: bundle.contract.identity.iso_label.clone(),
+        live_uki_filename: live_uki_filename.to_string(),
+        emergency_uki_filename: uki_set.emergency.clone(),
+        debug_uki_filename: uki_set.debug.clone(),
+        emergency_uki_cmdline: UkiFlavor::Emergency.cmdline(&live_cmdline)?,
+        debug_uki_cmdline: UkiFlavor::Debug.cmdline(&live_cmdline)?,
+        live_uki_cmdline: live_cmdline,
+        boot_menu_esp_dir: boot_menu_dir.clone(),
+        splash_bitmap: splash.bitmap.clone(),
+        splash_plymouth_theme: splash.plymouth.as_ref().map(|p| p.theme.clone()),
+        kernel_output_dir: kernel_output_dir.to_path_buf(),
+        kernel_release_path,
+        kernel_image_path: kernel_image_path.clone(),
+        iso_path: iso_path.clone(),
+        iso_filename,
+        product_name: product.canonical.to_string(),
+        product_dirname: product.release_dir_name.to_string(),
+        product_boot_label: product.issue_banner_label.to_string(),
+        product_required_kernel_cmdline: required_cmdline.clone(),
+        rootfs_filename,
+        initramfs_live_filename: initramfs_live_filename.clone(),
+        initramfs_compress: RepoConfig::load(&bundle.repo_root)?
+            .compression
+            .initramfs
+            .shell_filter(),
+        live_overlay_dirname: product.live_overlay_dir_name.to_string(),
+        live_overlay_image_filename: overlay_filename,
+        installer_image_filename: (product.canonical == crate::cli::PRODUCT_LIVE_TOOLS)
+            .then(|| INSTALLER_IMAGE_FILENAME.to_string()),
+        rootfs_source_pointer_filename: product.rootfs_source_pointer_filename.to_string(),
+        output_root: crate::repo_config::artifacts_root(&bundle.repo_root)?,
+        release_root_dir: build_layout.root_dir.clone(),
+        release_run_dir: output_dir.clone(),
+        build_run_id: build_layout.run_id.clone(),
+        distro_builder_bin,
+    };
+    hook_env
+        .write_json()
+        .with_context(|| format!("writing release hook environment for '{}'", distro_id))?;
+
+    // With errexit a failing helper, `$BUILD_UKIS` above all, stops the
+    // hook before it packs an ISO around an incomplete UKI set.
+    let status = Cmd::new("sh")
+        .arg("-e")
+        .arg_path(&native_build)
+        .dir(&bundle.repo_root)
+        .env(crate::repo_layout::REPO_ROOT_ENV, &bundle.repo_root)
+        .envs(hook_env.vars())
+        .allow_fail()
+        .run_interactive()
+        .with_context(|| {
+            format!(
+                "running variant release build hook '{}' for product '{}' on '{}'",
+                native_build.display(),
+                product.canonical,
+                distro_id
+            )
+        })?;
+    let _ = fs::remove_dir_all(&boot_menu_dir);
+
+    if !status.success() {
+        bail!("builder command failed for '{distro_id}' with status {status}");
+    }
+
+    if !iso_path.is_file() {
+        bail!(
+            "builder finished but ISO still missing for '{}': {}",
+            distro_id,
+            iso_path.display()
+        );
+    }
+
+    // `$BUILD_UKIS` checks the initramfs before the hook assembles the ISO;
+    // re-check the kept output so a hook that builds its UKIs some other way
+    // cannot publish a release with an unbootable initramfs.
+    let initramfs_path = output_dir.join(&initramfs_live_filename);
+    if initramfs_path.is_file() {
+        crate::cli::workflows::check_initramfs_for_distro(
+            &bundle.repo_root,
+            distro_id,
+            &initramfs_path,
+        )?;
+    } else {
+        eprintln!(
+            "  [WARN] release hook for '{}' did not keep {} in the run dir; its initramfs was only checked if the hook ran $BUILD_UKIS or $INITRAMFS_CONTRACT_CHECK",
+            distro_id,
+            initramfs_live_filename
+        );
+    }
+
+    // A hook that reuses an ESP from an earlier run ships a UKI whose kernel
+    // or initramfs no longer matches this build.
+    // The emergency and debug UKIs must ship too, though hooks that build
+    // them without `$BUILD_UKIS` may pick their own tokens.
+    let mut expect = BootExpectation::new().require_cmdline(
+        live_uki_filename,
+        &required_cmdline.split_whitespace().collect::<Vec<_>>(),
+    );
+    for flavor in [UkiFlavor::Emergency, UkiFlavor::Debug] {
+        expect = expect.require_cmdline(uki_set.filename(flavor), &[] as &[&str]);
+    }
+    if kernel_image_path.is_file() {
+        expect = expect.kernel_image(&kernel_image_path)?;
+    }
+    if initramfs_path.is_file() {
+        expect = expect.initrd_file(&initramfs_path)?;
+    }
+    if let Err(err) = verify_iso(&iso_path, &expect) {
+        // Do not leave an ISO behind that looks like a finished release.
+        let _ = fs::remove_file(&iso_path);
+        return Err(err).with_context(|| {
+            format!(
+                "release ISO for '{}' boots stale artifacts or misses a UKI\n\
+                 Remediation: make the release hook run $BUILD_UKIS from KERNEL_IMAGE_PATH and INITRAMFS_LIVE_FILENAME on every run, before it packs the ISO.",
+                distro_id
+            )
+        });
+    }
+
+    Ok(())
+}
+
+/// The live UKI command line: the contract's `extra_cmdline` plus the
+/// variant's boot splash parameters, and its pstore parameters when
+/// `pstore.toml` opts the release in. Also returns the splash config.
+pub(crate) fn live_uki_cmdline(
+    bundle: &LoadedVariantContract,
+    distro_id: &str,
+) -> Result<(String, SplashConfig)> {
+    let live_uki = &bundle.contract.transforms.live_uki;
+    let live_cmdline = CmdlineBuilder::parse(live_uki.extra_cmdline.as_deref().unwrap_or_default())
+        .and_then(|cmdline| cmdline.build())
+        .with_context(|| {
+            format!(
+                "validating `contract.transforms.live_uki.extra_cmdline` for '{}'",
+                distro_id
+            )
+        })?;
+    let variant_dir = RepoLayout::at(&bundle.repo_root)?.variant_dir(distro_id);
+    let splash = SplashConfig::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading splash config for '{}'", distro_id))?
+        .unwrap_or_default();
+    let live_cmdline = splash.extend_cmdline(&live_cmdline).with_context(|| {
+        format!(
+            "adding boot splash parameters to live cmdline for '{}'",
+            distro_id
+        )
+    })?;
+    let live_cmdline = match Pstore::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading pstore config for '{}'", distro_id))?
+    {
+        Some(pstore) if pstore.release_cmdline => {
+            pstore.extend_cmdline(&live_cmdline).with_context(|| {
+                format!(
+                    "adding pstore parameters to live cmdline for '{}'",
+                    distro_id
+                )
+            })?
+        }
+        _ => live_cmdline,
+    };
+    Ok((live_cmdline, splash))
+}
+
+/// Run-output directory holding the ESP files the hook copies from
+/// `BOOT_MENU_ESP_DIR`; removed once the hook has run.
+const BOOT_MENU_STAGING_DIRNAME: &str = ".boot-menu-esp";
+
+fn product_required_kernel_cmdline(
+    bundle: &LoadedVariantContract,
+    product: BuildProduct,
+) -> Result<String> {
+    match product.canonical {
+        crate::cli::PRODUCT_LIVE_BOOT | crate::cli::PRODUCT_LIVE_TOOLS => CmdlineBuilder::new()
+            .extend_tokens(&bundle.contract.scenarios.live_boot.required_kernel_cmdline)?
+            .build(),
+        _ => Ok(String::new()),
+    }
+}
@@ -0,0 +1,15 @@
+use anyhow::Result;
+
+use crate::remote::RemoteBuild;
+
+/// `remote <host> <command>...`: run `<command>` in a synced copy of this
+/// checkout on `<host>` and pull its outputs back.
+pub(crate) fn remote_cmd(host: &str, args: &[String]) -> Result<()> {
+    if args.is_empty() || args[0] == "remote" {
+        return Err(crate::cli::usage_error());
+    }
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    RemoteBuild::load(&repo_root, host)?
+        .with_json_output(crate::cli::json_output())
+        .run(args)
+}
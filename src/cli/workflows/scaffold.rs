@@ -0,0 +1,76 @@
+use anyhow::{bail, Context, Result};
+use distro_contract::{load_variant_contract_bundle_for_distro_from, require_valid_contract};
+
+use crate::{InitSystem, VariantScaffold};
+
+/// `new-variant <id> --init systemd|openrc [--from <distro_id>] [--name <os_name>]`
+pub(crate) fn new_variant_cmd(args: &[String]) -> Result<()> {
+    let Some((distro_id, mut rest)) = args.split_first() else {
+        return Err(crate::cli::usage_error());
+    };
+    let mut init = None;
+    let mut template = None;
+    let mut os_name = None;
+    while let [flag, value, tail @ ..] = rest {
+        match flag.as_str() {
+            "--init" => {
+                init = Some(match value.as_str() {
+                    "systemd" => InitSystem::Systemd,
+                    "openrc" => InitSystem::OpenRC,
+                    other => bail!(
+                        "unsupported init system '{}'; expected systemd or openrc",
+                        other
+                    ),
+                })
+            }
+            "--from" => template = Some(value.as_str()),
+            "--name" => os_name = Some(value.as_str()),
+            other => bail!(
+                "unsupported new-variant option '{}'; expected --init, --from or --name",
+                other
+            ),
+        }
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        bail!("`new-variant` option '{}' expects a value", rest[0]);
+    }
+    let init =
+        init.ok_or_else(|| anyhow::anyhow!("`new-variant` requires --init systemd|openrc"))?;
+
+    let mut scaffold = VariantScaffold::new(distro_id, init)?;
+    if let Some(template) = template {
+        scaffold = scaffold.template_variant(template);
+    }
+    if let Some(os_name) = os_name {
+        scaffold = scaffold.os_name(os_name);
+    }
+
+    let repo_root = crate::cli::workflows::locate_repo_root()?;
+    let variant_dir = scaffold
+        .write(&repo_root)
+        .with_context(|| format!("scaffolding variant '{}'", distro_id))?;
+
+    let validated = (|| -> Result<()> {
+        let bundle = load_variant_contract_bundle_for_distro_from(&repo_root, distro_id)?;
+        require_valid_contract(&bundle.contract)?;
+        Ok(())
+    })();
+    if let Err(err) = validated {
+        let _ = std::fs::remove_dir_all(&variant_dir);
+        return Err(err).with_context(|| {
+            format!(
+                "generated variant '{}' failed contract validation and was removed",
+                distro_id
+            )
+        });
+    }
+
+    println!("variant scaffold ready for {}:", distro_id);
+    println!("  directory: {}", variant_dir.display());
+    println!(
+        "  next:      see {}",
+        variant_dir.join("README.md").display()
+    );
+    Ok(())
+}
@@ -0,0 +1,522 @@
+//! Compression codecs for store blobs, tarballs and initramfs archives.
+//!
+//! Store blobs used to be hardwired to zstd and initramfs archives to gzip.
+//! A [`Compression`] names a codec and level in one value (`"zstd:19"`,
+//! `"gzip"`), so a consumer picks speed against ratio in one place instead
+//! of in each helper:
+//!
+//! ```toml
+//! # distro-builder.toml
+//! [compression]
+//! store = "zstd:3"
+//! initramfs = "xz:6"
+//! ```
+//!
+//! zstd streams are encoded and decoded in-process; xz, lz4 and gzip go
+//! through their command line tools, as the module and firmware
+//! compression already does. Decoders detect the codec from the stream's
+//! magic bytes, so blobs written under one setting stay readable after the
+//! setting changes.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::preflight::RequiredTools;
+
+/// A compression format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    Zstd,
+    Xz,
+    Lz4,
+    Gzip,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 4] = [Self::Zstd, Self::Xz, Self::Lz4, Self::Gzip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+            Self::Lz4 => "lz4",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// File name extension without the dot (`zst`, `xz`, `lz4`, `gz`).
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zstd => "zst",
+            Self::Xz => "xz",
+            Self::Lz4 => "lz4",
+            Self::Gzip => "gz",
+        }
+    }
+
+    /// Level used when none is given.
+    pub fn default_level(self) -> u32 {
+        match self {
+            Self::Zstd => 3,
+            Self::Xz => 6,
+            Self::Lz4 => 9,
+            Self::Gzip => 6,
+        }
+    }
+
+    /// Valid levels, fastest first.
+    pub fn levels(self) -> (u32, u32) {
+        match self {
+            Self::Zstd => (1, 19),
+            Self::Xz => (0, 9),
+            Self::Lz4 => (1, 12),
+            Self::Gzip => (1, 9),
+        }
+    }
+
+    /// Host tool and the package shipping it.
+    pub fn host_tool(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Zstd => ("zstd", "zstd"),
+            Self::Xz => ("xz", "xz"),
+            Self::Lz4 => ("lz4", "lz4"),
+            Self::Gzip => ("gzip", "gzip"),
+        }
+    }
+
+    /// Codec of a stream starting with `magic`, if it is compressed.
+    pub fn detect(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else if magic.starts_with(&[0x02, 0x21, 0x4c, 0x18])
+            || magic.starts_with(&[0x04, 0x22, 0x4d, 0x18])
+        {
+            Some(Self::Lz4)
+        } else if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else {
+            None
+        }
+    }
+
+    /// Codec of the file at `path`, from its first bytes.
+    pub fn detect_file(path: &Path) -> Result<Option<Self>> {
+        let mut magic = Vec::with_capacity(6);
+        File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?
+            .take(6)
+            .read_to_end(&mut magic)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Self::detect(&magic))
+    }
+
+    /// Arguments decompressing stdin to stdout.
+    fn decompress_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Zstd => &["-q", "-d", "-c"],
+            Self::Xz | Self::Lz4 | Self::Gzip => &["-d", "-c"],
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A codec and its level, written `codec[:level]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Compression {
+    pub codec: Codec,
+    pub level: u32,
+}
+
+impl Compression {
+    /// `codec` at `level`; fails when the codec has no such level.
+    pub fn new(codec: Codec, level: u32) -> Result<Self> {
+        let (min, max) = codec.levels();
+        if !(min..=max).contains(&level) {
+            bail!(
+                "{} compression level {} is out of range ({}-{})",
+                codec,
+                level,
+                min,
+                max
+            );
+        }
+        Ok(Self { codec, level })
+    }
+
+    /// `codec` at its default level.
+    pub fn with_default_level(codec: Codec) -> Self {
+        Self {
+            codec,
+            level: codec.default_level(),
+        }
+    }
+
+    /// Store blobs: fast zstd.
+    pub fn store_default() -> Self {
+        Self::with_default_level(Codec::Zstd)
+    }
+
+    /// Initramfs archives: gzip, which every kernel can unpack.
+    pub fn initramfs_default() -> Self {
+        Self::with_default_level(Codec::Gzip)
+    }
+
+    /// Arguments compressing stdin to stdout.
+    ///
+    /// lz4 writes the legacy frame format and xz CRC32 checks, the only
+    /// variants the kernel's initramfs decompressors accept.
+    pub fn compress_args(&self) -> Vec<String> {
+        let level = format!("-{}", self.level);
+        let args: &[&str] = match self.codec {
+            Codec::Zstd => &["-q", "-c"],
+            Codec::Xz => &["--check=crc32", "-c"],
+            Codec::Lz4 => &["-l", "-c"],
+            Codec::Gzip => &["-n", "-c"],
+        };
+        args.iter()
+            .map(|arg| arg.to_string())
+            .chain(std::iter::once(level))
+            .collect()
+    }
+
+    /// Shell pipeline stage compressing stdin to stdout, e.g. `gzip -n -c -6`.
+    pub fn shell_filter(&self) -> String {
+        let mut filter = self.codec.host_tool().0.to_string();
+        for arg in self.compress_args() {
+            filter.push(' ');
+            filter.push_str(&arg);
+        }
+        filter
+    }
+
+    /// Compress everything written to the returned encoder into `out`.
+    pub fn encoder(&self, out: File) -> Result<Encoder> {
+        if self.codec == Codec::Zstd {
+            let encoder = zstd::stream::Encoder::new(out, self.level as i32)
+                .context("Failed to start zstd encoder")?;
+            return Ok(Encoder::Zstd(encoder));
+        }
+        let (tool, package) = self.codec.host_tool();
+        let mut child = Command::new(tool)
+            .args(self.compress_args())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::from(out))
+            .spawn()
+            .with_context(|| format!("Failed to spawn {}. Install {}.", tool, package))?;
+        let stdin = child.stdin.take().context("compressor stdin unavailable")?;
+        Ok(Encoder::Process {
+            codec: self.codec,
+            child,
+            stdin: Some(stdin),
+        })
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::store_default()
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.codec, self.level)
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, level) = match s.trim().split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s.trim(), None),
+        };
+        let codec = match name.to_ascii_lowercase().as_str() {
+            "zstd" | "zst" => Codec::Zstd,
+            "xz" => Codec::Xz,
+            "lz4" => Codec::Lz4,
+            "gzip" | "gz" => Codec::Gzip,
+            _ => bail!(
+                "unknown compression '{}' (expected zstd, xz, lz4 or gzip, optionally with ':<level>')",
+                s
+            ),
+        };
+        match level {
+            None => Ok(Self::with_default_level(codec)),
+            Some(level) => {
+                let level = level
+                    .parse()
+                    .with_context(|| format!("invalid compression level in '{}'", s))?;
+                Self::new(codec, level)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Compression {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// `[compression]` in `distro-builder.toml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Directory blobs in the artifact store.
+    pub store: Compression,
+    /// Initramfs cpio archives.
+    pub initramfs: Compression,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            store: Compression::store_default(),
+            initramfs: Compression::initramfs_default(),
+        }
+    }
+}
+
+/// A compressing writer from [`Compression::encoder`].
+pub enum Encoder {
+    Zstd(zstd::stream::Encoder<'static, File>),
+    Process {
+        codec: Codec,
+        child: Child,
+        stdin: Option<ChildStdin>,
+    },
+}
+
+impl Encoder {
+    /// Flush the stream and wait for the compressor; the output is only
+    /// complete once this returns.
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Self::Zstd(encoder) => {
+                encoder.finish().context("Failed to finish zstd stream")?;
+            }
+            Self::Process {
+                codec,
+                mut child,
+                stdin,
+            } => {
+                drop(stdin);
+                let status = child
+                    .wait()
+                    .with_context(|| format!("Failed to wait for {}", codec))?;
+                if !status.success() {
+                    bail!("{} compression failed ({})", codec, status);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Zstd(encoder) => encoder.write(buf),
+            Self::Process { stdin, .. } => stdin
+                .as_mut()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))?
+                .write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Zstd(encoder) => encoder.flush(),
+            Self::Process { stdin, .. } => stdin.as_mut().map_or(Ok(()), |stdin| stdin.flush()),
+        }
+    }
+}
+
+/// A decompressing reader over a file, from [`decoder`].
+pub enum Decoder {
+    Zstd(zstd::stream::Decoder<'static, BufReader<File>>),
+    Process {
+        codec: Codec,
+        child: Child,
+        stdout: ChildStdout,
+    },
+}
+
+/// Decompress `input` with `codec`.
+pub fn decoder(codec: Codec, input: File) -> Result<Decoder> {
+    if codec == Codec::Zstd {
+        let decoder = zstd::stream::Decoder::new(input).context("Failed to start zstd decoder")?;
+        return Ok(Decoder::Zstd(decoder));
+    }
+    let (tool, package) = codec.host_tool();
+    let mut child = Command::new(tool)
+        .args(codec.decompress_args())
+        .stdin(Stdio::from(input))
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to spawn {}. Install {}.", tool, package))?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("decompressor stdout unavailable")?;
+    Ok(Decoder::Process {
+        codec,
+        child,
+        stdout,
+    })
+}
+
+impl Decoder {
+    /// Wait for the decompressor and fail if the stream was corrupt.
+    pub fn finish(self) -> Result<()> {
+        if let Self::Process {
+            codec,
+            mut child,
+            stdout,
+        } = self
+        {
+            drop(stdout);
+            let status = child
+                .wait()
+                .with_context(|| format!("Failed to wait for {}", codec))?;
+            if !status.success() {
+                bail!("{} decompression failed ({})", codec, status);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for Decoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Zstd(decoder) => decoder.read(buf),
+            Self::Process { stdout, .. } => stdout.read(buf),
+        }
+    }
+}
+
+/// Decompress `raw` if it starts with a known codec's magic; other input
+/// is returned unchanged.
+pub fn decompress_bytes(raw: &[u8]) -> Result<Vec<u8>> {
+    let Some(codec) = Codec::detect(raw) else {
+        return Ok(raw.to_vec());
+    };
+    if codec == Codec::Zstd {
+        return Ok(zstd::decode_all(raw)?);
+    }
+    let (tool, package) = codec.host_tool();
+    let mut child = Command::new(tool)
+        .args(codec.decompress_args())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to spawn {}. Install {}.", tool, package))?;
+
+    // Feed stdin from a separate thread so a full stdout pipe cannot deadlock.
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("decompressor stdin unavailable")?;
+    let input = raw.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to run {}", tool))?;
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("{} writer thread panicked", tool))?
+        .with_context(|| format!("Failed to write to {}", tool))?;
+    if !output.status.success() {
+        bail!(
+            "{} -dc failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Register the host tool `compression` needs when run as a command.
+pub fn register_host_tools(tools: &mut RequiredTools, compression: Compression) {
+    let (tool, package) = compression.codec.host_tool();
+    tools.require("compression", tool, package);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(
+            "zstd:19".parse::<Compression>().unwrap(),
+            Compression::new(Codec::Zstd, 19).unwrap()
+        );
+        assert_eq!(
+            "gz".parse::<Compression>().unwrap(),
+            Compression::initramfs_default()
+        );
+        assert_eq!(Compression::store_default().to_string(), "zstd:3");
+        assert!("brotli".parse::<Compression>().is_err());
+        assert!("gzip:12".parse::<Compression>().is_err());
+        assert!("xz:fast".parse::<Compression>().is_err());
+        assert_eq!(
+            Compression::new(Codec::Lz4, 12).unwrap().shell_filter(),
+            "lz4 -l -c -12"
+        );
+
+        let config: CompressionConfig = toml::from_str("initramfs = \"xz:9\"\n").unwrap();
+        assert_eq!(config.store, Compression::store_default());
+        assert_eq!(config.initramfs, Compression::new(Codec::Xz, 9).unwrap());
+    }
+
+    #[test]
+    fn test_roundtrip_and_detect() {
+        let temp = TempDir::new().unwrap();
+        let payload = b"initramfs payload ".repeat(1000);
+        for codec in Codec::ALL {
+            if codec != Codec::Zstd && !crate::process::exists(codec.host_tool().0) {
+                eprintln!("skipping {}: not installed", codec);
+                continue;
+            }
+            let path = temp.path().join(format!("blob.{}", codec.extension()));
+            let compression = Compression::with_default_level(codec);
+            let mut encoder = compression.encoder(File::create(&path).unwrap()).unwrap();
+            encoder.write_all(&payload).unwrap();
+            encoder.finish().unwrap();
+
+            assert_eq!(Codec::detect_file(&path).unwrap(), Some(codec));
+            let mut decoder = decoder(codec, File::open(&path).unwrap()).unwrap();
+            let mut out = Vec::new();
+            decoder.read_to_end(&mut out).unwrap();
+            decoder.finish().unwrap();
+            assert_eq!(out, payload);
+            assert_eq!(
+                decompress_bytes(&std::fs::read(&path).unwrap()).unwrap(),
+                payload
+            );
+        }
+        assert_eq!(Codec::detect(b"070701"), None);
+    }
+}
@@ -2,8 +2,18 @@
 //!
 //! Components are defined as data structures that describe WHAT needs
 //! to happen, not HOW. An executor interprets these definitions.
+//!
+//! Execution order does not depend on incidental ordering in an impl:
+//! [`sort_components`] orders components by phase and name, and
+//! [`canonical_ops`] sorts each run of interchangeable ops by path while
+//! keeping ops that depend on earlier ones where they were declared. Two
+//! builds from the same definitions therefore run the same steps in the
+//! same order, which [`ops_manifest`] records.
 
 use std::fmt;
+use std::ops::Deref;
+
+use crate::contracts::context::Features;
 
 /// Trait for anything that can be installed by an executor.
 ///
@@ -124,11 +134,145 @@ pub enum Op {
     /// Copy multiple binaries to /usr/sbin.
     Sbins(Vec<String>),
 
+    // Privileged access
+    /// Grant a user (`alice`) or group (`%wheel`) privileged access.
+    ///
+    /// `rule` is `ALL` or a command path, optionally prefixed with
+    /// `NOPASSWD:`. It is rendered for whichever of sudo and doas the
+    /// rootfs ships.
+    SudoRule { user_or_group: String, rule: String },
+
+    // Flavors
+    /// Run `ops` only when the distro config enables `feature` (see
+    /// [`DistroConfig::features`](crate::contracts::DistroConfig::features)).
+    When { feature: String, ops: Vec<Op> },
+
     // Extension point for distro-specific operations
     /// Distro-specific custom operation.
     Custom(String),
 }
 
+impl Op {
+    /// Class of ops that may be reordered among themselves, or `None` for
+    /// ops that must stay where they were declared.
+    ///
+    /// Consecutive ops of one class do not depend on each other's order:
+    /// directories are created with their parents, and users, groups,
+    /// copies and binaries each touch their own path. Symlinks, sudo rules,
+    /// custom and feature-gated ops may depend on anything declared before
+    /// them, so they split the op list into separately sorted runs.
+    fn commutative_class(&self) -> Option<u8> {
+        match self {
+            Op::Group { .. } => Some(0),
+            Op::User { .. } => Some(1),
+            Op::Dir(_) | Op::DirMode(..) | Op::Dirs(_) => Some(2),
+            Op::CopyTree(_) => Some(3),
+            Op::CopyFile(_) => Some(4),
+            Op::Bin(_) | Op::Sbin(_) | Op::Bins(_) | Op::Sbins(_) => Some(5),
+            Op::WriteFile(..) | Op::WriteFileMode(..) => Some(6),
+            Op::Symlink(..) | Op::SudoRule { .. } | Op::Custom(_) | Op::When { .. } => None,
+        }
+    }
+
+    /// Key ordering ops within a run of their commutative class.
+    fn order_key(&self) -> Option<&str> {
+        match self {
+            Op::Group { name, .. } | Op::User { name, .. } => Some(name),
+            Op::Dir(path)
+            | Op::DirMode(path, _)
+            | Op::CopyTree(path)
+            | Op::CopyFile(path)
+            | Op::Bin(path)
+            | Op::Sbin(path)
+            | Op::WriteFile(path, _)
+            | Op::WriteFileMode(path, _, _) => Some(path),
+            Op::Dirs(paths) | Op::Bins(paths) | Op::Sbins(paths) => {
+                paths.first().map(String::as_str)
+            }
+            Op::Symlink(..) | Op::SudoRule { .. } | Op::Custom(_) | Op::When { .. } => None,
+        }
+    }
+
+    /// Sort and deduplicate the lists of batch ops and canonicalize the ops
+    /// of `When`.
+    fn normalized(self) -> Self {
+        let sorted = |mut items: Vec<String>| {
+            items.sort();
+            items.dedup();
+            items
+        };
+        match self {
+            Op::Dirs(paths) => Op::Dirs(sorted(paths)),
+            Op::Bins(names) => Op::Bins(sorted(names)),
+            Op::Sbins(names) => Op::Sbins(sorted(names)),
+            Op::When { feature, ops } => Op::When {
+                feature,
+                ops: canonical_ops(ops),
+            },
+            other => other,
+        }
+    }
+}
+
+/// `ops` in canonical order, with adjacent exact duplicates removed.
+///
+/// Ops keep their declared order except within a run of consecutive ops of
+/// one commutative kind (directories, users, writes, ...), which is sorted
+/// by path or name. The sort is stable: two writes of one file keep their
+/// relative order, so the last one still wins. Symlinks, sudo rules,
+/// custom and feature-gated ops never move.
+pub fn canonical_ops(ops: Vec<Op>) -> Vec<Op> {
+    let mut ops: Vec<Op> = ops.into_iter().map(Op::normalized).collect();
+    let mut start = 0;
+    while start < ops.len() {
+        let class = ops[start].commutative_class();
+        let mut end = start + 1;
+        if class.is_some() {
+            while end < ops.len() && ops[end].commutative_class() == class {
+                end += 1;
+            }
+            ops[start..end].sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+        }
+        start = end;
+    }
+    ops.dedup();
+    ops
+}
+
+/// Sort components into execution order: by phase, then by name.
+pub fn sort_components<P>(components: &mut [P])
+where
+    P: Deref,
+    P::Target: Installable,
+{
+    components.sort_by(|a, b| (a.phase(), a.name()).cmp(&(b.phase(), b.name())));
+}
+
+/// One line per op that `components` perform, in execution order.
+///
+/// Identical definitions give byte-identical manifests, whatever order the
+/// components were listed in.
+pub fn ops_manifest<P>(components: &[P]) -> String
+where
+    P: Deref,
+    P::Target: Installable,
+{
+    let mut ordered: Vec<&P::Target> = components.iter().map(|c| c.deref()).collect();
+    sort_components(&mut ordered);
+    let mut manifest = String::new();
+    for component in ordered {
+        for op in canonical_ops(component.ops()) {
+            manifest.push_str(&format!(
+                "{}\t{}\t{:?}\n",
+                component.phase(),
+                component.name(),
+                op
+            ));
+        }
+    }
+    manifest
+}
+
 /// Create a directory.
 pub fn dir(path: impl Into<String>) -> Op {
     Op::Dir(path.into())
@@ -179,6 +323,39 @@ pub fn sbins(names: impl IntoIterator<Item = impl Into<String>>) -> Op {
     Op::Sbins(names.into_iter().map(|n| n.into()).collect())
 }
 
+/// Grant privileged access to a user or `%group`.
+pub fn sudo_rule(user_or_group: impl Into<String>, rule: impl Into<String>) -> Op {
+    Op::SudoRule {
+        user_or_group: user_or_group.into(),
+        rule: rule.into(),
+    }
+}
+
+/// Run `ops` only when `feature` is enabled.
+pub fn when(feature: impl Into<String>, ops: Vec<Op>) -> Op {
+    Op::When {
+        feature: feature.into(),
+        ops,
+    }
+}
+
+/// `ops` with every [`Op::When`] replaced by its ops when `features`
+/// enables it, and dropped otherwise.
+pub fn resolve_features(ops: Vec<Op>, features: &Features) -> Vec<Op> {
+    let mut resolved = Vec::with_capacity(ops.len());
+    for op in ops {
+        match op {
+            Op::When { feature, ops } => {
+                if features.contains(&feature) {
+                    resolved.extend(resolve_features(ops, features));
+                }
+            }
+            other => resolved.push(other),
+        }
+    }
+    resolved
+}
+
 /// Custom distro-specific operation.
 pub fn custom(name: impl Into<String>) -> Op {
     Op::Custom(name.into())
@@ -209,6 +386,151 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_when_follows_features() {
+        let ops = vec![
+            dir("etc/foo"),
+            when(
+                "wifi",
+                vec![bin("iwctl"), when("debug-tools", vec![bin("strace")])],
+            ),
+        ];
+        assert_eq!(
+            resolve_features(ops.clone(), &Features::default()),
+            vec![dir("etc/foo")]
+        );
+        assert_eq!(
+            resolve_features(ops.clone(), &Features::parse(["wifi"]).unwrap()),
+            vec![dir("etc/foo"), bin("iwctl")]
+        );
+        assert_eq!(
+            resolve_features(ops, &Features::parse(["wifi", "debug-tools"]).unwrap()),
+            vec![dir("etc/foo"), bin("iwctl"), bin("strace")]
+        );
+        assert!(Features::parse(["Wifi"]).is_err());
+    }
+
+    struct Fixture(&'static str, Phase, Vec<Op>);
+
+    fn app_user() -> Op {
+        Op::User {
+            name: "app".into(),
+            uid: 900,
+            gid: 900,
+            home: "/var/lib/app".into(),
+            shell: "/sbin/nologin".into(),
+        }
+    }
+
+    impl Installable for Fixture {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn phase(&self) -> Phase {
+            self.1
+        }
+        fn ops(&self) -> Vec<Op> {
+            self.2.clone()
+        }
+    }
+
+    #[test]
+    fn test_canonical_ops_sort_within_runs() {
+        let group = Op::Group {
+            name: "app".into(),
+            gid: 900,
+        };
+        let ops = vec![
+            dir("etc/app/conf.d"),
+            dir("etc/app"),
+            bins(["ls", "cat", "ls"]),
+            write_file("etc/app/conf", "a"),
+            write_file("etc/app/conf", "b"),
+            symlink("usr/bin/app2", "app"),
+            custom("first"),
+            app_user(),
+            group.clone(),
+            custom("second"),
+            when("wifi", vec![dir("etc/wifi"), dir("etc/iwd")]),
+            dir("etc/app"),
+        ];
+        assert_eq!(
+            canonical_ops(ops),
+            vec![
+                dir("etc/app"),
+                dir("etc/app/conf.d"),
+                bins(["cat", "ls"]),
+                write_file("etc/app/conf", "a"),
+                write_file("etc/app/conf", "b"),
+                symlink("usr/bin/app2", "app"),
+                custom("first"),
+                // A user declared before its group stays before it.
+                app_user(),
+                group,
+                custom("second"),
+                when("wifi", vec![dir("etc/iwd"), dir("etc/wifi")]),
+                dir("etc/app"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canonical_ops_keep_dependents_after_dependencies() {
+        // The custom op reads the file written before it, and the symlink
+        // must not be replaced by the later copy of its directory.
+        let ops = vec![
+            write_file("etc/app/seed", "x"),
+            custom("consume-seed"),
+            Op::CopyTree("usr/share/app".into()),
+            symlink("usr/share/app/current", "v1"),
+        ];
+        assert_eq!(canonical_ops(ops.clone()), ops);
+    }
+
+    #[test]
+    fn test_canonical_ops_are_independent_of_run_order() {
+        let ops = vec![
+            dir("var/lib/app"),
+            dir("etc/app"),
+            symlink("etc/app/link", "target"),
+            write_file("etc/b", "b"),
+            write_file("etc/a", "a"),
+        ];
+        let shuffled = vec![
+            dir("etc/app"),
+            dir("var/lib/app"),
+            symlink("etc/app/link", "target"),
+            write_file("etc/a", "a"),
+            write_file("etc/b", "b"),
+        ];
+        assert_eq!(canonical_ops(ops), canonical_ops(shuffled));
+    }
+
+    #[test]
+    fn test_ops_manifest_is_stable() {
+        let components: Vec<Box<dyn Installable>> = vec![
+            Box::new(Fixture("ssh", Phase::Services, vec![dir("etc/ssh")])),
+            Box::new(Fixture(
+                "fhs",
+                Phase::Filesystem,
+                vec![dir("var"), dir("etc")],
+            )),
+            Box::new(Fixture("chrony", Phase::Services, vec![dir("etc/chrony")])),
+        ];
+        let manifest = ops_manifest(&components);
+        let mut reversed = components;
+        reversed.reverse();
+        assert_eq!(manifest, ops_manifest(&reversed));
+        assert_eq!(
+            manifest
+                .lines()
+                .map(|line| line.split('\t').nth(1).unwrap())
+                .collect::<Vec<_>>(),
+            vec!["fhs", "fhs", "chrony", "ssh"]
+        );
+        assert!(manifest.starts_with("Filesystem\tfhs\tDir(\"etc\")\n"));
+    }
+
     #[test]
     fn test_phase_display() {
         assert_eq!(Phase::Filesystem.to_string(), "Filesystem");
@@ -1,5 +1,7 @@
 //! Build context and distro configuration contracts.
 
+use std::collections::BTreeSet;
+use std::fmt;
 use std::path::Path;
 
 use crate::contracts::kernel::KernelInstallConfig;
@@ -19,26 +21,148 @@ pub trait DistroConfig: KernelInstallConfig {
     fn iso_label(&self) -> &str;
 
     /// Kernel modules required for boot.
-    fn boot_modules(&self) -> &[&str];
+    fn boot_modules(&self) -> &[String];
 
     /// Default shell for the system.
     fn default_shell(&self) -> &str;
 
     /// Init system type.
     fn init_system(&self) -> InitSystem;
+
+    /// Release version for os-release `VERSION_ID` (e.g., "2026.1").
+    fn os_version(&self) -> Option<&str> {
+        None
+    }
+
+    /// Project home page for os-release `HOME_URL` and the motd.
+    fn home_url(&self) -> Option<&str> {
+        None
+    }
+
+    /// ANSI SGR color for the distro name (os-release `ANSI_COLOR`).
+    fn ansi_color(&self) -> Option<&str> {
+        None
+    }
+
+    /// UID/GID ranges for system and regular accounts.
+    fn uid_ranges(&self) -> UidRanges {
+        UidRanges::default()
+    }
+
+    /// Feature flags selecting the image flavor; none by default.
+    fn features(&self) -> &Features {
+        &NO_FEATURES
+    }
+}
+
+static NO_FEATURES: Features = Features(BTreeSet::new());
+
+/// Named feature flags (e.g. `wifi`, `desktop`, `debug-tools`) that pick an
+/// image flavor from one variant definition. Components query them with
+/// [`Op::When`](crate::contracts::component::Op::When).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Features(BTreeSet<String>);
+
+impl Features {
+    /// Parse flag names: lowercase letters, digits and `-`, starting with a
+    /// letter.
+    pub fn parse<I, S>(names: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut features = BTreeSet::new();
+        for name in names {
+            let name = name.as_ref().trim();
+            let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                anyhow::bail!(
+                    "invalid feature flag '{}': use lowercase letters, digits and '-'",
+                    name
+                );
+            }
+            features.insert(name.to_string());
+        }
+        Ok(Self(features))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.iter().collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// System and regular account ID ranges, as in `login.defs`
+/// (`SYS_UID_MIN`..`SYS_UID_MAX`, `UID_MIN`..`UID_MAX`). Both ends are
+/// inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UidRanges {
+    pub system: (u32, u32),
+    pub regular: (u32, u32),
+}
+
+impl Default for UidRanges {
+    fn default() -> Self {
+        Self {
+            system: (1, 999),
+            regular: (1000, 60000),
+        }
+    }
+}
+
+impl UidRanges {
+    pub fn is_system(&self, id: u32) -> bool {
+        (self.system.0..=self.system.1).contains(&id)
+    }
+
+    pub fn is_regular(&self, id: u32) -> bool {
+        (self.regular.0..=self.regular.1).contains(&id)
+    }
 }
 
 /// Package manager types supported by distro-builder.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PackageManager {
     /// RPM (used by LevitateOS)
     Rpm,
     /// APK (used by AcornOS, IuppiterOS / Alpine Linux)
     Apk,
+    /// pacman (Arch-based variants)
+    Pacman,
+}
+
+impl PackageManager {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PackageManager::Rpm => "rpm",
+            PackageManager::Apk => "apk",
+            PackageManager::Pacman => "pacman",
+        }
+    }
 }
 
 /// Init system types supported by distro-builder.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum InitSystem {
     /// systemd (used by LevitateOS)
     Systemd,
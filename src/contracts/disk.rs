@@ -1,8 +1,15 @@
 //! Disk image building contracts.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::path::Path;
 
+use crate::artifact::disk::bootloader::Bootloader;
+use crate::artifact::loader::{LoaderConf, LoaderEntry};
+use crate::build::first_boot::FirstBootService;
+use crate::contracts::context::PackageManager;
+use crate::identity::IdentityPolicy;
+use crate::qemu::DiskBootTest;
+
 /// UUIDs for disk image partitions.
 #[derive(Debug, Clone)]
 pub struct DiskUuids {
@@ -19,6 +26,20 @@ pub trait DiskImageConfig {
     /// Hostname to write to /etc/hostname.
     fn hostname(&self) -> &str;
 
+    /// Identity applied before `prepare_rootfs`, so the distro can still
+    /// override any of it. Installed disks boot with an uninitialized
+    /// machine-id so each install gets its own.
+    fn identity(&self) -> IdentityPolicy {
+        IdentityPolicy::first_boot().with_hostname(self.hostname())
+    }
+
+    /// First-boot service installed after `prepare_rootfs` and the
+    /// [`DiskImageConfig::extra_packages`] are in place, or `None`
+    /// to leave machine-id, SSH host keys and root size as built.
+    fn first_boot(&self) -> Option<FirstBootService> {
+        Some(FirstBootService::default())
+    }
+
     /// Boot entry filename (e.g., "iuppiter.conf").
     fn boot_entry_filename(&self) -> &str;
 
@@ -28,13 +49,31 @@ pub trait DiskImageConfig {
     /// Loader config content (loader/loader.conf).
     fn loader_config_content(&self) -> String;
 
+    /// Typed boot entry. Defaults to parsing `boot_entry_content`, so raw
+    /// strings get the same validation as entries built with [`LoaderEntry`].
+    fn loader_entry(&self, partuuid: &str) -> Result<LoaderEntry> {
+        LoaderEntry::parse(&self.boot_entry_content(partuuid))
+            .with_context(|| format!("invalid boot entry '{}'", self.boot_entry_filename()))
+    }
+
+    /// Typed loader config. Defaults to parsing `loader_config_content`.
+    fn loader_conf(&self) -> Result<LoaderConf> {
+        LoaderConf::parse(&self.loader_config_content()).context("invalid loader.conf")
+    }
+
     /// Path to kernel image.
     fn kernel_path(&self) -> &Path;
 
     /// Path to initramfs for installed system.
     fn initramfs_path(&self) -> &Path;
 
-    /// Path to systemd-boot EFI binary.
+    /// Bootloader installed on the EFI partition.
+    fn bootloader(&self) -> Bootloader {
+        Bootloader::SystemdBoot
+    }
+
+    /// Path to the EFI binary for [`DiskImageConfig::bootloader`]
+    /// (systemd-boot, a standalone GRUB image, or Limine's BOOTX64.EFI).
     fn bootloader_efi_path(&self) -> &Path;
 
     /// EFI partition size in MB.
@@ -49,11 +88,32 @@ pub trait DiskImageConfig {
     /// Prepare the rootfs for disk installation.
     /// Called after copying rootfs-staging to work dir.
     /// Distro implements: fstab, services, hostname, passwords, etc.
+    /// [`FstabBuilder::from_uuids`](crate::artifact::disk::FstabBuilder::from_uuids)
+    /// covers the standard root + ESP fstab.
     fn prepare_rootfs(&self, rootfs: &Path, uuids: &DiskUuids) -> Result<()>;
 
+    /// Package manager of the rootfs, used for [`DiskImageConfig::extra_packages`].
+    fn package_manager(&self) -> Option<PackageManager> {
+        None
+    }
+
+    /// Packages installed into this image only (e.g. a cloud agent that the
+    /// bare-metal image leaves out). They are installed by chrooting into the
+    /// prepared rootfs, after `prepare_rootfs` and before partitioning.
+    fn extra_packages(&self) -> Vec<String> {
+        vec![]
+    }
+
     /// Additional host tools required beyond the base set.
     /// Each entry is (tool_name, package_name).
     fn extra_required_tools(&self) -> Vec<(&str, &str)> {
         vec![]
     }
+
+    /// Boot test run on the finished image, or `None` to skip it.
+    /// [`SmokeTestConfig::disk_boot_test`](crate::smoke_test::SmokeTestConfig::disk_boot_test)
+    /// builds one from the variant's smoke test settings.
+    fn boot_test(&self) -> Option<DiskBootTest> {
+        None
+    }
 }
@@ -1,5 +1,60 @@
 //! Kernel build and installation contracts.
 
+use serde::{Deserialize, Serialize};
+
+/// Compression applied to kernel modules after installation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleCompression {
+    /// Leave modules as installed by `make modules_install`.
+    #[default]
+    None,
+    /// Follow the kernel's `CONFIG_MODULE_COMPRESS_*` setting.
+    Auto,
+    Xz,
+    Zstd,
+    Gzip,
+}
+
+impl ModuleCompression {
+    /// File extension appended to `.ko`, if any.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            ModuleCompression::None | ModuleCompression::Auto => None,
+            ModuleCompression::Xz => Some("xz"),
+            ModuleCompression::Zstd => Some("zst"),
+            ModuleCompression::Gzip => Some("gz"),
+        }
+    }
+}
+
+/// Compression applied to staged firmware blobs.
+///
+/// The kernel loads `<name>.xz` / `<name>.zst` when built with
+/// `CONFIG_FW_LOADER_COMPRESS` and the matching `CONFIG_FW_LOADER_COMPRESS_*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirmwareCompression {
+    /// Leave firmware uncompressed.
+    #[default]
+    None,
+    /// Best format the kernel config supports (zstd, then xz), or none.
+    Auto,
+    Xz,
+    Zstd,
+}
+
+impl FirmwareCompression {
+    /// File extension appended to compressed blobs, if any.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            FirmwareCompression::None | FirmwareCompression::Auto => None,
+            FirmwareCompression::Xz => Some("xz"),
+            FirmwareCompression::Zstd => Some("zst"),
+        }
+    }
+}
+
 /// Configuration for kernel installation.
 ///
 /// Implemented by distro-specific configs to customize
@@ -10,4 +65,31 @@ pub trait KernelInstallConfig {
 
     /// Kernel filename in /boot (e.g., "vmlinuz").
     fn kernel_filename(&self) -> &str;
+
+    /// Compression for installed modules. Defaults to none.
+    fn module_compression(&self) -> ModuleCompression {
+        ModuleCompression::None
+    }
+
+    /// Strip debug info from installed modules. Defaults to false.
+    fn strip_modules(&self) -> bool {
+        false
+    }
+
+    /// Compression for staged firmware. Defaults to none.
+    fn firmware_compression(&self) -> FirmwareCompression {
+        FirmwareCompression::None
+    }
+
+    /// Modules that must be installed, loadable or built in
+    /// (e.g. `erofs`, `overlay`).
+    fn required_modules(&self) -> &[String] {
+        &[]
+    }
+
+    /// Modules that must not ship: module names, or paths under the
+    /// version directory such as `kernel/drivers/staging`.
+    fn forbidden_modules(&self) -> &[String] {
+        &[]
+    }
 }
@@ -6,6 +6,6 @@ pub mod disk;
 pub mod kernel;
 
 pub use component::{Installable, Op, Phase};
-pub use context::{BuildContext, DistroConfig, InitSystem, PackageManager};
+pub use context::{BuildContext, DistroConfig, Features, InitSystem, PackageManager, UidRanges};
 pub use disk::{DiskImageConfig, DiskUuids};
-pub use kernel::KernelInstallConfig;
+pub use kernel::{KernelInstallConfig, ModuleCompression};
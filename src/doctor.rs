@@ -0,0 +1,571 @@
+//! Environment diagnosis for `distro-builder doctor`.
+//!
+//! A new contributor used to discover a missing tool, an unset recipe
+//! binary or a half-written store one failed build at a time. The doctor
+//! runs every check in one pass and collects the outcomes in a
+//! [`DoctorReport`] instead of stopping at the first problem, then prints
+//! the problems as numbered remediation steps, most severe first:
+//!
+//! - blocker: a build or boot test fails until it is fixed
+//! - warning: builds work, but slower or with less checking
+//! - note: worth knowing, nothing to fix
+//!
+//! This module holds the checks that need no variant contract: host tool
+//! profiles ([`check_tools`]), the checkout layout ([`check_repo_layout`]),
+//! the artifact store ([`check_store`]) and QEMU ([`check_qemu`]). The CLI
+//! adds contract lint for each variant.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::artifact_store::ArtifactStore;
+use crate::error::ErrorKind;
+use crate::preflight::{parse_version, version_at_least, RequiredTools};
+use crate::process::{self, Cmd};
+use crate::repo_layout::RepoLayout;
+use crate::uefi_firmware::{find_firmware, FirmwareArch, FirmwareFlavor, UEFI_CODE_ENV};
+
+/// Free space below which builds are likely to run out mid-way.
+const MIN_FREE_BYTES: u64 = 20 << 30;
+
+/// Oldest QEMU with `-machine memory-backend=` (pstore capture).
+const MIN_QEMU_VERSION: &str = "5.0";
+
+/// Broken store entries listed before the rest are summarized.
+const MAX_LISTED_PROBLEMS: usize = 5;
+
+/// How urgent a finding is; the report lists findings in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Blocker,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Blocker => "blocker",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        }
+    }
+}
+
+/// One problem found by a check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Failure class of a blocker; the doctor exits with the code of the
+    /// first one.
+    pub kind: Option<ErrorKind>,
+    /// Check that found it (`host tools`, `artifact store`, ...).
+    pub check: String,
+    pub problem: String,
+    /// What to do about it; empty for notes.
+    pub remediation: String,
+}
+
+impl Finding {
+    pub fn blocker(
+        check: &str,
+        kind: ErrorKind,
+        problem: impl Into<String>,
+        remediation: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity: Severity::Blocker,
+            kind: Some(kind),
+            check: check.to_string(),
+            problem: problem.into(),
+            remediation: remediation.into(),
+        }
+    }
+
+    pub fn warning(
+        check: &str,
+        problem: impl Into<String>,
+        remediation: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity: Severity::Warning,
+            kind: None,
+            check: check.to_string(),
+            problem: problem.into(),
+            remediation: remediation.into(),
+        }
+    }
+
+    pub fn note(check: &str, problem: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Note,
+            kind: None,
+            check: check.to_string(),
+            problem: problem.into(),
+            remediation: String::new(),
+        }
+    }
+}
+
+/// Outcome of every doctor check.
+#[derive(Debug, Clone, Default)]
+pub struct DoctorReport {
+    passed: Vec<String>,
+    findings: Vec<Finding>,
+}
+
+impl DoctorReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a check that found nothing, e.g. `repo layout (monorepo)`.
+    pub fn pass(&mut self, check: impl Into<String>) {
+        self.passed.push(check.into());
+    }
+
+    pub fn add(&mut self, finding: Finding) {
+        self.findings.push(finding);
+    }
+
+    /// Findings by severity; checks keep their order within one severity.
+    pub fn findings(&self) -> Vec<&Finding> {
+        let mut findings: Vec<&Finding> = self.findings.iter().collect();
+        findings.sort_by_key(|finding| finding.severity);
+        findings
+    }
+
+    /// Kind of the most severe blocker, `None` when nothing blocks a build.
+    pub fn blocker_kind(&self) -> Option<ErrorKind> {
+        self.findings()
+            .into_iter()
+            .find(|finding| finding.severity == Severity::Blocker)
+            .and_then(|finding| finding.kind)
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    /// Passed checks, then the findings as numbered steps.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for check in &self.passed {
+            let _ = writeln!(out, "  ok  {}", check);
+        }
+        let findings = self.findings();
+        if findings.is_empty() {
+            out.push_str("\nNo problems found.\n");
+            return out;
+        }
+        let _ = writeln!(
+            out,
+            "\n{} blocker(s), {} warning(s), {} note(s). Fix them in this order:",
+            self.count(Severity::Blocker),
+            self.count(Severity::Warning),
+            self.count(Severity::Note)
+        );
+        for (index, finding) in findings.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "\n{:>3}. [{}] {}: {}",
+                index + 1,
+                finding.severity.label(),
+                finding.check,
+                finding.problem.replace('\n', "\n       ")
+            );
+            if !finding.remediation.is_empty() {
+                let _ = writeln!(out, "       -> {}", finding.remediation);
+            }
+        }
+        out
+    }
+}
+
+/// Check the host tools of each `(profile, tools)`, where a profile is the
+/// set one kind of build registers (see [`crate::preflight`]). Missing tools
+/// are grouped by the package that provides them, so each package is one
+/// step however many profiles need it.
+pub fn check_tools(report: &mut DoctorReport, profiles: &[(String, RequiredTools)]) {
+    const CHECK: &str = "host tools";
+    // Package -> (tools, profiles needing them).
+    let mut missing: BTreeMap<&str, (BTreeSet<&str>, BTreeSet<&str>)> = BTreeMap::new();
+    let mut outdated = BTreeSet::new();
+    for (profile, tools) in profiles {
+        for (tool, package, _) in tools.missing() {
+            let entry = missing.entry(package).or_default();
+            entry.0.insert(tool);
+            entry.1.insert(profile.as_str());
+        }
+        outdated.extend(tools.outdated());
+    }
+
+    for (package, (tools, profiles)) in &missing {
+        report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::MissingTool,
+            format!("{} not found (needed by {})", join(tools), join(profiles)),
+            format!("install the '{}' package", package),
+        ));
+    }
+    for line in &outdated {
+        report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::MissingTool,
+            line.clone(),
+            "upgrade the package, or provide the tool through the tool container",
+        ));
+    }
+    if missing.is_empty() && outdated.is_empty() {
+        let names: Vec<&str> = profiles.iter().map(|(name, _)| name.as_str()).collect();
+        report.pass(format!("{} ({})", CHECK, names.join(", ")));
+    }
+}
+
+/// Check the checkout at `repo_root`: its layout resolves, the variants and
+/// recipes directories exist and a recipe binary can be found. Returns the
+/// layout when it resolves.
+pub fn check_repo_layout(report: &mut DoctorReport, repo_root: &Path) -> Option<RepoLayout> {
+    const CHECK: &str = "repo layout";
+    let layout = match RepoLayout::at(repo_root) {
+        Ok(layout) => layout,
+        Err(err) => {
+            report.add(Finding::blocker(
+                CHECK,
+                ErrorKind::InvalidConfig,
+                format!("{:#}", err),
+                "fix `layout` in distro-builder.toml or the layout environment variables",
+            ));
+            return None;
+        }
+    };
+    let before = report.findings.len();
+
+    if !layout.variants_dir().is_dir() {
+        report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::InvalidConfig,
+            format!(
+                "variants directory {} does not exist",
+                layout.variants_dir().display()
+            ),
+            "run from a checkout with distro-variants/, or scaffold one with `distro-builder new-variant`",
+        ));
+    } else if fs::read_dir(layout.variants_dir())
+        .map(|entries| entries.filter_map(Result::ok).all(|e| !e.path().is_dir()))
+        .unwrap_or(true)
+    {
+        report.add(Finding::warning(
+            CHECK,
+            format!("{} holds no variants", layout.variants_dir().display()),
+            "scaffold one with `distro-builder new-variant <distro_id> --init systemd|openrc`",
+        ));
+    }
+    if !layout.recipes_dir().is_dir() {
+        report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::InvalidConfig,
+            format!(
+                "recipes directory {} does not exist",
+                layout.recipes_dir().display()
+            ),
+            "check out the builder's recipes or point DISTRO_BUILDER_RECIPES_DIR at them",
+        ));
+    }
+    let recipe = crate::recipe::locate_recipe(layout.root());
+    if let Err(err) = &recipe {
+        report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::MissingTool,
+            format!("{:#}", err),
+            "set RECIPE_BIN=/path/to/recipe, or install recipe into PATH",
+        ));
+    }
+
+    if report.findings.len() == before {
+        report.pass(format!(
+            "{} ({:?} at {}; recipe: {})",
+            CHECK,
+            layout.mode(),
+            layout.root().display(),
+            recipe.unwrap_or_default()
+        ));
+    }
+    Some(layout)
+}
+
+/// Check the artifact store of `repo_root`: every index entry's blob is
+/// present and of the recorded size (and hash, with `rehash`), and the
+/// filesystem has room for another build.
+pub fn check_store(report: &mut DoctorReport, repo_root: &Path, rehash: bool) {
+    const CHECK: &str = "artifact store";
+    let root = match crate::repo_config::artifacts_root(repo_root) {
+        Ok(root) => root,
+        Err(err) => {
+            report.add(Finding::blocker(
+                CHECK,
+                ErrorKind::InvalidConfig,
+                format!("{:#}", err),
+                "fix the artifacts root in distro-builder.toml",
+            ));
+            return;
+        }
+    };
+    let before = report.findings.len();
+
+    let space_probe = root
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or(repo_root);
+    if let Ok(free) = fs2::available_space(space_probe) {
+        if free < MIN_FREE_BYTES {
+            report.add(Finding::warning(
+                CHECK,
+                format!(
+                    "only {} MiB free on the filesystem of {}",
+                    free >> 20,
+                    root.display()
+                ),
+                format!(
+                    "free at least {} GiB, e.g. with `distro-builder clean --work --store-unreferenced`",
+                    MIN_FREE_BYTES >> 30
+                ),
+            ));
+        }
+    }
+
+    if !root.exists() {
+        report.add(Finding::note(
+            CHECK,
+            format!(
+                "no store at {} yet; the first build creates it",
+                root.display()
+            ),
+        ));
+        return;
+    }
+    let compression = crate::repo_config::RepoConfig::load_or_default(repo_root)
+        .compression
+        .store;
+    let verified =
+        ArtifactStore::open_existing(&root, compression).and_then(|store| store.verify(rehash));
+    let verified = match verified {
+        Ok(verified) => verified,
+        Err(err) => {
+            report.add(Finding::warning(
+                CHECK,
+                format!("{:#}", err),
+                format!(
+                    "move {} aside; the next build starts a fresh store",
+                    root.display()
+                ),
+            ));
+            return;
+        }
+    };
+    if !verified.problems.is_empty() {
+        let mut problem = format!(
+            "{} of {} index entries are broken:",
+            verified.problems.len(),
+            verified.checked_entries
+        );
+        for line in verified.problems.iter().take(MAX_LISTED_PROBLEMS) {
+            problem.push_str(&format!("\n  {}", line));
+        }
+        if verified.problems.len() > MAX_LISTED_PROBLEMS {
+            problem.push_str(&format!(
+                "\n  ... and {} more",
+                verified.problems.len() - MAX_LISTED_PROBLEMS
+            ));
+        }
+        report.add(Finding::warning(
+            CHECK,
+            problem,
+            "delete the listed index files (builds store them again), then run `distro-builder clean --store-unreferenced`",
+        ));
+    }
+
+    if report.findings.len() == before {
+        report.pass(format!(
+            "{} ({} entries {}, {})",
+            CHECK,
+            verified.checked_entries,
+            if rehash { "rehashed" } else { "checked" },
+            root.display()
+        ));
+    }
+}
+
+/// Probe what boot tests need from QEMU: a recent enough binary, usable
+/// KVM and OVMF firmware. A missing QEMU is left to [`check_tools`], whose
+/// release profiles register it.
+pub fn check_qemu(report: &mut DoctorReport) {
+    const CHECK: &str = "qemu";
+    const QEMU: &str = "qemu-system-x86_64";
+    if !process::exists(QEMU) {
+        return;
+    }
+    let before = report.findings.len();
+
+    let version = Cmd::new(QEMU)
+        .arg("--version")
+        .allow_fail()
+        .run()
+        .ok()
+        .and_then(|result| parse_version(&result.stdout));
+    match &version {
+        Some(version) if version_at_least(version, MIN_QEMU_VERSION) => {}
+        Some(version) => report.add(Finding::warning(
+            CHECK,
+            format!(
+                "{} {} is older than {}; boot tests cannot capture pstore crash records",
+                QEMU,
+                dotted(version),
+                MIN_QEMU_VERSION
+            ),
+            "upgrade QEMU",
+        )),
+        None => report.add(Finding::warning(
+            CHECK,
+            format!("`{} --version` printed no version", QEMU),
+            "check that the QEMU installation works",
+        )),
+    }
+
+    let kvm = Path::new("/dev/kvm");
+    if !kvm.exists() {
+        report.add(Finding::warning(
+            CHECK,
+            "/dev/kvm does not exist; boot tests run under emulation, many times slower",
+            "enable virtualization in the firmware settings and load kvm_intel or kvm_amd",
+        ));
+    } else if fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(kvm)
+        .is_err()
+    {
+        report.add(Finding::warning(
+            CHECK,
+            "/dev/kvm is not accessible to this user; boot tests run under emulation",
+            "add yourself to the 'kvm' group and log in again",
+        ));
+    }
+
+    let firmware = find_firmware(FirmwareArch::X86_64, FirmwareFlavor::Standard);
+    if let Err(err) = &firmware {
+        report.add(Finding::blocker(
+            CHECK,
+            ErrorKind::MissingTool,
+            format!("{:#}", err),
+            format!(
+                "install edk2-ovmf (Fedora) or ovmf (Debian, Alpine), or set {}",
+                UEFI_CODE_ENV
+            ),
+        ));
+    }
+
+    if report.findings.len() == before {
+        report.pass(format!(
+            "{} ({}, KVM, firmware {})",
+            CHECK,
+            version.as_deref().map(dotted).unwrap_or_default(),
+            firmware
+                .map(|f| f.code.display().to_string())
+                .unwrap_or_default()
+        ));
+    }
+}
+
+fn join<S: AsRef<str>>(items: impl IntoIterator<Item = S>) -> String {
+    items
+        .into_iter()
+        .map(|item| item.as_ref().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn dotted(version: &[u64]) -> String {
+    version
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_report_orders_findings_by_severity() {
+        let mut report = DoctorReport::new();
+        report.pass("repo layout");
+        report.add(Finding::note("artifact store", "no store yet"));
+        report.add(Finding::warning("qemu", "no KVM", "load kvm_amd"));
+        report.add(Finding::blocker(
+            "contract",
+            ErrorKind::InvalidContract,
+            "levitate: bad",
+            "fix it",
+        ));
+        assert_eq!(report.blocker_kind(), Some(ErrorKind::InvalidContract));
+
+        let text = report.render();
+        let blocker = text.find("1. [blocker] contract: levitate: bad").unwrap();
+        let warning = text.find("2. [warning] qemu: no KVM").unwrap();
+        let note = text.find("3. [note] artifact store").unwrap();
+        assert!(blocker < warning && warning < note, "{text}");
+        assert!(text.starts_with("  ok  repo layout\n"), "{text}");
+        assert!(text.contains("-> load kvm_amd"), "{text}");
+    }
+
+    #[test]
+    fn test_missing_tools_grouped_by_package() {
+        let mut iso = RequiredTools::new();
+        iso.require_all(
+            "test",
+            &[
+                ("definitely-missing-mmd", "mtools"),
+                ("definitely-missing-mcopy", "mtools"),
+            ],
+        );
+        let mut release = RequiredTools::new();
+        release.require("test", "definitely-missing-mcopy", "mtools");
+        release.require("test", "ls", "coreutils");
+
+        let mut report = DoctorReport::new();
+        check_tools(
+            &mut report,
+            &[
+                ("iso-pipeline".to_string(), iso),
+                ("release".to_string(), release),
+            ],
+        );
+        let findings = report.findings();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].problem,
+            "definitely-missing-mcopy, definitely-missing-mmd not found (needed by iso-pipeline, release)"
+        );
+        assert_eq!(report.blocker_kind(), Some(ErrorKind::MissingTool));
+    }
+
+    #[test]
+    fn test_store_check_without_store_is_a_note() {
+        let temp = TempDir::new().unwrap();
+        let mut report = DoctorReport::new();
+        check_store(&mut report, temp.path(), false);
+        assert_eq!(report.blocker_kind(), None);
+        assert!(report
+            .findings()
+            .iter()
+            .any(|finding| finding.severity == Severity::Note));
+    }
+}
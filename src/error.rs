@@ -0,0 +1,228 @@
+//! Error kinds at the public API boundary.
+//!
+//! The crate reports failures as [`anyhow::Error`] so every layer can add
+//! context. Where a failure has a cause a caller may want to act on (a
+//! missing host tool, an invalid contract, a failed boot test), the error is
+//! tagged with an [`ErrorKind`]; the context chain and message stay as they
+//! were. [`kind_of`] recovers the kind from any error, and the CLI maps it to
+//! a stable exit code and to the `kind` field of `--json` error output.
+//!
+//! ```rust,ignore
+//! use distro_builder::error::{kind_of, ErrorKind};
+//!
+//! match builder.run(&stage) {
+//!     Err(err) if kind_of(&err) == ErrorKind::MissingTool => install_tools(),
+//!     other => other?,
+//! }
+//! ```
+
+use serde::Serialize;
+use std::fmt;
+
+/// What class of failure an error is.
+///
+/// Exit codes are part of the CLI contract: new kinds get new codes, and
+/// existing codes never change meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// Anything not classified below.
+    Internal,
+    /// Malformed command line.
+    Usage,
+    /// A required host tool is not installed.
+    MissingTool,
+    /// An external tool ran and failed.
+    ToolFailed,
+    /// A builder configuration file (`distro-builder.toml`, variant-local
+    /// settings) is invalid.
+    InvalidConfig,
+    /// The variant contract failed to load or validate.
+    InvalidContract,
+    /// The checkout violates the legacy-binding policy.
+    PolicyViolation,
+    /// A source could not be downloaded or failed verification.
+    DownloadFailed,
+    /// The built image did not pass its boot test.
+    BootTestFailed,
+    /// The build was stopped by SIGINT, SIGTERM or SIGHUP.
+    Interrupted,
+}
+
+impl ErrorKind {
+    pub const ALL: &'static [ErrorKind] = &[
+        Self::Internal,
+        Self::Usage,
+        Self::MissingTool,
+        Self::ToolFailed,
+        Self::InvalidConfig,
+        Self::InvalidContract,
+        Self::PolicyViolation,
+        Self::DownloadFailed,
+        Self::BootTestFailed,
+        Self::Interrupted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Internal => "internal",
+            Self::Usage => "usage",
+            Self::MissingTool => "missing-tool",
+            Self::ToolFailed => "tool-failed",
+            Self::InvalidConfig => "invalid-config",
+            Self::InvalidContract => "invalid-contract",
+            Self::PolicyViolation => "policy-violation",
+            Self::DownloadFailed => "download-failed",
+            Self::BootTestFailed => "boot-test-failed",
+            Self::Interrupted => "interrupted",
+        }
+    }
+
+    /// Process exit code the CLI uses for this kind.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Internal => 1,
+            Self::Usage => 2,
+            Self::MissingTool => 3,
+            Self::ToolFailed => 4,
+            Self::InvalidConfig => 5,
+            Self::InvalidContract => 6,
+            Self::PolicyViolation => 7,
+            Self::DownloadFailed => 8,
+            Self::BootTestFailed => 9,
+            // Shell convention for a process stopped by SIGINT.
+            Self::Interrupted => 130,
+        }
+    }
+
+    /// Kind behind a CLI exit code; unknown codes are [`ErrorKind::Internal`].
+    pub fn from_exit_code(code: i32) -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|kind| i32::from(kind.exit_code()) == code)
+            .unwrap_or(Self::Internal)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error tagged with its [`ErrorKind`].
+///
+/// Displays exactly like the wrapped error, so tagging never changes what
+/// the user reads.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    inner: anyhow::Error,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, err: impl Into<anyhow::Error>) -> Self {
+        Self {
+            kind,
+            inner: err.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+/// Tag the error of a result with a kind.
+pub trait ResultExt<T> {
+    fn error_kind(self, kind: ErrorKind) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
+    fn error_kind(self, kind: ErrorKind) -> anyhow::Result<T> {
+        self.map_err(|err| Error::new(kind, err).into())
+    }
+}
+
+/// Kind of `err`: the outermost tag in its chain, or
+/// [`ErrorKind::Internal`] when nothing tagged it.
+pub fn kind_of(err: &anyhow::Error) -> ErrorKind {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<Error>().map(Error::kind))
+        .unwrap_or(ErrorKind::Internal)
+}
+
+/// Machine-readable form of `err` for `--json` output.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    pub exit_code: u8,
+    pub message: String,
+    /// The message followed by each cause, outermost first.
+    pub chain: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn new(err: &anyhow::Error) -> Self {
+        let kind = kind_of(err);
+        Self {
+            kind,
+            exit_code: kind.exit_code(),
+            message: err.to_string(),
+            chain: err.chain().map(|cause| cause.to_string()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_kind_survives_context() {
+        let err = Err::<(), _>(anyhow!("mkfs.erofs not found"))
+            .error_kind(ErrorKind::MissingTool)
+            .context("building rootfs")
+            .unwrap_err();
+        assert_eq!(kind_of(&err), ErrorKind::MissingTool);
+        assert_eq!(
+            format!("{:#}", err),
+            "building rootfs: mkfs.erofs not found"
+        );
+
+        let report = ErrorReport::new(&err);
+        assert_eq!(report.exit_code, 3);
+        assert_eq!(
+            report.chain,
+            vec!["building rootfs", "mkfs.erofs not found"]
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["kind"], "missing-tool");
+
+        assert_eq!(kind_of(&anyhow!("plain")), ErrorKind::Internal);
+    }
+
+    #[test]
+    fn test_exit_codes_are_unique() {
+        let codes: HashSet<u8> = ErrorKind::ALL.iter().map(|kind| kind.exit_code()).collect();
+        assert_eq!(codes.len(), ErrorKind::ALL.len());
+        for kind in ErrorKind::ALL {
+            assert_eq!(ErrorKind::from_exit_code(kind.exit_code().into()), *kind);
+        }
+    }
+}
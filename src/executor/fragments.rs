@@ -0,0 +1,172 @@
+//! sysusers.d and tmpfiles.d generation from component ops.
+//!
+//! On systemd distros, users, groups and volatile directories are declared
+//! to `systemd-sysusers` and `systemd-tmpfiles` instead of being patched
+//! into `/etc/passwd` and pre-created in the image. That keeps them correct
+//! after a factory reset or an empty `/var`, and lets systemd merge them
+//! with package-provided entries. OpenRC distros keep the direct passwd
+//! writes from [`super::users`].
+
+use anyhow::{bail, Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::contracts::component::Op;
+
+/// Directory for generated sysusers.d fragments, relative to the rootfs.
+pub const SYSUSERS_DIR: &str = "usr/lib/sysusers.d";
+
+/// Directory for generated tmpfiles.d fragments, relative to the rootfs.
+pub const TMPFILES_DIR: &str = "usr/lib/tmpfiles.d";
+
+/// Prefixes whose contents may be missing at boot and must be recreated.
+const VOLATILE_PREFIXES: &[&str] = &["var/", "run/", "tmp/"];
+
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// `sysusers.d` lines for every `Op::User`/`Op::Group` in `ops`.
+///
+/// Groups are emitted before users so `u name uid:gid` can reference them.
+/// Returns `None` when there is nothing to declare.
+pub fn sysusers_fragment(ops: &[Op]) -> Option<String> {
+    let mut groups = String::new();
+    let mut users = String::new();
+    for op in ops {
+        match op {
+            Op::Group { name, gid } => {
+                let _ = writeln!(groups, "g {} {}", name, gid);
+            }
+            Op::User {
+                name,
+                uid,
+                gid,
+                home,
+                shell,
+            } => {
+                let _ = writeln!(
+                    users,
+                    "u {} {}:{} \"{}\" {} {}",
+                    name, uid, gid, name, home, shell
+                );
+            }
+            _ => {}
+        }
+    }
+    if groups.is_empty() && users.is_empty() {
+        return None;
+    }
+    Some(groups + &users)
+}
+
+/// `tmpfiles.d` lines for directory ops under `/var`, `/run` and `/tmp`.
+///
+/// Directories elsewhere live in the read-only image and need no entry.
+pub fn tmpfiles_fragment(ops: &[Op]) -> Option<String> {
+    let mut out = String::new();
+    let mut add = |path: &str, mode: u32| {
+        let path = path.trim_start_matches('/');
+        if VOLATILE_PREFIXES.iter().any(|p| path.starts_with(p)) {
+            let _ = writeln!(out, "d /{} {:04o} root root -", path, mode);
+        }
+    };
+    for op in ops {
+        match op {
+            Op::Dir(path) => add(path, DEFAULT_DIR_MODE),
+            Op::DirMode(path, mode) => add(path, *mode),
+            Op::Dirs(paths) => paths.iter().for_each(|p| add(p, DEFAULT_DIR_MODE)),
+            _ => {}
+        }
+    }
+    (!out.is_empty()).then_some(out)
+}
+
+/// Write `<name>.conf` fragments for a component's ops into `staging`.
+pub fn write_fragments(staging: &Path, name: &str, ops: &[Op]) -> Result<()> {
+    if name.is_empty() || name.contains('/') {
+        bail!("invalid fragment name '{}'", name);
+    }
+    let fragments = [
+        (SYSUSERS_DIR, sysusers_fragment(ops)),
+        (TMPFILES_DIR, tmpfiles_fragment(ops)),
+    ];
+    for (dir, content) in fragments {
+        let Some(content) = content else {
+            continue;
+        };
+        let dir = staging.join(dir);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.conf", name));
+        fs::write(
+            &path,
+            format!("# Generated by distro-builder for {}\n{}", name, content),
+        )
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// File name for a component's fragments: lowercase, spaces to dashes.
+pub fn fragment_name(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' => c.to_ascii_lowercase(),
+            'a'..='z' | '0'..='9' | '-' | '_' => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn ops() -> Vec<Op> {
+        vec![
+            Op::User {
+                name: "sshd".into(),
+                uid: 74,
+                gid: 74,
+                home: "/var/empty/sshd".into(),
+                shell: "/sbin/nologin".into(),
+            },
+            Op::Group {
+                name: "sshd".into(),
+                gid: 74,
+            },
+            Op::Dir("etc/ssh".into()),
+            Op::DirMode("var/empty/sshd".into(), 0o711),
+            Op::Dirs(vec!["run/sshd".into(), "usr/share/empty".into()]),
+        ]
+    }
+
+    #[test]
+    fn test_fragments() {
+        assert_eq!(
+            sysusers_fragment(&ops()).unwrap(),
+            "g sshd 74\nu sshd 74:74 \"sshd\" /var/empty/sshd /sbin/nologin\n"
+        );
+        assert_eq!(
+            tmpfiles_fragment(&ops()).unwrap(),
+            "d /var/empty/sshd 0711 root root -\nd /run/sshd 0755 root root -\n"
+        );
+        assert_eq!(sysusers_fragment(&[Op::Dir("etc".into())]), None);
+    }
+
+    #[test]
+    fn test_write_fragments() {
+        let temp = TempDir::new().unwrap();
+        write_fragments(temp.path(), &fragment_name("OpenSSH Server"), &ops()).unwrap();
+        let sysusers =
+            fs::read_to_string(temp.path().join(SYSUSERS_DIR).join("openssh-server.conf")).unwrap();
+        assert!(sysusers.starts_with("# Generated by distro-builder"));
+        assert!(temp
+            .path()
+            .join(TMPFILES_DIR)
+            .join("openssh-server.conf")
+            .is_file());
+        assert!(write_fragments(temp.path(), "../x", &ops()).is_err());
+    }
+}
@@ -24,21 +24,147 @@
 pub mod binaries;
 pub mod directories;
 pub mod files;
+pub mod fragments;
 pub mod openrc;
+pub mod privileges;
 pub mod users;
 
 use crate::build::context::BuildContext;
+use crate::contracts::{InitSystem, Installable};
+use anyhow::Context;
+use std::fs;
+use std::ops::Deref;
 use std::path::Path;
 
+/// File in the build output directory listing the ops
+/// [`execute_components_ctx`] runs, as rendered by
+/// [`ops_manifest`](super::ops_manifest).
+pub const OPS_MANIFEST_FILENAME: &str = "ops-manifest.txt";
+
 /// Execute a generic operation - BuildContext adapter version.
 ///
 /// This is a wrapper for use with types that implement the BuildContext trait.
-/// It delegates to execute_generic_op.
+/// It delegates to execute_generic_op, first checking `Op::User` against the
+/// distro's [`UidRanges`](crate::contracts::context::UidRanges) and running
+/// `Op::When` ops only when the distro enables the feature.
 pub fn execute_generic_op_ctx<C>(ctx: &C, op: &super::Op) -> anyhow::Result<()>
 where
     C: BuildContext,
 {
-    execute_generic_op(ctx.source(), ctx.staging(), op)
+    let mut resolved = Vec::new();
+    resolve_ops_ctx(ctx, std::slice::from_ref(op), &mut resolved)?;
+    for op in &resolved {
+        execute_generic_op(ctx.source(), ctx.staging(), op)?;
+    }
+    Ok(())
+}
+
+/// Execute a component's generic operations for the distro's init system -
+/// BuildContext adapter version.
+///
+/// Ops are put in [`canonical_ops`](super::canonical_ops) order, then
+/// `Op::When` and `Op::User` are handled as in [`execute_generic_op_ctx`]
+/// and the resulting ops run as in [`execute_generic_ops_for_init`] with the
+/// init system of `ctx.config()`.
+pub fn execute_generic_ops_ctx<C>(ctx: &C, component: &str, ops: &[super::Op]) -> anyhow::Result<()>
+where
+    C: BuildContext,
+{
+    run_ops_ctx(ctx, component, ops.to_vec(), &mut |op| {
+        execute_generic_op(ctx.source(), ctx.staging(), op)
+    })
+}
+
+/// Install `components` in [`sort_components`](super::sort_components)
+/// order - BuildContext adapter version.
+///
+/// Each component's ops run as in [`execute_generic_ops_ctx`], except that
+/// binary and custom ops go to `distro_op` at their place in canonical
+/// order. The [`ops_manifest`](super::ops_manifest) of `components` is
+/// written to [`OPS_MANIFEST_FILENAME`] in `ctx.output()` before anything
+/// runs, so a failed build still records what it was executing.
+pub fn execute_components_ctx<C, P, F>(
+    ctx: &C,
+    components: &[P],
+    mut distro_op: F,
+) -> anyhow::Result<()>
+where
+    C: BuildContext,
+    P: Deref,
+    P::Target: Installable,
+    F: FnMut(&super::Op) -> anyhow::Result<()>,
+{
+    let output = ctx.output();
+    fs::create_dir_all(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let manifest_path = output.join(OPS_MANIFEST_FILENAME);
+    fs::write(&manifest_path, super::ops_manifest(components))
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+
+    let mut ordered: Vec<&P::Target> = components.iter().map(|c| c.deref()).collect();
+    super::sort_components(&mut ordered);
+    for component in ordered {
+        run_ops_ctx(ctx, component.name(), component.ops(), &mut distro_op)
+            .with_context(|| format!("Failed to install component '{}'", component.name()))?;
+    }
+    Ok(())
+}
+
+/// Canonicalize and resolve `ops`, then run them for the distro's init
+/// system. Resolving after canonicalizing keeps the ops of a `When` where
+/// the manifest lists it.
+fn run_ops_ctx<C>(
+    ctx: &C,
+    component: &str,
+    ops: Vec<super::Op>,
+    distro_op: &mut dyn FnMut(&super::Op) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    C: BuildContext,
+{
+    let mut resolved = Vec::new();
+    resolve_ops_ctx(ctx, &super::canonical_ops(ops), &mut resolved)?;
+    run_ops_for_init(
+        ctx.source(),
+        ctx.staging(),
+        component,
+        &resolved,
+        ctx.config().init_system(),
+        distro_op,
+    )
+}
+
+/// Flatten `Op::When` for the enabled features and check `Op::User`
+/// against the distro's UID ranges.
+fn resolve_ops_ctx<C>(ctx: &C, ops: &[super::Op], out: &mut Vec<super::Op>) -> anyhow::Result<()>
+where
+    C: BuildContext,
+{
+    for op in ops {
+        match op {
+            super::Op::When { feature, ops } => {
+                if ctx.config().features().contains(feature) {
+                    resolve_ops_ctx(ctx, ops, out)?;
+                }
+            }
+            super::Op::User {
+                name,
+                uid,
+                home,
+                shell,
+                ..
+            } => {
+                let ranges = ctx.config().uid_ranges();
+                if let Some(problem) =
+                    users::account_range_problem(name, *uid, home, shell, &ranges)
+                {
+                    anyhow::bail!("{}", problem);
+                }
+                out.push(op.clone());
+            }
+            other => out.push(other.clone()),
+        }
+    }
+    Ok(())
 }
 
 /// Execute a generic operation that doesn't require distro-specific handling.
@@ -103,6 +229,14 @@ pub fn execute_generic_op(source: &Path, staging: &Path, op: &super::Op) -> anyh
             users::handle_group(source, staging, name, *gid)?;
         }
 
+        // Privileged access
+        super::Op::SudoRule {
+            user_or_group,
+            rule,
+        } => {
+            privileges::handle_sudo_rule(source, staging, user_or_group, rule)?;
+        }
+
         // Binary operations - these need distro-specific handling for library deps
         super::Op::Bin(_) | super::Op::Sbin(_) | super::Op::Bins(_) | super::Op::Sbins(_) => {
             // These require distro-specific binary handling (library paths differ)
@@ -113,11 +247,69 @@ pub fn execute_generic_op(source: &Path, staging: &Path, op: &super::Op) -> anyh
         super::Op::Custom(_) => {
             anyhow::bail!("Custom operations require distro-specific handling");
         }
+
+        // Feature checks need the distro config
+        super::Op::When { feature, .. } => {
+            anyhow::bail!(
+                "Op::When('{}') needs the distro's feature flags: use execute_generic_op_ctx or resolve_features first",
+                feature
+            );
+        }
     }
 
     Ok(())
 }
 
+/// Execute a component's generic operations for a specific init system.
+///
+/// Ops run in [`canonical_ops`](super::canonical_ops) order. On OpenRC
+/// this is [`execute_generic_op`] for each op. On systemd, user
+/// and group ops are declared in a `sysusers.d` fragment instead of being
+/// written to `/etc/passwd`, and directories under `/var`, `/run` and `/tmp`
+/// additionally get a `tmpfiles.d` entry so they survive an empty `/var`.
+pub fn execute_generic_ops_for_init(
+    source: &Path,
+    staging: &Path,
+    component: &str,
+    ops: &[super::Op],
+    init: InitSystem,
+) -> anyhow::Result<()> {
+    let ops = super::canonical_ops(ops.to_vec());
+    run_ops_for_init(source, staging, component, &ops, init, &mut |op| {
+        execute_generic_op(source, staging, op)
+    })
+}
+
+/// Run already ordered and resolved `ops`, handing the ones that need
+/// distro-specific handling to `distro_op`.
+fn run_ops_for_init(
+    source: &Path,
+    staging: &Path,
+    component: &str,
+    ops: &[super::Op],
+    init: InitSystem,
+    distro_op: &mut dyn FnMut(&super::Op) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    for op in ops {
+        let declarative = matches!(op, super::Op::User { .. } | super::Op::Group { .. });
+        if init == InitSystem::Systemd && declarative {
+            continue;
+        }
+        match op {
+            super::Op::Bin(_)
+            | super::Op::Sbin(_)
+            | super::Op::Bins(_)
+            | super::Op::Sbins(_)
+            | super::Op::Custom(_) => distro_op(op)?,
+            _ => execute_generic_op(source, staging, op)?,
+        }
+    }
+    if init == InitSystem::Systemd {
+        fragments::write_fragments(staging, &fragments::fragment_name(component), ops)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +366,121 @@ mod tests {
         assert!(passwd.contains("testuser"));
     }
 
+    #[test]
+    fn test_systemd_users_go_to_sysusers() {
+        let (_temp, source, staging) = temp_dirs();
+        let ops = [
+            super::super::Op::User {
+                name: "chrony".into(),
+                uid: 123,
+                gid: 123,
+                home: "/var/lib/chrony".into(),
+                shell: "/sbin/nologin".into(),
+            },
+            super::super::Op::Dir("var/lib/chrony".into()),
+        ];
+
+        execute_generic_ops_for_init(&source, &staging, "Chrony", &ops, InitSystem::Systemd)
+            .unwrap();
+        assert!(!staging.join("etc/passwd").exists());
+        assert!(staging.join("var/lib/chrony").is_dir());
+        assert!(staging.join("usr/lib/sysusers.d/chrony.conf").is_file());
+
+        fs::create_dir_all(staging.join("etc")).unwrap();
+        execute_generic_ops_for_init(&source, &staging, "Chrony", &ops, InitSystem::OpenRC)
+            .unwrap();
+        assert!(fs::read_to_string(staging.join("etc/passwd"))
+            .unwrap()
+            .contains("chrony:x:123:123"));
+    }
+
+    #[test]
+    fn test_ctx_ops_follow_config_init_system() {
+        use crate::build::context::{BuildSettings, TomlBuildContext};
+
+        let (temp, source, staging) = temp_dirs();
+        let ops = [
+            super::super::Op::Group {
+                name: "chrony".into(),
+                gid: 123,
+            },
+            super::super::Op::When {
+                feature: "ntp".into(),
+                ops: vec![super::super::Op::Dir("var/lib/chrony".into())],
+            },
+        ];
+        let settings = BuildSettings {
+            source,
+            staging: staging.clone(),
+            features: vec!["ntp".into()],
+            ..BuildSettings::default()
+        };
+        let ctx = TomlBuildContext::from_settings(temp.path(), settings, Vec::new());
+
+        execute_generic_ops_ctx(&ctx, "Chrony", &ops).unwrap();
+        assert!(staging.join("var/lib/chrony").is_dir());
+        assert!(staging.join("usr/lib/sysusers.d/chrony.conf").is_file());
+        assert!(!staging.join("etc/group").exists());
+    }
+
+    #[test]
+    fn test_components_run_in_order_and_write_manifest() {
+        use crate::build::context::{BuildSettings, TomlBuildContext};
+        use crate::contracts::Phase;
+
+        struct Component(&'static str, Phase, Vec<super::super::Op>);
+        impl Installable for Component {
+            fn name(&self) -> &str {
+                self.0
+            }
+            fn phase(&self) -> Phase {
+                self.1
+            }
+            fn ops(&self) -> Vec<super::super::Op> {
+                self.2.clone()
+            }
+        }
+
+        let (temp, source, staging) = temp_dirs();
+        let output = temp.path().join("output");
+        let components: Vec<Box<dyn Installable>> = vec![
+            Box::new(Component(
+                "app",
+                Phase::Config,
+                vec![
+                    super::super::Op::WriteFile("etc/app.conf".into(), "x".into()),
+                    super::super::Op::Custom("read-app-conf".into()),
+                ],
+            )),
+            Box::new(Component(
+                "fhs",
+                Phase::Filesystem,
+                vec![super::super::Op::Dir("etc".into())],
+            )),
+        ];
+        let settings = BuildSettings {
+            source,
+            staging: staging.clone(),
+            output: output.clone(),
+            ..BuildSettings::default()
+        };
+        let ctx = TomlBuildContext::from_settings(temp.path(), settings, Vec::new());
+
+        let mut seen = Vec::new();
+        execute_components_ctx(&ctx, &components, |op| {
+            // The custom op runs after the write declared before it.
+            assert!(staging.join("etc/app.conf").is_file());
+            seen.push(op.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(seen, vec![super::super::Op::Custom("read-app-conf".into())]);
+
+        let manifest = fs::read_to_string(output.join(OPS_MANIFEST_FILENAME)).unwrap();
+        assert_eq!(manifest, super::super::ops_manifest(&components));
+        assert!(manifest.starts_with("Filesystem\tfhs\t"));
+    }
+
     #[test]
     fn test_execute_generic_op_bin_fails() {
         let (_temp, source, staging) = temp_dirs();
@@ -1,9 +1,22 @@
 //! OpenRC operation handlers: service enabling, init script copying, conf.d writing.
 //!
 //! These operations handle OpenRC-specific service management for
-//! Alpine-based distributions (AcornOS, IuppiterOS).
+//! Alpine-based distributions (AcornOS, IuppiterOS):
+//!
+//! - runlevels: enabling and disabling services in any runlevel, stacking
+//!   runlevels, and the `sysinit` base set in the order Alpine installs it
+//! - conf.d: whole files, or individual variables merged into an existing
+//!   file, including the `rc_after`/`rc_need` ordering hints OpenRC reads
+//!   from there
+//! - init scripts: copying, and checking their headers before they ship
+//!
+//! The validation helpers at the bottom of this module inspect a finished
+//! staging tree and report runlevel services that cannot start because their
+//! script, binary, or a hard dependency is missing from the image. The live
+//! stages run them on OpenRC rootfs trees alongside the systemd checks.
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
 use std::fs;
 use std::path::Path;
 
@@ -26,6 +39,115 @@ pub fn enable_service(staging: &Path, service: &str, runlevel: &str) -> Result<(
     Ok(())
 }
 
+/// Remove a service from a runlevel. Missing links are not an error.
+pub fn disable_service(staging: &Path, service: &str, runlevel: &str) -> Result<()> {
+    let link = staging.join("etc/runlevels").join(runlevel).join(service);
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(&link).with_context(|| format!("Failed to remove {}", link.display()))?;
+    }
+    Ok(())
+}
+
+/// Enable a service in a runlevel and record its ordering hints in
+/// `/etc/conf.d/<service>` (see [`set_service_order`]).
+pub fn enable_service_ordered(
+    staging: &Path,
+    service: &str,
+    runlevel: &str,
+    order: &ServiceOrder,
+) -> Result<()> {
+    enable_service(staging, service, runlevel)?;
+    set_service_order(staging, service, order)
+}
+
+/// Services enabled in a runlevel, sorted. Stacked runlevels are not
+/// expanded.
+pub fn runlevel_services(staging: &Path, runlevel: &str) -> Result<Vec<String>> {
+    let runlevel_dir = staging.join("etc/runlevels").join(runlevel);
+    if !runlevel_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut services = Vec::new();
+    for entry in fs::read_dir(&runlevel_dir)
+        .with_context(|| format!("Failed to read {}", runlevel_dir.display()))?
+    {
+        let entry = entry?;
+        if entry.path().is_dir() {
+            continue;
+        }
+        services.push(entry.file_name().to_string_lossy().to_string());
+    }
+    services.sort();
+    Ok(services)
+}
+
+/// Stack `base` into `runlevel`, so entering `runlevel` also starts
+/// everything in `base` (what `rc-update -s add <base> <runlevel>` does).
+pub fn stack_runlevel(staging: &Path, runlevel: &str, base: &str) -> Result<()> {
+    let runlevels = staging.join("etc/runlevels");
+    fs::create_dir_all(runlevels.join(base))?;
+    let runlevel_dir = runlevels.join(runlevel);
+    fs::create_dir_all(&runlevel_dir)?;
+    let link = runlevel_dir.join(base);
+    if link.symlink_metadata().is_err() {
+        std::os::unix::fs::symlink(format!("../{}", base), &link)
+            .with_context(|| format!("Failed to stack {} into {}", base, runlevel))?;
+    }
+    Ok(())
+}
+
+/// Device manager started in the `sysinit` runlevel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceManager {
+    /// busybox mdev (Alpine default).
+    Mdev,
+    /// eudev / systemd-udev.
+    Udev,
+}
+
+impl DeviceManager {
+    /// `sysinit` services in start order.
+    pub fn sysinit_services(self) -> &'static [&'static str] {
+        match self {
+            DeviceManager::Mdev => &["devfs", "dmesg", "mdev", "hwdrivers"],
+            DeviceManager::Udev => &["devfs", "dmesg", "udev", "udev-trigger", "udev-settle"],
+        }
+    }
+}
+
+/// Enable the `sysinit` base set for `device_manager`.
+///
+/// OpenRC orders services by their `depend()` blocks, not by link order, so
+/// the hints Alpine's scripts lack are written to conf.d: each service runs
+/// after the one before it. Every script must already be in staging.
+pub fn enable_sysinit(staging: &Path, device_manager: DeviceManager) -> Result<()> {
+    let services = device_manager.sysinit_services();
+    let missing: Vec<&str> = services
+        .iter()
+        .copied()
+        .filter(|service| !staging.join("etc/init.d").join(service).is_file())
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "sysinit services missing from /etc/init.d: {}\n\
+             Copy them with copy_init_script before enabling the sysinit runlevel.",
+            missing.join(", ")
+        );
+    }
+    for pair in services.windows(2) {
+        enable_service_ordered(
+            staging,
+            pair[1],
+            "sysinit",
+            &ServiceOrder {
+                after: vec![pair[0].to_string()],
+                ..Default::default()
+            },
+        )?;
+    }
+    enable_service(staging, services[0], "sysinit")
+}
+
 /// Copy an OpenRC init script from source to staging.
 ///
 /// Fails if the script doesn't exist in source - all listed scripts are required.
@@ -61,6 +183,345 @@ pub fn write_conf(staging: &Path, service: &str, content: &str) -> Result<()> {
     Ok(())
 }
 
+/// Set variables in `/etc/conf.d/<service>`, keeping the rest of the file.
+///
+/// Existing assignments of the same names are replaced in place; new ones
+/// are appended. Values are double-quoted and escaped for `sh`.
+pub fn set_conf_vars(staging: &Path, service: &str, vars: &[(&str, &str)]) -> Result<()> {
+    for (name, _) in vars {
+        let valid = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            bail!("invalid conf.d variable name '{}' for {}", name, service);
+        }
+    }
+    let conf_path = staging.join("etc/conf.d").join(service);
+    let existing = match fs::read_to_string(&conf_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", conf_path.display()))
+        }
+    };
+
+    let assignment = |name: &str, value: &str| format!("{}={}", name, quote_conf_value(value));
+    let mut pending: Vec<&(&str, &str)> = vars.iter().collect();
+    let mut lines = Vec::new();
+    for line in existing.lines() {
+        let assigned = line
+            .trim_start()
+            .split_once('=')
+            .map(|(name, _)| name.trim());
+        match pending.iter().position(|(name, _)| Some(*name) == assigned) {
+            Some(index) => {
+                let (name, value) = pending.remove(index);
+                lines.push(assignment(name, value));
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+    lines.extend(
+        pending
+            .into_iter()
+            .map(|(name, value)| assignment(name, value)),
+    );
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    write_conf(staging, service, &content)
+}
+
+fn quote_conf_value(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Ordering hints for a service, written to its conf.d file as the
+/// `rc_need`/`rc_use`/`rc_after`/`rc_before` variables OpenRC merges into
+/// the script's `depend()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceOrder {
+    pub need: Vec<String>,
+    pub uses: Vec<String>,
+    pub after: Vec<String>,
+    pub before: Vec<String>,
+}
+
+/// Write `order` to `/etc/conf.d/<service>`. Empty lists are left alone.
+pub fn set_service_order(staging: &Path, service: &str, order: &ServiceOrder) -> Result<()> {
+    let fields = [
+        ("rc_need", &order.need),
+        ("rc_use", &order.uses),
+        ("rc_after", &order.after),
+        ("rc_before", &order.before),
+    ];
+    let joined: Vec<(&str, String)> = fields
+        .iter()
+        .filter(|(_, names)| !names.is_empty())
+        .map(|(var, names)| (*var, names.join(" ")))
+        .collect();
+    if joined.is_empty() {
+        return Ok(());
+    }
+    let vars: Vec<(&str, &str)> = joined
+        .iter()
+        .map(|(var, value)| (*var, value.as_str()))
+        .collect();
+    set_conf_vars(staging, service, &vars)
+}
+
+/// Dependency declarations parsed from an init script's `depend()` block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceDepends {
+    /// Hard dependencies (`need`) - the service will not start without these.
+    pub need: Vec<String>,
+    /// Soft dependencies (`use`, `want`) - started if present.
+    pub uses: Vec<String>,
+    /// Ordering hints (`after`).
+    pub after: Vec<String>,
+    /// Ordering hints (`before`).
+    pub before: Vec<String>,
+    /// Virtual service names this script provides (`provide`).
+    pub provide: Vec<String>,
+}
+
+/// Parse the `depend()` function of an OpenRC init script.
+///
+/// Only the simple declarative form used by packaged scripts is understood:
+/// one keyword per line followed by service names. Lines containing shell
+/// expansions are skipped since their values cannot be resolved statically.
+pub fn parse_depend(script: &str) -> ServiceDepends {
+    let mut deps = ServiceDepends::default();
+    let mut in_depend = false;
+
+    for raw in script.lines() {
+        let line = raw.split('#').next().unwrap_or("").trim();
+        if !in_depend {
+            if line.starts_with("depend()") || line.starts_with("depend ()") {
+                in_depend = true;
+            }
+            continue;
+        }
+        if line == "}" {
+            break;
+        }
+        if line.is_empty() || line == "{" || line.contains('$') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let names = words.map(|w| w.trim_end_matches(';').to_string());
+        let bucket = match keyword {
+            "need" => &mut deps.need,
+            "use" | "want" => &mut deps.uses,
+            "after" => &mut deps.after,
+            "before" => &mut deps.before,
+            "provide" => &mut deps.provide,
+            _ => continue,
+        };
+        bucket.extend(names.filter(|n| !n.is_empty()));
+    }
+
+    deps
+}
+
+/// Extract the absolute `command=` path from an init script, if declared.
+///
+/// Returns `None` when the script has no `command=` line or when the value
+/// depends on shell variables (e.g. `command=/usr/sbin/${RC_SVCNAME}`).
+pub fn parse_command(script: &str) -> Option<String> {
+    script.lines().find_map(|raw| {
+        let line = raw.trim();
+        let value = line.strip_prefix("command=")?;
+        let value = value.trim_matches(|c| c == '"' || c == '\'');
+        if value.starts_with('/') && !value.contains('$') {
+            Some(value.to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Interpreters an init script may declare.
+const OPENRC_SHEBANGS: &[&str] = &["#!/sbin/openrc-run", "#!/usr/sbin/openrc-run"];
+
+/// Problems with an init script's header and structure.
+///
+/// Checks the `openrc-run` shebang (the pre-0.16 `runscript` is rejected),
+/// that `depend()`, if present, is closed, and that `command=` is absolute
+/// or left to shell expansion.
+pub fn init_script_problems(script: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let first = script.lines().next().unwrap_or("").trim_end();
+    let interpreter = first.split_whitespace().next().unwrap_or("");
+    if interpreter.ends_with("/runscript") {
+        problems.push(format!(
+            "uses the removed '{}' interpreter; use #!/sbin/openrc-run",
+            interpreter
+        ));
+    } else if !OPENRC_SHEBANGS.contains(&interpreter) {
+        problems.push(format!(
+            "first line '{}' is not an openrc-run shebang",
+            first
+        ));
+    }
+
+    let mut depend_open = false;
+    for raw in script.lines() {
+        let line = raw.split('#').next().unwrap_or("").trim();
+        if line.starts_with("depend()") || line.starts_with("depend ()") {
+            depend_open = !line.ends_with('}');
+        } else if depend_open && line == "}" {
+            depend_open = false;
+        }
+        if let Some(value) = line.strip_prefix("command=") {
+            let value = value.trim_matches(|c| c == '"' || c == '\'');
+            if !value.is_empty() && !value.starts_with('/') && !value.starts_with('$') {
+                problems.push(format!("command '{}' is not an absolute path", value));
+            }
+        }
+    }
+    if depend_open {
+        problems.push("depend() block is not closed".to_string());
+    }
+    problems
+}
+
+/// Check the header of every script in `etc/init.d` of a staging tree,
+/// including that it is executable.
+pub fn validate_init_scripts(staging: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let init_dir = staging.join("etc/init.d");
+    if !init_dir.is_dir() {
+        return Ok(());
+    }
+    let mut scripts: Vec<_> = fs::read_dir(&init_dir)
+        .with_context(|| format!("Failed to read {}", init_dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.is_file())
+        .collect();
+    scripts.sort();
+
+    let mut problems = Vec::new();
+    for path in scripts {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // Helper functions sourced by other scripts, not services.
+        if name.starts_with("functions") {
+            continue;
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for problem in init_script_problems(&content) {
+            problems.push(format!("{}: {}", name, problem));
+        }
+        if fs::metadata(&path)?.permissions().mode() & 0o111 == 0 {
+            problems.push(format!("{}: not executable", name));
+        }
+    }
+    if !problems.is_empty() {
+        bail!(
+            "{} invalid OpenRC init script(s):\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Check every service enabled in `etc/runlevels/<runlevel>` of a staging tree.
+///
+/// Returns one human-readable problem per broken service. A service is broken
+/// when its init script is missing, its `command=` binary is not present in
+/// the image, or one of its `need` dependencies is neither an installed init
+/// script nor provided by one. Soft dependencies are ignored.
+pub fn find_runlevel_problems(staging: &Path, runlevel: &str) -> Result<Vec<String>> {
+    let runlevel_dir = staging.join("etc/runlevels").join(runlevel);
+    if !runlevel_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let init_dir = staging.join("etc/init.d");
+    let mut available = BTreeSet::new();
+    if init_dir.is_dir() {
+        for entry in fs::read_dir(&init_dir)
+            .with_context(|| format!("Failed to read {}", init_dir.display()))?
+        {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Ok(content) = fs::read_to_string(entry.path()) {
+                available.extend(parse_depend(&content).provide);
+            }
+            available.insert(name);
+        }
+    }
+
+    let mut enabled: Vec<String> = fs::read_dir(&runlevel_dir)
+        .with_context(|| format!("Failed to read {}", runlevel_dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    enabled.sort();
+
+    let mut problems = Vec::new();
+    for service in enabled {
+        let script_path = init_dir.join(&service);
+        let Ok(content) = fs::read_to_string(&script_path) else {
+            problems.push(format!(
+                "{}: enabled in runlevel '{}' but /etc/init.d/{} is missing",
+                service, runlevel, service
+            ));
+            continue;
+        };
+
+        if let Some(command) = parse_command(&content) {
+            let binary = staging.join(command.trim_start_matches('/'));
+            if fs::symlink_metadata(&binary).is_err() {
+                problems.push(format!("{}: command {} is missing", service, command));
+            }
+        }
+
+        for need in parse_depend(&content).need {
+            if !available.contains(&need) {
+                problems.push(format!(
+                    "{}: needs '{}' but no init script provides it",
+                    service, need
+                ));
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Fail if any service enabled in the `default` runlevel cannot start.
+pub fn validate_default_runlevel(staging: &Path) -> Result<()> {
+    let problems = find_runlevel_problems(staging, "default")?;
+    if !problems.is_empty() {
+        bail!(
+            "OpenRC default runlevel has {} broken service(s):\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -114,4 +575,134 @@ mod tests {
         let content = fs::read_to_string(staging.join("etc/conf.d/sshd")).unwrap();
         assert_eq!(content, "SSHD_OPTS=\"-p 22\"");
     }
+
+    #[test]
+    fn test_set_conf_vars_merges() {
+        let temp = TempDir::new().unwrap();
+        let staging = temp.path();
+
+        write_conf(staging, "sshd", "# keep me\nSSHD_OPTS=\"-p 22\"\n").unwrap();
+        set_service_order(
+            staging,
+            "sshd",
+            &ServiceOrder {
+                after: vec!["firewall".into(), "net".into()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        set_conf_vars(staging, "sshd", &[("SSHD_OPTS", "-p 2222 \"$x\"")]).unwrap();
+
+        let content = fs::read_to_string(staging.join("etc/conf.d/sshd")).unwrap();
+        assert_eq!(
+            content,
+            "# keep me\nSSHD_OPTS=\"-p 2222 \\\"\\$x\\\"\"\nrc_after=\"firewall net\"\n"
+        );
+        assert!(set_conf_vars(staging, "sshd", &[("bad-name", "x")]).is_err());
+    }
+
+    #[test]
+    fn test_runlevel_management() {
+        let temp = TempDir::new().unwrap();
+        let staging = temp.path();
+        let init_d = staging.join("etc/init.d");
+        fs::create_dir_all(&init_d).unwrap();
+
+        assert!(enable_sysinit(staging, DeviceManager::Mdev).is_err());
+        for service in DeviceManager::Mdev.sysinit_services() {
+            fs::write(init_d.join(service), "#!/sbin/openrc-run\n").unwrap();
+        }
+        enable_sysinit(staging, DeviceManager::Mdev).unwrap();
+        assert_eq!(
+            runlevel_services(staging, "sysinit").unwrap(),
+            vec!["devfs", "dmesg", "hwdrivers", "mdev"]
+        );
+        assert_eq!(
+            fs::read_to_string(staging.join("etc/conf.d/hwdrivers")).unwrap(),
+            "rc_after=\"mdev\"\n"
+        );
+
+        enable_service(staging, "sshd", "default").unwrap();
+        stack_runlevel(staging, "live", "default").unwrap();
+        assert_eq!(
+            fs::read_link(staging.join("etc/runlevels/live/default")).unwrap(),
+            Path::new("../default")
+        );
+        disable_service(staging, "sshd", "default").unwrap();
+        disable_service(staging, "sshd", "default").unwrap();
+        assert!(runlevel_services(staging, "default").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_init_script_problems() {
+        let good = "#!/sbin/openrc-run\ncommand=/usr/sbin/sshd\ndepend() {\n\tneed net\n}\n";
+        assert!(init_script_problems(good).is_empty());
+
+        let legacy = "#!/sbin/runscript\ncommand=sshd\ndepend() {\n\tneed net\n";
+        let problems = init_script_problems(legacy);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains("runscript"));
+        assert!(problems[1].contains("not an absolute path"));
+        assert!(problems[2].contains("not closed"));
+
+        let temp = TempDir::new().unwrap();
+        let init_d = temp.path().join("etc/init.d");
+        fs::create_dir_all(&init_d).unwrap();
+        fs::write(init_d.join("sshd"), good).unwrap();
+        assert!(validate_init_scripts(temp.path()).is_err());
+        make_executable(&init_d.join("sshd")).unwrap();
+        validate_init_scripts(temp.path()).unwrap();
+    }
+
+    #[test]
+    fn test_parse_depend() {
+        let script = "#!/sbin/openrc-run\n\
+            command=\"/usr/sbin/sshd\"\n\
+            depend() {\n\
+            \tneed net localmount # comment\n\
+            \tuse logger dns\n\
+            \tafter firewall\n\
+            \tprovide sshd-virt\n\
+            }\n";
+
+        let deps = parse_depend(script);
+        assert_eq!(deps.need, vec!["net", "localmount"]);
+        assert_eq!(deps.uses, vec!["logger", "dns"]);
+        assert_eq!(deps.after, vec!["firewall"]);
+        assert_eq!(deps.provide, vec!["sshd-virt"]);
+        assert_eq!(parse_command(script).as_deref(), Some("/usr/sbin/sshd"));
+    }
+
+    #[test]
+    fn test_find_runlevel_problems() {
+        let temp = TempDir::new().unwrap();
+        let staging = temp.path();
+        let init_d = staging.join("etc/init.d");
+        fs::create_dir_all(&init_d).unwrap();
+        fs::create_dir_all(staging.join("usr/sbin")).unwrap();
+        fs::write(staging.join("usr/sbin/sshd"), "").unwrap();
+
+        fs::write(init_d.join("networking"), "depend() {\n\tprovide net\n}\n").unwrap();
+        fs::write(
+            init_d.join("sshd"),
+            "command=/usr/sbin/sshd\ndepend() {\n\tneed net\n\tuse logger\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            init_d.join("chronyd"),
+            "command=/usr/sbin/chronyd\ndepend() {\n\tneed localmount\n}\n",
+        )
+        .unwrap();
+
+        enable_service(staging, "sshd", "default").unwrap();
+        enable_service(staging, "chronyd", "default").unwrap();
+        enable_service(staging, "ghost", "default").unwrap();
+
+        let problems = find_runlevel_problems(staging, "default").unwrap();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("chronyd: command /usr/sbin/chronyd"));
+        assert!(problems[1].starts_with("chronyd: needs 'localmount'"));
+        assert!(problems[2].starts_with("ghost:"));
+        assert!(validate_default_runlevel(staging).is_err());
+    }
 }
@@ -0,0 +1,284 @@
+//! Privileged access handler: Op::SudoRule
+//!
+//! Rules are written once, in a tool-neutral form, and rendered for the
+//! tool the rootfs ships: a file per user or group in `/etc/sudoers.d`, or
+//! lines in `/etc/doas.conf`. The rendered file is checked with `visudo -c`
+//! (or `doas -C`) when the host has it, before it replaces the old one.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use super::binaries::find_binary;
+use crate::process::{self, Cmd};
+
+const HEADER: &str = "# Generated by distro-builder\n";
+const NOPASSWD: &str = "NOPASSWD:";
+
+/// Privilege escalation tool shipped by the rootfs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegeTool {
+    Sudo,
+    Doas,
+}
+
+impl PrivilegeTool {
+    /// Tool shipped in `staging`, else in `source`. sudo wins when both are.
+    pub fn detect(source: &Path, staging: &Path) -> Option<Self> {
+        [staging, source].into_iter().find_map(|root| {
+            if find_binary(root, "sudo").is_some() {
+                Some(Self::Sudo)
+            } else if find_binary(root, "doas").is_some() {
+                Some(Self::Doas)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// A parsed `Op::SudoRule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SudoRule {
+    /// User name, or group name when `group` is set.
+    pub name: String,
+    pub group: bool,
+    pub nopasswd: bool,
+    /// Command path and arguments; `None` allows every command.
+    pub command: Option<Vec<String>>,
+}
+
+impl SudoRule {
+    /// Parse `user_or_group` (`alice` or `%wheel`) and `rule`
+    /// (`[NOPASSWD:] ALL|/path/to/cmd [args...]`).
+    pub fn parse(user_or_group: &str, rule: &str) -> Result<Self> {
+        let (name, group) = match user_or_group.strip_prefix('%') {
+            Some(name) => (name, true),
+            None => (user_or_group, false),
+        };
+        let valid_name = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-'));
+        if !valid_name {
+            bail!("invalid sudo rule principal '{}'", user_or_group);
+        }
+
+        let rule = rule.trim();
+        let (nopasswd, spec) = match rule.strip_prefix(NOPASSWD) {
+            Some(rest) => (true, rest.trim()),
+            None => (false, rule),
+        };
+        let command = if spec == "ALL" {
+            None
+        } else {
+            let words: Vec<String> = spec.split_whitespace().map(String::from).collect();
+            let unsafe_char = |c: char| matches!(c, ',' | ':' | '=' | '\\' | '#' | '"' | '\'');
+            if words.first().is_none_or(|cmd| !cmd.starts_with('/'))
+                || spec.chars().any(unsafe_char)
+            {
+                bail!(
+                    "invalid sudo rule '{}' for '{}': expected [NOPASSWD:] ALL or an absolute command path with plain arguments",
+                    rule,
+                    user_or_group
+                );
+            }
+            Some(words)
+        };
+        Ok(Self {
+            name: name.to_string(),
+            group,
+            nopasswd,
+            command,
+        })
+    }
+
+    /// sudoers line, e.g. `%wheel ALL=(ALL:ALL) NOPASSWD: ALL`.
+    pub fn to_sudoers(&self) -> String {
+        let who = if self.group {
+            format!("%{}", self.name)
+        } else {
+            self.name.clone()
+        };
+        let tag = if self.nopasswd { "NOPASSWD: " } else { "" };
+        let command = self
+            .command
+            .as_ref()
+            .map_or_else(|| "ALL".to_string(), |words| words.join(" "));
+        format!("{} ALL=(ALL:ALL) {}{}", who, tag, command)
+    }
+
+    /// doas.conf line, e.g. `permit persist :wheel`.
+    ///
+    /// `persist` matches sudo's default credential caching.
+    pub fn to_doas(&self) -> String {
+        let mut line = format!(
+            "permit {} {}{}",
+            if self.nopasswd { "nopass" } else { "persist" },
+            if self.group { ":" } else { "" },
+            self.name
+        );
+        if let Some(words) = &self.command {
+            line.push_str(" cmd ");
+            line.push_str(&words[0]);
+            if words.len() > 1 {
+                line.push_str(" args ");
+                line.push_str(&words[1..].join(" "));
+            }
+        }
+        line
+    }
+
+    /// File under `etc/sudoers.d` holding this principal's rules. sudo
+    /// skips names containing a dot, so none is used.
+    fn sudoers_file(&self) -> String {
+        if self.group {
+            format!("etc/sudoers.d/group-{}", self.name)
+        } else {
+            format!("etc/sudoers.d/{}", self.name)
+        }
+    }
+}
+
+/// Handle Op::SudoRule: render the rule for the shipped tool and merge it
+/// into its config. Rules already present are not duplicated.
+pub fn handle_sudo_rule(
+    source: &Path,
+    staging: &Path,
+    user_or_group: &str,
+    rule: &str,
+) -> Result<()> {
+    let parsed = SudoRule::parse(user_or_group, rule)?;
+    let Some(tool) = PrivilegeTool::detect(source, staging) else {
+        bail!(
+            "sudo rule for '{}' needs sudo or doas in the rootfs, and neither is installed\n\
+Remediation: add sudo or doas to the package list, or drop the rule",
+            user_or_group
+        );
+    };
+    match tool {
+        PrivilegeTool::Sudo => merge_rule(
+            &staging.join(parsed.sudoers_file()),
+            &parsed.to_sudoers(),
+            0o440,
+            tool,
+        ),
+        PrivilegeTool::Doas => merge_rule(
+            &staging.join("etc/doas.conf"),
+            &parsed.to_doas(),
+            0o400,
+            tool,
+        ),
+    }
+}
+
+/// Append `line` to the config at `path` unless present, validating the
+/// result before it replaces the file.
+fn merge_rule(path: &Path, line: &str, mode: u32, tool: PrivilegeTool) -> Result<()> {
+    let mut content = if path.exists() {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?
+    } else {
+        HEADER.to_string()
+    };
+    if content.lines().any(|existing| existing.trim() == line) {
+        return Ok(());
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    // doas rejects a config whose last line has no newline.
+    content.push_str(line);
+    content.push('\n');
+
+    let parent = path.parent().unwrap_or(Path::new("/"));
+    fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    let mut pending = path.as_os_str().to_os_string();
+    pending.push(".new");
+    let pending = PathBuf::from(pending);
+    fs::write(&pending, &content)
+        .with_context(|| format!("Failed to write {}", pending.display()))?;
+    fs::set_permissions(&pending, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions on {}", pending.display()))?;
+    if let Err(err) = validate(&pending, tool) {
+        let _ = fs::remove_file(&pending);
+        return Err(err)
+            .with_context(|| format!("Rejected rule '{}' for {}", line, path.display()));
+    }
+    fs::rename(&pending, path).with_context(|| format!("Failed to install {}", path.display()))
+}
+
+/// Check `path` with the host's `visudo -c` or `doas -C`; skipped when the
+/// host lacks the tool.
+fn validate(path: &Path, tool: PrivilegeTool) -> Result<()> {
+    let cmd = match tool {
+        PrivilegeTool::Sudo if process::exists("visudo") => {
+            Cmd::new("visudo").args(["-c", "-q", "-f"])
+        }
+        PrivilegeTool::Doas if process::exists("doas") => Cmd::new("doas").arg("-C"),
+        _ => return Ok(()),
+    };
+    let result = cmd.arg_path(path).allow_fail().run()?;
+    if !result.success() {
+        bail!("syntax check failed: {}", result.stderr_trimmed());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sudo_rule_rendering() {
+        let wheel = SudoRule::parse("%wheel", "ALL").unwrap();
+        assert_eq!(wheel.to_sudoers(), "%wheel ALL=(ALL:ALL) ALL");
+        assert_eq!(wheel.to_doas(), "permit persist :wheel");
+
+        let restart = SudoRule::parse("ops", "NOPASSWD: /sbin/rc-service sshd restart").unwrap();
+        assert_eq!(
+            restart.to_sudoers(),
+            "ops ALL=(ALL:ALL) NOPASSWD: /sbin/rc-service sshd restart"
+        );
+        assert_eq!(
+            restart.to_doas(),
+            "permit nopass ops cmd /sbin/rc-service args sshd restart"
+        );
+
+        assert!(SudoRule::parse("Root", "ALL").is_err());
+        assert!(SudoRule::parse("%", "ALL").is_err());
+        assert!(SudoRule::parse("ops", "rc-service").is_err());
+        assert!(SudoRule::parse("ops", "/bin/sh -c 'id'").is_err());
+    }
+
+    #[test]
+    fn test_handle_sudo_rule_follows_shipped_tool() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("source");
+        let staging = temp.path().join("staging");
+        fs::create_dir_all(source.join("usr/bin")).unwrap();
+        fs::create_dir_all(&staging).unwrap();
+        assert!(handle_sudo_rule(&source, &staging, "%wheel", "ALL").is_err());
+
+        fs::write(source.join("usr/bin/doas"), "").unwrap();
+        handle_sudo_rule(&source, &staging, "%wheel", "ALL").unwrap();
+        handle_sudo_rule(&source, &staging, "%wheel", "ALL").unwrap();
+        let doas = fs::read_to_string(staging.join("etc/doas.conf")).unwrap();
+        assert_eq!(doas, format!("{}permit persist :wheel\n", HEADER));
+
+        fs::write(source.join("usr/bin/sudo"), "").unwrap();
+        handle_sudo_rule(&source, &staging, "%wheel", "NOPASSWD: ALL").unwrap();
+        let sudoers = staging.join("etc/sudoers.d/group-wheel");
+        assert!(fs::read_to_string(&sudoers)
+            .unwrap()
+            .ends_with("%wheel ALL=(ALL:ALL) NOPASSWD: ALL\n"));
+        assert_eq!(
+            fs::metadata(&sudoers).unwrap().permissions().mode() & 0o777,
+            0o440
+        );
+    }
+}
@@ -1,11 +1,31 @@
 //! User/group operation handlers: Op::User, Op::Group
 //!
 //! These operations are distro-agnostic and work for any Linux distribution.
+//! Besides `passwd`/`group` entries this module sets `shadow` passwords
+//! ([`set_password`]), checks accounts against the distro's
+//! [`UidRanges`] ([`validate_uid_ranges`]) and allocates `/etc/subuid` and
+//! `/etc/subgid` blocks for rootless containers ([`ensure_subids`]).
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use std::fs;
 use std::path::Path;
 
+use crate::contracts::context::UidRanges;
+
+/// First subordinate ID handed out, above any real UID.
+pub const SUBID_START: u32 = 100_000;
+
+/// Subordinate IDs per user: one full 16-bit user namespace.
+pub const SUBID_COUNT: u32 = 65_536;
+
+/// Login shells that mark an account as not meant for interactive use.
+const NOLOGIN_SHELLS: &[&str] = &[
+    "/sbin/nologin",
+    "/usr/sbin/nologin",
+    "/bin/false",
+    "/usr/bin/false",
+];
+
 /// Read a UID from the rootfs passwd file.
 ///
 /// Returns:
@@ -149,6 +169,216 @@ pub fn ensure_group(
     Ok(())
 }
 
+/// Password field of a `shadow` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Password {
+    /// A `crypt(3)` hash such as `$6$salt$...` or `$y$...`.
+    Hash(String),
+    /// Password login disabled (`!`); key-based login still works.
+    Locked,
+    /// No password will ever match (`*`), for system accounts.
+    NoLogin,
+}
+
+impl Password {
+    fn field(&self) -> Result<&str> {
+        match self {
+            Password::Hash(hash) => {
+                let valid = hash.starts_with('$')
+                    && hash.split('$').count() >= 4
+                    && !hash.contains(|c: char| c == ':' || c.is_whitespace());
+                if !valid {
+                    bail!("invalid password hash: expected a crypt(3) string like $6$salt$hash");
+                }
+                Ok(hash)
+            }
+            Password::Locked => Ok("!"),
+            Password::NoLogin => Ok("*"),
+        }
+    }
+}
+
+/// Set `username`'s password in the staging `shadow` file, adding an entry
+/// when there is none. Password aging is left disabled.
+pub fn set_password(staging: &Path, username: &str, password: &Password) -> Result<()> {
+    let field = password.field()?;
+    let shadow_path = staging.join("etc/shadow");
+    let content = if shadow_path.exists() {
+        fs::read_to_string(&shadow_path)
+            .with_context(|| format!("Failed to read shadow file at {}", shadow_path.display()))?
+    } else {
+        String::new()
+    };
+
+    let mut found = false;
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let mut fields: Vec<&str> = line.split(':').collect();
+            if fields.len() >= 2 && fields[0] == username {
+                found = true;
+                fields[1] = field;
+                fields.join(":")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        lines.push(format!("{}:{}::0:99999:7:::", username, field));
+    }
+    let mut content = lines.join("\n");
+    content.push('\n');
+
+    if let Some(parent) = shadow_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&shadow_path, content)
+        .with_context(|| format!("Failed to write shadow for user {}", username))?;
+    let mut perms = fs::metadata(&shadow_path)?.permissions();
+    std::os::unix::fs::PermissionsExt::set_mode(&mut perms, 0o600);
+    fs::set_permissions(&shadow_path, perms)?;
+    Ok(())
+}
+
+/// Check staging accounts against `ranges`.
+///
+/// Accounts with a home under `/home` must have regular UIDs; accounts
+/// with a nologin shell must have system UIDs. `root` and `nobody` are
+/// exempt. Returns one problem per account.
+pub fn uid_range_problems(staging: &Path, ranges: &UidRanges) -> Result<Vec<String>> {
+    let passwd_path = staging.join("etc/passwd");
+    if !passwd_path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&passwd_path)
+        .with_context(|| format!("Failed to read passwd file at {}", passwd_path.display()))?;
+
+    let mut problems = Vec::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 7 {
+            continue;
+        }
+        let Ok(uid) = fields[2].parse::<u32>() else {
+            continue;
+        };
+        problems.extend(account_range_problem(
+            fields[0], uid, fields[5], fields[6], ranges,
+        ));
+    }
+    Ok(problems)
+}
+
+/// Range problem of one account, classified by its home and shell.
+pub fn account_range_problem(
+    name: &str,
+    uid: u32,
+    home: &str,
+    shell: &str,
+    ranges: &UidRanges,
+) -> Option<String> {
+    if matches!(name, "root" | "nobody") {
+        None
+    } else if home.starts_with("/home/") && !ranges.is_regular(uid) {
+        Some(format!(
+            "{}: home {} but UID {} is outside the regular range {}-{}",
+            name, home, uid, ranges.regular.0, ranges.regular.1
+        ))
+    } else if NOLOGIN_SHELLS.contains(&shell) && !ranges.is_system(uid) {
+        Some(format!(
+            "{}: shell {} but UID {} is outside the system range {}-{}",
+            name, shell, uid, ranges.system.0, ranges.system.1
+        ))
+    } else {
+        None
+    }
+}
+
+/// Fail if any staging account is outside its [`UidRanges`] range.
+pub fn validate_uid_ranges(staging: &Path, ranges: &UidRanges) -> Result<()> {
+    let problems = uid_range_problems(staging, ranges)?;
+    if !problems.is_empty() {
+        bail!(
+            "{} account(s) outside the distro UID ranges:\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Give `username` a block of [`SUBID_COUNT`] subordinate UIDs and GIDs in
+/// `/etc/subuid` and `/etc/subgid`, after the highest block in use.
+/// Users that already have an entry keep it.
+pub fn ensure_subids(staging: &Path, username: &str) -> Result<()> {
+    for file in ["etc/subuid", "etc/subgid"] {
+        let path = staging.join(file);
+        let mut content = if path.exists() {
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+        } else {
+            String::new()
+        };
+
+        let mut next = SUBID_START;
+        let mut present = false;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split(':').collect();
+            let (Some(start), Some(count)) = (
+                fields.get(1).and_then(|v| v.parse::<u32>().ok()),
+                fields.get(2).and_then(|v| v.parse::<u32>().ok()),
+            ) else {
+                bail!("Corrupted {}: invalid line '{}'", path.display(), line);
+            };
+            present |= fields[0] == username;
+            next = next.max(start.saturating_add(count));
+        }
+        if present {
+            continue;
+        }
+        if next.checked_add(SUBID_COUNT).is_none() {
+            bail!(
+                "no subordinate IDs left in {} for {}",
+                path.display(),
+                username
+            );
+        }
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&format!("{}:{}:{}\n", username, next, SUBID_COUNT));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// [`ensure_subids`] for every staging login account with a regular UID.
+pub fn ensure_subids_for_regular_users(staging: &Path, ranges: &UidRanges) -> Result<()> {
+    let passwd_path = staging.join("etc/passwd");
+    if !passwd_path.exists() {
+        return Ok(());
+    }
+    let content = fs::read_to_string(&passwd_path)
+        .with_context(|| format!("Failed to read passwd file at {}", passwd_path.display()))?;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 7 || NOLOGIN_SHELLS.contains(&fields[6]) {
+            continue;
+        }
+        if fields[2]
+            .parse::<u32>()
+            .is_ok_and(|uid| ranges.is_regular(uid))
+        {
+            ensure_subids(staging, fields[0])?;
+        }
+    }
+    Ok(())
+}
+
 /// Handle Op::User: Create or update a user
 pub fn handle_user(
     source: &Path,
@@ -321,6 +551,64 @@ mod tests {
         assert!(group_content.contains("testgroup:x:1000:"));
     }
 
+    #[test]
+    fn test_set_password() {
+        let (_temp, _source, staging) = temp_dirs();
+        fs::create_dir_all(staging.join("etc")).unwrap();
+        fs::write(staging.join("etc/shadow"), "root::0:0:99999:7:::\n").unwrap();
+
+        set_password(&staging, "root", &Password::Locked).unwrap();
+        set_password(
+            &staging,
+            "alice",
+            &Password::Hash("$6$salt$abcdef".to_string()),
+        )
+        .unwrap();
+        let shadow = fs::read_to_string(staging.join("etc/shadow")).unwrap();
+        assert_eq!(
+            shadow,
+            "root:!:0:0:99999:7:::\nalice:$6$salt$abcdef::0:99999:7:::\n"
+        );
+
+        for bad in ["plaintext", "$6$salt", "$6$salt$ha:sh"] {
+            assert!(set_password(&staging, "alice", &Password::Hash(bad.to_string())).is_err());
+        }
+    }
+
+    #[test]
+    fn test_uid_ranges_and_subids() {
+        let (_temp, _source, staging) = temp_dirs();
+        fs::create_dir_all(staging.join("etc")).unwrap();
+        fs::write(
+            staging.join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/sh\n\
+             sshd:x:22:22:sshd:/var/empty:/sbin/nologin\n\
+             alice:x:1000:1000:alice:/home/alice:/bin/sh\n\
+             bob:x:500:500:bob:/home/bob:/bin/sh\n\
+             daemon2:x:2000:2000::/:/sbin/nologin\n",
+        )
+        .unwrap();
+
+        let ranges = UidRanges::default();
+        let problems = uid_range_problems(&staging, &ranges).unwrap();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("bob:"));
+        assert!(problems[1].starts_with("daemon2:"));
+        assert!(validate_uid_ranges(&staging, &ranges).is_err());
+
+        fs::write(staging.join("etc/subuid"), "carol:100000:65536\n").unwrap();
+        ensure_subids_for_regular_users(&staging, &ranges).unwrap();
+        ensure_subids(&staging, "alice").unwrap();
+        assert_eq!(
+            fs::read_to_string(staging.join("etc/subuid")).unwrap(),
+            "carol:100000:65536\nalice:165536:65536\n"
+        );
+        assert_eq!(
+            fs::read_to_string(staging.join("etc/subgid")).unwrap(),
+            "alice:100000:65536\n"
+        );
+    }
+
     #[test]
     fn test_handle_user() {
         let (_temp, source, staging) = temp_dirs();
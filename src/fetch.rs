@@ -0,0 +1,530 @@
+//! Checksum-verified fetching of large build inputs into the download cache.
+//!
+//! Rootfs source ISOs and kernel tarballs used to be fetched by each recipe
+//! with its own `curl` invocation and its own idea of what a cached file is.
+//! [`Fetcher`] gives builder stages one path instead: every request names the
+//! file it produces, lists sources in failover order and (ideally) carries a
+//! SHA256. The result lands in a shared cache directory, normally the
+//! distro's `downloads` workspace, and a verified cached copy is reused.
+//!
+//! - HTTP(S) and `file://` sources go through `curl` and resume partial
+//!   downloads left behind by an interrupted run.
+//! - `.torrent` and `magnet:` sources go through `aria2c`, or
+//!   `transmission-cli` when aria2 is not installed.
+//!
+//! Recipes reach the same cache with `distro-builder cache fetch`: recipe
+//! runs started by the builder get [`recipe_fetch_env`], and the kernel and
+//! rootfs source recipes fetch through it when it is set.
+//!
+//! Concurrent builds share the cache safely: each entry is downloaded under
+//! an exclusive lock on `<file>.lock`, and a process that finds the lock held
+//! waits for the other download to finish and reuses its result instead of
+//! fetching the file a second time.
+//!
+//! ```rust,ignore
+//! let fetcher = Fetcher::for_distro(repo_root, "levitate")?;
+//! let iso = fetcher.fetch(
+//!     &FetchRequest::new("Rocky-10.0-x86_64-dvd1.iso")
+//!         .source("https://dl.rockylinux.org/pub/rocky/10/isos/x86_64/Rocky-10.0-x86_64-dvd1.iso.torrent")
+//!         .source("https://dl.rockylinux.org/pub/rocky/10/isos/x86_64/Rocky-10.0-x86_64-dvd1.iso")
+//!         .sha256("..."),
+//! )?;
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use fs2::FileExt;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::artifact::rootfs::format_size_human;
+use crate::build_summary;
+use crate::builder::{cli_binary, CLI_ENV};
+use crate::error::{ErrorKind, ResultExt};
+use crate::process::Cmd;
+use crate::repo_layout::REPO_ROOT_ENV;
+use crate::workspace::{WorkspaceManager, DOWNLOADS_NAMESPACE};
+
+/// Suffix of an in-progress HTTP download next to its final path.
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Suffix of the scratch directory a torrent client downloads into.
+const TORRENT_WORK_SUFFIX: &str = ".torrent-work";
+
+/// Suffix of the lock file serializing downloads of one cache entry.
+const LOCK_SUFFIX: &str = ".lock";
+
+/// How often a fetch waiting on another process retries the lock.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often a waiting fetch reports what the other process has downloaded.
+const LOCK_WAIT_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where one copy of a fetched file can come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchSource {
+    /// HTTP(S), FTP or `file://` URL fetched with `curl`.
+    Http(String),
+    /// `.torrent` URL or `magnet:` link fetched with a torrent client.
+    Torrent(String),
+}
+
+impl FetchSource {
+    /// Classify `url` by scheme and extension.
+    pub fn parse(url: &str) -> Self {
+        let url = url.trim();
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        if url.starts_with("magnet:") || path.ends_with(".torrent") {
+            Self::Torrent(url.to_string())
+        } else {
+            Self::Http(url.to_string())
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            Self::Http(url) | Self::Torrent(url) => url,
+        }
+    }
+}
+
+/// Torrent client used for [`FetchSource::Torrent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentBackend {
+    Aria2c,
+    Transmission,
+}
+
+impl TorrentBackend {
+    /// First installed client, preferring `aria2c`.
+    pub fn detect() -> Option<Self> {
+        if which::which("aria2c").is_ok() {
+            Some(Self::Aria2c)
+        } else if which::which("transmission-cli").is_ok() {
+            Some(Self::Transmission)
+        } else {
+            None
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Self::Aria2c => "aria2c",
+            Self::Transmission => "transmission-cli",
+        }
+    }
+}
+
+/// One file to fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRequest {
+    file_name: String,
+    sources: Vec<FetchSource>,
+    sha256: Option<String>,
+}
+
+impl FetchRequest {
+    /// Request for `file_name` in the cache directory.
+    pub fn new(file_name: impl Into<String>) -> Self {
+        Self {
+            file_name: file_name.into(),
+            sources: Vec::new(),
+            sha256: None,
+        }
+    }
+
+    /// Add a source URL; sources are tried in the order added.
+    pub fn source(mut self, url: impl AsRef<str>) -> Self {
+        self.sources.push(FetchSource::parse(url.as_ref()));
+        self
+    }
+
+    /// Add several source URLs.
+    pub fn sources<I, S>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.sources
+            .extend(urls.into_iter().map(|url| FetchSource::parse(url.as_ref())));
+        self
+    }
+
+    /// Expected SHA256 (hex). Without one, any cached copy is trusted.
+    pub fn sha256(mut self, hex: impl Into<String>) -> Self {
+        self.sha256 = Some(hex.into().trim().to_ascii_lowercase());
+        self
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.file_name.is_empty()
+            || self.file_name.contains('/')
+            || self.file_name.starts_with('.')
+        {
+            bail!("invalid fetch target name '{}'", self.file_name);
+        }
+        if self.sources.is_empty() {
+            bail!("no sources given for '{}'", self.file_name);
+        }
+        Ok(())
+    }
+}
+
+/// Distro whose download cache a recipe's `cache fetch` calls use.
+pub const RECIPE_FETCH_DISTRO_ENV: &str = "DISTRO_BUILDER_DISTRO";
+
+/// Environment for a recipe run so its downloads go through [`Fetcher`]:
+/// the recipe runs `"$DISTRO_BUILDER_BIN" cache fetch "$DISTRO_BUILDER_DISTRO"
+/// <file_name> <url>... --sha256 <hex>`.
+///
+/// The CLI is the one variant hooks call back into (see [`CLI_ENV`]) and
+/// runs against the checkout at `repo_root`. Empty when no CLI is found;
+/// recipes then download on their own.
+pub fn recipe_fetch_env(repo_root: &Path, distro_id: &str) -> Vec<(&'static str, String)> {
+    match cli_binary() {
+        Some(cli) => vec![
+            (CLI_ENV, cli.to_string_lossy().into_owned()),
+            (REPO_ROOT_ENV, repo_root.to_string_lossy().into_owned()),
+            (RECIPE_FETCH_DISTRO_ENV, distro_id.to_string()),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// Fetches [`FetchRequest`]s into one cache directory.
+#[derive(Debug, Clone)]
+pub struct Fetcher {
+    cache_dir: PathBuf,
+    torrent_backend: Option<TorrentBackend>,
+}
+
+impl Fetcher {
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cache_dir: cache_dir.into(),
+            torrent_backend: None,
+        }
+    }
+
+    /// Fetcher over the distro's persistent download cache.
+    pub fn for_distro(repo_root: &Path, distro_id: &str) -> Result<Self> {
+        let cache_dir =
+            WorkspaceManager::new(repo_root)?.persistent_dir(distro_id, DOWNLOADS_NAMESPACE)?;
+        Ok(Self::new(cache_dir))
+    }
+
+    /// Use `backend` for torrents instead of the detected client.
+    pub fn with_torrent_backend(mut self, backend: TorrentBackend) -> Self {
+        self.torrent_backend = Some(backend);
+        self
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Path `request` is (or will be) cached at.
+    pub fn cached_path(&self, request: &FetchRequest) -> PathBuf {
+        self.cache_dir.join(&request.file_name)
+    }
+
+    /// Return the cached file for `request`, downloading it first if needed.
+    ///
+    /// A cached copy failing its checksum is removed and fetched again.
+    /// Sources are tried in order; the error lists every failed source.
+    /// When another process is already downloading the same file, this
+    /// waits for it and returns its result.
+    pub fn fetch(&self, request: &FetchRequest) -> Result<PathBuf> {
+        request.validate()?;
+        fs::create_dir_all(&self.cache_dir)
+            .with_context(|| format!("Failed to create {}", self.cache_dir.display()))?;
+
+        // Files only appear at the target by rename, so a verified copy is
+        // complete and needs no lock.
+        let target = self.cached_path(request);
+        if target.is_file() && verify(&target, request.sha256.as_deref()).is_ok() {
+            build_summary::record_cache("download", true);
+            return Ok(target);
+        }
+
+        let _lock = DownloadLock::acquire(&target)?;
+        if target.is_file() {
+            match verify(&target, request.sha256.as_deref()) {
+                Ok(()) => {
+                    build_summary::record_cache("download", true);
+                    return Ok(target);
+                }
+                Err(err) => {
+                    eprintln!("  [WARN] {:#}; fetching again", err);
+                    fs::remove_file(&target)
+                        .with_context(|| format!("Failed to remove {}", target.display()))?;
+                }
+            }
+        }
+
+        build_summary::record_cache("download", false);
+        let mut failures = Vec::new();
+        for source in &request.sources {
+            eprintln!("  Fetching {} from {}", request.file_name, source.url());
+            let fetched = match source {
+                FetchSource::Http(url) => self.fetch_http(url, &target, request),
+                FetchSource::Torrent(url) => self.fetch_torrent(url, &target, request),
+            };
+            match fetched {
+                Ok(()) => return Ok(target),
+                Err(err) => {
+                    eprintln!("  [WARN] {:#}", err);
+                    failures.push(format!("{}: {:#}", source.url(), err));
+                }
+            }
+        }
+        Err(anyhow!(
+            "Failed to fetch {} from any source:\n  {}",
+            request.file_name,
+            failures.join("\n  ")
+        ))
+        .error_kind(ErrorKind::DownloadFailed)
+    }
+
+    fn fetch_http(&self, url: &str, target: &Path, request: &FetchRequest) -> Result<()> {
+        let partial = partial_path(target);
+        Cmd::new("curl")
+            .args(["-fSL", "--retry", "3", "--connect-timeout", "30"])
+            .args(["-C", "-", "--progress-bar", "-o"])
+            .arg_path(&partial)
+            .arg(url)
+            .error_msg(format!("Failed to download {}", url))
+            .run_interactive()?;
+        if let Err(err) = verify(&partial, request.sha256.as_deref()) {
+            // A corrupt partial would poison every later resume.
+            let _ = fs::remove_file(&partial);
+            return Err(err);
+        }
+        fs::rename(&partial, target)
+            .with_context(|| format!("Failed to move download to {}", target.display()))
+    }
+
+    fn fetch_torrent(&self, url: &str, target: &Path, request: &FetchRequest) -> Result<()> {
+        let Some(backend) = self.torrent_backend.or_else(TorrentBackend::detect) else {
+            bail!(
+                "no torrent client for {} (install aria2 or transmission-cli)",
+                url
+            );
+        };
+        let work = self
+            .cache_dir
+            .join(format!("{}{}", request.file_name, TORRENT_WORK_SUFFIX));
+        fs::create_dir_all(&work)
+            .with_context(|| format!("Failed to create {}", work.display()))?;
+
+        let cmd = match backend {
+            TorrentBackend::Aria2c => Cmd::new("aria2c")
+                .args([
+                    "--seed-time=0",
+                    "--follow-torrent=mem",
+                    "--summary-interval=30",
+                ])
+                .arg(format!("--dir={}", work.display()))
+                .arg(url),
+            TorrentBackend::Transmission => {
+                // transmission-cli keeps seeding; its finish script stops it.
+                let finish = work.join("finish.sh");
+                fs::write(&finish, "#!/bin/sh\nkill -INT \"$PPID\"\n")
+                    .with_context(|| format!("Failed to write {}", finish.display()))?;
+                make_executable(&finish)?;
+                Cmd::new("transmission-cli")
+                    .arg("-w")
+                    .arg_path(&work)
+                    .arg("-f")
+                    .arg_path(&finish)
+                    .arg(url)
+                    .allow_fail()
+            }
+        };
+        cmd.error_msg(format!("{} failed for {}", backend.program(), url))
+            .run_interactive()?;
+
+        let downloaded = find_named_file(&work, &request.file_name).with_context(|| {
+            format!(
+                "{} did not produce {} (torrent work dir kept at {})",
+                backend.program(),
+                request.file_name,
+                work.display()
+            )
+        })?;
+        verify(&downloaded, request.sha256.as_deref())?;
+        fs::rename(&downloaded, target)
+            .with_context(|| format!("Failed to move download to {}", target.display()))?;
+        fs::remove_dir_all(&work).with_context(|| format!("Failed to remove {}", work.display()))
+    }
+}
+
+/// Exclusive hold on downloading one cache entry, released on drop.
+struct DownloadLock {
+    _file: File,
+}
+
+impl DownloadLock {
+    /// Lock `target`, waiting for (and reporting on) another process that
+    /// holds it.
+    fn acquire(target: &Path) -> Result<Self> {
+        let path = suffixed_path(target, LOCK_SUFFIX);
+        // Never unlink the lock file: a process could still be waiting on
+        // the old inode while a new one is created at the same path.
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to create lock file {}", path.display()))?;
+        if file.try_lock_exclusive().is_ok() {
+            return Ok(Self { _file: file });
+        }
+
+        let name = target
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        eprintln!(
+            "  Waiting for another process downloading {} ({})",
+            name,
+            path.display()
+        );
+        let partial = partial_path(target);
+        let mut last_report = Instant::now();
+        loop {
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+            if file.try_lock_exclusive().is_ok() {
+                return Ok(Self { _file: file });
+            }
+            if last_report.elapsed() >= LOCK_WAIT_REPORT_INTERVAL {
+                last_report = Instant::now();
+                match fs::metadata(&partial) {
+                    Ok(meta) => eprintln!(
+                        "  Still waiting for {}: {} downloaded",
+                        name,
+                        format_size_human(meta.len())
+                    ),
+                    Err(_) => eprintln!("  Still waiting for {}", name),
+                }
+            }
+        }
+    }
+}
+
+/// SHA256 of a file, streamed.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn verify(path: &Path, sha256: Option<&str>) -> Result<()> {
+    let Some(expected) = sha256 else {
+        return Ok(());
+    };
+    let actual = sha256_file(path)?;
+    if actual != expected {
+        bail!(
+            "SHA256 mismatch for {}: expected {}, got {}",
+            path.display(),
+            expected,
+            actual
+        );
+    }
+    Ok(())
+}
+
+fn partial_path(target: &Path) -> PathBuf {
+    suffixed_path(target, PARTIAL_SUFFIX)
+}
+
+fn suffixed_path(target: &Path, suffix: &str) -> PathBuf {
+    let mut name = target.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn find_named_file(dir: &Path, file_name: &str) -> Option<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.file_type().is_file() && entry.file_name() == file_name)
+        .map(|entry| entry.into_path())
+}
+
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to chmod {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_source_classification() {
+        assert_eq!(
+            FetchSource::parse("https://example.org/a.iso.torrent?x=1"),
+            FetchSource::Torrent("https://example.org/a.iso.torrent?x=1".into())
+        );
+        assert_eq!(
+            FetchSource::parse("magnet:?xt=urn:btih:abc"),
+            FetchSource::Torrent("magnet:?xt=urn:btih:abc".into())
+        );
+        assert_eq!(
+            FetchSource::parse("file:///srv/a.iso"),
+            FetchSource::Http("file:///srv/a.iso".into())
+        );
+    }
+
+    #[test]
+    fn test_verified_cache_hit_skips_sources() {
+        let cache = TempDir::new().unwrap();
+        fs::write(cache.path().join("input.bin"), b"payload").unwrap();
+        let sha = sha256_file(&cache.path().join("input.bin")).unwrap();
+
+        // The only source is unreachable, so a hit must not touch it.
+        let request = FetchRequest::new("input.bin")
+            .source("http://127.0.0.1:9/input.bin")
+            .sha256(sha.to_uppercase());
+        let fetched = Fetcher::new(cache.path()).fetch(&request).unwrap();
+        assert_eq!(fetched, cache.path().join("input.bin"));
+
+        assert!(Fetcher::new(cache.path())
+            .fetch(&FetchRequest::new("../escape").source("http://127.0.0.1:9/x"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_waits_for_concurrent_download() {
+        let cache = TempDir::new().unwrap();
+        let target = cache.path().join("kernel.tar.xz");
+        let held = DownloadLock::acquire(&target).unwrap();
+
+        let cache_dir = cache.path().to_path_buf();
+        let waiter = std::thread::spawn(move || {
+            // Unreachable source: the waiter must reuse the other result.
+            Fetcher::new(cache_dir).fetch(
+                &FetchRequest::new("kernel.tar.xz").source("http://127.0.0.1:9/kernel.tar.xz"),
+            )
+        });
+        std::thread::sleep(Duration::from_millis(200));
+        fs::write(&target, b"tarball").unwrap();
+        drop(held);
+
+        assert_eq!(waiter.join().unwrap().unwrap(), target);
+    }
+}
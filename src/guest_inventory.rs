@@ -0,0 +1,279 @@
+//! Package and service inventory collected from a booted guest.
+//!
+//! After the boot test's checks pass, the host asks the guest for an
+//! inventory (`db_inventory` in the protocol library): installed packages
+//! as reported by apk or rpm, enabled services, kernel release and kernel
+//! command line. It is stored next to the run manifest as
+//! [`INVENTORY_FILENAME`], so two runs can be compared with
+//! [`GuestInventory::drift`] without booting either image again.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::guest_protocol::PROTOCOL_VERSION;
+use crate::run_history::{load_run_metadata, run_sort_key, RunMetadata};
+
+/// Block name the inventory is sent under.
+pub const INVENTORY_BLOCK_NAME: &str = "inventory";
+
+/// Evidence file in the run directory.
+pub const INVENTORY_FILENAME: &str = "guest-inventory.json";
+
+/// What was installed and enabled in a booted guest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuestInventory {
+    pub protocol_version: u32,
+    pub kernel_release: String,
+    pub cmdline: String,
+    /// `apk`, `rpm`, or `none`.
+    pub package_manager: String,
+    /// `name-version-release`, as the package manager prints them.
+    pub packages: Vec<String>,
+    /// systemd unit files, or `runlevel/service` for OpenRC.
+    pub enabled_services: Vec<String>,
+}
+
+impl GuestInventory {
+    /// Parse the inventory document sent by the guest.
+    pub fn from_json(value: serde_json::Value) -> Result<Self> {
+        let inventory: Self =
+            serde_json::from_value(value).context("guest inventory does not match the schema")?;
+        if inventory.protocol_version != PROTOCOL_VERSION {
+            bail!(
+                "guest inventory uses protocol v{}, host expects v{}",
+                inventory.protocol_version,
+                PROTOCOL_VERSION
+            );
+        }
+        Ok(inventory)
+    }
+
+    /// Package name -> `version-release`.
+    pub fn package_versions(&self) -> BTreeMap<&str, &str> {
+        self.packages
+            .iter()
+            .map(|package| split_package(package))
+            .collect()
+    }
+
+    /// Changes from `previous` to `self`.
+    pub fn drift(&self, previous: &Self) -> InventoryDrift {
+        let changed = |old: &str, new: &str| (old != new).then(|| (old.into(), new.into()));
+        let old_packages = previous.package_versions();
+        let new_packages = self.package_versions();
+        let mut drift = InventoryDrift {
+            kernel_release: changed(&previous.kernel_release, &self.kernel_release),
+            cmdline: changed(&previous.cmdline, &self.cmdline),
+            ..InventoryDrift::default()
+        };
+        for (name, version) in &new_packages {
+            match old_packages.get(name) {
+                None => drift.packages_added.push(format!("{}-{}", name, version)),
+                Some(old) if old != version => drift.packages_changed.push((
+                    name.to_string(),
+                    old.to_string(),
+                    version.to_string(),
+                )),
+                Some(_) => {}
+            }
+        }
+        for (name, version) in &old_packages {
+            if !new_packages.contains_key(name) {
+                drift.packages_removed.push(format!("{}-{}", name, version));
+            }
+        }
+        let old_services: BTreeSet<&String> = previous.enabled_services.iter().collect();
+        let new_services: BTreeSet<&String> = self.enabled_services.iter().collect();
+        drift.services_enabled = new_services
+            .difference(&old_services)
+            .map(|s| s.to_string())
+            .collect();
+        drift.services_disabled = old_services
+            .difference(&new_services)
+            .map(|s| s.to_string())
+            .collect();
+        drift
+    }
+}
+
+/// Split `name-version-release` at the version. Names may contain dashes;
+/// the last two dash-separated fields are the version and release.
+fn split_package(package: &str) -> (&str, &str) {
+    let mut dashes = package.rmatch_indices('-').map(|(idx, _)| idx);
+    match (dashes.next(), dashes.next()) {
+        (Some(_), Some(idx)) => (&package[..idx], &package[idx + 1..]),
+        _ => (package, ""),
+    }
+}
+
+/// Differences between two inventories.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InventoryDrift {
+    /// `(old, new)` kernel release.
+    pub kernel_release: Option<(String, String)>,
+    /// `(old, new)` kernel command line.
+    pub cmdline: Option<(String, String)>,
+    pub packages_added: Vec<String>,
+    pub packages_removed: Vec<String>,
+    /// `(name, old version, new version)`.
+    pub packages_changed: Vec<(String, String, String)>,
+    pub services_enabled: Vec<String>,
+    pub services_disabled: Vec<String>,
+}
+
+impl InventoryDrift {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for InventoryDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((old, new)) = &self.kernel_release {
+            writeln!(f, "  kernel: {} -> {}", old, new)?;
+        }
+        if let Some((old, new)) = &self.cmdline {
+            writeln!(f, "  cmdline: '{}' -> '{}'", old, new)?;
+        }
+        for package in &self.packages_added {
+            writeln!(f, "  + {}", package)?;
+        }
+        for package in &self.packages_removed {
+            writeln!(f, "  - {}", package)?;
+        }
+        for (name, old, new) in &self.packages_changed {
+            writeln!(f, "  ~ {} {} -> {}", name, old, new)?;
+        }
+        for service in &self.services_enabled {
+            writeln!(f, "  + service {}", service)?;
+        }
+        for service in &self.services_disabled {
+            writeln!(f, "  - service {}", service)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `inventory` as [`INVENTORY_FILENAME`] in `run_dir`.
+pub fn write_inventory(run_dir: &Path, inventory: &GuestInventory) -> Result<PathBuf> {
+    let path = run_dir.join(INVENTORY_FILENAME);
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    fs::write(&tmp, serde_json::to_vec_pretty(inventory)?)
+        .with_context(|| format!("writing '{}'", tmp.display()))?;
+    fs::rename(&tmp, &path).with_context(|| format!("replacing '{}'", path.display()))?;
+    Ok(path)
+}
+
+/// Inventory stored in `run_dir`, if the run recorded one.
+pub fn read_inventory(run_dir: &Path) -> Result<Option<GuestInventory>> {
+    let path = run_dir.join(INVENTORY_FILENAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let bytes = fs::read(&path).with_context(|| format!("reading '{}'", path.display()))?;
+    serde_json::from_slice(&bytes)
+        .map(Some)
+        .with_context(|| format!("parsing '{}'", path.display()))
+}
+
+/// Inventory of the newest successful run under `run_root_dir` other than
+/// `current_run_id` that recorded one, with its run id.
+pub fn previous_inventory(
+    run_root_dir: &Path,
+    current_run_id: &str,
+) -> Result<Option<(String, GuestInventory)>> {
+    let mut runs = load_run_metadata(run_root_dir)?;
+    runs.retain(|run| run.is_success() && run.run_id != current_run_id);
+    runs.sort_by_key(|run| Reverse(run_sort_key(run)));
+    for RunMetadata { run_id, .. } in runs {
+        if let Some(inventory) = read_inventory(&run_root_dir.join(&run_id))? {
+            return Ok(Some((run_id, inventory)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn inventory(kernel: &str, packages: &[&str], services: &[&str]) -> GuestInventory {
+        GuestInventory {
+            protocol_version: PROTOCOL_VERSION,
+            kernel_release: kernel.to_string(),
+            cmdline: "console=ttyS0".to_string(),
+            package_manager: "apk".to_string(),
+            packages: packages.iter().map(|p| p.to_string()).collect(),
+            enabled_services: services.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_drift_between_runs() {
+        let old = inventory(
+            "6.6.1",
+            &[
+                "busybox-1.36.1-r29",
+                "openssh-server-9.6_p1-r0",
+                "vim-9.0-r1",
+            ],
+            &["default/sshd"],
+        );
+        let new = inventory(
+            "6.6.2",
+            &[
+                "busybox-1.36.1-r30",
+                "openssh-server-9.6_p1-r0",
+                "curl-8.5.0-r0",
+            ],
+            &["default/sshd", "boot/chronyd"],
+        );
+        assert_eq!(
+            split_package("openssh-server-9.6_p1-r0"),
+            ("openssh-server", "9.6_p1-r0")
+        );
+        assert!(new.drift(&new).is_empty());
+
+        let drift = new.drift(&old);
+        assert_eq!(
+            drift.kernel_release,
+            Some(("6.6.1".to_string(), "6.6.2".to_string()))
+        );
+        assert_eq!(drift.cmdline, None);
+        assert_eq!(drift.packages_added, vec!["curl-8.5.0-r0"]);
+        assert_eq!(drift.packages_removed, vec!["vim-9.0-r1"]);
+        assert_eq!(
+            drift.packages_changed,
+            vec![(
+                "busybox".to_string(),
+                "1.36.1-r29".to_string(),
+                "1.36.1-r30".to_string()
+            )]
+        );
+        assert_eq!(drift.services_enabled, vec!["boot/chronyd"]);
+        assert!(drift
+            .to_string()
+            .contains("~ busybox 1.36.1-r29 -> 1.36.1-r30"));
+
+        let temp = TempDir::new().unwrap();
+        assert_eq!(read_inventory(temp.path()).unwrap(), None);
+        write_inventory(temp.path(), &new).unwrap();
+        assert_eq!(read_inventory(temp.path()).unwrap(), Some(new));
+
+        let wrong_version = serde_json::json!({
+            "protocol_version": PROTOCOL_VERSION + 1,
+            "kernel_release": "6.6.2",
+            "cmdline": "",
+            "package_manager": "none",
+            "packages": [],
+            "enabled_services": [],
+        });
+        assert!(GuestInventory::from_json(wrong_version).is_err());
+    }
+}
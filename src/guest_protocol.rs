@@ -0,0 +1,586 @@
+//! Serial marker protocol between guest test scripts and the host harness.
+//!
+//! Guests print single-line markers on the serial console. Every marker
+//! starts with `___` so it can be picked out of kernel and getty noise, and
+//! the READY marker carries the protocol version so the host can refuse
+//! images built against a different harness.
+//!
+//! ```text
+//! ___SHELL_READY___ proto=1
+//! ___DB_RESULT name=uefi status=pass___
+//! ___DB_JSON_BEGIN name=inventory___
+//! {"packages": 312}
+//! ___DB_JSON_END name=inventory___
+//! ___DB_B64_BEGIN name=results___
+//! eyJyZWNvcmRzIjogW119
+//! ___DB_B64_END name=results___
+//! ```
+//!
+//! The READY line keeps the historical `___SHELL_READY___` prefix so older
+//! tooling that only greps for it keeps working.
+//!
+//! Consoles that echo input, wrap long lines or interleave kernel messages
+//! can mangle a bare marker, so generated scripts wrap markers in a frame
+//! carrying the POSIX `cksum` CRC of the body:
+//!
+//! ```text
+//! ___DB_FRAME ck=1269651767___ ___SHELL_READY___ proto=1 ___DB_FRAME_END___
+//! ```
+//!
+//! The parser reassembles a frame split over a few lines and drops frames
+//! whose checksum does not match rather than acting on a corrupted marker.
+//! Unframed markers are still accepted.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::guest_inventory::INVENTORY_BLOCK_NAME;
+
+/// Version emitted by scripts packaged with this crate.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Prefix of the READY marker (also the legacy, unversioned marker).
+pub const READY_PREFIX: &str = "___SHELL_READY___";
+
+const RESULT_PREFIX: &str = "___DB_RESULT ";
+const JSON_BEGIN_PREFIX: &str = "___DB_JSON_BEGIN ";
+const JSON_END_PREFIX: &str = "___DB_JSON_END ";
+const B64_BEGIN_PREFIX: &str = "___DB_B64_BEGIN ";
+const B64_END_PREFIX: &str = "___DB_B64_END ";
+const MARKER_SUFFIX: &str = "___";
+const FRAME_BEGIN: &str = "___DB_FRAME ck=";
+const FRAME_HEADER_END: &str = "___ ";
+const FRAME_END: &str = " ___DB_FRAME_END___";
+
+/// Serial lines a wrapped frame may span before it is given up on.
+const MAX_FRAME_LINES: usize = 8;
+
+/// Upper bound on JSON block size, so a guest that never closes a block
+/// cannot grow the host's buffer forever.
+const MAX_JSON_LINES: usize = 10_000;
+
+/// The READY line current scripts print.
+pub fn ready_marker() -> String {
+    format!("{} proto={}", READY_PREFIX, PROTOCOL_VERSION)
+}
+
+/// [`ready_marker`] wrapped in a checksummed frame.
+pub fn framed_ready_marker() -> String {
+    frame(&ready_marker())
+}
+
+/// Wrap a marker line in a frame carrying its checksum.
+pub fn frame(body: &str) -> String {
+    format!(
+        "{}{}{}{}{}",
+        FRAME_BEGIN,
+        posix_cksum(body.as_bytes()),
+        FRAME_HEADER_END,
+        body,
+        FRAME_END
+    )
+}
+
+/// CRC computed by POSIX `cksum`, so guests can frame markers with
+/// `printf '%s' "$body" | cksum`.
+pub fn posix_cksum(data: &[u8]) -> u32 {
+    const POLY: u32 = 0x04C1_1DB7;
+    fn update(mut crc: u32, byte: u8) -> u32 {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ POLY
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    }
+    let mut crc = data.iter().fold(0, |crc, &byte| update(crc, byte));
+    let mut len = data.len();
+    while len != 0 {
+        crc = update(crc, (len & 0xff) as u8);
+        len >>= 8;
+    }
+    !crc
+}
+
+/// Outcome reported by a RESULT marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl TestStatus {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pass" => Some(Self::Pass),
+            "fail" => Some(Self::Fail),
+            "skip" => Some(Self::Skip),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+        })
+    }
+}
+
+/// A decoded event from the guest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuestEvent {
+    /// Shell is ready; `version` is `None` for legacy unversioned images.
+    Ready {
+        version: Option<u32>,
+    },
+    Result {
+        name: String,
+        status: TestStatus,
+    },
+    Json {
+        name: String,
+        value: serde_json::Value,
+    },
+}
+
+/// Fail unless the guest speaks [`PROTOCOL_VERSION`].
+pub fn check_protocol_version(version: Option<u32>) -> Result<()> {
+    match version {
+        Some(PROTOCOL_VERSION) => Ok(()),
+        Some(other) => bail!(
+            "guest speaks marker protocol v{}, host expects v{}\n\
+             Remediation: rebuild the image with this distro-builder version.",
+            other,
+            PROTOCOL_VERSION
+        ),
+        None => bail!(
+            "guest printed an unversioned {} marker (image predates marker protocol v{})\n\
+             Remediation: rebuild the image so its test scripts emit '{}'.",
+            READY_PREFIX,
+            PROTOCOL_VERSION,
+            ready_marker()
+        ),
+    }
+}
+
+/// Line-at-a-time decoder for serial output.
+#[derive(Debug, Default)]
+pub struct MarkerParser {
+    block: Option<Block>,
+    frame: Option<PendingFrame>,
+}
+
+/// A frame whose end sentinel has not arrived yet.
+#[derive(Debug, Default)]
+struct PendingFrame {
+    text: String,
+    lines: usize,
+}
+
+enum FrameParse<'a> {
+    Incomplete,
+    Valid(&'a str),
+    Invalid,
+}
+
+#[derive(Debug, Default)]
+struct Block {
+    name: String,
+    base64: bool,
+    lines: Vec<String>,
+}
+
+impl MarkerParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one serial line; returns an event when a marker completes.
+    pub fn feed(&mut self, line: &str) -> Result<Option<GuestEvent>> {
+        match self.unframe(line) {
+            Some(line) => self.feed_marker(&line),
+            None => Ok(None),
+        }
+    }
+
+    /// Strip framing from `line`. `None` means the line was consumed by a
+    /// pending frame or belonged to a corrupted one.
+    fn unframe<'a>(&mut self, line: &'a str) -> Option<Cow<'a, str>> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let pending = match (self.frame.take(), line.find(FRAME_BEGIN)) {
+            // A new frame start supersedes a half-received one.
+            (_, Some(idx)) => PendingFrame {
+                text: line[idx..].to_string(),
+                lines: 1,
+            },
+            (Some(mut pending), None) => {
+                // Terminal wrapping splits one line into several.
+                pending.text.push_str(line);
+                pending.lines += 1;
+                pending
+            }
+            (None, None) => return Some(Cow::Borrowed(line)),
+        };
+        match parse_frame(&pending.text) {
+            FrameParse::Valid(body) => Some(Cow::Owned(body.to_string())),
+            FrameParse::Invalid => None,
+            FrameParse::Incomplete => {
+                if pending.lines < MAX_FRAME_LINES {
+                    self.frame = Some(pending);
+                }
+                None
+            }
+        }
+    }
+
+    fn feed_marker(&mut self, line: &str) -> Result<Option<GuestEvent>> {
+        if let Some(block) = self.block.take() {
+            let end_prefix = if block.base64 {
+                B64_END_PREFIX
+            } else {
+                JSON_END_PREFIX
+            };
+            if let Some(fields) = marker_fields(line, end_prefix) {
+                return block.finish(field(&fields, "name").unwrap_or_default());
+            }
+            let mut block = block;
+            if block.lines.len() >= MAX_JSON_LINES {
+                bail!(
+                    "guest JSON block '{}' exceeds {} lines",
+                    block.name,
+                    MAX_JSON_LINES
+                );
+            }
+            block.lines.push(line.trim_end_matches('\r').to_string());
+            self.block = Some(block);
+            return Ok(None);
+        }
+
+        if let Some(rest) = line
+            .find(READY_PREFIX)
+            .map(|idx| &line[idx + READY_PREFIX.len()..])
+        {
+            let version = match rest
+                .split_whitespace()
+                .next()
+                .and_then(|t| t.strip_prefix("proto="))
+            {
+                Some(v) => Some(
+                    v.parse()
+                        .with_context(|| format!("invalid protocol version in '{}'", line))?,
+                ),
+                None => None,
+            };
+            return Ok(Some(GuestEvent::Ready { version }));
+        }
+        if let Some(fields) = marker_fields(line, RESULT_PREFIX) {
+            let name = field(&fields, "name").context("RESULT marker without name")?;
+            let status = field(&fields, "status")
+                .and_then(TestStatus::parse)
+                .with_context(|| format!("RESULT marker '{}' has no valid status", name))?;
+            return Ok(Some(GuestEvent::Result {
+                name: name.to_string(),
+                status,
+            }));
+        }
+        for (prefix, base64) in [(JSON_BEGIN_PREFIX, false), (B64_BEGIN_PREFIX, true)] {
+            if let Some(fields) = marker_fields(line, prefix) {
+                let name = field(&fields, "name").context("block marker without name")?;
+                self.block = Some(Block {
+                    name: name.to_string(),
+                    base64,
+                    lines: Vec::new(),
+                });
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Block {
+    fn finish(self, end_name: &str) -> Result<Option<GuestEvent>> {
+        if end_name != self.name {
+            bail!(
+                "JSON block '{}' closed with mismatched name '{}'",
+                self.name,
+                end_name
+            );
+        }
+        let text = if self.base64 {
+            let decoded = decode_base64(&self.lines.concat())
+                .with_context(|| format!("guest block '{}' is not valid base64", self.name))?;
+            String::from_utf8(decoded)
+                .with_context(|| format!("guest block '{}' is not UTF-8", self.name))?
+        } else {
+            self.lines.join("\n")
+        };
+        let value = serde_json::from_str(&text)
+            .with_context(|| format!("guest JSON block '{}' is not valid JSON", self.name))?;
+        Ok(Some(GuestEvent::Json {
+            name: self.name,
+            value,
+        }))
+    }
+}
+
+/// Check a frame that starts at `text[0]`.
+fn parse_frame(text: &str) -> FrameParse<'_> {
+    let Some(end) = text.find(FRAME_END) else {
+        return FrameParse::Incomplete;
+    };
+    let Some((ck, body)) = text[FRAME_BEGIN.len()..end].split_once(FRAME_HEADER_END) else {
+        return FrameParse::Invalid;
+    };
+    match ck.parse::<u32>() {
+        Ok(ck) if ck == posix_cksum(body.as_bytes()) => FrameParse::Valid(body),
+        _ => FrameParse::Invalid,
+    }
+}
+
+/// Standard-alphabet base64; whitespace is ignored, padding optional.
+fn decode_base64(input: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            other => bail!("invalid base64 byte {:?}", other as char),
+        };
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Shell helpers matching this protocol, installed next to `common.sh`.
+pub fn shell_library() -> String {
+    format!(
+        r#"# Generated by distro-builder: serial marker protocol v{version}.
+DB_PROTOCOL_VERSION={version}
+
+# db_frame <marker...>: checksummed frame so echo and wrapping are detected.
+db_frame() {{
+    _db_body="$*"
+    _db_ck=$(printf '%s' "$_db_body" | cksum)
+    echo "{begin}${{_db_ck%% *}}{header_end}${{_db_body}}{end}"
+}}
+
+db_ready() {{
+    echo "{ready}"
+}}
+
+# db_result <name> <pass|fail|skip>
+db_result() {{
+    db_frame "___DB_RESULT name=$1 status=$2___"
+}}
+
+# db_json <name> <json...>
+db_json() {{
+    _db_name="$1"
+    shift
+    db_frame "___DB_JSON_BEGIN name=${{_db_name}}___"
+    printf '%s\n' "$*"
+    db_frame "___DB_JSON_END name=${{_db_name}}___"
+}}
+
+# db_json_file <name> <path>: base64 so serial line discipline cannot mangle it.
+db_json_file() {{
+    db_frame "___DB_B64_BEGIN name=$1___"
+    base64 "$2"
+    db_frame "___DB_B64_END name=$1___"
+}}
+
+# stdin lines as a JSON array of strings / the first line as a JSON string.
+db_json_array() {{
+    sed 's/\\/\\\\/g; s/"/\\"/g' |
+        awk 'BEGIN {{ printf "[" }} {{ printf "%s\"%s\"", (NR > 1 ? "," : ""), $0 }} END {{ printf "]" }}'
+}}
+db_json_string() {{
+    sed 's/\\/\\\\/g; s/"/\\"/g' | awk 'NR == 1 {{ s = $0 }} END {{ printf "\"%s\"", s }}'
+}}
+
+# db_inventory: installed packages, enabled services, kernel release and
+# cmdline, sent as the {inventory} block.
+db_inventory() {{
+    _db_inv=/run/scenario-tests/{inventory}.json
+    mkdir -p /run/scenario-tests
+    if command -v apk >/dev/null 2>&1; then
+        _db_pm=apk
+        _db_pkgs() {{ apk info -v 2>/dev/null; }}
+    elif command -v rpm >/dev/null 2>&1; then
+        _db_pm=rpm
+        _db_pkgs() {{ rpm -qa --qf '%{{NAME}}-%{{VERSION}}-%{{RELEASE}}\n' 2>/dev/null; }}
+    else
+        _db_pm=none
+        _db_pkgs() {{ :; }}
+    fi
+    if command -v systemctl >/dev/null 2>&1; then
+        _db_svcs() {{ systemctl list-unit-files --state=enabled --no-legend 2>/dev/null | awk '{{ print $1 }}'; }}
+    else
+        _db_svcs() {{ for _db_s in /etc/runlevels/*/*; do [ -e "$_db_s" ] && echo "${{_db_s#/etc/runlevels/}}"; done; }}
+    fi
+    {{
+        printf '{{"protocol_version":%s,"kernel_release":' "$DB_PROTOCOL_VERSION"
+        uname -r | db_json_string
+        printf ',"cmdline":'
+        db_json_string </proc/cmdline
+        printf ',"package_manager":"%s","packages":' "$_db_pm"
+        _db_pkgs | sort | db_json_array
+        printf ',"enabled_services":'
+        _db_svcs | sort | db_json_array
+        printf '}}\n'
+    }} >"$_db_inv"
+    db_json_file {inventory} "$_db_inv"
+}}
+"#,
+        version = PROTOCOL_VERSION,
+        inventory = INVENTORY_BLOCK_NAME,
+        ready = framed_ready_marker(),
+        begin = FRAME_BEGIN,
+        header_end = FRAME_HEADER_END,
+        end = FRAME_END,
+    )
+}
+
+fn marker_fields<'a>(line: &'a str, prefix: &str) -> Option<Vec<(&'a str, &'a str)>> {
+    let start = line.find(prefix)? + prefix.len();
+    let body = line[start..].trim_end().strip_suffix(MARKER_SUFFIX)?;
+    Some(
+        body.split_whitespace()
+            .filter_map(|pair| pair.split_once('='))
+            .collect(),
+    )
+}
+
+fn field<'a>(fields: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready_versions() {
+        let mut parser = MarkerParser::new();
+        let event = parser.feed(&format!("[  3.1] {}", ready_marker())).unwrap();
+        assert_eq!(
+            event,
+            Some(GuestEvent::Ready {
+                version: Some(PROTOCOL_VERSION)
+            })
+        );
+        let legacy = parser.feed("___SHELL_READY___").unwrap();
+        assert_eq!(legacy, Some(GuestEvent::Ready { version: None }));
+        assert!(check_protocol_version(None).is_err());
+        assert!(check_protocol_version(Some(PROTOCOL_VERSION + 1)).is_err());
+    }
+
+    #[test]
+    fn test_result_and_json_block() {
+        let mut parser = MarkerParser::new();
+        assert_eq!(
+            parser
+                .feed("___DB_RESULT name=uefi status=pass___")
+                .unwrap(),
+            Some(GuestEvent::Result {
+                name: "uefi".to_string(),
+                status: TestStatus::Pass
+            })
+        );
+        assert_eq!(parser.feed("___DB_JSON_BEGIN name=inv___").unwrap(), None);
+        assert_eq!(parser.feed("{\"packages\":").unwrap(), None);
+        assert_eq!(parser.feed("3}\r").unwrap(), None);
+        assert_eq!(
+            parser.feed("___DB_JSON_END name=inv___").unwrap(),
+            Some(GuestEvent::Json {
+                name: "inv".to_string(),
+                value: serde_json::json!({"packages": 3})
+            })
+        );
+        assert!(parser.feed("___DB_RESULT name=x status=maybe___").is_err());
+    }
+
+    #[test]
+    fn test_base64_block() {
+        let mut parser = MarkerParser::new();
+        parser.feed("___DB_B64_BEGIN name=results___").unwrap();
+        parser.feed("eyJyZWNvcmRz").unwrap();
+        parser.feed("IjogW119").unwrap();
+        assert_eq!(
+            parser.feed("___DB_B64_END name=results___").unwrap(),
+            Some(GuestEvent::Json {
+                name: "results".to_string(),
+                value: serde_json::json!({"records": []})
+            })
+        );
+    }
+
+    #[test]
+    fn test_shell_library_uses_ready_marker() {
+        assert!(shell_library().contains(&framed_ready_marker()));
+    }
+
+    #[test]
+    fn test_posix_cksum() {
+        // Values from coreutils `printf ... | cksum`.
+        assert_eq!(posix_cksum(b""), 4_294_967_295);
+        assert_eq!(posix_cksum(b"abc"), 1_219_131_554);
+    }
+
+    #[test]
+    fn test_framed_markers() {
+        let ready = Some(GuestEvent::Ready {
+            version: Some(PROTOCOL_VERSION),
+        });
+        let framed = framed_ready_marker();
+
+        let mut parser = MarkerParser::new();
+        assert_eq!(parser.feed(&format!("echo\r{framed}\r")).unwrap(), ready);
+
+        // Wrapped at the terminal width.
+        let (head, tail) = framed.split_at(30);
+        assert_eq!(parser.feed(head).unwrap(), None);
+        assert_eq!(parser.feed(tail).unwrap(), ready);
+
+        // Corrupted body: dropped instead of misparsed.
+        let mangled = framed.replace("proto=1", "proto=l");
+        assert_eq!(parser.feed(&mangled).unwrap(), None);
+
+        // A restart discards the broken half frame.
+        assert_eq!(parser.feed(head).unwrap(), None);
+        assert_eq!(parser.feed(&framed).unwrap(), ready);
+
+        let result = frame("___DB_RESULT name=uefi status=pass___");
+        assert_eq!(
+            parser.feed(&result).unwrap(),
+            Some(GuestEvent::Result {
+                name: "uefi".to_string(),
+                status: TestStatus::Pass
+            })
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Typed test results collected from a booted guest.
+//!
+//! Guest scenario scripts append records to [`GUEST_RESULTS_PATH`]; after
+//! boot verification the host asks the guest to send that file over serial
+//! (see [`crate::guest_protocol`]) and merges it with its own host-side
+//! checks. The combined records are stored in the run manifest under
+//! `test_results`, so a run's pass/fail state no longer has to be recovered
+//! by grepping console logs.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::guest_inventory::GuestInventory;
+use crate::guest_protocol::{TestStatus, PROTOCOL_VERSION};
+use crate::run_history::run_manifest_path;
+
+/// Results file written by guest scenario scripts.
+pub const GUEST_RESULTS_PATH: &str = "/run/scenario-tests/results.json";
+
+/// Block name the results file is sent under.
+pub const RESULTS_BLOCK_NAME: &str = "results";
+
+/// Run manifest key holding merged results.
+const MANIFEST_KEY: &str = "test_results";
+
+/// One test outcome.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestRecord {
+    pub name: String,
+    pub status: TestStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl TestRecord {
+    pub fn new(name: impl Into<String>, status: TestStatus) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            duration_ms: None,
+            message: None,
+        }
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+}
+
+/// All records for one guest run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuestTestResults {
+    pub protocol_version: u32,
+    pub records: Vec<TestRecord>,
+    /// Guest inventory, when the boot test collected one. Stored as its
+    /// own evidence file rather than in the run manifest.
+    #[serde(skip)]
+    pub inventory: Option<GuestInventory>,
+}
+
+impl Default for GuestTestResults {
+    fn default() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            records: Vec::new(),
+            inventory: None,
+        }
+    }
+}
+
+impl GuestTestResults {
+    /// Parse the guest results document.
+    pub fn from_json(value: serde_json::Value) -> Result<Self> {
+        let results: Self =
+            serde_json::from_value(value).context("guest results do not match the schema")?;
+        if results.protocol_version != PROTOCOL_VERSION {
+            bail!(
+                "guest results use protocol v{}, host expects v{}",
+                results.protocol_version,
+                PROTOCOL_VERSION
+            );
+        }
+        Ok(results)
+    }
+
+    pub fn push(&mut self, record: TestRecord) {
+        self.records.push(record);
+    }
+
+    /// Append `other`'s records; a later record with the same name replaces
+    /// the earlier one.
+    pub fn merge(&mut self, other: GuestTestResults) {
+        if other.inventory.is_some() {
+            self.inventory = other.inventory;
+        }
+        for record in other.records {
+            self.records.retain(|r| r.name != record.name);
+            self.records.push(record);
+        }
+    }
+
+    pub fn failed(&self) -> Vec<&TestRecord> {
+        self.records
+            .iter()
+            .filter(|r| r.status == TestStatus::Fail)
+            .collect()
+    }
+
+    pub fn count(&self, status: TestStatus) -> usize {
+        self.records.iter().filter(|r| r.status == status).count()
+    }
+
+    /// Error listing every failed record, if any.
+    pub fn ensure_passed(&self) -> Result<()> {
+        let failed = self.failed();
+        if failed.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = failed
+            .iter()
+            .map(|r| match &r.message {
+                Some(message) => format!("  {}: {}", r.name, message),
+                None => format!("  {}", r.name),
+            })
+            .collect();
+        bail!(
+            "{} guest test(s) failed:\n{}",
+            failed.len(),
+            lines.join("\n")
+        );
+    }
+}
+
+/// Store `results` in the run manifest of `run_dir`, keeping other fields.
+pub fn merge_into_run_manifest(run_dir: &Path, results: &GuestTestResults) -> Result<()> {
+    let path = run_manifest_path(run_dir);
+    let bytes =
+        fs::read(&path).with_context(|| format!("reading run manifest '{}'", path.display()))?;
+    let mut manifest: serde_json::Value = serde_json::from_slice(&bytes)
+        .with_context(|| format!("parsing run manifest '{}'", path.display()))?;
+    let Some(object) = manifest.as_object_mut() else {
+        bail!("run manifest '{}' is not a JSON object", path.display());
+    };
+    object.insert(MANIFEST_KEY.to_string(), serde_json::to_value(results)?);
+
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    fs::write(&tmp, serde_json::to_vec_pretty(&manifest)?)
+        .with_context(|| format!("writing '{}'", tmp.display()))?;
+    fs::rename(&tmp, &path).with_context(|| format!("replacing '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_and_failures() {
+        let mut results = GuestTestResults::default();
+        results.push(TestRecord::new("uefi", TestStatus::Pass));
+        results.push(TestRecord::new("sshd", TestStatus::Fail));
+
+        let guest = GuestTestResults::from_json(serde_json::json!({
+            "protocol_version": PROTOCOL_VERSION,
+            "records": [
+                {"name": "sshd", "status": "pass", "duration_ms": 120},
+                {"name": "network", "status": "fail", "message": "no route"}
+            ]
+        }))
+        .unwrap();
+        results.merge(guest);
+
+        assert_eq!(results.count(TestStatus::Pass), 2);
+        let err = results.ensure_passed().unwrap_err().to_string();
+        assert!(err.contains("network: no route"));
+        assert!(!err.contains("sshd"));
+    }
+
+    #[test]
+    fn test_merge_into_run_manifest_keeps_fields() {
+        let run_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            run_manifest_path(run_dir.path()),
+            r#"{"run_id": "r1", "status": "success"}"#,
+        )
+        .unwrap();
+        let mut results = GuestTestResults::default();
+        results.push(TestRecord::new("boot", TestStatus::Pass));
+        merge_into_run_manifest(run_dir.path(), &results).unwrap();
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(run_manifest_path(run_dir.path())).unwrap()).unwrap();
+        assert_eq!(manifest["run_id"], "r1");
+        assert_eq!(manifest["test_results"]["records"][0]["status"], "pass");
+    }
+}
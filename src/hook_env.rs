@@ -0,0 +1,378 @@
+//! Environment contract between the builder and variant release hooks.
+//!
+//! `release build iso` runs the variant's `*-release.sh` hook with `sh -e`
+//! and passes everything the hook needs through environment variables.
+//! Errexit makes a failing helper such as `$BUILD_UKIS` stop the hook before
+//! it packs the ISO. The variables are described by [`HookEnvironment`]:
+//! each field documents the variable it becomes, and
+//! [`HookEnvironment::vars`] is the only place they are named. The same
+//! values are written as `hook-env.json` into the release run directory,
+//! and `HOOK_ENV_FILE` points at it, for hooks that would rather parse JSON
+//! than read a long list of variables.
+//!
+//! The contract is versioned by [`HOOK_ENV_VERSION`]. Variables are only
+//! ever added within a version; renaming or dropping one bumps it. Hooks
+//! declare the version they consume in a comment before their first
+//! command:
+//!
+//! ```sh
+//! #!/bin/sh
+//! # distro-builder-hook-env: 1
+//! set -eu
+//! ```
+//!
+//! [`check_hook_version`] refuses a hook that declares another version and
+//! warns about one that declares none.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Current hook environment version.
+pub const HOOK_ENV_VERSION: u32 = 1;
+
+/// File name of the JSON copy of the environment in the release run dir.
+pub const HOOK_ENV_FILENAME: &str = "hook-env.json";
+
+/// Comment key a hook declares its environment version with.
+pub const HOOK_ENV_DECLARATION: &str = "distro-builder-hook-env:";
+
+/// Everything a variant release hook receives. Field docs name the
+/// environment variable each field is exported as.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HookEnvironment {
+    /// `HOOK_ENV_VERSION`: always [`HOOK_ENV_VERSION`] when rendered.
+    pub version: u32,
+    /// `DISTRO_ID`.
+    pub distro_id: String,
+    /// `IDENTITY_OS_NAME`.
+    pub os_name: String,
+    /// `IDENTITY_OS_ID`.
+    pub os_id: String,
+    /// `IDENTITY_OS_VERSION`.
+    pub os_version: String,
+    /// `IDENTITY_ISO_LABEL`.
+    pub iso_label: String,
+    /// `LIVE_UKI_FILENAME`.
+    pub live_uki_filename: String,
+    /// `EMERGENCY_UKI_FILENAME`.
+    pub emergency_uki_filename: String,
+    /// `DEBUG_UKI_FILENAME`.
+    pub debug_uki_filename: String,
+    /// `LIVE_UKI_CMDLINE`.
+    pub live_uki_cmdline: String,
+    /// `EMERGENCY_UKI_CMDLINE`.
+    pub emergency_uki_cmdline: String,
+    /// `DEBUG_UKI_CMDLINE`.
+    pub debug_uki_cmdline: String,
+    /// `BOOT_MENU_ESP_DIR`: ESP files the hook copies onto the ISO's ESP.
+    pub boot_menu_esp_dir: PathBuf,
+    /// `SPLASH_BITMAP`; empty when the variant has no splash bitmap.
+    pub splash_bitmap: Option<PathBuf>,
+    /// `SPLASH_PLYMOUTH_THEME`; empty when the variant has no Plymouth theme.
+    pub splash_plymouth_theme: Option<String>,
+    /// `KERNEL_OUTPUT_DIR`.
+    pub kernel_output_dir: PathBuf,
+    /// `KERNEL_RELEASE_PATH`.
+    pub kernel_release_path: PathBuf,
+    /// `KERNEL_IMAGE_PATH`.
+    pub kernel_image_path: PathBuf,
+    /// `ISO_PATH`: where the hook must leave the ISO.
+    pub iso_path: PathBuf,
+    /// `ISO_FILENAME`.
+    pub iso_filename: String,
+    /// `PRODUCT_NAME`.
+    pub product_name: String,
+    /// `PRODUCT_DIRNAME` and `PRODUCT_ARTIFACT_TAG`.
+    pub product_dirname: String,
+    /// `PRODUCT_BOOT_LABEL`.
+    pub product_boot_label: String,
+    /// `PRODUCT_REQUIRED_KERNEL_CMDLINE`: tokens the live UKI must carry.
+    pub product_required_kernel_cmdline: String,
+    /// `ROOTFS_FILENAME`.
+    pub rootfs_filename: String,
+    /// `INITRAMFS_LIVE_FILENAME`.
+    pub initramfs_live_filename: String,
+    /// `INITRAMFS_COMPRESS`: the shell filter the hook compresses the
+    /// initramfs cpio with, from `[compression] initramfs` in
+    /// `distro-builder.toml` (e.g. `gzip -n -c -6`).
+    pub initramfs_compress: String,
+    /// `LIVE_OVERLAY_DIRNAME`.
+    pub live_overlay_dirname: String,
+    /// `LIVE_OVERLAY_IMAGE_FILENAME`.
+    pub live_overlay_image_filename: String,
+    /// `INSTALLER_IMAGE_FILENAME`: the installer squashfs `product prepare`
+    /// leaves next to the overlay, for the hook to copy into the ISO `live/`
+    /// directory. Empty for products without the installer stage.
+    pub installer_image_filename: Option<String>,
+    /// `ROOTFS_SOURCE_POINTER_FILENAME`.
+    pub rootfs_source_pointer_filename: String,
+    /// [`crate::repo_config::OUTPUT_ROOT_ENV`]: the artifacts root.
+    pub output_root: PathBuf,
+    /// `RELEASE_ROOT_DIR`.
+    pub release_root_dir: PathBuf,
+    /// `RELEASE_RUN_DIR` and `RELEASE_OUTPUT_DIR`.
+    pub release_run_dir: PathBuf,
+    /// `BUILD_RUN_ID`; empty for ad-hoc builds.
+    pub build_run_id: Option<String>,
+    /// `DISTRO_BUILDER_BIN`. Also prefixes the helper commands exported as
+    /// `BUILD_UKIS`, `INITRAMFS_CONTRACT_CHECK`, `NETBOOT_INIT` and
+    /// `WRITABLE_LAYER_INIT`.
+    pub distro_builder_bin: PathBuf,
+}
+
+impl HookEnvironment {
+    /// Path of the JSON copy inside the release run dir (`HOOK_ENV_FILE`).
+    pub fn json_path(&self) -> PathBuf {
+        self.release_run_dir.join(HOOK_ENV_FILENAME)
+    }
+
+    /// Every variable the hook receives, in a stable order.
+    pub fn vars(&self) -> Vec<(&'static str, OsString)> {
+        let text = |value: &str| OsString::from(value);
+        let path = |value: &Path| value.as_os_str().to_os_string();
+        let helper = |subcommand: &str| {
+            OsString::from(format!(
+                "{} artifact {} {}",
+                self.distro_builder_bin.display(),
+                subcommand,
+                self.distro_id
+            ))
+        };
+        vec![
+            ("HOOK_ENV_VERSION", text(&self.version.to_string())),
+            ("HOOK_ENV_FILE", path(&self.json_path())),
+            ("DISTRO_ID", text(&self.distro_id)),
+            ("IDENTITY_OS_NAME", text(&self.os_name)),
+            ("IDENTITY_OS_ID", text(&self.os_id)),
+            ("IDENTITY_OS_VERSION", text(&self.os_version)),
+            ("IDENTITY_ISO_LABEL", text(&self.iso_label)),
+            ("LIVE_UKI_FILENAME", text(&self.live_uki_filename)),
+            ("EMERGENCY_UKI_FILENAME", text(&self.emergency_uki_filename)),
+            ("DEBUG_UKI_FILENAME", text(&self.debug_uki_filename)),
+            ("LIVE_UKI_CMDLINE", text(&self.live_uki_cmdline)),
+            ("EMERGENCY_UKI_CMDLINE", text(&self.emergency_uki_cmdline)),
+            ("DEBUG_UKI_CMDLINE", text(&self.debug_uki_cmdline)),
+            ("BUILD_UKIS", helper("build-ukis")),
+            ("BOOT_MENU_ESP_DIR", path(&self.boot_menu_esp_dir)),
+            (
+                "SPLASH_BITMAP",
+                path(self.splash_bitmap.as_deref().unwrap_or(Path::new(""))),
+            ),
+            (
+                "SPLASH_PLYMOUTH_THEME",
+                text(self.splash_plymouth_theme.as_deref().unwrap_or_default()),
+            ),
+            ("KERNEL_OUTPUT_DIR", path(&self.kernel_output_dir)),
+            ("KERNEL_RELEASE_PATH", path(&self.kernel_release_path)),
+            ("KERNEL_IMAGE_PATH", path(&self.kernel_image_path)),
+            ("ISO_PATH", path(&self.iso_path)),
+            ("ISO_FILENAME", text(&self.iso_filename)),
+            ("PRODUCT_NAME", text(&self.product_name)),
+            ("PRODUCT_DIRNAME", text(&self.product_dirname)),
+            ("PRODUCT_ARTIFACT_TAG", text(&self.product_dirname)),
+            ("PRODUCT_BOOT_LABEL", text(&self.product_boot_label)),
+            (
+                "PRODUCT_REQUIRED_KERNEL_CMDLINE",
+                text(&self.product_required_kernel_cmdline),
+            ),
+            ("ROOTFS_FILENAME", text(&self.rootfs_filename)),
+            (
+                "INITRAMFS_LIVE_FILENAME",
+                text(&self.initramfs_live_filename),
+            ),
+            ("INITRAMFS_COMPRESS", text(&self.initramfs_compress)),
+            ("INITRAMFS_CONTRACT_CHECK", helper("check-initramfs")),
+            ("NETBOOT_INIT", helper("netboot-init")),
+            ("WRITABLE_LAYER_INIT", helper("writable-layer-init")),
+            ("LIVE_OVERLAY_DIRNAME", text(&self.live_overlay_dirname)),
+            (
+                "LIVE_OVERLAY_IMAGE_FILENAME",
+                text(&self.live_overlay_image_filename),
+            ),
+            (
+                "INSTALLER_IMAGE_FILENAME",
+                text(self.installer_image_filename.as_deref().unwrap_or_default()),
+            ),
+            (
+                "ROOTFS_SOURCE_POINTER_FILENAME",
+                text(&self.rootfs_source_pointer_filename),
+            ),
+            (crate::repo_config::OUTPUT_ROOT_ENV, path(&self.output_root)),
+            ("RELEASE_ROOT_DIR", path(&self.release_root_dir)),
+            ("RELEASE_RUN_DIR", path(&self.release_run_dir)),
+            ("RELEASE_OUTPUT_DIR", path(&self.release_run_dir)),
+            (
+                "BUILD_RUN_ID",
+                text(self.build_run_id.as_deref().unwrap_or_default()),
+            ),
+            ("DISTRO_BUILDER_BIN", path(&self.distro_builder_bin)),
+        ]
+    }
+
+    /// Write the JSON copy to [`Self::json_path`].
+    pub fn write_json(&self) -> Result<PathBuf> {
+        let path = self.json_path();
+        fs::create_dir_all(&self.release_run_dir)
+            .with_context(|| format!("Failed to create {}", self.release_run_dir.display()))?;
+        let payload = serde_json::to_vec_pretty(self).context("serializing hook environment")?;
+        fs::write(&path, payload).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// The version a hook script declares, from the comment block before its
+/// first command. `None` when it declares none.
+pub fn declared_version(script: &str) -> Result<Option<u32>> {
+    for line in script.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+        let Some(comment) = line.strip_prefix('#') else {
+            break;
+        };
+        if let Some(value) = comment.trim().strip_prefix(HOOK_ENV_DECLARATION) {
+            let value = value.trim();
+            return value
+                .parse()
+                .map(Some)
+                .with_context(|| format!("invalid hook environment version '{}'", value));
+        }
+    }
+    Ok(None)
+}
+
+/// Fail if the hook at `path` declares a version other than
+/// [`HOOK_ENV_VERSION`]; warn if it declares none.
+pub fn check_hook_version(path: &Path) -> Result<()> {
+    let script = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read release hook {}", path.display()))?;
+    let declared = declared_version(&script)
+        .with_context(|| format!("Failed to parse release hook {}", path.display()))?;
+    match declared {
+        Some(HOOK_ENV_VERSION) => Ok(()),
+        Some(other) => bail!(
+            "release hook {} consumes hook environment v{}, this builder provides v{}\n\
+             Remediation: port the hook to v{} (see distro_builder::hook_env) and update its '# {} {}' line.",
+            path.display(),
+            other,
+            HOOK_ENV_VERSION,
+            HOOK_ENV_VERSION,
+            HOOK_ENV_DECLARATION,
+            HOOK_ENV_VERSION
+        ),
+        None => {
+            eprintln!(
+                "  [WARN] release hook {} does not declare the hook environment version it consumes; add '# {} {}' after its shebang",
+                path.display(),
+                HOOK_ENV_DECLARATION,
+                HOOK_ENV_VERSION
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn sample() -> HookEnvironment {
+        HookEnvironment {
+            version: HOOK_ENV_VERSION,
+            distro_id: "levitate".to_string(),
+            os_name: "LevitateOS".to_string(),
+            os_id: "levitateos".to_string(),
+            os_version: "1.0".to_string(),
+            iso_label: "LEVITATEOS".to_string(),
+            live_uki_filename: "levitateos-live.efi".to_string(),
+            emergency_uki_filename: "levitateos-emergency.efi".to_string(),
+            debug_uki_filename: "levitateos-debug.efi".to_string(),
+            live_uki_cmdline: "quiet".to_string(),
+            emergency_uki_cmdline: "quiet emergency".to_string(),
+            debug_uki_cmdline: "debug".to_string(),
+            boot_menu_esp_dir: PathBuf::from("/run/.boot-menu-esp"),
+            splash_bitmap: None,
+            splash_plymouth_theme: Some("spinner".to_string()),
+            kernel_output_dir: PathBuf::from("/kernel"),
+            kernel_release_path: PathBuf::from("/kernel/release"),
+            kernel_image_path: PathBuf::from("/kernel/vmlinuz"),
+            iso_path: PathBuf::from("/run/levitateos.iso"),
+            iso_filename: "levitateos.iso".to_string(),
+            product_name: "live-boot".to_string(),
+            product_dirname: "live-boot".to_string(),
+            product_boot_label: "Live Boot".to_string(),
+            product_required_kernel_cmdline: "rd.live".to_string(),
+            rootfs_filename: "filesystem.erofs".to_string(),
+            initramfs_live_filename: "initramfs-live.cpio.gz".to_string(),
+            initramfs_compress: "gzip -n -c -6".to_string(),
+            live_overlay_dirname: "live-overlay".to_string(),
+            live_overlay_image_filename: "overlayfs.erofs".to_string(),
+            installer_image_filename: None,
+            rootfs_source_pointer_filename: "rootfs-source.json".to_string(),
+            output_root: PathBuf::from("/artifacts"),
+            release_root_dir: PathBuf::from("/artifacts/releases"),
+            release_run_dir: PathBuf::from("/run"),
+            build_run_id: None,
+            distro_builder_bin: PathBuf::from("/bin/distro-builder"),
+        }
+    }
+
+    fn lookup(vars: &[(&'static str, OsString)], name: &str) -> String {
+        let (_, value) = vars.iter().find(|(key, _)| *key == name).unwrap();
+        value.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn renders_each_variable_once() {
+        let vars = sample().vars();
+        let names: BTreeSet<_> = vars.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names.len(), vars.len());
+        assert_eq!(lookup(&vars, "HOOK_ENV_VERSION"), "1");
+        assert_eq!(lookup(&vars, "HOOK_ENV_FILE"), "/run/hook-env.json");
+        assert_eq!(lookup(&vars, "SPLASH_BITMAP"), "");
+        assert_eq!(lookup(&vars, "BUILD_RUN_ID"), "");
+        assert_eq!(lookup(&vars, "INITRAMFS_COMPRESS"), "gzip -n -c -6");
+        assert_eq!(
+            lookup(&vars, "NETBOOT_INIT"),
+            "/bin/distro-builder artifact netboot-init levitate"
+        );
+        assert_eq!(
+            lookup(&vars, "RELEASE_OUTPUT_DIR"),
+            lookup(&vars, "RELEASE_RUN_DIR")
+        );
+    }
+
+    #[test]
+    fn json_copy_round_trips() {
+        let mut env = sample();
+        let dir = tempfile::tempdir().unwrap();
+        env.release_run_dir = dir.path().to_path_buf();
+        let path = env.write_json().unwrap();
+        let parsed: HookEnvironment = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(parsed, env);
+    }
+
+    #[test]
+    fn reads_declared_version_from_leading_comments() {
+        assert_eq!(
+            declared_version("#!/bin/sh\n\n# distro-builder-hook-env: 1\nset -eu\n").unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            declared_version("#!/bin/sh\nset -eu\n# distro-builder-hook-env: 1\n").unwrap(),
+            None
+        );
+        assert!(declared_version("#!/bin/sh\n# distro-builder-hook-env: one\n").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let hook = dir.path().join("build-release.sh");
+        fs::write(&hook, "#!/bin/sh\n# distro-builder-hook-env: 2\n").unwrap();
+        assert!(check_hook_version(&hook).is_err());
+        fs::write(&hook, "#!/bin/sh\nexit 0\n").unwrap();
+        assert!(check_hook_version(&hook).is_ok());
+    }
+}
@@ -0,0 +1,226 @@
+//! Host identity written into images: hostname, machine-id, random seed.
+//!
+//! Every image gets exactly one [`IdentityPolicy`] applied to its root:
+//!
+//! - [`IdentityPolicy::fixed_for_tests`]: a well-known machine-id so boot
+//!   tests and journal paths are reproducible (live ISOs).
+//! - [`IdentityPolicy::first_boot`]: no identity baked in; systemd sees
+//!   `uninitialized` and runs first-boot units (installed disks, base rootfs).
+//! - [`IdentityPolicy::random`]: a fresh machine-id per build.
+//!
+//! Random seeds are always removed so two machines never start from the
+//! same entropy pool state.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// machine-id used by live images and boot tests.
+pub const TEST_MACHINE_ID: &str = "0123456789abcdef0123456789abcdef";
+
+/// systemd's marker for "generate on first boot and run ConditionFirstBoot units".
+const FIRST_BOOT_MARKER: &str = "uninitialized";
+
+/// Seed files carried over from the build host or package scripts.
+const RANDOM_SEED_PATHS: &[&str] = &[
+    "var/lib/systemd/random-seed",
+    "var/lib/urandom/random-seed",
+    "var/lib/misc/random-seed",
+];
+
+const SYSTEMD_BINARIES: &[&str] = &["usr/lib/systemd/systemd", "lib/systemd/systemd"];
+
+/// How `/etc/machine-id` is populated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineIdPolicy {
+    Fixed(String),
+    /// `uninitialized` on systemd roots; removed elsewhere so dbus/OpenRC
+    /// generate one at boot.
+    FirstBoot,
+    Random,
+}
+
+/// Identity settings applied to an image root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityPolicy {
+    pub hostname: Option<String>,
+    pub machine_id: MachineIdPolicy,
+}
+
+impl IdentityPolicy {
+    pub fn fixed_for_tests() -> Self {
+        Self {
+            hostname: None,
+            machine_id: MachineIdPolicy::Fixed(TEST_MACHINE_ID.to_string()),
+        }
+    }
+
+    pub fn first_boot() -> Self {
+        Self {
+            hostname: None,
+            machine_id: MachineIdPolicy::FirstBoot,
+        }
+    }
+
+    pub fn random() -> Self {
+        Self {
+            hostname: None,
+            machine_id: MachineIdPolicy::Random,
+        }
+    }
+
+    /// Also write `/etc/hostname`. Without this the hostname is left alone.
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Write the identity into `root` and drop any inherited random seed.
+    pub fn apply(&self, root: &Path) -> Result<()> {
+        let etc = root.join("etc");
+        fs::create_dir_all(&etc).with_context(|| format!("Failed to create {}", etc.display()))?;
+
+        if let Some(hostname) = &self.hostname {
+            validate_hostname(hostname)?;
+            write(&etc.join("hostname"), &format!("{}\n", hostname))?;
+        }
+
+        let machine_id_path = etc.join("machine-id");
+        match &self.machine_id {
+            MachineIdPolicy::Fixed(id) => {
+                validate_machine_id(id)?;
+                write(&machine_id_path, &format!("{}\n", id))?;
+            }
+            MachineIdPolicy::Random => write(&machine_id_path, &format!("{}\n", random_id()?))?,
+            MachineIdPolicy::FirstBoot if is_systemd_root(root) => {
+                write(&machine_id_path, &format!("{}\n", FIRST_BOOT_MARKER))?
+            }
+            MachineIdPolicy::FirstBoot => remove_if_present(&machine_id_path)?,
+        }
+
+        for rel in RANDOM_SEED_PATHS {
+            remove_if_present(&root.join(rel))?;
+        }
+        Ok(())
+    }
+}
+
+/// RFC 1123 check of a single name or an FQDN, which is what `hostnamectl`
+/// enforces too: dot-separated labels of at most 63 characters, and no more
+/// than the kernel's 64 in total.
+pub fn validate_hostname(hostname: &str) -> Result<()> {
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    let valid = hostname.len() <= 64 && hostname.split('.').all(valid_label);
+    if !valid {
+        bail!("invalid hostname '{}'", hostname);
+    }
+    Ok(())
+}
+
+fn validate_machine_id(id: &str) -> Result<()> {
+    if id.len() != 32 || !id.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')) {
+        bail!("machine-id '{}' must be 32 lowercase hex digits", id);
+    }
+    Ok(())
+}
+
+fn random_id() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("Failed to read /dev/urandom")?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub(crate) fn is_systemd_root(root: &Path) -> bool {
+    SYSTEMD_BINARIES
+        .iter()
+        .any(|rel| root.join(rel).symlink_metadata().is_ok())
+}
+
+fn write(path: &Path, content: &str) -> Result<()> {
+    // Replace symlinks rather than writing through them into the host.
+    remove_if_present(path)?;
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn remove_if_present(path: &Path) -> Result<()> {
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_first_boot_depends_on_init() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("var/lib/systemd")).unwrap();
+        fs::write(root.join("var/lib/systemd/random-seed"), "seed").unwrap();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/machine-id"), "deadbeef\n").unwrap();
+
+        IdentityPolicy::first_boot().apply(root).unwrap();
+        assert!(!root.join("etc/machine-id").exists());
+        assert!(!root.join("var/lib/systemd/random-seed").exists());
+
+        fs::create_dir_all(root.join("usr/lib/systemd")).unwrap();
+        fs::write(root.join("usr/lib/systemd/systemd"), "").unwrap();
+        IdentityPolicy::first_boot()
+            .with_hostname("acorn")
+            .apply(root)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("etc/machine-id")).unwrap(),
+            "uninitialized\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("etc/hostname")).unwrap(),
+            "acorn\n"
+        );
+    }
+
+    #[test]
+    fn test_fixed_and_random() {
+        let temp = TempDir::new().unwrap();
+        IdentityPolicy::fixed_for_tests()
+            .apply(temp.path())
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.path().join("etc/machine-id")).unwrap(),
+            format!("{}\n", TEST_MACHINE_ID)
+        );
+
+        IdentityPolicy::random().apply(temp.path()).unwrap();
+        let id = fs::read_to_string(temp.path().join("etc/machine-id")).unwrap();
+        assert!(validate_machine_id(id.trim()).is_ok());
+        assert_ne!(id.trim(), TEST_MACHINE_ID);
+
+        assert!(IdentityPolicy::first_boot()
+            .with_hostname("-bad")
+            .apply(temp.path())
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate_hostname_accepts_fqdn() {
+        assert!(validate_hostname("acorn").is_ok());
+        assert!(validate_hostname("build-01.lab.example.org").is_ok());
+        for bad in ["", "acorn.", ".acorn", "a..b", "lab.-acorn", "acorn_1"] {
+            assert!(validate_hostname(bad).is_err(), "{bad}");
+        }
+        assert!(validate_hostname(&format!("{}.example", "a".repeat(60))).is_err());
+    }
+}
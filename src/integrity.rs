@@ -0,0 +1,248 @@
+//! Hash-verified copies of staged trees.
+//!
+//! Overloaded NFS-backed workspaces occasionally corrupt a file during a
+//! copy without any error. With [`VERIFY_COPIES_ENV`] set to `1`:
+//!
+//! - tree and file producers hash what they copy at the source and check
+//!   the destination afterwards;
+//! - every EROFS image gets a manifest of its source tree,
+//!   `.<image>-files.json` (see [`files_manifest_path`]), which is checked
+//!   again when a later stage extracts the image.
+//!
+//! A mismatch fails the build and names the files. Images without a
+//! manifest (built with verification off, restored from the store) are
+//! extracted unchecked.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::parallel;
+
+/// Set to `1` to verify copies and extracted images by content hash.
+pub const VERIFY_COPIES_ENV: &str = "DISTRO_BUILDER_VERIFY_COPIES";
+
+/// At most this many mismatches are listed in an error.
+const MAX_REPORTED: usize = 20;
+
+/// Whether copies are verified in this process.
+pub fn enabled() -> bool {
+    std::env::var(VERIFY_COPIES_ENV).is_ok_and(|value| matches!(value.trim(), "1" | "true"))
+}
+
+/// Manifest file for `image`: `.<image>-files.json` in the same directory.
+pub fn files_manifest_path(image: &Path) -> PathBuf {
+    let name = image
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    image.with_file_name(format!(".{}-files.json", name))
+}
+
+/// Content fingerprints of a tree, by path relative to its root: the
+/// SHA-256 of each regular file, `-> target` for each symlink. Directories
+/// and special files are not recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeManifest {
+    pub entries: BTreeMap<String, String>,
+}
+
+impl TreeManifest {
+    /// Fingerprint every file under `root`, hashing on
+    /// [`parallel::io_threads`] threads.
+    pub fn of_tree(root: &Path) -> Result<Self> {
+        let mut paths = Vec::new();
+        for entry in WalkDir::new(root).follow_links(false).min_depth(1) {
+            let entry = entry.with_context(|| format!("Failed to walk {}", root.display()))?;
+            let file_type = entry.file_type();
+            if file_type.is_file() || file_type.is_symlink() {
+                paths.push(entry.into_path());
+            }
+        }
+        let fingerprints =
+            parallel::try_map(&paths, parallel::io_threads()?, |path| fingerprint(path))?;
+        let entries = paths
+            .iter()
+            .zip(fingerprints)
+            .map(|(path, fingerprint)| {
+                let rel = path.strip_prefix(root).unwrap_or(path);
+                (rel.to_string_lossy().into_owned(), fingerprint)
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Check that every recorded entry exists under `root` with the same
+    /// fingerprint. Files under `root` that are not recorded are allowed,
+    /// since copies land in trees other producers also write.
+    pub fn verify(&self, root: &Path) -> Result<()> {
+        let recorded: Vec<(&String, &String)> = self.entries.iter().collect();
+        let mismatches = parallel::try_map(&recorded, parallel::io_threads()?, |(rel, want)| {
+            let path = root.join(rel);
+            Ok(match fs::symlink_metadata(&path) {
+                Err(_) => Some(format!("  {} (missing)", rel)),
+                Ok(_) if fingerprint(&path)? != **want => {
+                    Some(format!("  {} (content differs)", rel))
+                }
+                Ok(_) => None,
+            })
+        })?;
+        let mismatches: Vec<String> = mismatches.into_iter().flatten().collect();
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        let mut listed = mismatches[..mismatches.len().min(MAX_REPORTED)].join("\n");
+        if mismatches.len() > MAX_REPORTED {
+            listed.push_str(&format!(
+                "\n  ... and {} more",
+                mismatches.len() - MAX_REPORTED
+            ));
+        }
+        bail!(
+            "{} of {} file(s) under {} do not match their source:\n{}\n\
+             Remediation: check the workspace filesystem (network storage, disk errors) and rebuild.",
+            mismatches.len(),
+            self.entries.len(),
+            root.display(),
+            listed
+        )
+    }
+
+    /// Load a manifest written by [`write`](Self::write); `None` when
+    /// `path` does not exist.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&content)
+            .map(Some)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_vec(self)?;
+        fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Run `copy`, which copies the tree `source` into `destination`, hashing
+/// the source first and verifying the copy when verification is enabled.
+pub fn verified_tree_copy(
+    source: &Path,
+    destination: &Path,
+    copy: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    if !enabled() {
+        return copy();
+    }
+    let manifest = TreeManifest::of_tree(source)
+        .with_context(|| format!("hashing copy source {}", source.display()))?;
+    copy()?;
+    manifest
+        .verify(destination)
+        .with_context(|| format!("verifying copy of {}", source.display()))
+}
+
+/// Check a single copied file against its source when verification is
+/// enabled.
+pub fn verify_file_copy(source: &Path, destination: &Path) -> Result<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    if fingerprint(source)? != fingerprint(destination)? {
+        bail!(
+            "copy of {} to {} does not match its source\n\
+             Remediation: check the workspace filesystem (network storage, disk errors) and rebuild.",
+            source.display(),
+            destination.display()
+        );
+    }
+    Ok(())
+}
+
+/// Record the manifest of `source_dir` next to `image` when verification is
+/// enabled; otherwise drop a stale one.
+pub fn record_image_manifest(source_dir: &Path, image: &Path) -> Result<()> {
+    let path = files_manifest_path(image);
+    if !enabled() {
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        return Ok(());
+    }
+    TreeManifest::of_tree(source_dir)
+        .with_context(|| format!("hashing image source {}", source_dir.display()))?
+        .write(&path)
+}
+
+/// Verify a tree extracted from `image` against the image's manifest when
+/// verification is enabled and the manifest exists.
+pub fn verify_extracted_image(image: &Path, extracted: &Path) -> Result<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    let Some(manifest) = TreeManifest::load(&files_manifest_path(image))? else {
+        return Ok(());
+    };
+    manifest
+        .verify(extracted)
+        .with_context(|| format!("verifying extraction of {}", image.display()))
+}
+
+fn fingerprint(path: &Path) -> Result<String> {
+    let meta =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)
+            .with_context(|| format!("Failed to read link {}", path.display()))?;
+        return Ok(format!("-> {}", target.display()));
+    }
+    let mut file = File::open(path)
+        .with_context(|| format!("Failed to read file for hashing: {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read file for hashing: {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_verify() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(source.join("etc")).unwrap();
+        fs::write(source.join("etc/hostname"), "levitate\n").unwrap();
+        std::os::unix::fs::symlink("etc/hostname", source.join("link")).unwrap();
+
+        let manifest = TreeManifest::of_tree(&source).unwrap();
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(manifest.entries["link"], "-> etc/hostname");
+        manifest.verify(&source).unwrap();
+
+        let path = files_manifest_path(&temp.path().join("filesystem.erofs"));
+        assert!(path.ends_with(".filesystem.erofs-files.json"));
+        manifest.write(&path).unwrap();
+        assert_eq!(TreeManifest::load(&path).unwrap(), Some(manifest.clone()));
+
+        let copy = temp.path().join("copy");
+        fs::create_dir_all(copy.join("etc")).unwrap();
+        fs::write(copy.join("etc/hostname"), "levitatf\n").unwrap();
+        fs::write(copy.join("extra"), "").unwrap();
+        let err = format!("{:#}", manifest.verify(&copy).unwrap_err());
+        assert!(err.contains("2 of 2 file(s)"), "{err}");
+        assert!(err.contains("etc/hostname (content differs)"), "{err}");
+        assert!(err.contains("link (missing)"), "{err}");
+    }
+}
@@ -7,6 +7,8 @@
 //! - **Artifact builders** - EROFS rootfs, initramfs, and ISO creation wrappers
 //! - **Build utilities** - Filesystem operations and context management
 //! - **Preflight checks** - Host tool validation before builds
+//! - **Embedding** - [`Builder`] drives builds from another program
+//! - **Errors** - [`ErrorKind`] classifies failures for callers and CLI exit codes
 //!
 //! # Architecture
 //!
@@ -58,42 +60,119 @@
 pub mod alpine;
 pub mod artifact;
 pub mod artifact_store;
+pub mod assets;
+pub mod audit;
+pub mod boot_log;
 pub mod build;
 pub mod build_host;
+pub mod build_summary;
+pub mod builder;
 pub mod cache;
+// Public only for the `distro-builder` binary; `Builder` is the library API.
+#[doc(hidden)]
+pub mod cli;
 pub mod component;
+pub mod compression;
 pub mod contracts;
+pub mod doctor;
+pub mod error;
 pub mod executor;
+pub mod fetch;
+pub mod guest_inventory;
+pub mod guest_protocol;
+pub mod guest_results;
+pub mod hook_env;
+pub mod identity;
+pub mod integrity;
+pub mod notify;
+pub mod parallel;
 pub(crate) mod pipeline;
+pub mod policy;
 pub mod preflight;
 pub mod process;
+pub mod progress;
+pub mod pstore;
 pub mod qemu;
 pub mod recipe;
+pub mod remote;
+pub mod repo_config;
+pub mod repo_layout;
+pub mod reproducible;
+pub mod resource_limits;
 pub mod run_history;
+pub mod scaffold;
+pub mod secureboot;
+pub mod smoke_test;
+pub mod supervise;
+pub mod test_bundle;
 pub mod timing;
+pub mod tool_container;
+pub mod uefi_firmware;
+pub mod workspace;
 
+pub use assets::{AssetResolver, AssetSource};
+pub use build::accessibility::Accessibility;
+pub use build::banner::BuildMetadata;
+pub use build::branding::Branding;
+pub use build::chroot::Chroot;
+pub use build::cmdline::CmdlineBuilder;
+pub use build::context::{BuildSettings, TomlBuildContext};
 pub use build::licenses::LicenseTracker;
-pub use contracts::component::{Installable, Op, Phase};
-pub use contracts::context::{BuildContext, DistroConfig, InitSystem, PackageManager};
-pub use contracts::kernel::KernelInstallConfig;
-pub use executor::{binaries, directories, files, openrc, users};
+pub use build::splash::SplashConfig;
+pub use build::timesync::TimeSync;
+pub use builder::{BuildEvent, Builder, Stage};
+pub use compression::{Codec, Compression};
+pub use contracts::component::{
+    canonical_ops, ops_manifest, resolve_features, sort_components, Installable, Op, Phase,
+};
+pub use contracts::context::{
+    BuildContext, DistroConfig, Features, InitSystem, PackageManager, UidRanges,
+};
+pub use contracts::kernel::{FirmwareCompression, KernelInstallConfig, ModuleCompression};
+pub use error::{ErrorKind, ResultExt};
+pub use executor::{binaries, directories, files, openrc, privileges, users};
 
 // Re-export commonly used artifact utilities
+pub use artifact::boot_check::{verify_esp_image, verify_iso, BootExpectation, InitrdDigest};
+pub use artifact::boot_menu::{BootMenuConfig, TroubleshootMode};
 pub use artifact::cpio::build_cpio;
 pub use artifact::disk::{
-    build_disk_image, build_disk_image_with_uuids, generate_disk_uuids, DiskImageConfig, DiskUuids,
+    build_disk_image, build_disk_image_with_uuids, generate_disk_uuids, Bootloader,
+    DiskImageConfig, DiskUuids, FstabBuilder, FstabEntry,
 };
+pub use artifact::esp::{EspFile, EspLayout};
 pub use artifact::filesystem::{atomic_move, copy_dir_recursive, create_initramfs_dirs};
+pub use artifact::image_contents::{list_contents, EntryKind, ImageEntry};
+pub use artifact::image_mount::{MountBackend, MountedImage, MountedIso};
+pub use artifact::initramfs_check::{check_initramfs, InitramfsContract, InitramfsListing};
+pub use artifact::installer::{
+    build_installer_squashfs, build_installer_stage, stage_installer_payload,
+    wire_installer_launcher, InstallerLauncherConfig,
+};
 pub use artifact::iso_utils::{
-    create_efi_boot_image, create_efi_dirs_in_fat, create_fat16_image, generate_iso_checksum,
-    mcopy_to_fat, run_xorriso, setup_iso_structure, AppendedPartition,
+    create_efi_boot_image, create_efi_dirs_in_fat, create_esp_image, create_fat16_image,
+    generate_iso_checksum, mcopy_to_fat, run_xorriso, setup_iso_structure, AppendedPartition,
 };
 pub use artifact::live_overlay::{
     create_openrc_live_overlay, create_systemd_live_overlay, InittabVariant, LiveOverlayConfig,
-    SystemdLiveOverlayConfig,
+    LivePersistence, SystemdLiveOverlayConfig, DEFAULT_PERSISTENCE_IMAGE,
+    DEFAULT_PERSISTENCE_IMAGE_MB, DEFAULT_PERSISTENCE_LABEL, DEFAULT_PERSISTENT_PATHS,
 };
-pub use artifact::overlayfs::{build_overlayfs_default, create_overlayfs_erofs};
-pub use artifact::rootfs::{build_erofs_default, create_erofs};
+pub use artifact::loader::{LoaderConf, LoaderEntry, LoaderTimeout};
+pub use artifact::multi_iso::{assemble_multi_iso, plan_multi_iso, MultiIsoPayload, MultiIsoPlan};
+pub use artifact::netboot::NetbootFeatures;
+pub use artifact::overlay_conflicts::{ConflictPolicy, ConflictSeverity, OverlayConflict};
+pub use artifact::overlayfs::{
+    build_overlayfs_default, build_overlayfs_default_with_deletions, create_overlayfs_erofs,
+    create_overlayfs_erofs_with_deletions, OverlayDeletions,
+};
+pub use artifact::payload_role::{verify_live_payloads, PayloadRole, PAYLOAD_ROLE_MARKER};
+pub use artifact::rootfs::{
+    build_erofs_default, build_erofs_default_with_store, create_erofs, create_erofs_with_store,
+    EROFS_TREE_HASH_ENV,
+};
+pub use artifact::uki::{UkiBuilder, UkiFlavor, UkiSet};
+pub use artifact::usb_image::{build_usb_image, UsbImageOptions, DEFAULT_PERSISTENCE_MB};
 pub use pipeline::io::resolve_release_product_rootfs_image_for_distro;
 pub use pipeline::planner::{
     is_release_buildable_product, plan_product_build_chain, plan_product_realization,
@@ -107,6 +186,19 @@ pub use pipeline::products::{
     prepare_installed_boot_product, prepare_live_boot_product, prepare_live_tools_product,
     BaseProductLayout, DerivedProductLayout, LiveBootProductSpec, OverlayLayout, ParentRootfsInput,
 };
+pub use pipeline::release_share::{
+    publish_release_artifacts, recorded_release_input_key, release_channels, release_input_key,
+    release_tag, resolve_release, restore_release_artifacts, restore_release_for_current_inputs,
+    ReleaseArtifactFiles, ReleaseArtifactRole, LATEST_RELEASE_CHANNEL, RELEASE_CHANNEL_ENV,
+    RELEASE_INPUT_KEY_FILENAME, RELEASE_STORE_RETENTION,
+};
 
 // Re-export process utilities
+pub use fetch::{FetchRequest, Fetcher};
+pub use identity::{IdentityPolicy, MachineIdPolicy};
 pub use process::{ensure_exists, find_first_existing, Cmd, CommandResult};
+pub use progress::{Progress, ProgressCallback, ProgressSource, ProgressUpdate};
+pub use repo_layout::{LayoutMode, RepoLayout};
+pub use scaffold::VariantScaffold;
+pub use secureboot::{KeyKind, SecureBootKeys};
+pub use workspace::{CleanupPolicy, ScratchDir, WorkspaceManager};
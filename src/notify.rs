@@ -0,0 +1,382 @@
+//! Build notifications for long runs started from chat-ops or CI.
+//!
+//! A release build can take an hour; whoever kicked it off should not have
+//! to poll for `build-summary.json`. With a notifier configured, the CLI
+//! sends a [`Notification`] when each summary stage ends and when the
+//! invocation ends, by running a command with the JSON on stdin, POSTing it
+//! to a webhook with `curl`, or both:
+//!
+//! ```toml
+//! [notify]
+//! # Run through `sh -c`; $DISTRO_BUILDER_NOTIFY_EVENT is `stage` or `finished`.
+//! command = "jq -c . >> ~/builds.log"
+//! webhook = "https://chat.example.com/hooks/builds"
+//! # Only notify when the invocation ends.
+//! # stages = false
+//! timeout_secs = 10
+//! ```
+//!
+//! [`NOTIFY_COMMAND_ENV`] and [`NOTIFY_WEBHOOK_ENV`] override the file; an
+//! empty value disables that channel. A notification that cannot be
+//! delivered is reported as a warning and never fails the build.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+use crate::build_summary::{self, BuildSummary, StageSummary, SummaryStatus};
+use crate::error::{ErrorKind, ResultExt};
+use crate::repo_config::RepoConfig;
+use crate::resource_limits::find_in_path;
+
+/// Overrides `notify.command`; empty disables it.
+pub const NOTIFY_COMMAND_ENV: &str = "DISTRO_BUILDER_NOTIFY_COMMAND";
+
+/// Overrides `notify.webhook`; empty disables it.
+pub const NOTIFY_WEBHOOK_ENV: &str = "DISTRO_BUILDER_NOTIFY_WEBHOOK";
+
+/// Set for the notify command to the [`NotifyEvent`] being sent.
+pub const NOTIFY_EVENT_ENV: &str = "DISTRO_BUILDER_NOTIFY_EVENT";
+
+/// Current notification schema version.
+pub const NOTIFICATION_SCHEMA_VERSION: u32 = 1;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+static INSTALLED: OnceLock<Installed> = OnceLock::new();
+
+/// `[notify]` in `distro-builder.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub command: Option<String>,
+    pub webhook: Option<String>,
+    /// Notify when each stage ends, not only when the invocation does.
+    pub stages: bool,
+    /// How long one delivery may take before it is abandoned.
+    pub timeout_secs: u64,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            command: None,
+            webhook: None,
+            stages: true,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// What a notification reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    /// A summary stage ended; the summary so far is attached.
+    Stage,
+    /// The invocation ended; the final summary is attached.
+    Finished,
+}
+
+impl NotifyEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stage => "stage",
+            Self::Finished => "finished",
+        }
+    }
+}
+
+/// The JSON document sent to the command and the webhook.
+#[derive(Debug, Serialize)]
+pub struct Notification<'a> {
+    pub schema_version: u32,
+    pub event: NotifyEvent,
+    /// The stage's status for [`NotifyEvent::Stage`], the invocation's
+    /// otherwise.
+    pub status: SummaryStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<&'a StageSummary>,
+    pub summary: &'a BuildSummary,
+}
+
+/// A validated notifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notifier {
+    pub command: Option<String>,
+    pub webhook: Option<String>,
+    pub stages: bool,
+    pub timeout: Duration,
+}
+
+struct Installed {
+    notifier: Notifier,
+    command: Vec<String>,
+    started_at: OffsetDateTime,
+}
+
+impl Notifier {
+    /// The notifier configured in `<repo_root>/distro-builder.toml` (when a
+    /// checkout is known) with the environment overrides applied; `None`
+    /// when neither channel is configured.
+    pub fn load(repo_root: Option<&Path>) -> Result<Option<Self>> {
+        let config = match repo_root {
+            Some(root) => RepoConfig::load(root)?.notify,
+            None => NotifyConfig::default(),
+        };
+        Self::from_config(
+            config,
+            std::env::var(NOTIFY_COMMAND_ENV).ok().as_deref(),
+            std::env::var(NOTIFY_WEBHOOK_ENV).ok().as_deref(),
+            find_in_path("curl").is_some(),
+        )
+    }
+
+    fn from_config(
+        config: NotifyConfig,
+        command_env: Option<&str>,
+        webhook_env: Option<&str>,
+        curl_installed: bool,
+    ) -> Result<Option<Self>> {
+        let pick = |env: Option<&str>, configured: Option<String>| {
+            env.map(str::to_string)
+                .or(configured)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let command = pick(command_env, config.command);
+        let webhook = pick(webhook_env, config.webhook);
+        if command.is_none() && webhook.is_none() {
+            return Ok(None);
+        }
+        if let Some(url) = &webhook {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(anyhow!("notify webhook '{}' is not an http(s) URL", url))
+                    .error_kind(ErrorKind::InvalidConfig);
+            }
+            if !curl_installed {
+                return Err(anyhow!(
+                    "a notify webhook is configured but curl is not installed\n\
+Remediation: install curl, or unset notify.webhook"
+                ))
+                .error_kind(ErrorKind::MissingTool);
+            }
+        }
+        if config.timeout_secs == 0 {
+            return Err(anyhow!("notify.timeout_secs must be at least 1"))
+                .error_kind(ErrorKind::InvalidConfig);
+        }
+        Ok(Some(Self {
+            command,
+            webhook,
+            stages: config.stages,
+            timeout: Duration::from_secs(config.timeout_secs),
+        }))
+    }
+
+    /// Deliver `notification` on every configured channel, reporting the
+    /// first failure after trying all of them.
+    pub fn send(&self, notification: &Notification<'_>) -> Result<()> {
+        let payload = serde_json::to_vec(notification).context("serializing notification")?;
+        let mut first_error = None;
+        if let Some(command) = &self.command {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", command])
+                .env(NOTIFY_EVENT_ENV, notification.event.as_str());
+            if let Err(err) = deliver(cmd, &payload, self.timeout) {
+                first_error.get_or_insert(err.context("notify command"));
+            }
+        }
+        if let Some(url) = &self.webhook {
+            let mut cmd = Command::new("curl");
+            cmd.args(["-fsS", "-o", "/dev/null", "-X", "POST"])
+                .args(["-H", "Content-Type: application/json"])
+                .args(["--max-time", &self.timeout.as_secs().to_string()])
+                .args(["--data-binary", "@-", url]);
+            if let Err(err) = deliver(cmd, &payload, self.timeout) {
+                first_error.get_or_insert(err.context(format!("notify webhook {}", url)));
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run `cmd` with `payload` on stdin, killing it after `timeout`.
+fn deliver(mut cmd: Command, payload: &[u8], timeout: Duration) -> Result<()> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that ignores its input closes the pipe early; its exit
+        // status is what counts.
+        let _ = stdin.write_all(payload);
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .with_context(|| format!("Failed to wait for {}", program))?
+        {
+            if !status.success() {
+                bail!("{} exited with {}", program, status);
+            }
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{} did not finish within {}s", program, timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Install the process-wide notifier for the invocation of `command` that
+/// started at `started_at`. Idempotent; the first installed notifier stays
+/// in effect.
+pub fn install(notifier: Option<Notifier>, command: &[String], started_at: OffsetDateTime) {
+    if let Some(notifier) = notifier {
+        let _ = INSTALLED.set(Installed {
+            notifier,
+            command: command.to_vec(),
+            started_at,
+        });
+    }
+}
+
+/// Notify that `stage` ended, with the summary recorded so far.
+pub fn stage_finished(stage: &StageSummary) {
+    let Some(installed) = INSTALLED.get().filter(|i| i.notifier.stages) else {
+        return;
+    };
+    let summary = BuildSummary::in_progress(
+        &installed.command,
+        installed.started_at,
+        build_summary::snapshot_records(),
+    );
+    send_or_warn(
+        &installed.notifier,
+        &Notification {
+            schema_version: NOTIFICATION_SCHEMA_VERSION,
+            event: NotifyEvent::Stage,
+            status: stage.status,
+            stage: Some(stage),
+            summary: &summary,
+        },
+    );
+}
+
+/// Notify that the invocation ended with `summary`.
+pub fn build_finished(summary: &BuildSummary) {
+    let Some(installed) = INSTALLED.get() else {
+        return;
+    };
+    send_or_warn(
+        &installed.notifier,
+        &Notification {
+            schema_version: NOTIFICATION_SCHEMA_VERSION,
+            event: NotifyEvent::Finished,
+            status: summary.status,
+            stage: None,
+            summary,
+        },
+    );
+}
+
+fn send_or_warn(notifier: &Notifier, notification: &Notification<'_>) {
+    if let Err(err) = notifier.send(notification) {
+        eprintln!(
+            "  [WARN] {} notification not delivered: {:#}",
+            notification.event.as_str(),
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_summary::Records;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn config(command: &str) -> NotifyConfig {
+        NotifyConfig {
+            command: Some(command.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let none = Notifier::from_config(NotifyConfig::default(), None, None, true);
+        assert_eq!(none.unwrap(), None);
+        let disabled = Notifier::from_config(config("true"), Some(""), None, true);
+        assert_eq!(disabled.unwrap(), None);
+
+        let notifier = Notifier::from_config(config("true"), None, Some("https://x/h"), true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(notifier.command.as_deref(), Some("true"));
+        assert_eq!(notifier.webhook.as_deref(), Some("https://x/h"));
+        assert!(Notifier::from_config(config("true"), None, Some("https://x/h"), false).is_err());
+        assert!(Notifier::from_config(config("true"), None, Some("x/h"), true).is_err());
+    }
+
+    #[test]
+    fn test_command_receives_notification() {
+        let temp = TempDir::new().unwrap();
+        let out = temp.path().join("payload.json");
+        let notifier = Notifier::from_config(
+            config(&format!(
+                "cat > {} && test \"${}\" = stage",
+                out.display(),
+                NOTIFY_EVENT_ENV
+            )),
+            None,
+            None,
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        let stage = StageSummary {
+            name: "kernel".to_string(),
+            status: SummaryStatus::Failed,
+            duration_ms: 5,
+        };
+        let summary = BuildSummary::in_progress(
+            &["release".to_string()],
+            OffsetDateTime::now_utc(),
+            Records::default(),
+        );
+        let notification = Notification {
+            schema_version: NOTIFICATION_SCHEMA_VERSION,
+            event: NotifyEvent::Stage,
+            status: stage.status,
+            stage: Some(&stage),
+            summary: &summary,
+        };
+        notifier.send(&notification).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&fs::read(&out).unwrap()).unwrap();
+        assert_eq!(value["event"], "stage");
+        assert_eq!(value["status"], "failed");
+        assert_eq!(value["stage"]["name"], "kernel");
+        assert_eq!(value["summary"]["status"], "running");
+
+        let failing = Notifier::from_config(config("exit 3"), None, None, false)
+            .unwrap()
+            .unwrap();
+        assert!(failing.send(&notification).is_err());
+    }
+}
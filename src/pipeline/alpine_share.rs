@@ -0,0 +1,239 @@
+//! Cross-distro sharing of the Alpine live source rootfs through the
+//! artifact store.
+//!
+//! The Alpine source recipe extracts the Alpine ISO and installs a package
+//! baseline with `apk.static`, once per distro. The result depends only on
+//! the Alpine release, the architecture and the package set, so AcornOS and
+//! IuppiterOS can share it: the first distro to build publishes its rootfs,
+//! the others restore it into their provider work directory before the
+//! recipe runs, and the recipe's own validity check then skips the rebuild.
+//!
+//! Every profile installs the same package baseline, so the key covers only
+//! the shared inputs: the recipe text stands in for the package list, and
+//! the distro that runs it does not matter. The payload keeps modes and
+//! ownership so a restored rootfs matches the one the recipe built.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::artifact_store::ArtifactStore;
+
+/// Artifact store kind for shared Alpine source rootfs trees.
+pub const SHARED_ALPINE_ROOTFS_KIND: &str = "alpine_rootfs_shared";
+
+/// Marker the recipe writes into a complete rootfs.
+const RECIPE_MARKER: &str = ".source-rootfs.recipe-version";
+
+/// Identity of an Alpine source rootfs that can be shared between distros.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlpineRootfsKey {
+    pub alpine_version: String,
+    pub arch: String,
+    /// Digest of the recipe text, which lists the package baseline.
+    pub packages_sha256: String,
+}
+
+impl AlpineRootfsKey {
+    /// Key for running `recipe_script`, whichever distro runs it.
+    pub fn for_recipe(recipe_script: &Path) -> Result<Self> {
+        let script = fs::read_to_string(recipe_script).with_context(|| {
+            format!("reading Alpine source recipe '{}'", recipe_script.display())
+        })?;
+        let field = |name: &str| {
+            recipe_ctx_string(&script, name).with_context(|| {
+                format!(
+                    "Alpine source recipe '{}' does not declare ctx.{}",
+                    recipe_script.display(),
+                    name
+                )
+            })
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(script.as_bytes());
+        Ok(Self {
+            alpine_version: field("alpine_version")?,
+            arch: field("arch")?,
+            packages_sha256: format!("{:x}", hasher.finalize()),
+        })
+    }
+
+    /// Store input key, e.g. `3.23.2-x86_64-<16 hex>`.
+    pub fn input_key(&self) -> String {
+        format!(
+            "{}-{}-{}",
+            self.alpine_version,
+            self.arch,
+            &self.packages_sha256[..16]
+        )
+    }
+}
+
+/// Value of a top-level `name: "value",` entry in the recipe's ctx map.
+fn recipe_ctx_string(script: &str, name: &str) -> Option<String> {
+    script.lines().find_map(|line| {
+        let value = line
+            .trim()
+            .strip_prefix(name)?
+            .trim_start()
+            .strip_prefix(':')?;
+        let value = value.trim().trim_end_matches(',').strip_prefix('"')?;
+        value.strip_suffix('"').map(str::to_string)
+    })
+}
+
+/// Restore a shared rootfs into `rootfs_dir` unless it already holds a
+/// complete one.
+///
+/// Returns the distro that published the payload, or `None` when nothing
+/// was restored.
+pub fn restore_alpine_rootfs(
+    store: &ArtifactStore,
+    rootfs_dir: &Path,
+    key: &AlpineRootfsKey,
+) -> Result<Option<String>> {
+    if rootfs_dir.join(RECIPE_MARKER).is_file() {
+        return Ok(None);
+    }
+    let input_key = key.input_key();
+    let Some(stored) = store.get(SHARED_ALPINE_ROOTFS_KIND, &input_key)? else {
+        return Ok(None);
+    };
+    store.materialize_to(SHARED_ALPINE_ROOTFS_KIND, &input_key, rootfs_dir)?;
+    if !rootfs_dir.join(RECIPE_MARKER).is_file() {
+        bail!(
+            "shared Alpine rootfs '{}' restored into '{}' has no recipe marker",
+            input_key,
+            rootfs_dir.display()
+        );
+    }
+    Ok(Some(
+        stored
+            .entry
+            .meta
+            .get("distro_id")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown")
+            .to_string(),
+    ))
+}
+
+/// Publish a complete rootfs unless the store already has this key.
+///
+/// Returns `true` when a new payload was stored.
+pub fn publish_alpine_rootfs(
+    store: &ArtifactStore,
+    distro_id: &str,
+    rootfs_dir: &Path,
+    key: &AlpineRootfsKey,
+) -> Result<bool> {
+    let input_key = key.input_key();
+    if store.get(SHARED_ALPINE_ROOTFS_KIND, &input_key)?.is_some() {
+        return Ok(false);
+    }
+    if !rootfs_dir.join(RECIPE_MARKER).is_file() {
+        bail!(
+            "cannot share Alpine rootfs for '{}': '{}' has no recipe marker",
+            distro_id,
+            rootfs_dir.display()
+        );
+    }
+    let mut meta = BTreeMap::new();
+    meta.insert("distro_id".to_string(), serde_json::json!(distro_id));
+    meta.insert("share_key".to_string(), serde_json::to_value(key)?);
+    store.put_tree_as_tar(SHARED_ALPINE_ROOTFS_KIND, &input_key, rootfs_dir, meta)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tempfile::TempDir;
+
+    const RECIPE: &str =
+        "let ctx = #{\n    alpine_version: \"3.23.2\",\n    arch: \"x86_64\",\n};\n";
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_rootfs_shared_between_distros_with_same_packages() {
+        let temp = TempDir::new().unwrap();
+        let recipe = temp.path().join("recipes/alpine-live-source-rootfs.rhai");
+        write(&recipe, RECIPE);
+        let provider = |distro: &str| {
+            temp.path()
+                .join(distro)
+                .join("rootfs-source-provider/alpine-live-source-rootfs")
+        };
+
+        // Each distro derives the key on its own; they must agree.
+        let acorn = AlpineRootfsKey::for_recipe(&recipe).unwrap();
+        let iuppiter = AlpineRootfsKey::for_recipe(&recipe).unwrap();
+        assert_eq!(acorn.alpine_version, "3.23.2");
+        assert!(acorn.input_key().starts_with("3.23.2-x86_64-"));
+        assert_eq!(acorn, iuppiter);
+
+        let store = ArtifactStore::open(temp.path()).unwrap();
+        let published = provider("acorn").join("rootfs");
+        write(&published.join("bin/busybox"), "busybox");
+        assert!(publish_alpine_rootfs(&store, "acorn", &published, &acorn).is_err());
+        write(&published.join(RECIPE_MARKER), "alpine-live-source-v6\n");
+        write(&published.join("etc/shadow"), "root:*::0:::::\n");
+        fs::set_permissions(
+            published.join("bin/busybox"),
+            fs::Permissions::from_mode(0o4755),
+        )
+        .unwrap();
+        fs::set_permissions(
+            published.join("etc/shadow"),
+            fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+        let as_root = unsafe { libc::geteuid() } == 0;
+        if as_root {
+            std::os::unix::fs::chown(published.join("etc/shadow"), Some(0), Some(42)).unwrap();
+        }
+        assert!(publish_alpine_rootfs(&store, "acorn", &published, &acorn).unwrap());
+        assert!(!publish_alpine_rootfs(&store, "acorn", &published, &acorn).unwrap());
+
+        let restored = provider("iuppiter").join("rootfs");
+        assert_eq!(
+            restore_alpine_rootfs(&store, &restored, &iuppiter).unwrap(),
+            Some("acorn".to_string())
+        );
+        assert_eq!(
+            fs::read_to_string(restored.join("bin/busybox")).unwrap(),
+            "busybox"
+        );
+        let mode = |rel: &str| {
+            fs::symlink_metadata(restored.join(rel))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        assert_eq!(mode("bin/busybox"), 0o4755);
+        assert_eq!(mode("etc/shadow"), 0o640);
+        if as_root {
+            let md = fs::symlink_metadata(restored.join("etc/shadow")).unwrap();
+            assert_eq!((md.uid(), md.gid()), (0, 42));
+        }
+        assert_eq!(
+            restore_alpine_rootfs(&store, &restored, &iuppiter).unwrap(),
+            None
+        );
+
+        write(
+            &recipe,
+            "let ctx = #{\n    alpine_version: \"3.23.2\",\n};\n",
+        );
+        assert!(AlpineRootfsKey::for_recipe(&recipe).is_err());
+    }
+}
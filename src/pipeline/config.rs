@@ -6,6 +6,7 @@ use distro_contract::{
 };
 use std::path::Path;
 
+use crate::artifact::payload_role::{PayloadRole, PAYLOAD_ROLE_MARKER};
 use crate::pipeline::live_tools::{InstallDocsFrontend, InstallExperience, LiveToolsRuntimeAction};
 use crate::pipeline::overlay::BootOverlayPolicy;
 use crate::pipeline::paths::resolve_repo_path;
@@ -18,6 +19,7 @@ use crate::InittabVariant;
 #[derive(Debug, Clone)]
 pub(crate) struct BootLoadedConfig {
     pub(crate) os_name: String,
+    pub(crate) os_version: String,
     pub(crate) required_services: Vec<String>,
     pub(crate) rootfs_source_policy: Option<RootfsSourcePolicy>,
     pub(crate) overlay: BootOverlayPolicy,
@@ -52,6 +54,7 @@ fn load_boot_payload_config_with_rootfs_source_policy(
     rootfs_source_policy: Option<RootfsSourcePolicy>,
 ) -> Result<BootLoadedConfig> {
     let os_name = contract.identity.os_name.clone();
+    let os_version = contract.identity.os_version.clone();
     let required_services = normalize_services(
         contract
             .scenarios
@@ -64,6 +67,7 @@ fn load_boot_payload_config_with_rootfs_source_policy(
 
     Ok(BootLoadedConfig {
         os_name,
+        os_version,
         required_services,
         rootfs_source_policy,
         overlay,
@@ -398,11 +402,23 @@ fn rootfs_producer_from_contract(producer: &PayloadProducerContract) -> Result<R
             path,
             content,
             mode,
-        } => RootfsProducer::WriteText {
-            path: normalized_relative_path(path, "path")?,
-            content: content.clone(),
-            mode: *mode,
-        },
+        } => {
+            let path = normalized_relative_path(path, "path")?;
+            if path == Path::new(PAYLOAD_ROLE_MARKER)
+                && !matches!(PayloadRole::parse(content), Ok(PayloadRole::Rootfs))
+            {
+                bail!(
+                    "Ring 2 payload producer writing '{}' must mark the rootfs, got '{}'",
+                    PAYLOAD_ROLE_MARKER,
+                    content.trim()
+                );
+            }
+            RootfsProducer::WriteText {
+                path,
+                content: content.clone(),
+                mode: *mode,
+            }
+        }
     })
 }
 fn install_experience_from_contract(contract: &ConformanceContract) -> InstallExperience {
@@ -648,7 +664,7 @@ mod tests {
         assert!(loaded
             .payload_producers
             .iter()
-            .any(|producer| matches!(producer, RootfsProducer::WriteText { path, .. } if path == Path::new(".live-payload-role"))));
+            .any(|producer| matches!(producer, RootfsProducer::WriteText { path, .. } if path == Path::new(PAYLOAD_ROLE_MARKER))));
     }
 
     fn assert_uses_openrc_ring_base_config(
@@ -665,7 +681,7 @@ mod tests {
             "expected canonical payload producers for {distro_id}"
         );
         assert!(loaded.payload_producers.iter().any(
-            |producer| matches!(producer, RootfsProducer::WriteText { path, .. } if path == Path::new(".live-payload-role"))
+            |producer| matches!(producer, RootfsProducer::WriteText { path, .. } if path == Path::new(PAYLOAD_ROLE_MARKER))
         ));
         match loaded.overlay {
             BootOverlayPolicy::OpenRc {
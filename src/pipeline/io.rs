@@ -3,6 +3,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use crate::artifact_store::DEFAULT_OUTPUT_SUBDIR;
+
 pub(crate) fn create_unique_output_dir(output_dir: &Path, logical_name: &Path) -> Result<PathBuf> {
     let stem = logical_name
         .file_name()
@@ -75,9 +77,8 @@ pub fn resolve_release_product_rootfs_image_for_distro(
     parent_product_label: &str,
     rootfs_filename: &str,
 ) -> Result<PathBuf> {
-    let product_root = repo_root
-        .join(".artifacts")
-        .join("out")
+    let product_root = crate::repo_config::artifacts_root(repo_root)?
+        .join(DEFAULT_OUTPUT_SUBDIR)
         .join(distro_id)
         .join("releases")
         .join(product_dir_name);
@@ -109,9 +110,8 @@ pub fn release_product_rootfs_exists_for_distro(
     product_dir_name: &str,
     rootfs_filename: &str,
 ) -> Result<bool> {
-    let product_root = repo_root
-        .join(".artifacts")
-        .join("out")
+    let product_root = crate::repo_config::artifacts_root(repo_root)?
+        .join(DEFAULT_OUTPUT_SUBDIR)
         .join(distro_id)
         .join("releases")
         .join(product_dir_name);
@@ -139,14 +139,20 @@ pub(crate) fn extract_erofs_rootfs(image: &Path, destination: &Path) -> Result<(
     })?;
 
     let extract_arg = format!("--extract={}", destination.display());
-    let output = Command::new("fsck.erofs")
-        .arg(extract_arg)
+    let mut command = Command::new("fsck.erofs");
+    command.arg(extract_arg);
+    // Keep SELinux labels of the parent image; only root may write
+    // security.* attributes, so unprivileged extraction leaves them out.
+    if unsafe { libc::geteuid() } == 0 {
+        command.arg("--xattrs");
+    }
+    let output = command
         .arg(image)
         .output()
         .with_context(|| format!("running fsck.erofs for '{}'", image.display()))?;
 
     if output.status.success() {
-        return Ok(());
+        return crate::integrity::verify_extracted_image(image, destination);
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
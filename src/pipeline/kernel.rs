@@ -1,14 +1,33 @@
 use anyhow::{bail, Context, Result};
 use distro_contract::VariantOwnerPaths;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
-use crate::pipeline::paths::normalize_distro_id;
+use crate::artifact_store::ArtifactStore;
+use crate::build::external_modules::ExternalModuleRegistry;
+use crate::build::kernel_headers::{store_kernel_headers, KernelHeaders};
+use crate::build::modules::check_module_policy;
+use crate::pipeline::kernel_share::{
+    publish_shared_kernel, restore_shared_kernel, KERNEL_RELEASE_FILE,
+};
+use crate::process::Cmd;
+use crate::workspace::{WorkspaceManager, DOWNLOADS_NAMESPACE};
+
+pub use crate::pipeline::kernel_share::KernelShareKey;
 
 #[derive(Debug, Clone)]
 pub struct KernelSpec {
     pub recipe_kernel_script: String,
     pub kernel_kconfig_path: String,
+    /// When set, the kernel root is shared with other distros through the
+    /// artifact store under this key.
+    pub share_key: Option<KernelShareKey>,
+    /// Out-of-tree modules rebuilt whenever the installed kernel changes.
+    pub external_modules: ExternalModuleRegistry,
+    /// Modules the installed kernel must provide (see
+    /// [`check_module_policy`]).
+    pub required_modules: Vec<String>,
+    /// Modules, or module paths, the installed kernel must not ship.
+    pub forbidden_modules: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -23,6 +42,8 @@ pub struct EvidenceSpec {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum KernelEnsureOutcome {
     AlreadyInstalled,
+    /// Restored from a kernel another distro published to the artifact store.
+    RestoredShared,
 }
 
 pub fn check_kernel_installed_with_recipe(
@@ -32,7 +53,50 @@ pub fn check_kernel_installed_with_recipe(
     kernel_output_dir: &Path,
     spec: &KernelSpec,
 ) -> Result<()> {
-    let recipe_script = repo_root.join(&spec.recipe_kernel_script);
+    run_kernel_recipe_phase(
+        "isinstalled",
+        repo_root,
+        variant_paths,
+        distro_id,
+        kernel_output_dir,
+        spec,
+    )
+    .context("build kernel isinstalled check failed")
+}
+
+/// Download and verify the kernel source tarball without building.
+///
+/// Runs only the recipe's `acquire` lifecycle step, so the tarball lands in
+/// the distro's downloads workspace where a later `cargo xtask kernels build`
+/// picks it up offline.
+pub fn acquire_kernel_source_with_recipe(
+    repo_root: &Path,
+    variant_paths: &VariantOwnerPaths,
+    distro_id: &str,
+    kernel_output_dir: &Path,
+    spec: &KernelSpec,
+) -> Result<()> {
+    run_kernel_recipe_phase(
+        "acquire",
+        repo_root,
+        variant_paths,
+        distro_id,
+        kernel_output_dir,
+        spec,
+    )
+    .context("build kernel source acquisition failed")
+}
+
+fn run_kernel_recipe_phase(
+    phase: &str,
+    repo_root: &Path,
+    variant_paths: &VariantOwnerPaths,
+    distro_id: &str,
+    kernel_output_dir: &Path,
+    spec: &KernelSpec,
+) -> Result<()> {
+    let recipe_script =
+        crate::repo_layout::RepoLayout::at(repo_root)?.resolve(&spec.recipe_kernel_script);
     let recipes_path = recipe_script
         .parent()
         .ok_or_else(|| {
@@ -52,15 +116,19 @@ pub fn check_kernel_installed_with_recipe(
         .context("Resolving recipe binary for build kernel")?;
     let kernel_artifact_root = kernel_output_dir.to_string_lossy().to_string();
     let defines = kernel_recipe_defines(&kernel_kconfig_path, &kernel_artifact_root);
-    crate::recipe::run_recipe_phase_json_with_defines(
+    let mirrors = crate::repo_config::kernel_mirrors(repo_root)?.join("\n");
+    let fetch_env = crate::fetch::recipe_fetch_env(repo_root, distro_id);
+    let mut envs = vec![(KERNEL_MIRRORS_ENV, mirrors.as_str())];
+    envs.extend(fetch_env.iter().map(|(key, value)| (*key, value.as_str())));
+    crate::recipe::run_recipe_phase_json_with_defines_and_env(
         &recipe_bin.path,
-        "isinstalled",
+        phase,
         &recipe_script,
         &build_dir,
         &defines,
+        &envs,
         Some(&recipes_path),
-    )
-    .context("build kernel isinstalled check failed")?;
+    )?;
 
     Ok(())
 }
@@ -72,25 +140,147 @@ pub fn ensure_kernel_preinstalled_with_recipe(
     kernel_output_dir: &Path,
     spec: &KernelSpec,
 ) -> Result<KernelEnsureOutcome> {
-    match check_kernel_installed_with_recipe(
-        repo_root,
-        variant_paths,
-        distro_id,
-        kernel_output_dir,
-        spec,
-    ) {
-        Ok(()) => Ok(KernelEnsureOutcome::AlreadyInstalled),
-        Err(e) => bail!(
-            "build-host kernel is not preinstalled for '{}': {}\n\
-             Kernel rebuilds are forbidden during release ISO builds.\n\
-             Remediation: run 'cargo xtask kernels build {}' (or '--rebuild' if provenance is stale), then retry the ISO build.",
+    let check = || {
+        check_kernel_installed_with_recipe(
+            repo_root,
+            variant_paths,
             distro_id,
-            e,
-            distro_id
-        ),
+            kernel_output_dir,
+            spec,
+        )
+    };
+    let outcome = match (check(), spec.share_key.as_ref()) {
+        (Ok(()), _) => KernelEnsureOutcome::AlreadyInstalled,
+        (Err(e), Some(key)) => {
+            let store = ArtifactStore::open(repo_root)?;
+            match restore_shared_kernel(&store, distro_id, kernel_output_dir, key)? {
+                Some(publisher) => {
+                    println!(
+                        "  Restored shared kernel {}{} built by '{}'",
+                        key.version, key.localversion, publisher
+                    );
+                    check().with_context(|| {
+                        format!(
+                            "shared kernel restored for '{}' failed the install check",
+                            distro_id
+                        )
+                    })?;
+                    KernelEnsureOutcome::RestoredShared
+                }
+                None => return Err(kernel_not_preinstalled(distro_id, e)),
+            }
+        }
+        (Err(e), None) => return Err(kernel_not_preinstalled(distro_id, e)),
+    };
+
+    if outcome == KernelEnsureOutcome::AlreadyInstalled && !spec.external_modules.is_empty() {
+        let release = installed_kernel_release(kernel_output_dir)?;
+        if !spec
+            .external_modules
+            .is_current(kernel_output_dir, &release)?
+        {
+            rebuild_external_modules(kernel_output_dir, &release, &spec.external_modules)
+                .with_context(|| {
+                    format!("rebuilding external kernel modules for '{}'", distro_id)
+                })?;
+        }
+    }
+
+    if !spec.required_modules.is_empty() || !spec.forbidden_modules.is_empty() {
+        let release = installed_kernel_release(kernel_output_dir)?;
+        check_module_policy(
+            &staged_modules_dir(kernel_output_dir, &release),
+            &spec.required_modules,
+            &spec.forbidden_modules,
+        )
+        .with_context(|| format!("checking kernel modules for '{}'", distro_id))?;
+    }
+
+    let workspace = WorkspaceManager::new(repo_root)?;
+    if let (KernelEnsureOutcome::AlreadyInstalled, Some(key)) = (outcome, spec.share_key.as_ref()) {
+        let published = ArtifactStore::open(repo_root).and_then(|store| {
+            publish_shared_kernel(&store, &workspace, distro_id, kernel_output_dir, key)
+        });
+        match published {
+            Ok(true) => println!(
+                "  Published kernel {}{} for sharing with other distros",
+                key.version, key.localversion
+            ),
+            Ok(false) => {}
+            Err(err) => eprintln!("  [WARN] failed to publish shared kernel: {:#}", err),
+        }
+    }
+
+    let variant_dir = crate::repo_layout::layout_or_default(repo_root).variant_dir(distro_id);
+    if KernelHeaders::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading kernel headers config for '{}'", distro_id))?
+        .is_some()
+    {
+        let store = ArtifactStore::open(repo_root)?;
+        if store_kernel_headers(&store, &workspace, distro_id, kernel_output_dir)
+            .with_context(|| format!("storing kernel headers for '{}'", distro_id))?
+        {
+            println!("  Stored kernel headers payload for '{}'", distro_id);
+        }
+    }
+
+    Ok(outcome)
+}
+
+fn installed_kernel_release(kernel_root: &Path) -> Result<String> {
+    let release_path = kernel_root.join(KERNEL_RELEASE_FILE);
+    let release = std::fs::read_to_string(&release_path)
+        .with_context(|| format!("reading kernel release '{}'", release_path.display()))?;
+    Ok(release.trim().to_string())
+}
+
+/// Module install path of the kernel staged in `kernel_root`.
+fn staged_module_install_path(kernel_root: &Path, release: &str) -> &'static str {
+    if kernel_root
+        .join("staging/usr/lib/modules")
+        .join(release)
+        .is_dir()
+    {
+        "/usr/lib/modules"
+    } else {
+        "/lib/modules"
     }
 }
 
+fn staged_modules_dir(kernel_root: &Path, release: &str) -> PathBuf {
+    kernel_root
+        .join("staging")
+        .join(staged_module_install_path(kernel_root, release).trim_start_matches('/'))
+        .join(release)
+}
+
+fn rebuild_external_modules(
+    kernel_root: &Path,
+    release: &str,
+    registry: &ExternalModuleRegistry,
+) -> Result<()> {
+    let staging = kernel_root.join("staging");
+    let module_install_path = staged_module_install_path(kernel_root, release);
+    registry.rebuild(
+        &kernel_root.join("kernel-build"),
+        &staging,
+        module_install_path,
+        release,
+        kernel_root,
+    )
+}
+
+fn kernel_not_preinstalled(distro_id: &str, e: anyhow::Error) -> anyhow::Error {
+    anyhow::anyhow!(
+        "build-host kernel is not preinstalled for '{}': {}\n\
+         Kernel rebuilds are forbidden during release ISO builds.\n\
+         Remediation: run 'cargo xtask kernels build {}' (or '--rebuild' if provenance is stale), then retry the ISO build.",
+        distro_id,
+        e,
+        distro_id
+    )
+}
+
 pub fn run_build_evidence_script(
     repo_root: &Path,
     variant_paths: &VariantOwnerPaths,
@@ -107,18 +297,17 @@ pub fn run_build_evidence_script(
     let kernel_image_path = kernel_output_dir.join(&spec.kernel_image_path);
     let iso_path = stage_output_dir.join(&spec.iso_filename);
 
-    let output = Command::new("sh")
-        .arg(&script)
-        .current_dir(repo_root)
+    let output = Cmd::new("sh")
+        .arg_path(&script)
+        .dir(repo_root)
         .env("KERNEL_RELEASE_PATH", &kernel_release_path)
         .env("KERNEL_IMAGE_PATH", &kernel_image_path)
         .env("ISO_PATH", &iso_path)
-        .output()
+        .allow_fail()
+        .run()
         .with_context(|| format!("executing build evidence script '{}'", script.display()))?;
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let combined = format!("{}{}", stdout, stderr);
+    let combined = format!("{}{}", output.stdout, output.stderr);
 
     if !output.status.success() {
         bail!(
@@ -139,20 +328,12 @@ pub fn run_build_evidence_script(
 }
 
 fn work_dir_for_distro(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
-    let normalized = normalize_distro_id(distro_id, "kernel recipe build directory")?;
-    let build_dir = repo_root
-        .join(".artifacts/work")
-        .join(normalized)
-        .join("downloads");
-    std::fs::create_dir_all(&build_dir).with_context(|| {
-        format!(
-            "creating kernel recipe work directory '{}'",
-            build_dir.display()
-        )
-    })?;
-    Ok(build_dir)
+    WorkspaceManager::new(repo_root)?.persistent_dir(distro_id, DOWNLOADS_NAMESPACE)
 }
 
+/// Newline-separated mirror base URLs the kernel recipe downloads from.
+pub(crate) const KERNEL_MIRRORS_ENV: &str = "KERNEL_MIRRORS";
+
 fn kernel_recipe_defines<'a>(
     kernel_kconfig_path: &'a str,
     kernel_artifact_root: &'a str,
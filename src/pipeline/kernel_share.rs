@@ -0,0 +1,309 @@
+//! Cross-distro kernel payload sharing through the artifact store.
+//!
+//! Distros that pin the same kernel version, kconfig content, and
+//! localversion produce bit-identical kernels, so the first distro to finish
+//! a build publishes its kernel artifact root and the others restore it
+//! instead of rebuilding.
+//!
+//! The localversion is part of the key because `CONFIG_LOCALVERSION` is
+//! compiled into the image (`uname -r`) and names the modules directory; it
+//! cannot be rewritten afterwards. What is distro-specific at install time is
+//! the provenance marker written into the restoring distro's kernel root and
+//! the check that the restored `kernel.release` carries that distro's
+//! localversion.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::artifact::filesystem::copy_dir_recursive;
+use crate::artifact_store::ArtifactStore;
+use crate::build::external_modules::{ExternalModuleRegistry, EXTERNAL_MODULES_STAMP};
+use crate::workspace::WorkspaceManager;
+
+/// Artifact store kind for shared kernel roots.
+pub const SHARED_KERNEL_KIND: &str = "kernel_shared";
+
+/// Provenance marker written into a kernel root restored from the store.
+pub const SHARE_MARKER_FILENAME: &str = ".kernel-share.json";
+
+/// Build outputs the recipe's `is_built`/`is_installed` checks look at,
+/// relative to `kernel-build/`, besides the arch's boot image. The full
+/// build tree is not shared.
+const SHARED_BUILD_FILES: &[&str] = &["include/config/kernel.release", ".config", "System.map"];
+
+pub(crate) const KERNEL_RELEASE_FILE: &str = "kernel-build/include/config/kernel.release";
+
+/// Identity of a kernel build that can be shared between distros.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernelShareKey {
+    pub version: String,
+    /// Kernel `ARCH` the kconfig targets (`x86`, `arm64`).
+    pub arch: String,
+    pub kconfig_sha256: String,
+    pub localversion: String,
+    /// Digest of registered out-of-tree modules built into the payload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_modules: Option<String>,
+}
+
+impl KernelShareKey {
+    pub fn new(version: &str, kconfig_path: &Path, localversion: &str) -> Result<Self> {
+        let kconfig = fs::read(kconfig_path)
+            .with_context(|| format!("reading kernel kconfig '{}'", kconfig_path.display()))?;
+        Ok(Self {
+            version: version.to_string(),
+            arch: kconfig_arch(&String::from_utf8_lossy(&kconfig)).to_string(),
+            kconfig_sha256: format!("{:x}", Sha256::digest(&kconfig)),
+            localversion: localversion.to_string(),
+            external_modules: None,
+        })
+    }
+
+    /// Include an out-of-tree module registry in the key.
+    pub fn with_external_modules(mut self, registry: &ExternalModuleRegistry) -> Result<Self> {
+        self.external_modules = if registry.is_empty() {
+            None
+        } else {
+            Some(registry.digest()?)
+        };
+        Ok(self)
+    }
+
+    /// Boot image the build leaves for this arch, relative to `kernel-build/`.
+    pub fn boot_image(&self) -> &'static str {
+        match self.arch.as_str() {
+            "arm64" => "arch/arm64/boot/Image",
+            _ => "arch/x86/boot/bzImage",
+        }
+    }
+
+    /// Store input key derived from all components.
+    pub fn input_key(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"kernel-share-v1\0");
+        hasher.update(self.version.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(self.arch.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(self.kconfig_sha256.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(self.localversion.as_bytes());
+        if let Some(digest) = &self.external_modules {
+            hasher.update(b"\0");
+            hasher.update(digest.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Kernel `ARCH` a kconfig targets: the arch symbol it enables, else the
+/// build host's, since kernels are built natively.
+fn kconfig_arch(kconfig: &str) -> &'static str {
+    let enabled = |symbol: &str| {
+        kconfig
+            .lines()
+            .any(|line| line.trim() == format!("{}=y", symbol))
+    };
+    if enabled("CONFIG_ARM64") {
+        "arm64"
+    } else if enabled("CONFIG_X86_64") || enabled("CONFIG_X86") {
+        "x86"
+    } else if std::env::consts::ARCH == "aarch64" {
+        "arm64"
+    } else {
+        "x86"
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShareMarker {
+    distro_id: String,
+    key: KernelShareKey,
+    published_by: Option<String>,
+}
+
+/// Publish an installed kernel root unless the store already has this key.
+/// The payload is staged in a `kernel-share` scratch directory of `workspace`.
+///
+/// Returns `true` when a new payload was stored.
+pub fn publish_shared_kernel(
+    store: &ArtifactStore,
+    workspace: &WorkspaceManager,
+    distro_id: &str,
+    kernel_root: &Path,
+    key: &KernelShareKey,
+) -> Result<bool> {
+    let input_key = key.input_key();
+    if store.get(SHARED_KERNEL_KIND, &input_key)?.is_some() {
+        return Ok(false);
+    }
+
+    let staging = kernel_root.join("staging");
+    if !staging.join("boot/vmlinuz").is_file() {
+        bail!(
+            "cannot share kernel for '{}': '{}' has no installed vmlinuz",
+            distro_id,
+            staging.display()
+        );
+    }
+
+    let mut scratch = workspace.scratch(distro_id, "kernel-share")?;
+    let payload_dir = scratch.path().join("payload");
+    stage_share_payload(kernel_root, &payload_dir, key)?;
+    let mut meta = BTreeMap::new();
+    meta.insert("distro_id".to_string(), serde_json::json!(distro_id));
+    meta.insert("share_key".to_string(), serde_json::to_value(key)?);
+    store.put_dir_as_tar(SHARED_KERNEL_KIND, &input_key, &payload_dir, meta)?;
+    scratch.mark_success();
+    Ok(true)
+}
+
+fn stage_share_payload(kernel_root: &Path, payload_dir: &Path, key: &KernelShareKey) -> Result<()> {
+    copy_dir_recursive(&kernel_root.join("staging"), &payload_dir.join("staging"))?;
+    for rel in SHARED_BUILD_FILES.iter().copied().chain([key.boot_image()]) {
+        let src = kernel_root.join("kernel-build").join(rel);
+        if !src.is_file() {
+            continue;
+        }
+        let dst = payload_dir.join("kernel-build").join(rel);
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&src, &dst).with_context(|| format!("copying '{}'", src.display()))?;
+    }
+    let stamp = kernel_root.join(EXTERNAL_MODULES_STAMP);
+    if stamp.is_file() {
+        fs::copy(&stamp, payload_dir.join(EXTERNAL_MODULES_STAMP))?;
+    }
+    Ok(())
+}
+
+/// Restore a shared kernel into `kernel_root`, replacing its contents.
+///
+/// Returns the distro that published the payload, or `None` if the store has
+/// no payload for `key`.
+pub fn restore_shared_kernel(
+    store: &ArtifactStore,
+    distro_id: &str,
+    kernel_root: &Path,
+    key: &KernelShareKey,
+) -> Result<Option<String>> {
+    let input_key = key.input_key();
+    let Some(stored) = store.get(SHARED_KERNEL_KIND, &input_key)? else {
+        return Ok(None);
+    };
+    let published_by = stored
+        .entry
+        .meta
+        .get("distro_id")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+
+    store.materialize_to(SHARED_KERNEL_KIND, &input_key, kernel_root)?;
+
+    let release_path = kernel_root.join(KERNEL_RELEASE_FILE);
+    let release = fs::read_to_string(&release_path)
+        .with_context(|| format!("reading shared kernel release '{}'", release_path.display()))?;
+    let release = release.trim();
+    if !release.ends_with(&key.localversion) {
+        bail!(
+            "shared kernel release '{}' does not carry localversion '{}' required by '{}'",
+            release,
+            key.localversion,
+            distro_id
+        );
+    }
+
+    let marker = ShareMarker {
+        distro_id: distro_id.to_string(),
+        key: key.clone(),
+        published_by: published_by.clone(),
+    };
+    fs::write(
+        kernel_root.join(SHARE_MARKER_FILENAME),
+        serde_json::to_vec_pretty(&marker)?,
+    )
+    .context("writing shared kernel marker")?;
+
+    Ok(Some(published_by.unwrap_or_else(|| "unknown".to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn key(localversion: &str, kconfig: &Path) -> KernelShareKey {
+        KernelShareKey::new("6.12.0", kconfig, localversion).unwrap()
+    }
+
+    #[test]
+    fn input_key_covers_every_component() {
+        let temp = TempDir::new().unwrap();
+        let kconfig = temp.path().join("kconfig");
+        write(&kconfig, "CONFIG_EFI=y\n");
+
+        let base = key("-levitate", &kconfig);
+        assert_eq!(base.input_key(), key("-levitate", &kconfig).input_key());
+        assert_ne!(base.input_key(), key("-acorn", &kconfig).input_key());
+
+        write(&kconfig, "CONFIG_EFI=n\n");
+        assert_ne!(base.input_key(), key("-levitate", &kconfig).input_key());
+    }
+
+    #[test]
+    fn boot_image_follows_kconfig_arch() {
+        let temp = TempDir::new().unwrap();
+        let kconfig = temp.path().join("kconfig");
+        write(&kconfig, "CONFIG_ARM64=y\nCONFIG_EFI=y\n");
+        let arm64 = key("-levitate", &kconfig);
+        assert_eq!(arm64.arch, "arm64");
+        assert_eq!(arm64.boot_image(), "arch/arm64/boot/Image");
+
+        write(&kconfig, "CONFIG_X86_64=y\nCONFIG_EFI=y\n");
+        let x86 = key("-levitate", &kconfig);
+        assert_eq!(x86.boot_image(), "arch/x86/boot/bzImage");
+        assert_ne!(arm64.input_key(), x86.input_key());
+    }
+
+    #[test]
+    fn publish_then_restore_into_other_distro() {
+        let temp = TempDir::new().unwrap();
+        let store = ArtifactStore::open(temp.path()).unwrap();
+        let kconfig = temp.path().join("kconfig");
+        write(&kconfig, "CONFIG_EFI=y\n");
+        let key = key("-shared", &kconfig);
+
+        let source = temp.path().join("kernel/acorn/current");
+        write(&source.join("staging/boot/vmlinuz"), "kernel");
+        write(
+            &source.join("staging/lib/modules/6.12.0-shared/modules.dep"),
+            "",
+        );
+        write(&source.join(KERNEL_RELEASE_FILE), "6.12.0-shared\n");
+        write(&source.join("kernel-build/vmlinux.o"), "not shared");
+        write(&source.join("kernel-build").join(key.boot_image()), "image");
+
+        let workspace = WorkspaceManager::new(temp.path()).unwrap();
+        assert!(publish_shared_kernel(&store, &workspace, "acorn", &source, &key).unwrap());
+        assert!(!publish_shared_kernel(&store, &workspace, "acorn", &source, &key).unwrap());
+        assert!(workspace.usage().unwrap().iter().all(|u| u.bytes == 0));
+
+        let target = temp.path().join("kernel/ralph/current");
+        let publisher = restore_shared_kernel(&store, "ralph", &target, &key).unwrap();
+        assert_eq!(publisher.as_deref(), Some("acorn"));
+        assert!(target.join("staging/boot/vmlinuz").is_file());
+        assert!(target.join(SHARE_MARKER_FILENAME).is_file());
+        assert!(!target.join("kernel-build/vmlinux.o").exists());
+        assert!(target.join("kernel-build").join(key.boot_image()).is_file());
+    }
+}
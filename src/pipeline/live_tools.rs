@@ -6,6 +6,7 @@ use std::os::unix::fs::{symlink, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use crate::build::shell_check::check_script;
 use crate::copy_dir_recursive;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
@@ -1052,6 +1053,7 @@ fn write_executable(path: &Path, content: &str) -> Result<()> {
         fs::create_dir_all(parent).with_context(|| format!("creating '{}'", parent.display()))?;
     }
     fs::write(path, content).with_context(|| format!("writing '{}'", path.display()))?;
+    check_script(path)?;
     let mut perms = fs::metadata(path)
         .with_context(|| format!("reading metadata '{}'", path.display()))?
         .permissions();
@@ -1066,5 +1068,5 @@ fn write_text(path: &Path, content: &str) -> Result<()> {
         fs::create_dir_all(parent).with_context(|| format!("creating '{}'", parent.display()))?;
     }
     fs::write(path, content).with_context(|| format!("writing '{}'", path.display()))?;
-    Ok(())
+    check_script(path)
 }
@@ -1,11 +1,15 @@
+pub(crate) mod alpine_share;
 pub(crate) mod config;
 pub(crate) mod io;
 pub(crate) mod kernel;
+pub(crate) mod kernel_share;
 pub(crate) mod live_tools;
 pub(crate) mod overlay;
 pub(crate) mod paths;
 pub(crate) mod plan;
 pub(crate) mod planner;
 pub(crate) mod products;
+pub(crate) mod release_share;
 pub(crate) mod scripts;
 pub(crate) mod source;
+pub(crate) mod stage_inputs;
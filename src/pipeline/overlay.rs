@@ -4,13 +4,33 @@ use std::os::unix::fs::symlink;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
+use crate::artifact::filesystem::copy_dir_recursive;
+use crate::assets::AssetResolver;
+use crate::build::accessibility::Accessibility;
+use crate::build::banner::BuildMetadata;
+use crate::build::consoles::SerialConsoles;
+use crate::build::locales::LocalePolicy;
+use crate::build::services::ServicePolicy;
+use crate::build::shell_check::check_script;
+use crate::build::swap::LiveSwap;
+use crate::contracts::context::Features;
+use crate::contracts::InitSystem;
+use crate::guest_protocol::framed_ready_marker;
+use crate::identity::IdentityPolicy;
 use crate::pipeline::io::rename_live_overlay_dir;
 use crate::{
     create_openrc_live_overlay, create_systemd_live_overlay, InittabVariant, LiveOverlayConfig,
     SystemdLiveOverlayConfig,
 };
 
-const BOOT_MACHINE_ID: &str = "0123456789abcdef0123456789abcdef\n";
+/// Variant directory of per-feature live overlay trees: `<feature>/` is
+/// copied over the generated live overlay when the feature is enabled.
+pub const FEATURE_OVERLAY_DIR: &str = "live-overlay.d";
+
+/// Variant directory of per-feature rootfs trees, copied over the live
+/// rootfs payload like [`FEATURE_OVERLAY_DIR`].
+pub const FEATURE_ROOTFS_DIR: &str = "rootfs.d";
+
 #[derive(Debug, Clone)]
 pub enum BootOverlayPolicy {
     Systemd {
@@ -22,27 +42,48 @@ pub enum BootOverlayPolicy {
     },
 }
 
+impl BootOverlayPolicy {
+    /// Init system the live rootfs boots with.
+    pub(crate) fn init_system(&self) -> InitSystem {
+        match self {
+            Self::Systemd { .. } => InitSystem::Systemd,
+            Self::OpenRc { .. } => InitSystem::OpenRC,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn create_live_overlay(
     output_dir: &Path,
     distro_id: &str,
-    os_name: &str,
-    overlay_label: &str,
+    build: &BuildMetadata,
     dir_name: &str,
     overlay: &BootOverlayPolicy,
+    assets: &AssetResolver,
+    accessibility: Option<&Accessibility>,
+    motd_template: Option<&str>,
+    services: &ServicePolicy,
+    swap: Option<&LiveSwap>,
+    consoles: &SerialConsoles,
 ) -> Result<PathBuf> {
-    let overlay_issue_banner = overlay_issue_banner(os_name, overlay_label);
+    let os_name = build.os_name.as_str();
     let live_overlay_dir = match overlay {
         BootOverlayPolicy::Systemd { issue_message } => create_systemd_live_overlay(
             output_dir,
             &SystemdLiveOverlayConfig {
                 os_name,
-                issue_message: issue_message
-                    .as_deref()
-                    .or(Some(overlay_issue_banner.as_str())),
-                masked_units: &[],
+                issue_message: issue_message.as_deref(),
+                motd_template,
+                build: Some(build),
+                masked_units: &services.masked_units(),
                 write_serial_test_profile: true,
-                machine_id: Some(BOOT_MACHINE_ID),
+                identity: Some(&IdentityPolicy::fixed_for_tests()),
                 enforce_utf8_locale_profile: false,
+                assets: Some(assets),
+                persistence: None,
+                accessibility,
+                swap,
+                consoles: Some(consoles),
             },
         )
         .with_context(|| format!("creating systemd live overlay for {}", distro_id))?,
@@ -55,7 +96,15 @@ pub(crate) fn create_live_overlay(
                 os_name,
                 inittab: *inittab,
                 seed_overlay: seed_overlay.as_deref(),
-                issue_message: Some(overlay_issue_banner.as_str()),
+                issue_message: None,
+                motd_template,
+                build: Some(build),
+                assets: Some(assets),
+                persistence: None,
+                accessibility,
+                disabled_services: &services.disabled_services(),
+                swap,
+                consoles: Some(consoles),
             },
         )
         .with_context(|| format!("creating openrc live overlay for {}", distro_id))?,
@@ -64,11 +113,33 @@ pub(crate) fn create_live_overlay(
     rename_live_overlay_dir(output_dir, &live_overlay_dir, dir_name).with_context(|| {
         format!(
             "renaming {} live overlay directory for '{}'",
-            overlay_label, distro_id
+            build.stage.as_deref().unwrap_or("live"),
+            distro_id
         )
     })
 }
 
+/// Copy `variant_dir/<trees_dir>/<feature>/` onto `dest` for each enabled
+/// feature, in name order. Trees of disabled features are left out.
+pub(crate) fn apply_feature_trees(
+    variant_dir: &Path,
+    trees_dir: &str,
+    features: &Features,
+    dest: &Path,
+) -> Result<()> {
+    let root = variant_dir.join(trees_dir);
+    for feature in features.iter() {
+        let tree = root.join(feature);
+        if !tree.is_dir() {
+            continue;
+        }
+        println!("  Applying {}/{}", trees_dir, feature);
+        copy_dir_recursive(&tree, dest)
+            .with_context(|| format!("copying '{}' -> '{}'", tree.display(), dest.display()))?;
+    }
+    Ok(())
+}
+
 pub(crate) fn ensure_systemd_default_target(rootfs_dir: &Path) -> Result<()> {
     let default_target = rootfs_dir.join("etc/systemd/system/default.target");
     if let Some(parent) = default_target.parent() {
@@ -125,24 +196,12 @@ pub(crate) fn ensure_systemd_sshd_dirs(rootfs_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn ensure_systemd_locale_completeness(rootfs_dir: &Path) -> Result<()> {
-    let locale_payload_candidates = [
-        "lib/locale/C.utf8/LC_CTYPE",
-        "usr/lib/locale/C.utf8/LC_CTYPE",
-        "lib64/locale/C.utf8/LC_CTYPE",
-        "usr/lib64/locale/C.utf8/LC_CTYPE",
-    ];
-    let has_utf8_payload = locale_payload_candidates
-        .iter()
-        .any(|rel| rootfs_dir.join(rel).is_file());
-    if !has_utf8_payload {
-        bail!(
-            "missing UTF-8 locale payload in live systemd rootfs '{}'; expected one of: {}",
-            rootfs_dir.display(),
-            locale_payload_candidates.join(", ")
-        );
-    }
-
+/// Check every locale the variant keeps against the rootfs and write
+/// `/etc/locale.conf` with its default.
+pub(crate) fn ensure_systemd_locale_completeness(
+    rootfs_dir: &Path,
+    locales: &LocalePolicy,
+) -> Result<()> {
     let lib_locale = rootfs_dir.join("lib/locale");
     let usr_lib_locale = rootfs_dir.join("usr/lib/locale");
     if lib_locale.is_dir() && !usr_lib_locale.exists() {
@@ -154,15 +213,7 @@ pub(crate) fn ensure_systemd_locale_completeness(rootfs_dir: &Path) -> Result<()
             .with_context(|| format!("linking '{}' -> '/lib/locale'", usr_lib_locale.display()))?;
     }
 
-    let etc_dir = rootfs_dir.join("etc");
-    fs::create_dir_all(&etc_dir).with_context(|| format!("creating '{}'", etc_dir.display()))?;
-    fs::write(etc_dir.join("locale.conf"), "LANG=C.UTF-8\n").with_context(|| {
-        format!(
-            "writing canonical locale config '{}'",
-            etc_dir.join("locale.conf").display()
-        )
-    })?;
-    Ok(())
+    locales.verify(rootfs_dir)
 }
 
 pub(crate) fn ensure_required_service_wiring(
@@ -261,9 +312,11 @@ pub(crate) fn ensure_required_service_wiring(
 
 pub(crate) fn ensure_openrc_shell(
     rootfs_source_dir: &Path,
-    os_name: &str,
+    build: &BuildMetadata,
     inittab: InittabVariant,
+    consoles: &SerialConsoles,
 ) -> Result<()> {
+    let os_name = build.os_name.as_str();
     let etc_dir = rootfs_source_dir.join("etc");
     let usr_local_bin = rootfs_source_dir.join("usr/local/bin");
     fs::create_dir_all(&etc_dir)
@@ -278,9 +331,13 @@ pub(crate) fn ensure_openrc_shell(
     let autologin = usr_local_bin.join("serial-autologin");
     fs::write(
         &autologin,
-        "#!/bin/sh\necho \"___SHELL_READY___\"\necho \"___SHELL_READY___\" >/dev/console 2>/dev/null || true\necho \"___SHELL_READY___\" >/dev/kmsg 2>/dev/null || true\nexec /bin/sh -l\n",
+        format!(
+            "#!/bin/sh\necho \"{ready}\"\necho \"{ready}\" >/dev/console 2>/dev/null || true\necho \"{ready}\" >/dev/kmsg 2>/dev/null || true\nexec /bin/sh -l\n",
+            ready = framed_ready_marker()
+        ),
     )
     .with_context(|| format!("writing '{}'", autologin.display()))?;
+    check_script(&autologin)?;
     let mut perms = fs::metadata(&autologin)
         .with_context(|| format!("reading metadata '{}'", autologin.display()))?
         .permissions();
@@ -300,22 +357,24 @@ tty3::respawn:/sbin/getty 38400 tty3
 tty4::respawn:/sbin/getty 38400 tty4
 tty5::respawn:/sbin/getty 38400 tty5
 tty6::respawn:/sbin/getty 38400 tty6
-ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0 vt100
+{serial}
 ::wait:/sbin/openrc default
 ::ctrlaltdel:/sbin/reboot
 ::shutdown:/sbin/openrc shutdown
-"#
+"#,
+            serial = consoles.inittab_entries()
         ),
         InittabVariant::SerialOnly => format!(
             r#"# /etc/inittab - {os_name} Live
 # Live boot starts in a minimal interactive shell.
 ::sysinit:/sbin/openrc sysinit
 ::sysinit:/sbin/openrc boot
-ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0 vt100
+{serial}
 ::wait:/sbin/openrc default
 ::ctrlaltdel:/sbin/reboot
 ::shutdown:/sbin/openrc shutdown
-"#
+"#,
+            serial = consoles.inittab_entries()
         ),
     };
     let inittab_path = etc_dir.join("inittab");
@@ -323,14 +382,8 @@ ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0
         .with_context(|| format!("writing '{}'", inittab_path.display()))?;
 
     let issue_path = etc_dir.join("issue");
-    fs::write(
-        &issue_path,
-        format!(
-            "\n{} Boot Live - \\l\n\nLogin as 'root' (no password)\n\n",
-            os_name
-        ),
-    )
-    .with_context(|| format!("writing '{}'", issue_path.display()))?;
+    fs::write(&issue_path, build.default_live_issue())
+        .with_context(|| format!("writing '{}'", issue_path.display()))?;
 
     let shadow_path = etc_dir.join("shadow");
     fs::write(
@@ -347,10 +400,3 @@ ttyS0::respawn:/sbin/getty -L -n -l /usr/local/bin/serial-autologin 115200 ttyS0
 
     Ok(())
 }
-
-fn overlay_issue_banner(os_name: &str, overlay_label: &str) -> String {
-    format!(
-        "\n{} {} Live - \\l\n\nLogin as 'root' (no password)\n\n",
-        os_name, overlay_label
-    )
-}
@@ -1,21 +1,14 @@
-use anyhow::{bail, Result};
+use anyhow::Result;
 use std::path::{Path, PathBuf};
 
 pub(crate) fn resolve_repo_path(repo_root: &Path, path: &str) -> PathBuf {
-    let candidate = Path::new(path);
-    if candidate.is_absolute() {
-        candidate.to_path_buf()
-    } else {
-        repo_root.join(candidate)
-    }
+    crate::repo_layout::layout_or_default(repo_root).resolve(path)
 }
 
-pub(crate) fn normalize_distro_id(distro_id: &str, purpose: &str) -> Result<&'static str> {
-    match distro_id.trim().to_ascii_lowercase().as_str() {
-        "levitate" | "leviso" => Ok("levitate"),
-        "acorn" | "acornos" => Ok("acorn"),
-        "iuppiter" | "iuppiteros" => Ok("iuppiter"),
-        "ralph" | "ralphos" => Ok("ralph"),
-        other => bail!("unsupported distro '{}' for {} resolution", other, purpose),
-    }
+pub(crate) fn normalize_distro_id(
+    repo_root: &Path,
+    distro_id: &str,
+    purpose: &str,
+) -> Result<String> {
+    crate::repo_config::canonical_distro_id(repo_root, distro_id, purpose)
 }
@@ -6,7 +6,13 @@ use std::path::Component;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-const LEGACY_ROOTFS_COMPONENT_SEQUENCES: &[&[&str]] = &[
+#[cfg(test)]
+use crate::artifact::payload_role::{PayloadRole, PAYLOAD_ROLE_MARKER};
+use crate::artifact::xattr::{copy_tree_xattrs, copy_xattrs, XattrCopy};
+use crate::build::branding::Branding;
+use crate::integrity::{verified_tree_copy, verify_file_copy};
+
+pub(crate) const LEGACY_ROOTFS_COMPONENT_SEQUENCES: &[&[&str]] = &[
     &["leviso", "downloads", "rootfs"],
     &["ralphos", "downloads", "rootfs"],
     &["acornos", "downloads", "rootfs"],
@@ -43,29 +49,25 @@ pub(crate) enum RootfsProducer {
 
 pub(crate) fn build_baseline_producers(
     distro_id: &str,
-    os_name: &str,
-    os_id: &str,
+    branding: &Branding,
 ) -> Vec<RootfsProducer> {
-    let os_release = format!(
-        "NAME=\"{}\"\nID={}\nPRETTY_NAME=\"{}\"\n",
-        os_name, os_id, os_name
-    );
     let product_manifest = format!(
         "{{\n  \"schema\": 1,\n  \"product\": \"base-rootfs\",\n  \"product_slug\": \"base_rootfs\",\n  \"distro_id\": \"{}\",\n  \"os_name\": \"{}\",\n  \"os_id\": \"{}\",\n  \"payload_role\": \"rootfs-source\"\n}}\n",
-        distro_id, os_name, os_id
+        distro_id, branding.name, branding.id
     );
-    vec![
-        RootfsProducer::WriteText {
-            path: PathBuf::from("usr/lib/product-manifest.json"),
-            content: product_manifest,
-            mode: None,
-        },
+    let mut producers = vec![RootfsProducer::WriteText {
+        path: PathBuf::from("usr/lib/product-manifest.json"),
+        content: product_manifest,
+        mode: None,
+    }];
+    producers.extend(branding.files().into_iter().map(|(path, content)| {
         RootfsProducer::WriteText {
-            path: PathBuf::from("etc/os-release"),
-            content: os_release,
+            path: PathBuf::from(path),
+            content,
             mode: None,
-        },
-    ]
+        }
+    }));
+    producers
 }
 
 #[cfg(test)]
@@ -73,8 +75,8 @@ pub(crate) fn boot_baseline_producers(overlay_kind: &str) -> Vec<RootfsProducer>
     if overlay_kind == "systemd" {
         return vec![
             RootfsProducer::WriteText {
-                path: PathBuf::from(".live-payload-role"),
-                content: "rootfs\n".to_string(),
+                path: PathBuf::from(PAYLOAD_ROLE_MARKER),
+                content: PayloadRole::Rootfs.marker(),
                 mode: None,
             },
             RootfsProducer::CopySymlink {
@@ -196,8 +198,8 @@ pub(crate) fn boot_baseline_producers(overlay_kind: &str) -> Vec<RootfsProducer>
     }
     vec![
         RootfsProducer::WriteText {
-            path: PathBuf::from(".live-payload-role"),
-            content: "rootfs\n".to_string(),
+            path: PathBuf::from(PAYLOAD_ROLE_MARKER),
+            content: PayloadRole::Rootfs.marker(),
             mode: None,
         },
         RootfsProducer::CopyTree {
@@ -297,7 +299,16 @@ pub(crate) fn apply_producer_plan(plan: &ProducerPlan, destination_root: &Path)
                         target_path.display()
                     )
                 })?;
-                rsync_tree(&source_path, &target_path)?;
+                verified_tree_copy(&source_path, &target_path, || {
+                    rsync_tree(&source_path, &target_path)
+                })?;
+                let xattrs = copy_tree_xattrs(&source_path, &target_path).with_context(|| {
+                    format!(
+                        "copying extended attributes from '{}'",
+                        source_path.display()
+                    )
+                })?;
+                warn_skipped_xattrs(&source_path, xattrs);
             }
             RootfsProducer::CopySymlink {
                 source,
@@ -390,6 +401,14 @@ pub(crate) fn apply_producer_plan(plan: &ProducerPlan, destination_root: &Path)
                         target_path.display()
                     )
                 })?;
+                verify_file_copy(&source_path, &target_path)?;
+                let xattrs = copy_xattrs(&source_path, &target_path).with_context(|| {
+                    format!(
+                        "copying extended attributes from '{}'",
+                        source_path.display()
+                    )
+                })?;
+                warn_skipped_xattrs(&source_path, xattrs);
             }
             RootfsProducer::WriteText {
                 path,
@@ -461,6 +480,18 @@ fn contains_component_sequence(haystack: &[String], needle: &[&str]) -> bool {
         .any(|window| window.iter().map(String::as_str).eq(needle.iter().copied()))
 }
 
+/// Labels the builder could not write leave the copy unlabeled; variants
+/// that need them relabel (`selinux.toml`).
+fn warn_skipped_xattrs(source: &Path, xattrs: XattrCopy) {
+    if xattrs.skipped > 0 {
+        eprintln!(
+            "  [WARN] {} extended attribute(s) from '{}' not preserved (unsupported or unprivileged)",
+            xattrs.skipped,
+            source.display()
+        );
+    }
+}
+
 fn rsync_tree(source_dir: &Path, destination_dir: &Path) -> Result<()> {
     let output = Command::new("rsync")
         .arg("-a")
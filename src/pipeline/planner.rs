@@ -1,6 +1,7 @@
 use crate::pipeline::io::{
     release_product_rootfs_exists_for_distro, resolve_release_product_rootfs_image_for_distro,
 };
+use crate::pipeline::release_share::restore_release_for_current_inputs;
 use anyhow::{bail, Context, Result};
 use distro_contract::ConformanceContract;
 use std::collections::HashSet;
@@ -180,14 +181,46 @@ fn resolve_parent_release_rootfs_image(
     parent_product: &str,
 ) -> Result<PathBuf> {
     let release_dir_name = release_dir_name_for_product(parent_product)?;
-    resolve_release_product_rootfs_image_for_distro(
-        repo_root,
-        distro_id,
-        release_dir_name,
-        parent_product,
-        &contract.artifacts.rootfs_name,
-    )
-    .with_context(|| {
+    let resolve = || {
+        resolve_release_product_rootfs_image_for_distro(
+            repo_root,
+            distro_id,
+            release_dir_name,
+            parent_product,
+            &contract.artifacts.rootfs_name,
+        )
+    };
+    // A pruned or never-local parent run is restored from the artifact store
+    // when its current inputs were published there, before giving up.
+    let resolved = match resolve() {
+        Ok(path) => Ok(path),
+        Err(err) => match restore_release_for_current_inputs(
+            repo_root,
+            distro_id,
+            contract,
+            parent_product,
+            release_dir_name,
+        ) {
+            Ok(Some(run_dir)) => {
+                println!(
+                    "  Restored parent release '{}' for '{}' from artifact store into {}",
+                    parent_product,
+                    distro_id,
+                    run_dir.display()
+                );
+                resolve()
+            }
+            Ok(None) => Err(err),
+            Err(restore_err) => {
+                eprintln!(
+                    "  [WARN] failed to restore parent release '{}' from artifact store: {:#}",
+                    parent_product, restore_err
+                );
+                Err(err)
+            }
+        },
+    };
+    resolved.with_context(|| {
         format!(
             "resolving canonical parent release rootfs '{}' for '{}'",
             parent_product, distro_id
@@ -286,6 +319,7 @@ fn product_for_logical_name(logical_name: &str) -> Result<&'static str> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::artifact_store::DEFAULT_OUTPUT_SUBDIR;
     use distro_contract::load_variant_contract_for_distro_from;
     use serde_json::json;
     use std::fs;
@@ -313,7 +347,7 @@ mod tests {
     ) -> PathBuf {
         let run_dir = repo_root
             .join(".artifacts")
-            .join("out")
+            .join(DEFAULT_OUTPUT_SUBDIR)
             .join(distro_id)
             .join("releases")
             .join(release_dir_name_for_product(product).expect("release dir name"))
@@ -509,6 +543,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn product_realization_restores_missing_parent_release_from_store() {
+        use crate::artifact_store::ArtifactStore;
+        use crate::pipeline::release_share::{
+            publish_release_artifacts, release_input_key, ReleaseArtifactFiles,
+        };
+
+        let repo_root = temp_repo_root();
+        let contract = workspace_contract("levitate");
+        let rootfs_filename = contract.artifacts.rootfs_name.clone();
+        write_successful_release_rootfs(
+            repo_root.path(),
+            "levitate",
+            PRODUCT_BASE_ROOTFS,
+            &rootfs_filename,
+        );
+        let live_boot_rootfs = write_successful_release_rootfs(
+            repo_root.path(),
+            "levitate",
+            PRODUCT_LIVE_BOOT,
+            &rootfs_filename,
+        );
+        let live_boot_run = live_boot_rootfs.parent().expect("run dir").to_path_buf();
+        let store = ArtifactStore::open(repo_root.path()).expect("open store");
+        let key = release_input_key(repo_root.path(), "levitate", &contract, PRODUCT_LIVE_BOOT)
+            .expect("live-boot input key");
+        let files = ReleaseArtifactFiles {
+            rootfs: Some(rootfs_filename.clone()),
+            ..Default::default()
+        };
+        publish_release_artifacts(
+            &store,
+            "levitate",
+            PRODUCT_LIVE_BOOT,
+            &key,
+            &live_boot_run,
+            &files,
+        )
+        .expect("publish live-boot");
+        fs::remove_dir_all(&live_boot_run).expect("drop live-boot run");
+
+        let plan =
+            plan_product_realization(repo_root.path(), "levitate", &contract, PRODUCT_LIVE_TOOLS)
+                .expect("plan live-tools from stored parent");
+
+        let restored = plan.ordered_steps[2]
+            .resolved_parent_rootfs_image
+            .as_ref()
+            .expect("restored live-boot rootfs");
+        assert_ne!(restored, &live_boot_rootfs);
+        assert_eq!(fs::read(restored).expect("read rootfs"), b"rootfs");
+    }
+
     #[test]
     fn planner_rejects_cycles() {
         let mut contract = workspace_contract("levitate");
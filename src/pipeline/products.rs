@@ -1,8 +1,18 @@
 use anyhow::{Context, Result};
 use distro_contract::ConformanceContract;
+use distro_spec::shared::ISO_LIVE_DIR;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::artifact::installer::{
+    build_installer_stage, InstallerLauncherConfig, INSTALLER_IMAGE_FILENAME,
+};
+use crate::build::branding::Branding;
+use crate::build::kernel_headers;
+use crate::build::package_manager::PackageManagerBootstrap;
+use crate::build::selinux::SelinuxRelabel;
+use crate::build::timesync::TimeSync;
+use crate::identity::IdentityPolicy;
 use crate::pipeline::config::{
     load_boot_config_from_contract, load_installed_boot_payload_config_from_contract,
     load_live_tools_config_from_contract,
@@ -11,11 +21,7 @@ use crate::pipeline::io::{
     create_empty_overlay_dir, create_unique_output_dir, extract_erofs_rootfs,
 };
 use crate::pipeline::live_tools::{add_required_tools, InstallExperience, LiveToolsRuntimeAction};
-use crate::pipeline::overlay::{
-    create_live_overlay, ensure_openrc_shell, ensure_required_service_wiring,
-    ensure_systemd_default_target, ensure_systemd_locale_completeness, ensure_systemd_sshd_dirs,
-    BootOverlayPolicy,
-};
+use crate::pipeline::overlay::BootOverlayPolicy;
 #[cfg(test)]
 use crate::pipeline::plan::boot_baseline_producers;
 #[cfg(test)]
@@ -23,10 +29,10 @@ use crate::pipeline::plan::ensure_non_legacy_rootfs_source;
 use crate::pipeline::plan::{
     apply_producer_plan, build_baseline_producers, ProducerPlan, RootfsProducer,
 };
-use crate::pipeline::scripts::install_scenario_test_scripts;
 use crate::pipeline::source::{
     cleanup_legacy_provider_dir, materialize_source_rootfs, RootfsSourcePolicy,
 };
+use crate::pipeline::stage_inputs::{apply_additive_producers, LiveStage, StageInputEngine};
 use crate::recipe::alpine_rootfs_source::is_alpine_rootfs_source_recipe;
 
 #[derive(Debug, Clone)]
@@ -45,6 +51,8 @@ pub struct LiveBootProduct {
 pub struct LiveToolsProduct {
     pub rootfs_source_dir: PathBuf,
     pub live_overlay_dir: PathBuf,
+    /// Installer squashfs the hook ships in the ISO `live/` directory.
+    pub installer_image: PathBuf,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +102,7 @@ pub struct LiveBootProductSpec {
     repo_root: PathBuf,
     pub distro_id: String,
     pub os_name: String,
+    os_version: String,
     pub rootfs_source_dir: PathBuf,
     parent_rootfs: ParentRootfsInput,
     resolved_parent_rootfs_image: Option<PathBuf>,
@@ -140,6 +149,21 @@ impl LiveBootProductSpec {
         self.resolved_parent_rootfs_image = Some(image);
         self
     }
+
+    fn stage_inputs(&self) -> StageInputEngine<'_> {
+        StageInputEngine {
+            stage: LiveStage::Boot,
+            repo_root: &self.repo_root,
+            distro_id: &self.distro_id,
+            os_name: &self.os_name,
+            os_version: &self.os_version,
+            add_plan: &self.add_plan,
+            rootfs_source_policy: &self.rootfs_source_policy,
+            live_overlay: &self.live_overlay,
+            overlay: &self.overlay,
+            required_services: &self.required_services,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -147,12 +171,16 @@ pub struct LiveToolsProductSpec {
     repo_root: PathBuf,
     pub distro_id: String,
     pub os_name: String,
+    os_id: String,
+    os_version: String,
     install_experience: InstallExperience,
     runtime_actions: Vec<LiveToolsRuntimeAction>,
     pub rootfs_source_dir: PathBuf,
     parent_rootfs: ParentRootfsInput,
     resolved_parent_rootfs_image: Option<PathBuf>,
     live_overlay: OverlayLayout,
+    add_plan: ProducerPlan,
+    rootfs_source_policy: Option<RootfsSourcePolicy>,
     overlay: BootOverlayPolicy,
     required_services: Vec<String>,
 }
@@ -162,6 +190,48 @@ impl LiveToolsProductSpec {
         self.resolved_parent_rootfs_image = Some(image);
         self
     }
+
+    /// Stage 03 Install: pack the install tools in `tool_dir` into the
+    /// installer image under `output_dir` and wire its launcher into
+    /// `live_overlay_dir`. Returns the image path.
+    pub fn build_installer(
+        &self,
+        tool_dir: &Path,
+        output_dir: &Path,
+        live_overlay_dir: &Path,
+    ) -> Result<PathBuf> {
+        let image_path = format!(
+            "/run/live-media/{}/{}",
+            ISO_LIVE_DIR, INSTALLER_IMAGE_FILENAME
+        );
+        build_installer_stage(
+            tool_dir,
+            output_dir,
+            live_overlay_dir,
+            &InstallerLauncherConfig {
+                os_name: &self.os_name,
+                os_id: &self.os_id,
+                init_system: self.overlay.init_system(),
+                image_path: &image_path,
+            },
+        )
+        .with_context(|| format!("building installer stage for '{}'", self.distro_id))
+    }
+
+    fn stage_inputs(&self) -> StageInputEngine<'_> {
+        StageInputEngine {
+            stage: LiveStage::Tools,
+            repo_root: &self.repo_root,
+            distro_id: &self.distro_id,
+            os_name: &self.os_name,
+            os_version: &self.os_version,
+            add_plan: &self.add_plan,
+            rootfs_source_policy: &self.rootfs_source_policy,
+            live_overlay: &self.live_overlay,
+            overlay: &self.overlay,
+            required_services: &self.required_services,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -185,20 +255,19 @@ impl InstalledBootProductSpec {
 
 pub fn load_base_rootfs_product_spec(
     distro_id: &str,
-    os_name: &str,
-    os_id: &str,
+    branding: &Branding,
     _output_root: &Path,
     layout: BaseProductLayout,
 ) -> Result<BaseRootfsProductSpec> {
     Ok(BaseRootfsProductSpec {
         distro_id: distro_id.to_string(),
-        os_name: os_name.to_string(),
-        os_id: os_id.to_string(),
+        os_name: branding.name.clone(),
+        os_id: branding.id.clone(),
         rootfs_source_dir: layout.rootfs_source_dir,
         live_overlay_dir_name: layout.live_overlay_dir_name,
         plan: ProducerPlan {
             source_rootfs_dir: None,
-            producers: build_baseline_producers(distro_id, os_name, os_id),
+            producers: build_baseline_producers(distro_id, branding),
         },
     })
 }
@@ -217,6 +286,11 @@ pub fn prepare_base_rootfs_product(
     let rootfs_source_dir = create_unique_output_dir(output_dir, &spec.rootfs_source_dir)?;
     apply_producer_plan(&spec.plan, &rootfs_source_dir)
         .with_context(|| format!("materializing base rootfs for '{}'", spec.distro_id))?;
+    // The base rootfs is shared by live and installed products; never bake in
+    // the identity package scripts generated at build time.
+    IdentityPolicy::first_boot()
+        .apply(&rootfs_source_dir)
+        .with_context(|| format!("resetting identity in base rootfs for '{}'", spec.distro_id))?;
 
     let live_overlay_dir = create_empty_overlay_dir(output_dir, &spec.live_overlay_dir_name)
         .with_context(|| format!("creating empty overlay for {}", spec.distro_id))?;
@@ -245,6 +319,7 @@ pub fn load_live_boot_product_spec(
         repo_root: repo_root.to_path_buf(),
         distro_id: distro_id.to_string(),
         os_name: loaded.os_name,
+        os_version: loaded.os_version,
         rootfs_source_dir: layout.rootfs_source_dir,
         parent_rootfs: layout.parent_rootfs,
         resolved_parent_rootfs_image: None,
@@ -269,113 +344,27 @@ pub fn prepare_live_boot_product(
         &spec.parent_rootfs,
         spec.resolved_parent_rootfs_image.as_deref(),
     )?;
-    fs::create_dir_all(output_dir).with_context(|| {
-        format!(
-            "creating live boot product output directory '{}'",
-            output_dir.display()
-        )
-    })?;
-
-    let rootfs_source_dir = create_unique_output_dir(output_dir, &spec.rootfs_source_dir)?;
-    cleanup_legacy_provider_dir(output_dir).with_context(|| {
-        format!(
-            "cleaning legacy live boot provider directory under '{}'",
-            output_dir.display()
-        )
-    })?;
-    extract_erofs_rootfs(parent_rootfs, &rootfs_source_dir).with_context(|| {
-        format!(
-            "extracting parent rootfs for live boot product from '{}'",
-            parent_rootfs.display()
-        )
-    })?;
-
-    let mut add_plan = spec.add_plan.clone();
-    if add_plan.producers.iter().any(|producer| {
-        matches!(
-            producer,
-            RootfsProducer::CopyTree { .. }
-                | RootfsProducer::CopySymlink { .. }
-                | RootfsProducer::CopyFile { .. }
-        )
-    }) {
-        let source_rootfs_dir = materialize_source_rootfs(
-            &spec.repo_root,
-            &spec.distro_id,
-            &spec.rootfs_source_policy,
-        )?;
-        add_plan.source_rootfs_dir = Some(source_rootfs_dir);
-    }
-
-    apply_producer_plan(&add_plan, &rootfs_source_dir).with_context(|| {
-        format!(
-            "applying live boot product additive producers for '{}'",
-            spec.distro_id
-        )
-    })?;
-    install_scenario_test_scripts(&spec.repo_root, &rootfs_source_dir).with_context(|| {
-        format!(
-            "installing scenario test scripts into live boot rootfs for '{}'",
-            spec.distro_id
-        )
-    })?;
-    if let BootOverlayPolicy::OpenRc { inittab, .. } = spec.overlay {
-        ensure_openrc_shell(&rootfs_source_dir, &spec.os_name, inittab).with_context(|| {
-            format!(
-                "ensuring OpenRC live boot serial shell for '{}'",
-                spec.distro_id
-            )
-        })?;
-    }
-    if matches!(&spec.overlay, BootOverlayPolicy::Systemd { .. }) {
-        ensure_systemd_default_target(&rootfs_source_dir).with_context(|| {
-            format!(
-                "ensuring systemd live boot default target for '{}'",
-                spec.distro_id
-            )
-        })?;
-        ensure_systemd_sshd_dirs(&rootfs_source_dir).with_context(|| {
-            format!(
-                "ensuring systemd live boot sshd directories for '{}'",
-                spec.distro_id
-            )
-        })?;
-        ensure_systemd_locale_completeness(&rootfs_source_dir).with_context(|| {
-            format!(
-                "ensuring systemd live boot locale completeness for '{}'",
-                spec.distro_id
-            )
-        })?;
-    }
-
-    let live_overlay_dir = create_live_overlay(
+    let variant_dir =
+        crate::repo_layout::layout_or_default(&spec.repo_root).variant_dir(&spec.distro_id);
+    let inputs = spec.stage_inputs().prepare(
+        parent_rootfs,
         output_dir,
-        &spec.distro_id,
-        &spec.os_name,
-        &spec.live_overlay.issue_banner_label,
-        &spec.live_overlay.dir_name,
-        &spec.overlay,
-    )?;
-
-    if let BootOverlayPolicy::OpenRc { inittab, .. } = spec.overlay {
-        ensure_openrc_shell(&live_overlay_dir, &spec.os_name, inittab).with_context(|| {
-            format!(
-                "ensuring OpenRC live overlay serial shell for '{}'",
-                spec.distro_id
+        &spec.rootfs_source_dir,
+        |rootfs_source_dir, _| {
+            kernel_headers::install_for_product(
+                &spec.repo_root,
+                &variant_dir,
+                &spec.distro_id,
+                "live-boot",
+                rootfs_source_dir,
             )
-        })?;
-    }
-    ensure_required_service_wiring(&live_overlay_dir, &spec.overlay, &spec.required_services)
-        .with_context(|| {
-            format!(
-                "ensuring live boot required service wiring for '{}'",
-                spec.distro_id
-            )
-        })?;
+            .with_context(|| format!("adding kernel headers for '{}'", spec.distro_id))
+        },
+    )?;
 
     Ok(LiveBootProduct {
-        rootfs_source_dir,
-        live_overlay_dir,
+        rootfs_source_dir: inputs.rootfs_source_dir,
+        live_overlay_dir: inputs.live_overlay_dir,
     })
 }
 
@@ -406,12 +395,21 @@ pub fn load_live_tools_product_spec(
         repo_root: repo_root.to_path_buf(),
         distro_id: distro_id.to_string(),
         os_name: loaded.os_name,
+        os_id: contract.identity.os_id.clone(),
+        os_version: live_boot_spec.os_version.clone(),
         install_experience: loaded.install_experience,
         runtime_actions: loaded.runtime_actions,
         rootfs_source_dir: layout.rootfs_source_dir,
         parent_rootfs: layout.parent_rootfs,
         resolved_parent_rootfs_image: None,
         live_overlay: layout.live_overlay,
+        // The contract declares no live-tools producers yet; the parent
+        // live-boot rootfs already carries live-boot's.
+        add_plan: ProducerPlan {
+            source_rootfs_dir: None,
+            producers: Vec::new(),
+        },
+        rootfs_source_policy: live_boot_spec.rootfs_source_policy.clone(),
         overlay: live_boot_spec.overlay.clone(),
         required_services: live_boot_spec.required_services().to_vec(),
     })
@@ -456,66 +454,42 @@ pub fn prepare_live_tools_product(
         &spec.parent_rootfs,
         spec.resolved_parent_rootfs_image.as_deref(),
     )?;
-    fs::create_dir_all(output_dir).with_context(|| {
-        format!(
-            "creating live tools product output directory '{}'",
-            output_dir.display()
-        )
-    })?;
-
-    let rootfs_source_dir = create_unique_output_dir(output_dir, &spec.rootfs_source_dir)?;
-    extract_erofs_rootfs(parent_rootfs, &rootfs_source_dir).with_context(|| {
-        format!(
-            "extracting parent rootfs for live tools product from '{}'",
-            parent_rootfs.display()
-        )
-    })?;
-
-    install_scenario_test_scripts(&spec.repo_root, &rootfs_source_dir).with_context(|| {
-        format!(
-            "installing scenario test scripts into live tools rootfs for '{}'",
-            spec.distro_id
-        )
-    })?;
-    if matches!(&spec.overlay, BootOverlayPolicy::Systemd { .. }) {
-        ensure_systemd_locale_completeness(&rootfs_source_dir).with_context(|| {
-            format!(
-                "ensuring systemd live tools locale completeness for '{}'",
-                spec.distro_id
-            )
-        })?;
-    }
-
-    let live_overlay_dir = create_live_overlay(
+    let variant_dir =
+        crate::repo_layout::layout_or_default(&spec.repo_root).variant_dir(&spec.distro_id);
+    let inputs = spec.stage_inputs().prepare(
+        parent_rootfs,
         output_dir,
-        &spec.distro_id,
-        &spec.os_name,
-        &spec.live_overlay.issue_banner_label,
-        &spec.live_overlay.dir_name,
-        &spec.overlay,
-    )?;
-
-    add_required_tools(
-        &spec.repo_root,
-        &rootfs_source_dir,
-        &live_overlay_dir,
-        &spec.distro_id,
-        spec.install_experience,
-        &spec.runtime_actions,
-    )
-    .with_context(|| format!("adding required live tools for '{}'", spec.distro_id))?;
-
-    ensure_required_service_wiring(&live_overlay_dir, &spec.overlay, &spec.required_services)
-        .with_context(|| {
-            format!(
-                "ensuring live tools required service wiring for '{}'",
-                spec.distro_id
+        &spec.rootfs_source_dir,
+        |rootfs_source_dir, live_overlay_dir| {
+            add_required_tools(
+                &spec.repo_root,
+                rootfs_source_dir,
+                live_overlay_dir,
+                &spec.distro_id,
+                spec.install_experience,
+                &spec.runtime_actions,
             )
-        })?;
+            .with_context(|| format!("adding required live tools for '{}'", spec.distro_id))?;
+            spec.build_installer(
+                &live_overlay_dir.join("usr/bin"),
+                output_dir,
+                live_overlay_dir,
+            )?;
+            kernel_headers::install_for_product(
+                &spec.repo_root,
+                &variant_dir,
+                &spec.distro_id,
+                "live-tools",
+                rootfs_source_dir,
+            )
+            .with_context(|| format!("adding kernel headers for '{}'", spec.distro_id))
+        },
+    )?;
 
     Ok(LiveToolsProduct {
-        rootfs_source_dir,
-        live_overlay_dir,
+        rootfs_source_dir: inputs.rootfs_source_dir,
+        live_overlay_dir: inputs.live_overlay_dir,
+        installer_image: output_dir.join(INSTALLER_IMAGE_FILENAME),
     })
 }
 
@@ -550,29 +524,66 @@ pub fn prepare_installed_boot_product(
         )
     })?;
 
-    let mut add_plan = spec.add_plan.clone();
-    if add_plan.producers.iter().any(|producer| {
-        matches!(
-            producer,
-            RootfsProducer::CopyTree { .. }
-                | RootfsProducer::CopySymlink { .. }
-                | RootfsProducer::CopyFile { .. }
+    apply_additive_producers(
+        &spec.add_plan,
+        &spec.rootfs_source_policy,
+        &spec.repo_root,
+        &spec.distro_id,
+        &rootfs_source_dir,
+    )
+    .with_context(|| {
+        format!(
+            "applying installed boot product additive producers for '{}'",
+            spec.distro_id
         )
-    }) {
-        let source_rootfs_dir = materialize_source_rootfs(
-            &spec.repo_root,
-            &spec.distro_id,
-            &spec.rootfs_source_policy,
-        )?;
-        add_plan.source_rootfs_dir = Some(source_rootfs_dir);
+    })?;
+    let variant_dir =
+        crate::repo_layout::layout_or_default(&spec.repo_root).variant_dir(&spec.distro_id);
+    if let Some(package_manager) = PackageManagerBootstrap::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading package manager config for '{}'", spec.distro_id))?
+    {
+        package_manager
+            .install_into_rootfs(&rootfs_source_dir)
+            .with_context(|| {
+                format!(
+                    "bootstrapping package manager in installed boot rootfs for '{}'",
+                    spec.distro_id
+                )
+            })?;
     }
-
-    apply_producer_plan(&add_plan, &rootfs_source_dir).with_context(|| {
+    // Installed systems are the ones left running for long test sessions.
+    if let Some(timesync) = TimeSync::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading time sync config for '{}'", spec.distro_id))?
+    {
+        timesync
+            .install_into_rootfs(&rootfs_source_dir)
+            .with_context(|| {
+                format!(
+                    "configuring time sync in installed boot rootfs for '{}'",
+                    spec.distro_id
+                )
+            })?;
+    }
+    kernel_headers::install_for_product(
+        &spec.repo_root,
+        &variant_dir,
+        &spec.distro_id,
+        "installed-boot",
+        &rootfs_source_dir,
+    )
+    .with_context(|| {
         format!(
-            "applying installed boot product additive producers for '{}'",
+            "adding kernel headers to installed boot rootfs for '{}'",
             spec.distro_id
         )
     })?;
+    if let Some(selinux) = SelinuxRelabel::load_for_variant(&variant_dir)
+        .with_context(|| format!("loading SELinux config for '{}'", spec.distro_id))?
+    {
+        selinux.relabel(&rootfs_source_dir).with_context(|| {
+            format!("relabeling installed boot rootfs for '{}'", spec.distro_id)
+        })?;
+    }
 
     let live_overlay_dir = create_empty_overlay_dir(output_dir, &spec.live_overlay_dir_name)
         .with_context(|| {
@@ -777,16 +788,28 @@ preseed_recipe_script = "distro-builder/recipes/fedora-preseed-iso.rhai"
 
     #[test]
     fn base_rootfs_baseline_contains_os_release_files() {
-        let producers = build_baseline_producers("levitate", "LevitateOS", "levitateos");
-        let paths: Vec<PathBuf> = producers
+        let branding = Branding::new("LevitateOS", "levitateos").version("2026.1");
+        let producers = build_baseline_producers("levitate", &branding);
+        let files: Vec<(PathBuf, &str)> = producers
             .iter()
             .filter_map(|p| match p {
-                RootfsProducer::WriteText { path, .. } => Some(path.clone()),
+                RootfsProducer::WriteText { path, content, .. } => {
+                    Some((path.clone(), content.as_str()))
+                }
                 _ => None,
             })
             .collect();
-        assert!(paths.contains(&PathBuf::from("etc/os-release")));
-        assert!(paths.contains(&PathBuf::from("usr/lib/product-manifest.json")));
+        let content = |path: &str| {
+            files
+                .iter()
+                .find(|(p, _)| p == Path::new(path))
+                .map(|(_, content)| *content)
+                .unwrap_or_else(|| panic!("baseline does not write {}", path))
+        };
+        assert!(content("etc/os-release").contains("PRETTY_NAME=\"LevitateOS 2026.1\"\n"));
+        assert!(content("etc/lsb-release").contains("DISTRIB_RELEASE=2026.1\n"));
+        assert!(content("etc/issue").contains("LevitateOS 2026.1"));
+        content("usr/lib/product-manifest.json");
     }
 
     #[test]
@@ -940,6 +963,7 @@ required_services = ["sshd"]
             repo_root: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
             distro_id: "levitate".to_string(),
             os_name: "LevitateOS".to_string(),
+            os_version: "0.1.0".to_string(),
             rootfs_source_dir: PathBuf::from("rootfs-source"),
             parent_rootfs: unresolved_parent_rootfs(),
             resolved_parent_rootfs_image: None,
@@ -984,6 +1008,8 @@ required_services = ["sshd"]
             repo_root: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
             distro_id: "levitate".to_string(),
             os_name: "LevitateOS".to_string(),
+            os_id: "levitateos".to_string(),
+            os_version: "0.1.0".to_string(),
             install_experience: InstallExperience::Ux,
             runtime_actions: Vec::new(),
             rootfs_source_dir: PathBuf::from("rootfs-source"),
@@ -0,0 +1,547 @@
+//! Release artifact publication to, and restore from, the artifact store.
+//!
+//! A successful release run publishes its ISO, rootfs EROFS and live
+//! initramfs under a release input key. The key covers everything that
+//! feeds a release: the variant tree, the shared ring0 helpers, the builder
+//! recipes, the kernel and rootfs source recipes with their defines, the
+//! product chain from `base-rootfs` down to the product, the resolved
+//! feature flags (`build-context.toml` plus `--feature`), and a digest of the
+//! running builder binary, so a rebuilt builder never restores stale output.
+//! A downstream product whose parent run directory is gone (pruned, fresh
+//! checkout with a warm store, CI cache) recomputes the parent's key and
+//! restores the parent into a new run directory instead of rebuilding it.
+//!
+//! Published artifacts are also tagged `<distro>-latest`, and
+//! `<distro>-<channel>` for each channel in [`RELEASE_CHANNEL_ENV`], so
+//! [`resolve_release`] can answer "the latest levitate live-boot ISO".
+
+use anyhow::{Context, Result};
+use distro_contract::ConformanceContract;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use walkdir::WalkDir;
+
+use crate::artifact_store::{
+    read_input_key_file, ArtifactStore, StoredArtifact, DEFAULT_OUTPUT_SUBDIR,
+};
+use crate::build::context::load_variant_features;
+use crate::pipeline::planner::plan_product_build_chain;
+use crate::pipeline::source::{rootfs_source_policy_from_contract, RootfsSourcePolicy};
+use crate::repo_layout::RepoLayout;
+use crate::run_history::{
+    allocate_run_dir, run_manifest_path, write_run_metadata, RunMetadata, RunStatus,
+};
+
+/// File in a release run directory recording the run's input key.
+pub const RELEASE_INPUT_KEY_FILENAME: &str = ".release-inputs.hash";
+
+/// Store index entries kept per product and artifact role.
+pub const RELEASE_STORE_RETENTION: usize = 3;
+
+/// Comma-separated channels (e.g. `nightly`) to tag published releases
+/// with, besides [`LATEST_RELEASE_CHANNEL`].
+pub const RELEASE_CHANNEL_ENV: &str = "DISTRO_BUILDER_RELEASE_CHANNEL";
+
+/// Channel every published release is tagged with.
+pub const LATEST_RELEASE_CHANNEL: &str = "latest";
+
+/// Artifact roles published for a release run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReleaseArtifactRole {
+    Iso,
+    Rootfs,
+    Initramfs,
+}
+
+impl ReleaseArtifactRole {
+    pub const ALL: [Self; 3] = [Self::Iso, Self::Rootfs, Self::Initramfs];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Iso => "iso",
+            Self::Rootfs => "rootfs",
+            Self::Initramfs => "initramfs",
+        }
+    }
+
+    /// Store kind, e.g. `release-live-boot-rootfs`.
+    pub fn store_kind(self, product: &str) -> String {
+        format!("release-{}-{}", product, self.as_str())
+    }
+}
+
+/// A release run's files by role, as file names inside the run directory.
+#[derive(Debug, Clone, Default)]
+pub struct ReleaseArtifactFiles {
+    pub iso: Option<String>,
+    pub rootfs: Option<String>,
+    pub initramfs: Option<String>,
+}
+
+impl ReleaseArtifactFiles {
+    fn get(&self, role: ReleaseArtifactRole) -> Option<&str> {
+        match role {
+            ReleaseArtifactRole::Iso => self.iso.as_deref(),
+            ReleaseArtifactRole::Rootfs => self.rootfs.as_deref(),
+            ReleaseArtifactRole::Initramfs => self.initramfs.as_deref(),
+        }
+    }
+}
+
+/// Input key for `product` on `distro_id`.
+pub fn release_input_key(
+    repo_root: &Path,
+    distro_id: &str,
+    contract: &ConformanceContract,
+    product: &str,
+) -> Result<String> {
+    let chain = plan_product_build_chain(contract, product)?.ordered_products;
+    let layout = RepoLayout::at(repo_root)?;
+    let variants = layout.variants_dir();
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"release-inputs-v3\0");
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(b"\0");
+    hasher.update(builder_revision()?.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(distro_id.as_bytes());
+    for product in &chain {
+        hasher.update(b"\0product\0");
+        hasher.update(product.as_bytes());
+    }
+    // Flags can come from the environment, so the variant tree alone does
+    // not determine them; `Features` iterates in sorted order.
+    let features = load_variant_features(&layout.variant_dir(distro_id))
+        .with_context(|| format!("loading feature flags for '{}'", distro_id))?;
+    for feature in features.iter() {
+        hasher.update(b"\0feature\0");
+        hasher.update(feature.as_bytes());
+    }
+    hash_tree(&mut hasher, &variants.join(distro_id))?;
+    hash_tree(&mut hasher, &variants.join("_shared"))?;
+    hasher.update(b"\0recipes\0");
+    hash_tree(&mut hasher, layout.recipes_dir())?;
+    hash_named_file(
+        &mut hasher,
+        "kernel-recipe",
+        &layout.resolve(&contract.build.kernel.recipe_script),
+    )?;
+    hash_rootfs_source(&mut hasher, repo_root, contract)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Digest of the running builder executable. Two builders with the same
+/// package version but different code produce different release keys.
+fn builder_revision() -> Result<&'static str> {
+    static REVISION: OnceLock<String> = OnceLock::new();
+    if let Some(revision) = REVISION.get() {
+        return Ok(revision);
+    }
+    let exe = std::env::current_exe().context("resolving builder executable path")?;
+    let mut hasher = Sha256::new();
+    hash_file_contents(&mut hasher, &exe)?;
+    Ok(REVISION.get_or_init(|| format!("{:x}", hasher.finalize())))
+}
+
+/// Feed the rootfs source kind, its recipe scripts and its defines.
+fn hash_rootfs_source(
+    hasher: &mut Sha256,
+    repo_root: &Path,
+    contract: &ConformanceContract,
+) -> Result<()> {
+    match rootfs_source_policy_from_contract(repo_root, contract)? {
+        Some(RootfsSourcePolicy::RecipeRpmDvd {
+            recipe_script,
+            preseed_recipe_script,
+        }) => {
+            hasher.update(b"\0rootfs-source\0recipe_rpm_dvd");
+            hash_named_file(hasher, "rootfs-recipe", &recipe_script)?;
+            hash_named_file(hasher, "preseed-recipe", &preseed_recipe_script)?;
+        }
+        Some(RootfsSourcePolicy::RecipeCustom {
+            recipe_script,
+            defines,
+        }) => {
+            hasher.update(b"\0rootfs-source\0recipe_custom");
+            hash_named_file(hasher, "rootfs-recipe", &recipe_script)?;
+            for (name, value) in &defines {
+                hasher.update(b"\0define\0");
+                hasher.update(name.as_bytes());
+                hasher.update(b"=");
+                hasher.update(value.as_bytes());
+            }
+        }
+        None => hasher.update(b"\0rootfs-source\0none"),
+    }
+    Ok(())
+}
+
+/// Feed `path`'s contents under `label`. A missing file is hashed as such,
+/// so the key still changes once it appears.
+fn hash_named_file(hasher: &mut Sha256, label: &str, path: &Path) -> Result<()> {
+    hasher.update(b"\0");
+    hasher.update(label.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .as_bytes(),
+    );
+    if path.is_file() {
+        hasher.update(b"\0file\0");
+        hash_file_contents(hasher, path)
+    } else {
+        hasher.update(b"\0missing");
+        Ok(())
+    }
+}
+
+fn hash_file_contents(hasher: &mut Sha256, path: &Path) -> Result<()> {
+    let mut file = fs::File::open(path).with_context(|| format!("opening '{}'", path.display()))?;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .with_context(|| format!("reading '{}'", path.display()))?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// Feed relative paths, modes and contents of every file under `root` into
+/// `hasher` in a stable order. A missing `root` contributes nothing.
+fn hash_tree(hasher: &mut Sha256, root: &Path) -> Result<()> {
+    if !root.is_dir() {
+        return Ok(());
+    }
+    let walker = WalkDir::new(root).sort_by_file_name().follow_links(false);
+    for entry in walker {
+        let entry = entry.with_context(|| format!("walking '{}'", root.display()))?;
+        let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if entry.file_type().is_symlink() {
+            let target = fs::read_link(entry.path())?;
+            hasher.update(b"\0link\0");
+            hasher.update(rel.to_string_lossy().as_bytes());
+            hasher.update(b"\0");
+            hasher.update(target.to_string_lossy().as_bytes());
+        } else if entry.file_type().is_file() {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = entry.metadata()?.permissions().mode() & 0o777;
+            hasher.update(b"\0file\0");
+            hasher.update(rel.to_string_lossy().as_bytes());
+            hasher.update(format!("\0{:o}\0", mode).as_bytes());
+            hash_file_contents(hasher, entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Store tag for `distro_id` on `channel`, e.g. `levitate-nightly`.
+pub fn release_tag(distro_id: &str, channel: &str) -> String {
+    format!("{}-{}", distro_id, channel)
+}
+
+/// Channels a release published now is tagged with.
+pub fn release_channels() -> Vec<String> {
+    let mut channels = vec![LATEST_RELEASE_CHANNEL.to_string()];
+    if let Some(value) = std::env::var_os(RELEASE_CHANNEL_ENV) {
+        for channel in value.to_string_lossy().split(',').map(str::trim) {
+            if !channel.is_empty() && !channels.iter().any(|c| c == channel) {
+                channels.push(channel.to_string());
+            }
+        }
+    }
+    channels
+}
+
+/// The stored `role` artifact of `product` last published for `distro_id`
+/// on `channel`.
+pub fn resolve_release(
+    store: &ArtifactStore,
+    distro_id: &str,
+    product: &str,
+    role: ReleaseArtifactRole,
+    channel: &str,
+) -> Result<Option<StoredArtifact>> {
+    store.resolve_tag(&role.store_kind(product), &release_tag(distro_id, channel))
+}
+
+/// Publish a successful release run's artifacts under `input_key`.
+///
+/// Also writes [`RELEASE_INPUT_KEY_FILENAME`] into `run_dir`, tags each
+/// artifact for [`release_channels`] and trims each kind to
+/// [`RELEASE_STORE_RETENTION`] untagged entries. Returns how many artifacts
+/// were stored.
+pub fn publish_release_artifacts(
+    store: &ArtifactStore,
+    distro_id: &str,
+    product: &str,
+    input_key: &str,
+    run_dir: &Path,
+    files: &ReleaseArtifactFiles,
+) -> Result<usize> {
+    let key_file = run_dir.join(RELEASE_INPUT_KEY_FILENAME);
+    fs::write(&key_file, format!("{}\n", input_key))
+        .with_context(|| format!("writing '{}'", key_file.display()))?;
+
+    let run_id = run_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let channels = release_channels();
+    let mut published = 0;
+    for role in ReleaseArtifactRole::ALL {
+        let Some(filename) = files.get(role) else {
+            continue;
+        };
+        let path = run_dir.join(filename);
+        if !path.is_file() {
+            continue;
+        }
+        let kind = role.store_kind(product);
+        let mut meta = BTreeMap::new();
+        meta.insert("distro_id".to_string(), serde_json::json!(distro_id));
+        meta.insert("product".to_string(), serde_json::json!(product));
+        meta.insert("run_id".to_string(), serde_json::json!(run_id));
+        meta.insert("filename".to_string(), serde_json::json!(filename));
+        store
+            .put_blob_file(&kind, input_key, &path, meta)
+            .with_context(|| format!("publishing '{}' as {}", path.display(), kind))?;
+        for channel in &channels {
+            store
+                .tag(&kind, input_key, &release_tag(distro_id, channel))
+                .with_context(|| format!("tagging {} for channel '{}'", kind, channel))?;
+        }
+        store.prune_kind_keep_last(&kind, RELEASE_STORE_RETENTION)?;
+        published += 1;
+    }
+    Ok(published)
+}
+
+/// Restore a release whose run directory is missing.
+///
+/// Materializes every stored role for `input_key` into a freshly allocated
+/// run directory under `product_root` and marks it successful, so the normal
+/// latest-successful-run lookup finds it. Returns `None` when the store has
+/// no rootfs for the key.
+pub fn restore_release_artifacts(
+    store: &ArtifactStore,
+    distro_id: &str,
+    product_root: &Path,
+    product: &str,
+    input_key: &str,
+) -> Result<Option<PathBuf>> {
+    if store
+        .get(&ReleaseArtifactRole::Rootfs.store_kind(product), input_key)?
+        .is_none()
+    {
+        return Ok(None);
+    }
+
+    let (run_id, run_dir) = allocate_run_dir(product_root)?;
+    let restored = (|| -> Result<()> {
+        for role in ReleaseArtifactRole::ALL {
+            let kind = role.store_kind(product);
+            let Some(stored) = store.get(&kind, input_key)? else {
+                continue;
+            };
+            let filename = stored
+                .entry
+                .meta
+                .get("filename")
+                .and_then(|v| v.as_str())
+                .with_context(|| format!("store entry {}:{} has no filename", kind, input_key))?;
+            store.materialize_to(&kind, input_key, &run_dir.join(filename))?;
+        }
+        fs::write(
+            run_dir.join(RELEASE_INPUT_KEY_FILENAME),
+            format!("{}\n", input_key),
+        )?;
+        let mut manifest = RunMetadata::new(&run_id, RunStatus::Success, utc_compact_now());
+        manifest.distro_id = distro_id.to_string();
+        manifest.target_kind = "release-product".to_string();
+        manifest.target_name = product.to_string();
+        manifest.finished_at_utc = Some(manifest.created_at_utc.clone());
+        manifest.root_dir = product_root.display().to_string();
+        manifest.output_dir = run_dir.display().to_string();
+        manifest.extra.insert(
+            "restored_from_store".to_string(),
+            serde_json::json!(input_key),
+        );
+        write_run_metadata(&run_manifest_path(&run_dir), &manifest)?;
+        Ok(())
+    })();
+    if let Err(err) = restored {
+        let _ = fs::remove_dir_all(&run_dir);
+        return Err(err).with_context(|| {
+            format!(
+                "restoring release '{}' from the artifact store into '{}'",
+                product,
+                run_dir.display()
+            )
+        });
+    }
+    Ok(Some(run_dir))
+}
+
+/// [`restore_release_artifacts`] for `product` under its current
+/// [`release_input_key`], into the distro's release tree at
+/// `releases/<release_dir_name>`.
+pub fn restore_release_for_current_inputs(
+    repo_root: &Path,
+    distro_id: &str,
+    contract: &ConformanceContract,
+    product: &str,
+    release_dir_name: &str,
+) -> Result<Option<PathBuf>> {
+    let input_key =
+        release_input_key(repo_root, distro_id, contract, product).with_context(|| {
+            format!(
+                "computing release input key for '{}' on '{}'",
+                product, distro_id
+            )
+        })?;
+    let store = ArtifactStore::open(repo_root)?;
+    let product_root = crate::repo_config::artifacts_root(repo_root)?
+        .join(DEFAULT_OUTPUT_SUBDIR)
+        .join(distro_id)
+        .join("releases")
+        .join(release_dir_name);
+    restore_release_artifacts(&store, distro_id, &product_root, product, &input_key)
+}
+
+/// Input key recorded in a release run directory, if any.
+pub fn recorded_release_input_key(run_dir: &Path) -> Result<Option<String>> {
+    read_input_key_file(&run_dir.join(RELEASE_INPUT_KEY_FILENAME))
+}
+
+fn utc_compact_now() -> String {
+    let now = time::OffsetDateTime::now_utc();
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_then_restore_into_new_run() {
+        let repo = tempfile::tempdir().expect("repo tempdir");
+        let store = ArtifactStore::open(repo.path()).expect("open store");
+        let product_root = repo
+            .path()
+            .join(".artifacts/out/levitate/releases/base-rootfs");
+        let run_dir = product_root.join("run-1");
+        fs::create_dir_all(&run_dir).expect("create run dir");
+        fs::write(run_dir.join("filesystem.erofs"), b"rootfs").expect("write rootfs");
+        fs::write(run_dir.join("levitate.iso"), b"iso").expect("write iso");
+
+        let files = ReleaseArtifactFiles {
+            iso: Some("levitate.iso".to_string()),
+            rootfs: Some("filesystem.erofs".to_string()),
+            initramfs: Some("initramfs-live.img".to_string()),
+        };
+        let key = "a".repeat(64);
+        let published =
+            publish_release_artifacts(&store, "levitate", "base-rootfs", &key, &run_dir, &files)
+                .expect("publish");
+        assert_eq!(published, 2);
+        let latest = resolve_release(
+            &store,
+            "levitate",
+            "base-rootfs",
+            ReleaseArtifactRole::Iso,
+            LATEST_RELEASE_CHANNEL,
+        )
+        .expect("resolve latest")
+        .expect("latest iso");
+        assert_eq!(latest.entry.input_key, key);
+        assert_eq!(
+            recorded_release_input_key(&run_dir).expect("read key"),
+            Some(key.clone())
+        );
+
+        fs::remove_dir_all(&product_root).expect("drop run dirs");
+        assert!(restore_release_artifacts(
+            &store,
+            "levitate",
+            &product_root,
+            "base-rootfs",
+            &"b".repeat(64)
+        )
+        .expect("restore unknown key")
+        .is_none());
+        let restored =
+            restore_release_artifacts(&store, "levitate", &product_root, "base-rootfs", &key)
+                .expect("restore")
+                .expect("stored release");
+        assert_eq!(
+            fs::read(restored.join("filesystem.erofs")).expect("read rootfs"),
+            b"rootfs"
+        );
+        let run_id = restored.file_name().unwrap().to_string_lossy().to_string();
+        assert_eq!(
+            crate::run_history::latest_successful_run_id(&product_root).expect("latest run"),
+            Some(run_id)
+        );
+    }
+
+    #[test]
+    fn input_key_tracks_recipes_and_rootfs_source() {
+        let workspace_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .canonicalize()
+            .expect("canonicalize workspace root");
+        let mut contract =
+            distro_contract::load_variant_contract_for_distro_from(&workspace_root, "acorn")
+                .expect("load acorn contract");
+        let repo = tempfile::tempdir().expect("repo tempdir");
+        let key = |contract: &ConformanceContract| {
+            release_input_key(repo.path(), "acorn", contract, "base-rootfs").expect("input key")
+        };
+
+        let base = key(&contract);
+        assert_eq!(base, key(&contract));
+
+        let recipes = RepoLayout::at(repo.path())
+            .expect("layout")
+            .recipes_dir()
+            .to_path_buf();
+        fs::create_dir_all(&recipes).expect("create recipes dir");
+        fs::write(recipes.join("helper.rhai"), b"// helper\n").expect("write helper recipe");
+        let with_helper = key(&contract);
+        assert_ne!(base, with_helper);
+
+        contract
+            .sources
+            .rootfs_source
+            .defines
+            .insert("EXTRA_PACKAGES".to_string(), "htop".to_string());
+        let with_define = key(&contract);
+        assert_ne!(with_helper, with_define);
+
+        let build_context = RepoLayout::at(repo.path())
+            .expect("layout")
+            .variant_dir("acorn")
+            .join(crate::build::context::BUILD_CONTEXT_FILENAME);
+        fs::create_dir_all(build_context.parent().unwrap()).expect("create variant dir");
+        fs::write(&build_context, "features = [\"wifi\"]\n").expect("write build context");
+        assert_ne!(with_define, key(&contract));
+    }
+}
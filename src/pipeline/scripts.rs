@@ -1,7 +1,26 @@
 use anyhow::{bail, Context, Result};
+use serde::Serialize;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::cache::hash_file;
+use crate::guest_protocol::{shell_library, PROTOCOL_VERSION};
+
+/// Bundle manifest, relative to the rootfs.
+pub(crate) const SCENARIO_MANIFEST_PATH: &str = "usr/local/lib/scenario-tests/manifest.json";
+
+#[derive(Debug, Serialize)]
+struct BundleManifest {
+    protocol_version: u32,
+    files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Serialize)]
+struct BundleFile {
+    path: String,
+    sha256: String,
+}
 
 pub(crate) fn install_scenario_test_scripts(
     repo_root: &Path,
@@ -22,6 +41,7 @@ pub(crate) fn install_scenario_test_scripts(
     fs::create_dir_all(&lib_dst)
         .with_context(|| format!("creating scenario scripts lib dir '{}'", lib_dst.display()))?;
 
+    let mut installed: Vec<PathBuf> = Vec::new();
     let entries = fs::read_dir(&scripts_src)
         .with_context(|| format!("reading scenario scripts dir '{}'", scripts_src.display()))?;
     for entry in entries {
@@ -49,6 +69,7 @@ pub(crate) fn install_scenario_test_scripts(
             perms.set_mode(0o755);
             fs::set_permissions(&dest, perms)
                 .with_context(|| format!("setting permissions '{}'", dest.display()))?;
+            installed.push(dest);
         }
     }
 
@@ -73,14 +94,49 @@ pub(crate) fn install_scenario_test_scripts(
     perms.set_mode(0o644);
     fs::set_permissions(&common_dst, perms)
         .with_context(|| format!("setting permissions '{}'", common_dst.display()))?;
+    installed.push(common_dst);
 
-    Ok(())
+    let protocol_dst = lib_dst.join("protocol.sh");
+    fs::write(&protocol_dst, shell_library()).with_context(|| {
+        format!(
+            "writing marker protocol library '{}'",
+            protocol_dst.display()
+        )
+    })?;
+    installed.push(protocol_dst);
+
+    write_bundle_manifest(rootfs_source_dir, installed)
+}
+
+fn write_bundle_manifest(rootfs_source_dir: &Path, mut installed: Vec<PathBuf>) -> Result<()> {
+    installed.sort();
+    let mut files = Vec::with_capacity(installed.len());
+    for path in installed {
+        files.push(BundleFile {
+            path: path
+                .strip_prefix(rootfs_source_dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string(),
+            sha256: hash_file(&path)?,
+        });
+    }
+    let manifest = BundleManifest {
+        protocol_version: PROTOCOL_VERSION,
+        files,
+    };
+    let manifest_path = rootfs_source_dir.join(SCENARIO_MANIFEST_PATH);
+    fs::write(&manifest_path, serde_json::to_vec_pretty(&manifest)?).with_context(|| {
+        format!(
+            "writing scenario bundle manifest '{}'",
+            manifest_path.display()
+        )
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     fn workspace_root() -> PathBuf {
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -122,5 +178,16 @@ mod tests {
                 .is_file(),
             "expected shared scenario common library to be installed"
         );
+
+        let manifest: serde_json::Value = serde_json::from_slice(
+            &fs::read(rootfs_dir.path().join(SCENARIO_MANIFEST_PATH)).expect("read manifest"),
+        )
+        .expect("parse manifest");
+        assert_eq!(manifest["protocol_version"], PROTOCOL_VERSION);
+        assert!(manifest["files"]
+            .as_array()
+            .expect("files array")
+            .iter()
+            .any(|f| f["path"] == "usr/local/lib/scenario-tests/protocol.sh"));
     }
 }
@@ -9,9 +9,17 @@ use std::path::{Path, PathBuf};
 #[cfg(test)]
 use serde::Deserialize;
 
-use crate::pipeline::paths::{normalize_distro_id, resolve_repo_path};
+use crate::artifact_store::ArtifactStore;
+use crate::pipeline::alpine_share::{
+    publish_alpine_rootfs, restore_alpine_rootfs, AlpineRootfsKey,
+};
+use crate::pipeline::paths::resolve_repo_path;
 use crate::pipeline::plan::ensure_non_legacy_rootfs_source;
+use crate::recipe::alpine_rootfs_source::is_alpine_rootfs_source_recipe;
 use crate::recipe::rootfs_source::{materialize_rootfs_from_recipe, RootfsSourceRecipeSpec};
+#[cfg(test)]
+use crate::workspace::DOWNLOADS_NAMESPACE;
+use crate::workspace::{WorkspaceManager, ROOTFS_SOURCE_PROVIDER_NAMESPACE};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum RootfsSourcePolicy {
@@ -212,6 +220,10 @@ pub(crate) fn materialize_source_rootfs(
                     build_dir.display()
                 )
             })?;
+            let share_key = alpine_share_key(recipe_script);
+            if let Some(key) = &share_key {
+                restore_shared_alpine_rootfs(repo_root, &build_dir.join("rootfs"), key);
+            }
             let source_rootfs_dir = materialize_rootfs_from_recipe(
                 repo_root,
                 &build_dir,
@@ -227,6 +239,9 @@ pub(crate) fn materialize_source_rootfs(
                 )
             })?;
             ensure_non_legacy_rootfs_source(&source_rootfs_dir)?;
+            if let Some(key) = &share_key {
+                publish_shared_alpine_rootfs(repo_root, distro_id, &source_rootfs_dir, key);
+            }
             Ok(source_rootfs_dir)
         }
         None => bail!(
@@ -236,6 +251,53 @@ pub(crate) fn materialize_source_rootfs(
     }
 }
 
+/// Share key for the Alpine source recipe; `None` for other recipes, or
+/// when the key cannot be derived.
+fn alpine_share_key(recipe_script: &Path) -> Option<AlpineRootfsKey> {
+    if !is_alpine_rootfs_source_recipe(recipe_script) {
+        return None;
+    }
+    AlpineRootfsKey::for_recipe(recipe_script)
+        .map_err(|err| eprintln!("  [WARN] {:#}; Alpine rootfs will not be shared", err))
+        .ok()
+}
+
+/// Restore a rootfs another distro published. Failures only cost the
+/// rebuild, so they are warnings.
+fn restore_shared_alpine_rootfs(repo_root: &Path, rootfs_dir: &Path, key: &AlpineRootfsKey) {
+    let restored = ArtifactStore::open(repo_root)
+        .and_then(|store| restore_alpine_rootfs(&store, rootfs_dir, key));
+    match restored {
+        Ok(Some(publisher)) => println!(
+            "  Restored shared Alpine {} {} rootfs built by '{}'",
+            key.alpine_version, key.arch, publisher
+        ),
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("  [WARN] failed to restore shared Alpine rootfs: {:#}", err);
+            let _ = fs::remove_dir_all(rootfs_dir);
+        }
+    }
+}
+
+fn publish_shared_alpine_rootfs(
+    repo_root: &Path,
+    distro_id: &str,
+    rootfs_dir: &Path,
+    key: &AlpineRootfsKey,
+) {
+    let published = ArtifactStore::open(repo_root)
+        .and_then(|store| publish_alpine_rootfs(&store, distro_id, rootfs_dir, key));
+    match published {
+        Ok(true) => println!(
+            "  Published Alpine {} {} rootfs for sharing with other distros",
+            key.alpine_version, key.arch
+        ),
+        Ok(false) => {}
+        Err(err) => eprintln!("  [WARN] failed to publish shared Alpine rootfs: {:#}", err),
+    }
+}
+
 pub(crate) fn cleanup_legacy_provider_dir(output_dir: &Path) -> Result<()> {
     let entries = fs::read_dir(output_dir)
         .with_context(|| format!("reading output directory '{}'", output_dir.display()))?;
@@ -263,18 +325,7 @@ pub(crate) fn cleanup_legacy_provider_dir(output_dir: &Path) -> Result<()> {
 }
 
 fn rootfs_source_provider_work_dir(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
-    let normalized = normalize_distro_id(distro_id, "rootfs source provider work directory")?;
-    let provider_dir = repo_root
-        .join(".artifacts/work")
-        .join(normalized)
-        .join("rootfs-source-provider");
-    fs::create_dir_all(&provider_dir).with_context(|| {
-        format!(
-            "creating rootfs source provider work directory '{}'",
-            provider_dir.display()
-        )
-    })?;
-    Ok(provider_dir)
+    WorkspaceManager::new(repo_root)?.persistent_dir(distro_id, ROOTFS_SOURCE_PROVIDER_NAMESPACE)
 }
 
 fn rootfs_source_provider_recipe_work_dir(
@@ -292,18 +343,7 @@ fn rootfs_source_provider_recipe_work_dir(
 
 #[cfg(test)]
 fn downloads_work_dir(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
-    let normalized = normalize_distro_id(distro_id, "work downloads directory")?;
-    let downloads = repo_root
-        .join(".artifacts/work")
-        .join(normalized)
-        .join("downloads");
-    fs::create_dir_all(&downloads).with_context(|| {
-        format!(
-            "creating rootfs source work downloads directory '{}'",
-            downloads.display()
-        )
-    })?;
-    Ok(downloads)
+    WorkspaceManager::new(repo_root)?.persistent_dir(distro_id, DOWNLOADS_NAMESPACE)
 }
 
 #[cfg(test)]
@@ -0,0 +1,417 @@
+//! Input handling shared by the live stages.
+//!
+//! Stage 01 (live boot) and Stage 02 (live tools) both start from a parent
+//! release rootfs, apply the stage's additive producers, and generate a live
+//! overlay under the same overlay policy, locale checks and service wiring.
+//! [`StageInputEngine`] runs that sequence once, parameterized by
+//! [`LiveStage`]; a stage only supplies its spec and, through
+//! [`StageInputEngine::prepare`], the steps that are its own.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assets::AssetResolver;
+use crate::build::accessibility::Accessibility;
+use crate::build::banner::{load_motd_template, BuildMetadata};
+use crate::build::consoles::SerialConsoles;
+use crate::build::context::{load_variant_banner_build_id, load_variant_features};
+use crate::build::first_boot::FirstBootService;
+use crate::build::locales::LocalePolicy;
+use crate::build::selinux::SelinuxRelabel;
+use crate::build::services::{disable_openrc_services, ServicePolicy};
+use crate::build::splash::SplashConfig;
+use crate::build::swap::LiveSwap;
+use crate::build::timesync::TimeSync;
+use crate::build::writable_layer::WritableLayer;
+use crate::contracts::context::Features;
+use crate::executor::openrc;
+use crate::pipeline::io::{create_unique_output_dir, extract_erofs_rootfs};
+use crate::pipeline::overlay::{
+    apply_feature_trees, create_live_overlay, ensure_openrc_shell, ensure_required_service_wiring,
+    ensure_systemd_default_target, ensure_systemd_locale_completeness, ensure_systemd_sshd_dirs,
+    BootOverlayPolicy, FEATURE_OVERLAY_DIR, FEATURE_ROOTFS_DIR,
+};
+use crate::pipeline::plan::{apply_producer_plan, ProducerPlan, RootfsProducer};
+use crate::pipeline::products::OverlayLayout;
+use crate::pipeline::scripts::install_scenario_test_scripts;
+use crate::pipeline::source::{
+    cleanup_legacy_provider_dir, materialize_source_rootfs, RootfsSourcePolicy,
+};
+use crate::pstore::Pstore;
+
+/// A stage whose product is a bootable live image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LiveStage {
+    /// Stage 01: `live-boot`.
+    Boot,
+    /// Stage 02: `live-tools`.
+    Tools,
+}
+
+impl LiveStage {
+    /// Label used in progress and error messages.
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Boot => "live boot",
+            Self::Tools => "live tools",
+        }
+    }
+}
+
+/// What a stage contributes on top of its parent rootfs.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StageInputEngine<'a> {
+    pub(crate) stage: LiveStage,
+    pub(crate) repo_root: &'a Path,
+    pub(crate) distro_id: &'a str,
+    pub(crate) os_name: &'a str,
+    pub(crate) os_version: &'a str,
+    pub(crate) add_plan: &'a ProducerPlan,
+    pub(crate) rootfs_source_policy: &'a Option<RootfsSourcePolicy>,
+    pub(crate) live_overlay: &'a OverlayLayout,
+    pub(crate) overlay: &'a BootOverlayPolicy,
+    pub(crate) required_services: &'a [String],
+}
+
+/// Directories a stage hands to the ISO transforms.
+#[derive(Debug, Clone)]
+pub(crate) struct StageInputs {
+    pub(crate) rootfs_source_dir: PathBuf,
+    pub(crate) live_overlay_dir: PathBuf,
+}
+
+impl StageInputEngine<'_> {
+    /// Run the whole stage: [`prepare_rootfs`](Self::prepare_rootfs),
+    /// [`prepare_live_overlay`](Self::prepare_live_overlay), then
+    /// `extend(rootfs, overlay)` for the stage's own steps, then the
+    /// required service wiring and, for variants with `selinux.toml`, the
+    /// relabel of both trees.
+    pub(crate) fn prepare(
+        &self,
+        parent_rootfs: &Path,
+        output_dir: &Path,
+        rootfs_dir_name: &Path,
+        extend: impl FnOnce(&Path, &Path) -> Result<()>,
+    ) -> Result<StageInputs> {
+        let rootfs_source_dir = self.prepare_rootfs(parent_rootfs, output_dir, rootfs_dir_name)?;
+        let live_overlay_dir = self.prepare_live_overlay(&rootfs_source_dir, output_dir)?;
+        extend(&rootfs_source_dir, &live_overlay_dir)?;
+        ensure_required_service_wiring(&live_overlay_dir, self.overlay, self.required_services)
+            .with_context(|| {
+                format!(
+                    "ensuring {} required service wiring for '{}'",
+                    self.stage.label(),
+                    self.distro_id
+                )
+            })?;
+        if let Some(selinux) = SelinuxRelabel::load_for_variant(&self.variant_dir())
+            .with_context(|| format!("loading SELinux config for '{}'", self.distro_id))?
+        {
+            for tree in [&rootfs_source_dir, &live_overlay_dir] {
+                selinux
+                    .relabel_tree(&rootfs_source_dir, tree)
+                    .with_context(|| {
+                        format!(
+                            "relabeling {} tree '{}' for '{}'",
+                            self.stage.label(),
+                            tree.display(),
+                            self.distro_id
+                        )
+                    })?;
+            }
+        }
+        Ok(StageInputs {
+            rootfs_source_dir,
+            live_overlay_dir,
+        })
+    }
+
+    /// Extract `parent_rootfs` into a fresh directory under `output_dir`
+    /// and apply the stage's producers, splash, feature trees, scenario
+    /// test scripts and first-boot service.
+    pub(crate) fn prepare_rootfs(
+        &self,
+        parent_rootfs: &Path,
+        output_dir: &Path,
+        rootfs_dir_name: &Path,
+    ) -> Result<PathBuf> {
+        let label = self.stage.label();
+        fs::create_dir_all(output_dir).with_context(|| {
+            format!(
+                "creating {} product output directory '{}'",
+                label,
+                output_dir.display()
+            )
+        })?;
+
+        let rootfs_source_dir = create_unique_output_dir(output_dir, rootfs_dir_name)?;
+        cleanup_legacy_provider_dir(output_dir).with_context(|| {
+            format!(
+                "cleaning legacy {} provider directory under '{}'",
+                label,
+                output_dir.display()
+            )
+        })?;
+        extract_erofs_rootfs(parent_rootfs, &rootfs_source_dir).with_context(|| {
+            format!(
+                "extracting parent rootfs for {} product from '{}'",
+                label,
+                parent_rootfs.display()
+            )
+        })?;
+
+        apply_additive_producers(
+            self.add_plan,
+            self.rootfs_source_policy,
+            self.repo_root,
+            self.distro_id,
+            &rootfs_source_dir,
+        )
+        .with_context(|| {
+            format!(
+                "applying {} product additive producers for '{}'",
+                label, self.distro_id
+            )
+        })?;
+
+        let variant_dir = self.variant_dir();
+        if let Some(splash) = SplashConfig::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading splash config for '{}'", self.distro_id))?
+        {
+            splash
+                .install_into_rootfs(&rootfs_source_dir)
+                .with_context(|| format!("installing boot splash for '{}'", self.distro_id))?;
+        }
+        if let Some(timesync) = TimeSync::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading time sync config for '{}'", self.distro_id))?
+        {
+            timesync
+                .install_into_rootfs(&rootfs_source_dir)
+                .with_context(|| format!("configuring time sync for '{}'", self.distro_id))?;
+        }
+        let features = self.features()?;
+        apply_feature_trees(
+            &variant_dir,
+            FEATURE_ROOTFS_DIR,
+            &features,
+            &rootfs_source_dir,
+        )
+        .with_context(|| format!("applying feature rootfs trees for '{}'", self.distro_id))?;
+        install_scenario_test_scripts(self.repo_root, &rootfs_source_dir).with_context(|| {
+            format!(
+                "installing scenario test scripts into {} rootfs for '{}'",
+                label, self.distro_id
+            )
+        })?;
+        // Last, after the producers and feature trees add their packages.
+        if let Some(first_boot) = FirstBootService::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading first-boot config for '{}'", self.distro_id))?
+        {
+            first_boot
+                .install_into_rootfs(&rootfs_source_dir)
+                .with_context(|| {
+                    format!("installing first-boot service for '{}'", self.distro_id)
+                })?;
+        }
+        Ok(rootfs_source_dir)
+    }
+
+    /// Apply the init-system fixes to `rootfs_source_dir` and generate the
+    /// stage's live overlay under `output_dir`.
+    pub(crate) fn prepare_live_overlay(
+        &self,
+        rootfs_source_dir: &Path,
+        output_dir: &Path,
+    ) -> Result<PathBuf> {
+        let label = self.stage.label();
+        let variant_dir = self.variant_dir();
+        let locales = LocalePolicy::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading locale policy for '{}'", self.distro_id))?;
+        locales
+            .trim(rootfs_source_dir)
+            .with_context(|| format!("trimming locale payload for '{}'", self.distro_id))?;
+        let mut accessibility = Accessibility::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading accessibility config for '{}'", self.distro_id))?;
+        if let Some(accessibility) = &mut accessibility {
+            accessibility.retain_shipped(rootfs_source_dir);
+        }
+        let mut swap = LiveSwap::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading swap config for '{}'", self.distro_id))?;
+        if let Some(swap) = &mut swap {
+            swap.detect_generator(rootfs_source_dir);
+        }
+        let consoles = SerialConsoles::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading console config for '{}'", self.distro_id))?
+            .unwrap_or_default();
+        let build = self.build_metadata(output_dir, self.features()?)?;
+        let motd_template = load_motd_template(&variant_dir)
+            .with_context(|| format!("loading motd template for '{}'", self.distro_id))?;
+        let services = ServicePolicy::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading service policy for '{}'", self.distro_id))?;
+        services
+            .check(
+                matches!(self.overlay, BootOverlayPolicy::Systemd { .. }),
+                self.required_services,
+            )
+            .with_context(|| format!("checking service policy for '{}'", self.distro_id))?;
+
+        match self.overlay {
+            BootOverlayPolicy::OpenRc { inittab, .. } => {
+                ensure_openrc_shell(rootfs_source_dir, &build, *inittab, &consoles).with_context(
+                    || {
+                        format!(
+                            "ensuring OpenRC {} serial shell for '{}'",
+                            label, self.distro_id
+                        )
+                    },
+                )?;
+                for removed in
+                    disable_openrc_services(rootfs_source_dir, &services.disabled_services())
+                        .with_context(|| {
+                            format!(
+                                "disabling OpenRC services in {} rootfs for '{}'",
+                                label, self.distro_id
+                            )
+                        })?
+                {
+                    println!("  Disabled {}", removed);
+                }
+                openrc::validate_init_scripts(rootfs_source_dir).with_context(|| {
+                    format!(
+                        "checking OpenRC {} init scripts for '{}'",
+                        label, self.distro_id
+                    )
+                })?;
+                openrc::validate_default_runlevel(rootfs_source_dir).with_context(|| {
+                    format!(
+                        "checking OpenRC {} default runlevel for '{}'",
+                        label, self.distro_id
+                    )
+                })?;
+            }
+            BootOverlayPolicy::Systemd { .. } => {
+                ensure_systemd_default_target(rootfs_source_dir).with_context(|| {
+                    format!(
+                        "ensuring systemd {} default target for '{}'",
+                        label, self.distro_id
+                    )
+                })?;
+                ensure_systemd_sshd_dirs(rootfs_source_dir).with_context(|| {
+                    format!(
+                        "ensuring systemd {} sshd directories for '{}'",
+                        label, self.distro_id
+                    )
+                })?;
+                ensure_systemd_locale_completeness(rootfs_source_dir, &locales).with_context(
+                    || {
+                        format!(
+                            "ensuring systemd {} locale completeness for '{}'",
+                            label, self.distro_id
+                        )
+                    },
+                )?;
+            }
+        }
+
+        let live_overlay_dir = create_live_overlay(
+            output_dir,
+            self.distro_id,
+            &build,
+            &self.live_overlay.dir_name,
+            self.overlay,
+            &AssetResolver::for_variant(self.repo_root, self.distro_id),
+            accessibility.as_ref(),
+            motd_template.as_deref(),
+            &services,
+            swap.as_ref(),
+            &consoles,
+        )?;
+        if let Some(layer) = WritableLayer::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading writable layer config for '{}'", self.distro_id))?
+        {
+            layer.apply_overlay(&live_overlay_dir).with_context(|| {
+                format!("applying writable layer settings for '{}'", self.distro_id)
+            })?;
+        }
+        if let Some(pstore) = Pstore::load_for_variant(&variant_dir)
+            .with_context(|| format!("loading pstore config for '{}'", self.distro_id))?
+        {
+            pstore
+                .apply_overlay(
+                    &live_overlay_dir,
+                    matches!(self.overlay, BootOverlayPolicy::OpenRc { .. }),
+                )
+                .with_context(|| format!("enabling ramoops for '{}'", self.distro_id))?;
+        }
+        apply_feature_trees(
+            &variant_dir,
+            FEATURE_OVERLAY_DIR,
+            &build.features,
+            &live_overlay_dir,
+        )
+        .with_context(|| format!("applying feature overlay trees for '{}'", self.distro_id))?;
+        if let BootOverlayPolicy::OpenRc { inittab, .. } = self.overlay {
+            ensure_openrc_shell(&live_overlay_dir, &build, *inittab, &consoles).with_context(
+                || {
+                    format!(
+                        "ensuring OpenRC {} overlay serial shell for '{}'",
+                        label, self.distro_id
+                    )
+                },
+            )?;
+        }
+        Ok(live_overlay_dir)
+    }
+
+    fn variant_dir(&self) -> PathBuf {
+        crate::repo_layout::layout_or_default(self.repo_root).variant_dir(self.distro_id)
+    }
+
+    fn features(&self) -> Result<Features> {
+        load_variant_features(&self.variant_dir())
+            .with_context(|| format!("loading feature flags for '{}'", self.distro_id))
+    }
+
+    /// What the live banners say about this build. The run id and commit
+    /// are only detected when the variant opts into them, since they would
+    /// make otherwise identical images differ.
+    fn build_metadata(&self, output_dir: &Path, features: Features) -> Result<BuildMetadata> {
+        let build = BuildMetadata::new(self.os_name)
+            .stage(&self.live_overlay.issue_banner_label)
+            .version(self.os_version)
+            .features(features);
+        let banner_build_id = load_variant_banner_build_id(&self.variant_dir())
+            .with_context(|| format!("loading banner settings for '{}'", self.distro_id))?;
+        Ok(if banner_build_id {
+            build.detect(self.repo_root, output_dir)
+        } else {
+            build
+        })
+    }
+}
+
+/// Apply `plan` to `rootfs_dir`, materializing the stage's source rootfs
+/// first when a producer copies from it.
+pub(crate) fn apply_additive_producers(
+    plan: &ProducerPlan,
+    rootfs_source_policy: &Option<RootfsSourcePolicy>,
+    repo_root: &Path,
+    distro_id: &str,
+    rootfs_dir: &Path,
+) -> Result<()> {
+    let mut plan = plan.clone();
+    if plan.producers.iter().any(|producer| {
+        matches!(
+            producer,
+            RootfsProducer::CopyTree { .. }
+                | RootfsProducer::CopySymlink { .. }
+                | RootfsProducer::CopyFile { .. }
+        )
+    }) {
+        let source_rootfs_dir =
+            materialize_source_rootfs(repo_root, distro_id, rootfs_source_policy)?;
+        plan.source_rootfs_dir = Some(source_rootfs_dir);
+    }
+    apply_producer_plan(&plan, rootfs_dir)
+}
@@ -0,0 +1,276 @@
+//! Legacy-binding policy audit.
+//!
+//! Variants must bind to the canonical owner-directory layout (see
+//! `docs/06`), never to the flat-root manifests, legacy ring filenames or
+//! per-distro crate downloads that predate it. The CLI runs this audit in
+//! process before every command, so deployed hosts without a cargo
+//! toolchain get the same guard; `distro-builder policy
+//! audit-legacy-bindings` lists every violation.
+
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::error::{ErrorKind, ResultExt};
+use crate::pipeline::plan::LEGACY_ROOTFS_COMPONENT_SEQUENCES;
+use crate::repo_layout::RepoLayout;
+
+/// Owner manifests that now live in owner directories, by their flat-root
+/// file names.
+const FLAT_ROOT_MANIFESTS: &[&str] = &[
+    "identity.toml",
+    "build-host.toml",
+    "sources.toml",
+    "products.toml",
+    "transforms.toml",
+    "release.toml",
+    "scenarios.toml",
+];
+
+/// Support files that belong under `build-host/` or `ring0/hooks/`.
+const FLAT_ROOT_SUPPORT: &[&str] = &[
+    "kconfig",
+    "build-capability.sh",
+    "build-release.sh",
+    "boot-release.sh",
+    "live-tools-release.sh",
+];
+
+/// Directories replaced by `ring3/` and `ring2/overlays/`.
+const FLAT_ROOT_DIRS: &[&str] = &["recipes", "profile"];
+
+/// Manifest keys removed from the contract.
+const LEGACY_KEYS: &[&str] = &["profile_overlay"];
+
+/// Text files scanned for references to legacy distro crate downloads.
+const SCANNED_EXTENSIONS: &[&str] = &["toml", "sh", "rhai"];
+
+/// What a [`PolicyViolation`] breaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyRule {
+    /// Owner manifest or support file at the variant root.
+    FlatRootLayout,
+    /// `ringN-*.toml` instead of `ringN/<owner>.toml`.
+    LegacyRingFilename,
+    /// Manifest key the contract no longer accepts.
+    LegacyKey,
+    /// Reference to `<legacy crate>/downloads/rootfs`.
+    LegacyCratePath,
+}
+
+impl PolicyRule {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::FlatRootLayout => "flat-root-layout",
+            Self::LegacyRingFilename => "legacy-ring-filename",
+            Self::LegacyKey => "legacy-key",
+            Self::LegacyCratePath => "legacy-crate-path",
+        }
+    }
+}
+
+/// One offending file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub rule: PolicyRule,
+    pub path: PathBuf,
+    pub detail: String,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {}: {}",
+            self.rule.as_str(),
+            self.path.display(),
+            self.detail
+        )
+    }
+}
+
+/// Audit every variant and the recipes of the checkout at `repo_root`.
+pub fn audit_legacy_bindings(repo_root: &Path) -> Result<Vec<PolicyViolation>> {
+    let layout = RepoLayout::at(repo_root)?;
+    let mut violations = Vec::new();
+
+    let variants_dir = layout.variants_dir();
+    if variants_dir.is_dir() {
+        let mut variants = fs::read_dir(variants_dir)
+            .with_context(|| format!("Failed to read {}", variants_dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>();
+        variants.sort();
+        for variant in &variants {
+            audit_variant_root(variant, &mut violations);
+            audit_tree(variant, &mut violations)?;
+        }
+    }
+    if layout.recipes_dir().is_dir() {
+        audit_tree(layout.recipes_dir(), &mut violations)?;
+    }
+    Ok(violations)
+}
+
+/// Fail with every violation listed when the checkout breaks the policy.
+pub fn enforce_legacy_binding_policy(repo_root: &Path) -> Result<()> {
+    let violations = audit_legacy_bindings(repo_root)?;
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let listed = violations
+        .iter()
+        .map(|violation| format!("  {}", violation))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Err(anyhow!(
+        "{} legacy-binding policy violation(s):\n{}",
+        violations.len(),
+        listed
+    ))
+    .error_kind(ErrorKind::PolicyViolation)
+}
+
+fn audit_variant_root(variant: &Path, violations: &mut Vec<PolicyViolation>) {
+    for name in FLAT_ROOT_MANIFESTS.iter().chain(FLAT_ROOT_SUPPORT) {
+        let path = variant.join(name);
+        if path.is_file() {
+            violations.push(PolicyViolation {
+                rule: PolicyRule::FlatRootLayout,
+                path,
+                detail: "move into its owner directory".to_string(),
+            });
+        }
+    }
+    for name in FLAT_ROOT_DIRS {
+        let path = variant.join(name);
+        if path.is_dir() {
+            violations.push(PolicyViolation {
+                rule: PolicyRule::FlatRootLayout,
+                path,
+                detail: "root-level directory replaced by ring3/ and ring2/overlays/".to_string(),
+            });
+        }
+    }
+}
+
+fn audit_tree(dir: &Path, violations: &mut Vec<PolicyViolation>) -> Result<()> {
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to walk {}", dir.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy();
+        if is_legacy_ring_filename(&name) {
+            violations.push(PolicyViolation {
+                rule: PolicyRule::LegacyRingFilename,
+                path: path.to_path_buf(),
+                detail: "use ringN/<owner>.toml".to_string(),
+            });
+        }
+
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if !extension.is_some_and(|ext| SCANNED_EXTENSIONS.contains(&ext)) {
+            continue;
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if extension == Some("toml") {
+            if let Ok(value) = content.parse::<toml::Value>() {
+                for key in LEGACY_KEYS {
+                    if contains_key(&value, key) {
+                        violations.push(PolicyViolation {
+                            rule: PolicyRule::LegacyKey,
+                            path: path.to_path_buf(),
+                            detail: format!("'{}' is no longer accepted", key),
+                        });
+                    }
+                }
+            }
+        }
+        let lowered = content.to_ascii_lowercase();
+        for sequence in LEGACY_ROOTFS_COMPONENT_SEQUENCES {
+            let needle = sequence.join("/");
+            if lowered.contains(&needle) {
+                violations.push(PolicyViolation {
+                    rule: PolicyRule::LegacyCratePath,
+                    path: path.to_path_buf(),
+                    detail: format!("references '{}'", needle),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_legacy_ring_filename(name: &str) -> bool {
+    let Some(stem) = name.strip_suffix(".toml") else {
+        return false;
+    };
+    let Some(rest) = stem.strip_prefix("ring") else {
+        return false;
+    };
+    let mut chars = rest.chars();
+    chars.next().is_some_and(|c| c.is_ascii_digit()) && chars.next() == Some('-')
+}
+
+fn contains_key(value: &toml::Value, key: &str) -> bool {
+    match value {
+        toml::Value::Table(table) => table
+            .iter()
+            .any(|(name, child)| name == key || contains_key(child, key)),
+        toml::Value::Array(items) => items.iter().any(|item| contains_key(item, key)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_audit_flags_legacy_bindings() {
+        let repo = TempDir::new().unwrap();
+        let variant = repo.path().join("distro-variants/oak");
+        fs::create_dir_all(variant.join("ring3")).unwrap();
+        fs::create_dir_all(variant.join("ring2")).unwrap();
+        fs::write(variant.join("ring3/sources.toml"), "[source]\n").unwrap();
+        fs::write(variant.join("smoke-test.toml"), "timeout_secs = 60\n").unwrap();
+        assert!(audit_legacy_bindings(repo.path()).unwrap().is_empty());
+        enforce_legacy_binding_policy(repo.path()).unwrap();
+
+        fs::write(variant.join("products.toml"), "").unwrap();
+        fs::write(variant.join("ring3/ring3-sources.toml"), "").unwrap();
+        fs::write(
+            variant.join("ring2/products.toml"),
+            "[live]\nprofile_overlay = \"profile/live\"\n",
+        )
+        .unwrap();
+        fs::write(
+            variant.join("ring3/fetch.sh"),
+            "cp ../leviso/downloads/rootfs/x .\n",
+        )
+        .unwrap();
+
+        let rules = audit_legacy_bindings(repo.path())
+            .unwrap()
+            .into_iter()
+            .map(|violation| violation.rule)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            vec![
+                PolicyRule::FlatRootLayout,
+                PolicyRule::LegacyKey,
+                PolicyRule::LegacyCratePath,
+                PolicyRule::LegacyRingFilename,
+            ]
+        );
+        assert!(enforce_legacy_binding_policy(repo.path()).is_err());
+    }
+}
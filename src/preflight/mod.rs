@@ -3,6 +3,19 @@
 //! Validates that the host system has required tools before building.
 //! This prevents cryptic errors during the build process.
 //!
+//! There is no fixed tool list: each subsystem registers the tools it will
+//! invoke for the selected configuration into a [`RequiredTools`] set (see
+//! `register_host_tools` in `artifact::rootfs`, `artifact::cpio`,
+//! `artifact::iso_utils`, `artifact::usb_image`, `artifact::disk::helpers`,
+//! `build::selinux` and `qemu`), and the
+//! derived set is checked once before the build starts. A disk-only build
+//! never asks for xorriso.
+//!
+//! Tools the configured tool container provides (see `tool_container`)
+//! count as present even when the host lacks them. A variant can add tools,
+//! optionally with a minimum version, in `host-tools.toml` (see
+//! [`VariantHostTools`]).
+//!
 //! # Example
 //!
 //! ```rust
@@ -20,9 +33,22 @@
 //! }
 //! ```
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
 use std::process::Command;
 
+use crate::compression::Compression;
+use crate::error::{ErrorKind, ResultExt};
+use crate::process::Cmd;
+use crate::tool_container;
+
+mod variant_tools;
+
+pub use variant_tools::{
+    VariantHostTool, VariantHostTools, HOST_TOOLS_FILENAME, VARIANT_SUBSYSTEM,
+};
+
 /// Check if a command exists on the host system.
 ///
 /// Uses `which` to locate the command in PATH.
@@ -34,18 +60,228 @@ pub fn command_exists(cmd: &str) -> bool {
         .unwrap_or(false)
 }
 
-/// Required host tools for building distribution ISOs.
-///
-/// Each tuple is (command_name, package_name).
-pub const REQUIRED_TOOLS: &[(&str, &str)] = &[
-    ("mkfs.erofs", "erofs-utils"),
-    ("xorriso", "xorriso"),
-    ("mkfs.fat", "dosfstools"),
-    ("mmd", "mtools"),
-    ("mcopy", "mtools"),
-    ("cpio", "cpio"),
-    ("gzip", "gzip"),
-];
+/// Whether `tool` can run: on the host or in the tool container.
+fn tool_available(tool: &str) -> bool {
+    command_exists(tool) || tool_container::provides(tool)
+}
+
+/// Host tools a build will invoke, registered by the subsystems it runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequiredTools {
+    /// Tool -> (package, subsystems that invoke it).
+    tools: BTreeMap<String, (String, BTreeSet<&'static str>)>,
+    /// Tool -> (minimum version, arguments that print the version).
+    min_versions: BTreeMap<String, (String, Vec<String>)>,
+}
+
+impl RequiredTools {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `subsystem` invokes `tool`, shipped by `package`.
+    pub fn require(
+        &mut self,
+        subsystem: &'static str,
+        tool: impl Into<String>,
+        package: impl Into<String>,
+    ) -> &mut Self {
+        let package = package.into();
+        self.tools
+            .entry(tool.into())
+            .or_insert_with(|| (package, BTreeSet::new()))
+            .1
+            .insert(subsystem);
+        self
+    }
+
+    /// [`require`](Self::require) each `(command, package)` pair.
+    pub fn require_all(&mut self, subsystem: &'static str, tools: &[(&str, &str)]) -> &mut Self {
+        for (tool, package) in tools {
+            self.require(subsystem, *tool, *package);
+        }
+        self
+    }
+
+    /// Require at least `min_version` of `tool`, read from the output of
+    /// `tool <version_args>`. The higher of two requirements wins.
+    pub fn require_min_version(
+        &mut self,
+        tool: impl Into<String>,
+        min_version: impl Into<String>,
+        version_args: Vec<String>,
+    ) -> &mut Self {
+        let min_version = min_version.into();
+        let tool = tool.into();
+        let higher = self.min_versions.get(&tool).is_none_or(|(current, _)| {
+            compare_versions(&min_version, current) == Some(Ordering::Greater)
+        });
+        if higher {
+            self.min_versions.insert(tool, (min_version, version_args));
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// `(command, package)` pairs, sorted by command.
+    pub fn tools(&self) -> Vec<(&str, &str)> {
+        self.tools
+            .iter()
+            .map(|(tool, (package, _))| (tool.as_str(), package.as_str()))
+            .collect()
+    }
+
+    /// Subsystems that registered `tool`.
+    pub fn needed_by(&self, tool: &str) -> Vec<&'static str> {
+        self.tools
+            .get(tool)
+            .map(|(_, subsystems)| subsystems.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Registered tools found neither on the host nor in the tool container,
+    /// as `(command, package, subsystems)`.
+    pub fn missing(&self) -> Vec<(&str, &str, Vec<&'static str>)> {
+        self.tools
+            .iter()
+            .filter(|(tool, _)| !tool_available(tool))
+            .map(|(tool, (package, subsystems))| {
+                (
+                    tool.as_str(),
+                    package.as_str(),
+                    subsystems.iter().copied().collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// One line per available tool older than its minimum version, or
+    /// whose version could not be determined.
+    pub fn outdated(&self) -> Vec<String> {
+        let mut outdated = Vec::new();
+        for (tool, (min_version, args)) in &self.min_versions {
+            if !tool_available(tool) {
+                continue;
+            }
+            let package = self.tools.get(tool).map_or(tool.as_str(), |(p, _)| p);
+            match tool_version(tool, args) {
+                Some(version) if version_at_least(&version, min_version) => {}
+                Some(version) => outdated.push(format!(
+                    "{} {} is older than {} (upgrade: {})",
+                    tool,
+                    version
+                        .iter()
+                        .map(u64::to_string)
+                        .collect::<Vec<_>>()
+                        .join("."),
+                    min_version,
+                    package
+                )),
+                None => outdated.push(format!(
+                    "{} version unknown, {} required ('{} {}' printed no version)",
+                    tool,
+                    min_version,
+                    tool,
+                    args.join(" ")
+                )),
+            }
+        }
+        outdated
+    }
+
+    /// Check that every registered tool is on the host or in the tool
+    /// container.
+    ///
+    /// The error names, for each missing tool, the package to install and
+    /// the subsystems that need it. Available tools with a minimum version
+    /// are then asked for theirs.
+    pub fn check(&self) -> Result<()> {
+        let missing: Vec<String> = self
+            .missing()
+            .into_iter()
+            .map(|(tool, package, subsystems)| {
+                format!(
+                    "  {} (install: {}; needed by {})",
+                    tool,
+                    package,
+                    subsystems.join(", ")
+                )
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Missing required host tools:\n{}",
+                missing.join("\n")
+            ))
+            .error_kind(ErrorKind::MissingTool);
+        }
+
+        let outdated: Vec<String> = self
+            .outdated()
+            .into_iter()
+            .map(|line| format!("  {}", line))
+            .collect();
+        if !outdated.is_empty() {
+            return Err(anyhow!("Outdated host tools:\n{}", outdated.join("\n")))
+                .error_kind(ErrorKind::MissingTool);
+        }
+        Ok(())
+    }
+}
+
+/// First version number in `text` (`9.4`, `v1.7.1`, `2`), preferring
+/// dotted ones so a year or build number is not taken for the version.
+pub(crate) fn parse_version(text: &str) -> Option<Vec<u64>> {
+    let mut first = None;
+    for word in text.split(|c: char| c.is_whitespace() || matches!(c, ',' | '(' | ')' | ':')) {
+        let word = word.strip_prefix('v').unwrap_or(word);
+        let end = word
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(word.len());
+        let candidate = word[..end].trim_end_matches('.');
+        if !candidate.starts_with(|c: char| c.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(parts) = candidate
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+        else {
+            continue;
+        };
+        if parts.len() > 1 {
+            return Some(parts);
+        }
+        first.get_or_insert(parts);
+    }
+    first
+}
+
+fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    Some(cmp_parts(&parse_version(a)?, &parse_version(b)?))
+}
+
+fn cmp_parts(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    let at = |parts: &[u64], i: usize| parts.get(i).copied().unwrap_or(0);
+    (0..len)
+        .map(|i| at(a, i).cmp(&at(b, i)))
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+pub(crate) fn version_at_least(version: &[u64], min_version: &str) -> bool {
+    parse_version(min_version).is_some_and(|min| cmp_parts(version, &min) != Ordering::Less)
+}
+
+/// Version `tool` reports for `args`, from stdout or else stderr.
+fn tool_version(tool: &str, args: &[String]) -> Option<Vec<u64>> {
+    let result = Cmd::new(tool).args(args).allow_fail().run().ok()?;
+    parse_version(&result.stdout).or_else(|| parse_version(&result.stderr))
+}
 
 /// Check that specific tools are available.
 ///
@@ -61,7 +297,7 @@ pub fn check_required_tools(tools: &[(&str, &str)]) -> Result<()> {
     let mut missing = Vec::new();
 
     for (tool, package) in tools {
-        if !command_exists(tool) {
+        if !tool_available(tool) {
             missing.push((*tool, *package));
         }
     }
@@ -72,17 +308,31 @@ pub fn check_required_tools(tools: &[(&str, &str)]) -> Result<()> {
             .map(|(t, p)| format!("  {} (install: {})", t, p))
             .collect::<Vec<_>>()
             .join("\n");
-        bail!("Missing required host tools:\n{}", msg);
+        return Err(anyhow!("Missing required host tools:\n{}", msg))
+            .error_kind(ErrorKind::MissingTool);
     }
 
     Ok(())
 }
 
-/// Check that all standard ISO-building tools are available.
+/// Tools of the standard ISO pipeline: EROFS rootfs, initramfs compressed
+/// with `initramfs` (`[compression] initramfs` in `distro-builder.toml`)
+/// and ISO with its EFI boot image.
+pub fn iso_pipeline_tools(initramfs: Compression) -> RequiredTools {
+    let mut tools = RequiredTools::new();
+    crate::artifact::rootfs::register_host_tools(&mut tools);
+    crate::artifact::cpio::register_host_tools(&mut tools, initramfs);
+    crate::artifact::iso_utils::register_host_tools(&mut tools, true);
+    tools
+}
+
+/// Check that the standard ISO pipeline's tools are available, for the
+/// default initramfs compression.
 ///
-/// This checks all tools in [`REQUIRED_TOOLS`].
+/// Builds that run a narrower set of steps should register those
+/// subsystems into a [`RequiredTools`] and check that instead.
 pub fn check_host_tools() -> Result<()> {
-    check_required_tools(REQUIRED_TOOLS)
+    iso_pipeline_tools(Compression::initramfs_default()).check()
 }
 
 #[cfg(test)]
@@ -104,6 +354,49 @@ mod tests {
         assert!(check_required_tools(tools).is_ok());
     }
 
+    #[test]
+    fn test_required_tools_follow_registered_subsystems() {
+        let mut tools = RequiredTools::new();
+        crate::artifact::rootfs::register_host_tools(&mut tools);
+        crate::qemu::register_host_tools(&mut tools);
+        let commands: Vec<&str> = tools.tools().iter().map(|(tool, _)| *tool).collect();
+        assert!(commands.contains(&"mkfs.erofs"));
+        assert!(!commands.contains(&"xorriso"));
+        assert!(!commands.contains(&"mcopy"));
+
+        tools.require("disk", "mkfs.erofs", "erofs-utils");
+        assert_eq!(tools.needed_by("mkfs.erofs"), vec!["disk", "erofs"]);
+
+        tools.require("test", "nonexistent_command_xyz", "fake-package");
+        let err = tools.check().unwrap_err().to_string();
+        assert!(err.contains("nonexistent_command_xyz (install: fake-package; needed by test)"));
+    }
+
+    #[test]
+    fn test_min_versions() {
+        assert_eq!(parse_version("ls (GNU coreutils) 9.4"), Some(vec![9, 4]));
+        assert_eq!(
+            parse_version("mkfs.erofs 1.7.1\nCopyright 2023"),
+            Some(vec![1, 7, 1])
+        );
+        assert_eq!(parse_version("tool v2"), Some(vec![2]));
+        assert_eq!(parse_version("no version here"), None);
+        assert!(version_at_least(&[1, 7], "1.7.0"));
+        assert!(!version_at_least(&[1, 6, 9], "1.7"));
+
+        let mut tools = RequiredTools::new();
+        tools.require("test", "sh", "dash");
+        tools.require_min_version("sh", "1", vec!["-c".into(), "echo 3.2".into()]);
+        tools.require_min_version("sh", "0.5", vec![]);
+        assert!(tools.check().is_ok());
+        tools.require_min_version("sh", "3.10", vec!["-c".into(), "echo 3.2".into()]);
+        let err = tools.check().unwrap_err().to_string();
+        assert!(
+            err.contains("sh 3.2 is older than 3.10 (upgrade: dash)"),
+            "{err}"
+        );
+    }
+
     #[test]
     fn test_check_required_tools_failure() {
         let tools = &[("nonexistent_command_xyz", "fake-package")];
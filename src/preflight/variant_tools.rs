@@ -0,0 +1,147 @@
+//! Extra host tools declared by a variant.
+//!
+//! The subsystems register what the shared pipeline invokes; a variant whose
+//! hooks or overlays call more (a signing tool, a newer `mkfs.erofs` for a
+//! compression mode) lists them in `host-tools.toml` so preflight catches a
+//! missing or outdated tool before the build starts:
+//!
+//! ```toml
+//! [[tool]]
+//! name = "mkfs.erofs"
+//! package = "erofs-utils"
+//! min_version = "1.7"
+//!
+//! [[tool]]
+//! name = "sbsign"
+//! package = "sbsigntools"
+//! # Arguments that print the version (default: --version).
+//! version_args = ["--version"]
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use super::RequiredTools;
+
+/// Variant-local host tool requirements file.
+pub const HOST_TOOLS_FILENAME: &str = "host-tools.toml";
+
+/// Subsystem name variant tools are registered under.
+pub const VARIANT_SUBSYSTEM: &str = "variant";
+
+/// One declared tool.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VariantHostTool {
+    pub name: String,
+    pub package: String,
+    /// Lowest acceptable version, dot-separated numbers.
+    #[serde(default)]
+    pub min_version: Option<String>,
+    #[serde(default)]
+    pub version_args: Option<Vec<String>>,
+}
+
+/// `host-tools.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VariantHostTools {
+    #[serde(rename = "tool")]
+    pub tools: Vec<VariantHostTool>,
+}
+
+impl VariantHostTools {
+    /// Load `host-tools.toml` from `variant_dir`; empty when the variant has
+    /// none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(HOST_TOOLS_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (idx, tool) in self.tools.iter().enumerate() {
+            if tool.name.trim().is_empty() || tool.name.contains(['/', ' ']) {
+                bail!("tool name '{}' is not a command name", tool.name);
+            }
+            if tool.package.trim().is_empty() {
+                bail!("tool '{}' has no package", tool.name);
+            }
+            if self.tools[..idx].iter().any(|t| t.name == tool.name) {
+                bail!("tool '{}' is listed twice", tool.name);
+            }
+            if let Some(min) = &tool.min_version {
+                if super::parse_version(min).is_none() {
+                    bail!(
+                        "tool '{}' min_version '{}' is not a version",
+                        tool.name,
+                        min
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the declared tools and their minimum versions to `tools`.
+    pub fn register(&self, tools: &mut RequiredTools) {
+        for tool in &self.tools {
+            tools.require(VARIANT_SUBSYSTEM, &tool.name, &tool.package);
+            if let Some(min) = &tool.min_version {
+                let args = tool
+                    .version_args
+                    .clone()
+                    .unwrap_or_else(|| vec!["--version".to_string()]);
+                tools.require_min_version(&tool.name, min, args);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_load_and_register() {
+        let temp = TempDir::new().unwrap();
+        assert!(VariantHostTools::load_for_variant(temp.path())
+            .unwrap()
+            .tools
+            .is_empty());
+
+        fs::write(
+            temp.path().join(HOST_TOOLS_FILENAME),
+            "[[tool]]\nname = \"ls\"\npackage = \"coreutils\"\nmin_version = \"1.0\"\n\n\
+             [[tool]]\nname = \"sbsign\"\npackage = \"sbsigntools\"\n",
+        )
+        .unwrap();
+        let config = VariantHostTools::load_for_variant(temp.path()).unwrap();
+        let mut tools = RequiredTools::new();
+        config.register(&mut tools);
+        assert_eq!(
+            tools.tools(),
+            vec![("ls", "coreutils"), ("sbsign", "sbsigntools")]
+        );
+        assert_eq!(tools.needed_by("ls"), vec![VARIANT_SUBSYSTEM]);
+
+        fs::write(
+            temp.path().join(HOST_TOOLS_FILENAME),
+            "[[tool]]\nname = \"ls\"\npackage = \"coreutils\"\nmin_version = \"new\"\n",
+        )
+        .unwrap();
+        assert!(VariantHostTools::load_for_variant(temp.path()).is_err());
+    }
+}
@@ -3,10 +3,18 @@
 //! This module provides a unified API for running external commands,
 //! ensuring all commands capture stderr and provide useful error messages.
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Result};
+use std::ffi::{OsStr, OsString};
 use std::path::Path;
 use std::process::{Command, ExitStatus, Stdio};
 
+use crate::error::{Error, ErrorKind, ResultExt};
+use crate::progress::Progress;
+use crate::reproducible;
+use crate::resource_limits;
+use crate::supervise;
+use crate::tool_container;
+
 /// Result of a command execution.
 #[derive(Debug, Clone)]
 pub struct CommandResult {
@@ -74,10 +82,14 @@ pub struct Cmd {
     program: String,
     args: Vec<String>,
     current_dir: Option<std::path::PathBuf>,
+    /// Extra environment variables.
+    envs: Vec<(String, OsString)>,
     /// If true, don't fail on non-zero exit.
     allow_fail: bool,
     /// Custom error message prefix.
     error_prefix: Option<String>,
+    /// Progress reporting while the command runs.
+    progress: Option<Progress>,
 }
 
 impl Cmd {
@@ -87,8 +99,10 @@ impl Cmd {
             program: program.as_ref().to_string(),
             args: Vec::new(),
             current_dir: None,
+            envs: Vec::new(),
             allow_fail: false,
             error_prefix: None,
+            progress: None,
         }
     }
 
@@ -122,6 +136,26 @@ impl Cmd {
         self
     }
 
+    /// Set an environment variable.
+    pub fn env(mut self, key: impl AsRef<str>, value: impl AsRef<OsStr>) -> Self {
+        self.envs
+            .push((key.as_ref().to_string(), value.as_ref().to_os_string()));
+        self
+    }
+
+    /// Set multiple environment variables.
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self = self.env(key, value);
+        }
+        self
+    }
+
     /// Allow non-zero exit codes without failing.
     pub fn allow_fail(mut self) -> Self {
         self.allow_fail = true;
@@ -134,6 +168,16 @@ impl Cmd {
         self
     }
 
+    /// Report progress while the command runs.
+    ///
+    /// With [`Cmd::run`] line-based sources parse the captured output; with
+    /// [`Cmd::run_interactive`] only output-size polling applies, since the
+    /// output goes straight to the terminal.
+    pub fn progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
     /// Run the command and capture output.
     pub fn run(self) -> Result<CommandResult> {
         let mut cmd = Command::new(&self.program);
@@ -142,16 +186,19 @@ impl Cmd {
         if let Some(ref dir) = self.current_dir {
             cmd.current_dir(dir);
         }
-
-        let output = cmd
-            .output()
-            .with_context(|| format!("Failed to execute '{}'. Is it installed?", self.program))?;
-
-        let result = CommandResult {
-            status: output.status,
-            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
-            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
-        };
+        cmd.envs(self.envs.iter().map(|(key, value)| (key, value)));
+
+        reproducible::export(&mut cmd)?;
+        supervise::check_interrupted()?;
+        let mut limited = resource_limits::limit(tool_container::route(cmd)?)?;
+        let cmd = &mut limited.command;
+        supervise::prepare(cmd);
+        let result = match &self.progress {
+            Some(progress) => run_captured_with_progress(cmd, progress),
+            None => run_captured(cmd),
+        }
+        .map_err(|err| spawn_error(&self.program, err))?;
+        supervise::check_interrupted()?;
 
         if !self.allow_fail && !result.success() {
             let prefix = self
@@ -160,47 +207,125 @@ impl Cmd {
 
             let stderr = result.stderr_trimmed();
             let exit_desc = result.exit_description();
-            if stderr.is_empty() {
-                bail!("{} ({})", prefix, exit_desc);
+            let err = if stderr.is_empty() {
+                anyhow!("{} ({})", prefix, exit_desc)
             } else {
-                bail!("{} ({}):\n{}", prefix, exit_desc, stderr);
-            }
+                anyhow!("{} ({}):\n{}", prefix, exit_desc, stderr)
+            };
+            return Err(err).error_kind(ErrorKind::ToolFailed);
         }
 
         Ok(result)
     }
 
-    /// Run the command with inherited stdio (interactive/streaming).
+    /// Run the command with inherited output (streaming).
     ///
     /// Output goes directly to the terminal. Use for long-running commands
-    /// where the user should see progress (e.g., kernel builds).
+    /// where the user should see progress (e.g., kernel builds). Stdin is
+    /// inherited; a supervised child gets the terminal for its process group
+    /// while it runs (see [`supervise::prepare_interactive`]).
     pub fn run_interactive(self) -> Result<ExitStatus> {
         let mut cmd = Command::new(&self.program);
         cmd.args(&self.args);
-        cmd.stdin(Stdio::inherit());
-        cmd.stdout(Stdio::inherit());
-        cmd.stderr(Stdio::inherit());
 
         if let Some(ref dir) = self.current_dir {
             cmd.current_dir(dir);
         }
+        cmd.envs(self.envs.iter().map(|(key, value)| (key, value)));
 
-        let status = cmd
-            .status()
-            .with_context(|| format!("Failed to execute '{}'. Is it installed?", self.program))?;
+        reproducible::export(&mut cmd)?;
+        supervise::check_interrupted()?;
+        let mut limited = resource_limits::limit(tool_container::route(cmd)?)?;
+        let cmd = &mut limited.command;
+        cmd.stdin(Stdio::inherit());
+        cmd.stdout(Stdio::inherit());
+        cmd.stderr(Stdio::inherit());
+        let tracker = self
+            .progress
+            .as_ref()
+            .filter(|progress| !progress.source().parses_output())
+            .map(Progress::start);
+        let handed_terminal = supervise::prepare_interactive(cmd);
+        let status = cmd.spawn().and_then(|mut child| {
+            let _supervised = supervise::register_interactive(child.id(), handed_terminal);
+            child.wait()
+        });
+        if let Some(tracker) = tracker {
+            tracker.finish();
+        }
+        let status = status.map_err(|err| spawn_error(&self.program, err))?;
+        supervise::check_interrupted()?;
 
         if !self.allow_fail && !status.success() {
             let prefix = self
                 .error_prefix
                 .unwrap_or_else(|| format!("'{}' failed", self.program));
             let exit_desc = exit_description_from_status(&status);
-            bail!("{} ({})", prefix, exit_desc);
+            return Err(anyhow!("{} ({})", prefix, exit_desc)).error_kind(ErrorKind::ToolFailed);
         }
 
         Ok(status)
     }
 }
 
+/// A command that could not be started; a missing binary is
+/// [`ErrorKind::MissingTool`].
+fn spawn_error(program: &str, err: std::io::Error) -> anyhow::Error {
+    let kind = if err.kind() == std::io::ErrorKind::NotFound {
+        ErrorKind::MissingTool
+    } else {
+        ErrorKind::Internal
+    };
+    let err = anyhow::Error::new(err)
+        .context(format!("Failed to execute '{}'. Is it installed?", program));
+    Error::new(kind, err).into()
+}
+
+/// Spawn `cmd` with captured output, supervised until it exits.
+fn run_captured(cmd: &mut Command) -> std::io::Result<CommandResult> {
+    let child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let _supervised = supervise::register(child.id());
+    let output = child.wait_with_output()?;
+    Ok(CommandResult {
+        status: output.status,
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// Spawn `cmd` with piped output, feeding both streams to `progress` as
+/// they arrive.
+fn run_captured_with_progress(
+    cmd: &mut Command,
+    progress: &Progress,
+) -> std::io::Result<CommandResult> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let _supervised = supervise::register(child.id());
+    let stdout = child.stdout.take().expect("stdout piped");
+    let stderr = child.stderr.take().expect("stderr piped");
+    let tracker = progress.start();
+    let (stdout, stderr) = std::thread::scope(|scope| {
+        let out = scope.spawn(|| tracker.drain(stdout));
+        let err = tracker.drain(stderr);
+        (out.join().unwrap_or_default(), err)
+    });
+    let status = child.wait();
+    tracker.finish();
+    Ok(CommandResult {
+        status: status?,
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+    })
+}
+
 /// Get a human-readable exit description from an ExitStatus.
 fn exit_description_from_status(status: &ExitStatus) -> String {
     if let Some(code) = status.code() {
@@ -360,6 +485,27 @@ mod tests {
         assert!(!result.stderr.is_empty());
     }
 
+    #[test]
+    fn test_run_with_progress_keeps_output() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let progress = Progress::xorriso("iso").with_callback(std::sync::Arc::new(move |update| {
+            sink.lock().unwrap().push(update.percent);
+        }));
+        let result = Cmd::new("sh")
+            .args([
+                "-c",
+                "echo out; echo 'xorriso : UPDATE :  50.00% done' >&2; echo err >&2",
+            ])
+            .progress(progress)
+            .run()
+            .unwrap();
+
+        assert_eq!(result.stdout_trimmed(), "out");
+        assert!(result.stderr.ends_with("err\n"));
+        assert_eq!(*seen.lock().unwrap(), vec![Some(50.0)]);
+    }
+
     #[test]
     fn test_run_failure_includes_stderr() {
         let err = run("ls", ["/nonexistent_path_12345"]).unwrap_err();
@@ -412,6 +558,18 @@ mod tests {
         assert_eq!(result.stdout_trimmed(), "one two three");
     }
 
+    #[test]
+    fn test_cmd_env() {
+        let result = Cmd::new("sh")
+            .args(["-c", "echo \"$ONE $TWO\""])
+            .env("ONE", "first")
+            .envs([("TWO", "second")])
+            .run()
+            .unwrap();
+
+        assert_eq!(result.stdout_trimmed(), "first second");
+    }
+
     #[test]
     fn test_custom_error_message() {
         let err = Cmd::new("false") // `false` always exits with 1
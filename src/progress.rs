@@ -0,0 +1,485 @@
+//! Progress reporting for long-running external tools.
+//!
+//! `mkfs.erofs`, `mksquashfs`, `xorriso` and `cp -a` over a full rootfs take
+//! minutes and are silent (or print nothing parseable) for most of that
+//! time, which looks like a hang. A [`Progress`] attached to a
+//! [`Cmd`](crate::process::Cmd) reports while the tool runs, either by
+//! parsing the tool's own progress lines or, when the tool has none, by
+//! polling the size of what it is writing.
+//!
+//! Updates go to a [`ProgressCallback`]. The default one prints a throttled
+//! line to stderr; set `DISTRO_BUILDER_PROGRESS=0` to silence it, or
+//! `DISTRO_BUILDER_PROGRESS_FORMAT=json` to get one machine-readable line per
+//! update (see [`parse_json_progress_line`]). Embedders replace it for the
+//! whole process with [`install_reporter`].
+
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::artifact::rootfs::format_size_human;
+
+/// Environment variable that disables the default stderr reporter when `0`.
+pub const PROGRESS_ENV: &str = "DISTRO_BUILDER_PROGRESS";
+
+/// Environment variable selecting the default reporter's output format;
+/// `json` prints every update as [`JSON_PROGRESS_PREFIX`] plus a JSON object.
+pub const PROGRESS_FORMAT_ENV: &str = "DISTRO_BUILDER_PROGRESS_FORMAT";
+
+/// Prefix of the stderr lines written in the `json` progress format.
+pub const JSON_PROGRESS_PREFIX: &str = "[progress-json] ";
+
+/// How often output size is sampled for [`ProgressSource::OutputSize`].
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Minimum time between two lines from the stderr reporter.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reporter [`Progress::new`] uses instead of [`stderr_reporter`].
+static INSTALLED: Mutex<Option<ProgressCallback>> = Mutex::new(None);
+
+/// One progress sample.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressUpdate {
+    pub label: String,
+    /// Bytes written so far, when measured by polling.
+    pub done_bytes: Option<u64>,
+    /// Expected final size, when known.
+    pub total_bytes: Option<u64>,
+    /// Completion in percent, from the tool or from `done/total`.
+    pub percent: Option<f64>,
+    pub elapsed: Duration,
+}
+
+/// Receives progress samples. Called from a helper thread.
+pub type ProgressCallback = Arc<dyn Fn(&ProgressUpdate) + Send + Sync>;
+
+/// Where progress information comes from.
+#[derive(Debug, Clone)]
+pub enum ProgressSource {
+    /// `xorriso : UPDATE :  45.12% done` lines on stderr.
+    Xorriso,
+    /// `mksquashfs -progress` bar lines ending in `NN%`.
+    Mksquashfs,
+    /// Poll the size of a file or directory the tool is writing.
+    OutputSize {
+        path: PathBuf,
+        expected_bytes: Option<u64>,
+    },
+}
+
+impl ProgressSource {
+    /// Parse one output line into a percentage, for line-based sources.
+    pub fn parse_line(&self, line: &str) -> Option<f64> {
+        match self {
+            Self::Xorriso => parse_xorriso_percent(line),
+            Self::Mksquashfs => parse_mksquashfs_percent(line),
+            Self::OutputSize { .. } => None,
+        }
+    }
+
+    /// Whether this source needs the tool's output captured and parsed.
+    pub fn parses_output(&self) -> bool {
+        !matches!(self, Self::OutputSize { .. })
+    }
+}
+
+/// Progress reporting attached to a command.
+#[derive(Clone)]
+pub struct Progress {
+    label: String,
+    source: ProgressSource,
+    callback: ProgressCallback,
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Progress")
+            .field("label", &self.label)
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Progress {
+    pub fn new(label: impl Into<String>, source: ProgressSource) -> Self {
+        Self {
+            label: label.into(),
+            source,
+            callback: default_reporter(),
+        }
+    }
+
+    /// Parse xorriso's own progress lines.
+    pub fn xorriso(label: impl Into<String>) -> Self {
+        Self::new(label, ProgressSource::Xorriso)
+    }
+
+    /// Parse `mksquashfs -progress` output.
+    pub fn mksquashfs(label: impl Into<String>) -> Self {
+        Self::new(label, ProgressSource::Mksquashfs)
+    }
+
+    /// Poll the size of `path` while the tool writes it.
+    ///
+    /// With `expected_bytes` (e.g. the source tree size for a copy) updates
+    /// carry a percentage; without it they report bytes written only.
+    pub fn output_size(
+        label: impl Into<String>,
+        path: impl Into<PathBuf>,
+        expected_bytes: Option<u64>,
+    ) -> Self {
+        Self::new(
+            label,
+            ProgressSource::OutputSize {
+                path: path.into(),
+                expected_bytes,
+            },
+        )
+    }
+
+    /// Send updates to `callback` instead of the default stderr reporter.
+    pub fn with_callback(mut self, callback: ProgressCallback) -> Self {
+        self.callback = callback;
+        self
+    }
+
+    pub fn source(&self) -> &ProgressSource {
+        &self.source
+    }
+
+    /// Start tracking a command that was just spawned.
+    pub(crate) fn start(&self) -> ProgressTracker {
+        let started = Instant::now();
+        let stop = Arc::new(AtomicBool::new(false));
+        let poller = match &self.source {
+            ProgressSource::OutputSize {
+                path,
+                expected_bytes,
+            } => {
+                let path = path.clone();
+                let expected = *expected_bytes;
+                let label = self.label.clone();
+                let callback = Arc::clone(&self.callback);
+                let stop = Arc::clone(&stop);
+                Some(std::thread::spawn(move || {
+                    while !sleep_unless_stopped(&stop, POLL_INTERVAL) {
+                        let done = output_size(&path);
+                        callback(&ProgressUpdate {
+                            label: label.clone(),
+                            done_bytes: Some(done),
+                            total_bytes: expected,
+                            percent: expected
+                                .filter(|total| *total > 0)
+                                .map(|total| (done as f64 / total as f64 * 100.0).min(100.0)),
+                            elapsed: started.elapsed(),
+                        });
+                    }
+                }))
+            }
+            _ => None,
+        };
+        ProgressTracker {
+            progress: self.clone(),
+            started,
+            stop,
+            poller,
+        }
+    }
+}
+
+/// A running progress report; stops its poller when finished or dropped.
+pub(crate) struct ProgressTracker {
+    progress: Progress,
+    started: Instant,
+    stop: Arc<AtomicBool>,
+    poller: Option<JoinHandle<()>>,
+}
+
+impl ProgressTracker {
+    /// Feed one line of tool output.
+    pub(crate) fn line(&self, line: &str) {
+        if let Some(percent) = self.progress.source.parse_line(line) {
+            (self.progress.callback)(&ProgressUpdate {
+                label: self.progress.label.clone(),
+                done_bytes: None,
+                total_bytes: None,
+                percent: Some(percent),
+                elapsed: self.started.elapsed(),
+            });
+        }
+    }
+
+    /// Read `reader` to the end, splitting on `\n` and `\r` (progress bars
+    /// redraw with `\r`), feeding each line to [`Self::line`]. Returns the
+    /// raw bytes.
+    pub(crate) fn drain(&self, mut reader: impl Read) -> Vec<u8> {
+        let mut all = Vec::new();
+        let mut pending = Vec::new();
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            all.extend_from_slice(&buf[..n]);
+            for &byte in &buf[..n] {
+                if byte == b'\n' || byte == b'\r' {
+                    self.line(&String::from_utf8_lossy(&pending));
+                    pending.clear();
+                } else {
+                    pending.push(byte);
+                }
+            }
+        }
+        if !pending.is_empty() {
+            self.line(&String::from_utf8_lossy(&pending));
+        }
+        all
+    }
+
+    pub(crate) fn finish(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(poller) = self.poller.take() {
+            let _ = poller.join();
+        }
+    }
+}
+
+impl Drop for ProgressTracker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Sleep up to `total`, waking early when `stop` is set. Returns `true` if
+/// stopped.
+fn sleep_unless_stopped(stop: &AtomicBool, total: Duration) -> bool {
+    let step = Duration::from_millis(100);
+    let mut slept = Duration::ZERO;
+    while slept < total {
+        if stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        std::thread::sleep(step);
+        slept += step;
+    }
+    stop.load(Ordering::Relaxed)
+}
+
+/// Size of a file, or the apparent size of a directory tree.
+fn output_size(path: &Path) -> u64 {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => crate::workspace::dir_size_bytes(path).unwrap_or(0),
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Percentage from an `xorriso : UPDATE :  45.12% done, ...` line.
+pub fn parse_xorriso_percent(line: &str) -> Option<f64> {
+    let rest = line.trim_start().strip_prefix("xorriso : UPDATE :")?;
+    let (number, _) = rest.trim_start().split_once("% done")?;
+    number.trim().parse().ok()
+}
+
+/// Percentage from a `mksquashfs -progress` bar, e.g.
+/// `[=======-    ] 1200/4000  30%`.
+pub fn parse_mksquashfs_percent(line: &str) -> Option<f64> {
+    let line = line.trim_end();
+    if !line.starts_with('[') {
+        return None;
+    }
+    let number = line.strip_suffix('%')?.rsplit(' ').next()?;
+    number.parse().ok()
+}
+
+/// Default reporter: one stderr line per [`REPORT_INTERVAL`] or per 10%.
+///
+/// Returns a no-op reporter when `DISTRO_BUILDER_PROGRESS=0`.
+pub fn stderr_reporter() -> ProgressCallback {
+    if std::env::var(PROGRESS_ENV).is_ok_and(|value| value == "0") {
+        return Arc::new(|_| {});
+    }
+    if std::env::var(PROGRESS_FORMAT_ENV).is_ok_and(|value| value == "json") {
+        return Arc::new(|update| {
+            if let Ok(json) = serde_json::to_string(update) {
+                eprintln!("{}{}", JSON_PROGRESS_PREFIX, json);
+            }
+        });
+    }
+    let last: Mutex<Option<(Instant, Option<f64>)>> = Mutex::new(None);
+    Arc::new(move |update| {
+        let Ok(mut last) = last.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        let due = match *last {
+            None => true,
+            Some((at, percent)) => {
+                now.duration_since(at) >= REPORT_INTERVAL
+                    || matches!(
+                        (percent, update.percent),
+                        (Some(before), Some(after)) if after - before >= 10.0
+                    )
+            }
+        };
+        if due {
+            eprintln!("  [progress] {}", format_update(update));
+            *last = Some((now, update.percent));
+        }
+    })
+}
+
+/// Send the updates of every [`Progress`] created from now on to `callback`,
+/// until the returned guard is dropped.
+pub fn install_reporter(callback: ProgressCallback) -> InstalledReporter {
+    let previous = INSTALLED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .replace(callback);
+    InstalledReporter { previous }
+}
+
+/// Restores the previously installed reporter when dropped.
+#[must_use = "the reporter is uninstalled when this guard is dropped"]
+pub struct InstalledReporter {
+    previous: Option<ProgressCallback>,
+}
+
+impl Drop for InstalledReporter {
+    fn drop(&mut self) {
+        *INSTALLED.lock().unwrap_or_else(|err| err.into_inner()) = self.previous.take();
+    }
+}
+
+/// The installed reporter, else [`stderr_reporter`].
+fn default_reporter() -> ProgressCallback {
+    INSTALLED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_else(stderr_reporter)
+}
+
+/// Parse a line written by the `json` progress format.
+pub fn parse_json_progress_line(line: &str) -> Option<ProgressUpdate> {
+    serde_json::from_str(line.trim().strip_prefix(JSON_PROGRESS_PREFIX.trim_end())?).ok()
+}
+
+/// Human-readable one-line summary of an update.
+pub fn format_update(update: &ProgressUpdate) -> String {
+    let mut line = update.label.clone();
+    if let Some(percent) = update.percent {
+        line.push_str(&format!(": {:.0}%", percent));
+    }
+    match (update.done_bytes, update.total_bytes) {
+        (Some(done), Some(total)) => line.push_str(&format!(
+            " ({} / {})",
+            format_size_human(done),
+            format_size_human(total)
+        )),
+        (Some(done), None) => line.push_str(&format!(" ({} written)", format_size_human(done))),
+        _ => {}
+    }
+    let secs = update.elapsed.as_secs();
+    line.push_str(&format!(", {}m{:02}s elapsed", secs / 60, secs % 60));
+    if let Some(eta) = estimate_remaining(update) {
+        let eta = eta.as_secs();
+        line.push_str(&format!(", ~{}m{:02}s left", eta / 60, eta % 60));
+    }
+    line
+}
+
+/// Linear time-remaining estimate from the percentage so far.
+pub fn estimate_remaining(update: &ProgressUpdate) -> Option<Duration> {
+    let percent = update.percent?;
+    if !(1.0..100.0).contains(&percent) {
+        return None;
+    }
+    let elapsed = update.elapsed.as_secs_f64();
+    Some(Duration::from_secs_f64(
+        elapsed * (100.0 - percent) / percent,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tool_lines() {
+        assert_eq!(
+            parse_xorriso_percent("xorriso : UPDATE :  45.12% done, estimate finish Thu"),
+            Some(45.12)
+        );
+        assert_eq!(
+            parse_xorriso_percent("xorriso : UPDATE : 1024 files added"),
+            None
+        );
+        assert_eq!(
+            parse_mksquashfs_percent("[=======-     ] 1200/4000  30%"),
+            Some(30.0)
+        );
+        assert_eq!(parse_mksquashfs_percent("Parallel mksquashfs: 8"), None);
+    }
+
+    #[test]
+    fn test_tracker_drains_carriage_return_lines() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        let progress = Progress::mksquashfs("rootfs").with_callback(Arc::new(move |update| {
+            sink.lock().unwrap().push(update.percent.unwrap());
+        }));
+        let tracker = progress.start();
+        let raw = tracker.drain(&b"[==  ] 1/4  25%\r[====] 4/4 100%\ndone\n"[..]);
+        tracker.finish();
+        assert_eq!(raw.len(), 37);
+        assert_eq!(*seen.lock().unwrap(), vec![25.0, 100.0]);
+    }
+
+    #[test]
+    fn test_installed_reporter_replaces_default() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        let installed = install_reporter(Arc::new(move |update| {
+            sink.lock().unwrap().push(update.percent.unwrap());
+        }));
+        let progress = Progress::mksquashfs("rootfs");
+        drop(installed);
+        let tracker = progress.start();
+        tracker.drain(&b"[====] 4/4 100%\n"[..]);
+        tracker.finish();
+        assert_eq!(*seen.lock().unwrap(), vec![100.0]);
+    }
+
+    #[test]
+    fn test_format_update_with_estimate() {
+        let update = ProgressUpdate {
+            label: "cp rootfs".to_string(),
+            done_bytes: Some(1024 * 1024),
+            total_bytes: Some(4 * 1024 * 1024),
+            percent: Some(25.0),
+            elapsed: Duration::from_secs(30),
+        };
+        assert_eq!(
+            format_update(&update),
+            "cp rootfs: 25% (1 MB / 4 MB), 0m30s elapsed, ~1m30s left"
+        );
+
+        let line = format!(
+            "{}{}",
+            JSON_PROGRESS_PREFIX,
+            serde_json::to_string(&update).unwrap()
+        );
+        assert_eq!(parse_json_progress_line(&line), Some(update));
+        assert_eq!(parse_json_progress_line("  [progress] cp rootfs"), None);
+    }
+}
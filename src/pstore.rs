@@ -0,0 +1,487 @@
+//! Crash capture for boot tests through ramoops (pstore in guest RAM).
+//!
+//! A kernel panic late in boot often comes after the serial console has
+//! stopped draining: the guest hangs, the boot test times out, and the last
+//! lines it saw say nothing about the crash. A variant that ships
+//! `pstore.toml` reserves a region of guest RAM for ramoops:
+//!
+//! ```toml
+//! # Guest physical address of the region; must lie below 2 GiB.
+//! address = 0x30000000
+//! size_kb = 1024
+//! record_size_kb = 64
+//! console_size_kb = 256
+//! # Also reserve the region in the shipped live UKI (off by default).
+//! release_cmdline = false
+//! ```
+//!
+//! The live overlay loads the `ramoops` module ([`Pstore::apply_overlay`]).
+//! Boot tests back guest RAM with a host file and hand the guest the
+//! `memmap=` and `ramoops.*` parameters through an SMBIOS OEM string that
+//! systemd-stub appends to the UKI command line ([`Pstore::qemu_args`]), so
+//! the release UKI does not reserve memory on real hardware. After a failed
+//! boot the region's dmesg and console records are extracted ([`extract`])
+//! and saved next to the boot medium.
+//!
+//! Crashes before `ramoops` loads are only captured when the kernel has it
+//! built in (`CONFIG_PSTORE_RAM=y`).
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use crate::build::cmdline::CmdlineBuilder;
+
+/// Variant-local pstore configuration file.
+pub const PSTORE_CONFIG_FILENAME: &str = "pstore.toml";
+
+/// modules-load.d entry loading `ramoops` in the live system.
+pub const MODULES_LOAD_CONF: &str = "etc/modules-load.d/pstore.conf";
+
+/// `persistent_ram_buffer` signature ("DBGC", little-endian).
+const RAMOOPS_SIG: u32 = 0x4347_4244;
+
+/// `sig`, `start` and `size` in front of every ramoops zone.
+const ZONE_HEADER_LEN: usize = 12;
+
+/// Header of a dmesg record: `====<sec>.<nsec>-<C|D>`.
+const DMESG_HEADER: &str = "====";
+
+/// Guest RAM below this address maps 1:1 to the backing file on both the
+/// `pc` and `q35` machines.
+const LOWMEM_LIMIT: u64 = 0x8000_0000;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Kernel log lines quoted in a boot failure.
+const REPORT_TAIL_LINES: usize = 20;
+
+/// SMBIOS type 11 string whose value systemd-stub appends to the command
+/// line of the UKI it boots.
+const SMBIOS_CMDLINE_EXTRA: &str = "io.systemd.stub.kernel-cmdline-extra";
+
+/// ramoops region of one variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pstore {
+    /// Guest physical address of the reserved region.
+    pub address: u64,
+    pub size_kb: u64,
+    /// Size of each dmesg record; the rest of the region minus the console
+    /// ring holds as many records as fit.
+    pub record_size_kb: u64,
+    /// Console ring, the kernel's console output up to the crash.
+    pub console_size_kb: u64,
+    /// Bake the parameters into the shipped live UKI as well; boot tests
+    /// pass them on their own.
+    pub release_cmdline: bool,
+}
+
+impl Default for Pstore {
+    fn default() -> Self {
+        Self {
+            address: 0x3000_0000,
+            size_kb: 1024,
+            record_size_kb: 64,
+            console_size_kb: 256,
+            release_cmdline: false,
+        }
+    }
+}
+
+impl Pstore {
+    /// Load `pstore.toml` from `variant_dir`; `None` when the variant does
+    /// not capture crashes.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Option<Self>> {
+        let path = variant_dir.join(PSTORE_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", path.display()))?;
+        Ok(Some(config))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (name, kb) in [
+            ("size_kb", self.size_kb),
+            ("record_size_kb", self.record_size_kb),
+            ("console_size_kb", self.console_size_kb),
+        ] {
+            if kb < 4 || !kb.is_power_of_two() {
+                bail!("{} must be a power of two of at least 4, got {}", name, kb);
+            }
+        }
+        if self.record_size_kb + self.console_size_kb > self.size_kb {
+            bail!(
+                "record_size_kb ({}) plus console_size_kb ({}) exceed size_kb ({})",
+                self.record_size_kb,
+                self.console_size_kb,
+                self.size_kb
+            );
+        }
+        if !self.address.is_multiple_of(PAGE_SIZE) {
+            bail!("address {:#x} is not page aligned", self.address);
+        }
+        if self.address == 0 || self.end() > LOWMEM_LIMIT {
+            bail!(
+                "region {:#x}..{:#x} must lie between 0 and {:#x}",
+                self.address,
+                self.end(),
+                LOWMEM_LIMIT
+            );
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.size_kb * 1024
+    }
+
+    fn end(&self) -> u64 {
+        self.address + self.size()
+    }
+
+    /// Kernel parameters reserving the region and pointing ramoops at it.
+    /// dmesg records are kept uncompressed so the host can read them.
+    pub fn cmdline_tokens(&self) -> Vec<String> {
+        vec![
+            format!("memmap={}K${:#x}", self.size_kb, self.address),
+            format!("ramoops.mem_address={:#x}", self.address),
+            format!("ramoops.mem_size={}", self.size()),
+            format!("ramoops.record_size={}", self.record_size_kb * 1024),
+            format!("ramoops.console_size={}", self.console_size_kb * 1024),
+            "ramoops.ecc=0".to_string(),
+            "pstore.compress=none".to_string(),
+        ]
+    }
+
+    /// Append [`Self::cmdline_tokens`] to `cmdline`. Parameters the command
+    /// line already sets are left alone.
+    pub fn extend_cmdline(&self, cmdline: &str) -> Result<String> {
+        let mut builder = CmdlineBuilder::parse(cmdline)?;
+        for token in self.cmdline_tokens() {
+            let key = token.split('=').next().unwrap_or(&token);
+            let present = match key {
+                "memmap" => builder.contains(&token),
+                _ => builder.contains(key),
+            };
+            if !present {
+                builder = builder.extend_str(&token)?;
+            }
+        }
+        builder.build()
+    }
+
+    /// Load `ramoops` at boot in a live overlay. On OpenRC the `modules`
+    /// service, which reads modules-load.d, is added to the boot runlevel.
+    pub fn apply_overlay(&self, live_overlay: &Path, openrc: bool) -> Result<()> {
+        let conf = live_overlay.join(MODULES_LOAD_CONF);
+        if let Some(parent) = conf.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&conf, "# Generated by distro-builder: pstore\nramoops\n")
+            .with_context(|| format!("Failed to write {}", conf.display()))?;
+        if openrc {
+            let dir = live_overlay.join("etc/runlevels/boot");
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            let link = dir.join("modules");
+            if link.symlink_metadata().is_err() {
+                symlink("/etc/init.d/modules", &link)
+                    .context("Failed to enable modules in runlevel boot")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// QEMU arguments backing `memory_gb` of guest RAM with `ram_file`, so
+    /// the region can be read after the guest is gone, and passing
+    /// [`Self::cmdline_tokens`] to the UKI the guest boots.
+    pub fn qemu_args(&self, ram_file: &Path, memory_gb: u32) -> Result<Vec<String>> {
+        let memory = u64::from(memory_gb) << 30;
+        if self.end() > memory.min(LOWMEM_LIMIT) {
+            bail!(
+                "pstore region {:#x}..{:#x} lies outside the guest's {} GiB of low memory",
+                self.address,
+                self.end(),
+                memory_gb
+            );
+        }
+        Ok(vec![
+            "-object".to_string(),
+            format!(
+                "memory-backend-file,id=pstore-ram,size={}G,mem-path={},share=on",
+                memory_gb,
+                ram_file.display()
+            ),
+            "-machine".to_string(),
+            "memory-backend=pstore-ram".to_string(),
+            "-smbios".to_string(),
+            format!(
+                "type=11,value={}={}",
+                SMBIOS_CMDLINE_EXTRA,
+                self.cmdline_tokens().join(" ")
+            ),
+        ])
+    }
+
+    /// Read the region out of `ram_file`.
+    pub fn read_region(&self, ram_file: &Path) -> Result<Vec<u8>> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut file = fs::File::open(ram_file)
+            .with_context(|| format!("Failed to open {}", ram_file.display()))?;
+        file.seek(SeekFrom::Start(self.address))
+            .with_context(|| format!("Failed to seek in {}", ram_file.display()))?;
+        let mut region = vec![0u8; self.size() as usize];
+        file.read_exact(&mut region)
+            .with_context(|| format!("Failed to read pstore region of {}", ram_file.display()))?;
+        Ok(region)
+    }
+
+    /// Extract the records left in `ram_file`, save them under `out_dir` and
+    /// summarize them for a boot failure message. `None` when ramoops left
+    /// nothing (it never loaded, or the guest did not log).
+    pub fn failure_report(&self, ram_file: &Path, out_dir: &Path) -> Result<Option<String>> {
+        let records = extract(self, &self.read_region(ram_file)?);
+        if records.is_empty() {
+            return Ok(None);
+        }
+        write_records(&records, out_dir)?;
+        let mut report = format!(
+            "pstore: {} record(s) saved to {}",
+            records.len(),
+            out_dir.display()
+        );
+        let latest = records
+            .iter()
+            .filter(|r| r.kind == RecordKind::Dmesg && !r.compressed)
+            .max_by_key(|r| r.timestamp())
+            .or_else(|| records.iter().find(|r| r.kind == RecordKind::Console));
+        if let Some(record) = latest {
+            report.push_str(&format!("\nLast lines of {}:\n", record.name));
+            report.push_str(&record.tail(REPORT_TAIL_LINES));
+        }
+        Ok(Some(report))
+    }
+}
+
+/// Which ramoops zone a record came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// Kernel log dumped on panic or oops.
+    Dmesg,
+    /// Console ring.
+    Console,
+}
+
+/// One non-empty ramoops zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PstoreRecord {
+    pub kind: RecordKind,
+    /// File name, as the kernel names it under `/sys/fs/pstore`.
+    pub name: String,
+    /// The kernel compressed the record; boot with `pstore.compress=none`.
+    pub compressed: bool,
+    pub data: Vec<u8>,
+}
+
+impl PstoreRecord {
+    /// `(sec, nsec)` from a dmesg record header.
+    fn timestamp(&self) -> (u64, u64) {
+        let header = self.data.split(|b| *b == b'\n').next().unwrap_or_default();
+        let header = String::from_utf8_lossy(header);
+        let stamp = header
+            .strip_prefix(DMESG_HEADER)
+            .and_then(|rest| rest.split('-').next())
+            .unwrap_or_default();
+        let (sec, nsec) = stamp.split_once('.').unwrap_or((stamp, "0"));
+        (sec.parse().unwrap_or(0), nsec.parse().unwrap_or(0))
+    }
+
+    /// Last `lines` lines of the record as text.
+    pub fn tail(&self, lines: usize) -> String {
+        let text = String::from_utf8_lossy(&self.data);
+        let all: Vec<&str> = text.trim_end().lines().collect();
+        all[all.len().saturating_sub(lines)..].join("\n")
+    }
+}
+
+/// Records in `region`, laid out as ramoops does: dmesg zones of
+/// `record_size_kb` first, then the console ring.
+pub fn extract(config: &Pstore, region: &[u8]) -> Vec<PstoreRecord> {
+    let record_size = (config.record_size_kb * 1024) as usize;
+    let console_size = (config.console_size_kb * 1024) as usize;
+    let dmesg_size = region.len().saturating_sub(console_size);
+    let mut records = Vec::new();
+
+    for (index, zone) in region[..dmesg_size].chunks_exact(record_size).enumerate() {
+        if let Some(data) = zone_data(zone) {
+            let compressed = data.starts_with(DMESG_HEADER.as_bytes())
+                && data
+                    .split(|b| *b == b'\n')
+                    .next()
+                    .is_some_and(|header| header.ends_with(b"-C"));
+            records.push(PstoreRecord {
+                kind: RecordKind::Dmesg,
+                name: format!("dmesg-ramoops-{}", index),
+                compressed,
+                data,
+            });
+        }
+    }
+    if let Some(data) = region.get(dmesg_size..).and_then(zone_data) {
+        records.push(PstoreRecord {
+            kind: RecordKind::Console,
+            name: "console-ramoops".to_string(),
+            compressed: false,
+            data,
+        });
+    }
+    records
+}
+
+/// Contents of one zone in write order, unwrapping the ring at `start`.
+fn zone_data(zone: &[u8]) -> Option<Vec<u8>> {
+    let word = |at: usize| -> Option<usize> {
+        let bytes: [u8; 4] = zone.get(at..at + 4)?.try_into().ok()?;
+        Some(u32::from_le_bytes(bytes) as usize)
+    };
+    if word(0)? != RAMOOPS_SIG as usize {
+        return None;
+    }
+    let (start, size) = (word(4)?, word(8)?);
+    let data = zone.get(ZONE_HEADER_LEN..)?;
+    if size == 0 || size > data.len() || start > size {
+        return None;
+    }
+    let mut out = data[start..size].to_vec();
+    out.extend_from_slice(&data[..start]);
+    Some(out)
+}
+
+/// Save `records` as files named after them under `out_dir`, replacing an
+/// earlier run's records.
+pub fn write_records(records: &[PstoreRecord], out_dir: &Path) -> Result<Vec<PathBuf>> {
+    if out_dir.exists() {
+        fs::remove_dir_all(out_dir)
+            .with_context(|| format!("Failed to remove {}", out_dir.display()))?;
+    }
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+    records
+        .iter()
+        .map(|record| {
+            let path = out_dir.join(&record.name);
+            fs::write(&path, &record.data)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn zone(len: usize, start: usize, content: &[u8]) -> Vec<u8> {
+        let mut zone = vec![0u8; len];
+        zone[..4].copy_from_slice(&RAMOOPS_SIG.to_le_bytes());
+        zone[4..8].copy_from_slice(&(start as u32).to_le_bytes());
+        zone[8..12].copy_from_slice(&(content.len() as u32).to_le_bytes());
+        // Stored rotated: the oldest byte sits at `start`.
+        let rotated: Vec<u8> = content[content.len() - start..]
+            .iter()
+            .chain(&content[..content.len() - start])
+            .copied()
+            .collect();
+        zone[ZONE_HEADER_LEN..ZONE_HEADER_LEN + content.len()].copy_from_slice(&rotated);
+        zone
+    }
+
+    #[test]
+    fn test_validate_and_cmdline() {
+        let config = Pstore::default();
+        config.validate().unwrap();
+        assert_eq!(
+            config.extend_cmdline("quiet ramoops.ecc=1").unwrap(),
+            "quiet ramoops.ecc=1 memmap=1024K$0x30000000 ramoops.mem_address=0x30000000 \
+             ramoops.mem_size=1048576 ramoops.record_size=65536 ramoops.console_size=262144 \
+             pstore.compress=none"
+        );
+
+        let unaligned = Pstore {
+            address: 0x3000_0100,
+            ..Pstore::default()
+        };
+        assert!(unaligned.validate().is_err());
+        let too_high = Pstore {
+            address: 0x7ff8_0000,
+            ..Pstore::default()
+        };
+        assert!(too_high.validate().is_err());
+        let crowded = Pstore {
+            size_kb: 256,
+            ..Pstore::default()
+        };
+        assert!(crowded.validate().is_err());
+        let args = Pstore::default().qemu_args(Path::new("ram"), 4).unwrap();
+        assert_eq!(
+            args[args.len() - 2..],
+            [
+                "-smbios".to_string(),
+                "type=11,value=io.systemd.stub.kernel-cmdline-extra=memmap=1024K$0x30000000 \
+                 ramoops.mem_address=0x30000000 ramoops.mem_size=1048576 \
+                 ramoops.record_size=65536 ramoops.console_size=262144 ramoops.ecc=0 \
+                 pstore.compress=none"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_records() {
+        let config = Pstore {
+            address: 0x4000,
+            size_kb: 16,
+            record_size_kb: 4,
+            console_size_kb: 8,
+            ..Pstore::default()
+        };
+        let mut region = zone(4096, 0, b"====10.000000001-D\nold oops\n");
+        region.extend(vec![0u8; 4096]);
+        region.extend(zone(
+            8192,
+            6,
+            b"console line 1\nKernel panic - not syncing\n",
+        ));
+        let records = extract(&config, &region);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "dmesg-ramoops-0");
+        assert_eq!(records[0].timestamp(), (10, 1));
+        assert!(!records[0].compressed);
+        assert_eq!(records[1].kind, RecordKind::Console);
+        assert_eq!(records[1].tail(1), "Kernel panic - not syncing");
+
+        let temp = TempDir::new().unwrap();
+        let ram = temp.path().join("ram");
+        let mut file = vec![0u8; config.address as usize];
+        file.extend(&region);
+        fs::write(&ram, file).unwrap();
+        let out = temp.path().join("pstore");
+        let report = config.failure_report(&ram, &out).unwrap().unwrap();
+        assert!(report.contains("2 record(s)"), "{report}");
+        assert!(report.ends_with("====10.000000001-D\nold oops"), "{report}");
+        assert!(out.join("console-ramoops").is_file());
+    }
+}
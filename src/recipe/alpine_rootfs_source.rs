@@ -2,7 +2,7 @@ use anyhow::{bail, Context, Result};
 use std::path::{Path, PathBuf};
 
 use super::{find_recipe, run_recipe_phase_json_with_defines_and_env};
-use crate::pipeline::paths::normalize_distro_id;
+use crate::workspace::{WorkspaceManager, DOWNLOADS_NAMESPACE};
 
 pub const ALPINE_ROOTFS_SOURCE_RECIPE_FILENAME: &str = "alpine-live-source-rootfs.rhai";
 
@@ -33,7 +33,9 @@ pub fn preseed_alpine_rootfs_source_assets(
         )
     })?;
 
-    let recipe_path = repo_root.join("distro-builder/recipes/alpine-preseed-source-assets.rhai");
+    let recipe_path = crate::repo_layout::RepoLayout::at(repo_root)?
+        .recipes_dir()
+        .join("alpine-preseed-source-assets.rhai");
     if !recipe_path.is_file() {
         bail!(
             "Alpine preseed recipe script not found: '{}'",
@@ -50,11 +52,14 @@ pub fn preseed_alpine_rootfs_source_assets(
         )
     })?;
 
-    let envs = if refresh {
-        vec![("ALPINE_FORCE_REFRESH", "1")]
-    } else {
-        Vec::new()
-    };
+    let fetch_env = crate::fetch::recipe_fetch_env(repo_root, distro_id);
+    let mut envs: Vec<(&str, &str)> = fetch_env
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .collect();
+    if refresh {
+        envs.push(("ALPINE_FORCE_REFRESH", "1"));
+    }
 
     let ctx = run_recipe_phase_json_with_defines_and_env(
         &recipe_bin.path,
@@ -101,9 +106,7 @@ pub fn preseed_alpine_rootfs_source_assets(
 }
 
 fn downloads_work_dir(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
-    let normalized = normalize_distro_id(distro_id, "Alpine rootfs source preseed")?;
-    Ok(repo_root
-        .join(".artifacts/work")
-        .join(normalized)
-        .join("downloads"))
+    Ok(WorkspaceManager::new(repo_root)?
+        .distro_dir(distro_id)?
+        .join(DOWNLOADS_NAMESPACE))
 }
@@ -1,6 +1,7 @@
 //! Shared Linux kernel recipe wrapper.
 
-use super::{find_recipe, run_recipe_json_with_defines};
+use super::{find_recipe, run_recipe_phase_json_with_defines_and_env};
+use crate::pipeline::kernel::KERNEL_MIRRORS_ENV;
 use anyhow::Result;
 use distro_spec::shared::KernelSource;
 use std::path::{Path, PathBuf};
@@ -23,6 +24,14 @@ impl LinuxPaths {
     }
 }
 
+/// Output directory name for a distro crate dir, e.g. `AcornOS` -> `acorn`.
+fn distro_output_name(monorepo_dir: &Path, base_dir: &Path) -> Result<String> {
+    let dir_name = base_dir.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    Ok(crate::repo_config::RepoConfig::load(monorepo_dir)?
+        .resolve_distro_alias(dir_name)
+        .unwrap_or_else(|| dir_name.to_string()))
+}
+
 /// Run the linux.rhai recipe and return the output paths.
 ///
 /// # Arguments
@@ -34,30 +43,20 @@ pub fn linux(
     _kernel_source: &KernelSource,
     _module_install_path: &str,
 ) -> Result<LinuxPaths> {
-    let monorepo_dir = base_dir
-        .parent()
-        .map(|p| p.to_path_buf())
-        .unwrap_or_else(|| base_dir.to_path_buf());
+    let layout = crate::repo_layout::RepoLayout::for_distro_dir(base_dir);
+    let monorepo_dir = layout.root().to_path_buf();
 
     let downloads_dir = base_dir.join("downloads");
-    let kernel_artifact_root = monorepo_dir
-        .join(".artifacts/kernel")
-        .join(
-            match base_dir.file_name().and_then(|s| s.to_str()).unwrap_or("") {
-                "leviso" => "levitate",
-                "AcornOS" => "acorn",
-                "IuppiterOS" => "iuppiter",
-                "RalphOS" => "ralph",
-                other => other,
-            },
-        )
+    let kernel_artifact_root = crate::repo_config::artifacts_root(&monorepo_dir)?
+        .join("kernel")
+        .join(distro_output_name(&monorepo_dir, base_dir)?)
         .join("current")
         .to_string_lossy()
         .to_string();
 
     // Single SSOT kernel recipe for all distros (parity). Distro-specific overrides
     // (e.g. <distro>/deps/linux.rhai) are intentionally ignored.
-    let shared_recipe = monorepo_dir.join("distro-builder/recipes/linux.rhai");
+    let shared_recipe = layout.recipes_dir().join("linux.rhai");
     if !shared_recipe.exists() {
         anyhow::bail!(
             "Linux recipe not found at expected shared path:\n  - {}",
@@ -81,18 +80,27 @@ pub fn linux(
     ];
 
     // Find and run recipe, parse JSON output
-    let recipes_dir = monorepo_dir.join("distro-builder/recipes");
+    let recipes_dir = layout.recipes_dir().to_path_buf();
     let recipe_bin = find_recipe(&monorepo_dir)?;
-    let ctx = run_recipe_json_with_defines(
+    let mirrors = crate::repo_config::kernel_mirrors(&monorepo_dir)?.join("\n");
+    let fetch_env = crate::fetch::recipe_fetch_env(
+        &monorepo_dir,
+        &distro_output_name(&monorepo_dir, base_dir)?,
+    );
+    let mut envs = vec![(KERNEL_MIRRORS_ENV, mirrors.as_str())];
+    envs.extend(fetch_env.iter().map(|(key, value)| (*key, value.as_str())));
+    let ctx = run_recipe_phase_json_with_defines_and_env(
         &recipe_bin.path,
+        "install",
         &recipe_path,
         &downloads_dir,
         &defines,
+        &envs,
         Some(&recipes_dir),
     )?;
 
     // Extract paths from ctx (recipe sets these)
-    let output_dir = crate::artifact_store::central_output_dir_for_distro(base_dir);
+    let output_dir = crate::artifact_store::central_output_dir_for_distro(base_dir)?;
 
     let source = ctx["source_path"]
         .as_str()
@@ -7,7 +7,8 @@
 //! 1. `RECIPE_BIN` env var (path to binary)
 //! 2. `RECIPE_SRC` env var (path to source, will build)
 //! 3. Workspace binary under `target/{debug,release}/recipe`
-//! 4. Monorepo submodule (`../tools/recipe`, built from source if needed)
+//! 4. Monorepo submodule (`../tools/recipe`, built from source if needed;
+//!    skipped in the standalone layout)
 //! 5. System PATH (`which recipe`)
 
 pub mod alpine;
@@ -94,7 +95,7 @@ impl RecipeBinary {
 
 /// Find the recipe binary using the resolution order.
 pub fn find_recipe(monorepo_dir: &Path) -> Result<RecipeBinary> {
-    let submodule = monorepo_dir.join("tools/recipe");
+    let submodule = crate::repo_layout::layout_or_default(monorepo_dir).recipe_source_dir();
 
     // 1. Check RECIPE_BIN env var
     if let Ok(bin_path) = env::var("RECIPE_BIN") {
@@ -154,8 +155,11 @@ pub fn find_recipe(monorepo_dir: &Path) -> Result<RecipeBinary> {
     }
 
     // 4. Check monorepo submodule and build if needed.
-    if submodule.join("Cargo.toml").exists() {
-        return build_from_source(&submodule, monorepo_dir, RecipeSource::Monorepo);
+    if let Some(submodule) = submodule
+        .as_deref()
+        .filter(|dir| dir.join("Cargo.toml").exists())
+    {
+        return build_from_source(submodule, monorepo_dir, RecipeSource::Monorepo);
     }
 
     // 5. Check system PATH as final fallback.
@@ -177,7 +181,53 @@ pub fn find_recipe(monorepo_dir: &Path) -> Result<RecipeBinary> {
          - Ensure tools/recipe is checked out in this monorepo\n\
          - Install recipe to PATH",
         monorepo_dir.join("target").display(),
-        submodule.display()
+        submodule.as_deref().map_or_else(
+            || "(standalone layout)".to_string(),
+            |dir| dir.display().to_string()
+        )
+    )
+}
+
+/// Where [`find_recipe`] would take the binary from, without building it.
+pub fn locate_recipe(monorepo_dir: &Path) -> Result<String> {
+    if let Ok(bin_path) = env::var("RECIPE_BIN") {
+        let binary = RecipeBinary {
+            path: PathBuf::from(&bin_path),
+        };
+        if binary.is_valid() {
+            return Ok(format!("RECIPE_BIN ({})", bin_path));
+        }
+        bail!(
+            "RECIPE_BIN points to a missing or non-executable path: {}",
+            bin_path
+        );
+    }
+    if let Ok(src_path) = env::var("RECIPE_SRC") {
+        if Path::new(&src_path).join("Cargo.toml").exists() {
+            return Ok(format!("RECIPE_SRC ({}), built on first use", src_path));
+        }
+        bail!("RECIPE_SRC is not a valid Cargo crate: {}", src_path);
+    }
+    for profile in ["release", "debug"] {
+        let binary = RecipeBinary {
+            path: monorepo_dir.join("target").join(profile).join("recipe"),
+        };
+        if binary.is_valid() {
+            return Ok(binary.path.display().to_string());
+        }
+    }
+    if let Some(submodule) = crate::repo_layout::layout_or_default(monorepo_dir)
+        .recipe_source_dir()
+        .filter(|dir| dir.join("Cargo.toml").exists())
+    {
+        return Ok(format!("{}, built on first use", submodule.display()));
+    }
+    if let Ok(path) = which::which("recipe") {
+        return Ok(path.display().to_string());
+    }
+    bail!(
+        "Could not find recipe binary (searched RECIPE_BIN, RECIPE_SRC, {}, the tools/recipe checkout and PATH)",
+        monorepo_dir.join("target").display()
     )
 }
 
@@ -337,6 +387,10 @@ pub fn run_recipe_phase_json_with_defines_and_env(
         cmd.env(key, value);
     }
 
+    // Kernel builds run here; limit them before the hooks below are set.
+    let mut limited = crate::resource_limits::limit(cmd)?;
+    let cmd = &mut limited.command;
+
     #[cfg(unix)]
     {
         // Ensure recipe subprocesses cannot outlive distro-builder when parent gets cancelled.
@@ -351,14 +405,20 @@ pub fn run_recipe_phase_json_with_defines_and_env(
         }
     }
 
+    crate::supervise::check_interrupted()?;
+    crate::supervise::prepare(cmd);
     let mut child = cmd
+        .stdin(Stdio::null())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .spawn()
         .with_context(|| format!("Failed to execute recipe: {}", recipe_bin.display()))?;
+    let supervised = crate::supervise::register(child.id());
     let status = child
         .wait()
         .with_context(|| format!("Failed to execute recipe: {}", recipe_bin.display()))?;
+    drop(supervised);
+    crate::supervise::check_interrupted()?;
 
     if !status.success() {
         bail!(
@@ -399,7 +459,7 @@ pub fn install_tools(base_dir: &Path) -> Result<()> {
 
     let downloads_dir = base_dir.join("downloads");
     let staging_bin =
-        crate::artifact_store::central_output_dir_for_distro(base_dir).join("staging/usr/bin");
+        crate::artifact_store::central_output_dir_for_distro(base_dir)?.join("staging/usr/bin");
 
     // Find recipe binary once
     let recipe_bin = find_recipe(&monorepo_dir)?;
@@ -5,7 +5,7 @@ use std::path::{Path, PathBuf};
 use super::{
     find_recipe, run_recipe_phase_json_with_defines, run_recipe_phase_json_with_defines_and_env,
 };
-use crate::pipeline::paths::normalize_distro_id;
+use crate::workspace::{WorkspaceManager, DOWNLOADS_NAMESPACE};
 
 #[derive(Debug, Clone)]
 pub struct RootfsSourceRecipeSpec {
@@ -14,11 +14,7 @@ pub struct RootfsSourceRecipeSpec {
 }
 
 fn resolve_recipe_path(repo_root: &Path, recipe_script: &Path) -> PathBuf {
-    if recipe_script.is_absolute() {
-        recipe_script.to_path_buf()
-    } else {
-        repo_root.join(recipe_script)
-    }
+    crate::repo_layout::layout_or_default(repo_root).resolve(recipe_script)
 }
 
 pub fn materialize_rootfs_from_recipe(
@@ -108,11 +104,14 @@ pub fn preseed_rootfs_source_dvd(
         )
     })?;
 
-    let envs = if refresh {
-        vec![("ROOTFS_SOURCE_FORCE_REFRESH", "1")]
-    } else {
-        Vec::new()
-    };
+    let fetch_env = crate::fetch::recipe_fetch_env(repo_root, distro_id);
+    let mut envs: Vec<(&str, &str)> = fetch_env
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .collect();
+    if refresh {
+        envs.push(("ROOTFS_SOURCE_FORCE_REFRESH", "1"));
+    }
 
     let ctx = run_recipe_phase_json_with_defines_and_env(
         &recipe_bin.path,
@@ -142,9 +141,7 @@ pub fn preseed_rootfs_source_dvd(
 }
 
 fn downloads_work_dir(repo_root: &Path, distro_id: &str) -> Result<PathBuf> {
-    let normalized = normalize_distro_id(distro_id, "rootfs source preseed")?;
-    Ok(repo_root
-        .join(".artifacts/work")
-        .join(normalized)
-        .join("downloads"))
+    Ok(WorkspaceManager::new(repo_root)?
+        .distro_dir(distro_id)?
+        .join(DOWNLOADS_NAMESPACE))
 }
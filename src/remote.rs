@@ -0,0 +1,383 @@
+//! Running builder commands on a remote host over SSH.
+//!
+//! `distro-builder remote <host> <command>...` runs `<command>` in a copy of
+//! the checkout on `<host>` and brings the results back:
+//!
+//! 1. the checkout (without `target/` and the artifacts root) is rsynced to
+//!    the remote directory;
+//! 2. the artifact store (`blobs/`, `index/`, `tags/`) is pushed, so the
+//!    remote restores cached kernels and images instead of rebuilding them.
+//!    Blobs are content-addressed, so existing ones are never re-sent;
+//! 3. the command runs with its output streamed to the local terminal, on a
+//!    forced remote terminal unless `--json` asks for clean stdout;
+//! 4. `out/` (run directories and release artifacts) and the store are
+//!    pulled back into the local artifacts root, also when the command
+//!    failed, so its logs are at hand.
+//!
+//! The remote exit code maps back to the same [`ErrorKind`], so scripts see
+//! what a local run would report. Settings live in `distro-builder.toml`:
+//!
+//! ```toml
+//! [remote]
+//! # Checkout copy on the remote, relative to the remote home directory.
+//! dir = "src/levitate"
+//! # How the remote invokes the builder.
+//! command = "cargo run -q --release --bin distro-builder --"
+//! ssh_options = ["-p", "2222"]
+//! ```
+//!
+//! The remote always uses `<dir>/.artifacts` as its artifacts root.
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::artifact_store::{DEFAULT_OUTPUT_SUBDIR, DEFAULT_STORE_DIR};
+use crate::error::{ErrorKind, ResultExt};
+use crate::preflight::check_required_tools;
+use crate::process::Cmd;
+use crate::repo_config::{artifacts_root, RepoConfig, OUTPUT_ROOT_ENV};
+
+/// Remote directory when `remote.dir` is not configured, joined with the
+/// checkout's directory name.
+pub const DEFAULT_REMOTE_DIR: &str = "distro-builder-remote";
+
+/// Builder invocation when `remote.command` is not configured.
+pub const DEFAULT_REMOTE_COMMAND: &str = "distro-builder";
+
+/// Store directories synced in both directions.
+const STORE_DIRS: &[&str] = &["blobs", "index", "tags"];
+
+/// ssh's own exit code for connection and authentication failures.
+const SSH_FAILURE_EXIT: i32 = 255;
+
+/// `[remote]` in `distro-builder.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteConfig {
+    pub dir: Option<String>,
+    pub command: Option<String>,
+    /// Extra arguments for ssh (also used by rsync's transport).
+    pub ssh_options: Vec<String>,
+}
+
+/// A remote host and where the checkout lives on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteBuild {
+    pub host: String,
+    pub dir: String,
+    pub command: String,
+    pub ssh_options: Vec<String>,
+    /// Keep stdout byte-exact for `--json`: no forced terminal.
+    pub json_output: bool,
+    repo_root: PathBuf,
+    artifacts_root: PathBuf,
+}
+
+impl RemoteBuild {
+    /// Remote build of the checkout at `repo_root` on `host`, configured by
+    /// its `distro-builder.toml`.
+    pub fn load(repo_root: &Path, host: &str) -> Result<Self> {
+        let config = RepoConfig::load(repo_root)?.remote;
+        Self::new(repo_root, artifacts_root(repo_root)?, host, config)
+    }
+
+    fn new(
+        repo_root: &Path,
+        artifacts_root: PathBuf,
+        host: &str,
+        config: RemoteConfig,
+    ) -> Result<Self> {
+        if host.is_empty() || host.starts_with('-') || host.contains(char::is_whitespace) {
+            return Err(anyhow!("invalid remote host '{}'", host)).error_kind(ErrorKind::Usage);
+        }
+        let dir = match config.dir {
+            Some(dir) => dir,
+            None => {
+                let name = repo_root
+                    .file_name()
+                    .ok_or_else(|| anyhow!("checkout '{}' has no name", repo_root.display()))?;
+                format!("{}/{}", DEFAULT_REMOTE_DIR, name.to_string_lossy())
+            }
+        };
+        if dir.is_empty() || dir.split('/').any(|part| part == "..") {
+            return Err(anyhow!(
+                "invalid remote.dir '{}': use a path inside the remote home or an absolute path",
+                dir
+            ))
+            .error_kind(ErrorKind::InvalidConfig);
+        }
+        Ok(Self {
+            host: host.to_string(),
+            dir: dir.trim_end_matches('/').to_string(),
+            command: config
+                .command
+                .unwrap_or_else(|| DEFAULT_REMOTE_COMMAND.to_string()),
+            ssh_options: config.ssh_options,
+            json_output: false,
+            repo_root: repo_root.to_path_buf(),
+            artifacts_root,
+        })
+    }
+
+    /// Run without a forced remote terminal, which would turn LF into CRLF
+    /// and merge stderr into stdout.
+    pub fn with_json_output(mut self, json_output: bool) -> Self {
+        self.json_output = json_output;
+        self
+    }
+
+    /// Sync inputs, run `args` remotely, then pull results back. The pull
+    /// happens even when the command fails.
+    pub fn run(&self, args: &[String]) -> Result<()> {
+        check_required_tools(&[("ssh", "openssh"), ("rsync", "rsync")])?;
+        println!("Remote build on {}:{}", self.host, self.dir);
+        self.push()?;
+        let result = self.exec(args);
+        let pulled = self.pull();
+        result?;
+        pulled
+    }
+
+    fn push(&self) -> Result<()> {
+        self.ssh(&format!("mkdir -p {}", shell_quote(&self.remote_store())))
+            .error_msg(format!("Failed to prepare {}:{}", self.host, self.dir))
+            .run()?;
+        println!("  Syncing checkout");
+        let mut excludes = vec!["--exclude=/target/".to_string()];
+        excludes.push(format!("--exclude=/{}/", DEFAULT_STORE_DIR));
+        if let Ok(rel) = self.artifacts_root.strip_prefix(&self.repo_root) {
+            excludes.push(format!("--exclude=/{}/", rel.display()));
+        }
+        self.rsync()
+            .args(["--delete"])
+            .args(excludes)
+            .arg(format!("{}/", self.repo_root.display()))
+            .arg(self.remote_path(&self.dir))
+            .error_msg("Failed to sync the checkout to the remote")
+            .run_interactive()?;
+        println!("  Pushing artifact store");
+        for (local, remote) in self.store_pairs() {
+            if local.is_dir() {
+                self.sync_store_dir(&format!("{}/", local.display()), &self.remote_path(&remote))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn pull(&self) -> Result<()> {
+        println!("  Pulling outputs and artifact store");
+        let out = self.artifacts_root.join(DEFAULT_OUTPUT_SUBDIR);
+        std::fs::create_dir_all(&out)?;
+        self.rsync()
+            .arg("--update")
+            .arg(self.remote_path(&format!(
+                "{}/{}/",
+                self.remote_store(),
+                DEFAULT_OUTPUT_SUBDIR
+            )))
+            .arg_path(&out)
+            .allow_fail()
+            .run_interactive()?;
+        for (local, remote) in self.store_pairs() {
+            std::fs::create_dir_all(&local)?;
+            self.sync_store_dir(
+                &self.remote_path(&format!("{}/", remote)),
+                &local.display().to_string(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn exec(&self, args: &[String]) -> Result<()> {
+        let status = self
+            .ssh(&self.remote_command(args))
+            .allow_fail()
+            .run_interactive()?;
+        match status.code() {
+            Some(0) => Ok(()),
+            Some(SSH_FAILURE_EXIT) => {
+                Err(anyhow!("ssh to {} failed", self.host)).error_kind(ErrorKind::ToolFailed)
+            }
+            Some(code) => Err(anyhow!(
+                "remote command failed on {} (exit code {})",
+                self.host,
+                code
+            ))
+            .error_kind(ErrorKind::from_exit_code(code)),
+            None => bail!("ssh to {} was killed by a signal", self.host),
+        }
+    }
+
+    /// Shell command the remote runs for `args`.
+    fn remote_command(&self, args: &[String]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+        format!(
+            "cd {} && {}={} {} {}",
+            shell_quote(&self.dir),
+            OUTPUT_ROOT_ENV,
+            DEFAULT_STORE_DIR,
+            self.command,
+            args.join(" ")
+        )
+    }
+
+    fn remote_store(&self) -> String {
+        format!("{}/{}", self.dir, DEFAULT_STORE_DIR)
+    }
+
+    fn remote_path(&self, path: &str) -> String {
+        format!("{}:{}", self.host, path)
+    }
+
+    fn store_pairs(&self) -> Vec<(PathBuf, String)> {
+        STORE_DIRS
+            .iter()
+            .map(|dir| {
+                (
+                    self.artifacts_root.join(dir),
+                    format!("{}/{}", self.remote_store(), dir),
+                )
+            })
+            .collect()
+    }
+
+    /// Blobs never change once written; index entries and tags may be
+    /// rewritten, so the newer copy wins.
+    fn sync_store_dir(&self, from: &str, to: &str) -> Result<()> {
+        let mode = if from.trim_end_matches('/').ends_with("blobs") {
+            "--ignore-existing"
+        } else {
+            "--update"
+        };
+        self.rsync()
+            .arg(mode)
+            .arg(from)
+            .arg(to)
+            .error_msg(format!("Failed to sync {} -> {}", from, to))
+            .run_interactive()?;
+        Ok(())
+    }
+
+    fn ssh(&self, command: &str) -> Cmd {
+        Cmd::new("ssh").args(self.ssh_args(command))
+    }
+
+    /// `ssh -tt`: the forced terminal makes the remote command hang up when
+    /// the local one is interrupted. `--json` runs without it, so stdout
+    /// stays byte-exact and separate from stderr.
+    fn ssh_args(&self, command: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.json_output {
+            args.push("-tt".to_string());
+        }
+        args.extend(self.ssh_options.iter().cloned());
+        args.push(self.host.clone());
+        args.push(command.to_string());
+        args
+    }
+
+    /// `--protect-args` hands remote paths to rsync as-is instead of through
+    /// the remote shell, so a remote dir with spaces stays one path.
+    fn rsync(&self) -> Cmd {
+        let mut transport = vec!["ssh".to_string()];
+        transport.extend(self.ssh_options.iter().map(|option| shell_quote(option)));
+        Cmd::new("rsync")
+            .args(["-a", "--partial", "--protect-args", "-e"])
+            .arg(transport.join(" "))
+    }
+}
+
+/// `value` quoted for a POSIX shell.
+pub fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | ':' | '=' | '@' | ',')
+        });
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(config: RemoteConfig) -> Result<RemoteBuild> {
+        RemoteBuild::new(
+            Path::new("/home/dev/levitate"),
+            PathBuf::from("/home/dev/levitate/.artifacts"),
+            "builder@buildbox",
+            config,
+        )
+    }
+
+    #[test]
+    fn test_remote_command() {
+        let remote = build(RemoteConfig::default()).unwrap();
+        assert_eq!(remote.dir, "distro-builder-remote/levitate");
+        let args: Vec<String> = ["release", "build", "iso", "levitate", "--feature", "it's"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(
+            remote.remote_command(&args),
+            "cd distro-builder-remote/levitate && DISTRO_BUILDER_OUTPUT_ROOT=.artifacts \
+             distro-builder release build iso levitate --feature 'it'\\''s'"
+        );
+        assert_eq!(
+            remote.store_pairs()[0],
+            (
+                PathBuf::from("/home/dev/levitate/.artifacts/blobs"),
+                "distro-builder-remote/levitate/.artifacts/blobs".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let remote = build(RemoteConfig {
+            dir: Some("/srv/build/".to_string()),
+            command: Some("cargo run -q --bin distro-builder --".to_string()),
+            ssh_options: vec!["-p".to_string(), "2222".to_string()],
+        })
+        .unwrap();
+        assert_eq!(remote.dir, "/srv/build");
+        assert!(remote
+            .remote_command(&[])
+            .contains("cargo run -q --bin distro-builder --"));
+
+        assert!(build(RemoteConfig {
+            dir: Some("../elsewhere".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+        for host in ["", "-oProxyCommand=x", "build box"] {
+            assert!(RemoteBuild::new(
+                Path::new("/repo"),
+                PathBuf::from("/repo/.artifacts"),
+                host,
+                RemoteConfig::default()
+            )
+            .is_err());
+        }
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+    }
+
+    #[test]
+    fn test_json_output_runs_without_tty() {
+        let remote = build(RemoteConfig {
+            dir: Some("build dir/levitate".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let command = remote.remote_command(&[]);
+        assert!(command.starts_with("cd 'build dir/levitate' && "));
+        assert_eq!(remote.ssh_args("true"), ["-tt", "builder@buildbox", "true"]);
+        let remote = remote.with_json_output(true);
+        assert_eq!(remote.ssh_args("true"), ["builder@buildbox", "true"]);
+    }
+}
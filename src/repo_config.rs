@@ -0,0 +1,341 @@
+//! Repo-level builder configuration (`<repo>/distro-builder.toml`).
+//!
+//! Settings here apply to every variant in the checkout and describe the
+//! host rather than a distro, e.g. where build outputs live:
+//!
+//! ```toml
+//! # Put .artifacts on a scratch NVMe instead of the repo disk.
+//! output_root = "/scratch/levitate-artifacts"
+//!
+//! # Kernel tarballs: a local mirror is tried first, then these mirrors in
+//! # order (replacing the built-in kernel.org list).
+//! kernel_local_mirror = "/srv/mirror/kernel"
+//! kernel_mirrors = ["https://mirrors.edge.kernel.org/pub/linux/kernel"]
+//!
+//! # Vendored without the LevitateOS superrepo (see `repo_layout`).
+//! # layout = "standalone"
+//!
+//! # CPU/memory limits per external command (see `resource_limits`).
+//! [resource_limits]
+//! cpu_quota = "400%"
+//! memory_max = "8G"
+//!
+//! # Run missing Linux-only tools in a pinned image (see `tool_container`).
+//! [tool_container]
+//! image = "ghcr.io/example/distro-builder-tools@sha256:<digest>"
+//!
+//! # `distro-builder remote <host> ...` settings (see `remote`).
+//! [remote]
+//! dir = "src/levitate"
+//!
+//! # Report stage and build completion (see `notify`).
+//! [notify]
+//! webhook = "https://chat.example.com/hooks/builds"
+//!
+//! # Codecs for store blobs and initramfs archives (see `compression`).
+//! [compression]
+//! store = "zstd:3"
+//! initramfs = "gzip:9"
+//!
+//! # Directory or legacy names -> canonical distro id.
+//! [distro_aliases]
+//! OakOS = "oak"
+//! ```
+//!
+//! Environment variables take precedence over the file.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::artifact_store::DEFAULT_STORE_DIR;
+use crate::compression::CompressionConfig;
+use crate::error::{ErrorKind, ResultExt};
+use crate::notify::NotifyConfig;
+use crate::remote::RemoteConfig;
+use crate::repo_layout::{layout_or_default, LayoutMode};
+use crate::resource_limits::ResourceLimitsConfig;
+use crate::tool_container::ToolContainerConfig;
+
+/// Repo-level configuration file name.
+pub const REPO_CONFIG_FILENAME: &str = "distro-builder.toml";
+
+/// Overrides where `.artifacts` (outputs, kernels, work dirs and the store)
+/// lives. Takes precedence over `output_root` in the config file.
+pub const OUTPUT_ROOT_ENV: &str = "DISTRO_BUILDER_OUTPUT_ROOT";
+
+/// Local kernel mirror (a directory or URL laid out like kernel.org's
+/// `pub/linux/kernel/`), tried before every configured mirror.
+pub const KERNEL_MIRROR_ENV: &str = "DISTRO_BUILDER_KERNEL_MIRROR";
+
+/// Mirrors tried in order when `kernel_mirrors` is not configured.
+pub const DEFAULT_KERNEL_MIRRORS: &[&str] = &[
+    "https://cdn.kernel.org/pub/linux/kernel",
+    "https://mirrors.edge.kernel.org/pub/linux/kernel",
+    "https://www.kernel.org/pub/linux/kernel",
+];
+
+/// Aliases known without configuration: legacy distro crate directory names
+/// and their lowercase forms.
+const BUILTIN_DISTRO_ALIASES: &[(&str, &str)] = &[
+    ("leviso", "levitate"),
+    ("levitate", "levitate"),
+    ("acornos", "acorn"),
+    ("acorn", "acorn"),
+    ("iuppiteros", "iuppiter"),
+    ("iuppiter", "iuppiter"),
+    ("ralphos", "ralph"),
+    ("ralph", "ralph"),
+];
+
+/// Parsed `distro-builder.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    /// Replacement for `<repo>/.artifacts`; relative paths are resolved
+    /// against the repo root.
+    pub output_root: Option<PathBuf>,
+    /// Extra name -> distro id mappings, matched case-insensitively and
+    /// layered over the built-in legacy aliases.
+    pub distro_aliases: BTreeMap<String, String>,
+    /// Directory or URL tried before `kernel_mirrors`; relative paths are
+    /// resolved against the repo root.
+    pub kernel_local_mirror: Option<String>,
+    /// Kernel tarball mirrors, tried in order. Replaces
+    /// [`DEFAULT_KERNEL_MIRRORS`] when non-empty.
+    pub kernel_mirrors: Vec<String>,
+    /// Checkout layout; detected from the directory when unset.
+    pub layout: Option<LayoutMode>,
+    /// Limits applied to every external command.
+    pub resource_limits: ResourceLimitsConfig,
+    /// Container fallback for Linux-only tools missing on the host.
+    pub tool_container: ToolContainerConfig,
+    /// Remote host settings for `distro-builder remote`.
+    pub remote: RemoteConfig,
+    /// Build notification channels.
+    pub notify: NotifyConfig,
+    /// Codecs for store blobs and initramfs archives.
+    pub compression: CompressionConfig,
+}
+
+impl RepoConfig {
+    /// Load `<repo_root>/distro-builder.toml`, or defaults when absent.
+    pub fn load(repo_root: &Path) -> Result<Self> {
+        let path = repo_root.join(REPO_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+            .error_kind(ErrorKind::InvalidConfig)
+    }
+
+    /// Like [`load`](Self::load), reporting a broken file and falling back
+    /// to defaults, for callers that cannot fail.
+    pub fn load_or_default(repo_root: &Path) -> Self {
+        Self::load(repo_root).unwrap_or_else(|err| {
+            eprintln!("  [WARN] {:#}; using default builder configuration", err);
+            Self::default()
+        })
+    }
+
+    /// Distro id for an alias such as a legacy crate directory name.
+    pub fn resolve_distro_alias(&self, name: &str) -> Option<String> {
+        let key = name.trim().to_ascii_lowercase();
+        self.distro_aliases
+            .iter()
+            .find(|(alias, _)| alias.to_ascii_lowercase() == key)
+            .map(|(_, id)| id.clone())
+            .or_else(|| {
+                BUILTIN_DISTRO_ALIASES
+                    .iter()
+                    .find(|(alias, _)| *alias == key)
+                    .map(|(_, id)| id.to_string())
+            })
+    }
+}
+
+/// Canonical distro id for `name` in `repo_root`.
+///
+/// Aliases resolve first; otherwise any name with a
+/// `distro-variants/<name>` directory is its own id.
+pub fn canonical_distro_id(repo_root: &Path, name: &str, purpose: &str) -> Result<String> {
+    if let Some(id) = RepoConfig::load(repo_root)?.resolve_distro_alias(name) {
+        return Ok(id);
+    }
+    let name = name.trim();
+    if !name.is_empty()
+        && !name.contains(['/', '\\'])
+        && layout_or_default(repo_root).variant_dir(name).is_dir()
+    {
+        return Ok(name.to_string());
+    }
+    bail!(
+        "unsupported distro '{}' for {} resolution\n\
+Remediation: add distro-variants/{} or map the name under [distro_aliases] in {}",
+        name,
+        purpose,
+        name,
+        REPO_CONFIG_FILENAME
+    )
+}
+
+/// Root holding `out/`, `kernel/`, `work/` and the artifact store.
+///
+/// Resolution order: [`OUTPUT_ROOT_ENV`], then `output_root` from
+/// `distro-builder.toml`, then `<repo_root>/.artifacts`.
+pub fn artifacts_root(repo_root: &Path) -> Result<PathBuf> {
+    artifacts_root_with_env(repo_root, |key| std::env::var_os(key))
+}
+
+/// Like [`artifacts_root`] with an explicit environment lookup.
+pub fn artifacts_root_with_env(
+    repo_root: &Path,
+    env: impl Fn(&str) -> Option<std::ffi::OsString>,
+) -> Result<PathBuf> {
+    if let Some(root) = env(OUTPUT_ROOT_ENV).filter(|value| !value.is_empty()) {
+        return Ok(repo_root.join(root));
+    }
+    match RepoConfig::load(repo_root)?.output_root {
+        Some(root) => Ok(repo_root.join(root)),
+        None => Ok(repo_root.join(DEFAULT_STORE_DIR)),
+    }
+}
+
+/// Base URLs kernel tarballs are fetched from, in failover order.
+///
+/// Resolution order: [`KERNEL_MIRROR_ENV`], then `kernel_local_mirror`, then
+/// `kernel_mirrors` (or [`DEFAULT_KERNEL_MIRRORS`]). Local directories become
+/// `file://` URLs; duplicates are dropped.
+pub fn kernel_mirrors(repo_root: &Path) -> Result<Vec<String>> {
+    kernel_mirrors_with_env(repo_root, |key| std::env::var_os(key))
+}
+
+/// Like [`kernel_mirrors`] with an explicit environment lookup.
+pub fn kernel_mirrors_with_env(
+    repo_root: &Path,
+    env: impl Fn(&str) -> Option<std::ffi::OsString>,
+) -> Result<Vec<String>> {
+    let config = RepoConfig::load(repo_root)?;
+    let local = env(KERNEL_MIRROR_ENV)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string_lossy().into_owned());
+    let configured = if config.kernel_mirrors.is_empty() {
+        DEFAULT_KERNEL_MIRRORS
+            .iter()
+            .map(|m| m.to_string())
+            .collect()
+    } else {
+        config.kernel_mirrors
+    };
+
+    let mut mirrors: Vec<String> = Vec::new();
+    for entry in local
+        .into_iter()
+        .chain(config.kernel_local_mirror)
+        .chain(configured)
+    {
+        let entry = entry.trim().trim_end_matches('/');
+        if entry.is_empty() {
+            continue;
+        }
+        let url = if entry.contains("://") {
+            entry.to_string()
+        } else {
+            format!("file://{}", repo_root.join(entry).display())
+        };
+        if !mirrors.contains(&url) {
+            mirrors.push(url);
+        }
+    }
+    Ok(mirrors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_output_root_resolution() {
+        let repo = TempDir::new().unwrap();
+        let no_env = |_: &str| None;
+        assert_eq!(
+            artifacts_root_with_env(repo.path(), no_env).unwrap(),
+            repo.path().join(".artifacts")
+        );
+
+        std::fs::write(
+            repo.path().join(REPO_CONFIG_FILENAME),
+            "output_root = \"../scratch\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            artifacts_root_with_env(repo.path(), no_env).unwrap(),
+            repo.path().join("../scratch")
+        );
+
+        let env = |_: &str| Some("/mnt/nvme/artifacts".into());
+        assert_eq!(
+            artifacts_root_with_env(repo.path(), env).unwrap(),
+            PathBuf::from("/mnt/nvme/artifacts")
+        );
+
+        std::fs::write(repo.path().join(REPO_CONFIG_FILENAME), "outptu_root = 1\n").unwrap();
+        assert!(artifacts_root_with_env(repo.path(), no_env).is_err());
+    }
+
+    #[test]
+    fn test_kernel_mirror_order() {
+        let repo = TempDir::new().unwrap();
+        let no_env = |_: &str| None;
+        assert_eq!(
+            kernel_mirrors_with_env(repo.path(), no_env).unwrap(),
+            DEFAULT_KERNEL_MIRRORS
+        );
+
+        std::fs::write(
+            repo.path().join(REPO_CONFIG_FILENAME),
+            "kernel_local_mirror = \"mirror/\"\n\
+             kernel_mirrors = [\"https://example.org/linux/\", \"https://example.org/linux\"]\n",
+        )
+        .unwrap();
+        let env = |_: &str| Some("https://ci-cache.internal/kernel".into());
+        assert_eq!(
+            kernel_mirrors_with_env(repo.path(), env).unwrap(),
+            vec![
+                "https://ci-cache.internal/kernel".to_string(),
+                format!("file://{}", repo.path().join("mirror").display()),
+                "https://example.org/linux".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_distro_aliases() {
+        let repo = TempDir::new().unwrap();
+        std::fs::write(
+            repo.path().join(REPO_CONFIG_FILENAME),
+            "[distro_aliases]\nOakOS = \"oak\"\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(repo.path().join("distro-variants/birch")).unwrap();
+
+        assert_eq!(
+            canonical_distro_id(repo.path(), "oakos", "test").unwrap(),
+            "oak"
+        );
+        assert_eq!(
+            canonical_distro_id(repo.path(), "AcornOS", "test").unwrap(),
+            "acorn"
+        );
+        assert_eq!(
+            canonical_distro_id(repo.path(), "birch", "test").unwrap(),
+            "birch"
+        );
+        assert!(canonical_distro_id(repo.path(), "elm", "test").is_err());
+    }
+}
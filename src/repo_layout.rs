@@ -0,0 +1,290 @@
+//! Where a checkout keeps variants, recipes and builder assets.
+//!
+//! The builder grew up inside the LevitateOS superrepo, where the checkout
+//! root holds `distro-variants/`, this crate as `distro-builder/` and the
+//! recipe tool as `tools/recipe`. Contract files name recipes relative to
+//! that root (`distro-builder/recipes/linux.rhai`). [`RepoLayout`] keeps
+//! those assumptions in one place and adds a standalone mode for projects
+//! that vendor only this crate:
+//!
+//! ```text
+//! monorepo                       standalone
+//! <root>/distro-variants/        <crate>/distro-variants/
+//! <root>/distro-builder/         <crate>/
+//! <root>/distro-builder/recipes/ <crate>/recipes/
+//! <root>/tools/recipe/           (recipe from RECIPE_BIN/RECIPE_SRC or PATH)
+//! ```
+//!
+//! The mode comes from [`LAYOUT_ENV`], then `layout` in
+//! `distro-builder.toml`, then the shape of the directory: a root without a
+//! `distro-builder/` subdirectory that has its own `Cargo.toml` and
+//! `recipes/` is standalone. [`VARIANTS_DIR_ENV`] and [`RECIPES_DIR_ENV`]
+//! override single directories in either mode.
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, ErrorKind};
+use crate::repo_config::RepoConfig;
+
+/// Checkout root, overriding discovery (used by the CLI and embedders).
+pub const REPO_ROOT_ENV: &str = "DISTRO_BUILDER_REPO_ROOT";
+
+/// Forces the layout mode: `monorepo` or `standalone`.
+pub const LAYOUT_ENV: &str = "DISTRO_BUILDER_LAYOUT";
+
+/// Replacement for the `distro-variants` directory.
+pub const VARIANTS_DIR_ENV: &str = "DISTRO_BUILDER_VARIANTS_DIR";
+
+/// Replacement for the recipes directory.
+pub const RECIPES_DIR_ENV: &str = "DISTRO_BUILDER_RECIPES_DIR";
+
+/// Contract paths are written relative to the monorepo root with this prefix
+/// for files shipped in this crate.
+const BUILDER_PREFIX: &str = "distro-builder/";
+
+/// How the checkout is organized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutMode {
+    /// LevitateOS superrepo: this crate is `<root>/distro-builder`.
+    Monorepo,
+    /// Everything under this crate's directory.
+    Standalone,
+}
+
+impl LayoutMode {
+    fn parse(value: &str) -> Result<Self> {
+        match value.trim() {
+            "monorepo" => Ok(Self::Monorepo),
+            "standalone" => Ok(Self::Standalone),
+            other => Err(Error::new(
+                ErrorKind::InvalidConfig,
+                anyhow!(
+                    "invalid {} '{}': expected 'monorepo' or 'standalone'",
+                    LAYOUT_ENV,
+                    other
+                ),
+            )
+            .into()),
+        }
+    }
+}
+
+/// Resolved directories of one checkout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoLayout {
+    mode: LayoutMode,
+    root: PathBuf,
+    variants_dir: PathBuf,
+    builder_dir: PathBuf,
+    recipes_dir: PathBuf,
+}
+
+impl RepoLayout {
+    /// Layout of the checkout at `root`.
+    pub fn at(root: &Path) -> Result<Self> {
+        Self::at_with_env(root, |key| std::env::var_os(key))
+    }
+
+    /// Like [`at`](Self::at) with an explicit environment lookup.
+    pub fn at_with_env(root: &Path, env: impl Fn(&str) -> Option<OsString>) -> Result<Self> {
+        let env = |key: &str| env(key).filter(|value| !value.is_empty());
+        let mode = match env(LAYOUT_ENV) {
+            Some(value) => LayoutMode::parse(&value.to_string_lossy())?,
+            None => match RepoConfig::load(root)?.layout {
+                Some(mode) => mode,
+                None => detect_mode(root),
+            },
+        };
+        let mut layout = Self::with_mode(root, mode);
+        if let Some(dir) = env(VARIANTS_DIR_ENV) {
+            layout.variants_dir = root.join(dir);
+        }
+        if let Some(dir) = env(RECIPES_DIR_ENV) {
+            layout.recipes_dir = root.join(dir);
+        }
+        Ok(layout)
+    }
+
+    /// Layout for `mode` at `root` without consulting the environment.
+    pub fn with_mode(root: &Path, mode: LayoutMode) -> Self {
+        let builder_dir = match mode {
+            LayoutMode::Monorepo => root.join("distro-builder"),
+            LayoutMode::Standalone => root.to_path_buf(),
+        };
+        Self {
+            mode,
+            root: root.to_path_buf(),
+            variants_dir: root.join("distro-variants"),
+            recipes_dir: builder_dir.join("recipes"),
+            builder_dir,
+        }
+    }
+
+    /// Find the checkout containing `start`.
+    ///
+    /// [`REPO_ROOT_ENV`] wins; otherwise the nearest ancestor with a
+    /// variants directory is the root.
+    pub fn discover(start: &Path) -> Result<Self> {
+        if let Some(root) = std::env::var_os(REPO_ROOT_ENV).filter(|value| !value.is_empty()) {
+            let layout = Self::at(Path::new(&root))?;
+            if !layout.variants_dir.is_dir() {
+                bail!(
+                    "{} points at '{}', which has no variants directory at '{}'",
+                    REPO_ROOT_ENV,
+                    layout.root.display(),
+                    layout.variants_dir.display()
+                );
+            }
+            return Ok(layout);
+        }
+        for ancestor in start.ancestors() {
+            let layout = Self::at(ancestor)?;
+            if layout.variants_dir.is_dir() {
+                return Ok(layout);
+            }
+        }
+        bail!(
+            "no distro-builder checkout found above '{}'\n\
+Remediation: run from a checkout with distro-variants/, or set {}",
+            start.display(),
+            REPO_ROOT_ENV
+        )
+    }
+
+    /// Layout for a legacy distro crate directory such as `<repo>/AcornOS`.
+    ///
+    /// In the monorepo the checkout root is the parent; a standalone crate
+    /// directory is its own root.
+    pub fn for_distro_dir(base_dir: &Path) -> Self {
+        let candidates = [base_dir.parent(), Some(base_dir)];
+        candidates
+            .into_iter()
+            .flatten()
+            .filter_map(|root| Self::at(root).ok())
+            .find(|layout| layout.variants_dir.is_dir())
+            .unwrap_or_else(|| {
+                Self::with_mode(base_dir.parent().unwrap_or(base_dir), LayoutMode::Monorepo)
+            })
+    }
+
+    pub fn mode(&self) -> LayoutMode {
+        self.mode
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn variants_dir(&self) -> &Path {
+        &self.variants_dir
+    }
+
+    pub fn variant_dir(&self, distro_id: &str) -> PathBuf {
+        self.variants_dir.join(distro_id)
+    }
+
+    /// This crate's directory (embedded asset overrides live here).
+    pub fn builder_dir(&self) -> &Path {
+        &self.builder_dir
+    }
+
+    pub fn recipes_dir(&self) -> &Path {
+        &self.recipes_dir
+    }
+
+    /// Recipe tool sources built on demand, when the layout has them.
+    pub fn recipe_source_dir(&self) -> Option<PathBuf> {
+        match self.mode {
+            LayoutMode::Monorepo => Some(self.root.join("tools/recipe")),
+            LayoutMode::Standalone => None,
+        }
+    }
+
+    /// Resolve a path declared in a contract or variant file.
+    ///
+    /// Absolute paths are kept; `distro-builder/recipes/...` follows the
+    /// recipes directory and other `distro-builder/...` paths follow this
+    /// crate, so monorepo-style declarations work in standalone mode.
+    pub fn resolve(&self, declared: impl AsRef<Path>) -> PathBuf {
+        let declared = declared.as_ref();
+        if declared.is_absolute() {
+            return declared.to_path_buf();
+        }
+        if let Ok(in_builder) = declared.strip_prefix(BUILDER_PREFIX) {
+            if let Ok(recipe) = in_builder.strip_prefix("recipes") {
+                return self.recipes_dir.join(recipe);
+            }
+            return self.builder_dir.join(in_builder);
+        }
+        self.root.join(declared)
+    }
+}
+
+/// Layout of `repo_root` for callers that cannot fail; a broken config or
+/// environment falls back to the monorepo layout with a warning.
+pub fn layout_or_default(repo_root: &Path) -> RepoLayout {
+    RepoLayout::at(repo_root).unwrap_or_else(|err| {
+        eprintln!("  [WARN] {:#}; assuming the monorepo layout", err);
+        RepoLayout::with_mode(repo_root, LayoutMode::Monorepo)
+    })
+}
+
+fn detect_mode(root: &Path) -> LayoutMode {
+    if !root.join("distro-builder").is_dir()
+        && root.join("Cargo.toml").is_file()
+        && root.join("recipes").is_dir()
+    {
+        LayoutMode::Standalone
+    } else {
+        LayoutMode::Monorepo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_monorepo_paths_resolve_in_standalone_mode() {
+        let repo = TempDir::new().unwrap();
+        let no_env = |_: &str| None;
+        let layout = RepoLayout::at_with_env(repo.path(), no_env).unwrap();
+        assert_eq!(layout.mode(), LayoutMode::Monorepo);
+        assert_eq!(
+            layout.resolve("distro-builder/recipes/linux.rhai"),
+            repo.path().join("distro-builder/recipes/linux.rhai")
+        );
+
+        fs::write(repo.path().join("Cargo.toml"), "[package]\n").unwrap();
+        fs::create_dir_all(repo.path().join("recipes")).unwrap();
+        let layout = RepoLayout::at_with_env(repo.path(), no_env).unwrap();
+        assert_eq!(layout.mode(), LayoutMode::Standalone);
+        assert_eq!(
+            layout.resolve("distro-builder/recipes/linux.rhai"),
+            repo.path().join("recipes/linux.rhai")
+        );
+        assert_eq!(
+            layout.resolve("distro-builder/assets/issue"),
+            repo.path().join("assets/issue")
+        );
+        assert_eq!(layout.recipe_source_dir(), None);
+
+        let env = |key: &str| match key {
+            LAYOUT_ENV => Some("monorepo".into()),
+            VARIANTS_DIR_ENV => Some("variants".into()),
+            _ => None,
+        };
+        let layout = RepoLayout::at_with_env(repo.path(), env).unwrap();
+        assert_eq!(layout.mode(), LayoutMode::Monorepo);
+        assert_eq!(layout.variant_dir("oak"), repo.path().join("variants/oak"));
+
+        let bad = |key: &str| (key == LAYOUT_ENV).then(|| "superrepo".into());
+        assert!(RepoLayout::at_with_env(repo.path(), bad).is_err());
+    }
+}
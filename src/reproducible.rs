@@ -0,0 +1,169 @@
+//! Timestamp normalization for reproducible artifacts (`SOURCE_DATE_EPOCH`).
+//!
+//! The epoch comes from [`set_source_date_epoch`] when a build sets one,
+//! otherwise from the `SOURCE_DATE_EPOCH` environment variable. When known
+//! it is:
+//!
+//! - exported to every [`Cmd`](crate::process::Cmd) child, which covers the
+//!   tools that read it themselves: `xorriso` (volume and file dates),
+//!   `mkfs.fat` (volume id and dates), `mtools` and `mkfs.ext4`;
+//! - the `-T` timestamp of every EROFS image (`0` without an epoch);
+//! - the ceiling for file mtimes in cpio archives (see [`clamp_mtimes`]).
+//!
+//! Store blobs are tar archives with zeroed mtimes and need nothing more.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+/// Environment variable defined by reproducible-builds.org.
+pub const SOURCE_DATE_EPOCH_ENV: &str = "SOURCE_DATE_EPOCH";
+
+static OVERRIDE: RwLock<Option<u64>> = RwLock::new(None);
+
+/// Use `epoch` for artifacts built from now on (`None` falls back to the
+/// environment). Returns the previous setting.
+pub fn set_source_date_epoch(epoch: Option<u64>) -> Option<u64> {
+    let mut current = OVERRIDE.write().unwrap_or_else(|err| err.into_inner());
+    std::mem::replace(&mut *current, epoch)
+}
+
+/// Run `f` with `epoch` set, restoring the previous setting afterwards.
+pub fn with_source_date_epoch<T>(epoch: u64, f: impl FnOnce() -> T) -> T {
+    let previous = set_source_date_epoch(Some(epoch));
+    let result = f();
+    set_source_date_epoch(previous);
+    result
+}
+
+/// The epoch in effect, if any. A malformed `SOURCE_DATE_EPOCH` is an error
+/// rather than silently ignored, since the build would not be reproducible.
+pub fn source_date_epoch() -> Result<Option<u64>> {
+    if let Some(epoch) = *OVERRIDE.read().unwrap_or_else(|err| err.into_inner()) {
+        return Ok(Some(epoch));
+    }
+    match std::env::var(SOURCE_DATE_EPOCH_ENV) {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("invalid {}='{}'", SOURCE_DATE_EPOCH_ENV, value)),
+        _ => Ok(None),
+    }
+}
+
+/// The epoch as a time, or `None` without one.
+pub fn source_date_time() -> Result<Option<SystemTime>> {
+    Ok(source_date_epoch()?.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)))
+}
+
+/// `mkfs.erofs -T` value: the epoch, or 0 so images stay reproducible
+/// without one.
+pub fn erofs_timestamp() -> Result<u64> {
+    Ok(source_date_epoch()?.unwrap_or(0))
+}
+
+/// Export the epoch to `cmd` so the tool sees it even inside a tool
+/// container.
+pub(crate) fn export(cmd: &mut Command) -> Result<()> {
+    if let Some(epoch) = source_date_epoch()? {
+        cmd.env(SOURCE_DATE_EPOCH_ENV, epoch.to_string());
+    }
+    Ok(())
+}
+
+/// Set every mtime under `root` that is newer than `epoch` to `epoch`,
+/// without following symlinks. Returns the number of entries changed.
+pub fn clamp_mtimes(root: &Path, epoch: u64) -> Result<usize> {
+    let mut changed = 0;
+    for entry in WalkDir::new(root).follow_links(false) {
+        let entry = entry.with_context(|| format!("Failed to walk {}", root.display()))?;
+        let path = entry.path();
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Failed to stat {}", path.display()))?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if mtime <= epoch {
+            continue;
+        }
+        set_mtime_nofollow(path, epoch)?;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+fn set_mtime_nofollow(path: &Path, epoch: u64) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("path contains NUL: {}", path.display()))?;
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: epoch as libc::time_t,
+            tv_nsec: 0,
+        },
+    ];
+    // SAFETY: c_path is NUL-terminated and times holds two timespecs.
+    let rc = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if rc != 0 {
+        bail!(
+            "Failed to set mtime of {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_override_and_clamp() {
+        let previous = set_source_date_epoch(None);
+        with_source_date_epoch(1_700_000_000, || {
+            assert_eq!(source_date_epoch().unwrap(), Some(1_700_000_000));
+            assert_eq!(erofs_timestamp().unwrap(), 1_700_000_000);
+            let mut cmd = Command::new("true");
+            export(&mut cmd).unwrap();
+            assert!(cmd
+                .get_envs()
+                .any(|(k, v)| k == SOURCE_DATE_EPOCH_ENV && v == Some("1700000000".as_ref())));
+        });
+        set_source_date_epoch(previous);
+
+        let temp = TempDir::new().unwrap();
+        fs::create_dir(temp.path().join("dir")).unwrap();
+        fs::write(temp.path().join("dir/file"), "x").unwrap();
+        std::os::unix::fs::symlink("missing", temp.path().join("link")).unwrap();
+        assert_eq!(clamp_mtimes(temp.path(), 1000).unwrap(), 4);
+        let mtime = fs::symlink_metadata(temp.path().join("link"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(mtime, UNIX_EPOCH + Duration::from_secs(1000));
+        assert_eq!(clamp_mtimes(temp.path(), 1000).unwrap(), 0);
+    }
+}
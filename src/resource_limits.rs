@@ -0,0 +1,549 @@
+//! CPU and memory limits for external build commands.
+//!
+//! A kernel `make -j$(nproc)` or a `mkfs.erofs` over a large rootfs takes
+//! every core and as much page cache as it can get, which starves everything
+//! else on a shared build machine. Limits configured in
+//! `distro-builder.toml` apply to each external command on its own:
+//!
+//! ```toml
+//! [resource_limits]
+//! # Percent of one CPU (systemd CPUQuota), or a CPU count such as "4".
+//! cpu_quota = "400%"
+//! # Bytes, or with a K/M/G/T suffix (powers of 1024).
+//! memory_max = "8G"
+//! # "systemd-run" (default): a transient scope per command.
+//! # "cgroup": a child cgroup of cgroup_parent, which must be a delegated
+//! # cgroup v2 directory with the cpu and memory controllers enabled.
+//! backend = "systemd-run"
+//! # cgroup_parent = "/sys/fs/cgroup/distro-builder"
+//! ```
+//!
+//! [`CPU_QUOTA_ENV`] and [`MEMORY_MAX_ENV`] override the file. The CLI
+//! [`install`]s the limits at startup; commands run through
+//! [`Cmd`](crate::process::Cmd) and recipe phases (kernel builds, rootfs
+//! source recipes) then go through [`limit`]. Without limits configured
+//! nothing is wrapped.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::error::{ErrorKind, ResultExt};
+use crate::repo_config::RepoConfig;
+
+/// Overrides `resource_limits.cpu_quota`; empty disables the CPU limit.
+pub const CPU_QUOTA_ENV: &str = "DISTRO_BUILDER_CPU_QUOTA";
+
+/// Overrides `resource_limits.memory_max`; empty disables the memory limit.
+pub const MEMORY_MAX_ENV: &str = "DISTRO_BUILDER_MEMORY_MAX";
+
+/// `cpu.max` period, in microseconds.
+const CPU_PERIOD_US: u64 = 100_000;
+
+static INSTALLED: OnceLock<ResourceLimits> = OnceLock::new();
+static CGROUP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// How limits are applied to a command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitBackend {
+    /// `systemd-run --scope` around the command.
+    #[default]
+    SystemdRun,
+    /// A cgroup v2 directory the command joins before exec.
+    Cgroup,
+}
+
+impl LimitBackend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::SystemdRun => "systemd-run",
+            Self::Cgroup => "cgroup",
+        }
+    }
+}
+
+/// `[resource_limits]` in `distro-builder.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimitsConfig {
+    pub cpu_quota: Option<String>,
+    pub memory_max: Option<String>,
+    pub backend: LimitBackend,
+    pub cgroup_parent: Option<PathBuf>,
+}
+
+/// Validated limits for external commands.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// CPU time per scheduling period, in percent of one CPU.
+    pub cpu_quota_percent: Option<u32>,
+    pub memory_max_bytes: Option<u64>,
+    pub backend: LimitBackend,
+    pub cgroup_parent: Option<PathBuf>,
+}
+
+impl ResourceLimits {
+    /// Limits from `<repo_root>/distro-builder.toml` (when a checkout is
+    /// known) with the environment overrides applied.
+    pub fn load(repo_root: Option<&Path>) -> Result<Self> {
+        let config = match repo_root {
+            Some(root) => RepoConfig::load(root)?.resource_limits,
+            None => ResourceLimitsConfig::default(),
+        };
+        Self::from_config(
+            config,
+            std::env::var(CPU_QUOTA_ENV).ok().as_deref(),
+            std::env::var(MEMORY_MAX_ENV).ok().as_deref(),
+        )
+    }
+
+    fn from_config(
+        config: ResourceLimitsConfig,
+        cpu_quota_env: Option<&str>,
+        memory_max_env: Option<&str>,
+    ) -> Result<Self> {
+        let cpu_quota = cpu_quota_env.or(config.cpu_quota.as_deref());
+        let memory_max = memory_max_env.or(config.memory_max.as_deref());
+        let limits = Self {
+            cpu_quota_percent: cpu_quota
+                .map(parse_cpu_quota)
+                .transpose()
+                .map(Option::flatten)
+                .error_kind(ErrorKind::InvalidConfig)?,
+            memory_max_bytes: memory_max
+                .map(parse_memory_max)
+                .transpose()
+                .map(Option::flatten)
+                .error_kind(ErrorKind::InvalidConfig)?,
+            backend: config.backend,
+            cgroup_parent: config.cgroup_parent,
+        };
+        if limits.is_active()
+            && limits.backend == LimitBackend::Cgroup
+            && limits.cgroup_parent.is_none()
+        {
+            return Err(anyhow!(
+                "resource_limits.backend = \"cgroup\" needs cgroup_parent\n\
+Remediation: set cgroup_parent to a delegated cgroup v2 directory, or use backend = \"systemd-run\""
+            ))
+            .error_kind(ErrorKind::InvalidConfig);
+        }
+        Ok(limits)
+    }
+
+    /// Whether any limit is set.
+    pub fn is_active(&self) -> bool {
+        self.cpu_quota_percent.is_some() || self.memory_max_bytes.is_some()
+    }
+
+    /// Check the backend can apply the limits on this host.
+    pub fn check(&self) -> Result<()> {
+        if !self.is_active() {
+            return Ok(());
+        }
+        match self.backend {
+            LimitBackend::SystemdRun => {
+                if find_in_path("systemd-run").is_none() {
+                    return Err(anyhow!(
+                        "resource limits are configured but systemd-run is not installed\n\
+Remediation: install systemd, use backend = \"cgroup\", or unset the limits"
+                    ))
+                    .error_kind(ErrorKind::MissingTool);
+                }
+            }
+            LimitBackend::Cgroup => {
+                let parent = self.cgroup_parent()?;
+                if !parent.join("cgroup.subtree_control").is_file() {
+                    return Err(anyhow!(
+                        "cgroup_parent '{}' is not a cgroup v2 directory",
+                        parent.display()
+                    ))
+                    .error_kind(ErrorKind::InvalidConfig);
+                }
+                let enabled = fs::read_to_string(parent.join("cgroup.subtree_control"))
+                    .with_context(|| format!("Failed to read cgroup {}", parent.display()))?;
+                for (controller, wanted) in [
+                    ("cpu", self.cpu_quota_percent.is_some()),
+                    ("memory", self.memory_max_bytes.is_some()),
+                ] {
+                    if wanted && !enabled.split_whitespace().any(|c| c == controller) {
+                        return Err(anyhow!(
+                            "the {} controller is not enabled below '{}'\n\
+Remediation: echo +{} > {}/cgroup.subtree_control",
+                            controller,
+                            parent.display(),
+                            controller,
+                            parent.display()
+                        ))
+                        .error_kind(ErrorKind::InvalidConfig);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `systemd-run` arguments placing a command in a limited scope; the
+    /// command follows after `--`.
+    pub fn systemd_run_args(&self, as_root: bool) -> Vec<String> {
+        let mut args = vec![
+            "--scope".to_string(),
+            "--quiet".to_string(),
+            "--collect".to_string(),
+        ];
+        if !as_root {
+            args.push("--user".to_string());
+        }
+        if let Some(percent) = self.cpu_quota_percent {
+            args.push("-p".to_string());
+            args.push(format!("CPUQuota={}%", percent));
+        }
+        if let Some(bytes) = self.memory_max_bytes {
+            args.push("-p".to_string());
+            args.push(format!("MemoryMax={}", bytes));
+        }
+        args.push("--".to_string());
+        args
+    }
+
+    /// Limit `cmd`. Call before configuring stdio or `pre_exec` hooks: the
+    /// `systemd-run` backend builds a new command carrying over only the
+    /// program, arguments, environment changes (a cleared environment
+    /// through `env -i`) and working directory.
+    pub fn apply(&self, mut cmd: Command) -> Result<LimitedCommand> {
+        if !self.is_active() {
+            return Ok(LimitedCommand {
+                command: cmd,
+                _cgroup: None,
+            });
+        }
+        match self.backend {
+            LimitBackend::SystemdRun => Ok(LimitedCommand {
+                command: self.wrap_in_scope(&cmd),
+                _cgroup: None,
+            }),
+            LimitBackend::Cgroup => {
+                let cgroup = CommandCgroup::create(self.cgroup_parent()?, self)?;
+                cgroup.attach(&mut cmd)?;
+                Ok(LimitedCommand {
+                    command: cmd,
+                    _cgroup: Some(cgroup),
+                })
+            }
+        }
+    }
+
+    fn cgroup_parent(&self) -> Result<&Path> {
+        self.cgroup_parent
+            .as_deref()
+            .ok_or_else(|| anyhow!("resource_limits.cgroup_parent is not set"))
+    }
+
+    fn wrap_in_scope(&self, cmd: &Command) -> Command {
+        // SAFETY: geteuid has no preconditions.
+        let as_root = unsafe { libc::geteuid() } == 0;
+        let mut scope = Command::new("systemd-run");
+        scope.args(self.systemd_run_args(as_root));
+        if env_cleared(cmd) {
+            // systemd-run needs its own environment to reach the service
+            // manager, so the command's cleared one is rebuilt by `env -i`.
+            scope.args(["env", "-i"]);
+            for (key, value) in cmd.get_envs() {
+                if let Some(value) = value {
+                    let mut pair = key.to_os_string();
+                    pair.push("=");
+                    pair.push(value);
+                    scope.arg(pair);
+                }
+            }
+        } else {
+            for (key, value) in cmd.get_envs() {
+                match value {
+                    Some(value) => scope.env(key, value),
+                    None => scope.env_remove(key),
+                };
+            }
+        }
+        scope.arg(cmd.get_program()).args(cmd.get_args());
+        if let Some(dir) = cmd.get_current_dir() {
+            scope.current_dir(dir);
+        }
+        scope
+    }
+}
+
+/// Whether [`Command::env_clear`] was called on `cmd`. std has no getter
+/// for it; on Unix the `Debug` output starts with `env -i` exactly then.
+fn env_cleared(cmd: &Command) -> bool {
+    format!("{:?}", cmd).starts_with("env -i ")
+}
+
+/// A command with its limits applied. Keep it alive until the command has
+/// exited; a `cgroup` backend directory is removed on drop.
+#[derive(Debug)]
+pub struct LimitedCommand {
+    pub command: Command,
+    _cgroup: Option<CommandCgroup>,
+}
+
+/// Install the process-wide limits [`limit`] applies, after checking the
+/// backend works. Idempotent; the first installed limits stay in effect.
+pub fn install(limits: ResourceLimits) -> Result<()> {
+    limits.check()?;
+    if limits.is_active() {
+        let mut parts = Vec::new();
+        if let Some(percent) = limits.cpu_quota_percent {
+            parts.push(format!("cpu {}%", percent));
+        }
+        if let Some(bytes) = limits.memory_max_bytes {
+            parts.push(format!(
+                "memory {}",
+                crate::artifact::rootfs::format_size_human(bytes)
+            ));
+        }
+        eprintln!(
+            "  Limiting external commands to {} ({})",
+            parts.join(", "),
+            limits.backend.as_str()
+        );
+    }
+    let _ = INSTALLED.set(limits);
+    Ok(())
+}
+
+/// Apply the installed limits to `cmd`; unchanged when none are installed.
+/// See [`ResourceLimits::apply`] for ordering requirements.
+pub fn limit(cmd: Command) -> Result<LimitedCommand> {
+    match INSTALLED.get() {
+        Some(limits) => limits.apply(cmd),
+        None => Ok(LimitedCommand {
+            command: cmd,
+            _cgroup: None,
+        }),
+    }
+}
+
+/// A per-command child of `cgroup_parent`.
+#[derive(Debug)]
+struct CommandCgroup {
+    dir: PathBuf,
+}
+
+impl CommandCgroup {
+    fn create(parent: &Path, limits: &ResourceLimits) -> Result<Self> {
+        let dir = parent.join(format!(
+            "distro-builder-{}-{}",
+            std::process::id(),
+            CGROUP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&dir)
+            .with_context(|| format!("Failed to create cgroup {}", dir.display()))?;
+        let cgroup = Self { dir };
+        if let Some(percent) = limits.cpu_quota_percent {
+            let quota = u64::from(percent) * CPU_PERIOD_US / 100;
+            cgroup.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD_US))?;
+        }
+        if let Some(bytes) = limits.memory_max_bytes {
+            cgroup.write("memory.max", &bytes.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        let path = self.dir.join(file);
+        fs::write(&path, value).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Make the child join this cgroup between fork and exec.
+    fn attach(&self, cmd: &mut Command) -> Result<()> {
+        let procs = CString::new(self.dir.join("cgroup.procs").as_os_str().as_bytes())
+            .context("cgroup path contains a NUL byte")?;
+        // SAFETY: open, write and close are async-signal-safe, and the
+        // closure only reads its own CString.
+        unsafe {
+            cmd.pre_exec(move || {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // "0" moves the writing process.
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                let err = std::io::Error::last_os_error();
+                libc::close(fd);
+                if written != 1 {
+                    return Err(err);
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CommandCgroup {
+    fn drop(&mut self) {
+        // Fails while a daemonized grandchild still lives in it; the empty
+        // directory is harmless.
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// `200%` (percent of one CPU) or a CPU count such as `2`; empty means no
+/// limit.
+fn parse_cpu_quota(value: &str) -> Result<Option<u32>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let percent = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<u32>().ok(),
+        None => value
+            .parse::<u32>()
+            .ok()
+            .and_then(|cpus| cpus.checked_mul(100)),
+    };
+    match percent {
+        Some(percent) if percent > 0 => Ok(Some(percent)),
+        _ => bail!(
+            "invalid cpu_quota '{}'\n\
+Remediation: use a percentage of one CPU such as \"200%\", or a CPU count such as \"2\"",
+            value
+        ),
+    }
+}
+
+/// Bytes with an optional K/M/G/T suffix; empty means no limit.
+fn parse_memory_max(value: &str) -> Result<Option<u64>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let (number, shift) = match value.char_indices().last() {
+        Some((at, suffix)) if suffix.is_ascii_alphabetic() => {
+            let shift = match suffix.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => u32::MAX,
+            };
+            (&value[..at], shift)
+        }
+        _ => (value, 0),
+    };
+    let bytes = number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|_| shift != u32::MAX)
+        .and_then(|n| n.checked_mul(1u64 << shift))
+        .filter(|bytes| *bytes > 0);
+    match bytes {
+        Some(bytes) => Ok(Some(bytes)),
+        None => bail!(
+            "invalid memory_max '{}'\n\
+Remediation: use bytes or a K/M/G/T suffix such as \"8G\"",
+            value
+        ),
+    }
+}
+
+pub(crate) fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH")?
+        .to_str()?
+        .split(':')
+        .map(|dir| Path::new(dir).join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_parsing() {
+        assert_eq!(parse_cpu_quota("250%").unwrap(), Some(250));
+        assert_eq!(parse_cpu_quota("4").unwrap(), Some(400));
+        assert_eq!(parse_cpu_quota(" ").unwrap(), None);
+        assert!(parse_cpu_quota("0%").is_err());
+        assert!(parse_cpu_quota("half").is_err());
+        assert_eq!(parse_memory_max("8G").unwrap(), Some(8 << 30));
+        assert_eq!(parse_memory_max("512m").unwrap(), Some(512 << 20));
+        assert_eq!(parse_memory_max("4096").unwrap(), Some(4096));
+        assert!(parse_memory_max("8X").is_err());
+        assert!(parse_memory_max("G").is_err());
+    }
+
+    #[test]
+    fn test_config_and_env_overrides() {
+        let config: ResourceLimitsConfig =
+            toml::from_str("cpu_quota = \"200%\"\nmemory_max = \"2G\"\n").unwrap();
+        let limits = ResourceLimits::from_config(config.clone(), None, None).unwrap();
+        assert!(limits.is_active());
+        assert_eq!(
+            limits.systemd_run_args(false),
+            [
+                "--scope",
+                "--quiet",
+                "--collect",
+                "--user",
+                "-p",
+                "CPUQuota=200%",
+                "-p",
+                "MemoryMax=2147483648",
+                "--"
+            ]
+        );
+
+        let limits = ResourceLimits::from_config(config, Some(""), Some("1G")).unwrap();
+        assert_eq!(limits.cpu_quota_percent, None);
+        assert_eq!(limits.memory_max_bytes, Some(1 << 30));
+        assert!(!limits
+            .systemd_run_args(true)
+            .contains(&"--user".to_string()));
+
+        let cgroup: ResourceLimitsConfig =
+            toml::from_str("memory_max = \"1G\"\nbackend = \"cgroup\"\n").unwrap();
+        assert!(ResourceLimits::from_config(cgroup, None, None).is_err());
+        assert!(!ResourceLimits::from_config(Default::default(), None, None)
+            .unwrap()
+            .is_active());
+    }
+
+    #[test]
+    fn test_scope_carries_command_setup() {
+        let limits = ResourceLimits {
+            cpu_quota_percent: Some(100),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("make");
+        cmd.arg("-j8").env("ARCH", "x86_64").current_dir("/tmp");
+        let limited = limits.apply(cmd).unwrap();
+        let command = &limited.command;
+        assert_eq!(command.get_program(), "systemd-run");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(&args[args.len() - 3..], ["--", "make", "-j8"]);
+        assert_eq!(command.get_current_dir(), Some(Path::new("/tmp")));
+        assert!(command
+            .get_envs()
+            .any(|(key, value)| key == "ARCH" && value == Some("x86_64".as_ref())));
+
+        let mut cmd = Command::new("make");
+        cmd.env_clear().env("PATH", "/usr/bin").arg("-j8");
+        let limited = limits.apply(cmd).unwrap();
+        let args: Vec<_> = limited.command.get_args().collect();
+        assert_eq!(
+            &args[args.len() - 6..],
+            ["--", "env", "-i", "PATH=/usr/bin", "make", "-j8"]
+        );
+        assert_eq!(limited.command.get_envs().count(), 0);
+    }
+}
@@ -1,22 +1,110 @@
+//! Release run history: the `run-manifest.json` schema and run directories.
+//!
+//! Every release run directory carries a manifest describing the run. The
+//! manifest is versioned by [`RUN_MANIFEST_SCHEMA_VERSION`]; older manifests
+//! are migrated in memory on read, so external tools can consume run history
+//! through [`read_run_metadata`] and [`load_run_metadata`] without caring
+//! which builder version wrote a run. Fields this schema does not know about
+//! (guest test results, store provenance) are carried in
+//! [`RunMetadata::extra`] and survive a read/write round trip.
+
 use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{self, AuditAction};
 
 const RUN_MANIFEST_FILENAME: &str = "run-manifest.json";
 const RUN_ID_SALT_BITS: u32 = 32;
 static RUN_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-#[derive(Debug, Deserialize, Clone)]
+/// Current `run-manifest.json` schema version.
+///
+/// - 0: unversioned manifests; only `run_id`, `status` and the timestamps
+///   are guaranteed.
+/// - 1: adds `schema_version`; target and path fields are always written.
+pub const RUN_MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// Lifecycle state of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Building,
+    Success,
+    Failed,
+    /// A status written by a newer builder.
+    #[serde(other)]
+    Unknown,
+}
+
+impl RunStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Building => "building",
+            Self::Success => "success",
+            Self::Failed => "failed",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Typed contents of a run's `run-manifest.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RunMetadata {
+    pub schema_version: u32,
     pub run_id: String,
-    pub status: String,
+    #[serde(default)]
+    pub distro_id: String,
+    #[serde(default)]
+    pub target_kind: String,
+    #[serde(default)]
+    pub target_name: String,
+    pub status: RunStatus,
     pub created_at_utc: String,
+    #[serde(default)]
     pub finished_at_utc: Option<String>,
+    #[serde(default)]
+    pub root_dir: String,
+    #[serde(default)]
+    pub output_dir: String,
+    #[serde(default)]
+    pub iso_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_config: Option<serde_json::Value>,
+    /// Fields outside this schema, kept verbatim.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl RunMetadata {
+    /// A new manifest at the current schema version.
+    pub fn new(run_id: impl Into<String>, status: RunStatus, created_at_utc: String) -> Self {
+        Self {
+            schema_version: RUN_MANIFEST_SCHEMA_VERSION,
+            run_id: run_id.into(),
+            distro_id: String::new(),
+            target_kind: String::new(),
+            target_name: String::new(),
+            status,
+            created_at_utc,
+            finished_at_utc: None,
+            root_dir: String::new(),
+            output_dir: String::new(),
+            iso_path: String::new(),
+            effective_config: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == RunStatus::Success
+    }
 }
 
 pub fn manifest_path(run_dir: &Path) -> PathBuf {
@@ -27,6 +115,70 @@ pub fn run_manifest_path(run_dir: &Path) -> PathBuf {
     run_dir.join(RUN_MANIFEST_FILENAME)
 }
 
+/// Bring a raw manifest up to [`RUN_MANIFEST_SCHEMA_VERSION`].
+///
+/// Fails for manifests from a newer builder rather than misreading them.
+pub fn migrate_run_manifest(mut value: serde_json::Value) -> Result<serde_json::Value> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("run manifest is not a JSON object"))?;
+    let version = match object.get("schema_version") {
+        None => 0,
+        Some(raw) => raw
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("run manifest schema_version is not an integer"))?,
+    };
+    if version > RUN_MANIFEST_SCHEMA_VERSION {
+        bail!(
+            "run manifest schema_version {} is newer than supported version {}; \
+upgrade distro-builder to read this run",
+            version,
+            RUN_MANIFEST_SCHEMA_VERSION
+        );
+    }
+    if version == 0 {
+        // v0 -> v1: only the version marker is new; missing target and path
+        // fields default to empty.
+        object.insert(
+            "schema_version".to_string(),
+            serde_json::json!(RUN_MANIFEST_SCHEMA_VERSION),
+        );
+    }
+    Ok(value)
+}
+
+/// Read and migrate one `run-manifest.json`.
+pub fn read_run_metadata(path: &Path) -> Result<RunMetadata> {
+    let bytes =
+        fs::read(path).with_context(|| format!("reading run metadata '{}'", path.display()))?;
+    let raw: serde_json::Value = serde_json::from_slice(&bytes)
+        .with_context(|| format!("parsing run metadata '{}'", path.display()))?;
+    let migrated = migrate_run_manifest(raw)
+        .with_context(|| format!("migrating run metadata '{}'", path.display()))?;
+    serde_json::from_value(migrated)
+        .with_context(|| format!("parsing run metadata '{}'", path.display()))
+}
+
+/// Atomically write `metadata` to `path`.
+pub fn write_run_metadata(path: &Path, metadata: &RunMetadata) -> Result<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| anyhow!("path without parent '{}'", path.display()))?;
+    fs::create_dir_all(parent)
+        .with_context(|| format!("creating parent directory '{}'", parent.display()))?;
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    let payload = serde_json::to_vec_pretty(metadata).context("serializing run metadata")?;
+    fs::write(&tmp, payload).with_context(|| format!("writing temp file '{}'", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| {
+        format!(
+            "renaming temp file '{}' to '{}'",
+            tmp.display(),
+            path.display()
+        )
+    })
+}
+
 pub fn load_runs_metadata(stage_root_dir: &Path) -> Result<Vec<RunMetadata>> {
     load_run_metadata(stage_root_dir)
 }
@@ -55,18 +207,14 @@ pub fn load_run_metadata(run_root_dir: &Path) -> Result<Vec<RunMetadata>> {
         if !path.is_file() {
             continue;
         }
-        let bytes = fs::read(&path)
-            .with_context(|| format!("reading run metadata '{}'", path.display()))?;
-        let parsed: RunMetadata = serde_json::from_slice(&bytes)
-            .with_context(|| format!("parsing run metadata '{}'", path.display()))?;
-        runs.push(parsed);
+        runs.push(read_run_metadata(&path)?);
     }
     Ok(runs)
 }
 
 pub fn latest_successful_run_id(run_root_dir: &Path) -> Result<Option<String>> {
     let mut runs = load_run_metadata(run_root_dir)?;
-    runs.retain(|run| run.status == "success");
+    runs.retain(RunMetadata::is_success);
     runs.sort_by_key(|run| Reverse(run_sort_key(run)));
     Ok(runs.first().map(|r| r.run_id.clone()))
 }
@@ -78,6 +226,12 @@ pub fn prune_old_runs(run_root_dir: &Path, keep: usize) -> Result<()> {
         let path = run_root_dir.join(&run.run_id);
         fs::remove_dir_all(&path)
             .with_context(|| format!("removing expired run directory '{}'", path.display()))?;
+        audit::record_output(
+            &path,
+            AuditAction::RunPrune {
+                run_dir: path.clone(),
+            },
+        );
     }
     Ok(())
 }
@@ -97,6 +251,12 @@ pub fn allocate_run_dir(run_root_dir: &Path) -> Result<(String, PathBuf)> {
         }
         fs::create_dir_all(&run_root)
             .with_context(|| format!("creating run output directory '{}'", run_root.display()))?;
+        audit::record_output(
+            &run_root,
+            AuditAction::RunCreate {
+                run_dir: run_root.clone(),
+            },
+        );
         return Ok((run_id, run_root));
     }
     bail!(
@@ -105,7 +265,7 @@ pub fn allocate_run_dir(run_root_dir: &Path) -> Result<(String, PathBuf)> {
     )
 }
 
-fn run_sort_key(run: &RunMetadata) -> String {
+pub(crate) fn run_sort_key(run: &RunMetadata) -> String {
     run.finished_at_utc
         .clone()
         .unwrap_or_else(|| run.created_at_utc.clone())
@@ -187,4 +347,39 @@ mod tests {
             assert!(tmp.path().join(format!("run-{idx}")).is_dir());
         }
     }
+
+    #[test]
+    fn unversioned_manifest_is_migrated_and_keeps_extra_fields() {
+        let tmp = tempfile::tempdir().expect("tempdir");
+        let path = manifest_path(tmp.path());
+        fs::write(
+            &path,
+            serde_json::to_vec_pretty(&json!({
+                "run_id": "run-1",
+                "status": "success",
+                "created_at_utc": "20260313T120000Z",
+                "guest_tests": {"records": []},
+            }))
+            .expect("serialize manifest"),
+        )
+        .expect("write manifest");
+
+        let run = read_run_metadata(&path).expect("read legacy manifest");
+        assert_eq!(run.schema_version, RUN_MANIFEST_SCHEMA_VERSION);
+        assert_eq!(run.status, RunStatus::Success);
+        assert!(run.extra.contains_key("guest_tests"));
+
+        write_run_metadata(&path, &run).expect("rewrite manifest");
+        assert_eq!(read_run_metadata(&path).expect("reread manifest"), run);
+    }
+
+    #[test]
+    fn newer_manifest_schema_is_rejected() {
+        let err = migrate_run_manifest(json!({
+            "schema_version": RUN_MANIFEST_SCHEMA_VERSION + 1,
+            "run_id": "run-1",
+        }))
+        .expect_err("newer schema must not be read");
+        assert!(err.to_string().contains("upgrade distro-builder"));
+    }
 }
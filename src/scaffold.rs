@@ -0,0 +1,420 @@
+//! New distro variant scaffolding.
+//!
+//! Generates a canonical owner-directory tree under `distro-variants/<id>/`
+//! (see `docs/06_MIGRATION_VARIANT_LAYOUT.md`) with defaults that pass
+//! contract validation, so onboarding a distro no longer starts by copying
+//! and hand-pruning an existing variant.
+//!
+//! The init system picks the template: systemd variants get a Fedora DVD
+//! rootfs source and a systemd live overlay, OpenRC variants an Alpine
+//! rootfs source and an OpenRC overlay with a seed directory. Only the
+//! kernel config is copied from an existing variant of the same family.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::contracts::InitSystem;
+
+/// Kernel pinned for new variants; matches the shared linux recipe.
+pub const DEFAULT_KERNEL_VERSION: &str = "6.12.71";
+pub const DEFAULT_KERNEL_SHA256: &str =
+    "143e8bc76cc41f831b51aa5e75819bed55bed41f299d35922820f1d2d2b02600";
+
+const SCHEMA_VERSION: u32 = 6;
+const ISO_LABEL_MAX: usize = 32;
+
+/// A variant to generate.
+#[derive(Debug, Clone)]
+pub struct VariantScaffold {
+    distro_id: String,
+    init: InitSystem,
+    os_name: String,
+    template_variant: Option<String>,
+}
+
+impl VariantScaffold {
+    pub fn new(distro_id: &str, init: InitSystem) -> Result<Self> {
+        validate_distro_id(distro_id)?;
+        Ok(Self {
+            distro_id: distro_id.to_string(),
+            init,
+            os_name: default_os_name(distro_id),
+            template_variant: None,
+        })
+    }
+
+    /// Display name for `identity.toml` (default: `<Id>OS`).
+    pub fn os_name(mut self, os_name: &str) -> Self {
+        self.os_name = os_name.to_string();
+        self
+    }
+
+    /// Existing variant to copy the kernel config from. Defaults to
+    /// `levitate` for systemd and `acorn` for OpenRC.
+    pub fn template_variant(mut self, distro_id: &str) -> Self {
+        self.template_variant = Some(distro_id.to_string());
+        self
+    }
+
+    pub fn distro_id(&self) -> &str {
+        &self.distro_id
+    }
+
+    fn template(&self) -> &str {
+        match (&self.template_variant, self.init) {
+            (Some(template), _) => template,
+            (None, InitSystem::Systemd) => "levitate",
+            (None, InitSystem::OpenRC) => "acorn",
+        }
+    }
+
+    fn os_id(&self) -> String {
+        format!("{}os", self.distro_id.replace('-', ""))
+    }
+
+    fn iso_label(&self) -> String {
+        let mut label: String = self
+            .distro_id
+            .chars()
+            .map(|c| match c {
+                '-' => '_',
+                c => c.to_ascii_uppercase(),
+            })
+            .collect();
+        label.truncate(ISO_LABEL_MAX);
+        label
+    }
+
+    fn overlay_seed_dir(&self) -> String {
+        format!("distro-variants/{}/ring2/overlays/live", self.distro_id)
+    }
+
+    /// Text files of the scaffold, relative to the variant directory, with
+    /// their modes. The kernel config is copied separately by [`Self::write`].
+    pub fn files(&self) -> Vec<(PathBuf, String, u32)> {
+        let id = &self.distro_id;
+        let mut files = vec![
+            (
+                "identity/identity.toml",
+                format!(
+                    "schema_version = {SCHEMA_VERSION}\n\n[identity]\nos_name = \"{}\"\nos_id = \"{}\"\niso_label = \"{}\"\nos_version = \"0.1.0\"\ndefault_hostname = \"{id}\"\n",
+                    self.os_name,
+                    self.os_id(),
+                    self.iso_label()
+                ),
+                0o644,
+            ),
+            (
+                "build-host/build-host.toml",
+                format!(
+                    "schema_version = {SCHEMA_VERSION}\n\n[build_host]\nrequired_build_tools = [\"recipe\"]\nkernel_kconfig_path = \"kernel/kconfig\"\nrecipe_kernel_script = \"distro-builder/recipes/linux.rhai\"\nrecipe_kernel_invocation = \"recipe install\"\nkernel_release_path = \"boot/vmlinuz-linux\"\nkernel_image_path = \"boot/vmlinuz-linux\"\nkernel_modules_path = \"usr/lib/modules/<kernel.release>\"\nkernel_version = \"{DEFAULT_KERNEL_VERSION}\"\nkernel_sha256 = \"{DEFAULT_KERNEL_SHA256}\"\nkernel_localversion = \"-{id}\"\nmodule_install_path = \"/usr/lib/modules\"\n\n[build_host.evidence]\nscript_path = \"evidence/build-capability.sh\"\npass_marker = \"BUILD_CAPABILITY_PASS\"\n"
+                ),
+                0o644,
+            ),
+            ("ring3/sources.toml", self.sources_toml(), 0o644),
+            ("ring2/products.toml", self.products_toml(), 0o644),
+            (
+                "ring1/transforms.toml",
+                format!(
+                    "schema_version = {SCHEMA_VERSION}\n\n[ring1_transforms.rootfs]\noutput_name = \"filesystem.erofs\"\n\n[ring1_transforms.overlay]\noutput_name = \"overlayfs.erofs\"\n\n[ring1_transforms.initramfs_live]\noutput_name = \"initramfs-live.img\"\n\n[ring1_transforms.live_uki]\noutput_names = [\"live.efi\", \"emergency.efi\", \"debug.efi\"]\n"
+                ),
+                0o644,
+            ),
+            (
+                "ring0/release.toml",
+                format!(
+                    "schema_version = {SCHEMA_VERSION}\n\n[ring0_release.iso]\noutput_name = \"{id}.iso\"\n"
+                ),
+                0o644,
+            ),
+            (
+                "scenarios/scenarios.toml",
+                format!(
+                    "schema_version = {SCHEMA_VERSION}\n\n[scenarios.live_environment]\nrequired_services = [{}]\n",
+                    self.required_services()
+                        .iter()
+                        .map(|s| format!("\"{}\"", s))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                0o644,
+            ),
+            (
+                "build-host/evidence/build-capability.sh",
+                EVIDENCE_SCRIPT.to_string(),
+                0o755,
+            ),
+            ("README.md", self.readme(), 0o644),
+        ];
+        for hook in [
+            "ring0/hooks/build-release.sh",
+            "ring0/hooks/boot-release.sh",
+            "ring0/hooks/live-tools-release.sh",
+        ] {
+            files.push((hook, RELEASE_HOOK.to_string(), 0o755));
+        }
+        if self.init == InitSystem::OpenRC {
+            files.push((
+                "ring2/overlays/live/etc/motd",
+                format!("Welcome to {} (live)\n", self.os_name),
+                0o644,
+            ));
+        }
+        files
+            .into_iter()
+            .map(|(path, content, mode)| (PathBuf::from(path), content, mode))
+            .collect()
+    }
+
+    fn required_services(&self) -> &'static [&'static str] {
+        match self.init {
+            InitSystem::Systemd => &["sshd"],
+            InitSystem::OpenRC => &["dhcpcd", "networking", "sshd"],
+        }
+    }
+
+    fn sources_toml(&self) -> String {
+        let source = match self.init {
+            InitSystem::Systemd => "kind = \"recipe_rpm_dvd\"\nrecipe_script = \"distro-builder/recipes/fedora-dvd-source-rootfs.rhai\"\npreseed_recipe_script = \"distro-builder/recipes/fedora-preseed-iso.rhai\"\n".to_string(),
+            InitSystem::OpenRC => format!(
+                "kind = \"recipe_custom\"\nrecipe_script = \"distro-builder/recipes/{}\"\n",
+                crate::recipe::alpine_rootfs_source::ALPINE_ROOTFS_SOURCE_RECIPE_FILENAME
+            ),
+        };
+        format!("schema_version = {SCHEMA_VERSION}\n\n[ring3_sources.rootfs_source]\n{source}")
+    }
+
+    fn products_toml(&self) -> String {
+        let overlay = match self.init {
+            InitSystem::Systemd => "overlay_kind = \"systemd\"\n".to_string(),
+            InitSystem::OpenRC => format!(
+                "overlay_kind = \"openrc\"\nopenrc_inittab = \"serial_only\"\nseed_overlay = \"{}\"\n",
+                self.overlay_seed_dir()
+            ),
+        };
+        format!(
+            r#"schema_version = {SCHEMA_VERSION}
+
+[ring2_products.rootfs_base]
+logical_name = "product.rootfs.base"
+description = "Canonical base root filesystem tree"
+
+[ring2_products.live_overlay]
+logical_name = "product.payload.live_overlay"
+description = "Read-only live overlay payload tree"
+{overlay}
+[ring2_products.boot_live]
+logical_name = "product.payload.boot.live"
+description = "Live boot payload inputs"
+extends = "product.rootfs.base"
+
+[ring2_products.live_tools]
+logical_name = "product.payload.live_tools"
+description = "Live tools payload tree"
+extends = "product.payload.boot.live"
+
+[ring2_products.boot_installed]
+logical_name = "product.payload.boot.installed"
+description = "Installed-system boot payload inputs"
+extends = "product.rootfs.base"
+
+[ring2_products.kernel_staging]
+logical_name = "product.kernel.staging"
+description = "Kernel image and modules staging product"
+"#
+        )
+    }
+
+    fn readme(&self) -> String {
+        format!(
+            "# {} (`{}`)\n\n\
+             Generated by `distro-builder new-variant {} --init {}` from the `{}` kernel config.\n\n\
+             Before the first build:\n\n\
+             1. Register a `{}` kernel spec in `distro-builder/recipes/linux-base.rhai`.\n\
+             2. Review `build-host/kernel/kconfig` (`CONFIG_LOCALVERSION` is `-{}`).\n\
+             3. Adjust identity, services and products in the owner manifests.\n",
+            self.os_name,
+            self.distro_id,
+            self.distro_id,
+            self.init.to_string().to_ascii_lowercase(),
+            self.template(),
+            self.distro_id,
+            self.distro_id
+        )
+    }
+
+    /// Write the scaffold to `<repo_root>/distro-variants/<id>` and return
+    /// that directory. Refuses to touch an existing variant.
+    pub fn write(&self, repo_root: &Path) -> Result<PathBuf> {
+        let variants_dir = crate::repo_layout::RepoLayout::at(repo_root)?
+            .variants_dir()
+            .to_path_buf();
+        let variant_dir = variants_dir.join(&self.distro_id);
+        if variant_dir.exists() {
+            bail!(
+                "variant directory already exists: {}\n\
+                 Remediation: pick a new id or remove the directory first.",
+                variant_dir.display()
+            );
+        }
+        let template_kconfig = variants_dir
+            .join(self.template())
+            .join("build-host/kernel/kconfig");
+        if !template_kconfig.is_file() {
+            bail!(
+                "template kernel config not found: {}\n\
+                 Remediation: pass an existing variant with --from <distro_id>.",
+                template_kconfig.display()
+            );
+        }
+
+        for (rel, content, mode) in self.files() {
+            let path = variant_dir.join(rel);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            fs::write(&path, content)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to chmod {}", path.display()))?;
+        }
+
+        let kconfig = variant_dir.join("build-host/kernel/kconfig");
+        fs::create_dir_all(variant_dir.join("build-host/kernel"))
+            .with_context(|| format!("Failed to create {}", variant_dir.display()))?;
+        let template = fs::read_to_string(&template_kconfig)
+            .with_context(|| format!("Failed to read {}", template_kconfig.display()))?;
+        fs::write(&kconfig, retarget_localversion(&template, &self.distro_id))
+            .with_context(|| format!("Failed to write {}", kconfig.display()))?;
+
+        Ok(variant_dir)
+    }
+}
+
+/// Variant ids become directory names, recipe targets and ISO file names.
+pub fn validate_distro_id(distro_id: &str) -> Result<()> {
+    let valid = !distro_id.is_empty()
+        && distro_id.len() <= ISO_LABEL_MAX
+        && distro_id.starts_with(|c: char| c.is_ascii_lowercase())
+        && distro_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        bail!(
+            "invalid variant id '{}': use 1-{} lowercase letters, digits or '-', starting with a letter",
+            distro_id,
+            ISO_LABEL_MAX
+        );
+    }
+    Ok(())
+}
+
+/// `oak-tree` -> `OakTreeOS`.
+fn default_os_name(distro_id: &str) -> String {
+    let mut name = String::new();
+    for part in distro_id.split('-') {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+    }
+    name.push_str("OS");
+    name
+}
+
+fn retarget_localversion(kconfig: &str, distro_id: &str) -> String {
+    let mut out = String::with_capacity(kconfig.len());
+    let mut replaced = false;
+    for line in kconfig.lines() {
+        if line.starts_with("CONFIG_LOCALVERSION=") {
+            out.push_str(&format!("CONFIG_LOCALVERSION=\"-{}\"\n", distro_id));
+            replaced = true;
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    if !replaced {
+        out.push_str(&format!("CONFIG_LOCALVERSION=\"-{}\"\n", distro_id));
+    }
+    out
+}
+
+const RELEASE_HOOK: &str = r#"#!/bin/sh
+# Release hook: delegate to the shared ring0 release builder.
+set -eu
+hook_dir="$(cd "$(dirname "$0")" && pwd)"
+exec sh "$hook_dir/../../../_shared/ring0/hooks/build-release.sh" "$@"
+"#;
+
+const EVIDENCE_SCRIPT: &str = r#"#!/bin/sh
+# Build-capability evidence: the kernel artifacts exist and are non-empty.
+set -eu
+[ -s "$KERNEL_IMAGE_PATH" ] || { echo "missing kernel image: $KERNEL_IMAGE_PATH" >&2; exit 1; }
+[ -s "$KERNEL_RELEASE_PATH" ] || { echo "missing kernel release: $KERNEL_RELEASE_PATH" >&2; exit 1; }
+echo BUILD_CAPABILITY_PASS
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_scaffold_openrc_variant() {
+        let temp = TempDir::new().unwrap();
+        let template = temp.path().join("distro-variants/acorn/build-host/kernel");
+        fs::create_dir_all(&template).unwrap();
+        fs::write(
+            template.join("kconfig"),
+            "CONFIG_X=y\nCONFIG_LOCALVERSION=\"-acorn\"\n",
+        )
+        .unwrap();
+
+        let scaffold = VariantScaffold::new("oak-tree", InitSystem::OpenRC).unwrap();
+        let dir = scaffold.write(temp.path()).unwrap();
+
+        for (rel, content, _) in scaffold.files() {
+            if rel.extension().is_some_and(|e| e == "toml") {
+                content
+                    .parse::<toml::Table>()
+                    .unwrap_or_else(|e| panic!("{}: {}", rel.display(), e));
+            }
+            assert!(dir.join(&rel).is_file(), "{}", rel.display());
+        }
+        let identity = fs::read_to_string(dir.join("identity/identity.toml")).unwrap();
+        assert!(identity.contains("os_name = \"OakTreeOS\""));
+        assert!(identity.contains("iso_label = \"OAK_TREE\""));
+        let products = fs::read_to_string(dir.join("ring2/products.toml")).unwrap();
+        assert!(
+            products.contains("seed_overlay = \"distro-variants/oak-tree/ring2/overlays/live\"")
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("build-host/kernel/kconfig")).unwrap(),
+            "CONFIG_X=y\nCONFIG_LOCALVERSION=\"-oak-tree\"\n"
+        );
+        let mode = fs::metadata(dir.join("ring0/hooks/build-release.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        assert!(scaffold.write(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_ids_and_missing_template() {
+        assert!(VariantScaffold::new("Levitate", InitSystem::Systemd).is_err());
+        assert!(VariantScaffold::new("_shared", InitSystem::Systemd).is_err());
+        assert!(VariantScaffold::new("a/b", InitSystem::Systemd).is_err());
+
+        let temp = TempDir::new().unwrap();
+        let scaffold = VariantScaffold::new("pine", InitSystem::Systemd).unwrap();
+        assert!(scaffold.write(temp.path()).is_err());
+        assert!(!temp.path().join("distro-variants/pine").exists());
+    }
+}
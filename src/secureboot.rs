@@ -0,0 +1,297 @@
+//! Secure Boot key material management.
+//!
+//! One key directory holds the PK/KEK/db hierarchy used both for signing
+//! EFI binaries (UKIs, systemd-boot) and for enrolling the same keys into an
+//! OVMF variable store so QEMU can boot the signed artifacts with Secure Boot
+//! enforced. The db certificate doubles as the machine-owner key (MOK) that
+//! users import with `mokutil --import` on shim-based installs.
+//!
+//! # Layout
+//!
+//! ```text
+//! <dir>/
+//!   GUID.txt            owner GUID for the signature lists
+//!   PK.key  PK.crt  PK.der  PK.esl
+//!   KEK.key KEK.crt KEK.der KEK.esl
+//!   db.key  db.crt  db.der  db.esl
+//! ```
+//!
+//! # Signing
+//!
+//! When [`SECUREBOOT_KEYS_ENV`] names a key directory, builds sign with its
+//! db key: [`UkiBuilder`](crate::artifact::uki::UkiBuilder) signs every UKI
+//! it builds, and [`create_esp_image`](crate::artifact::iso_utils::create_esp_image)
+//! signs the bootloader and UKIs placed on the ISO's El Torito ESP.
+//!
+//! # Host tools
+//!
+//! - `openssl` - key and certificate generation, DER export
+//! - `cert-to-efi-sig-list` (efitools) - ESL export
+//! - `sbsign` (sbsigntools) - EFI binary signing
+//! - `virt-fw-vars` (virt-firmware) - OVMF_VARS enrollment
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::artifact::disk::helpers::generate_uuid;
+use crate::process::{self, Cmd};
+
+/// Key directory builds sign EFI binaries with; unsigned when unset.
+pub const SECUREBOOT_KEYS_ENV: &str = "DISTRO_BUILDER_SECUREBOOT_KEYS";
+
+const OWNER_GUID_FILENAME: &str = "GUID.txt";
+const CERT_VALIDITY_DAYS: &str = "3650";
+
+/// A key in the Secure Boot hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    /// Platform key - owns the KEK list.
+    Pk,
+    /// Key exchange key - authorizes db updates.
+    Kek,
+    /// Signature database key - signs bootable EFI binaries.
+    Db,
+}
+
+impl KeyKind {
+    pub const ALL: [KeyKind; 3] = [KeyKind::Pk, KeyKind::Kek, KeyKind::Db];
+
+    /// File stem used for every artifact of this key.
+    pub fn stem(self) -> &'static str {
+        match self {
+            KeyKind::Pk => "PK",
+            KeyKind::Kek => "KEK",
+            KeyKind::Db => "db",
+        }
+    }
+}
+
+/// A directory of Secure Boot keys.
+#[derive(Debug, Clone)]
+pub struct SecureBootKeys {
+    dir: PathBuf,
+    owner_guid: String,
+}
+
+impl SecureBootKeys {
+    /// Open an existing key directory, failing if any artifact is missing.
+    pub fn open(dir: &Path) -> Result<Self> {
+        let guid_path = dir.join(OWNER_GUID_FILENAME);
+        let owner_guid = fs::read_to_string(&guid_path)
+            .with_context(|| format!("Failed to read {}", guid_path.display()))?
+            .trim()
+            .to_string();
+        let keys = Self {
+            dir: dir.to_path_buf(),
+            owner_guid,
+        };
+
+        for kind in KeyKind::ALL {
+            for path in [
+                keys.key(kind),
+                keys.cert(kind),
+                keys.der(kind),
+                keys.esl(kind),
+            ] {
+                if !path.is_file() {
+                    bail!(
+                        "Secure Boot key material incomplete: {} is missing\n\
+                         Remove {} and regenerate the keys.",
+                        path.display(),
+                        dir.display()
+                    );
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Open the key directory named by [`SECUREBOOT_KEYS_ENV`], if set.
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var_os(SECUREBOOT_KEYS_ENV).filter(|dir| !dir.is_empty()) {
+            Some(dir) => Self::open(Path::new(&dir))
+                .with_context(|| format!("Failed to open {} keys", SECUREBOOT_KEYS_ENV))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Open the key directory, generating a fresh hierarchy if it is empty.
+    pub fn ensure(dir: &Path, common_name: &str) -> Result<Self> {
+        if dir.join(OWNER_GUID_FILENAME).exists() {
+            Self::open(dir)
+        } else {
+            Self::generate(dir, common_name)
+        }
+    }
+
+    /// Generate a new PK/KEK/db hierarchy in `dir`.
+    ///
+    /// Each certificate is self-signed; `common_name` prefixes the subject so
+    /// the keys are recognisable in firmware setup menus.
+    pub fn generate(dir: &Path, common_name: &str) -> Result<Self> {
+        for (tool, package) in [("openssl", "openssl"), ("cert-to-efi-sig-list", "efitools")] {
+            if !process::exists(tool) {
+                bail!("{} not found. Install {}.", tool, package);
+            }
+        }
+
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create key directory {}", dir.display()))?;
+
+        let keys = Self {
+            dir: dir.to_path_buf(),
+            owner_guid: generate_uuid()?,
+        };
+
+        for kind in KeyKind::ALL {
+            let subject = format!("/CN={} {}/", common_name, kind.stem());
+            Cmd::new("openssl")
+                .args(["req", "-new", "-x509", "-newkey", "rsa:2048", "-nodes"])
+                .args(["-sha256", "-days", CERT_VALIDITY_DAYS, "-subj", &subject])
+                .arg("-keyout")
+                .arg_path(&keys.key(kind))
+                .arg("-out")
+                .arg_path(&keys.cert(kind))
+                .error_msg(format!("openssl failed to generate {} key", kind.stem()))
+                .run()?;
+            restrict_permissions(&keys.key(kind))?;
+
+            Cmd::new("openssl")
+                .args(["x509", "-outform", "DER", "-in"])
+                .arg_path(&keys.cert(kind))
+                .arg("-out")
+                .arg_path(&keys.der(kind))
+                .error_msg(format!("openssl failed to export {} as DER", kind.stem()))
+                .run()?;
+
+            Cmd::new("cert-to-efi-sig-list")
+                .args(["-g", &keys.owner_guid])
+                .arg_path(&keys.cert(kind))
+                .arg_path(&keys.esl(kind))
+                .error_msg("cert-to-efi-sig-list failed. Install efitools.")
+                .run()?;
+        }
+
+        // Written last so a partially generated directory is not mistaken
+        // for a complete one by `ensure`.
+        fs::write(
+            dir.join(OWNER_GUID_FILENAME),
+            format!("{}\n", keys.owner_guid),
+        )?;
+
+        println!("  Generated Secure Boot keys in {}", dir.display());
+        Ok(keys)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn owner_guid(&self) -> &str {
+        &self.owner_guid
+    }
+
+    /// PEM private key.
+    pub fn key(&self, kind: KeyKind) -> PathBuf {
+        self.dir.join(format!("{}.key", kind.stem()))
+    }
+
+    /// PEM certificate.
+    pub fn cert(&self, kind: KeyKind) -> PathBuf {
+        self.dir.join(format!("{}.crt", kind.stem()))
+    }
+
+    /// DER certificate (firmware setup menus, `mokutil --import`).
+    pub fn der(&self, kind: KeyKind) -> PathBuf {
+        self.dir.join(format!("{}.der", kind.stem()))
+    }
+
+    /// EFI signature list.
+    pub fn esl(&self, kind: KeyKind) -> PathBuf {
+        self.dir.join(format!("{}.esl", kind.stem()))
+    }
+
+    /// Certificate users enroll as their machine-owner key.
+    pub fn mok_der(&self) -> PathBuf {
+        self.der(KeyKind::Db)
+    }
+
+    /// Sign an EFI binary with the db key.
+    pub fn sign_efi(&self, input: &Path, output: &Path) -> Result<()> {
+        Cmd::new("sbsign")
+            .arg("--key")
+            .arg_path(&self.key(KeyKind::Db))
+            .arg("--cert")
+            .arg_path(&self.cert(KeyKind::Db))
+            .arg("--output")
+            .arg_path(output)
+            .arg_path(input)
+            .error_msg(format!(
+                "sbsign failed for {}. Install sbsigntools.",
+                input.display()
+            ))
+            .run()?;
+        Ok(())
+    }
+
+    /// Write an OVMF variable store with these keys enrolled and Secure Boot on.
+    ///
+    /// `template` is the distro-provided empty `OVMF_VARS.fd`; it is never
+    /// modified.
+    pub fn enroll_ovmf_vars(&self, template: &Path, output: &Path) -> Result<()> {
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Cmd::new("virt-fw-vars")
+            .arg("--input")
+            .arg_path(template)
+            .arg("--output")
+            .arg_path(output)
+            .args(["--set-pk", &self.owner_guid])
+            .arg_path(&self.cert(KeyKind::Pk))
+            .args(["--add-kek", &self.owner_guid])
+            .arg_path(&self.cert(KeyKind::Kek))
+            .args(["--add-db", &self.owner_guid])
+            .arg_path(&self.cert(KeyKind::Db))
+            .arg("--secure-boot")
+            .error_msg("virt-fw-vars failed. Install virt-firmware (python3-virt-firmware).")
+            .run()?;
+        Ok(())
+    }
+}
+
+fn restrict_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict permissions on {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_key_paths() {
+        let keys = SecureBootKeys {
+            dir: PathBuf::from("/keys"),
+            owner_guid: "00000000-0000-0000-0000-000000000000".into(),
+        };
+        assert_eq!(keys.key(KeyKind::Pk), PathBuf::from("/keys/PK.key"));
+        assert_eq!(keys.esl(KeyKind::Kek), PathBuf::from("/keys/KEK.esl"));
+        assert_eq!(keys.mok_der(), PathBuf::from("/keys/db.der"));
+    }
+
+    #[test]
+    fn test_open_incomplete_dir_fails() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join(OWNER_GUID_FILENAME), "guid\n").unwrap();
+        fs::write(temp.path().join("PK.key"), "").unwrap();
+
+        let err = SecureBootKeys::open(temp.path()).unwrap_err().to_string();
+        assert!(err.contains("PK.crt"), "{}", err);
+    }
+}
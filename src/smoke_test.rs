@@ -0,0 +1,249 @@
+//! Boot smoke test run automatically after a release build.
+//!
+//! A release build used to count as green once its ISO existed. The release
+//! workflow now boots every freshly assembled ISO with
+//! [`test_iso_boot_with_policy`] and stores the records in the run manifest,
+//! so "built" means "boots". `--no-test` skips it for one invocation; a
+//! variant tunes or skips it with a `smoke-test.toml`:
+//!
+//! ```toml
+//! # enabled = false            # never boot-test this variant
+//! skip_products = ["base-rootfs"]
+//! timeout_secs = 240
+//! memory_gb = 4
+//! # ssh = false                # the image ships no sshd
+//! ssh_timeout_secs = 60
+//! # Directories of test scripts run in the guest after the checks.
+//! test_bundles = ["tests/smoke"]
+//! ```
+//!
+//! Besides the in-guest checks, the smoke test connects to the guest's sshd
+//! through a forwarded port and runs `true` (see [`SshProbe`]). It watches
+//! the first console of the variant's `consoles.toml`
+//! ([`crate::build::consoles`]), `ttyS0` by default. Each `test_bundles`
+//! directory, relative to the variant, is attached as a
+//! [`crate::test_bundle::TestBundle`]. A variant with a `pstore.toml`
+//! ([`crate::pstore`]) gets the kernel's crash records in a failed test's
+//! error.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::boot_log::BootLogPolicy;
+use crate::build::consoles::SerialConsoles;
+use crate::error::{ErrorKind, ResultExt};
+use crate::guest_protocol::TestStatus;
+use crate::guest_results::{GuestTestResults, TestRecord};
+use crate::pstore::Pstore;
+use crate::qemu::{test_iso_boot_with_pstore, DiskBootTest, SshProbe};
+use crate::test_bundle::TestBundle;
+
+/// File name of the per-variant smoke test settings.
+pub const SMOKE_TEST_CONFIG_FILENAME: &str = "smoke-test.toml";
+
+/// Record name summarizing the whole smoke test in the run manifest.
+pub const SMOKE_TEST_RECORD: &str = "smoke-test";
+
+/// Per-variant smoke test settings.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmokeTestConfig {
+    pub enabled: bool,
+    /// Release products (e.g. `base-rootfs`) that are never boot-tested.
+    pub skip_products: Vec<String>,
+    pub timeout_secs: u64,
+    pub memory_gb: u32,
+    /// QEMU `-cpu` model when KVM is unavailable.
+    pub cpu_mode: String,
+    /// Profile script carrying the test instrumentation; only used in
+    /// failure messages. Defaults to `00-<distro>-test.sh`.
+    pub test_script: Option<String>,
+    /// Connect to the guest's sshd from the host.
+    pub ssh: bool,
+    /// How long sshd gets to become reachable after the shell is up.
+    pub ssh_timeout_secs: u64,
+    /// Test bundle directories, relative to the variant directory.
+    pub test_bundles: Vec<PathBuf>,
+}
+
+impl Default for SmokeTestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            skip_products: Vec::new(),
+            timeout_secs: 180,
+            memory_gb: 4,
+            cpu_mode: "max".to_string(),
+            test_script: None,
+            ssh: true,
+            ssh_timeout_secs: 60,
+            test_bundles: Vec::new(),
+        }
+    }
+}
+
+impl SmokeTestConfig {
+    /// Load the variant's settings, or the defaults when it has none.
+    pub fn load_for_variant(variant_dir: &Path) -> Result<Self> {
+        let path = variant_dir.join(SMOKE_TEST_CONFIG_FILENAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+            .error_kind(ErrorKind::InvalidConfig)
+    }
+
+    /// Why `product` is not boot-tested, if it is not.
+    pub fn skip_reason(&self, product: &str) -> Option<String> {
+        if !self.enabled {
+            return Some(format!("disabled in {}", SMOKE_TEST_CONFIG_FILENAME));
+        }
+        if self.skip_products.iter().any(|p| p == product) {
+            return Some(format!(
+                "'{}' listed in skip_products of {}",
+                product, SMOKE_TEST_CONFIG_FILENAME
+            ));
+        }
+        None
+    }
+
+    /// The configured test bundles, resolved against `variant_dir`.
+    pub fn bundles(&self, variant_dir: &Path) -> Result<Vec<TestBundle>> {
+        self.test_bundles
+            .iter()
+            .map(|dir| TestBundle::new(&variant_dir.join(dir)))
+            .collect::<Result<_>>()
+            .error_kind(ErrorKind::InvalidConfig)
+    }
+
+    /// Boot `iso` and return the verification records, with a passing
+    /// [`SMOKE_TEST_RECORD`] added. Applies the variant's boot log policy.
+    pub fn run(&self, iso: &Path, variant_dir: &Path, distro_id: &str) -> Result<GuestTestResults> {
+        let policy =
+            BootLogPolicy::load_for_variant(variant_dir).error_kind(ErrorKind::InvalidConfig)?;
+        let test_script = self
+            .test_script
+            .clone()
+            .unwrap_or_else(|| format!("00-{}-test.sh", distro_id));
+        let ssh = self.ssh.then(|| SshProbe {
+            timeout: Duration::from_secs(self.ssh_timeout_secs),
+        });
+        let consoles = SerialConsoles::load_for_variant(variant_dir)
+            .error_kind(ErrorKind::InvalidConfig)?
+            .unwrap_or_default();
+        let bundles = self.bundles(variant_dir)?;
+        let pstore = Pstore::load_for_variant(variant_dir).error_kind(ErrorKind::InvalidConfig)?;
+        let mut results = test_iso_boot_with_pstore(
+            iso,
+            self.timeout_secs,
+            distro_id,
+            &test_script,
+            &self.cpu_mode,
+            self.memory_gb,
+            &policy,
+            ssh.as_ref(),
+            consoles.primary(),
+            &bundles,
+            pstore.as_ref(),
+        )
+        .error_kind(ErrorKind::BootTestFailed)?;
+        results.push(TestRecord::new(SMOKE_TEST_RECORD, TestStatus::Pass));
+        Ok(results)
+    }
+
+    /// Disk image boot test with these settings, for
+    /// [`DiskImageConfig::boot_test`](crate::artifact::disk::DiskImageConfig::boot_test).
+    pub fn disk_boot_test(&self, variant_dir: &Path, distro_id: &str) -> Result<DiskBootTest> {
+        let mut test = DiskBootTest::new(distro_id);
+        if let Some(test_script) = &self.test_script {
+            test.test_script_name = test_script.clone();
+        }
+        test.timeout_secs = self.timeout_secs;
+        test.cpu_mode = self.cpu_mode.clone();
+        test.memory_gb = self.memory_gb;
+        test.log_policy =
+            BootLogPolicy::load_for_variant(variant_dir).error_kind(ErrorKind::InvalidConfig)?;
+        test.ssh = self.ssh.then(|| SshProbe {
+            timeout: Duration::from_secs(self.ssh_timeout_secs),
+        });
+        if let Some(consoles) =
+            SerialConsoles::load_for_variant(variant_dir).error_kind(ErrorKind::InvalidConfig)?
+        {
+            test.console = consoles.primary().to_string();
+        }
+        test.test_bundles = self.bundles(variant_dir)?;
+        Ok(test)
+    }
+}
+
+/// Manifest records for a smoke test that was not run.
+pub fn skipped_results(reason: &str) -> GuestTestResults {
+    let mut results = GuestTestResults::default();
+    results.push(TestRecord::new(SMOKE_TEST_RECORD, TestStatus::Skip).with_message(reason));
+    results
+}
+
+/// Manifest records for a smoke test that failed with `err`.
+pub fn failed_results(err: &anyhow::Error) -> GuestTestResults {
+    let mut results = GuestTestResults::default();
+    results.push(
+        TestRecord::new(SMOKE_TEST_RECORD, TestStatus::Fail).with_message(format!("{:#}", err)),
+    );
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_variant_config_and_skips() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(
+            SmokeTestConfig::load_for_variant(temp.path()).unwrap(),
+            SmokeTestConfig::default()
+        );
+
+        fs::write(
+            temp.path().join(SMOKE_TEST_CONFIG_FILENAME),
+            "skip_products = [\"base-rootfs\"]\ntimeout_secs = 240\nssh = false\n",
+        )
+        .unwrap();
+        let config = SmokeTestConfig::load_for_variant(temp.path()).unwrap();
+        assert_eq!(config.timeout_secs, 240);
+        assert!(!config.ssh);
+        assert!(config.skip_reason("base-rootfs").is_some());
+        assert!(config.skip_reason("live-boot").is_none());
+        let disk = config.disk_boot_test(temp.path(), "acorn").unwrap();
+        assert_eq!(disk.timeout_secs, 240);
+        assert_eq!(disk.test_script_name, "00-acorn-test.sh");
+        assert!(disk.ssh.is_none());
+        assert_eq!(disk.console, "ttyS0");
+        assert!(disk.test_bundles.is_empty());
+
+        fs::create_dir_all(temp.path().join("tests/smoke")).unwrap();
+        fs::write(
+            temp.path().join(SMOKE_TEST_CONFIG_FILENAME),
+            "test_bundles = [\"tests/smoke\"]\n",
+        )
+        .unwrap();
+        let config = SmokeTestConfig::load_for_variant(temp.path()).unwrap();
+        let bundles = config.bundles(temp.path()).unwrap();
+        assert_eq!(bundles[0].name, "smoke");
+        assert_eq!(bundles[0].dir, temp.path().join("tests/smoke"));
+
+        fs::write(
+            temp.path().join(SMOKE_TEST_CONFIG_FILENAME),
+            "enable = false\n",
+        )
+        .unwrap();
+        assert!(SmokeTestConfig::load_for_variant(temp.path()).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Supervision of spawned commands.
+//!
+//! An interrupted build used to leave qemu, xorriso or a kernel `make`
+//! running after the builder exited, holding recipe locks and scratch
+//! directories. Once [`install_signal_handlers`] has run, every command
+//! started through [`prepare`] gets its own process group and is registered
+//! with [`register`] while it runs. SIGINT, SIGTERM and SIGHUP then send
+//! SIGTERM to each registered group, so a child's own children go with it;
+//! the builder's wait returns, the error unwinds through the usual `Drop`
+//! guards, and [`check_interrupted`] turns it into
+//! [`ErrorKind::Interrupted`]. A second signal sends SIGKILL and ends the
+//! builder at once.
+//!
+//! Without installed handlers (library use, tests) [`prepare`] leaves the
+//! process group alone, so a terminal Ctrl-C still reaches the children.
+//!
+//! A child in its own process group is stopped when it reads the terminal,
+//! so [`prepare_interactive`] also makes the child's group the terminal's
+//! foreground group when the builder holds it; the guard from
+//! [`register_interactive`] takes the terminal back once the child exits.
+
+use anyhow::{anyhow, bail, Result};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use crate::error::{ErrorKind, ResultExt};
+
+/// Process groups tracked at once; more concurrent children go unsupervised.
+const MAX_GROUPS: usize = 64;
+
+const HANDLED_SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+static GROUPS: [AtomicI32; MAX_GROUPS] = [const { AtomicI32::new(0) }; MAX_GROUPS];
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Install the SIGINT/SIGTERM/SIGHUP handlers. Idempotent.
+pub fn install_signal_handlers() -> Result<()> {
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    for &signal in HANDLED_SIGNALS {
+        // SAFETY: `on_signal` only touches atomics and calls async-signal-safe
+        // functions (kill, signal, raise).
+        let rc = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if rc != 0 {
+            let err = std::io::Error::last_os_error();
+            bail!("Failed to install handler for signal {}: {}", signal, err);
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    let first = RECEIVED
+        .compare_exchange(0, signal, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok();
+    let mut supervised = false;
+    for slot in &GROUPS {
+        let pgid = slot.load(Ordering::SeqCst);
+        if pgid > 0 {
+            supervised = true;
+            // SAFETY: kill is async-signal-safe.
+            unsafe {
+                libc::kill(-pgid, if first { libc::SIGTERM } else { libc::SIGKILL });
+            }
+        }
+    }
+    if !first || !supervised {
+        // Nothing to wait for, or the user insists: die of the signal.
+        // SAFETY: signal and raise are async-signal-safe.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+/// Put the command in its own process group, killed with the builder, when
+/// handlers are installed.
+pub fn prepare(cmd: &mut Command) -> &mut Command {
+    if !INSTALLED.load(Ordering::SeqCst) {
+        return cmd;
+    }
+    cmd.process_group(0);
+    // SAFETY: prctl is async-signal-safe and touches no shared state.
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    cmd
+}
+
+/// [`prepare`] for a command that reads the terminal: when the builder's
+/// process group owns the controlling terminal, the child takes it over
+/// before exec. Returns whether it does; pass that to
+/// [`register_interactive`].
+pub fn prepare_interactive(cmd: &mut Command) -> bool {
+    prepare(cmd);
+    if !INSTALLED.load(Ordering::SeqCst) || !owns_terminal() {
+        return false;
+    }
+    // SAFETY: getpid, signal and tcsetpgrp are async-signal-safe.
+    unsafe {
+        cmd.pre_exec(|| {
+            hand_terminal(libc::getpid());
+            Ok(())
+        });
+    }
+    true
+}
+
+/// Whether the builder's process group is the foreground group of the
+/// terminal on stdin.
+fn owns_terminal() -> bool {
+    // SAFETY: plain queries on the stdin descriptor.
+    unsafe {
+        libc::isatty(libc::STDIN_FILENO) == 1
+            && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+    }
+}
+
+/// Make `pgid` the terminal's foreground group. SIGTTOU is ignored for the
+/// call, since a background group asking for the terminal would be stopped.
+fn hand_terminal(pgid: libc::pid_t) {
+    // SAFETY: signal and tcsetpgrp are async-signal-safe.
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+/// Registration of a running child; unregisters on drop.
+#[derive(Debug)]
+pub struct Supervised {
+    slot: Option<usize>,
+    /// Take the terminal back from the child's group on drop.
+    reclaim_terminal: bool,
+}
+
+/// Register the child `pid`, spawned after [`prepare`], until the returned
+/// guard is dropped.
+pub fn register(pid: u32) -> Supervised {
+    if !INSTALLED.load(Ordering::SeqCst) {
+        return Supervised {
+            slot: None,
+            reclaim_terminal: false,
+        };
+    }
+    let pgid = pid as i32;
+    let slot = GROUPS.iter().position(|slot| {
+        slot.compare_exchange(0, pgid, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    });
+    if slot.is_some() && RECEIVED.load(Ordering::SeqCst) != 0 {
+        // Interrupted between spawn and registration.
+        // SAFETY: plain kill(2) of a group we just created.
+        unsafe {
+            libc::kill(-pgid, libc::SIGTERM);
+        }
+    }
+    Supervised {
+        slot,
+        reclaim_terminal: false,
+    }
+}
+
+/// [`register`] for a child spawned after [`prepare_interactive`];
+/// `handed_terminal` is what that returned.
+pub fn register_interactive(pid: u32, handed_terminal: bool) -> Supervised {
+    let mut supervised = register(pid);
+    supervised.reclaim_terminal = handed_terminal;
+    supervised
+}
+
+impl Drop for Supervised {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            GROUPS[slot].store(0, Ordering::SeqCst);
+        }
+        if self.reclaim_terminal {
+            // SAFETY: getpgrp has no preconditions.
+            hand_terminal(unsafe { libc::getpgrp() });
+        }
+    }
+}
+
+/// Signal the builder received, if any.
+pub fn interrupted() -> Option<i32> {
+    match RECEIVED.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Fail with [`ErrorKind::Interrupted`] once a signal arrived.
+pub fn check_interrupted() -> Result<()> {
+    match interrupted() {
+        None => Ok(()),
+        Some(signal) => Err(anyhow!("interrupted by {}", signal_name(signal)))
+            .error_kind(ErrorKind::Interrupted),
+    }
+}
+
+fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGHUP => "SIGHUP",
+        _ => "signal",
+    }
+}
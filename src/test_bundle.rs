@@ -0,0 +1,417 @@
+//! Host-provided test bundles run inside a booted guest.
+//!
+//! A bundle is a host directory of test scripts for one feature. Boot tests
+//! take bundles next to the image under test, so a suite runs against any
+//! built ISO or disk image without rebuilding its overlay:
+//!
+//! 1. [`TestBundle::pack`] copies the directory onto a small FAT image
+//!    labelled `DBTEST<n>`, under `tests/`, together with the marker
+//!    protocol helpers ([`shell_library`]) and a generated `run.sh`;
+//! 2. the image is attached as a read-only virtio drive;
+//! 3. after the built-in checks the harness mounts it in the guest and runs
+//!    `run.sh`, which executes each top-level `*.sh` in name order from
+//!    `tests/` and reports it as `<bundle>/<script>`.
+//!
+//! A script passes with exit status 0 and is skipped with 77; anything else
+//! fails it, and the tail of its output is printed on the console. The
+//! bundle ends with a RESULT marker named after the bundle itself.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use walkdir::WalkDir;
+
+use crate::guest_protocol::shell_library;
+use crate::preflight::RequiredTools;
+use crate::process::Cmd;
+
+/// Default time a bundle gets to report all its results.
+pub const DEFAULT_BUNDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Guest directory bundles are mounted under, one subdirectory per label.
+pub const GUEST_BUNDLE_ROOT: &str = "/run/db-tests";
+
+/// Exit status that marks a script as skipped.
+pub const SKIP_EXIT_CODE: i32 = 77;
+
+const RUNNER_FILENAME: &str = "run.sh";
+const PROTOCOL_FILENAME: &str = "protocol.sh";
+const TESTS_DIR: &str = "tests";
+const MIN_IMAGE_KIB: u64 = 2048;
+
+/// Register the host tools [`TestBundle::pack`] invokes.
+pub fn register_host_tools(tools: &mut RequiredTools) {
+    tools.require_all(
+        "test-bundle",
+        &[
+            ("mkfs.fat", "dosfstools"),
+            ("mmd", "mtools"),
+            ("mcopy", "mtools"),
+        ],
+    );
+}
+
+/// A host directory of test scripts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestBundle {
+    /// Result name prefix; the directory name by default.
+    pub name: String,
+    pub dir: PathBuf,
+    pub timeout: Duration,
+}
+
+/// A bundle packed onto a FAT image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedBundle {
+    pub name: String,
+    /// Filesystem label the guest finds the drive by.
+    pub label: String,
+    pub image: PathBuf,
+    pub timeout: Duration,
+}
+
+impl TestBundle {
+    /// Bundle for `dir`, named after it.
+    pub fn new(dir: &Path) -> Result<Self> {
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::named(&name, dir)
+    }
+
+    pub fn named(name: &str, dir: &Path) -> Result<Self> {
+        validate_name(name).with_context(|| format!("test bundle {}", dir.display()))?;
+        Ok(Self {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            timeout: DEFAULT_BUNDLE_TIMEOUT,
+        })
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Top-level `*.sh` files, in the order they run.
+    pub fn scripts(&self) -> Result<Vec<String>> {
+        if !self.dir.is_dir() {
+            bail!("test bundle {} is not a directory", self.dir.display());
+        }
+        let mut scripts = Vec::new();
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?
+        {
+            let entry = entry.with_context(|| format!("Failed to read {}", self.dir.display()))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.ends_with(".sh") || !entry.path().is_file() {
+                continue;
+            }
+            validate_name(&name)
+                .with_context(|| format!("script in test bundle {}", self.dir.display()))?;
+            scripts.push(name);
+        }
+        scripts.sort();
+        if scripts.is_empty() {
+            bail!("test bundle {} has no *.sh scripts", self.dir.display());
+        }
+        Ok(scripts)
+    }
+
+    /// The generated `run.sh`.
+    pub fn runner_script(&self) -> Result<String> {
+        let mut script = format!(
+            r#"#!/bin/sh
+# Generated by distro-builder: runs test bundle '{name}'.
+_db_root=$(cd "$(dirname "$0")" && pwd)
+. "$_db_root/{protocol}"
+_db_logs={guest_root}/logs/{name}
+mkdir -p "$_db_logs"
+_db_failed=0
+
+# db_run <result name> <script>
+db_run() {{
+    (cd "$_db_root/{tests}" && sh "./$2") >"$_db_logs/$1.log" 2>&1
+    _db_rc=$?
+    if [ "$_db_rc" -eq 0 ]; then
+        db_result "{name}/$1" pass
+    elif [ "$_db_rc" -eq {skip} ]; then
+        db_result "{name}/$1" skip
+    else
+        _db_failed=1
+        echo "{name}/$1 exited with $_db_rc:"
+        tail -n 40 "$_db_logs/$1.log" | sed 's/^/  | /'
+        db_result "{name}/$1" fail
+    fi
+}}
+
+"#,
+            name = self.name,
+            protocol = PROTOCOL_FILENAME,
+            guest_root = GUEST_BUNDLE_ROOT,
+            tests = TESTS_DIR,
+            skip = SKIP_EXIT_CODE,
+        );
+        for file in self.scripts()? {
+            let stem = file.strip_suffix(".sh").unwrap_or(&file);
+            script.push_str(&format!("db_run {} {}\n", stem, file));
+        }
+        script.push_str(&format!(
+            "\nif [ \"$_db_failed\" -eq 0 ]; then\n    db_result {name} pass\nelse\n    db_result {name} fail\nfi\n",
+            name = self.name
+        ));
+        Ok(script)
+    }
+
+    /// Pack the bundle into a FAT image at `image` labelled `label`.
+    pub fn pack(&self, image: &Path, label: &str) -> Result<PackedBundle> {
+        if label.is_empty() || label.len() > 11 {
+            bail!("FAT label '{}' must be 1 to 11 characters", label);
+        }
+        let runner = self.runner_script()?;
+        let staging = image.with_extension("staging");
+        if staging.exists() {
+            fs::remove_dir_all(&staging)
+                .with_context(|| format!("Failed to remove {}", staging.display()))?;
+        }
+        fs::create_dir_all(&staging)
+            .with_context(|| format!("Failed to create {}", staging.display()))?;
+        let packed = self.pack_from(&staging, &runner, image, label);
+        let _ = fs::remove_dir_all(&staging);
+        packed?;
+        Ok(PackedBundle {
+            name: self.name.clone(),
+            label: label.to_string(),
+            image: image.to_path_buf(),
+            timeout: self.timeout,
+        })
+    }
+
+    fn pack_from(&self, staging: &Path, runner: &str, image: &Path, label: &str) -> Result<()> {
+        let runner_path = staging.join(RUNNER_FILENAME);
+        let protocol_path = staging.join(PROTOCOL_FILENAME);
+        fs::write(&runner_path, runner)
+            .with_context(|| format!("Failed to write {}", runner_path.display()))?;
+        fs::write(&protocol_path, shell_library())
+            .with_context(|| format!("Failed to write {}", protocol_path.display()))?;
+
+        let payload = dir_size(&self.dir)? + runner.len() as u64 + shell_library().len() as u64;
+        let size_kib = (payload / 1024 * 5 / 4 + 1024).max(MIN_IMAGE_KIB);
+        if image.exists() {
+            fs::remove_file(image)
+                .with_context(|| format!("Failed to remove {}", image.display()))?;
+        }
+        Cmd::new("mkfs.fat")
+            .args(["-n", label, "-C"])
+            .arg_path(image)
+            .arg(size_kib.to_string())
+            .error_msg("mkfs.fat failed to create the test bundle image. Install dosfstools.")
+            .run()?;
+
+        let img = image.to_string_lossy();
+        for file in [&runner_path, &protocol_path] {
+            Cmd::new("mcopy")
+                .args(["-i", &img])
+                .arg_path(file)
+                .arg("::/")
+                .error_msg(format!("mcopy failed to copy {}", file.display()))
+                .run()?;
+        }
+        Cmd::new("mmd")
+            .args(["-i", &img, &format!("::/{}", TESTS_DIR)])
+            .error_msg("mmd failed. Install mtools.")
+            .run()?;
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?
+        {
+            let path = entry
+                .with_context(|| format!("Failed to read {}", self.dir.display()))?
+                .path();
+            Cmd::new("mcopy")
+                .args(["-s", "-i", &img])
+                .arg_path(&path)
+                .arg(format!("::/{}/", TESTS_DIR))
+                .error_msg(format!("mcopy failed to copy {}", path.display()))
+                .run()?;
+        }
+        Ok(())
+    }
+}
+
+impl PackedBundle {
+    /// QEMU arguments attaching the image as a read-only virtio drive.
+    pub fn qemu_args(&self) -> Vec<String> {
+        vec![
+            "-drive".to_string(),
+            format!(
+                "file={},format=raw,if=virtio,readonly=on",
+                self.image.display()
+            ),
+        ]
+    }
+
+    /// Guest command that mounts the drive and runs the bundle. A drive
+    /// that cannot be mounted fails the bundle; the marker is split so the
+    /// console's echo of the command is not taken for it.
+    pub fn guest_command(&self) -> String {
+        let mountpoint = format!("{}/{}", GUEST_BUNDLE_ROOT, self.label);
+        format!(
+            "mkdir -p {mnt} && _db_dev=$(findfs LABEL={label} 2>/dev/null || blkid -L {label}) && \
+             mount -t vfat -o ro \"$_db_dev\" {mnt} && sh {mnt}/{runner} || \
+             echo \"___DB_RES\"\"ULT name={name} status=fail___\"",
+            mnt = mountpoint,
+            label = self.label,
+            runner = RUNNER_FILENAME,
+            name = self.name,
+        )
+    }
+}
+
+/// Pack `bundles` into `work_dir` as `DBTEST0`, `DBTEST1`, ...
+pub fn pack_bundles(bundles: &[TestBundle], work_dir: &Path) -> Result<Vec<PackedBundle>> {
+    if bundles.is_empty() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(work_dir)
+        .with_context(|| format!("Failed to create {}", work_dir.display()))?;
+    for (idx, bundle) in bundles.iter().enumerate() {
+        if bundles[..idx].iter().any(|b| b.name == bundle.name) {
+            bail!("test bundle name '{}' is used twice", bundle.name);
+        }
+    }
+    bundles
+        .iter()
+        .enumerate()
+        .map(|(idx, bundle)| {
+            let label = format!("DBTEST{}", idx);
+            bundle.pack(&work_dir.join(format!("{}.img", bundle.name)), &label)
+        })
+        .collect()
+}
+
+/// Names end up in shell commands and markers: no whitespace or quoting.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        bail!(
+            "name '{}' must be letters, digits, '.', '_' or '-' and not start with '.'",
+            name
+        );
+    }
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in WalkDir::new(dir) {
+        let entry = entry.with_context(|| format!("Failed to walk {}", dir.display()))?;
+        if entry.file_type().is_file() {
+            total += entry
+                .metadata()
+                .with_context(|| format!("Failed to stat {}", entry.path().display()))?
+                .len();
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guest_protocol::{GuestEvent, MarkerParser};
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_runner_reports_each_script() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().join("network");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("10-pass.sh"), "test -f data.txt\n").unwrap();
+        fs::write(dir.join("20-skip.sh"), "exit 77\n").unwrap();
+        fs::write(dir.join("30-fail.sh"), "echo boom; exit 3\n").unwrap();
+        fs::write(dir.join("data.txt"), "x").unwrap();
+
+        let bundle = TestBundle::new(&dir).unwrap();
+        assert_eq!(
+            bundle.scripts().unwrap(),
+            ["10-pass.sh", "20-skip.sh", "30-fail.sh"]
+        );
+
+        // Lay the bundle out as the guest sees it and run it.
+        let mount = temp.path().join("mnt");
+        fs::create_dir_all(&mount).unwrap();
+        fs::write(
+            mount.join(RUNNER_FILENAME),
+            bundle
+                .runner_script()
+                .unwrap()
+                .replace(GUEST_BUNDLE_ROOT, &temp.path().display().to_string()),
+        )
+        .unwrap();
+        fs::write(mount.join(PROTOCOL_FILENAME), shell_library()).unwrap();
+        fs::create_dir(mount.join(TESTS_DIR)).unwrap();
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, mount.join(TESTS_DIR).join(path.file_name().unwrap())).unwrap();
+        }
+        let output = Command::new("sh")
+            .arg(mount.join(RUNNER_FILENAME))
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("  | boom"), "{stdout}");
+
+        let mut parser = MarkerParser::new();
+        let results: Vec<(String, String)> = stdout
+            .lines()
+            .filter_map(|line| match parser.feed(line).unwrap() {
+                Some(GuestEvent::Result { name, status }) => Some((name, status.to_string())),
+                _ => None,
+            })
+            .collect();
+        let results: Vec<(&str, &str)> = results
+            .iter()
+            .map(|(n, s)| (n.as_str(), s.as_str()))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("network/10-pass", "pass"),
+                ("network/20-skip", "skip"),
+                ("network/30-fail", "fail"),
+                ("network", "fail"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_names_and_guest_command() {
+        let temp = TempDir::new().unwrap();
+        assert!(TestBundle::named("has space", temp.path()).is_err());
+        assert!(TestBundle::new(temp.path()).is_err());
+        let empty = temp.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        assert!(TestBundle::new(&empty).unwrap().scripts().is_err());
+
+        let packed = PackedBundle {
+            name: "network".to_string(),
+            label: "DBTEST0".to_string(),
+            image: PathBuf::from("/tmp/network.img"),
+            timeout: DEFAULT_BUNDLE_TIMEOUT,
+        };
+        let command = packed.guest_command();
+        assert!(command.contains("findfs LABEL=DBTEST0"));
+        assert!(!command.contains("___DB_RESULT"));
+        assert_eq!(
+            packed.qemu_args()[1],
+            "file=/tmp/network.img,format=raw,if=virtio,readonly=on"
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Container fallback for Linux-only host tools.
+//!
+//! On macOS or a minimal host, `mkfs.erofs`, `xorriso` or the mtools may
+//! not be installable. With a tool image configured in
+//! `distro-builder.toml`, a command whose program is listed and missing on
+//! the host runs inside that image through podman or docker instead:
+//!
+//! ```toml
+//! [tool_container]
+//! # Must be pinned by digest so every host runs the same tool versions.
+//! image = "ghcr.io/example/distro-builder-tools@sha256:<digest>"
+//! # "podman" or "docker"; the first one on PATH when unset.
+//! # runtime = "podman"
+//! # Replaces the default list of tools that may run in the container.
+//! # tools = ["mkfs.erofs", "xorriso"]
+//! ```
+//!
+//! [`TOOL_CONTAINER_IMAGE_ENV`] overrides `image`; an empty value disables
+//! the fallback. Every absolute path named on the command line (or its
+//! nearest existing parent) and the working directory are bind-mounted at
+//! the same path, so the tool reads and writes the host tree directly.
+//! Natively installed tools always win. The CLI [`install`]s the setting at
+//! startup; [`Cmd`](crate::process::Cmd) routes commands through [`route`].
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use crate::error::{ErrorKind, ResultExt};
+use crate::repo_config::RepoConfig;
+use crate::resource_limits::find_in_path;
+
+/// Overrides `tool_container.image`; empty disables the fallback.
+pub const TOOL_CONTAINER_IMAGE_ENV: &str = "DISTRO_BUILDER_TOOL_CONTAINER";
+
+/// Tools that may run in the container when `tools` is not configured.
+pub const DEFAULT_CONTAINER_TOOLS: &[&str] = &[
+    "mkfs.erofs",
+    "fsck.erofs",
+    "dump.erofs",
+    "xorriso",
+    "mcopy",
+    "mmd",
+    "mformat",
+    "mdir",
+    "mkfs.fat",
+    "mkfs.vfat",
+    "sgdisk",
+];
+
+static INSTALLED: OnceLock<ToolContainer> = OnceLock::new();
+static ANNOUNCED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Container engine running the tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRuntime {
+    Podman,
+    Docker,
+}
+
+impl ContainerRuntime {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Podman => "podman",
+            Self::Docker => "docker",
+        }
+    }
+}
+
+/// `[tool_container]` in `distro-builder.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolContainerConfig {
+    pub image: Option<String>,
+    pub runtime: Option<ContainerRuntime>,
+    pub tools: Option<Vec<String>>,
+}
+
+/// A validated container fallback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolContainer {
+    pub image: String,
+    pub runtime: ContainerRuntime,
+    pub tools: BTreeSet<String>,
+}
+
+impl ToolContainer {
+    /// The fallback configured in `<repo_root>/distro-builder.toml` (when a
+    /// checkout is known) with the environment override applied; `None`
+    /// when no image is configured.
+    pub fn load(repo_root: Option<&Path>) -> Result<Option<Self>> {
+        let config = match repo_root {
+            Some(root) => RepoConfig::load(root)?.tool_container,
+            None => ToolContainerConfig::default(),
+        };
+        Self::from_config(
+            config,
+            std::env::var(TOOL_CONTAINER_IMAGE_ENV).ok().as_deref(),
+            |runtime| find_in_path(runtime.as_str()).is_some(),
+        )
+    }
+
+    fn from_config(
+        config: ToolContainerConfig,
+        image_env: Option<&str>,
+        runtime_installed: impl Fn(ContainerRuntime) -> bool,
+    ) -> Result<Option<Self>> {
+        let image = match image_env.or(config.image.as_deref()).map(str::trim) {
+            None | Some("") => return Ok(None),
+            Some(image) => image,
+        };
+        if !image.contains("@sha256:") {
+            return Err(anyhow!(
+                "tool container image '{}' is not pinned\n\
+Remediation: reference the image by digest, e.g. '<name>@sha256:<digest>'",
+                image
+            ))
+            .error_kind(ErrorKind::InvalidConfig);
+        }
+        let runtime = match config.runtime {
+            Some(runtime) if runtime_installed(runtime) => runtime,
+            Some(runtime) => {
+                return Err(anyhow!(
+                    "tool_container.runtime is {} but it is not installed",
+                    runtime.as_str()
+                ))
+                .error_kind(ErrorKind::MissingTool)
+            }
+            None => [ContainerRuntime::Podman, ContainerRuntime::Docker]
+                .into_iter()
+                .find(|runtime| runtime_installed(*runtime))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "a tool container image is configured but neither podman nor docker is installed\n\
+Remediation: install podman, or unset tool_container.image"
+                    )
+                })
+                .error_kind(ErrorKind::MissingTool)?,
+        };
+        let tools = match config.tools {
+            Some(tools) => tools,
+            None => DEFAULT_CONTAINER_TOOLS
+                .iter()
+                .map(|tool| tool.to_string())
+                .collect(),
+        };
+        if let Some(tool) = tools
+            .iter()
+            .find(|tool| tool.is_empty() || tool.contains('/'))
+        {
+            return Err(anyhow!(
+                "invalid tool_container tool '{}': use a bare command name",
+                tool
+            ))
+            .error_kind(ErrorKind::InvalidConfig);
+        }
+        Ok(Some(Self {
+            image: image.to_string(),
+            runtime,
+            tools: tools.into_iter().collect(),
+        }))
+    }
+
+    /// Whether `program` may run in the container.
+    pub fn covers(&self, program: &str) -> bool {
+        self.tools.contains(program)
+    }
+
+    /// `cmd` run in the container: program, arguments, environment changes
+    /// and working directory carry over; stdio and `pre_exec` hooks do not,
+    /// so wrap before configuring them.
+    pub fn wrap(&self, cmd: &Command) -> Result<Command> {
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let workdir = match cmd.get_current_dir() {
+            Some(dir) => dir.to_path_buf(),
+            None => std::env::current_dir()
+                .map_err(|err| anyhow!("Failed to resolve the working directory: {}", err))?,
+        };
+
+        let mut container = Command::new(self.runtime.as_str());
+        container.args([
+            "run",
+            "--rm",
+            "--network=none",
+            "--security-opt",
+            "label=disable",
+        ]);
+        match self.runtime {
+            ContainerRuntime::Podman => {
+                container.arg("--userns=keep-id");
+            }
+            ContainerRuntime::Docker => {
+                // SAFETY: getuid and getgid have no preconditions.
+                let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+                container.args(["--user", &format!("{}:{}", uid, gid)]);
+            }
+        }
+        for dir in mount_dirs(&workdir, &args) {
+            let dir = dir.to_string_lossy();
+            if dir.contains(':') {
+                bail!("cannot bind-mount '{}' into the tool container", dir);
+            }
+            container.args(["-v", &format!("{}:{}", dir, dir)]);
+        }
+        for (key, value) in cmd.get_envs() {
+            if let Some(value) = value {
+                container.args([
+                    "-e",
+                    &format!("{}={}", key.to_string_lossy(), value.to_string_lossy()),
+                ]);
+            }
+        }
+        container
+            .args(["-w", &workdir.to_string_lossy()])
+            .arg(&self.image)
+            .arg(program)
+            .args(args);
+        Ok(container)
+    }
+}
+
+/// Directories to bind-mount for a command run in `workdir` with `args`:
+/// the working directory and, for every absolute path in the arguments
+/// (including `--opt=/path`), the path itself when it is a directory or its
+/// nearest existing parent. Directories below another mount are dropped.
+fn mount_dirs(workdir: &Path, args: &[String]) -> Vec<PathBuf> {
+    let mut dirs = BTreeSet::new();
+    dirs.insert(workdir.to_path_buf());
+    for arg in args {
+        let Some(start) = arg.find('/') else {
+            continue;
+        };
+        if start != 0 && !arg[..start].ends_with('=') {
+            continue;
+        }
+        let mut dir = PathBuf::from(&arg[start..]);
+        while !dir.is_dir() {
+            if !dir.pop() {
+                break;
+            }
+        }
+        if dir.parent().is_some() {
+            dirs.insert(dir);
+        }
+    }
+    let mut mounts: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if !mounts.iter().any(|mount| dir.starts_with(mount)) {
+            mounts.push(dir);
+        }
+    }
+    mounts
+}
+
+/// Install the process-wide fallback [`route`] uses. Idempotent; the first
+/// installed fallback stays in effect.
+pub fn install(container: Option<ToolContainer>) {
+    if let Some(container) = container {
+        eprintln!(
+            "  Host tools that are missing run in {} ({})",
+            container.image,
+            container.runtime.as_str()
+        );
+        let _ = INSTALLED.set(container);
+    }
+}
+
+/// Whether the installed fallback runs `tool` when the host lacks it.
+pub fn provides(tool: &str) -> bool {
+    INSTALLED
+        .get()
+        .is_some_and(|container| container.covers(tool))
+}
+
+/// `cmd` itself, or `cmd` run in the installed tool container when its
+/// program is covered and missing on the host.
+pub fn route(cmd: Command) -> Result<Command> {
+    let Some(container) = INSTALLED.get() else {
+        return Ok(cmd);
+    };
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    if !container.covers(&program) || find_in_path(&program).is_some() {
+        return Ok(cmd);
+    }
+    if ANNOUNCED.lock().unwrap().insert(program.clone()) {
+        eprintln!(
+            "  [container] {} not on the host; using {}",
+            program, container.image
+        );
+    }
+    container.wrap(&cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    const IMAGE: &str = "registry.example/tools@sha256:0123";
+
+    fn config(image: &str) -> ToolContainerConfig {
+        ToolContainerConfig {
+            image: Some(image.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let none = ToolContainer::from_config(ToolContainerConfig::default(), None, |_| true);
+        assert_eq!(none.unwrap(), None);
+        let disabled = ToolContainer::from_config(config(IMAGE), Some(""), |_| true);
+        assert_eq!(disabled.unwrap(), None);
+
+        let docker_only = |runtime| runtime == ContainerRuntime::Docker;
+        let container = ToolContainer::from_config(config(IMAGE), None, docker_only)
+            .unwrap()
+            .unwrap();
+        assert_eq!(container.runtime, ContainerRuntime::Docker);
+        assert!(container.covers("mkfs.erofs"));
+        assert!(!container.covers("qemu-system-x86_64"));
+
+        assert!(ToolContainer::from_config(config("tools:latest"), None, |_| true).is_err());
+        assert!(ToolContainer::from_config(config(IMAGE), None, |_| false).is_err());
+        let mut pinned_podman = config(IMAGE);
+        pinned_podman.runtime = Some(ContainerRuntime::Podman);
+        assert!(ToolContainer::from_config(pinned_podman, None, docker_only).is_err());
+        let mut bad_tool = config(IMAGE);
+        bad_tool.tools = Some(vec!["/usr/bin/xorriso".to_string()]);
+        assert!(ToolContainer::from_config(bad_tool, None, |_| true).is_err());
+    }
+
+    #[test]
+    fn test_wrap_mounts_paths() {
+        let temp = TempDir::new().unwrap();
+        let staging = temp.path().join("staging");
+        let out = temp.path().join("out");
+        fs::create_dir_all(staging.join("etc")).unwrap();
+        fs::create_dir_all(&out).unwrap();
+
+        let args = [
+            out.join("rootfs.erofs").display().to_string(),
+            staging.display().to_string(),
+            format!("--exclude-path={}", staging.join("etc").display()),
+            "-zlz4hc".to_string(),
+        ];
+        let dirs = mount_dirs(temp.path(), &args);
+        assert_eq!(dirs, [temp.path().to_path_buf()]);
+        let dirs = mount_dirs(&out, &args);
+        assert_eq!(dirs, [out.clone(), staging.clone()]);
+
+        let container = ToolContainer::from_config(config(IMAGE), None, |_| true)
+            .unwrap()
+            .unwrap();
+        let mut cmd = Command::new("mkfs.erofs");
+        cmd.args(&args)
+            .current_dir(&out)
+            .env("SOURCE_DATE_EPOCH", "0");
+        let wrapped = container.wrap(&cmd).unwrap();
+        assert_eq!(wrapped.get_program(), "podman");
+        let wrapped: Vec<String> = wrapped
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let image_at = wrapped.iter().position(|arg| arg == IMAGE).unwrap();
+        assert_eq!(wrapped[image_at + 1], "mkfs.erofs");
+        assert_eq!(wrapped[image_at + 2..], args);
+        assert!(wrapped.contains(&"SOURCE_DATE_EPOCH=0".to_string()));
+        assert!(wrapped.contains(&format!("{}:{}", staging.display(), staging.display())));
+    }
+}
@@ -0,0 +1,355 @@
+//! UEFI firmware (OVMF/AAVMF) discovery for QEMU.
+//!
+//! Distributions install edk2 builds under different names: split
+//! `CODE`/`VARS` images, 2M and 4M flash layouts, SMM builds for Secure
+//! Boot, and `.fd`, `.raw` or combined images. The registry below lists the
+//! known locations per architecture and [`FirmwareFlavor`], always as a
+//! code image together with the variable store template of the same build
+//! (a 4M code image cannot boot with a 2M store).
+//!
+//! Hosts with firmware elsewhere point the builder at it:
+//!
+//! ```text
+//! DISTRO_BUILDER_UEFI_CODE=/opt/edk2/OVMF_CODE.fd
+//! DISTRO_BUILDER_UEFI_VARS=/opt/edk2/OVMF_VARS.fd   # optional
+//! ```
+
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::{ErrorKind, ResultExt};
+
+/// Overrides the firmware code image for every architecture and flavor.
+pub const UEFI_CODE_ENV: &str = "DISTRO_BUILDER_UEFI_CODE";
+
+/// Overrides the variable store template; used with [`UEFI_CODE_ENV`].
+pub const UEFI_VARS_ENV: &str = "DISTRO_BUILDER_UEFI_VARS";
+
+/// Guest architecture the firmware is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareArch {
+    X86_64,
+    Aarch64,
+}
+
+impl FirmwareArch {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::X86_64 => "x86_64",
+            Self::Aarch64 => "aarch64",
+        }
+    }
+
+    /// Architecture of the build host; `None` for hosts without a registry.
+    pub fn host() -> Option<Self> {
+        match std::env::consts::ARCH {
+            "x86_64" => Some(Self::X86_64),
+            "aarch64" => Some(Self::Aarch64),
+            _ => None,
+        }
+    }
+
+    /// Package that provides the firmware, for remediation messages.
+    fn packages(self) -> &'static str {
+        match self {
+            Self::X86_64 => "edk2-ovmf (Fedora, Arch) or ovmf (Debian, Ubuntu)",
+            Self::Aarch64 => "edk2-aarch64 (Fedora, Arch) or qemu-efi-aarch64 (Debian, Ubuntu)",
+        }
+    }
+}
+
+impl fmt::Display for FirmwareArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Which firmware build to boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareFlavor {
+    /// Plain UEFI boot.
+    Standard,
+    /// A build that can enforce Secure Boot once keys are enrolled (SMM on
+    /// x86_64).
+    SecureBoot,
+}
+
+impl fmt::Display for FirmwareFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Standard => "standard",
+            Self::SecureBoot => "Secure Boot",
+        })
+    }
+}
+
+/// One known install location of a firmware build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareCandidate {
+    /// Code (executable) flash image.
+    pub code: &'static str,
+    /// Empty variable store of the same build; `None` for combined images.
+    pub vars: Option<&'static str>,
+}
+
+const fn pair(code: &'static str, vars: &'static str) -> FirmwareCandidate {
+    FirmwareCandidate {
+        code,
+        vars: Some(vars),
+    }
+}
+
+const X86_64_STANDARD: &[FirmwareCandidate] = &[
+    // Fedora/RHEL
+    pair(
+        "/usr/share/edk2/ovmf/OVMF_CODE.fd",
+        "/usr/share/edk2/ovmf/OVMF_VARS.fd",
+    ),
+    pair(
+        "/usr/share/OVMF/OVMF_CODE.fd",
+        "/usr/share/OVMF/OVMF_VARS.fd",
+    ),
+    // Debian/Ubuntu
+    pair(
+        "/usr/share/OVMF/OVMF_CODE_4M.fd",
+        "/usr/share/OVMF/OVMF_VARS_4M.fd",
+    ),
+    FirmwareCandidate {
+        code: "/usr/share/qemu/OVMF.fd",
+        vars: None,
+    },
+    // Arch
+    pair(
+        "/usr/share/edk2/x64/OVMF_CODE.4m.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.4m.fd",
+    ),
+    pair(
+        "/usr/share/edk2-ovmf/x64/OVMF_CODE.fd",
+        "/usr/share/edk2-ovmf/x64/OVMF_VARS.fd",
+    ),
+    // NixOS
+    pair(
+        "/run/libvirt/nix-ovmf/OVMF_CODE.fd",
+        "/run/libvirt/nix-ovmf/OVMF_VARS.fd",
+    ),
+];
+
+const X86_64_SECURE_BOOT: &[FirmwareCandidate] = &[
+    // Fedora/RHEL
+    pair(
+        "/usr/share/edk2/ovmf/OVMF_CODE.secboot.fd",
+        "/usr/share/edk2/ovmf/OVMF_VARS.fd",
+    ),
+    // Debian/Ubuntu
+    pair(
+        "/usr/share/OVMF/OVMF_CODE_4M.secboot.fd",
+        "/usr/share/OVMF/OVMF_VARS_4M.fd",
+    ),
+    pair(
+        "/usr/share/OVMF/OVMF_CODE.secboot.fd",
+        "/usr/share/OVMF/OVMF_VARS.fd",
+    ),
+    // Arch
+    pair(
+        "/usr/share/edk2/x64/OVMF_CODE.secboot.4m.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.4m.fd",
+    ),
+    pair(
+        "/usr/share/edk2-ovmf/x64/OVMF_CODE.secboot.fd",
+        "/usr/share/edk2-ovmf/x64/OVMF_VARS.fd",
+    ),
+];
+
+// aarch64 has no SMM: the standard build enforces Secure Boot once keys are
+// enrolled, so both flavors share these.
+const AARCH64: &[FirmwareCandidate] = &[
+    // Fedora/RHEL
+    pair(
+        "/usr/share/edk2/aarch64/QEMU_EFI-pflash.raw",
+        "/usr/share/edk2/aarch64/vars-template-pflash.raw",
+    ),
+    // Debian/Ubuntu
+    pair(
+        "/usr/share/AAVMF/AAVMF_CODE.fd",
+        "/usr/share/AAVMF/AAVMF_VARS.fd",
+    ),
+    FirmwareCandidate {
+        code: "/usr/share/qemu-efi-aarch64/QEMU_EFI.fd",
+        vars: None,
+    },
+    // Arch
+    pair(
+        "/usr/share/edk2/aarch64/QEMU_CODE.fd",
+        "/usr/share/edk2/aarch64/QEMU_VARS.fd",
+    ),
+];
+
+/// Known locations of `arch`/`flavor` firmware, in search order.
+pub fn candidates(arch: FirmwareArch, flavor: FirmwareFlavor) -> &'static [FirmwareCandidate] {
+    match (arch, flavor) {
+        (FirmwareArch::X86_64, FirmwareFlavor::Standard) => X86_64_STANDARD,
+        (FirmwareArch::X86_64, FirmwareFlavor::SecureBoot) => X86_64_SECURE_BOOT,
+        (FirmwareArch::Aarch64, _) => AARCH64,
+    }
+}
+
+/// Firmware found on the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UefiFirmware {
+    pub code: PathBuf,
+    /// Variable store template matching `code`, when the host has one.
+    pub vars: Option<PathBuf>,
+}
+
+/// Find `arch`/`flavor` firmware, honouring [`UEFI_CODE_ENV`] and
+/// [`UEFI_VARS_ENV`].
+///
+/// Fails with every searched path listed when nothing is installed.
+pub fn find_firmware(arch: FirmwareArch, flavor: FirmwareFlavor) -> Result<UefiFirmware> {
+    let env_path = |name| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    select(
+        arch,
+        flavor,
+        env_path(UEFI_CODE_ENV),
+        env_path(UEFI_VARS_ENV),
+        Path::is_file,
+    )
+    .error_kind(ErrorKind::MissingTool)
+}
+
+fn select(
+    arch: FirmwareArch,
+    flavor: FirmwareFlavor,
+    code_override: Option<PathBuf>,
+    vars_override: Option<PathBuf>,
+    exists: impl Fn(&Path) -> bool,
+) -> Result<UefiFirmware> {
+    if let Some(code) = code_override {
+        for (name, path) in [
+            (UEFI_CODE_ENV, Some(&code)),
+            (UEFI_VARS_ENV, vars_override.as_ref()),
+        ] {
+            if let Some(path) = path.filter(|path| !exists(path)) {
+                bail!("{}='{}' does not exist", name, path.display());
+            }
+        }
+        return Ok(UefiFirmware {
+            code,
+            vars: vars_override,
+        });
+    }
+
+    let registry = candidates(arch, flavor);
+    if let Some(candidate) = registry
+        .iter()
+        .find(|candidate| exists(Path::new(candidate.code)))
+    {
+        return Ok(UefiFirmware {
+            code: PathBuf::from(candidate.code),
+            vars: candidate
+                .vars
+                .map(PathBuf::from)
+                .filter(|vars| exists(vars)),
+        });
+    }
+
+    let searched: Vec<String> = registry
+        .iter()
+        .map(|candidate| format!("  {}", candidate.code))
+        .collect();
+    Err(anyhow!(
+        "no {} UEFI firmware ({}) found; searched:\n{}\n\
+         Remediation: install {}, or set {} (and optionally {}) to the firmware images.",
+        arch,
+        flavor,
+        searched.join("\n"),
+        arch.packages(),
+        UEFI_CODE_ENV,
+        UEFI_VARS_ENV
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_registry_pairs_matching_layouts() {
+        for arch in [FirmwareArch::X86_64, FirmwareArch::Aarch64] {
+            for flavor in [FirmwareFlavor::Standard, FirmwareFlavor::SecureBoot] {
+                let registry = candidates(arch, flavor);
+                assert!(!registry.is_empty());
+                for candidate in registry {
+                    let code_4m = candidate.code.to_ascii_lowercase().contains("4m");
+                    if let Some(vars) = candidate.vars {
+                        assert_eq!(code_4m, vars.to_ascii_lowercase().contains("4m"));
+                    }
+                }
+            }
+        }
+        assert!(candidates(FirmwareArch::X86_64, FirmwareFlavor::SecureBoot)
+            .iter()
+            .all(|candidate| candidate.code.contains("secboot")));
+    }
+
+    #[test]
+    fn test_selection_and_overrides() {
+        let debian_4m = |path: &Path| {
+            path == Path::new("/usr/share/OVMF/OVMF_CODE_4M.fd")
+                || path == Path::new("/usr/share/OVMF/OVMF_VARS_4M.fd")
+        };
+        let found = select(
+            FirmwareArch::X86_64,
+            FirmwareFlavor::Standard,
+            None,
+            None,
+            debian_4m,
+        )
+        .unwrap();
+        assert_eq!(found.code, Path::new("/usr/share/OVMF/OVMF_CODE_4M.fd"));
+        assert_eq!(
+            found.vars.as_deref(),
+            Some(Path::new("/usr/share/OVMF/OVMF_VARS_4M.fd"))
+        );
+
+        let err = select(
+            FirmwareArch::Aarch64,
+            FirmwareFlavor::Standard,
+            None,
+            None,
+            |_| false,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("/usr/share/AAVMF/AAVMF_CODE.fd"), "{err}");
+        assert!(err.contains(UEFI_CODE_ENV), "{err}");
+
+        let temp = TempDir::new().unwrap();
+        let code = temp.path().join("CODE.fd");
+        fs::write(&code, "").unwrap();
+        let found = select(
+            FirmwareArch::Aarch64,
+            FirmwareFlavor::SecureBoot,
+            Some(code.clone()),
+            None,
+            Path::is_file,
+        )
+        .unwrap();
+        assert_eq!(found.code, code);
+        assert!(select(
+            FirmwareArch::X86_64,
+            FirmwareFlavor::Standard,
+            Some(code),
+            Some(temp.path().join("missing.fd")),
+            Path::is_file,
+        )
+        .is_err());
+    }
+}
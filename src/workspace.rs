@@ -0,0 +1,342 @@
+//! Work directory management under `.artifacts/work`.
+//!
+//! Every builder step that needs scratch space goes through
+//! [`WorkspaceManager`] so work directories follow one layout:
+//!
+//! ```text
+//! .artifacts/work/<distro>/
+//!   downloads/                  persistent download cache (never auto-cleaned)
+//!   rootfs-source-provider/     persistent provider state
+//!   scratch/<namespace>-<id>/   per-run scratch, removed per CleanupPolicy
+//! ```
+//!
+//! Scratch directories are removed when their [`ScratchDir`] guard drops,
+//! according to its policy, so failed runs can keep their evidence while
+//! successful runs do not leak gigabytes of extracted rootfs trees.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+
+use crate::pipeline::paths::normalize_distro_id;
+
+/// Persistent download cache namespace; excluded from work cleanup.
+pub const DOWNLOADS_NAMESPACE: &str = "downloads";
+
+/// Persistent rootfs source provider state; excluded from work cleanup.
+pub const ROOTFS_SOURCE_PROVIDER_NAMESPACE: &str = "rootfs-source-provider";
+
+/// Namespaces [`WorkspaceManager::clean_work`] keeps.
+pub const PERSISTENT_NAMESPACES: &[&str] = &[DOWNLOADS_NAMESPACE, ROOTFS_SOURCE_PROVIDER_NAMESPACE];
+
+const SCRATCH_DIR: &str = "scratch";
+
+/// When a scratch directory is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CleanupPolicy {
+    /// Remove on drop regardless of outcome.
+    Always,
+    /// Remove only after [`ScratchDir::mark_success`]; failures keep their files.
+    #[default]
+    OnSuccess,
+    /// Never remove automatically.
+    Never,
+}
+
+/// Disk usage of one namespace in the workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkUsage {
+    pub distro_id: String,
+    pub namespace: String,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Allocates and accounts for work directories under `<repo>/.artifacts/work`.
+#[derive(Debug, Clone)]
+pub struct WorkspaceManager {
+    repo_root: PathBuf,
+    root: PathBuf,
+    policy: CleanupPolicy,
+}
+
+impl WorkspaceManager {
+    pub fn new(repo_root: &Path) -> Result<Self> {
+        Ok(Self {
+            repo_root: repo_root.to_path_buf(),
+            root: crate::repo_config::artifacts_root(repo_root)?.join("work"),
+            policy: CleanupPolicy::default(),
+        })
+    }
+
+    /// Set the default policy for scratch directories allocated afterwards.
+    pub fn with_policy(mut self, policy: CleanupPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Root work directory for a distro (legacy aliases are normalized).
+    pub fn distro_dir(&self, distro_id: &str) -> Result<PathBuf> {
+        let normalized = normalize_distro_id(&self.repo_root, distro_id, "work directory")?;
+        Ok(self.root.join(normalized))
+    }
+
+    /// A persistent, created-on-demand namespace such as `downloads`.
+    pub fn persistent_dir(&self, distro_id: &str, namespace: &str) -> Result<PathBuf> {
+        validate_namespace(namespace)?;
+        let dir = self.distro_dir(distro_id)?.join(namespace);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("creating work directory '{}'", dir.display()))?;
+        Ok(dir)
+    }
+
+    /// Allocate a fresh scratch directory cleaned up per the manager's policy.
+    pub fn scratch(&self, distro_id: &str, namespace: &str) -> Result<ScratchDir> {
+        validate_namespace(namespace)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let path = self.distro_dir(distro_id)?.join(SCRATCH_DIR).join(format!(
+            "{}-{}-{}",
+            namespace,
+            std::process::id(),
+            nanos
+        ));
+        fs::create_dir_all(&path)
+            .with_context(|| format!("creating scratch directory '{}'", path.display()))?;
+        Ok(ScratchDir {
+            path,
+            policy: self.policy,
+            succeeded: false,
+            kept: false,
+        })
+    }
+
+    /// Sizes of every namespace, sorted by distro then namespace.
+    pub fn usage(&self) -> Result<Vec<WorkUsage>> {
+        let mut usage = Vec::new();
+        for distro_dir in sorted_subdirs(&self.root)? {
+            let distro_id = file_name(&distro_dir);
+            for ns_dir in sorted_subdirs(&distro_dir)? {
+                usage.push(WorkUsage {
+                    distro_id: distro_id.clone(),
+                    namespace: file_name(&ns_dir),
+                    bytes: dir_size_bytes(&ns_dir)?,
+                    path: ns_dir,
+                });
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Remove all work directories except the [`PERSISTENT_NAMESPACES`];
+    /// returns bytes freed.
+    pub fn clean_work(&self) -> Result<u64> {
+        self.remove_where(|namespace| !PERSISTENT_NAMESPACES.contains(&namespace))
+    }
+
+    /// Remove only the download caches; returns bytes freed.
+    pub fn clean_downloads(&self) -> Result<u64> {
+        self.remove_where(|namespace| namespace == DOWNLOADS_NAMESPACE)
+    }
+
+    fn remove_where(&self, select: impl Fn(&str) -> bool) -> Result<u64> {
+        let mut freed = 0;
+        for entry in self.usage()? {
+            if !select(&entry.namespace) {
+                continue;
+            }
+            fs::remove_dir_all(&entry.path)
+                .with_context(|| format!("removing work directory '{}'", entry.path.display()))?;
+            freed += entry.bytes;
+        }
+        Ok(freed)
+    }
+}
+
+/// A scratch directory that removes itself on drop according to its policy.
+#[derive(Debug)]
+pub struct ScratchDir {
+    path: PathBuf,
+    policy: CleanupPolicy,
+    succeeded: bool,
+    /// Kept on purpose with [`ScratchDir::keep`], not left by a failure.
+    kept: bool,
+}
+
+impl ScratchDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record that the step using this directory succeeded.
+    pub fn mark_success(&mut self) {
+        self.succeeded = true;
+    }
+
+    /// Keep the directory regardless of policy and return its path.
+    pub fn keep(mut self) -> PathBuf {
+        self.policy = CleanupPolicy::Never;
+        self.kept = true;
+        self.path.clone()
+    }
+
+    pub fn size_bytes(&self) -> Result<u64> {
+        dir_size_bytes(&self.path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let remove = match self.policy {
+            CleanupPolicy::Always => true,
+            CleanupPolicy::OnSuccess => self.succeeded,
+            CleanupPolicy::Never => false,
+        };
+        if !remove {
+            if !self.succeeded && !self.kept {
+                eprintln!(
+                    "  [WARN] keeping work directory for inspection: {}",
+                    self.path.display()
+                );
+            }
+            return;
+        }
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            eprintln!(
+                "  [WARN] failed to remove work directory {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Apparent size of all regular files under `path`, not following symlinks.
+pub fn dir_size_bytes(path: &Path) -> Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let mut total = 0;
+    for entry in WalkDir::new(path).follow_links(false) {
+        let entry = entry.with_context(|| format!("walking '{}'", path.display()))?;
+        if entry.file_type().is_file() {
+            total += entry
+                .metadata()
+                .with_context(|| format!("reading metadata '{}'", entry.path().display()))?
+                .len();
+        }
+    }
+    Ok(total)
+}
+
+fn validate_namespace(namespace: &str) -> Result<()> {
+    if namespace.is_empty()
+        || namespace == SCRATCH_DIR
+        || !namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!("invalid work namespace '{}'", namespace);
+    }
+    Ok(())
+}
+
+fn sorted_subdirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("reading '{}'", dir.display()))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scratch_removed_only_on_success_by_default() {
+        let repo = tempfile::tempdir().expect("repo tempdir");
+        let manager = WorkspaceManager::new(repo.path()).expect("workspace");
+
+        let mut ok = manager.scratch("leviso", "extract").expect("alloc scratch");
+        let ok_path = ok.path().to_path_buf();
+        assert!(ok_path.starts_with(repo.path().join(".artifacts/work/levitate/scratch")));
+        ok.mark_success();
+        drop(ok);
+        assert!(!ok_path.exists());
+
+        let failed = manager
+            .scratch("levitate", "extract")
+            .expect("alloc scratch");
+        let failed_path = failed.path().to_path_buf();
+        drop(failed);
+        assert!(failed_path.exists());
+    }
+
+    #[test]
+    fn clean_work_preserves_downloads_and_reports_bytes() {
+        let repo = tempfile::tempdir().expect("repo tempdir");
+        let manager = WorkspaceManager::new(repo.path())
+            .expect("workspace")
+            .with_policy(CleanupPolicy::Never);
+
+        let downloads = manager
+            .persistent_dir("acorn", DOWNLOADS_NAMESPACE)
+            .expect("downloads dir");
+        fs::write(downloads.join("alpine.iso"), vec![0u8; 64]).expect("write download");
+        let scratch = manager.scratch("acorn", "rootfs").expect("scratch").keep();
+        fs::write(scratch.join("blob"), vec![0u8; 100]).expect("write scratch");
+
+        let usage = manager.usage().expect("usage");
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].namespace, "downloads");
+        assert_eq!(usage[1].bytes, 100);
+
+        assert_eq!(manager.clean_work().expect("clean"), 100);
+        assert!(downloads.join("alpine.iso").is_file());
+        assert!(!scratch.exists());
+
+        assert_eq!(manager.clean_downloads().expect("clean downloads"), 64);
+        assert!(!downloads.exists());
+    }
+
+    #[test]
+    fn clean_work_preserves_provider_state() {
+        let repo = tempfile::tempdir().expect("repo tempdir");
+        let manager = WorkspaceManager::new(repo.path())
+            .expect("workspace")
+            .with_policy(CleanupPolicy::Never);
+
+        let provider = manager
+            .persistent_dir("acorn", ROOTFS_SOURCE_PROVIDER_NAMESPACE)
+            .expect("provider dir");
+        fs::write(provider.join("rootfs.tar"), vec![0u8; 32]).expect("write provider state");
+        let scratch = manager.scratch("acorn", "rootfs").expect("scratch").keep();
+        fs::write(scratch.join("blob"), vec![0u8; 100]).expect("write scratch");
+
+        assert_eq!(manager.clean_work().expect("clean"), 100);
+        assert!(provider.join("rootfs.tar").is_file());
+        assert!(!scratch.exists());
+    }
+}